@@ -0,0 +1,227 @@
+//! ANSI escape sequence handling for captured command output
+//!
+//! Commands like `ls`, `git` and `cargo` colour their output with SGR escape sequences.
+//! This module either strips those sequences or, with the `tui` feature, converts them into
+//! ratatui styles so the output can be rendered inside a `Paragraph`.
+
+#[cfg(feature = "tui")]
+use ratatui::style::{Color, Modifier, Style};
+#[cfg(feature = "tui")]
+use ratatui::text::{Line, Span, Text};
+
+const ESC: char = '\x1b';
+const BEL: char = '\x07';
+
+/// Piece of output between escape sequences
+enum Token<'a> {
+    /// Printable text, may contain newlines
+    Text(&'a str),
+    /// Select Graphic Rendition parameters, i.e. the `31;1` of `\x1b[31;1m`, only read by `to_text`
+    #[cfg_attr(not(feature = "tui"), allow(dead_code))]
+    Sgr(Vec<u16>),
+}
+
+/// Split `input` into text and SGR tokens.
+/// Escape sequences other than SGR (cursor movement, OSC titles, ...) are dropped.
+fn tokenize(input: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    let mut text_start = 0;
+
+    while let Some((idx, c)) = chars.next() {
+        if c != ESC {
+            continue;
+        }
+        if idx > text_start {
+            tokens.push(Token::Text(&input[text_start..idx]));
+        }
+
+        match chars.peek().map(|&(_, c)| c) {
+            // CSI: ESC [ parameters final-byte
+            Some('[') => {
+                chars.next();
+                let params_start = chars.peek().map_or(input.len(), |&(i, _)| i);
+                let mut params_end = input.len();
+                let mut final_byte = None;
+                for (i, c) in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        params_end = i;
+                        final_byte = Some(c);
+                        break;
+                    }
+                }
+                if final_byte == Some('m') {
+                    let params = &input[params_start..params_end];
+                    tokens.push(Token::Sgr(
+                        params.split(';').map(|p| p.parse().unwrap_or(0)).collect(),
+                    ));
+                }
+            }
+            // OSC: ESC ] ... terminated by BEL or ESC \
+            Some(']') => {
+                chars.next();
+                while let Some((_, c)) = chars.next() {
+                    if c == BEL {
+                        break;
+                    }
+                    if c == ESC {
+                        chars.next_if(|&(_, c)| c == '\\');
+                        break;
+                    }
+                }
+            }
+            // Any other two-character escape
+            Some(_) => {
+                chars.next();
+            }
+            None => {}
+        }
+        text_start = chars.peek().map_or(input.len(), |&(i, _)| i);
+    }
+    if text_start < input.len() {
+        tokens.push(Token::Text(&input[text_start..]));
+    }
+
+    tokens
+}
+
+/// Remove every ANSI escape sequence from `input`
+pub fn strip(input: &str) -> String {
+    tokenize(input)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Text(text) => Some(text),
+            Token::Sgr(_) => None,
+        })
+        .collect()
+}
+
+/// Convert ANSI coloured `input` into a ratatui `Text`, one `Line` per output line
+#[cfg(feature = "tui")]
+pub fn to_text(input: &str) -> Text<'static> {
+    let mut lines = Vec::new();
+    let mut spans = Vec::new();
+    let mut style = Style::default();
+
+    for token in tokenize(input) {
+        match token {
+            Token::Text(text) => {
+                let mut parts = text.split('\n');
+                if let Some(first) = parts.next() {
+                    push_span(&mut spans, first, style);
+                }
+                for part in parts {
+                    lines.push(Line::from(std::mem::take(&mut spans)));
+                    push_span(&mut spans, part, style);
+                }
+            }
+            Token::Sgr(params) => style = apply_sgr(style, &params),
+        }
+    }
+    lines.push(Line::from(spans));
+
+    Text::from(lines)
+}
+
+#[cfg(feature = "tui")]
+fn push_span(spans: &mut Vec<Span<'static>>, text: &str, style: Style) {
+    let text = text.strip_suffix('\r').unwrap_or(text);
+    if !text.is_empty() {
+        spans.push(Span::styled(text.to_string(), style));
+    }
+}
+
+/// Fold SGR parameters into `style`
+#[cfg(feature = "tui")]
+fn apply_sgr(mut style: Style, params: &[u16]) -> Style {
+    let mut params = params.iter().copied();
+    while let Some(param) = params.next() {
+        style = match param {
+            0 => Style::default(),
+            1 => style.add_modifier(Modifier::BOLD),
+            2 => style.add_modifier(Modifier::DIM),
+            3 => style.add_modifier(Modifier::ITALIC),
+            4 => style.add_modifier(Modifier::UNDERLINED),
+            5 => style.add_modifier(Modifier::SLOW_BLINK),
+            7 => style.add_modifier(Modifier::REVERSED),
+            9 => style.add_modifier(Modifier::CROSSED_OUT),
+            22 => style.remove_modifier(Modifier::BOLD | Modifier::DIM),
+            23 => style.remove_modifier(Modifier::ITALIC),
+            24 => style.remove_modifier(Modifier::UNDERLINED),
+            25 => style.remove_modifier(Modifier::SLOW_BLINK),
+            27 => style.remove_modifier(Modifier::REVERSED),
+            29 => style.remove_modifier(Modifier::CROSSED_OUT),
+            30..=37 => style.fg(Color::Indexed((param - 30) as u8)),
+            38 => match extended_color(&mut params) {
+                Some(color) => style.fg(color),
+                None => style,
+            },
+            39 => style.fg(Color::Reset),
+            40..=47 => style.bg(Color::Indexed((param - 40) as u8)),
+            48 => match extended_color(&mut params) {
+                Some(color) => style.bg(color),
+                None => style,
+            },
+            49 => style.bg(Color::Reset),
+            90..=97 => style.fg(Color::Indexed((param - 90 + 8) as u8)),
+            100..=107 => style.bg(Color::Indexed((param - 100 + 8) as u8)),
+            _ => style,
+        };
+    }
+    style
+}
+
+/// Parse the `5;n` or `2;r;g;b` tail of a 38/48 parameter
+#[cfg(feature = "tui")]
+fn extended_color(params: &mut impl Iterator<Item = u16>) -> Option<Color> {
+    match params.next()? {
+        5 => Some(Color::Indexed(params.next()? as u8)),
+        2 => Some(Color::Rgb(
+            params.next()? as u8,
+            params.next()? as u8,
+            params.next()? as u8,
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED_OUTPUT: &str = "\x1b[31merror\x1b[0m: build failed\n\x1b[1;32mok\x1b[m";
+
+    #[test]
+    fn strip_sequences() {
+        assert_eq!(strip(RED_OUTPUT), "error: build failed\nok");
+        assert_eq!(strip("\x1b]0;title\x07plain\x1b[2K"), "plain");
+        assert_eq!(strip("no escapes"), "no escapes");
+    }
+
+    #[cfg(feature = "tui")]
+    #[test]
+    fn parse_sequences() {
+        let text = to_text(RED_OUTPUT);
+        assert_eq!(text.lines.len(), 2);
+
+        let first = &text.lines[0].spans;
+        assert_eq!(first[0].content, "error");
+        assert_eq!(first[0].style.fg, Some(Color::Indexed(1)));
+        assert_eq!(first[1].content, ": build failed");
+        assert_eq!(first[1].style, Style::default());
+
+        let second = &text.lines[1].spans;
+        assert_eq!(second[0].content, "ok");
+        assert_eq!(second[0].style.fg, Some(Color::Indexed(2)));
+        assert!(second[0].style.add_modifier.contains(Modifier::BOLD));
+    }
+
+    #[cfg(feature = "tui")]
+    #[test]
+    fn extended_colors() {
+        let text = to_text("\x1b[38;5;208mA\x1b[48;2;1;2;3mB");
+        let spans = &text.lines[0].spans;
+        assert_eq!(spans[0].style.fg, Some(Color::Indexed(208)));
+        assert_eq!(spans[1].style.bg, Some(Color::Rgb(1, 2, 3)));
+    }
+}
@@ -0,0 +1,145 @@
+//! Entry points for using aurish as a library
+//!
+//! These do what both binaries do for a single prompt: pick the client the config asks
+//! for, send the prompt with the usual system prompt for the current shell, and run the
+//! suggested commands. The conversation context, the queue and the confirmation prompts
+//! stay with the frontends.
+
+#[cfg(feature = "backend-blocking")]
+use crate::backend::BKclient;
+#[cfg(feature = "backend-async")]
+use crate::backend::{Bclient, Fit, OllamaReq};
+#[cfg(feature = "backend-async")]
+use crate::error::AurishError;
+#[cfg(feature = "backend-async")]
+use crate::risk::danger_reason;
+#[cfg(feature = "backend-async")]
+use crate::config::Config;
+use crate::shell::{IShell, ShellOutput};
+#[cfg(feature = "backend-async")]
+use crate::utils::sanitize_commands;
+
+/// A command suggested by the model
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuggestedCommand {
+    pub command: String,
+    /// Why the command needs a second look before running it, `None` if it looks harmless.
+    /// See `risk::danger_reason`, the config's `dangerous_patterns` included.
+    pub danger: Option<String>,
+}
+
+/// Request for the commands doing what `prompt` asks, with the model of `config` and,
+/// unless it skips it or it would go over `max_prompt_tokens`, the system info
+#[cfg(feature = "backend-async")]
+pub fn request(config: &Config, prompt: &str) -> OllamaReq {
+    fitted_request(config, prompt).0
+}
+
+/// `request`, with what `OllamaReq::fit` did to keep it within the config's `max_prompt_tokens`
+#[cfg(feature = "backend-async")]
+pub fn fitted_request(config: &Config, prompt: &str) -> (OllamaReq, Fit) {
+    let mut req = OllamaReq::for_shell(config.get_model(), config.get_shell());
+    req.set_suggestion_mode(config.get_suggestion_mode());
+    req.set_language(config.get_language());
+    req.set_system_info(!config.skips_system_info());
+    req.prompt(prompt.trim());
+    let fit = req.fit(config.get_max_prompt_tokens());
+    (req, fit)
+}
+
+/// The commands cleaned up by `sanitize_commands`, each checked by `danger_reason`
+#[cfg(feature = "backend-async")]
+fn suggestions(config: &Config, commands: Vec<String>) -> Vec<SuggestedCommand> {
+    sanitize_commands(commands, config.get_multiline_commands())
+        .into_iter()
+        .map(|command| SuggestedCommand {
+            danger: danger_reason(&command, config.get_dangerous_patterns()),
+            command,
+        })
+        .collect()
+}
+
+/// Ask the model of `config` for commands doing what `prompt` asks
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let (config, _) = aurish::config::load_config(None, false)?;
+/// for suggestion in aurish::generate_commands(&config, "list hidden files").await? {
+///     println!("{}", suggestion.command);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "backend-async")]
+pub async fn generate_commands(config: &Config, prompt: &str) -> Result<Vec<SuggestedCommand>, AurishError> {
+    let commands = Bclient::from_config(config).send_ollama(&request(config, prompt)).await?;
+    Ok(suggestions(config, commands))
+}
+
+/// `generate_commands` without an async runtime
+///
+/// ```no_run
+/// let (config, _) = aurish::config::load_config(None, false)?;
+/// for suggestion in aurish::generate_commands_blocking(&config, "free disk space")? {
+///     match suggestion.danger {
+///         Some(reason) => println!("{}  # careful: {}", suggestion.command, reason),
+///         None => println!("{}", suggestion.command),
+///     }
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[cfg(feature = "backend-blocking")]
+pub fn generate_commands_blocking(config: &Config, prompt: &str) -> Result<Vec<SuggestedCommand>, AurishError> {
+    let commands = BKclient::from_config(config).send_ollama(&request(config, prompt))?;
+    Ok(suggestions(config, commands))
+}
+
+/// Run `command` in `shell`, which keeps the working directory for the next command
+///
+/// ```
+/// let shell = aurish::shell::IShell::new();
+/// let output = aurish::execute(&shell, "echo hello");
+/// assert!(output.is_success());
+/// assert_eq!(output.stdout_str().trim(), "hello");
+/// ```
+pub fn execute(shell: &IShell, command: &str) -> ShellOutput {
+    shell.run_command(command)
+}
+
+#[cfg(all(test, feature = "backend-async"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggestions_are_checked() {
+        let config: Config = serde_json::from_str(
+            r#"{"ollama_api": "http://localhost:11434/api/generate", "model": "llama3", "proxy": "", "dangerous_patterns": ["kubectl delete"]}"#,
+        ).unwrap();
+        let commands = ["ls -a", "kubectl delete pod web", " ", "rm -rf /"].map(String::from).to_vec();
+        let suggested = suggestions(&config, commands);
+        assert_eq!(suggested[0], SuggestedCommand { command: "ls -a".to_string(), danger: None });
+        assert_eq!(suggested[1].danger.as_deref(), Some("matches the configured pattern `kubectl delete`"));
+        assert!(suggested[2].danger.is_some());
+        assert_eq!(request(&config, "  list files\n").model(), "llama3");
+    }
+
+    #[test]
+    fn system_info_can_be_skipped() {
+        let system = |config: &str| {
+            let config: Config = serde_json::from_str(config).unwrap();
+            serde_json::to_value(request(&config, "list files")).unwrap()["system"].as_str().unwrap().to_string()
+        };
+        let summary = crate::system_info::current().summary();
+        let config = r#"{"ollama_api": "http://localhost:11434/api/generate", "model": "llama3", "proxy": ""}"#;
+        assert!(system(config).ends_with(&summary));
+        let skipped = config.replace('}', r#", "skip_system_info": true}"#);
+        assert!(!system(&skipped).contains(&summary));
+
+        // left out over the token budget
+        let budget = config.replace('}', r#", "max_prompt_tokens": 1}"#);
+        assert!(!system(&budget).contains(&summary));
+        let (_, fit) = fitted_request(&serde_json::from_str(&budget).unwrap(), "list files");
+        assert_eq!(fit.trimmed, [crate::backend::Trimmed::SystemInfo]);
+        assert!(fit.is_over());
+    }
+}
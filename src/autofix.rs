@@ -0,0 +1,126 @@
+//! Asking the model again when a command fails
+//!
+//! With auto-fix on, a command that exits with an error is sent back to the model with what
+//! it printed, asking for a corrected command. The correction runs only once the user
+//! confirms it, and if it fails too the loop goes on, up to a number of attempts. A
+//! correction that looks dangerous ends the loop without running: it is left as the next
+//! pending command for the user to look at.
+
+use crate::risk::danger_reason;
+use crate::session;
+use crate::utils::{sanitize_commands, MultilineCommands};
+
+/// Attempts when the config does not set `auto_fix_attempts`
+pub const DEFAULT_ATTEMPTS: u32 = 3;
+
+/// Most attempts the config may ask for
+pub const MAX_ATTEMPTS: u32 = 10;
+
+/// Output longer than this is cut in the prompt
+const OUTPUT_BYTES: usize = 2000;
+
+/// Prompt asking for a corrected `command`, which exited with `exit_code` after printing
+/// `stdout` and `stderr`. Only stderr is sent, or stdout if stderr is empty.
+pub fn fix_prompt(command: &str, exit_code: Option<i32>, stdout: &str, stderr: &str) -> String {
+    let status = match exit_code {
+        Some(code) => format!("exited with code {}", code),
+        None => "was terminated by a signal".to_string(),
+    };
+    let output = if stderr.trim().is_empty() { stdout } else { stderr };
+    let mut prompt = format!("This command {}:\n{}\n", status, command);
+    if !output.trim().is_empty() {
+        prompt.push_str(&format!("It printed:\n{}\n", session::truncate(output.trim_end(), OUTPUT_BYTES)));
+    }
+    prompt.push_str("\nGive one corrected command that does what it was meant to do.");
+    prompt
+}
+
+/// Attempts at fixing the command that failed first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoFix {
+    /// The command that failed first
+    pub original: String,
+    attempt: u32,
+    max_attempts: u32,
+}
+
+impl AutoFix {
+    pub fn new(original: &str, max_attempts: u32) -> Self {
+        AutoFix { original: original.to_string(), attempt: 0, max_attempts }
+    }
+
+    /// Count one more attempt, false once all of them were made
+    pub fn next_attempt(&mut self) -> bool {
+        if self.attempt >= self.max_attempts {
+            return false;
+        }
+        self.attempt += 1;
+        true
+    }
+
+    /// Attempts made so far
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// e.g. `auto-fix attempt 2/3`
+    pub fn label(&self) -> String {
+        format!("auto-fix attempt {}/{}", self.attempt, self.max_attempts)
+    }
+}
+
+/// What the model answered to `fix_prompt`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Correction {
+    /// A command to run once the user confirms it
+    Run(String),
+    /// A command that must not be run by the loop, with the reason, see `risk::danger_reason`
+    Dangerous { command: String, reason: String },
+    /// No usable command
+    Nothing,
+}
+
+/// The first of the commands the model answered, checked against the built-in rules and
+/// `patterns`
+pub fn correction(commands: Vec<String>, multiline: MultilineCommands, patterns: &[String]) -> Correction {
+    let Some(command) = sanitize_commands(commands, multiline).into_iter().next() else {
+        return Correction::Nothing;
+    };
+    match danger_reason(&command, patterns) {
+        Some(reason) => Correction::Dangerous { command, reason },
+        None => Correction::Run(command),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attempts_are_counted() {
+        let mut fix = AutoFix::new("cargo biuld", 2);
+        assert!(fix.next_attempt());
+        assert_eq!(fix.label(), "auto-fix attempt 1/2");
+        assert!(fix.next_attempt());
+        assert_eq!(fix.label(), "auto-fix attempt 2/2");
+        assert!(!fix.next_attempt());
+        assert_eq!(fix.attempt(), 2);
+    }
+
+    #[test]
+    fn prompts_and_corrections() {
+        let prompt = fix_prompt("cat notes.txt", Some(1), "", "cat: notes.txt: No such file or directory\n");
+        assert_eq!(prompt, "This command exited with code 1:\ncat notes.txt\n\
+            It printed:\ncat: notes.txt: No such file or directory\n\n\
+            Give one corrected command that does what it was meant to do.");
+        assert!(fix_prompt("grep -q x f", Some(1), "", "").contains("code 1:\ngrep -q x f\n\nGive"));
+        assert!(fix_prompt("make", None, "error: no rule\n", "").contains("terminated by a signal:\nmake\nIt printed:\nerror: no rule\n"));
+
+        let multiline = MultilineCommands::default();
+        assert_eq!(correction(vec![" ".to_string(), "ls notes/".to_string(), "pwd".to_string()], multiline, &[]), Correction::Run("ls notes/".to_string()));
+        assert_eq!(correction(Vec::new(), multiline, &[]), Correction::Nothing);
+        assert!(matches!(correction(vec!["rm -rf /".to_string()], multiline, &[]), Correction::Dangerous { .. }));
+        let patterns = ["kubectl delete".to_string()];
+        assert!(matches!(correction(vec!["kubectl delete pod web".to_string()], multiline, &patterns), Correction::Dangerous { .. }));
+    }
+}
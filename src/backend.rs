@@ -1,204 +1,1300 @@
-use reqwest::{Client, Proxy};
-use reqwest::blocking::Client as BlockingClinet;
-use serde::{Deserialize, Serialize, Deserializer};
-use serde_json::{Result, Value, json};
-use std::error::Error;
-use std::string::ToString;
-use std::collections::HashMap;
-use std::env;
-
-
-// pub const OLLAMA_GEN_API: String = String::from("http://localhost:11434/api/generate");
-
-#[derive(Debug, Serialize)]
-pub struct OllamaReq {
-    model: String,
-    prompt: String,
-    stream: bool,
-    format: Value,
-    system: String,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct OllamaRes {
-    model: String,
-    created_at: String,
-    response: String,
-    done: bool,
-    done_reason: String,
-    context: Vec<u64>,
-    total_duration: u64,
-    load_duration: u64,
-    prompt_eval_count: u64,
-    prompt_eval_duration: u64,
-    eval_count: u64,
-    eval_duration: u64,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct Command {
-    commands: Vec<String>
-}
-
-pub struct Bclient {
-    client: Client,
-    target: String,
-}
-
-pub struct BKclient {
-    client: BlockingClinet,
-    target: String,
-}
-
-impl OllamaReq {
-    pub fn new(model: &str) -> OllamaReq {
-        let shell_type = which_shell();
-        OllamaReq {
-            model: model.to_string(),
-            prompt: String::new(),
-            stream: false,
-            format: json!(
-                {
-                    "type": "object",
-                    "properties": {
-                    "commands": {
-                        "type": "array"
-                    },
-                },
-                    "required": ["commands"]
-                }
-            ),
-            system: format!("You are {} expert, your task is give {} commands that meets user requirements. Your answer should only contains commands. Respond using JSON.", &shell_type, &shell_type),
-        }
-    }
-
-    pub fn prompt(&mut self, prompt: &str) {
-        self.prompt = prompt.to_string();
-    }
-
-    pub fn set_model(&mut self, model: &str) {
-        self.model = model.to_string();
-    }
-
-}
-
-fn which_shell() -> String {
-    /// Detect which shell AI interact with.
-    /// On windows, the default shell this function returned is PowerShell.
-    if cfg!(target_os = "windows") {
-        match env::var("PSModulePath") {
-            Ok(_p) => return "PowerShell".to_string(),
-            Err(_e) => {
-                match env::var("COMSPEC") {
-                    Ok(_c) => return "Cmd".to_string(),
-                    Err(_e) => panic!("Shell Not found!"),
-                }
-            },
-        }
-    } else {
-        match env::var("SHELL") {
-            Ok(shell) => {
-                let shell_lower = shell.to_lowercase();
-                if shell_lower.contains("bash") {
-                    return "Bash".to_string();
-                } else if shell_lower.contains("zsh") {
-                    return "Zsh".to_string();
-                } else if shell_lower.contains("fish") {
-                    return "Fish".to_string();
-                } else if shell_lower.contains("ksh") {
-                    return "Ksh".to_string();
-                } else {
-                    panic!("Shell Not supported")
-                }
-            },
-            Err(_e) => panic!("Shell Not found!"),
-        }
-    }
-}
-
-pub trait ClientInit {
-    fn new(target: &str) -> Self;
-    fn new_with_proxy(target: &str, proxy: &str) -> Self;
-}
-
-impl Default for Bclient {
-    fn default() -> Self {
-        Bclient {
-            client: Client::new(),
-            target: "http://localhost:11434/api/generate".to_string(),
-        }
-    }
-}
-
-impl Default for BKclient {
-    fn default() -> Self {
-        BKclient {
-            client: BlockingClinet::new(),
-            target: "http://localhost:11434/api/generate".to_string(),
-        }
-    }
-}
-
-impl ClientInit for Bclient {
-    fn new(target: &str) -> Self {
-        Bclient {
-            client: Client::new(),
-            target: target.to_string(),
-        }
-    }
-
-    fn new_with_proxy(target: &str, proxy: &str) -> Self {
-        Bclient {
-            client: Client::builder()
-                .proxy(Proxy::http(proxy).unwrap()).build().unwrap(),
-            target: target.to_string(),
-        }
-    }
-}
-
-impl ClientInit for BKclient {
-    fn new(target: &str) -> Self {
-        BKclient {
-            client: BlockingClinet::new(),
-            target: target.to_string(),
-        }
-    }
-
-    fn new_with_proxy(target: &str, proxy: &str) -> Self {
-        BKclient {
-            client: BlockingClinet::builder()
-                .proxy(Proxy::http(proxy).unwrap()).build().unwrap(),
-            target: target.to_string(),
-        }
-    }
-}
-
-impl Bclient {
-    pub async fn send_ollama(&self, data: &OllamaReq) -> Result<Vec<String>> {
-        // println!("Request body: {:#?}", &data);
-        let res = self.client.post(&self.target)
-            .json(data)
-            .send()
-            .await.unwrap();
-        // println!("Raw response: {:#?}", &res);
-        let res_body = res.text().await.unwrap();
-        // println!("Response body: {:#?}", &res_body);
-        let ollama_res: OllamaRes = serde_json::from_str(&res_body).unwrap();
-        // println!("Ollama response: {:#?}", &ollama_res);
-        let inner_json: Command = serde_json::from_str(&ollama_res.response).unwrap();
-        Ok(inner_json.commands)
-    }
-}
-
-impl BKclient {
-    pub fn send_ollama(&self, data: &OllamaReq) -> Result<Vec<String>> {
-        let res = self.client.post(&self.target)
-            .json(data)
-            .send()
-            .unwrap();
-        let res_body = res.text().unwrap();
-        let ollama_res: OllamaRes = serde_json::from_str(&res_body).unwrap();
-        let inner__json: Command = serde_json::from_str(&ollama_res.response).unwrap();
-        Ok(inner__json.commands)
-    }
-}
+use reqwest::{Client, Proxy, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::string::ToString;
+#[cfg(feature = "backend-blocking")]
+use std::future::Future;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+#[cfg(feature = "backend-blocking")]
+use tokio::runtime::{Builder, Runtime};
+use log::{debug, info, trace, warn};
+use crate::error::BackendError;
+use crate::logging::{Record, RequestLog};
+use crate::config::Config;
+use crate::i18n::Language;
+use crate::project_context::ContextFile;
+use crate::shell::{self, ShellType};
+use crate::suggestions::{self, SuggestionMode};
+use crate::system_info::{self, SystemInfo};
+
+
+// pub const OLLAMA_GEN_API: String = String::from("http://localhost:11434/api/generate");
+
+/// Body of a request to `/api/generate`. Read back, e.g. from a test fixture, the fields that
+/// are not sent keep their defaults: no attached context or system details to leave out,
+/// `SuggestionMode::Auto`, the detected shell and English.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OllamaReq {
+    model: String,
+    /// Attached context followed by the user's own text, see `prompt_with`
+    prompt: String,
+    /// Length of the attached context at the start of `prompt`
+    #[serde(skip)]
+    attached_len: usize,
+    stream: bool,
+    /// JSON schema of the answer, left out for a plain text answer
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    format: Value,
+    system: String,
+    /// Whether `system` ends with the summary of `system_info`
+    #[serde(skip)]
+    system_info: bool,
+    /// How many commands `format` and `system` ask for
+    #[serde(skip)]
+    suggestion_mode: SuggestionMode,
+    /// Shell the commands are asked for, named in `system`
+    #[serde(skip, default = "shell::detect")]
+    shell: ShellType,
+    /// Notes on the project the commands run in, at the start of `system`, see `project_context`
+    #[serde(skip)]
+    project_context: String,
+    /// Language `system` is written in, see `i18n`
+    #[serde(skip)]
+    language: Language,
+    #[serde(default, skip_serializing_if = "ModelOptions::is_empty")]
+    options: ModelOptions,
+    /// Conversation so far as returned with the previous answer, empty for a new one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    context: Vec<u64>,
+}
+
+/// Rough number of tokens `text` makes for the model, a token being some 4 characters
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Context `OllamaReq::fit` can leave out of a request, in the order it does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trimmed {
+    /// The conversation so far, see `OllamaReq::set_context`
+    History,
+    /// The context attached before the user's text, see `OllamaReq::prompt_with`
+    Attached,
+    /// The description of the system in the system prompt
+    SystemInfo,
+}
+
+impl Trimmed {
+    fn describe(self) -> &'static str {
+        match self {
+            Trimmed::History => "the conversation so far",
+            Trimmed::Attached => "what happened to the last commands",
+            Trimmed::SystemInfo => "the system details",
+        }
+    }
+}
+
+/// What `OllamaReq::fit` did to a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fit {
+    /// Context left out, in the order it was
+    pub trimmed: Vec<Trimmed>,
+    /// Estimated tokens of the request as it is sent
+    pub tokens: usize,
+    pub max_tokens: usize,
+}
+
+impl Fit {
+    /// Whether the request is still over the budget, the user's text alone being too long
+    pub fn is_over(&self) -> bool {
+        self.tokens > self.max_tokens
+    }
+
+    /// What to tell the user, `None` if the request fit as it was
+    pub fn warning(&self) -> Option<String> {
+        let trimmed: Vec<_> = self.trimmed.iter().map(|trimmed| trimmed.describe()).collect();
+        match (trimmed.is_empty(), self.is_over()) {
+            (true, false) => None,
+            (false, false) => Some(format!(
+                "Left out {} to keep the prompt within max_prompt_tokens ({})",
+                trimmed.join(" and "),
+                self.max_tokens,
+            )),
+            (_, true) => Some(format!(
+                "The prompt is about {} tokens, over max_prompt_tokens ({}), the model may cut it",
+                self.tokens,
+                self.max_tokens,
+            )),
+        }
+    }
+}
+
+/// Model parameters sent along with a prompt, unset ones are left to the server
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelOptions {
+    /// Makes the answer reproducible, a different seed gives a different answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl ModelOptions {
+    fn is_empty(&self) -> bool {
+        self.seed.is_none()
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct OllamaRes {
+    model: String,
+    created_at: String,
+    response: String,
+    done: bool,
+    done_reason: String,
+    context: Vec<u64>,
+    total_duration: u64,
+    load_duration: u64,
+    prompt_eval_count: u64,
+    prompt_eval_duration: u64,
+    eval_count: u64,
+    eval_duration: u64,
+}
+
+/// Commands suggested by the model and how long generating them took
+#[derive(Debug, Clone, PartialEq)]
+pub struct Generation {
+    pub commands: Vec<String>,
+    pub stats: GenerationStats,
+    /// Ollama's encoding of the conversation up to this answer, see `OllamaReq::set_context`
+    pub context: Vec<u64>,
+    /// Commands left out of the answer for the suggestion mode, see `SuggestionMode::limit`
+    pub dropped: usize,
+    /// Whether this is the answer to the same request a moment ago, not asked again, see
+    /// `Bclient::with_dedup`
+    pub cached: bool,
+}
+
+impl Generation {
+    /// What to tell the user about `dropped`, `None` if no command was left out
+    pub fn notice(&self) -> Option<String> {
+        suggestions::dropped_notice(self.dropped)
+    }
+}
+
+/// Answer of one model to a prompt sent to several, see `Bclient::send_ollama_multi`
+#[derive(Debug)]
+pub struct ModelAnswer {
+    pub model: String,
+    /// Time until the answer arrived or the request failed
+    pub latency: Duration,
+    pub commands: Result<Vec<String>, BackendError>,
+}
+
+/// Statistics Ollama reports with an answer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GenerationStats {
+    /// Tokens in the answer
+    pub eval_count: u64,
+    /// Time spent generating the answer
+    pub eval_duration: Duration,
+    /// Time spent on the whole request, loading the model included
+    pub total_duration: Duration,
+    /// Time spent loading the model, next to nothing once it is in memory
+    pub load_duration: Duration,
+}
+
+impl GenerationStats {
+    /// Generation speed, `None` if the server did not report it
+    pub fn tokens_per_sec(&self) -> Option<f64> {
+        if self.eval_duration.is_zero() {
+            None
+        } else {
+            Some(self.eval_count as f64 / self.eval_duration.as_secs_f64())
+        }
+    }
+}
+
+impl OllamaRes {
+    fn stats(&self) -> GenerationStats {
+        GenerationStats {
+            eval_count: self.eval_count,
+            eval_duration: Duration::from_nanos(self.eval_duration),
+            total_duration: Duration::from_nanos(self.total_duration),
+            load_duration: Duration::from_nanos(self.load_duration),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Command {
+    commands: Vec<String>
+}
+
+/// Response of `/api/tags`
+#[derive(Debug, Deserialize)]
+struct Tags {
+    models: Vec<ModelTag>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelTag {
+    name: String,
+}
+
+/// Response of `/api/ps`, the models loaded into memory
+#[derive(Debug, Deserialize)]
+struct RunningModels {
+    models: Vec<ModelTag>,
+}
+
+/// How long the TUI answers the same request from the last answer, see `Bclient::with_dedup`
+pub const DEDUP_WINDOW: Duration = Duration::from_secs(10);
+
+/// Async client of the Ollama server
+///
+/// The reqwest client is only built with the first request, since loading the TLS roots
+/// and setting up the proxy take a while that the TUI should not wait for before its
+/// first frame. Clones share it, and their requests for commands: only one of them is
+/// sent at a time, see `generate`.
+#[derive(Clone)]
+pub struct Bclient {
+    client: Arc<OnceLock<Client>>,
+    /// Proxy the client is built with, if any
+    proxy: Option<String>,
+    target: String,
+    /// Limit for a whole request, none by default
+    timeout: Option<Duration>,
+    /// Where each request for commands is recorded, if anywhere
+    request_log: Option<RequestLog>,
+    /// Requests for commands sent per minute at most, no limit by default
+    rate_limit: Option<u32>,
+    /// How long the same request is answered with the last answer, never by default
+    dedup: Option<Duration>,
+    traffic: Arc<Mutex<Traffic>>,
+}
+
+/// What the clones of a `Bclient` know about their requests for commands
+#[derive(Debug, Default)]
+struct Traffic {
+    /// Whether one is being answered
+    in_flight: bool,
+    /// When those of the last minute were sent, oldest first
+    sent: VecDeque<Instant>,
+    /// The last answer, when it came and the request it answered, see `OllamaReq::dedup_key`
+    last: Option<(String, Instant, Generation)>,
+}
+
+/// How `Bclient::admit` lets a request for commands through
+enum Admitted {
+    Send(InFlight),
+    /// With the last answer and how old it is
+    Cached(Generation, Duration),
+}
+
+/// Marks a request for commands as being answered, until dropped with the request
+struct InFlight(Arc<Mutex<Traffic>>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        lock_traffic(&self.0).in_flight = false;
+    }
+}
+
+fn lock_traffic(traffic: &Mutex<Traffic>) -> MutexGuard<'_, Traffic> {
+    // still usable after a thread panicked holding it, there is nothing half-done in it
+    traffic.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Blocking client, for `aurish-cli` and `generate_commands_blocking`
+///
+/// It runs a `Bclient` on a current-thread tokio runtime, created with the first request
+/// and kept for the next ones. Creating it takes some 10-30µs and `block_on` under 2µs per
+/// request, nothing next to the request itself: 300 requests to a local mock server took
+/// the same time as with the former `reqwest::blocking` client. Like any blocking client,
+/// it must not be used from within an async runtime.
+#[cfg(feature = "backend-blocking")]
+pub struct BKclient {
+    inner: Bclient,
+    runtime: OnceLock<Runtime>,
+}
+
+impl OllamaReq {
+    /// Request for commands, with a system prompt describing the system, see `system_info`
+    pub fn new(model: &str) -> OllamaReq {
+        OllamaReq::for_shell(model, shell::detect())
+    }
+
+    /// `new` for commands of `shell`, for a caller that detected it already
+    pub fn for_shell(model: &str, shell: ShellType) -> OllamaReq {
+        OllamaReq {
+            model: model.to_string(),
+            prompt: String::new(),
+            stream: false,
+            format: SuggestionMode::default().schema(),
+            system: commands_system(Language::default(), shell, Some(system_info::current()), SuggestionMode::default()),
+            system_info: true,
+            suggestion_mode: SuggestionMode::default(),
+            shell,
+            options: ModelOptions::default(),
+            context: Vec::new(),
+            attached_len: 0,
+            project_context: String::new(),
+            language: Language::default(),
+        }
+    }
+
+    /// Whether the system prompt of a request for commands describes the system, on by
+    /// default
+    pub fn set_system_info(&mut self, include: bool) {
+        let system = commands_system(self.language, self.shell, include.then(system_info::current), self.suggestion_mode);
+        self.system = format!("{}{}", self.project_context, system);
+        self.system_info = include;
+    }
+
+    /// Ask in `language`, see `i18n`
+    pub fn set_language(&mut self, language: Language) {
+        self.language = language;
+        self.set_system_info(self.system_info);
+    }
+
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// Start the system prompt with the notes of `file`, or leave them out with `None`
+    pub fn set_project_context(&mut self, file: Option<&ContextFile>) {
+        self.project_context = file.map(ContextFile::preamble).unwrap_or_default();
+        self.set_system_info(self.system_info);
+    }
+
+    /// Ask for commands of `shell` instead of the detected one
+    pub fn set_shell(&mut self, shell: ShellType) {
+        self.shell = shell;
+        self.set_system_info(self.system_info);
+    }
+
+    pub fn shell(&self) -> ShellType {
+        self.shell
+    }
+
+    /// Ask for as many commands as `mode` wants, see `suggestions`
+    pub fn set_suggestion_mode(&mut self, mode: SuggestionMode) {
+        self.suggestion_mode = mode;
+        self.format = mode.schema();
+        self.set_system_info(self.system_info);
+    }
+
+    pub fn suggestion_mode(&self) -> SuggestionMode {
+        self.suggestion_mode
+    }
+
+    /// Request asking what `command` of `shell_type` does, answered in plain text instead
+    /// of commands, asked in `language`
+    pub fn explain(model: &str, command: &str, shell_type: ShellType, language: Language) -> OllamaReq {
+        OllamaReq {
+            model: model.to_string(),
+            prompt: command.to_string(),
+            stream: false,
+            format: Value::Null,
+            system: language.strings().explain_system(shell_type),
+            system_info: false,
+            suggestion_mode: SuggestionMode::default(),
+            shell: shell_type,
+            options: ModelOptions::default(),
+            context: Vec::new(),
+            attached_len: 0,
+            project_context: String::new(),
+            language,
+        }
+    }
+
+    pub fn prompt(&mut self, prompt: &str) {
+        self.prompt_with("", prompt);
+    }
+
+    /// The prompt as sent, attached context first
+    pub fn prompt_text(&self) -> &str {
+        &self.prompt
+    }
+
+    /// The system prompt as sent, project notes first and system details last
+    pub fn system_prompt(&self) -> &str {
+        &self.system
+    }
+
+    /// JSON schema the answer has to follow, `None` for a plain text answer
+    pub fn format(&self) -> Option<&Value> {
+        Some(&self.format).filter(|format| !format.is_null())
+    }
+
+    /// Send `prompt` preceded by `attached`, context the model can do without when the
+    /// request is over the token budget, see `fit`
+    pub fn prompt_with(&mut self, attached: &str, prompt: &str) {
+        self.prompt = format!("{}{}", attached, prompt);
+        self.attached_len = attached.len();
+    }
+
+    /// Rough number of tokens of the prompt, the system prompt and the conversation so far
+    pub fn estimated_tokens(&self) -> usize {
+        estimate_tokens(&self.system) + estimate_tokens(&self.prompt) + self.context.len()
+    }
+
+    /// Leave out context until the request is estimated to stay within `max_tokens`: first
+    /// the conversation so far, then the attached context, then the system details. The
+    /// user's own text is never cut, a prompt still too long is only reported by `Fit::is_over`.
+    pub fn fit(&mut self, max_tokens: usize) -> Fit {
+        let mut trimmed = Vec::new();
+        for step in [Trimmed::History, Trimmed::Attached, Trimmed::SystemInfo] {
+            if self.estimated_tokens() <= max_tokens {
+                break;
+            }
+            match step {
+                Trimmed::History if !self.context.is_empty() => self.context.clear(),
+                Trimmed::Attached if self.attached_len > 0 => {
+                    self.prompt.drain(..self.attached_len);
+                    self.attached_len = 0;
+                },
+                Trimmed::SystemInfo if self.system_info => self.set_system_info(false),
+                _ => continue,
+            }
+            trimmed.push(step);
+        }
+        Fit { trimmed, tokens: self.estimated_tokens(), max_tokens }
+    }
+
+    pub fn set_model(&mut self, model: &str) {
+        self.model = model.to_string();
+    }
+
+    /// What tells requests apart for `Bclient::with_dedup`: all that is sent but the
+    /// conversation, which the answer to the first of two same prompts extends
+    fn dedup_key(&self) -> String {
+        let without_context = OllamaReq { context: Vec::new(), ..self.clone() };
+        serde_json::to_string(&without_context).unwrap_or_default()
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Continue the conversation of a previous answer, an empty `context` starts a new one
+    pub fn set_context(&mut self, context: Vec<u64>) {
+        self.context = context;
+    }
+
+    /// Seed of the next requests, `None` lets the server pick one
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.options.seed = seed;
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.options.seed
+    }
+
+    /// The body exactly as sent, pretty-printed. For debugging only: nothing is redacted,
+    /// unlike in `RequestLog`, so passwords in the prompt are shown too.
+    pub fn to_pretty_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+/// System prompt in `language` of a request for commands of `shell_type`, ending with the
+/// summary of `system_info`
+fn commands_system(language: Language, shell_type: ShellType, system_info: Option<&SystemInfo>, mode: SuggestionMode) -> String {
+    let system = language.strings().commands_system(shell_type, mode);
+    match system_info {
+        Some(info) => format!("{} {}", system, info.summary()),
+        None => system,
+    }
+}
+
+/// Host and port of the server `generate_api` belongs to, like `localhost:11434`, or
+/// `generate_api` itself if it is no URL
+pub fn server_name(generate_api: &str) -> String {
+    match reqwest::Url::parse(generate_api) {
+        Ok(url) => match (url.host_str(), url.port_or_known_default()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            _ => generate_api.to_string(),
+        },
+        Err(_) => generate_api.to_string(),
+    }
+}
+
+/// Whether `model` is among the models listed by `list_models`, where `llama3` and
+/// `llama3:latest` are the same model
+pub fn has_model(models: &[String], model: &str) -> bool {
+    let latest = |name: &str| if name.contains(':') { name.to_string() } else { format!("{}:latest", name) };
+    models.iter().any(|listed| latest(listed) == latest(model))
+}
+
+/// Endpoint `/api/<endpoint>` of the server `generate_api` belongs to, like `/api/tags`
+fn api_url(generate_api: &str, endpoint: &str) -> String {
+    match generate_api.rfind("/api/") {
+        Some(idx) => format!("{}/api/{}", &generate_api[..idx], endpoint),
+        None => format!("{}/api/{}", generate_api.trim_end_matches('/'), endpoint),
+    }
+}
+
+/// Log a request before sending it. The prompt itself is logged `redact`ed with the
+/// `logging` feature, and as is only at trace level.
+fn log_request(target: &str, data: &OllamaReq) {
+    info!("POST {} model={} prompt={} chars", target, data.model, data.prompt.chars().count());
+    #[cfg(feature = "logging")]
+    info!("prompt: {:?}", crate::logging::redact(&data.prompt));
+    trace!("prompt: {:?}", data.prompt);
+}
+
+/// Log how a request for commands sent at `started` went, and record it in `request_log`
+fn log_outcome(target: &str, request_log: Option<&RequestLog>, data: &OllamaReq, started: Instant, result: &Result<Generation, BackendError>) {
+    let latency = started.elapsed();
+    match result {
+        Ok(generation) => info!(
+            "{} commands after {:.2}s, {} tokens at {:.1} tokens/s",
+            generation.commands.len(),
+            latency.as_secs_f64(),
+            generation.stats.eval_count,
+            generation.stats.tokens_per_sec().unwrap_or_default(),
+        ),
+        Err(e) => info!("no commands after {:.2}s: {}", latency.as_secs_f64(), e),
+    }
+    if let Some(request_log) = request_log {
+        request_log.append(&Record::new(target, &data.model, &data.prompt, latency, result));
+    }
+}
+
+fn log_response(res_body: &str) {
+    debug!("response: {} bytes", res_body.len());
+    trace!("response body: {}", res_body);
+}
+
+/// Error body of Ollama, e.g. `{"error": "model 'x' not found"}`
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: String,
+}
+
+/// Longest part of a body without an `error` field kept in `BackendError::Server`
+const MAX_ERROR_BODY: usize = 200;
+
+/// The body of `res`, or the error the server answered with instead
+async fn response_body(res: Response) -> Result<String, BackendError> {
+    let status = res.status();
+    let res_body = res.text().await?;
+    log_response(&res_body);
+    if !status.is_success() {
+        return Err(server_error(status, &res_body));
+    }
+    Ok(res_body)
+}
+
+/// What a server answering with `status` said went wrong: the `error` of Ollama's error
+/// body, or the start of the body if it is something else, like a proxy's error page
+fn server_error(status: StatusCode, res_body: &str) -> BackendError {
+    let message = match serde_json::from_str::<ErrorBody>(res_body) {
+        Ok(body) => body.error,
+        Err(_) => {
+            let text = res_body.trim();
+            match text.char_indices().nth(MAX_ERROR_BODY) {
+                Some((end, _)) => format!("{}…", &text[..end]),
+                None => text.to_string(),
+            }
+        },
+    };
+    warn!("Ollama answered {}: {}", status, message);
+    BackendError::Server { status, message }
+}
+
+/// The commands in the answer to an `OllamaReq::new` request
+fn parse_generation(res_body: &str) -> Result<Generation, BackendError> {
+    let ollama_res: OllamaRes = serde_json::from_str(res_body)
+        .inspect_err(|e| warn!("not an Ollama response: {}", e))?;
+    let inner_json: Command = serde_json::from_str(&ollama_res.response)
+        .inspect_err(|e| warn!("no commands in the answer {:?}: {}", ollama_res.response, e))?;
+    debug!("parsed {} commands: {:?}", inner_json.commands.len(), inner_json.commands);
+    Ok(Generation { commands: inner_json.commands, stats: ollama_res.stats(), context: ollama_res.context, dropped: 0, cached: false })
+}
+
+pub trait ClientInit {
+    fn new(target: &str) -> Self;
+    fn new_with_proxy(target: &str, proxy: &str) -> Self;
+}
+
+impl Default for Bclient {
+    fn default() -> Self {
+        Bclient {
+            client: Arc::default(),
+            proxy: None,
+            target: "http://localhost:11434/api/generate".to_string(),
+            timeout: None,
+            request_log: None,
+            rate_limit: None,
+            dedup: None,
+            traffic: Arc::default(),
+        }
+    }
+}
+
+#[cfg(feature = "backend-blocking")]
+impl Default for BKclient {
+    fn default() -> Self {
+        BKclient::from(Bclient::default())
+    }
+}
+
+#[cfg(feature = "backend-blocking")]
+impl From<Bclient> for BKclient {
+    fn from(inner: Bclient) -> Self {
+        BKclient { inner, runtime: OnceLock::new() }
+    }
+}
+
+impl ClientInit for Bclient {
+    fn new(target: &str) -> Self {
+        Bclient {
+            client: Arc::default(),
+            proxy: None,
+            target: target.to_string(),
+            timeout: None,
+            request_log: None,
+            rate_limit: None,
+            dedup: None,
+            traffic: Arc::default(),
+        }
+    }
+
+    fn new_with_proxy(target: &str, proxy: &str) -> Self {
+        Bclient {
+            client: Arc::default(),
+            proxy: Some(proxy.to_string()),
+            target: target.to_string(),
+            timeout: None,
+            request_log: None,
+            rate_limit: None,
+            dedup: None,
+            traffic: Arc::default(),
+        }
+    }
+}
+
+#[cfg(feature = "backend-blocking")]
+impl ClientInit for BKclient {
+    fn new(target: &str) -> Self {
+        BKclient::from(Bclient::new(target))
+    }
+
+    fn new_with_proxy(target: &str, proxy: &str) -> Self {
+        BKclient::from(Bclient::new_with_proxy(target, proxy))
+    }
+}
+
+impl Bclient {
+    /// Client for the endpoint, proxy and timeout of `config`
+    pub fn from_config(config: &Config) -> Self {
+        let client = if config.uses_proxy() {
+            Bclient::new_with_proxy(config.get_ollama_api(), config.get_proxy())
+        } else {
+            Bclient::new(config.get_ollama_api())
+        };
+        client
+            .with_timeout(config.get_timeout())
+            .with_request_log(config.get_log_requests_to().map(RequestLog::new))
+            .with_rate_limit(config.get_max_requests_per_minute())
+    }
+
+    /// Whether `other` talks to the same endpoint the same way, so this client can be kept
+    /// in place of it
+    pub fn same_settings(&self, other: &Bclient) -> bool {
+        (&self.target, &self.proxy, self.timeout, &self.request_log, self.rate_limit, self.dedup)
+            == (&other.target, &other.proxy, other.timeout, &other.request_log, other.rate_limit, other.dedup)
+    }
+
+    /// Client for `config` that answers the same requests from the last answer like this
+    /// one, `None` if this one fits `config` already
+    pub fn for_config(&self, config: &Config) -> Option<Bclient> {
+        let rebuilt = Bclient::from_config(config).with_dedup(self.dedup);
+        (!self.same_settings(&rebuilt)).then_some(rebuilt)
+    }
+
+    /// Refuse requests for commands beyond `per_minute` in the last minute with
+    /// `BackendError::RateLimited`
+    pub fn with_rate_limit(mut self, per_minute: Option<u32>) -> Self {
+        self.rate_limit = per_minute;
+        self
+    }
+
+    /// Answer a request for commands the same as the last one, sent at most `window` after
+    /// its answer came, with that answer marked `cached` instead of asking again
+    pub fn with_dedup(mut self, window: Option<Duration>) -> Self {
+        self.dedup = window;
+        self
+    }
+
+    /// Give up on requests taking longer than `timeout`
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Record each request for commands in `request_log`
+    pub fn with_request_log(mut self, request_log: Option<RequestLog>) -> Self {
+        self.request_log = request_log;
+        self
+    }
+
+    pub async fn send_ollama(&self, data: &OllamaReq) -> Result<Vec<String>, BackendError> {
+        Ok(self.generate(data).await?.commands)
+    }
+
+    /// Like `send_ollama`, also returning the statistics and the conversation context.
+    /// Fails with `BackendError::Busy` while the answer to another request for commands is
+    /// awaited, and with `BackendError::RateLimited` beyond `with_rate_limit`.
+    pub async fn generate(&self, data: &OllamaReq) -> Result<Generation, BackendError> {
+        let key = data.dedup_key();
+        let _in_flight = match self.admit(&key, Instant::now()) {
+            Ok(Admitted::Send(in_flight)) => in_flight,
+            Ok(Admitted::Cached(generation, age)) => {
+                info!("same request as {:.1}s ago, answered from the last answer", age.as_secs_f64());
+                return Ok(generation);
+            },
+            Err(e) => {
+                info!("request not sent: {}", e);
+                return Err(e);
+            },
+        };
+        let result = self.generate_now(data).await;
+        if let (Some(_), Ok(generation)) = (self.dedup, &result) {
+            lock_traffic(&self.traffic).last = Some((key, Instant::now(), generation.clone()));
+        }
+        result
+    }
+
+    /// Whether a request for commands, `key`, can go out at `now`: one at a time does, and
+    /// `rate_limit` of them a minute. The same as the last one within `dedup` gets its answer.
+    fn admit(&self, key: &str, now: Instant) -> Result<Admitted, BackendError> {
+        let mut traffic = lock_traffic(&self.traffic);
+        if traffic.in_flight {
+            return Err(BackendError::Busy);
+        }
+        if let (Some(window), Some((last_key, answered, generation))) = (self.dedup, &traffic.last) {
+            let age = now.saturating_duration_since(*answered);
+            if last_key == key && age <= window {
+                return Ok(Admitted::Cached(Generation { cached: true, ..generation.clone() }, age));
+            }
+        }
+        let minute = Duration::from_secs(60);
+        while traffic.sent.front().is_some_and(|sent| now.saturating_duration_since(*sent) >= minute) {
+            traffic.sent.pop_front();
+        }
+        if let (Some(limit), Some(oldest)) = (self.rate_limit, traffic.sent.front()) {
+            if traffic.sent.len() >= limit as usize {
+                let retry_in = minute.saturating_sub(now.saturating_duration_since(*oldest));
+                return Err(BackendError::RateLimited { limit, retry_in });
+            }
+        }
+        traffic.sent.push_back(now);
+        traffic.in_flight = true;
+        Ok(Admitted::Send(InFlight(self.traffic.clone())))
+    }
+
+    /// `generate` without waiting its turn, for the requests of `send_ollama_multi`
+    async fn generate_now(&self, data: &OllamaReq) -> Result<Generation, BackendError> {
+        let started = Instant::now();
+        let result = self.fetch_generation(data).await;
+        log_outcome(&self.target, self.request_log.as_ref(), data, started, &result);
+        result
+    }
+
+    async fn fetch_generation(&self, data: &OllamaReq) -> Result<Generation, BackendError> {
+        let res_body = response_body(self.post(data).send().await?).await?;
+        let mut generation = parse_generation(&res_body)?;
+        generation.dropped = data.suggestion_mode.limit(&mut generation.commands);
+        if generation.dropped > 0 {
+            warn!("{} mode: left out {} more command(s)", data.suggestion_mode, generation.dropped);
+        }
+        Ok(generation)
+    }
+
+    /// The whole response of Ollama as JSON, statistics included
+    pub async fn send_raw(&self, data: &OllamaReq) -> Result<Value, BackendError> {
+        let res_body = response_body(self.post(data).send().await?).await?;
+        Ok(serde_json::from_str(&res_body)?)
+    }
+
+    /// The answer as plain text, for requests without a JSON schema like `OllamaReq::explain`
+    pub async fn send_text(&self, data: &OllamaReq) -> Result<String, BackendError> {
+        let res_body = response_body(self.post(data).send().await?).await?;
+        let ollama_res: OllamaRes = serde_json::from_str(&res_body)?;
+        Ok(ollama_res.response.trim().to_string())
+    }
+
+    /// Send `req` to each of `models` at once, for comparing their answers. The answers
+    /// are in the order of `models`; one failing does not affect the others.
+    pub async fn send_ollama_multi(&self, models: &[&str], req: &OllamaReq) -> Vec<ModelAnswer> {
+        let requests = models.iter().map(|model| async move {
+            let mut req = req.clone();
+            req.set_model(model);
+            let started = Instant::now();
+            let commands = self.generate_now(&req).await.map(|generation| generation.commands);
+            ModelAnswer { model: model.to_string(), latency: started.elapsed(), commands }
+        });
+        futures_util::future::join_all(requests).await
+    }
+
+    /// Names of the models available on the Ollama server, which also tells whether
+    /// the server is reachable at all. Gives up after 10s without a timeout set.
+    pub async fn list_models(&self) -> Result<Vec<String>, BackendError> {
+        let res = self.http().get(api_url(&self.target, "tags"))
+            .timeout(self.timeout.unwrap_or(Duration::from_secs(10)))
+            .send()
+            .await?;
+        let res_body = response_body(res).await?;
+        let tags: Tags = serde_json::from_str(&res_body)?;
+        Ok(tags.models.into_iter().map(|tag| tag.name).collect())
+    }
+
+    /// Whether `model` is loaded into memory, asked from `/api/ps`. If it is not, the next
+    /// request loads it first, which can take half a minute. Gives up after 2s, so a check
+    /// before a request never holds it up for long.
+    pub async fn model_loaded(&self, model: &str) -> Result<bool, BackendError> {
+        let res = self.http().get(api_url(&self.target, "ps"))
+            .timeout(Duration::from_secs(2))
+            .send()
+            .await?;
+        let res_body = response_body(res).await?;
+        let running: RunningModels = serde_json::from_str(&res_body)?;
+        let names: Vec<String> = running.models.into_iter().map(|tag| tag.name).collect();
+        Ok(has_model(&names, model))
+    }
+
+    /// The reqwest client, built on first use
+    fn http(&self) -> &Client {
+        self.client.get_or_init(|| {
+            let started = Instant::now();
+            let client = match &self.proxy {
+                Some(proxy) => Client::builder().proxy(Proxy::http(proxy).unwrap()).build().unwrap(),
+                None => Client::new(),
+            };
+            trace!("built the HTTP client in {:?}", started.elapsed());
+            client
+        })
+    }
+
+    fn post(&self, data: &OllamaReq) -> RequestBuilder {
+        log_request(&self.target, data);
+        let req = self.http().post(&self.target).json(data);
+        match self.timeout {
+            Some(timeout) => req.timeout(timeout),
+            None => req,
+        }
+    }
+}
+
+#[cfg(feature = "backend-blocking")]
+impl BKclient {
+    /// Client for the endpoint, proxy and timeout of `config`
+    pub fn from_config(config: &Config) -> Self {
+        BKclient::from(Bclient::from_config(config))
+    }
+
+    /// Give up on requests taking longer than `timeout`
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.inner = self.inner.with_timeout(timeout);
+        self
+    }
+
+    /// Record each request for commands in `request_log`
+    pub fn with_request_log(mut self, request_log: Option<RequestLog>) -> Self {
+        self.inner = self.inner.with_request_log(request_log);
+        self
+    }
+
+    pub fn send_ollama(&self, data: &OllamaReq) -> Result<Vec<String>, BackendError> {
+        self.block_on(self.inner.send_ollama(data))
+    }
+
+    /// See `Bclient::generate`
+    pub fn generate(&self, data: &OllamaReq) -> Result<Generation, BackendError> {
+        self.block_on(self.inner.generate(data))
+    }
+
+    /// See `Bclient::send_ollama_multi`
+    pub fn send_ollama_multi(&self, models: &[&str], data: &OllamaReq) -> Result<Vec<ModelAnswer>, BackendError> {
+        self.block_on(async { Ok(self.inner.send_ollama_multi(models, data).await) })
+    }
+
+    /// See `Bclient::send_raw`
+    pub fn send_raw(&self, data: &OllamaReq) -> Result<Value, BackendError> {
+        self.block_on(self.inner.send_raw(data))
+    }
+
+    /// See `Bclient::send_text`
+    pub fn send_text(&self, data: &OllamaReq) -> Result<String, BackendError> {
+        self.block_on(self.inner.send_text(data))
+    }
+
+    /// See `Bclient::list_models`
+    pub fn list_models(&self) -> Result<Vec<String>, BackendError> {
+        self.block_on(self.inner.list_models())
+    }
+
+    /// See `Bclient::model_loaded`
+    pub fn model_loaded(&self, model: &str) -> Result<bool, BackendError> {
+        self.block_on(self.inner.model_loaded(model))
+    }
+
+    /// Run `future` to completion on the runtime, starting it first if needed
+    fn block_on<T>(&self, future: impl Future<Output = Result<T, BackendError>>) -> Result<T, BackendError> {
+        let runtime = match self.runtime.get() {
+            Some(runtime) => runtime,
+            None => {
+                let runtime = Builder::new_current_thread().enable_all().build().map_err(BackendError::Runtime)?;
+                self.runtime.get_or_init(|| runtime)
+            },
+        };
+        runtime.block_on(future)
+    }
+}
+
+#[cfg(all(test, feature = "backend-blocking"))]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Answer the first request with `status` and `body`, whatever its path.
+    /// Returns the `/api/generate` url of the server.
+    fn mock_server(status: &'static str, body: &'static str) -> String {
+        slow_mock_server(status, body, Duration::ZERO, 1)
+    }
+
+    /// Answer the first `requests` requests like `mock_server`, each `delay` after it arrived
+    fn slow_mock_server(status: &'static str, body: &'static str, delay: Duration, requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/generate", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let stream = stream.unwrap();
+                std::thread::spawn(move || respond(stream, status, body, delay));
+            }
+        });
+        url
+    }
+
+    /// Read the whole request from `stream`, then answer it after `delay`
+    fn respond(mut stream: std::net::TcpStream, status: &str, body: &str, delay: Duration) {
+        // read the whole request before answering
+        let mut request = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let n = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            let complete = text.find("\r\n\r\n").is_some_and(|head_end| {
+                let content_length = text[..head_end]
+                    .lines()
+                    .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                    .unwrap_or(0);
+                request.len() >= head_end + 4 + content_length
+            });
+            if complete || n == 0 {
+                break;
+            }
+        }
+        std::thread::sleep(delay);
+        // a request given up on meanwhile closed the connection
+        let _ = write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body,
+        );
+    }
+
+    fn status_and_message(result: Result<impl std::fmt::Debug, BackendError>) -> (u16, String) {
+        match result {
+            Err(BackendError::Server { status, message }) => (status.as_u16(), message),
+            other => panic!("expected a server error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn server_names() {
+        assert_eq!(server_name("http://localhost:11434/api/generate"), "localhost:11434");
+        assert_eq!(server_name("https://ollama.example.com/api/generate"), "ollama.example.com:443");
+        assert_eq!(server_name("not a url"), "not a url");
+        let models = ["llama3:latest".to_string(), "mistral:7b".to_string()];
+        assert!(has_model(&models, "llama3"));
+        assert!(has_model(&models, "llama3:latest"));
+        assert!(has_model(&models, "mistral:7b"));
+        assert!(!has_model(&models, "mistral"));
+        assert!(has_model(&["codellama".to_string()], "codellama:latest"));
+    }
+
+    #[test]
+    fn missing_model() {
+        let client = BKclient::new(&mock_server("404 Not Found", r#"{"error":"model 'llama9' not found"}"#));
+        let error = client.send_ollama(&OllamaReq::new("llama9")).unwrap_err();
+        assert!(error.is_missing_model());
+        assert_eq!(error.to_string(), "Ollama answered 404 Not Found: model 'llama9' not found");
+
+        // a wrong path is a 404 too, but not about the model
+        let client = BKclient::new(&mock_server("404 Not Found", "404 page not found"));
+        let error = client.send_ollama(&OllamaReq::new("llama3")).unwrap_err();
+        assert!(!error.is_missing_model());
+        assert_eq!(error.to_string(), "Ollama answered 404 Not Found: 404 page not found");
+    }
+
+    #[test]
+    fn bad_request() {
+        let client = BKclient::new(&mock_server("400 Bad Request", r#"{"error":"invalid format: expected \"json\" or a JSON schema"}"#));
+        let (status, message) = status_and_message(client.send_text(&OllamaReq::explain("llama3", "ls", ShellType::Bash, Language::English)));
+        assert_eq!((status, message.as_str()), (400, r#"invalid format: expected "json" or a JSON schema"#));
+    }
+
+    #[test]
+    fn internal_server_error() {
+        let client = BKclient::new(&mock_server("500 Internal Server Error", r#"{"error":"llama runner process has terminated"}"#));
+        let (status, message) = status_and_message(client.send_raw(&OllamaReq::new("llama3")));
+        assert_eq!((status, message.as_str()), (500, "llama runner process has terminated"));
+
+        // a body that is not Ollama's, like the page of a proxy in between
+        let client = BKclient::new(&mock_server("502 Bad Gateway", ""));
+        let error = client.list_models().unwrap_err();
+        assert_eq!(error.to_string(), "Ollama answered 502 Bad Gateway");
+    }
+
+    #[test]
+    fn trimming_order() {
+        assert_eq!((estimate_tokens(""), estimate_tokens("abcd"), estimate_tokens("abcde")), (0, 1, 2));
+
+        let mut req = OllamaReq::new("llama3");
+        req.set_context(vec![1; 500]);
+        req.prompt_with(&"x".repeat(400), "free disk space");
+        let prompt = estimate_tokens("free disk space");
+        let system = estimate_tokens(&req.system);
+        let without_info = estimate_tokens(&commands_system(Language::English, shell::detect(), None, SuggestionMode::Auto));
+        assert_eq!(req.estimated_tokens(), system + 500 + 100 + prompt);
+
+        // within the budget, nothing is left out
+        let fit = req.clone().fit(system + 600 + prompt);
+        assert_eq!((fit.trimmed.as_slice(), fit.warning()), (&[][..], None));
+
+        // the conversation goes first
+        let mut history = req.clone();
+        let fit = history.fit(system + 100 + prompt);
+        assert_eq!(fit.trimmed, [Trimmed::History]);
+        assert!(history.context.is_empty());
+        assert_eq!(history.prompt.len(), 400 + "free disk space".len());
+        assert_eq!(fit.warning().unwrap(), format!("Left out the conversation so far to keep the prompt within max_prompt_tokens ({})", fit.max_tokens));
+
+        // then the attached context, then the system details
+        let mut attached = req.clone();
+        assert_eq!(attached.fit(system + prompt).trimmed, [Trimmed::History, Trimmed::Attached]);
+        assert_eq!((attached.prompt.as_str(), attached.system_info), ("free disk space", true));
+        let mut info = req.clone();
+        let fit = info.fit(without_info + prompt);
+        assert_eq!(fit.trimmed, [Trimmed::History, Trimmed::Attached, Trimmed::SystemInfo]);
+        assert_eq!((info.prompt.as_str(), info.system.as_str(), fit.is_over()), ("free disk space", commands_system(Language::English, shell::detect(), None, SuggestionMode::Auto).as_str(), false));
+
+        // never the user's own text
+        let mut over = req.clone();
+        let fit = over.fit(1);
+        assert_eq!((over.prompt.as_str(), fit.tokens, fit.is_over()), ("free disk space", without_info + prompt, true));
+        assert_eq!(fit.warning().unwrap(), format!("The prompt is about {} tokens, over max_prompt_tokens (1), the model may cut it", fit.tokens));
+        // nothing to leave out of a plain request
+        let mut plain = OllamaReq::explain("llama3", "ls -la", ShellType::Bash, Language::English);
+        assert!(plain.fit(1).trimmed.is_empty());
+    }
+
+    #[test]
+    fn single_suggestions() {
+        let mut req = OllamaReq::new("llama3");
+        let body = |req: &OllamaReq| serde_json::to_value(req).unwrap();
+        assert!(body(&req)["format"]["properties"]["commands"].get("maxItems").is_none());
+        req.set_suggestion_mode(SuggestionMode::Single);
+        assert_eq!(body(&req)["format"]["properties"]["commands"]["maxItems"], 1);
+        let system = body(&req)["system"].as_str().unwrap().to_string();
+        assert!(system.contains(Language::English.strings().instruction(SuggestionMode::Single)));
+        assert!(system.ends_with(&system_info::current().summary()));
+
+        // models do not always keep to the schema
+        let answer = r#"{"model": "llama3", "created_at": "2025-01-01T00:00:00Z", "done": true, "done_reason": "stop",
+            "response": "{\"commands\": [\"df -h\", \"du -sh *\", \"ncdu\"]}", "context": [],
+            "total_duration": 0, "load_duration": 0, "prompt_eval_count": 0, "prompt_eval_duration": 0,
+            "eval_count": 0, "eval_duration": 0}"#;
+        let generation = BKclient::new(&mock_server("200 OK", answer)).generate(&req).unwrap();
+        assert_eq!((generation.commands.as_slice(), generation.dropped), (&["df -h".to_string()][..], 2));
+        assert!(generation.notice().unwrap().contains("2 more"));
+        req.set_suggestion_mode(SuggestionMode::Plan);
+        let generation = BKclient::new(&mock_server("200 OK", answer)).generate(&req).unwrap();
+        assert_eq!((generation.commands.len(), generation.notice()), (3, None));
+    }
+
+    #[test]
+    fn request_fixtures() {
+        let mut req = OllamaReq::for_shell("llama3", ShellType::Bash);
+        req.prompt_with("Commands run so far: make\n", "now install it");
+        req.set_seed(Some(7));
+        assert_eq!(req.prompt_text(), "Commands run so far: make\nnow install it");
+        assert!(req.system_prompt().starts_with("You are Bash expert"));
+        assert_eq!(req.format(), Some(&SuggestionMode::Auto.schema()));
+        assert_eq!(OllamaReq::explain("llama3", "ls", ShellType::Bash, Language::English).format(), None);
+
+        // what is printed is what is sent, and reads back the same
+        let json = req.to_pretty_json();
+        assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), serde_json::to_value(&req).unwrap());
+        let read: OllamaReq = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.to_pretty_json(), json);
+        assert_eq!((read.model(), read.seed()), ("llama3", Some(7)));
+
+        // a fixture needs only what a request sends anyway
+        let read: OllamaReq = serde_json::from_str(r#"{"model": "mistral", "prompt": "free disk space", "stream": false, "system": "You are Bash expert"}"#).unwrap();
+        assert_eq!((read.prompt_text(), read.format(), read.seed()), ("free disk space", None, None));
+        let mut copy = req.clone();
+        assert_eq!(copy, req);
+        copy.set_seed(None);
+        assert_ne!(copy, req);
+    }
+
+    #[test]
+    fn client_built_on_first_use() {
+        let client = Bclient::new_with_proxy("http://localhost:11434/api/generate", "http://localhost:3128");
+        let clone = client.clone();
+        assert!(client.client.get().is_none());
+        clone.http();
+        // shared by the clones
+        assert!(client.client.get().is_some());
+    }
+
+    #[test]
+    fn chosen_shell() {
+        let mut req = OllamaReq::new("llama3");
+        assert_eq!(req.shell(), shell::detect());
+        req.set_shell(ShellType::Fish);
+        assert!(req.system.starts_with("You are Fish expert, your task is give Fish commands"));
+        assert!(req.system.ends_with(&system_info::current().summary()));
+        let explain = OllamaReq::explain("llama3", "Get-ChildItem", ShellType::PowerShell, Language::English);
+        assert!(explain.system.starts_with("You are PowerShell expert. Explain what this PowerShell command does"));
+    }
+
+    #[test]
+    fn project_notes_start_the_system_prompt() {
+        let mut req = OllamaReq::for_shell("llama3", ShellType::Bash);
+        let file = ContextFile { path: "/src/app/AURISH.md".into(), text: "Build with `just`.\n".to_string(), truncated: false };
+        req.set_project_context(Some(&file));
+        assert!(req.system.starts_with("Notes on the project the commands run in, from /src/app/AURISH.md:\nBuild with `just`.\n\nYou are Bash expert"));
+        // kept when the rest of the system prompt changes
+        req.set_shell(ShellType::Zsh);
+        req.fit(1);
+        assert_eq!(req.system, format!("{}{}", file.preamble(), commands_system(Language::English, ShellType::Zsh, None, SuggestionMode::Auto)));
+        req.set_project_context(None);
+        assert!(req.system.starts_with("You are Zsh expert"));
+    }
+
+    #[test]
+    fn loaded_models() {
+        assert_eq!(api_url("http://localhost:11434/api/generate", "ps"), "http://localhost:11434/api/ps");
+        assert_eq!(api_url("http://localhost:11434/", "tags"), "http://localhost:11434/api/tags");
+
+        let ps = r#"{"models":[{"name":"llama3:latest","model":"llama3:latest","size":5137025024,
+            "digest":"365c0bd3c000","expires_at":"2025-01-01T00:05:00Z","size_vram":5137025024}]}"#;
+        let client = BKclient::new(&mock_server("200 OK", ps));
+        assert!(client.model_loaded("llama3").unwrap());
+        let client = BKclient::new(&mock_server("200 OK", ps));
+        assert!(!client.model_loaded("mistral").unwrap());
+        let client = BKclient::new(&mock_server("200 OK", r#"{"models":[]}"#));
+        assert!(!client.model_loaded("llama3:latest").unwrap());
+
+        // servers from before /api/ps
+        let client = BKclient::new(&mock_server("404 Not Found", "404 page not found"));
+        assert_eq!(status_and_message(client.model_loaded("llama3")).0, 404);
+        let client = BKclient::new(&mock_server("200 OK", "{}"));
+        assert!(matches!(client.model_loaded("llama3"), Err(BackendError::ParseError(_))));
+    }
+
+    #[test]
+    fn long_error_bodies_are_cut() {
+        let long = "x".repeat(MAX_ERROR_BODY + 50);
+        let BackendError::Server { message, .. } = server_error(StatusCode::INTERNAL_SERVER_ERROR, &long) else {
+            unreachable!();
+        };
+        assert_eq!(message.chars().count(), MAX_ERROR_BODY + 1);
+        assert!(message.ends_with('…'));
+    }
+
+    const COMMANDS: &str = r#"{"model": "llama3", "created_at": "2025-01-01T00:00:00Z", "done": true, "done_reason": "stop",
+        "response": "{\"commands\": [\"df -h\"]}", "context": [7],
+        "total_duration": 0, "load_duration": 0, "prompt_eval_count": 0, "prompt_eval_duration": 0,
+        "eval_count": 0, "eval_duration": 0}"#;
+
+    #[tokio::test]
+    async fn one_request_at_a_time() {
+        let client = Bclient::new(&slow_mock_server("200 OK", COMMANDS, Duration::from_millis(300), 3));
+        let req = OllamaReq::new("llama3");
+        let first = tokio::spawn({
+            let (client, req) = (client.clone(), req.clone());
+            async move { client.generate(&req).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // clones wait for the same answer
+        assert!(matches!(client.clone().generate(&req).await, Err(BackendError::Busy)));
+        assert_eq!(first.await.unwrap().unwrap().commands, ["df -h"]);
+        assert_eq!(client.generate(&req).await.unwrap().commands, ["df -h"]);
+
+        // a request given up on lets the next one go
+        let given_up = tokio::spawn({
+            let (client, req) = (client.clone(), req.clone());
+            async move { client.generate(&req).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        given_up.abort();
+        assert!(given_up.await.unwrap_err().is_cancelled());
+        assert!(client.admit("next", Instant::now()).is_ok());
+
+        // comparing asks several models at once
+        let client = Bclient::new(&slow_mock_server("200 OK", COMMANDS, Duration::from_millis(100), 2));
+        let answers = client.send_ollama_multi(&["llama3", "mistral"], &req).await;
+        assert!(answers.iter().all(|answer| answer.commands.is_ok()), "{:?}", answers);
+    }
+
+    #[tokio::test]
+    async fn rate_limit() {
+        let client = Bclient::new(&slow_mock_server("200 OK", COMMANDS, Duration::ZERO, 2)).with_rate_limit(Some(2));
+        let req = OllamaReq::new("llama3");
+        client.generate(&req).await.unwrap();
+        client.generate(&req).await.unwrap();
+        match client.generate(&req).await {
+            Err(BackendError::RateLimited { limit: 2, retry_in }) => assert!(retry_in > Duration::from_secs(55), "{:?}", retry_in),
+            other => panic!("expected the limit, got {:?}", other),
+        }
+        // a minute later, two more can go
+        let later = Instant::now() + Duration::from_secs(60);
+        assert!(client.admit("next", later).is_ok());
+        assert!(client.admit("next", later).is_ok());
+        assert!(matches!(client.admit("next", later), Err(BackendError::RateLimited { retry_in, .. }) if retry_in == Duration::from_secs(60)));
+        assert_eq!(
+            BackendError::RateLimited { limit: 2, retry_in: Duration::from_millis(4200) }.to_string(),
+            "2 requests sent in the last minute already, try again in 5s",
+        );
+    }
+
+    #[tokio::test]
+    async fn same_request_answered_from_cache() {
+        let client = Bclient::new(&slow_mock_server("200 OK", COMMANDS, Duration::from_millis(50), 2)).with_dedup(Some(DEDUP_WINDOW));
+        let mut req = OllamaReq::new("llama3");
+        req.prompt("free disk space");
+        let first = client.generate(&req).await.unwrap();
+        assert!(!first.cached);
+
+        // the conversation the answer continues does not tell them apart
+        req.set_context(first.context.clone());
+        let again = client.generate(&req).await.unwrap();
+        assert_eq!(again, Generation { cached: true, ..first.clone() });
+
+        // another seed does, as does a prompt or the time passed
+        req.set_seed(Some(7));
+        assert!(!client.generate(&req).await.unwrap().cached);
+        let key = req.dedup_key();
+        assert!(matches!(client.admit(&key, Instant::now()), Ok(Admitted::Cached(..))));
+        assert!(matches!(client.admit(&key, Instant::now() + DEDUP_WINDOW + Duration::from_secs(1)), Ok(Admitted::Send(_))));
+        req.prompt("free more disk space");
+        assert!(matches!(client.admit(&req.dedup_key(), Instant::now()), Ok(Admitted::Send(_))));
+
+        // without `with_dedup`, each one is asked
+        assert!(Bclient::new("http://127.0.0.1:9/api/generate").dedup.is_none());
+    }
+}
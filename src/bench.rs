@@ -0,0 +1,133 @@
+//! Benchmark of the Ollama endpoint for `aurish-cli bench`
+//!
+//! A few fixed prompts are sent one after another with the usual request, and each answer
+//! is timed. Failed requests are counted rather than ending the run, so a flaky server
+//! still gets a report.
+
+use std::time::{Duration, Instant};
+use crate::backend::{BKclient, GenerationStats, OllamaReq};
+use crate::error::BackendError;
+
+/// Prompts sent in turn, everyday requests of different length
+pub const PROMPTS: [&str; 3] = [
+    "Show all files within the current path, hidden ones included.",
+    "Find the five largest files under the current directory.",
+    "Create a folder named backup, copy every .txt file of the current directory into it and compress it into backup.tar.gz.",
+];
+
+/// How one request of a benchmark went
+#[derive(Debug, Clone, PartialEq)]
+pub enum Round {
+    /// Answered with a valid command array
+    Ok { latency: Duration, stats: GenerationStats },
+    /// Answered, but not with the `commands` array asked for
+    Invalid { latency: Duration },
+    /// The request failed, with the error message
+    Failed(String),
+}
+
+impl Round {
+    pub fn latency(&self) -> Option<Duration> {
+        match self {
+            Round::Ok { latency, .. } | Round::Invalid { latency } => Some(*latency),
+            Round::Failed(_) => None,
+        }
+    }
+}
+
+/// Send `rounds` requests for `model` through `client`, calling `progress` after each one
+pub fn run(client: &BKclient, model: &str, rounds: usize, mut progress: impl FnMut(usize, &Round)) -> Vec<Round> {
+    (0..rounds)
+        .map(|i| {
+            let mut req = OllamaReq::new(model);
+            req.prompt(PROMPTS[i % PROMPTS.len()]);
+            let start = Instant::now();
+            let round = match client.generate(&req) {
+                Ok(generation) => Round::Ok { latency: start.elapsed(), stats: generation.stats },
+                Err(BackendError::ParseError(_)) => Round::Invalid { latency: start.elapsed() },
+                Err(e) => Round::Failed(e.to_string()),
+            };
+            progress(i + 1, &round);
+            round
+        })
+        .collect()
+}
+
+/// Minimum, average and maximum of `values`, `None` if there are none
+fn spread(values: &[f64]) -> Option<(f64, f64, f64)> {
+    if values.is_empty() {
+        return None;
+    }
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let avg = values.iter().sum::<f64>() / values.len() as f64;
+    Some((min, avg, max))
+}
+
+fn row(name: &str, unit: &str, values: &[f64]) -> String {
+    match spread(values) {
+        Some((min, avg, max)) => format!("{:<14}{:>10.2}{:>10.2}{:>10.2}  {}", name, min, avg, max, unit),
+        None => format!("{:<14}{:>10}{:>10}{:>10}", name, "-", "-", "-"),
+    }
+}
+
+/// Table of the results of `rounds`
+pub fn report(rounds: &[Round]) -> String {
+    let latencies: Vec<f64> = rounds.iter()
+        .filter_map(Round::latency)
+        .map(|latency| latency.as_secs_f64())
+        .collect();
+    let stats: Vec<&GenerationStats> = rounds.iter()
+        .filter_map(|round| match round {
+            Round::Ok { stats, .. } => Some(stats),
+            _ => None,
+        })
+        .collect();
+    let speeds: Vec<f64> = stats.iter().filter_map(|stats| stats.tokens_per_sec()).collect();
+    let loads: Vec<f64> = stats.iter().map(|stats| stats.load_duration.as_secs_f64()).collect();
+    let failed = rounds.iter().filter(|round| matches!(round, Round::Failed(_))).count();
+
+    [
+        format!("{:<14}{:>10}{:>10}{:>10}", "", "min", "avg", "max"),
+        row("latency", "s", &latencies),
+        row("speed", "tokens/s", &speeds),
+        row("load", "s", &loads),
+        format!("{} requests: {} valid commands, {} invalid answers, {} failed",
+            rounds.len(), stats.len(), latencies.len() - stats.len(), failed),
+    ].join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spread_of_values() {
+        assert_eq!(spread(&[]), None);
+        assert_eq!(spread(&[2.0]), Some((2.0, 2.0, 2.0)));
+        assert_eq!(spread(&[3.0, 1.0, 2.0]), Some((1.0, 2.0, 3.0)));
+    }
+
+    #[test]
+    fn report_counts_rounds() {
+        let stats = GenerationStats {
+            eval_count: 50,
+            eval_duration: Duration::from_secs(2),
+            total_duration: Duration::from_secs(3),
+            load_duration: Duration::from_millis(500),
+        };
+        let rounds = [
+            Round::Ok { latency: Duration::from_secs(3), stats },
+            Round::Invalid { latency: Duration::from_secs(1) },
+            Round::Failed("Ollama request error: connection refused".to_string()),
+        ];
+        let table = report(&rounds);
+        assert!(table.contains("latency             1.00      2.00      3.00  s"), "{}", table);
+        assert!(table.contains("speed              25.00     25.00     25.00  tokens/s"), "{}", table);
+        assert!(table.contains("load                0.50      0.50      0.50  s"), "{}", table);
+        assert!(table.ends_with("3 requests: 1 valid commands, 1 invalid answers, 1 failed"));
+
+        let table = report(&rounds[2..]);
+        assert!(table.contains("latency                -         -         -"), "{}", table);
+    }
+}
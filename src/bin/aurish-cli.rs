@@ -1,135 +1,987 @@
-use clap::{Subcommand, Parser, CommandFactory};
-use std::fs;
-use std::fs::File;
-use std::io::Write;
-use std::env;
-use std::path::Path;
-use serde::de::Error;
-use aurish::shared::Config;
-use aurish::backend::{BKclient, OllamaReq, ClientInit};
-use aurish::frontend::App_cli;
-
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    /// Set proxy (e.g., --set-proxy http://proxy.example.com:port)
-    #[arg(long = "set-proxy")]
-    set_proxy: Option<String>,
-
-    /// Set ollama API (e.g., --set-ollama-api "http://localhost:11434/api/generate")
-    #[arg(long = "set-ollama-api")]
-    set_ollama_api: Option<String>,
-
-    /// Set model (e.g., --set-model llama3:8b)
-    #[arg(long = "set-model")]
-    set_model: Option<String>,
-
-    /// Subcommand to execute: show or dry-run or run
-    #[command(subcommand)]
-    command: Option<Commands>,
-}
-
-#[derive(Subcommand, Debug)]
-enum Commands {
-    /// Show current configuration
-    Show,
-    /// Execute a dry run of the configuration
-    // #[command(alias = "dry-run")]
-    DryRun,
-    /// Execute aurish-cli interactive version (lightweight compare to aurish)
-    // #[command(alias = "run")]
-    Run,
-}
-
-fn main() -> Result<(), Box<dyn std::error::Error>>{
-    let args = Args::parse();
-    let mut config = get_config().unwrap();
-
-    if let Some(proxy) = args.set_proxy {
-        config.set_proxy(proxy);
-        write_to(config).unwrap();
-        return Ok(());
-    }
-    if let Some(api) = args.set_ollama_api {
-        config.set_ollama_api(api);
-        write_to(config).unwrap();
-        return Ok(());
-    }
-    if let Some(model) = args.set_model {
-        config.set_model(model);
-        write_to(config).unwrap();
-        return Ok(());
-    }
-
-    if let Some(cmd) = args.command {
-        match cmd {
-            Commands::Show => {
-                println!("Config: {:?}", config);
-                return Ok(())
-            },
-            Commands::DryRun => {
-                dry_run(config);
-                return Ok(())
-            },
-            Commands::Run => {
-                run_app_cli(config).unwrap();
-                return Ok(())
-            }
-        }
-    } else {
-        Args::command().print_help().unwrap();
-        println!();
-    }
-
-    Ok(())
-}
-
-pub fn get_config() -> Result<Config, Box<dyn std::error::Error>> {
-    if let Ok(contents) = fs::read_to_string("config.json") {
-        let config: Config = serde_json::from_str(&contents).unwrap();
-        Ok(config)
-    } else {
-        let default_config = Config::default();
-        let json_str = serde_json::to_string_pretty(&default_config).unwrap();
-        let path = Path::new("./config.json");
-        let mut file = File::create(path).unwrap();
-        file.write_all(json_str.as_bytes())?;
-        Ok(default_config)
-    }
-}
-
-pub fn write_to(config: Config) -> Result<(), Box<dyn std::error::Error>> {
-    let json_str = serde_json::to_string_pretty(&config)?;
-    let path = Path::new("./config.json");
-    let mut file = File::open(path)?;
-    file.write_all(json_str.as_bytes())?;
-    Ok(())
-}
-
-pub fn dry_run(config: Config) {
-    let mut req = OllamaReq::new(&config.get_model());
-    println!("Data to send: {:#?}", &req);
-    req.prompt("How to show all files within current path? And then create a folder named test under current path.");
-    if config.uses_proxy() {
-        let client = BKclient::new_with_proxy(&config.get_ollama_api(), &config.get_proxy());
-        let res = client.send_ollama(&req).unwrap();
-        println!("ollama response: {:?}", res)
-    } else {
-        let client = BKclient::new(&config.get_ollama_api());
-        let res = client.send_ollama(&req).unwrap();
-        println!("ollama response: {:?}", res)
-    }
-}
-
-pub fn run_app_cli(config: Config) -> Result<(), rustyline::error::ReadlineError> {
-    if config.uses_proxy() {
-        let client = BKclient::new_with_proxy(&config.get_ollama_api(), &config.get_proxy());
-        let mut app = App_cli::new(&config.get_model());
-        app.run(client)
-    } else {
-        let client = BKclient::new(&config.get_ollama_api());
-        let mut app = App_cli::new(&config.get_model());
-        app.run(client)
-    }
-}
-
+use clap::{Subcommand, Parser, CommandFactory, ValueHint};
+use clap_complete::Shell;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
+#[cfg(feature = "watch")]
+use std::time::{SystemTime, UNIX_EPOCH};
+use aurish::config::{load_config, resolve_path, write_config, Config, Overrides, ProfileArgs, Settings};
+use aurish::error::{CliError, ConfigError, FavoritesError, HistoryError};
+use aurish::favorites::{self, Favorites};
+use aurish::templates::Templates;
+use aurish::backend::{self, BKclient, OllamaReq};
+use aurish::bench::{self, Round};
+use aurish::frontend::App_cli;
+use aurish::report::RunReport;
+use aurish::session::{self, Session};
+use aurish::transcripts::{self, Found, Transcripts};
+use aurish::suggestions::SuggestionMode;
+use aurish::script;
+use aurish::system_info;
+use aurish::logging::{self, LogArgs};
+use aurish::output;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use aurish::shell::{self, IShell};
+use aurish::shell_history::ShellHistory;
+use aurish::utils::{read_commands, read_prompt, sanitize_commands, wrap_text, MAX_PROMPT_BYTES};
+#[cfg(feature = "watch")]
+use aurish::watch;
+use aurish::workdir::ContextLevel;
+use aurish::project_context;
+use aurish::i18n::Language;
+
+#[derive(Parser, Debug)]
+#[command(name = "aurish-cli", version, about, long_about = None, propagate_version = true)]
+struct Args {
+    /// Config file to use, instead of $AURISH_CONFIG or the user config directory
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    config: Option<PathBuf>,
+
+    #[command(flatten)]
+    log: LogArgs,
+
+    /// Print without colors, also done when output is not a terminal or NO_COLOR is set
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Set proxy (e.g., --set-proxy http://proxy.example.com:port)
+    #[arg(long = "set-proxy", value_hint = ValueHint::Url)]
+    set_proxy: Option<String>,
+
+    /// Stop using a proxy
+    #[arg(long = "unset-proxy", conflicts_with = "set_proxy")]
+    unset_proxy: bool,
+
+    /// Set ollama API (e.g., --set-ollama-api "http://localhost:11434/api/generate")
+    #[arg(long = "set-ollama-api", value_hint = ValueHint::Url)]
+    set_ollama_api: Option<String>,
+
+    /// Set model (e.g., --set-model llama3:8b)
+    #[arg(long = "set-model")]
+    set_model: Option<String>,
+
+    /// Subcommand to execute
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Show current configuration
+    Show,
+    /// Check that Ollama answers and has the model, and show what was detected about this
+    /// system, which the model is told about, and the shell history file
+    Doctor,
+    /// Create the config file step by step, testing the connection to Ollama
+    Init {
+        /// Replace an existing config file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Send a test prompt to check that Ollama answers with the current configuration
+    // #[command(alias = "dry-run")]
+    DryRun {
+        /// Prompt to send instead of the built-in one
+        prompt: Option<String>,
+        /// Print the whole response of Ollama as JSON, statistics included
+        #[arg(long)]
+        raw: bool,
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+    /// Start the interactive version of aurish-cli (lightweight compared to aurish)
+    // #[command(alias = "run")]
+    Run {
+        /// Ask before running each command, also set by `"confirm_commands": true` in the config
+        #[arg(long)]
+        confirm: bool,
+        /// Queue every suggested command instead of asking which ones to run, also set by
+        /// `"skip_selection": true` in the config
+        #[arg(long)]
+        no_select: bool,
+        /// Start by asking the prompt in this file
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        initial_prompt_file: Option<PathBuf>,
+        /// Ask the model to correct a command that fails, running each correction once
+        /// confirmed, also set by `"auto_fix": true` in the config
+        #[arg(long)]
+        auto_fix: bool,
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+    /// Review and run the commands in a file, a line each, like suggested ones in the
+    /// interactive aurish-cli, e.g. steps copied from a wiki
+    Queue {
+        /// The file, blank lines are left out and a line ending in `\` goes on with the next
+        #[arg(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+        /// Ask before running each command, also set by `"confirm_commands": true` in the config
+        #[arg(long)]
+        confirm: bool,
+        /// Ask the model to correct a command that fails, also set by `"auto_fix": true` in the config
+        #[arg(long)]
+        auto_fix: bool,
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+    /// Send one prompt and print the suggested commands, one per line
+    Ask {
+        /// The prompt, read from stdin if it is `-` or left out while stdin is not a terminal.
+        /// With --template, the arguments of the template.
+        prompt: Option<String>,
+        /// Read the prompt from this file instead
+        #[arg(long, conflicts_with = "prompt", value_hint = ValueHint::FilePath)]
+        file: Option<PathBuf>,
+        /// Ask the prompt of this template in templates.toml, e.g. `-t install ripgrep`
+        #[arg(short = 't', long, value_name = "NAME", conflicts_with = "file")]
+        template: Option<String>,
+        /// Print `{"commands": [...]}` instead
+        #[arg(long)]
+        json: bool,
+        /// Run the commands one after another, stopping at the first failure with its exit code
+        #[arg(long, conflicts_with = "json")]
+        execute: bool,
+        /// Ask for the single best command instead of `suggestion_mode` of the config
+        #[arg(long)]
+        single: bool,
+        /// With --file, ask again each time the file changes, until Ctrl-C
+        #[arg(long, requires = "file", conflicts_with_all = ["execute", "save_script", "compare"])]
+        watch: bool,
+        /// Also save the commands as a script for the current shell, e.g. `plan.sh`
+        #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath)]
+        save_script: Option<PathBuf>,
+        /// Replace the file given to --save-script if it exists
+        #[arg(long, requires = "save_script")]
+        force: bool,
+        /// With --execute, write a JSON report of the commands run: exit codes, durations
+        /// and output samples
+        #[arg(long, value_name = "PATH", requires = "execute", value_hint = ValueHint::FilePath)]
+        report: Option<PathBuf>,
+        /// Ask each of these models at once and print their commands side by side, e.g.
+        /// `llama3,mistral`
+        #[arg(long, value_name = "MODELS", value_delimiter = ',', conflicts_with_all = ["execute", "save_script"])]
+        compare: Vec<String>,
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+    /// Ask the model what a command does before running it
+    Explain {
+        /// The command, read from stdin if it is `-` or left out while stdin is not a terminal
+        #[arg(value_hint = ValueHint::CommandString)]
+        command: Option<String>,
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+    /// Time a few requests to the Ollama endpoint and report latency and generation speed
+    Bench {
+        /// Number of requests to send
+        #[arg(short = 'n', long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..))]
+        count: u64,
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+    /// List the models on the Ollama server, the configured one marked with `*`
+    Models {
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+    /// List, switch, add or remove profiles, named combinations of endpoint and model
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+    /// List, save, remove or run favorites, commands saved under a name
+    Fav {
+        #[command(subcommand)]
+        action: FavAction,
+    },
+    /// List the saved sessions, newest first, or show or search them
+    History {
+        #[command(subcommand)]
+        action: Option<HistoryAction>,
+        /// Sessions to list
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+        /// Print JSON instead, for scripts
+        #[arg(long, global = true)]
+        json: bool,
+    },
+    /// Print the completion script for a shell, e.g. `source <(aurish-cli completions bash)`
+    Completions {
+        /// Shell to complete in
+        shell: Shell,
+    },
+}
+
+/// Exit codes: 0 on success, 1 if the config or the request to Ollama failed, 2 for invalid
+/// usage, and for `ask --execute` the exit code of the first failing command
+#[derive(Subcommand, Debug)]
+enum ProfileAction {
+    /// List the profiles, the active one marked with `*`
+    List,
+    /// Use a profile from now on, `default` being the top-level settings
+    Use {
+        name: String,
+    },
+    /// Add a profile, e.g. `profile add remote --api http://gpu:11434/api/generate --model mixtral`
+    Add {
+        name: String,
+        #[command(flatten)]
+        settings: ProfileArgs,
+    },
+    /// Remove a profile
+    Remove {
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum FavAction {
+    /// List the favorites, oldest first
+    List,
+    /// Save a command as a favorite, e.g. `fav add ports 'ss -tlnp'`
+    Add {
+        name: String,
+        /// The command, read from stdin if it is `-` or left out while stdin is not a terminal
+        #[arg(value_hint = ValueHint::CommandString)]
+        command: Option<String>,
+        /// Replace a favorite of the same name without asking
+        #[arg(long)]
+        force: bool,
+    },
+    /// Remove a favorite
+    Rm {
+        name: String,
+    },
+    /// Run a favorite in the current directory, exiting with its exit code if it fails
+    Run {
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum HistoryAction {
+    /// Print the transcript of a session, given its id from `history`
+    Show {
+        id: String,
+    },
+    /// List the prompts and commands of every session containing a text, ignoring case
+    /// unless it has capitals
+    Search {
+        text: String,
+    },
+}
+
+impl Commands {
+    /// Flags changing the config for this run only
+    fn overrides(&self) -> Option<&Overrides> {
+        match self {
+            Commands::DryRun { overrides, .. }
+            | Commands::Run { overrides, .. }
+            | Commands::Queue { overrides, .. }
+            | Commands::Ask { overrides, .. }
+            | Commands::Explain { overrides, .. }
+            | Commands::Bench { overrides, .. }
+            | Commands::Models { overrides } => Some(overrides),
+            Commands::Show | Commands::Doctor | Commands::Init { .. } | Commands::Profile { .. } | Commands::Fav { .. }
+            | Commands::History { .. } | Commands::Completions { .. } => None,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            output::error(&e);
+            if let Some(hint) = e.hint() {
+                output::hint(hint);
+            }
+            ExitCode::from(e.exit_code())
+        },
+    }
+}
+
+fn run(args: Args) -> Result<(), CliError> {
+    if args.no_color {
+        output::disable_color();
+    }
+    logging::init(&args.log, None)
+        .map_err(|e| CliError::Usage(format!("could not start the log: {}", e)))?;
+    if let Some(Commands::Completions { shell }) = args.command {
+        clap_complete::generate(shell, &mut Args::command(), "aurish-cli", &mut io::stdout());
+        return Ok(());
+    }
+    // runs before loading, which would create the config file with the defaults
+    if let Some(Commands::Init { force }) = args.command {
+        return init(&resolve_path(args.config.as_deref()), force);
+    }
+    let (mut config, config_path) = load_config(args.config.as_deref(), true)?;
+    log::info!("config {}: model={} api={}", config_path.display(), config.get_model(), config.get_ollama_api());
+    log::debug!("{:?}", config);
+
+    let settings = Settings {
+        ollama_api: args.set_ollama_api,
+        model: args.set_model,
+        proxy: args.set_proxy,
+        unset_proxy: args.unset_proxy,
+    };
+    if !settings.is_empty() {
+        // `ask` keeps stdout for the commands
+        let summary = |line: &str| match args.command {
+            Some(Commands::Ask { .. }) => output::note(line),
+            _ => output::message(line),
+        };
+        let changes = settings.apply(&mut config)?;
+        if changes.is_empty() {
+            summary("Nothing changed");
+        } else {
+            changes.iter().for_each(|change| summary(change));
+            // applied again to the file as it is now, so a model the TUI saved meanwhile stays
+            Config::update(&config_path, |saved| settings.apply(saved))?;
+        }
+        // with a subcommand, the new settings are used for it right away
+        if args.command.is_none() {
+            return Ok(());
+        }
+    }
+    for field in config.unknown_fields() {
+        output::warning(format!("unknown field `{}` in {}, ignored", field, config_path.display()));
+    }
+    if let Some(code) = config.unknown_language() {
+        output::warning(format!("unknown language `{}` in {}, using English; known languages are {}", code, config_path.display(), Language::codes()));
+    }
+    if let Err(problems) = config.validate() {
+        let error = ConfigError::Problems { path: config_path.clone(), problems };
+        match args.command {
+            // these help fixing the config or do not need it, so they still work
+            Some(Commands::Show | Commands::Doctor | Commands::Profile { .. } | Commands::Fav { .. } | Commands::History { .. }) => output::error(error),
+            _ => return Err(error.into()),
+        }
+    }
+    // after saving, so they never end up in the config file
+    if let Some(overrides) = args.command.as_ref().and_then(Commands::overrides) {
+        overrides.apply(&mut config)?;
+        log::info!("for this run: model={} api={} timeout={:?}", config.get_model(), config.get_ollama_api(), config.get_timeout());
+    }
+
+    match args.command {
+        Some(Commands::Show) => {
+            output::message(format!("Config file: {}", config_path.display()));
+            output::message(format!("Profile: {}", config.active_profile()));
+            output::message(format!("Config: {:?}", config));
+            Ok(())
+        },
+        Some(Commands::Doctor) => {
+            doctor(&config);
+            Ok(())
+        },
+        Some(Commands::DryRun { prompt, raw, .. }) => dry_run(config, prompt, raw),
+        Some(Commands::Run { confirm, no_select, initial_prompt_file, auto_fix, .. }) => {
+            let initial_prompt = initial_prompt_file.as_deref().map(prompt_from_file).transpose()?;
+            run_app_cli(config, confirm, no_select, auto_fix, initial_prompt, Vec::new())
+        },
+        Some(Commands::Queue { file, confirm, auto_fix, .. }) => {
+            let commands = read_commands(&file).map_err(|e| CliError::Usage(format!("could not read {}: {}", file.display(), e)))?;
+            if commands.is_empty() {
+                return Err(CliError::Usage(format!("{}: no commands in it", file.display())));
+            }
+            run_app_cli(config, confirm, true, auto_fix, None, commands)
+        },
+        Some(Commands::Ask { prompt, file, template, json, execute, single, watch, save_script, force, report, compare, .. }) => {
+            if single {
+                config.set_suggestion_mode(SuggestionMode::Single);
+            }
+            if watch {
+                // clap does not check `requires` next to a prompt, which conflicts with --file
+                let Some(path) = &file else {
+                    return Err(CliError::Usage("--watch needs the prompt file to watch, given with --file".to_string()));
+                };
+                return ask_watch(&config, path, json);
+            }
+            let prompt = match (template, file) {
+                (Some(name), _) => Templates::load_default()?.expand(&name, prompt.as_deref().unwrap_or_default())?,
+                (None, Some(path)) => prompt_from_file(&path)?,
+                (None, None) => argument_or_stdin("prompt", prompt)?,
+            };
+            if compare.is_empty() {
+                ask(&config, &prompt, json, execute, report.as_deref(), save_script.as_deref(), force)
+            } else {
+                ask_compare(config, &prompt, &compare, json)
+            }
+        },
+        Some(Commands::Explain { command, .. }) => explain(config, command),
+        Some(Commands::Bench { count, .. }) => bench(config, count as usize),
+        Some(Commands::Models { .. }) => models(&config),
+        Some(Commands::Profile { action }) => profile(config, &config_path, action),
+        Some(Commands::Fav { action }) => fav(&config, action),
+        Some(Commands::History { action, limit, json }) => history(&config, action, limit, json),
+        Some(Commands::Init { .. } | Commands::Completions { .. }) => unreachable!("handled before loading the config"),
+        None => {
+            Args::command().print_help().map_err(|e| CliError::Terminal(e.into()))?;
+            output::message("");
+            Ok(())
+        },
+    }
+}
+
+/// Ask for each setting with its current value pre-filled, then write the config to `path`
+pub fn init(path: &Path, force: bool) -> Result<(), CliError> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return Err(CliError::Usage("init needs a terminal to ask questions, use --set-ollama-api, --set-model and --set-proxy instead".to_string()));
+    }
+    if path.exists() && !force {
+        return Err(CliError::Usage(format!("{} already exists, pass --force to replace it", path.display())));
+    }
+    // with --force, the answers start from the existing settings
+    let mut config = Config::load(path).unwrap_or_default();
+    let mut editor = DefaultEditor::new()?;
+    output::message(format!("Creating {}, press Enter to keep a value or Ctrl-C to cancel", path.display()));
+
+    match ask_settings(&mut editor, &mut config).map_err(CliError::from) {
+        Err(CliError::Cancelled) => {
+            output::message("Nothing was written");
+            return Err(CliError::Cancelled);
+        },
+        answers => answers?,
+    }
+
+    write_config(&config, path)?;
+    output::message(format!("Saved {}", path.display()));
+    Ok(())
+}
+
+/// The questions of `init`, each answer applied to `config` once it is valid
+fn ask_settings(editor: &mut DefaultEditor, config: &mut Config) -> Result<(), ReadlineError> {
+    let default_proxy = config.get_proxy().to_string();
+    loop {
+        let proxy = editor.readline_with_initial("Proxy (leave empty for none): ", (&default_proxy, ""))?;
+        let proxy = proxy.trim();
+        let settings = if proxy.is_empty() {
+            Settings { unset_proxy: true, ..Settings::default() }
+        } else {
+            Settings { proxy: Some(proxy.to_string()), ..Settings::default() }
+        };
+        match settings.apply(config) {
+            Ok(_) => break,
+            Err(e) => output::error(e),
+        }
+    }
+
+    let models = loop {
+        let api = editor.readline_with_initial("Ollama API: ", (config.get_ollama_api(), ""))?;
+        let settings = Settings { ollama_api: Some(api.trim().to_string()), ..Settings::default() };
+        if let Err(e) = settings.apply(config) {
+            output::error(e);
+            continue;
+        }
+        output::progress("Connecting to Ollama... ");
+        match BKclient::from_config(config).list_models() {
+            Ok(models) => {
+                output::message("ok");
+                break models;
+            },
+            Err(e) => {
+                output::message(format!("failed: {}", e));
+                let keep = editor.readline_with_initial("Keep this endpoint anyway? [y/N] ", ("", ""))?;
+                if keep.trim().eq_ignore_ascii_case("y") {
+                    break Vec::new();
+                }
+            },
+        }
+    };
+
+    if !models.is_empty() {
+        output::message("Models on the server:");
+        for (idx, model) in models.iter().enumerate() {
+            output::message(format!("  {}) {}", idx + 1, model));
+        }
+    }
+    loop {
+        let default_model = match models.iter().position(|model| model == config.get_model()) {
+            Some(idx) => (idx + 1).to_string(),
+            None => config.get_model().to_string(),
+        };
+        let answer = editor.readline_with_initial("Model (number or name): ", (&default_model, ""))?;
+        let model = match answer.trim().parse::<usize>() {
+            Ok(number) if (1..=models.len()).contains(&number) => models[number - 1].clone(),
+            _ => answer.trim().to_string(),
+        };
+        if !models.is_empty() && !models.contains(&model) {
+            output::message(format!("{} is not on the server, pull it with `ollama pull {}` before using it", model, model));
+        }
+        let settings = Settings { model: Some(model), ..Settings::default() };
+        match settings.apply(config) {
+            Ok(_) => break,
+            Err(e) => output::error(e),
+        }
+    }
+    Ok(())
+}
+
+/// `doctor`: whether Ollama answers, and the shell and system info the prompts are written for
+fn doctor(config: &Config) {
+    let (server, model) = (backend::server_name(config.get_ollama_api()), config.get_model());
+    match BKclient::from_config(config).list_models() {
+        Ok(models) if backend::has_model(&models, model) => match BKclient::from_config(config).model_loaded(model) {
+            Ok(true) => output::message(format!("Ollama: connected to {}, {} is installed and loaded into memory", server, model)),
+            Ok(false) => output::message(format!("Ollama: connected to {}, {} is installed, the first request loads it into memory, which can take a while", server, model)),
+            Err(_) => output::message(format!("Ollama: connected to {}, {} is installed", server, model)),
+        },
+        Ok(_) => output::message(format!("Ollama: connected to {}, but {} is not installed, download it with `ollama pull {}`", server, model, model)),
+        Err(e) => output::message(format!("Ollama: could not reach {}: {}. Is it running? Start it with `ollama serve`", server, e)),
+    }
+    let info = system_info::current();
+    output::message(match config.get_shell_override() {
+        Some(chosen) => format!("Shell: {}, from `shell` in the config ({} was detected)", chosen, shell::detect()),
+        None => format!("Shell: {}, detected; set `shell` in the config to use another", shell::detect()),
+    });
+    output::message(format!("OS: {}", info.os));
+    output::message(format!("Distribution: {}", info.distro.as_deref().unwrap_or("unknown")));
+    output::message(format!("Architecture: {}", info.arch));
+    output::message(format!("Package manager: {}", info.package_manager.as_deref().unwrap_or("none found")));
+    if config.skips_system_info() {
+        output::message("Told to the model: nothing, `skip_system_info` is set in the config");
+    } else {
+        output::message(format!("Told to the model: {}", info.summary()));
+    }
+    output::message(match config.unknown_language() {
+        Some(code) => format!("Language: en, `{}` in the config is unknown, known languages are {}", code, Language::codes()),
+        None => format!("Language: {}, of the system prompt and the interface", config.get_language()),
+    });
+    output::message(match config.get_context_level() {
+        ContextLevel::None => "Current directory: not told to the model, `context_level` is \"none\"",
+        ContextLevel::Directory => "Current directory: told to the model with each prompt",
+        ContextLevel::Listing => "Current directory: told to the model with each prompt, with the names in it",
+    });
+    match std::env::current_dir().ok().and_then(|dir| project_context::find(&dir, project_context::MAX_DEPTH)) {
+        Some(path) => output::message(format!("Project notes: {}, put before the system prompt here", path.display())),
+        None => output::message("Project notes: no AURISH.md or .aurish-context here or above"),
+    }
+    match ShellHistory::for_shell(config.get_shell()) {
+        Some(history) if config.appends_to_shell_history() => {
+            output::message(format!("Shell history: commands are added to {}", history.path().display()));
+        },
+        Some(history) => output::message(format!("Shell history: {}, set `append_to_shell_history` to add commands to it", history.path().display())),
+        None => output::message("Shell history: no history file known for this shell"),
+    }
+    output::message(match config.inherits_locale() {
+        true => "Command locale: yours, `inherit_locale` is set in the config",
+        false => "Command locale: C, so tools print the same format everywhere; set `inherit_locale` to keep yours",
+    });
+    if let Some(root) = config.get_sandbox_root() {
+        output::message(format!("Sandbox: `cd` stays inside {}", root.display()));
+    }
+}
+
+/// Prompt of `dry-run` without one given
+const DRY_RUN_PROMPT: &str = "How to show all files within current path? And then create a folder named test under current path.";
+
+pub fn dry_run(config: Config, prompt: Option<String>, raw: bool) -> Result<(), CliError> {
+    output::message(format!("Endpoint: {}", config.get_ollama_api()));
+    if let Some(timeout) = config.get_timeout() {
+        output::message(format!("Timeout: {}s", timeout.as_secs()));
+    }
+    let req = aurish::api::request(&config, prompt.as_deref().map(str::trim).filter(|prompt| !prompt.is_empty()).unwrap_or(DRY_RUN_PROMPT));
+    output::message(format!("Data to send: {}", req.to_pretty_json()));
+    if raw {
+        let res = BKclient::from_config(&config).send_raw(&req)?;
+        output::message(format!("ollama response: {}", serde_json::to_string_pretty(&res).unwrap_or_else(|_| res.to_string())));
+    } else {
+        let res = BKclient::from_config(&config).send_ollama(&req)?;
+        output::message(format!("ollama response: {:?}", res));
+    }
+    Ok(())
+}
+
+/// Answer one prompt without the interactive loop. Only the commands (or their output
+/// with `execute`) go to stdout, so scripts can use it; errors go to stderr. With `execute`,
+/// `report` is where to write a `RunReport` of the commands run.
+pub fn ask(config: &Config, prompt: &str, json: bool, execute: bool, report: Option<&Path>, script: Option<&Path>, force: bool) -> Result<(), CliError> {
+    let (request, fit) = aurish::api::fitted_request(config, prompt);
+    if let Some(warning) = fit.warning() {
+        output::warning(warning);
+    }
+    let generation = BKclient::from_config(config).generate(&request)?;
+    if let Some(notice) = generation.notice() {
+        output::note(notice);
+    }
+    let commands = sanitize_commands(generation.commands, config.get_multiline_commands());
+    if commands.is_empty() {
+        return Err(CliError::NoCommands);
+    }
+    if let Some(path) = script {
+        let lines: Vec<_> = commands.iter().map(|command| (command.as_str(), None)).collect();
+        let path = script::save(path, config.get_shell(), &lines, force)?;
+        output::note(format!("Saved {} command(s) to {}", commands.len(), path.display()));
+    }
+
+    if json {
+        output::message(serde_json::json!({ "commands": commands }));
+    } else if execute {
+        let shell = IShell::new();
+        shell.set_shell_type(config.get_shell());
+        shell.set_inherit_locale(config.inherits_locale());
+        shell.set_sandbox_root(config.get_sandbox_root())?;
+        if config.appends_to_shell_history() {
+            shell.set_history(ShellHistory::for_shell(shell.shell_type()));
+        }
+        let mut session = Session::default();
+        session.prompt_sent(config.get_model(), prompt);
+        session.commands_received(&commands);
+        let mut failed = None;
+        for command in &commands {
+            if failed.is_some() {
+                // left out after a failure
+                session.command_skipped(command);
+                continue;
+            }
+            let started = Instant::now();
+            let result = shell.run_command(command);
+            let stderr = String::from_utf8_lossy(&result.stderr);
+            session.command_executed(command, None, result.code, started.elapsed(), &result.stdout_str(), &stderr);
+            output::shell_output(&result);
+            // stdout is the output of the commands
+            output::executed(command, result.code, true);
+            if !result.is_success() {
+                failed = Some(CliError::CommandFailed { command: command.clone(), code: result.code });
+            }
+        }
+        // the exit code tells how the commands went, even if the report cannot be written
+        if let Some(path) = report {
+            if let Err(e) = RunReport::from_session(&session).write(path) {
+                output::warning(format!("could not write the report to {}: {}", path.display(), e));
+            }
+        }
+        if let Some(error) = failed {
+            return Err(error);
+        }
+    } else {
+        for command in &commands {
+            output::command(None, command);
+        }
+    }
+    Ok(())
+}
+
+/// `ask --file <path> --watch`: ask the prompt in `path` again each time the file changes,
+/// with a separator and the time before each answer. A failed request or an unreadable
+/// file is printed and the watch goes on; Ctrl-C ends it.
+#[cfg(feature = "watch")]
+fn ask_watch(config: &Config, path: &Path, json: bool) -> Result<(), CliError> {
+    let (_watcher, mut changes) = watch::watch(path).map_err(|source| CliError::Watch { path: path.to_path_buf(), source })?;
+    output::note(format!("Watching {}, press Ctrl-C to stop", path.display()));
+    loop {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        // on stderr like the notes of `ask`, stdout keeps only the commands
+        output::note(format!("── {} ──", watch::clock(now)));
+        if let Err(e) = prompt_from_file(path).and_then(|prompt| ask(config, &prompt, json, false, None, None, false)) {
+            output::error(&e);
+            if let Some(hint) = e.hint() {
+                output::hint(hint);
+            }
+        }
+        if changes.wait().is_none() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(not(feature = "watch"))]
+fn ask_watch(_config: &Config, _path: &Path, _json: bool) -> Result<(), CliError> {
+    Err(CliError::Usage("--watch needs aurish-cli built with the `watch` feature".to_string()))
+}
+
+/// `ask --compare`: the commands of each of `models` side by side, or as a JSON array.
+/// Fails only if every model failed.
+pub fn ask_compare(config: Config, prompt: &str, models: &[String], json: bool) -> Result<(), CliError> {
+    let models: Vec<&str> = models.iter().map(|model| model.trim()).filter(|model| !model.is_empty()).collect();
+    if models.len() < 2 {
+        return Err(CliError::Usage("--compare needs at least two models, e.g. --compare llama3,mistral".to_string()));
+    }
+    let req = aurish::api::request(&config, prompt);
+    let answers = BKclient::from_config(&config).send_ollama_multi(&models, &req)?;
+
+    if json {
+        let answers: Vec<_> = answers.iter()
+            .map(|answer| match &answer.commands {
+                Ok(commands) => serde_json::json!({ "model": answer.model, "seconds": answer.latency.as_secs_f64(), "commands": commands }),
+                Err(e) => serde_json::json!({ "model": answer.model, "seconds": answer.latency.as_secs_f64(), "error": e.to_string() }),
+            })
+            .collect();
+        output::message(serde_json::Value::from(answers));
+    } else {
+        output::comparison(&answers);
+    }
+    if answers.iter().all(|answer| answer.commands.is_err()) {
+        if let Some(Err(e)) = answers.into_iter().next().map(|answer| answer.commands) {
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+/// Print what the model says `command` does, wrapped to the terminal width
+pub fn explain(config: Config, command: Option<String>) -> Result<(), CliError> {
+    let command = argument_or_stdin("command", command)?;
+    let req = OllamaReq::explain(config.get_model(), command.trim(), config.get_shell(), config.get_language());
+    let explanation = BKclient::from_config(&config).send_text(&req)?;
+    let width = match crossterm::terminal::size() {
+        Ok((columns, _)) if io::stdout().is_terminal() => usize::from(columns).min(100),
+        _ => 80,
+    };
+    for line in wrap_text(&explanation, width) {
+        output::explanation(line);
+    }
+    Ok(())
+}
+
+/// `value` itself, or stdin if it is `-` or missing while stdin is piped.
+/// `what` names the value in the error message.
+fn argument_or_stdin(what: &str, value: Option<String>) -> Result<String, CliError> {
+    let value = match value {
+        Some(value) if value != "-" => value,
+        None if io::stdin().is_terminal() => {
+            return Err(CliError::Usage(format!("no {} given, pass it as an argument or on stdin", what)));
+        },
+        _ => read_prompt(io::stdin().lock(), MAX_PROMPT_BYTES)
+            .map_err(|e| CliError::Usage(format!("stdin: {}", e)))?,
+    };
+    if value.trim().is_empty() {
+        return Err(CliError::Usage(format!("the {} is empty", what)));
+    }
+    Ok(value)
+}
+
+/// A prompt kept in a file, with the same limits as one piped to stdin
+fn prompt_from_file(path: &Path) -> Result<String, CliError> {
+    let file = fs::File::open(path)
+        .map_err(|e| CliError::Usage(format!("could not open {}: {}", path.display(), e)))?;
+    let prompt = read_prompt(file, MAX_PROMPT_BYTES)
+        .map_err(|e| CliError::Usage(format!("{}: {}", path.display(), e)))?;
+    if prompt.trim().is_empty() {
+        return Err(CliError::Usage(format!("{}: the prompt is empty", path.display())));
+    }
+    Ok(prompt)
+}
+
+/// Carry out a `profile` command, saving the config if it changed
+fn profile(mut config: Config, path: &Path, action: ProfileAction) -> Result<(), CliError> {
+    match action {
+        ProfileAction::List => {
+            for (name, profile) in config.profiles() {
+                let marker = if name == config.active_profile() { '*' } else { ' ' };
+                let mut line = format!("{} {:<12} {}  {}", marker, name, profile.model, profile.ollama_api);
+                if !profile.proxy.is_empty() {
+                    line.push_str(&format!("  (proxy {})", profile.proxy));
+                }
+                output::message(line);
+            }
+            return Ok(());
+        },
+        ProfileAction::Use { name } => {
+            config.use_profile(&name)?;
+            output::message(format!("Using profile {}: {} at {}", name, config.get_model(), config.get_ollama_api()));
+        },
+        ProfileAction::Add { name, settings } => {
+            if name.trim().is_empty() || name != name.trim() {
+                return Err(CliError::Usage(format!("invalid profile name `{}`", name)));
+            }
+            let profile = settings.profile(&config)?;
+            config.add_profile(&name, profile)?;
+            output::message(format!("Added profile {}, switch to it with `aurish-cli profile use {}`", name, name));
+        },
+        ProfileAction::Remove { name } => {
+            config.remove_profile(&name)?;
+            output::message(format!("Removed profile {}, the active one is {}", name, config.active_profile()));
+        },
+    }
+    Ok(write_config(&config, path)?)
+}
+
+/// Carry out a `fav` command on the favorites in the data directory
+fn fav(config: &Config, action: FavAction) -> Result<(), CliError> {
+    let store = Favorites::open_default()?;
+    match action {
+        FavAction::List => {
+            let favorites = store.list()?;
+            if favorites.is_empty() {
+                output::note("No favorites yet, save one with `aurish-cli fav add <name> <command>`");
+            }
+            let width = favorites.iter().map(|favorite| favorite.name.chars().count()).max().unwrap_or(0);
+            for favorite in favorites {
+                output::message(format!("{:<width$}  {}", favorite.name, favorite.command, width = width));
+            }
+        },
+        FavAction::Add { name, command, force } => {
+            let name = favorites::check_name(&name)?;
+            let command = argument_or_stdin("command", command)?;
+            match store.get(name)? {
+                None => {
+                    store.add(name, &command)?;
+                    output::message(format!("Saved favorite {}, run it with `aurish-cli fav run {}`", name, name));
+                },
+                Some(existing) => {
+                    // without a terminal to ask on, only --force replaces it
+                    if !force && !io::stdin().is_terminal() {
+                        return Err(FavoritesError::Exists(name.to_string()).into());
+                    }
+                    if !force {
+                        output::message(format!("Favorite {} is: {}", name, existing.command));
+                        let answer = DefaultEditor::new()?.readline(&format!("Replace it with `{}`? [y/N] ", command.trim()))?;
+                        if !answer.trim().eq_ignore_ascii_case("y") {
+                            output::message(format!("Kept favorite {}", name));
+                            return Ok(());
+                        }
+                    }
+                    store.replace(name, &command)?;
+                    output::message(format!("Replaced favorite {}", name));
+                },
+            }
+        },
+        FavAction::Rm { name } => {
+            let removed = store.remove(&name)?;
+            output::message(format!("Removed favorite {}: {}", removed.name, removed.command));
+        },
+        FavAction::Run { name } => {
+            let favorite = store.get(&name)?.ok_or_else(|| FavoritesError::NotFound(name.trim().to_string()))?;
+            let shell = IShell::new();
+            shell.set_shell_type(config.get_shell());
+            shell.set_inherit_locale(config.inherits_locale());
+            shell.set_sandbox_root(config.get_sandbox_root())?;
+            if config.appends_to_shell_history() {
+                shell.set_history(ShellHistory::for_shell(shell.shell_type()));
+            }
+            let result = shell.run_command(&favorite.command);
+            output::shell_output(&result);
+            // stdout is the output of the command
+            output::executed(&favorite.command, result.code, true);
+            if !result.is_success() {
+                return Err(CliError::CommandFailed { command: favorite.command, code: result.code });
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Carry out a `history` command on the sessions in `session_dir` or the data directory.
+/// Transcripts that cannot be read are warned about and left out.
+fn history(config: &Config, action: Option<HistoryAction>, limit: usize, json: bool) -> Result<(), CliError> {
+    let store = Transcripts::open(config.get_session_dir())?;
+    let warn = |skipped: Vec<HistoryError>| skipped.into_iter().for_each(|e| output::warning(format!("skipped: {}", e)));
+    match action {
+        None => {
+            let Found { mut items, skipped } = store.list();
+            warn(skipped);
+            items.truncate(limit);
+            if json {
+                output::message(serde_json::to_string_pretty(&items).unwrap_or_default());
+            } else if items.is_empty() {
+                output::note(format!("No saved sessions in {}, press `e` in aurish or set `export_on_quit` to save them", store.dir().display()));
+            }
+            for summary in items.iter().filter(|_| !json) {
+                let counts = match summary.failed {
+                    0 => format!("{} run", summary.executed),
+                    failed => format!("{} run, {} failed", summary.executed, failed),
+                };
+                let prompt = summary.first_prompt.as_deref().and_then(|prompt| prompt.lines().next()).unwrap_or("(no prompt)");
+                output::message(format!("{}  {}  {:<16}  {}", summary.id, session::date_time(summary.started), counts, prompt));
+            }
+        },
+        Some(HistoryAction::Show { id }) => {
+            let session = store.load(&id)?;
+            if json {
+                output::message(serde_json::to_string_pretty(&session).unwrap_or_default());
+            } else {
+                output::message(format!("Session {}, started {} UTC", id.trim(), session::date_time(session.started)));
+                transcripts::transcript_lines(&session).into_iter().for_each(output::message);
+            }
+        },
+        Some(HistoryAction::Search { text }) => {
+            let Found { items, skipped } = store.search(&text);
+            warn(skipped);
+            if json {
+                output::message(serde_json::to_string_pretty(&items).unwrap_or_default());
+            } else if items.is_empty() {
+                output::note(format!("Nothing in the saved sessions contains `{}`", text.trim()));
+            }
+            for hit in items.iter().filter(|_| !json) {
+                output::message(format!("{}  {:<9}  {}", hit.id, hit.kind, hit.text));
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Print the models on the server, marking the configured one. `llama3` is the same model
+/// as `llama3:latest`.
+fn models(config: &Config) -> Result<(), CliError> {
+    let models = BKclient::from_config(config).list_models()?;
+    let configured = config.get_model();
+    let is_configured = |model: &str| backend::has_model(&[model.to_string()], configured);
+    if models.is_empty() {
+        output::note(format!("No models on {}, download one with `ollama pull <model>`", config.get_ollama_api()));
+        return Ok(());
+    }
+    for model in &models {
+        let marker = if is_configured(model) { '*' } else { ' ' };
+        output::message(format!("{} {}", marker, model));
+    }
+    if !backend::has_model(&models, configured) {
+        output::warning(format!("the configured model `{}` is not on the server, pick one with `--set-model <model>`", configured));
+    }
+    Ok(())
+}
+
+/// Send `count` requests, printing each result and then the table of all of them.
+/// Fails only if every request failed.
+fn bench(config: Config, count: usize) -> Result<(), CliError> {
+    output::message(format!("Endpoint: {}", config.get_ollama_api()));
+    output::message(format!("Model: {}", config.get_model()));
+    let client = BKclient::from_config(&config);
+    let rounds = bench::run(&client, config.get_model(), count, |i, round| match round {
+        Round::Ok { latency, .. } => output::message(format!("[{}/{}] {:.2}s", i, count, latency.as_secs_f64())),
+        Round::Invalid { latency } => output::message(format!("[{}/{}] {:.2}s, no valid commands", i, count, latency.as_secs_f64())),
+        Round::Failed(e) => output::message(format!("[{}/{}] failed: {}", i, count, e)),
+    });
+    output::message(format!("\n{}", bench::report(&rounds)));
+    if rounds.iter().all(|round| matches!(round, Round::Failed(_))) {
+        return Err(CliError::BenchFailed(count));
+    }
+    Ok(())
+}
+
+pub fn run_app_cli(
+    config: Config,
+    confirm: bool,
+    no_select: bool,
+    auto_fix: bool,
+    initial_prompt: Option<String>,
+    commands: Vec<String>,
+) -> Result<(), CliError> {
+    let confirm = confirm || config.confirms_commands();
+    let select = !(no_select || config.skips_selection());
+    let auto_fix = (auto_fix || config.auto_fixes()).then(|| config.get_auto_fix_attempts());
+    let mut app = App_cli::new(config.get_model())
+        .with_confirm(confirm)
+        .with_select(select)
+        .with_system_info(!config.skips_system_info())
+        .with_language(config.get_language())
+        .with_multiline_commands(config.get_multiline_commands())
+        .with_context_level(config.get_context_level())
+        .with_suggestion_mode(config.get_suggestion_mode())
+        .with_shell_type(config.get_shell_override())
+        .with_shell_history(config.appends_to_shell_history())
+        .with_inherited_locale(config.inherits_locale())
+        .with_sandbox_root(config.get_sandbox_root())?
+        .with_max_prompt_tokens(config.get_max_prompt_tokens())
+        .with_auto_fix(auto_fix)
+        .with_dangerous_patterns(config.get_dangerous_patterns().to_vec())
+        .with_session_export(config.get_session_dir().map(PathBuf::from), config.exports_on_quit())
+        .with_initial_prompt(initial_prompt)
+        .with_queued_commands(commands);
+    Ok(app.run(BKclient::from_config(&config))?)
+}
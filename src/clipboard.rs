@@ -0,0 +1,53 @@
+//! System clipboard access for the TUI
+//!
+//! Backed by `arboard` when the `clipboard` feature is enabled. Without it, or when no
+//! clipboard is reachable (e.g. over SSH), copying and pasting return a `ClipboardError`.
+
+use crate::error::ClipboardError;
+
+/// Handle to the system clipboard, opened on first use
+#[derive(Default)]
+pub struct Clipboard {
+    /// Kept open because on X11 the copied text is only served while it is alive
+    #[cfg(feature = "clipboard")]
+    inner: Option<arboard::Clipboard>,
+}
+
+impl Clipboard {
+    /// Put `text` on the clipboard
+    #[cfg(feature = "clipboard")]
+    pub fn copy(&mut self, text: &str) -> Result<(), ClipboardError> {
+        self.open()?.set_text(text).map_err(unavailable)
+    }
+
+    /// The text on the clipboard
+    #[cfg(feature = "clipboard")]
+    pub fn paste(&mut self) -> Result<String, ClipboardError> {
+        self.open()?.get_text().map_err(unavailable)
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn open(&mut self) -> Result<&mut arboard::Clipboard, ClipboardError> {
+        if self.inner.is_none() {
+            self.inner = Some(arboard::Clipboard::new().map_err(unavailable)?);
+        }
+        Ok(self.inner.as_mut().expect("clipboard was just opened"))
+    }
+
+    /// Put `text` on the clipboard
+    #[cfg(not(feature = "clipboard"))]
+    pub fn copy(&mut self, _text: &str) -> Result<(), ClipboardError> {
+        Err(ClipboardError::NotSupported)
+    }
+
+    /// The text on the clipboard
+    #[cfg(not(feature = "clipboard"))]
+    pub fn paste(&mut self) -> Result<String, ClipboardError> {
+        Err(ClipboardError::NotSupported)
+    }
+}
+
+#[cfg(feature = "clipboard")]
+fn unavailable(err: arboard::Error) -> ClipboardError {
+    ClipboardError::Unavailable(err.to_string())
+}
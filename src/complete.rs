@@ -0,0 +1,296 @@
+//! File and directory name completion for the Shell box
+//!
+//! Tab completes the word before the cursor against the entries of the directory it names,
+//! relative to the shell's current directory. A unique match is inserted, several matches
+//! are first completed to their common prefix and then cycled through on each further Tab.
+
+use std::fs;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use crate::shell::ShellType;
+
+/// Characters POSIX shells need escaped in a word
+const POSIX_SPECIAL: &str = "'\"\\$`()&;|<>*?[]{}!#";
+/// Characters that make PowerShell need the word quoted
+const POWERSHELL_SPECIAL: &str = "'\"`$&(){}[];,|<>@#";
+/// Characters that make cmd.exe need the word quoted
+const CMD_SPECIAL: &str = "&()[]{}^=;!'+,`~";
+
+/// State of a completion, kept between Tab presses to cycle through the candidates
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    /// Line before and after the completed word
+    before: String,
+    after: String,
+    /// Matching names, quoted for the shell
+    candidates: Vec<String>,
+    /// Candidate in the line, `None` while it holds their common prefix
+    selected: Option<usize>,
+    /// Text currently standing in for the word
+    replacement: String,
+}
+
+impl Completion {
+    /// Complete the word before `cursor`, a position in characters.
+    /// Returns `None` if no entry matches.
+    pub fn start(line: &str, cursor: usize, cwd: &Path, shell: ShellType) -> Option<Completion> {
+        let chars: Vec<char> = line.chars().collect();
+        let cursor = cursor.min(chars.len());
+        let start = word_start(&chars[..cursor], shell);
+        let word: String = chars[start..cursor].iter().collect();
+        let value = unquote(&word, shell);
+
+        let split = value.rfind(|c| is_separator(c, shell)).map_or(0, |idx| idx + 1);
+        let (dir_text, prefix) = value.split_at(split);
+        let matches = list_matches(&resolve_dir(dir_text, cwd), prefix);
+        if matches.is_empty() {
+            return None;
+        }
+
+        let candidates: Vec<String> = matches.iter()
+            .map(|(name, _)| quote(&format!("{}{}", dir_text, name), shell))
+            .collect();
+        let (replacement, selected) = match matches.as_slice() {
+            [(_, is_dir)] => {
+                // a unique file is done, a directory can be continued
+                let space = if *is_dir { "" } else { " " };
+                (format!("{}{}", candidates[0], space), Some(0))
+            },
+            _ => {
+                let common = common_prefix(matches.iter().map(|(name, _)| name.as_str()));
+                if common.chars().count() > prefix.chars().count() {
+                    (quote(&format!("{}{}", dir_text, common), shell), None)
+                } else {
+                    (candidates[0].clone(), Some(0))
+                }
+            },
+        };
+
+        Some(Completion {
+            before: chars[..start].iter().collect(),
+            after: chars[cursor..].iter().collect(),
+            candidates,
+            selected,
+            replacement,
+        })
+    }
+
+    /// Put the next candidate in the line, wrapping around after the last one
+    pub fn cycle(&mut self) {
+        if self.candidates.len() < 2 {
+            return;
+        }
+        let next = self.selected.map_or(0, |idx| (idx + 1) % self.candidates.len());
+        self.selected = Some(next);
+        self.replacement = self.candidates[next].clone();
+    }
+
+    /// The line with the completion applied
+    pub fn line(&self) -> String {
+        format!("{}{}{}", self.before, self.replacement, self.after)
+    }
+
+    /// Cursor position in characters, right after the completed word
+    pub fn cursor(&self) -> usize {
+        self.before.chars().count() + self.replacement.chars().count()
+    }
+
+    /// Whether the line was left as this completion made it, so Tab keeps cycling
+    pub fn is_current(&self, line: &str, cursor: usize) -> bool {
+        line == self.line() && cursor == self.cursor()
+    }
+
+    /// Number of candidates
+    pub fn count(&self) -> usize {
+        self.candidates.len()
+    }
+
+    /// Index of the candidate in the line, if cycling started
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+}
+
+fn is_separator(c: char, shell: ShellType) -> bool {
+    c == '/' || (!shell.is_posix() && c == '\\')
+}
+
+/// Start of the word ending at the end of `chars`, skipping quoted and escaped whitespace
+fn word_start(chars: &[char], shell: ShellType) -> usize {
+    let (mut start, mut quote, mut escaped) = (0, None, false);
+    for (idx, &c) in chars.iter().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if quote == Some(c) {
+            quote = None;
+        } else if quote.is_none() {
+            match c {
+                '\\' if shell.is_posix() => escaped = true,
+                '\'' | '"' => quote = Some(c),
+                c if c.is_whitespace() => start = idx + 1,
+                _ => {},
+            }
+        }
+    }
+    start
+}
+
+/// The word as the shell sees it, without quotes and escapes
+fn unquote(word: &str, shell: ShellType) -> String {
+    if !shell.is_posix() {
+        return word.chars().filter(|c| *c != '\'' && *c != '"').collect();
+    }
+    let (mut value, mut quote, mut escaped) = (String::new(), None, false);
+    for c in word.chars() {
+        if escaped {
+            value.push(c);
+            escaped = false;
+        } else if quote == Some(c) {
+            quote = None;
+        } else if c == '\\' && quote != Some('\'') {
+            escaped = true;
+        } else if quote.is_none() && (c == '\'' || c == '"') {
+            quote = Some(c);
+        } else {
+            value.push(c);
+        }
+    }
+    value
+}
+
+/// `value` written so the shell reads it as one word
+fn quote(value: &str, shell: ShellType) -> String {
+    match shell {
+        ShellType::PowerShell if value.chars().any(|c| c.is_whitespace() || POWERSHELL_SPECIAL.contains(c)) => {
+            format!("'{}'", value.replace('\'', "''"))
+        },
+        ShellType::Cmd if value.chars().any(|c| c.is_whitespace() || CMD_SPECIAL.contains(c)) => {
+            format!("\"{}\"", value)
+        },
+        ShellType::PowerShell | ShellType::Cmd => value.to_string(),
+        _ => {
+            let mut quoted = String::new();
+            for c in value.chars() {
+                if c.is_whitespace() || POSIX_SPECIAL.contains(c) {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            quoted
+        },
+    }
+}
+
+/// Directory named by the part of the word before its last separator
+fn resolve_dir(dir_text: &str, cwd: &Path) -> PathBuf {
+    if dir_text.is_empty() {
+        return cwd.to_path_buf();
+    }
+    if let Some(rest) = dir_text.strip_prefix('~') {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(rest.trim_start_matches(['/', '\\']));
+        }
+    }
+    cwd.join(dir_text)
+}
+
+/// Sorted names in `dir` starting with `prefix` and whether they are directories.
+/// Directory names end with a separator, hidden entries need a `.` prefix.
+fn list_matches(dir: &Path, prefix: &str) -> Vec<(String, bool)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut matches: Vec<(String, bool)> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            // follows symlinks, so links to directories complete like directories
+            let is_dir = entry.path().is_dir();
+            Some(if is_dir { (format!("{}{}", name, MAIN_SEPARATOR), true) } else { (name, false) })
+        })
+        .collect();
+    matches.sort();
+    matches
+}
+
+fn common_prefix<'a>(mut names: impl Iterator<Item = &'a str>) -> String {
+    let Some(first) = names.next() else {
+        return String::new();
+    };
+    let mut common: Vec<char> = first.chars().collect();
+    for name in names {
+        let same = common.iter().zip(name.chars()).take_while(|(a, b)| **a == *b).count();
+        common.truncate(same);
+    }
+    common.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Directory with `notes.txt`, `notes-old.txt`, `.hidden`, `my docs/` and `src/main.rs`
+    fn tree(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aurish-complete-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("my docs")).unwrap();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        for file in ["notes.txt", "notes-old.txt", ".hidden", "src/main.rs"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        dir
+    }
+
+    fn complete(line: &str, dir: &Path, shell: ShellType) -> Option<Completion> {
+        Completion::start(line, line.chars().count(), dir, shell)
+    }
+
+    #[test]
+    fn prefix_and_cycle() {
+        let dir = tree("cycle");
+        assert_eq!(complete("cd sr", &dir, ShellType::Bash).unwrap().line(), "cd src/");
+        assert_eq!(complete("cat src/ma", &dir, ShellType::Bash).unwrap().line(), "cat src/main.rs ");
+        assert!(complete("cat missing", &dir, ShellType::Bash).is_none());
+
+        // completes the common prefix first, then cycles through the matches
+        let mut completion = complete("cat no", &dir, ShellType::Bash).unwrap();
+        assert_eq!((completion.line(), completion.cursor(), completion.count()), ("cat notes".to_string(), 9, 2));
+        assert_eq!(completion.selected(), None);
+        completion.cycle();
+        assert_eq!(completion.line(), "cat notes-old.txt");
+        completion.cycle();
+        assert_eq!(completion.line(), "cat notes.txt");
+        completion.cycle();
+        assert_eq!(completion.line(), "cat notes-old.txt");
+        assert!(completion.is_current("cat notes-old.txt", 17));
+        assert!(!completion.is_current("cat notes-old.txtx", 18));
+
+        // text after the cursor is kept
+        let completion = Completion::start("cat sr | wc -l", 6, &dir, ShellType::Bash).unwrap();
+        assert_eq!((completion.line(), completion.cursor()), ("cat src/ | wc -l".to_string(), 8));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hidden_entries() {
+        let dir = tree("hidden");
+        let all = complete("ls ", &dir, ShellType::Bash).unwrap();
+        assert_eq!(all.count(), 4);
+        assert_eq!(complete("ls .h", &dir, ShellType::Bash).unwrap().line(), "ls .hidden ");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn quoting() {
+        let dir = tree("quoting");
+        assert_eq!(complete("cd my", &dir, ShellType::Bash).unwrap().line(), "cd my\\ docs/");
+        assert_eq!(complete("cd my", &dir, ShellType::PowerShell).unwrap().line(), "cd 'my docs/'");
+        assert_eq!(complete("cd my", &dir, ShellType::Cmd).unwrap().line(), "cd \"my docs/\"");
+        // escaped and quoted words are completed as what they stand for
+        assert_eq!(complete("cd my\\ d", &dir, ShellType::Bash).unwrap().line(), "cd my\\ docs/");
+        assert_eq!(complete("cd 'my d", &dir, ShellType::Zsh).unwrap().line(), "cd my\\ docs/");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,1262 @@
+//! The config file: its settings in `Config`, where it lives, and how it is checked and
+//! changed
+//!
+//! Both binaries look for it in this order: the `--config` flag, the `AURISH_CONFIG`
+//! environment variable, `aurish/config.toml` or `aurish/config.json` in the user config
+//! directory (e.g. `~/.config/aurish/config.toml` on Linux), and finally `config.json` in
+//! the current directory, where older versions kept it.
+//!
+//! A config file is TOML if its name ends in `.toml` and JSON otherwise. It is written
+//! back in the format it was read in; new files in the user config directory are TOML.
+//!
+//! The model, endpoint and timeout can also be changed for one run, without touching the
+//! file: a flag beats its environment variable, which beats the config file. So can the
+//! profile, whose endpoint and model the other overrides then change.
+//!
+//! Saves hold a lock on `.<name>.lock` next to the file, so `aurish-cli --set-model` and a
+//! running TUI do not lose each other's changes, and `Config::watch` lets the TUI pick up
+//! a file changed under it.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use serde::{Deserialize, Serialize};
+use url::Url;
+use crate::autofix;
+use crate::i18n::Language;
+use crate::shell::{self, ShellType};
+use crate::suggestions::SuggestionMode;
+use crate::theme::ThemeConfig;
+use crate::utils::{self, MultilineCommands};
+use crate::workdir::ContextLevel;
+#[cfg(feature = "tui")]
+use crate::{
+    error::ThemeError,
+    keymap::{self, Binding},
+    theme::{self, Theme},
+};
+
+pub use crate::error::ConfigError;
+
+/// Environment variable naming the config file
+pub const CONFIG_ENV: &str = "AURISH_CONFIG";
+/// Config file of older versions, relative to the current directory
+pub const LEGACY_PATH: &str = "config.json";
+/// Environment variable overriding the model for one run
+pub const MODEL_ENV: &str = "AURISH_MODEL";
+/// Environment variable overriding the Ollama endpoint for one run
+pub const API_ENV: &str = "AURISH_OLLAMA_API";
+/// Environment variable overriding the request timeout for one run, in seconds
+pub const TIMEOUT_ENV: &str = "AURISH_TIMEOUT";
+/// Environment variable overriding the active profile for one run
+pub const PROFILE_ENV: &str = "AURISH_PROFILE";
+
+/// Format of a config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+}
+
+impl ConfigFormat {
+    /// TOML for `.toml` files, JSON for anything else
+    pub fn of(path: &Path) -> ConfigFormat {
+        match path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("toml") => ConfigFormat::Toml,
+            _ => ConfigFormat::Json,
+        }
+    }
+}
+
+/// Name of the profile made of the top-level `ollama_api`, `model`, `proxy` and `timeout`
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Config {
+    ollama_api: String,
+    model: String,
+    proxy: String,
+    /// Other endpoint and model combinations, see `use_profile`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    profiles: BTreeMap<String, ProfileConfig>,
+    /// Profile the endpoint and model are taken from, the top-level ones if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    active_profile: Option<String>,
+    /// Commands containing any of these always ask for confirmation before running
+    #[serde(default)]
+    dangerous_patterns: Vec<String>,
+    /// Colors of the TUI, see `Theme::from_config`
+    #[serde(default)]
+    theme: ThemeConfig,
+    /// Directory session transcripts are exported to, instead of the data directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    session_dir: Option<PathBuf>,
+    /// Export the session transcript when quitting
+    #[serde(default)]
+    export_on_quit: bool,
+    /// Keybindings of the TUI, one of `keymap::KEYMAPS`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keymap: Option<String>,
+    /// Keys of actions in the TUI instead of those of `keymap`, see `keymap::with_overrides`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    keybindings: BTreeMap<String, KeySpecs>,
+    /// `aurish-cli run` asks before running each command
+    #[serde(default)]
+    confirm_commands: bool,
+    /// `aurish-cli run` queues every suggested command instead of asking which ones to run
+    #[serde(default)]
+    skip_selection: bool,
+    /// Seconds a request to Ollama may take, no limit if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timeout: Option<u64>,
+    /// Requests for commands sent in a minute at most, e.g. to a shared remote server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_requests_per_minute: Option<u32>,
+    /// JSON Lines file each request for commands is recorded in, see `logging::RequestLog`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    log_requests_to: Option<PathBuf>,
+    /// Model `c` in the TUI compares the current one with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compare_model: Option<String>,
+    /// Whether a suggested command of several lines is split into several or joined
+    #[serde(default)]
+    multiline_commands: MultilineCommands,
+    /// Add the commands that succeed to the history file of the user's shell
+    #[serde(default)]
+    append_to_shell_history: bool,
+    /// Run commands in the user's locale instead of `LC_ALL=C`, see `IShell::set_inherit_locale`
+    #[serde(default)]
+    inherit_locale: bool,
+    /// Directory `cd` cannot leave, see `IShell::set_sandbox_root`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sandbox_root: Option<PathBuf>,
+    /// Shell to run and ask for commands of instead of the detected one, like `zsh` or `pwsh`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shell: Option<String>,
+    /// Leave the OS and package manager out of the system prompt, see `system_info`
+    #[serde(default)]
+    skip_system_info: bool,
+    /// Language of the system prompt and the interfaces, like `es`, see `i18n`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    /// What the model is told about the current directory, see `workdir`
+    #[serde(default)]
+    context_level: ContextLevel,
+    /// How many commands the model is asked for, see `suggestions`
+    #[serde(default)]
+    suggestion_mode: SuggestionMode,
+    /// Start the TUI without the welcome panel in the Output box
+    #[serde(default)]
+    hide_welcome: bool,
+    /// Estimated tokens a prompt may take with its context, see `OllamaReq::fit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_prompt_tokens: Option<usize>,
+    /// Send a failed command back to the model for a corrected one, see `autofix`
+    #[serde(default)]
+    auto_fix: bool,
+    /// Corrections asked for in a row before giving up, `autofix::DEFAULT_ATTEMPTS` if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    auto_fix_attempts: Option<u32>,
+    /// Fields this version does not know, e.g. from a newer one. They are warned about
+    /// and written back unchanged.
+    #[serde(flatten)]
+    unknown: BTreeMap<String, serde_json::Value>,
+}
+
+/// Longest `timeout` accepted, a day
+const MAX_TIMEOUT: u64 = 24 * 60 * 60;
+
+/// Prompt tokens allowed when the config sets no `max_prompt_tokens`
+pub const DEFAULT_MAX_PROMPT_TOKENS: usize = 4096;
+
+/// A named endpoint and model, used instead of the top-level ones while it is active
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProfileConfig {
+    pub ollama_api: String,
+    pub model: String,
+    #[serde(default)]
+    pub proxy: String,
+    /// Seconds a request may take, the top-level `timeout` if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            ollama_api: String::from("http://localhost:11434/api/generate"),
+            model: String::from("llama3:latest"),
+            proxy: String::from(""),
+            profiles: BTreeMap::new(),
+            active_profile: None,
+            dangerous_patterns: Vec::new(),
+            theme: ThemeConfig::default(),
+            session_dir: None,
+            export_on_quit: false,
+            keymap: None,
+            keybindings: BTreeMap::new(),
+            confirm_commands: false,
+            skip_selection: false,
+            timeout: None,
+            max_requests_per_minute: None,
+            log_requests_to: None,
+            compare_model: None,
+            multiline_commands: MultilineCommands::default(),
+            append_to_shell_history: false,
+            inherit_locale: false,
+            sandbox_root: None,
+            shell: None,
+            skip_system_info: false,
+            language: None,
+            context_level: ContextLevel::default(),
+            suggestion_mode: SuggestionMode::default(),
+            hide_welcome: false,
+            max_prompt_tokens: None,
+            auto_fix: false,
+            auto_fix_attempts: None,
+            unknown: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|source| ConfigError::Read { path: path.to_path_buf(), source })?;
+        let config: Config = match ConfigFormat::of(path) {
+            ConfigFormat::Json => serde_json::from_str(&contents).map_err(|source| ConfigError::Parse { path: path.to_path_buf(), source })?,
+            ConfigFormat::Toml => toml::from_str(&contents).map_err(|source| ConfigError::ParseToml { path: path.to_path_buf(), source })?,
+        };
+        match &config.active_profile {
+            Some(name) if !config.profiles.contains_key(name) => Err(ConfigError::UnknownProfile(name.clone())),
+            _ => Ok(config),
+        }
+    }
+
+    /// Write the config to `path`, in the format its extension asks for. It is written to a
+    /// temporary file next to it first and then moved over it, so a crash halfway leaves the
+    /// old config intact.
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let _lock = lock(path)?;
+        self.save_locked(path)
+    }
+
+    /// Load the config at `path`, let `change` change it and save it again, all under the
+    /// lock, so a save by another process in between is not overwritten. Nothing is saved
+    /// if `change` fails.
+    pub fn update<T>(path: &Path, change: impl FnOnce(&mut Config) -> Result<T, ConfigError>) -> Result<T, ConfigError> {
+        let _lock = lock(path)?;
+        let mut config = Config::load(path)?;
+        let changed = change(&mut config)?;
+        config.save_locked(path)?;
+        Ok(changed)
+    }
+
+    /// Call `callback` with the config at `path` each time the file changes, checking every
+    /// `interval`. Reading it can fail, e.g. when the new file does not parse. A removed
+    /// file is left alone until it is back. Watching stops when the `ConfigWatch` is dropped.
+    pub fn watch<F>(path: &Path, interval: Duration, mut callback: F) -> ConfigWatch
+    where
+        F: FnMut(Result<Config, ConfigError>) + Send + 'static,
+    {
+        let path = path.to_path_buf();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let mut seen = stamp(&path);
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let now = stamp(&path);
+                if now != seen {
+                    seen = now;
+                    if now.is_some() {
+                        callback(Config::load(&path));
+                    }
+                }
+            }
+        });
+        ConfigWatch { _stop: stop }
+    }
+
+    fn save_locked(&self, path: &Path) -> Result<(), ConfigError> {
+        let text = match ConfigFormat::of(path) {
+            ConfigFormat::Json => serde_json::to_string_pretty(self).map_err(io::Error::from),
+            ConfigFormat::Toml => toml::to_string_pretty(self).map_err(io::Error::other),
+        };
+        text.and_then(|text| utils::write_atomically(path, &text))
+            .map_err(|source| ConfigError::Write { path: path.to_path_buf(), source })
+    }
+
+    /// Names of the fields in the config file this version does not know
+    pub fn unknown_fields(&self) -> impl Iterator<Item = &str> {
+        self.unknown.keys().map(String::as_str)
+    }
+
+    /// Check the values serde cannot: URLs, names and ranges. Returns every problem found.
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut problems = Vec::new();
+        let mut problem = |field: &str, value: &str, reason: String| {
+            problems.push(ConfigError::Field { field: field.to_string(), value: value.to_string(), reason });
+        };
+        for (name, profile) in self.profiles() {
+            // fields of the default profile are at the top level
+            let field = |key: &str| match name {
+                DEFAULT_PROFILE => key.to_string(),
+                _ => format!("profiles.{}.{}", name, key),
+            };
+            if let Some(reason) = url_problem(&profile.ollama_api, &API_SCHEMES) {
+                problem(&field("ollama_api"), &profile.ollama_api, reason);
+            }
+            if !profile.proxy.is_empty() {
+                if let Some(reason) = url_problem(&profile.proxy, &PROXY_SCHEMES) {
+                    problem(&field("proxy"), &profile.proxy, reason);
+                }
+            }
+            if profile.model.trim().is_empty() {
+                problem(&field("model"), &profile.model, "the model name is empty".to_string());
+            }
+            if let Some(timeout) = profile.timeout.filter(|timeout| !(1..=MAX_TIMEOUT).contains(timeout)) {
+                problem(&field("timeout"), &timeout.to_string(), format!("expected 1 to {} seconds", MAX_TIMEOUT));
+            }
+        }
+        #[cfg(feature = "tui")]
+        match Theme::from_config(&self.theme) {
+            Err(ThemeError::UnknownPreset(name)) => {
+                problem("theme", &name, format!("valid themes are {}", theme::PRESETS.join(", ")));
+            },
+            Err(ThemeError::UnknownColor { field, value }) => problem(
+                &format!("theme.{}", field),
+                &value,
+                format!("valid colors are {}, `#rrggbb` or a number from 0 to 255", theme::COLOR_NAMES.join(", ")),
+            ),
+            Ok(_) => {},
+        }
+        #[cfg(feature = "tui")]
+        match keymap::by_name(self.get_keymap()) {
+            None => problem("keymap", self.get_keymap(), format!("valid keymaps are {}", keymap::KEYMAPS.join(", "))),
+            Some(bindings) => {
+                for error in keymap::with_overrides(&bindings, &self.keybindings).err().unwrap_or_default() {
+                    problem(&format!("keybindings.{}", error.action()), error.spec(), error.to_string());
+                }
+            },
+        }
+        if let Some(pattern) = self.dangerous_patterns.iter().find(|pattern| pattern.trim().is_empty()) {
+            problem("dangerous_patterns", pattern, "an empty pattern would match every command".to_string());
+        }
+        if let Some(Err(error)) = self.shell.as_deref().map(str::parse::<ShellType>) {
+            problem("shell", self.shell.as_deref().unwrap_or_default(), error.to_string());
+        }
+        if let Some(root) = self.sandbox_root.as_deref().filter(|root| !root.is_dir()) {
+            problem("sandbox_root", &root.display().to_string(), "no such directory".to_string());
+        }
+        if let Some(model) = self.compare_model.as_deref().filter(|model| model.trim().is_empty()) {
+            problem("compare_model", model, "the model name is empty".to_string());
+        }
+        if self.max_requests_per_minute == Some(0) {
+            problem("max_requests_per_minute", "0", "expected a number of requests above 0".to_string());
+        }
+        if self.max_prompt_tokens == Some(0) {
+            problem("max_prompt_tokens", "0", "expected a number of tokens above 0".to_string());
+        }
+        if let Some(attempts) = self.auto_fix_attempts.filter(|attempts| !(1..=autofix::MAX_ATTEMPTS).contains(attempts)) {
+            problem("auto_fix_attempts", &attempts.to_string(), format!("expected 1 to {} attempts", autofix::MAX_ATTEMPTS));
+        }
+        if problems.is_empty() { Ok(()) } else { Err(problems) }
+    }
+
+    /// The active profile, `None` for the default one
+    fn profile(&self) -> Option<&ProfileConfig> {
+        self.active_profile.as_ref().and_then(|name| self.profiles.get(name))
+    }
+
+    fn profile_mut(&mut self) -> Option<&mut ProfileConfig> {
+        self.active_profile.as_ref().and_then(|name| self.profiles.get_mut(name))
+    }
+
+    /// Name of the profile the endpoint and model are taken from
+    pub fn active_profile(&self) -> &str {
+        self.active_profile.as_deref().unwrap_or(DEFAULT_PROFILE)
+    }
+
+    /// Every profile by name, the default one first
+    pub fn profiles(&self) -> Vec<(&str, ProfileConfig)> {
+        let default = ProfileConfig {
+            ollama_api: self.ollama_api.clone(),
+            model: self.model.clone(),
+            proxy: self.proxy.clone(),
+            timeout: self.timeout,
+        };
+        std::iter::once((DEFAULT_PROFILE, default))
+            .chain(self.profiles.iter().map(|(name, profile)| (name.as_str(), profile.clone())))
+            .collect()
+    }
+
+    /// Take the endpoint and model from the profile `name` from now on
+    pub fn use_profile(&mut self, name: &str) -> Result<(), ConfigError> {
+        if name == DEFAULT_PROFILE {
+            self.active_profile = None;
+        } else if self.profiles.contains_key(name) {
+            self.active_profile = Some(name.to_string());
+        } else {
+            return Err(ConfigError::UnknownProfile(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Add the profile `name`, which must not exist yet
+    pub fn add_profile(&mut self, name: &str, profile: ProfileConfig) -> Result<(), ConfigError> {
+        if name == DEFAULT_PROFILE || self.profiles.contains_key(name) {
+            return Err(ConfigError::ProfileExists(name.to_string()));
+        }
+        self.profiles.insert(name.to_string(), profile);
+        Ok(())
+    }
+
+    /// Remove the profile `name`, going back to the default one if it was active
+    pub fn remove_profile(&mut self, name: &str) -> Result<ProfileConfig, ConfigError> {
+        if name == DEFAULT_PROFILE {
+            return Err(ConfigError::Invalid {
+                setting: "profile remove",
+                value: name.to_string(),
+                reason: "the default profile is made of the top-level settings and cannot be removed".to_string(),
+            });
+        }
+        let profile = self.profiles.remove(name).ok_or_else(|| ConfigError::UnknownProfile(name.to_string()))?;
+        if self.active_profile.as_deref() == Some(name) {
+            self.active_profile = None;
+        }
+        Ok(profile)
+    }
+
+    pub fn set_proxy(&mut self, proxy: String) {
+        match self.profile_mut() {
+            Some(profile) => profile.proxy = proxy,
+            None => self.proxy = proxy,
+        }
+    }
+
+    pub fn set_ollama_api(&mut self, api: String) {
+        match self.profile_mut() {
+            Some(profile) => profile.ollama_api = api,
+            None => self.ollama_api = api,
+        }
+    }
+
+    pub fn set_model(&mut self, model: String) {
+        match self.profile_mut() {
+            Some(profile) => profile.model = model,
+            None => self.model = model,
+        }
+    }
+
+    pub fn get_model(&self) -> &str {
+        self.profile().map_or(self.model.as_str(), |profile| profile.model.as_str())
+    }
+
+    pub fn get_ollama_api(&self) -> &str {
+        self.profile().map_or(self.ollama_api.as_str(), |profile| profile.ollama_api.as_str())
+    }
+
+    pub fn get_proxy(&self) -> &str {
+        self.profile().map_or(self.proxy.as_str(), |profile| profile.proxy.as_str())
+    }
+
+    pub fn get_dangerous_patterns(&self) -> &[String] {
+        &self.dangerous_patterns
+    }
+
+    pub fn get_theme(&self) -> &ThemeConfig {
+        &self.theme
+    }
+
+    pub fn get_session_dir(&self) -> Option<&Path> {
+        self.session_dir.as_deref()
+    }
+
+    pub fn exports_on_quit(&self) -> bool {
+        self.export_on_quit
+    }
+
+    pub fn get_log_requests_to(&self) -> Option<&Path> {
+        self.log_requests_to.as_deref()
+    }
+
+    pub fn get_compare_model(&self) -> Option<&str> {
+        self.compare_model.as_deref()
+    }
+
+    /// Name of the keybinding table, `default` unless set
+    pub fn get_keymap(&self) -> &str {
+        self.keymap.as_deref().unwrap_or("default")
+    }
+
+    /// Keys given to actions by name, on top of `get_keymap`
+    pub fn get_keybindings(&self) -> &BTreeMap<String, KeySpecs> {
+        &self.keybindings
+    }
+
+    /// The keybinding table of `get_keymap` with `get_keybindings` applied, the built-in
+    /// keys where `validate` would have complained
+    #[cfg(feature = "tui")]
+    pub fn keybinding_table(&self) -> Vec<Binding> {
+        let bindings = keymap::by_name(self.get_keymap()).unwrap_or_else(keymap::default_bindings);
+        keymap::with_overrides(&bindings, &self.keybindings).unwrap_or(bindings)
+    }
+
+    pub fn set_timeout(&mut self, seconds: Option<u64>) {
+        match self.profile_mut() {
+            Some(profile) => profile.timeout = seconds,
+            None => self.timeout = seconds,
+        }
+    }
+
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.profile().and_then(|profile| profile.timeout).or(self.timeout).map(Duration::from_secs)
+    }
+
+    /// Requests for commands allowed per minute, no limit if `None`
+    pub fn get_max_requests_per_minute(&self) -> Option<u32> {
+        self.max_requests_per_minute
+    }
+
+    pub fn confirms_commands(&self) -> bool {
+        self.confirm_commands
+    }
+
+    pub fn skips_selection(&self) -> bool {
+        self.skip_selection
+    }
+
+    pub fn skips_system_info(&self) -> bool {
+        self.skip_system_info
+    }
+
+    /// Language of `language` in the config, English if it is unset or unknown
+    pub fn get_language(&self) -> Language {
+        self.language.as_deref().and_then(|code| code.parse().ok()).unwrap_or_default()
+    }
+
+    /// `language` in the config if there is no table for it, to warn that English is used
+    pub fn unknown_language(&self) -> Option<&str> {
+        self.language.as_deref().filter(|code| code.parse::<Language>().is_err())
+    }
+
+    pub fn get_context_level(&self) -> ContextLevel {
+        self.context_level
+    }
+
+    pub fn get_suggestion_mode(&self) -> SuggestionMode {
+        self.suggestion_mode
+    }
+
+    pub fn set_suggestion_mode(&mut self, mode: SuggestionMode) {
+        self.suggestion_mode = mode;
+    }
+
+    pub fn get_multiline_commands(&self) -> MultilineCommands {
+        self.multiline_commands
+    }
+
+    pub fn appends_to_shell_history(&self) -> bool {
+        self.append_to_shell_history
+    }
+
+    pub fn inherits_locale(&self) -> bool {
+        self.inherit_locale
+    }
+
+    pub fn get_sandbox_root(&self) -> Option<&Path> {
+        self.sandbox_root.as_deref()
+    }
+
+    /// Shell of `shell` in the config, the detected one if unset or unknown
+    pub fn get_shell(&self) -> ShellType {
+        self.get_shell_override().unwrap_or_else(shell::detect)
+    }
+
+    /// Shell of `shell` in the config, `None` to keep the detected one
+    pub fn get_shell_override(&self) -> Option<ShellType> {
+        self.shell.as_deref().and_then(|name| name.parse().ok())
+    }
+
+    pub fn hides_welcome(&self) -> bool {
+        self.hide_welcome
+    }
+
+    /// Token budget of a prompt with its context, `DEFAULT_MAX_PROMPT_TOKENS` if unset
+    pub fn get_max_prompt_tokens(&self) -> usize {
+        self.max_prompt_tokens.unwrap_or(DEFAULT_MAX_PROMPT_TOKENS)
+    }
+
+    pub fn auto_fixes(&self) -> bool {
+        self.auto_fix
+    }
+
+    /// Corrections asked for in a row by auto-fix, `autofix::DEFAULT_ATTEMPTS` if unset
+    pub fn get_auto_fix_attempts(&self) -> u32 {
+        self.auto_fix_attempts.unwrap_or(autofix::DEFAULT_ATTEMPTS)
+    }
+
+    /// Check whether proxy in Config is set
+    pub fn uses_proxy(&self) -> bool {
+        !self.get_proxy().is_empty()
+    }
+}
+
+/// Config file in the user config directory, `None` if the platform has none
+pub fn standard_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| path_in(&dir.join("aurish")))
+}
+
+/// `config.toml` in `dir`, unless only `config.json` exists there
+fn path_in(dir: &Path) -> PathBuf {
+    let (toml, json) = (dir.join("config.toml"), dir.join("config.json"));
+    if !toml.exists() && json.exists() {
+        json
+    } else {
+        toml
+    }
+}
+
+/// The config file to use, `flag` being the `--config` argument. The file need not exist:
+/// without one anywhere this is the standard location, for creating it there.
+pub fn resolve_path(flag: Option<&Path>) -> PathBuf {
+    first_existing(search_paths(flag))
+}
+
+/// Where the config file is looked for, in order: only the `--config` argument or
+/// `AURISH_CONFIG` if given, the standard location and the legacy one otherwise
+pub fn search_paths(flag: Option<&Path>) -> Vec<PathBuf> {
+    search_paths_with(flag, env::var_os(CONFIG_ENV).map(PathBuf::from), standard_path(), Path::new(LEGACY_PATH))
+}
+
+fn search_paths_with(flag: Option<&Path>, from_env: Option<PathBuf>, standard: Option<PathBuf>, legacy: &Path) -> Vec<PathBuf> {
+    if let Some(path) = flag.map(Path::to_path_buf).or(from_env.filter(|path| !path.as_os_str().is_empty())) {
+        return vec![path];
+    }
+    standard.into_iter().chain([legacy.to_path_buf()]).collect()
+}
+
+/// The first of `searched` that exists, the first one if none does
+fn first_existing(mut searched: Vec<PathBuf>) -> PathBuf {
+    let index = searched.iter().position(|path| path.exists()).unwrap_or(0);
+    searched.swap_remove(index)
+}
+
+/// Load the config and tell where it was found. With `create`, a missing config file is
+/// written with the defaults first, creating its directory.
+pub fn load_config(flag: Option<&Path>, create: bool) -> Result<(Config, PathBuf), ConfigError> {
+    load_from(search_paths(flag), create)
+}
+
+fn load_from(searched: Vec<PathBuf>, create: bool) -> Result<(Config, PathBuf), ConfigError> {
+    if !searched.iter().any(|path| path.exists()) && !create {
+        return Err(ConfigError::NotFound { searched });
+    }
+    let path = first_existing(searched);
+    if path.exists() {
+        return Config::load(&path).map(|config| (config, path));
+    }
+    let config = Config::default();
+    write_config(&config, &path)?;
+    Ok((config, path))
+}
+
+/// How often the TUI looks whether its config file changed
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Keeps `Config::watch` watching, until dropped
+#[derive(Debug)]
+pub struct ConfigWatch {
+    _stop: mpsc::Sender<()>,
+}
+
+/// Lock `.<name>.lock` next to the config at `path`, which stays locked while the file is
+/// open
+fn lock(path: &Path) -> Result<File, ConfigError> {
+    utils::lock_beside(path).map_err(|source| ConfigError::Write { path: path.to_path_buf(), source })
+}
+
+/// What tells a changed file apart: when it was modified and how long it is
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+/// Save `config` to `path` like `Config::save`, creating the directory it goes in first
+pub fn write_config(config: &Config, path: &Path) -> Result<(), ConfigError> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|source| ConfigError::Write { path: path.to_path_buf(), source })?;
+    }
+    config.save(path)
+}
+
+/// Changes to the config given with `aurish-cli --set-*` flags
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    pub ollama_api: Option<String>,
+    pub model: Option<String>,
+    pub proxy: Option<String>,
+    pub unset_proxy: bool,
+}
+
+impl Settings {
+    pub fn is_empty(&self) -> bool {
+        *self == Settings::default()
+    }
+
+    /// Check every value, then apply them all to `config`.
+    /// Returns a line per changed value like `model: llama3:latest -> llama3:8b`,
+    /// and leaves `config` untouched if any value is invalid.
+    pub fn apply(&self, config: &mut Config) -> Result<Vec<String>, ConfigError> {
+        if let Some(api) = &self.ollama_api {
+            check_url("--set-ollama-api", api, &API_SCHEMES)?;
+        }
+        if let Some(proxy) = &self.proxy {
+            check_url("--set-proxy", proxy, &PROXY_SCHEMES)?;
+        }
+        if let Some(model) = self.model.as_ref().filter(|model| model.trim().is_empty()) {
+            return Err(invalid("--set-model", model, "the model name is empty"));
+        }
+
+        let mut changes = Vec::new();
+        let mut change = |name: &str, old: &str, new: &str| {
+            if old != new {
+                let show = |value: &str| if value.is_empty() { "(none)".to_string() } else { value.to_string() };
+                changes.push(format!("{}: {} -> {}", name, show(old), show(new)));
+            }
+        };
+        if let Some(api) = &self.ollama_api {
+            change("ollama_api", config.get_ollama_api(), api);
+            config.set_ollama_api(api.clone());
+        }
+        if let Some(model) = &self.model {
+            change("model", config.get_model(), model.trim());
+            config.set_model(model.trim().to_string());
+        }
+        if let Some(proxy) = self.proxy.as_ref().or(self.unset_proxy.then_some(&String::new())) {
+            change("proxy", config.get_proxy(), proxy);
+            config.set_proxy(proxy.clone());
+        }
+        Ok(changes)
+    }
+}
+
+/// Changes to the config for a single run, given as flags to both binaries. They are only
+/// applied to the loaded config and never saved.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::Args))]
+pub struct Overrides {
+    /// Profile to use for this run, instead of $AURISH_PROFILE or the active one
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub profile: Option<String>,
+    /// Model to use for this run, instead of $AURISH_MODEL or the config
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub model: Option<String>,
+    /// Ollama endpoint to use for this run, instead of $AURISH_OLLAMA_API or the config
+    #[cfg_attr(feature = "clap", arg(long, value_hint = clap::ValueHint::Url))]
+    pub api: Option<String>,
+    /// Seconds a request may take, instead of $AURISH_TIMEOUT or the config
+    #[cfg_attr(feature = "clap", arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..)))]
+    pub timeout: Option<u64>,
+}
+
+impl Overrides {
+    /// Apply the flags, or else the environment variables, to `config`
+    pub fn apply(&self, config: &mut Config) -> Result<(), ConfigError> {
+        self.apply_with(config, |name| env::var(name).ok())
+    }
+
+    fn apply_with(&self, config: &mut Config, env: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        let from_env = |name: &'static str| env(name).filter(|value| !value.trim().is_empty()).map(|value| (name, value));
+        let profile = self.profile.clone().or_else(|| from_env(PROFILE_ENV).map(|(_, profile)| profile));
+        let model = self.model.clone().map(|model| ("--model", model)).or_else(|| from_env(MODEL_ENV));
+        let api = self.api.clone().map(|api| ("--api", api)).or_else(|| from_env(API_ENV));
+        let timeout = match (self.timeout, from_env(TIMEOUT_ENV)) {
+            (Some(seconds), _) => Some(seconds),
+            (None, Some((name, value))) => match value.trim().parse() {
+                Ok(seconds) if seconds > 0 => Some(seconds),
+                _ => return Err(invalid(name, &value, "expected a number of seconds above 0")),
+            },
+            (None, None) => None,
+        };
+
+        if let Some((name, api)) = &api {
+            check_url(name, api, &API_SCHEMES)?;
+        }
+        if let Some((name, model)) = model.as_ref().filter(|(_, model)| model.trim().is_empty()) {
+            return Err(invalid(name, model, "the model name is empty"));
+        }
+        // first, so the other overrides change the chosen profile
+        if let Some(profile) = profile {
+            config.use_profile(profile.trim())?;
+        }
+        if let Some((_, model)) = model {
+            config.set_model(model.trim().to_string());
+        }
+        if let Some((_, api)) = api {
+            config.set_ollama_api(api);
+        }
+        if timeout.is_some() {
+            config.set_timeout(timeout);
+        }
+        Ok(())
+    }
+}
+
+/// Endpoint and model of a profile added with `aurish-cli profile add`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::Args))]
+pub struct ProfileArgs {
+    /// Ollama endpoint, the one of the active profile if left out
+    #[cfg_attr(feature = "clap", arg(long, value_hint = clap::ValueHint::Url))]
+    pub api: Option<String>,
+    /// Model, the one of the active profile if left out
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub model: Option<String>,
+    /// Proxy for the endpoint, none if left out
+    #[cfg_attr(feature = "clap", arg(long, value_hint = clap::ValueHint::Url))]
+    pub proxy: Option<String>,
+    /// Seconds a request may take, the top-level timeout if left out
+    #[cfg_attr(feature = "clap", arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..)))]
+    pub timeout: Option<u64>,
+}
+
+impl ProfileArgs {
+    /// Check the values and make the profile, taking what is missing from the active one
+    pub fn profile(&self, config: &Config) -> Result<ProfileConfig, ConfigError> {
+        if let Some(api) = &self.api {
+            check_url("--api", api, &API_SCHEMES)?;
+        }
+        if let Some(proxy) = &self.proxy {
+            check_url("--proxy", proxy, &PROXY_SCHEMES)?;
+        }
+        if let Some(model) = self.model.as_ref().filter(|model| model.trim().is_empty()) {
+            return Err(invalid("--model", model, "the model name is empty"));
+        }
+        Ok(ProfileConfig {
+            ollama_api: self.api.clone().unwrap_or_else(|| config.get_ollama_api().to_string()),
+            model: self.model.as_deref().unwrap_or(config.get_model()).trim().to_string(),
+            proxy: self.proxy.clone().unwrap_or_default(),
+            timeout: self.timeout,
+        })
+    }
+}
+
+/// Keys of an action in the `keybindings` of the config: one spec, or a list of them.
+/// An empty list leaves the action without keys.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum KeySpecs {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeySpecs {
+    pub fn specs(&self) -> &[String] {
+        match self {
+            KeySpecs::One(spec) => std::slice::from_ref(spec),
+            KeySpecs::Many(specs) => specs,
+        }
+    }
+}
+
+/// URL schemes of the Ollama endpoint
+pub(crate) const API_SCHEMES: [&str; 2] = ["http", "https"];
+/// URL schemes of a proxy
+pub(crate) const PROXY_SCHEMES: [&str; 4] = ["http", "https", "socks5", "socks5h"];
+
+/// Why `value` is not a URL with one of `schemes`, `None` if it is one
+pub(crate) fn url_problem(value: &str, schemes: &[&str]) -> Option<String> {
+    match Url::parse(value) {
+        Err(e) => Some(e.to_string()),
+        Ok(url) if !schemes.contains(&url.scheme()) => {
+            Some(format!("the scheme must be one of {}", schemes.join(", ")))
+        },
+        Ok(_) => None,
+    }
+}
+
+fn check_url(setting: &'static str, value: &str, schemes: &[&str]) -> Result<(), ConfigError> {
+    match url_problem(value, schemes) {
+        Some(reason) => Err(invalid(setting, value, &reason)),
+        None => Ok(()),
+    }
+}
+
+fn invalid(setting: &'static str, value: &str, reason: &str) -> ConfigError {
+    ConfigError::Invalid { setting, value: value.to_string(), reason: reason.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aurish-config-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn resolution_order() {
+        let dir = temp_dir("resolve");
+        let (flag, from_env) = (dir.join("flag.json"), dir.join("env.json"));
+        let (standard, legacy) = (dir.join("aurish").join("config.json"), dir.join("config.json"));
+        let resolve = |flag: Option<&Path>, from_env: Option<PathBuf>| {
+            first_existing(search_paths_with(flag, from_env, Some(standard.clone()), &legacy))
+        };
+
+        assert_eq!(resolve(Some(&flag), Some(from_env.clone())), flag);
+        assert_eq!(resolve(None, Some(from_env.clone())), from_env);
+        assert_eq!(resolve(None, Some(PathBuf::new())), standard);
+        // an existing legacy file is still used, unless the standard one exists too
+        fs::write(&legacy, "{}").unwrap();
+        assert_eq!(resolve(None, None), legacy);
+        fs::create_dir_all(standard.parent().unwrap()).unwrap();
+        fs::write(&standard, "{}").unwrap();
+        assert_eq!(resolve(None, None), standard);
+        assert_eq!(first_existing(search_paths_with(None, None, None, &legacy)), legacy);
+        assert_eq!(search_paths_with(None, None, Some(standard.clone()), &legacy), [standard.clone(), legacy.clone()]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn standard_file_name() {
+        let dir = temp_dir("standard");
+        assert_eq!(path_in(&dir), dir.join("config.toml"));
+        fs::write(dir.join("config.json"), "{}").unwrap();
+        assert_eq!(path_in(&dir), dir.join("config.json"));
+        fs::write(dir.join("config.toml"), "").unwrap();
+        assert_eq!(path_in(&dir), dir.join("config.toml"));
+        assert_eq!(ConfigFormat::of(Path::new("aurish.TOML")), ConfigFormat::Toml);
+        assert_eq!(ConfigFormat::of(Path::new("aurish")), ConfigFormat::Json);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn config_formats() {
+        let dir = temp_dir("formats");
+        let fixtures = [
+            ("config.json", include_str!("../tests/fixtures/config.json")),
+            ("config.toml", include_str!("../tests/fixtures/config.toml")),
+        ];
+        for (name, text) in fixtures {
+            let path = dir.join(name);
+            fs::write(&path, text).unwrap();
+            let mut config = Config::load(&path).unwrap();
+            assert_eq!((config.active_profile(), config.get_model()), ("remote", "mixtral"), "{}", name);
+            assert_eq!(config.get_proxy(), "socks5://127.0.0.1:1080");
+            let names: Vec<_> = config.profiles().into_iter().map(|(name, _)| name.to_string()).collect();
+            assert_eq!(names, ["default", "laptop", "remote"]);
+            assert!(config.confirms_commands());
+            assert_eq!(config.unknown_fields().collect::<Vec<_>>(), ["options"]);
+
+            // saved in the same format, unknown sections included
+            Settings { model: Some("mixtral:8x22b".to_string()), ..Settings::default() }.apply(&mut config).unwrap();
+            config.save(&path).unwrap();
+            let saved = fs::read_to_string(&path).unwrap();
+            assert_eq!(saved.trim_start().starts_with('{'), name.ends_with(".json"), "{}", saved);
+            let config = Config::load(&path).unwrap();
+            assert_eq!(config.get_model(), "mixtral:8x22b");
+            assert_eq!(config.unknown_fields().collect::<Vec<_>>(), ["options"]);
+            assert!(saved.contains("temperature"), "{}", saved);
+        }
+
+        let path = dir.join("broken.toml");
+        fs::write(&path, "model = ").unwrap();
+        assert!(matches!(Config::load(&path), Err(ConfigError::ParseToml { .. })));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_config() {
+        let dir = temp_dir("missing");
+        let path = dir.join("aurish").join("config.json");
+        let legacy = dir.join("config.json");
+        let searched = vec![path.clone(), legacy.clone()];
+        let error = load_from(searched.clone(), false).unwrap_err();
+        assert!(matches!(&error, ConfigError::NotFound { searched: paths } if *paths == searched));
+        let message = error.to_string();
+        assert!(message.starts_with(&format!("no config found at {} or {}, ", path.display(), legacy.display())), "{}", message);
+
+        // created at the first place searched
+        let (config, loaded) = load_from(searched.clone(), true).unwrap();
+        assert_eq!((config.get_model(), loaded), ("llama3:latest", path.clone()));
+        assert_eq!(Config::load(&path).unwrap().get_model(), "llama3:latest");
+
+        fs::write(&path, "{").unwrap();
+        assert!(load_from(searched, false).unwrap_err().to_string().contains("is not a valid config"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn apply_settings() {
+        let mut config = Config::default();
+        let settings = Settings {
+            model: Some("llama3:8b".to_string()),
+            proxy: Some("http://proxy:8080".to_string()),
+            ..Settings::default()
+        };
+        assert_eq!(settings.apply(&mut config).unwrap(), ["model: llama3:latest -> llama3:8b", "proxy: (none) -> http://proxy:8080"]);
+        assert_eq!((config.get_model(), config.get_proxy()), ("llama3:8b", "http://proxy:8080"));
+
+        let unset = Settings { unset_proxy: true, ..Settings::default() };
+        assert_eq!(unset.apply(&mut config).unwrap(), ["proxy: http://proxy:8080 -> (none)"]);
+        assert!(!config.uses_proxy());
+        assert!(unset.apply(&mut config).unwrap().is_empty());
+
+        // nothing is applied when one value is wrong
+        let invalid = Settings {
+            model: Some("mistral".to_string()),
+            ollama_api: Some("localhost:11434/api/generate".to_string()),
+            ..Settings::default()
+        };
+        let error = invalid.apply(&mut config).unwrap_err().to_string();
+        assert!(error.starts_with("invalid value `localhost:11434/api/generate` for --set-ollama-api"), "{}", error);
+        assert_eq!(config.get_model(), "llama3:8b");
+        let empty = Settings { model: Some(" ".to_string()), ..Settings::default() };
+        assert!(empty.apply(&mut config).is_err());
+    }
+
+    #[test]
+    fn override_precedence() {
+        let env = |name: &str| match name {
+            MODEL_ENV => Some("mistral".to_string()),
+            TIMEOUT_ENV => Some("30".to_string()),
+            _ => None,
+        };
+        let mut config = Config::default();
+        Overrides::default().apply_with(&mut config, env).unwrap();
+        assert_eq!((config.get_model(), config.get_timeout()), ("mistral", Some(std::time::Duration::from_secs(30))));
+        assert_eq!(config.get_ollama_api(), "http://localhost:11434/api/generate");
+
+        let flags = Overrides {
+            model: Some("llama3:8b".to_string()),
+            api: Some("http://gpu:11434/api/generate".to_string()),
+            ..Overrides::default()
+        };
+        flags.apply_with(&mut config, env).unwrap();
+        assert_eq!((config.get_model(), config.get_ollama_api()), ("llama3:8b", "http://gpu:11434/api/generate"));
+
+        let bad_env = |name: &str| (name == TIMEOUT_ENV).then(|| "soon".to_string());
+        let error = Overrides::default().apply_with(&mut config, bad_env).unwrap_err();
+        assert!(error.to_string().starts_with("invalid value `soon` for AURISH_TIMEOUT"), "{}", error);
+        let bad_flag = Overrides { api: Some("gpu:11434".to_string()), ..Overrides::default() };
+        assert!(bad_flag.apply_with(&mut config, |_| None).unwrap_err().to_string().contains("for --api"));
+    }
+
+    #[test]
+    fn profiles() {
+        let dir = temp_dir("profiles");
+        let path = dir.join("config.json");
+        // a config without profiles is the default profile
+        fs::write(&path, r#"{"ollama_api": "http://localhost:11434/api/generate", "model": "llama3:latest", "proxy": ""}"#).unwrap();
+        let mut config = Config::load(&path).unwrap();
+        assert_eq!(config.active_profile(), "default");
+
+        let remote = ProfileArgs {
+            api: Some("http://gpu:11434/api/generate".to_string()),
+            model: Some("mixtral".to_string()),
+            timeout: Some(300),
+            ..ProfileArgs::default()
+        };
+        config.add_profile("remote", remote.profile(&config).unwrap()).unwrap();
+        assert!(matches!(config.add_profile("remote", remote.profile(&config).unwrap()), Err(ConfigError::ProfileExists(_))));
+        assert!(matches!(config.add_profile("default", remote.profile(&config).unwrap()), Err(ConfigError::ProfileExists(_))));
+        assert!(ProfileArgs { api: Some("gpu".to_string()), ..ProfileArgs::default() }.profile(&config).is_err());
+
+        config.use_profile("remote").unwrap();
+        assert_eq!((config.get_model(), config.get_ollama_api()), ("mixtral", "http://gpu:11434/api/generate"));
+        assert_eq!(config.get_timeout(), Some(std::time::Duration::from_secs(300)));
+        // settings change the active profile only
+        Settings { model: Some("mixtral:8x22b".to_string()), ..Settings::default() }.apply(&mut config).unwrap();
+        config.save(&path).unwrap();
+        let mut config = Config::load(&path).unwrap();
+        assert_eq!((config.active_profile(), config.get_model()), ("remote", "mixtral:8x22b"));
+        let names: Vec<_> = config.profiles().into_iter().map(|(name, profile)| (name.to_string(), profile.model)).collect();
+        assert_eq!(names, [("default".to_string(), "llama3:latest".to_string()), ("remote".to_string(), "mixtral:8x22b".to_string())]);
+
+        // an override picks another profile for one run
+        let flags = Overrides { profile: Some("default".to_string()), ..Overrides::default() };
+        let mut run = Config::load(&path).unwrap();
+        flags.apply_with(&mut run, |_| None).unwrap();
+        assert_eq!(run.get_model(), "llama3:latest");
+        let unknown = Overrides { profile: Some("laptop".to_string()), ..Overrides::default() };
+        assert!(matches!(unknown.apply_with(&mut run, |_| None), Err(ConfigError::UnknownProfile(_))));
+
+        assert!(config.remove_profile("default").is_err());
+        config.remove_profile("remote").unwrap();
+        assert_eq!((config.active_profile(), config.get_model()), ("default", "llama3:latest"));
+
+        fs::write(&path, r#"{"ollama_api": "http://localhost:11434/api/generate", "model": "m", "proxy": "", "active_profile": "gone"}"#).unwrap();
+        assert!(matches!(Config::load(&path), Err(ConfigError::UnknownProfile(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn config_changes_are_saved() {
+        let dir = std::env::temp_dir().join(format!("aurish-config-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+        Config::default().save(&path).unwrap();
+
+        let mut config = Config::load(&path).unwrap();
+        config.set_proxy("http://proxy.example.com:3128".to_string());
+        config.save(&path).unwrap();
+        assert_eq!(Config::load(&path).unwrap().get_proxy(), "http://proxy.example.com:3128");
+        // the temporary file is gone, only the lock is left next to it
+        let names: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(names.len(), 2, "{:?}", names);
+        assert!(names.contains(&".config.json.lock".into()), "{:?}", names);
+
+        let missing = dir.join("missing").join("config.json");
+        let error = Config::default().save(&missing).unwrap_err().to_string();
+        assert!(error.contains(&missing.display().to_string()), "{}", error);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_updates_keep_every_change() {
+        let dir = std::env::temp_dir().join(format!("aurish-config-update-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        Config::default().save(&path).unwrap();
+        let writers: Vec<_> = (0..8)
+            .map(|n| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    Config::update(&path, |config| {
+                        config.dangerous_patterns.push(format!("pattern {}", n));
+                        Ok(())
+                    })
+                    .unwrap()
+                })
+            })
+            .collect();
+        writers.into_iter().for_each(|writer| writer.join().unwrap());
+        let mut patterns = Config::load(&path).unwrap().dangerous_patterns;
+        patterns.sort();
+        assert_eq!(patterns, (0..8).map(|n| format!("pattern {}", n)).collect::<Vec<_>>());
+
+        // a failed change saves nothing
+        let error = Config::update(&path, |config| {
+            config.set_model("never-saved".to_string());
+            Err::<(), _>(ConfigError::UnknownProfile("gone".to_string()))
+        });
+        assert!(error.is_err());
+        assert_eq!(Config::load(&path).unwrap().get_model(), "llama3:latest");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn watch_reports_external_changes() {
+        let dir = std::env::temp_dir().join(format!("aurish-config-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+        Config::default().save(&path).unwrap();
+        let before = fs::metadata(&path).unwrap().modified().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let watch = Config::watch(&path, Duration::from_millis(10), move |loaded| tx.send(loaded).unwrap());
+        // nothing changed yet
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        // another process saves a new model and endpoint
+        let mut config = Config::load(&path).unwrap();
+        config.set_model("mistral:7b".to_string());
+        config.set_ollama_api("http://192.168.1.5:11434/api/generate".to_string());
+        config.save(&path).unwrap();
+        File::options().write(true).open(&path).unwrap().set_modified(before + Duration::from_secs(5)).unwrap();
+        let reloaded = rx.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!((reloaded.get_model(), reloaded.get_ollama_api()), ("mistral:7b", "http://192.168.1.5:11434/api/generate"));
+
+        // a broken file is reported as such
+        fs::write(&path, "{ not json").unwrap();
+        assert!(matches!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), Err(ConfigError::Parse { .. })));
+
+        // and nothing more once the watch is dropped
+        drop(watch);
+        thread::sleep(Duration::from_millis(50));
+        Config::default().save(&path).unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "tui")]
+    fn config_validation() {
+        let problems = |json: &str| -> Vec<String> {
+            let config: Config = serde_json::from_str(json).unwrap();
+            config.validate().err().unwrap_or_default().iter().map(ToString::to_string).collect()
+        };
+        let valid = |extra: &str| {
+            format!(r#"{{"ollama_api": "http://localhost:11434/api/generate", "model": "llama3:latest", "proxy": ""{}}}"#, extra)
+        };
+        assert!(problems(&valid("")).is_empty());
+
+        let wrong = problems(r#"{"ollama_api": "localhost:11434", "model": " ", "proxy": "proxy:8080", "timeout": 0}"#);
+        assert_eq!(wrong.len(), 4, "{:?}", wrong);
+        assert!(wrong[0].starts_with("invalid value `localhost:11434` for ollama_api: "), "{}", wrong[0]);
+        assert!(wrong[1].starts_with("invalid value `proxy:8080` for proxy: "), "{}", wrong[1]);
+        assert_eq!(wrong[2], "invalid value ` ` for model: the model name is empty");
+        assert_eq!(wrong[3], "invalid value `0` for timeout: expected 1 to 86400 seconds");
+        assert_eq!(problems(&valid(r#", "timeout": 90000"#)).len(), 1);
+        assert_eq!(problems(&valid(r#", "max_requests_per_minute": 0"#))[0], "invalid value `0` for max_requests_per_minute: expected a number of requests above 0");
+        assert_eq!(problems(&valid(r#", "sandbox_root": "/no/such/aurish/root""#))[0], "invalid value `/no/such/aurish/root` for sandbox_root: no such directory");
+        assert_eq!(problems(&valid(r#", "max_prompt_tokens": 0"#))[0], "invalid value `0` for max_prompt_tokens: expected a number of tokens above 0");
+        assert_eq!(problems(&valid(r#", "auto_fix": true, "auto_fix_attempts": 11"#))[0], "invalid value `11` for auto_fix_attempts: expected 1 to 10 attempts");
+
+        let profile = problems(&valid(r#", "profiles": {"remote": {"ollama_api": "ftp://gpu", "model": "mixtral"}}"#));
+        assert_eq!(profile, ["invalid value `ftp://gpu` for profiles.remote.ollama_api: the scheme must be one of http, https"]);
+
+        assert!(problems(&valid(r#", "theme": "solarized""#))[0].starts_with("invalid value `solarized` for theme: "));
+        let color = problems(&valid(r#", "theme": {"stderr": "mauve"}"#));
+        assert!(color[0].starts_with("invalid value `mauve` for theme.stderr: "), "{:?}", color);
+        assert!(problems(&valid(r#", "keymap": "emacs""#))[0].ends_with("valid keymaps are default, vim"));
+        assert!(problems(&valid(r#", "dangerous_patterns": ["rm", " "]"#))[0].contains("for dangerous_patterns"));
+        assert!(problems(&valid(r#", "shell": "tcsh""#))[0].starts_with("invalid value `tcsh` for shell: unknown shell `tcsh`, valid shells are "));
+        let shell: Config = serde_json::from_str(&valid(r#", "shell": "PWSH""#)).unwrap();
+        assert_eq!((shell.validate().is_ok(), shell.get_shell_override()), (true, Some(ShellType::PowerShell)));
+        assert_eq!(Config::default().get_shell_override(), None);
+        let keys = problems(&valid(r#", "keybindings": {"quit": "ctrl+q", "fly": "f", "ask_ai": ["i", "shift+1"], "toggle_help": "a"}"#));
+        assert_eq!(keys.len(), 3, "{:?}", keys);
+        assert!(keys[0].starts_with("invalid value `shift+1` for keybindings.ask_ai: shift only goes with letters"), "{}", keys[0]);
+        assert!(keys[1].starts_with("invalid value `f` for keybindings.fly: unknown action, valid actions are: quit, ask_ai, "), "{}", keys[1]);
+        assert_eq!(keys[2], "invalid value `a` for keybindings.toggle_help: conflicts with `ask_ai` among the Normal keys");
+
+        let config: Config = serde_json::from_str(r#"{"ollama_api": "x", "model": "", "proxy": ""}"#).unwrap();
+        let error = ConfigError::Problems { path: PathBuf::from("config.json"), problems: config.validate().unwrap_err() };
+        let message = error.to_string();
+        assert!(message.starts_with("config.json has 2 problem(s):\n  invalid value `x` for ollama_api: "), "{}", message);
+        assert!(message.ends_with("\n  invalid value `` for model: the model name is empty"), "{}", message);
+    }
+
+    #[test]
+    fn unknown_config_fields() {
+        let json = r#"{"ollama_api": "http://localhost:11434/api/generate", "model": "m", "proxy": "", "colour": "red", "future": {"a": 1}}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert_eq!(config.unknown_fields().collect::<Vec<_>>(), ["colour", "future"]);
+        assert!(config.validate().is_ok());
+        // written back, for the version that knows them
+        let saved = serde_json::to_value(&config).unwrap();
+        assert_eq!(saved["future"]["a"], 1);
+        assert!(Config::default().unknown_fields().next().is_none());
+    }
+
+    #[test]
+    fn config_language() {
+        let language = |fields: &str| {
+            let json = format!(r#"{{"ollama_api": "http://localhost:11434/api/generate", "model": "m", "proxy": ""{}}}"#, fields);
+            let config: Config = serde_json::from_str(&json).unwrap();
+            (config.get_language(), config.unknown_language().map(str::to_string), config.validate().is_ok())
+        };
+        assert_eq!(language(""), (Language::English, None, true));
+        assert_eq!(language(r#", "language": "es""#), (Language::Spanish, None, true));
+        // English then, with a warning rather than an error
+        assert_eq!(language(r#", "language": "klingon""#), (Language::English, Some("klingon".to_string()), true));
+        assert!(serde_json::to_value(Config::default()).unwrap().get("language").is_none());
+    }
+}
@@ -0,0 +1,122 @@
+//! Character-level diff of a suggested command and the command the user made of it
+//!
+//! Commands are short, so this is the textbook longest common subsequence over characters,
+//! after taking off the common prefix and suffix. Past `MAX_CELLS` the differing middle is
+//! shown as replaced in one piece instead.
+
+/// Largest LCS table built, in cells
+const MAX_CELLS: usize = 250_000;
+
+/// A run of characters of the diff, in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// In both commands
+    Same(String),
+    /// Only in the original
+    Removed(String),
+    /// Only in the edited command
+    Added(String),
+}
+
+/// What changed from `original` to `edited`, adjacent runs of the same kind merged
+pub fn diff_chars(original: &str, edited: &str) -> Vec<Change> {
+    let old: Vec<char> = original.chars().collect();
+    let new: Vec<char> = edited.chars().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old_mid, new_mid) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut changes = Vec::new();
+    push(&mut changes, Change::Same(old[..prefix].iter().collect()));
+    if old_mid.len().saturating_mul(new_mid.len()) > MAX_CELLS {
+        push(&mut changes, Change::Removed(old_mid.iter().collect()));
+        push(&mut changes, Change::Added(new_mid.iter().collect()));
+    } else {
+        for change in lcs_diff(old_mid, new_mid) {
+            push(&mut changes, change);
+        }
+    }
+    push(&mut changes, Change::Same(old[old.len() - suffix..].iter().collect()));
+    changes
+}
+
+/// Diff of `old` and `new` one character at a time, removals before additions
+fn lcs_diff(old: &[char], new: &[char]) -> Vec<Change> {
+    // lcs[i][j] is the length of the LCS of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = match old[i] == new[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut changes = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            changes.push(Change::Same(old[i].to_string()));
+            (i, j) = (i + 1, j + 1);
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            changes.push(Change::Removed(old[i].to_string()));
+            i += 1;
+        } else {
+            changes.push(Change::Added(new[j].to_string()));
+            j += 1;
+        }
+    }
+    changes
+}
+
+/// Append `change`, merged into the last one if it is of the same kind. Empty runs are dropped.
+fn push(changes: &mut Vec<Change>, change: Change) {
+    match (changes.last_mut(), change) {
+        (_, Change::Same(text) | Change::Removed(text) | Change::Added(text)) if text.is_empty() => {},
+        (Some(Change::Same(last)), Change::Same(text))
+        | (Some(Change::Removed(last)), Change::Removed(text))
+        | (Some(Change::Added(last)), Change::Added(text)) => last.push_str(&text),
+        (_, change) => changes.push(change),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn same(text: &str) -> Change {
+        Change::Same(text.to_string())
+    }
+
+    fn removed(text: &str) -> Change {
+        Change::Removed(text.to_string())
+    }
+
+    fn added(text: &str) -> Change {
+        Change::Added(text.to_string())
+    }
+
+    #[test]
+    fn insertions_and_deletions() {
+        assert_eq!(diff_chars("ls", "ls"), [same("ls")]);
+        assert_eq!(diff_chars("", ""), []);
+        assert_eq!(diff_chars("du -sh *", "du -sh * | sort -h"), [same("du -sh *"), added(" | sort -h")]);
+        assert_eq!(diff_chars("rm -rf build", "rm -r build"), [same("rm -r"), removed("f"), same(" build")]);
+        assert_eq!(diff_chars("git push", "sudo git push"), [added("sudo "), same("git push")]);
+        assert_eq!(diff_chars("cp a.txt b.txt", "cp -v a.txt c.txt"), [
+            same("cp "), added("-v "), same("a.txt "), removed("b"), added("c"), same(".txt"),
+        ]);
+    }
+
+    #[test]
+    fn replacements() {
+        assert_eq!(diff_chars("ls", "pwd"), [removed("ls"), added("pwd")]);
+        assert_eq!(diff_chars("", "ls"), [added("ls")]);
+        assert_eq!(diff_chars("ls", ""), [removed("ls")]);
+        // characters, not bytes
+        assert_eq!(diff_chars("echo é", "echo è"), [same("echo "), removed("é"), added("è")]);
+        // too long to compare character by character
+        let (old, new) = (format!("x{}y", "a".repeat(600)), format!("x{}y", "b".repeat(600)));
+        assert_eq!(diff_chars(&old, &new), [same("x"), removed(&"a".repeat(600)), added(&"b".repeat(600)), same("y")]);
+    }
+}
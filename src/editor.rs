@@ -0,0 +1,295 @@
+//! Multi-line text editing for the Asking AI box
+//!
+//! `tui_input::Input` only holds a single line, which makes pasting an error message or a
+//! few lines of a config file into a prompt impossible. `PromptEditor` keeps the text as a
+//! list of lines with a cursor, and wraps it for display at character boundaries so the
+//! cursor position on screen is always exact.
+
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use unicode_width::UnicodeWidthChar;
+
+/// Text being edited, always at least one (possibly empty) line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptEditor {
+    lines: Vec<String>,
+    /// Line of the cursor
+    row: usize,
+    /// Position of the cursor in `lines[row]`, in characters
+    col: usize,
+}
+
+impl Default for PromptEditor {
+    fn default() -> Self {
+        PromptEditor { lines: vec![String::new()], row: 0, col: 0 }
+    }
+}
+
+/// Text wrapped to a width, as it is drawn
+pub struct Wrapped {
+    pub rows: Vec<String>,
+    /// Row and display column of the cursor
+    pub cursor: (usize, usize),
+}
+
+impl PromptEditor {
+    /// Editor holding `value`, with the cursor at its end
+    pub fn new(value: String) -> Self {
+        let mut editor = PromptEditor::default();
+        editor.insert_str(&value);
+        editor
+    }
+
+    /// The text, lines joined with `\n`
+    pub fn value(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    /// Line and character position of the cursor
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.col)
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn reset(&mut self) {
+        *self = PromptEditor::default();
+    }
+
+    pub fn insert_char(&mut self, c: char) {
+        let idx = self.byte_idx(self.col);
+        self.lines[self.row].insert(idx, c);
+        self.col += 1;
+    }
+
+    /// Split the line at the cursor
+    pub fn insert_newline(&mut self) {
+        let idx = self.byte_idx(self.col);
+        let rest = self.lines[self.row].split_off(idx);
+        self.row += 1;
+        self.col = 0;
+        self.lines.insert(self.row, rest);
+    }
+
+    /// Insert pasted text as is, `\r\n` and `\r` line endings become newlines
+    pub fn insert_str(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        for c in text.chars() {
+            match c {
+                '\n' => self.insert_newline(),
+                '\t' => self.insert_char(' '),
+                c if c.is_control() => {},
+                c => self.insert_char(c),
+            }
+        }
+    }
+
+    /// Delete the character before the cursor, joining lines at the start of one
+    pub fn backspace(&mut self) {
+        if self.col > 0 {
+            self.col -= 1;
+            let idx = self.byte_idx(self.col);
+            self.lines[self.row].remove(idx);
+        } else if self.row > 0 {
+            let line = self.lines.remove(self.row);
+            self.row -= 1;
+            self.col = self.line_len(self.row);
+            self.lines[self.row].push_str(&line);
+        }
+    }
+
+    /// Delete the character under the cursor, joining lines at the end of one
+    pub fn delete(&mut self) {
+        if self.col < self.line_len(self.row) {
+            let idx = self.byte_idx(self.col);
+            self.lines[self.row].remove(idx);
+        } else if self.row + 1 < self.lines.len() {
+            let line = self.lines.remove(self.row + 1);
+            self.lines[self.row].push_str(&line);
+        }
+    }
+
+    /// Delete the word before the cursor
+    pub fn delete_word(&mut self) {
+        let chars: Vec<char> = self.lines[self.row].chars().collect();
+        let mut start = self.col;
+        while start > 0 && chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        let (from, to) = (self.byte_idx(start), self.byte_idx(self.col));
+        self.lines[self.row].replace_range(from..to, "");
+        self.col = start;
+    }
+
+    /// Delete from the start of the line to the cursor
+    pub fn delete_line_before(&mut self) {
+        let idx = self.byte_idx(self.col);
+        self.lines[self.row].replace_range(..idx, "");
+        self.col = 0;
+    }
+
+    pub fn move_left(&mut self) {
+        if self.col > 0 {
+            self.col -= 1;
+        } else if self.row > 0 {
+            self.row -= 1;
+            self.col = self.line_len(self.row);
+        }
+    }
+
+    pub fn move_right(&mut self) {
+        if self.col < self.line_len(self.row) {
+            self.col += 1;
+        } else if self.row + 1 < self.lines.len() {
+            self.row += 1;
+            self.col = 0;
+        }
+    }
+
+    /// Move to the line above, returns false on the first line
+    pub fn move_up(&mut self) -> bool {
+        if self.row == 0 {
+            return false;
+        }
+        self.row -= 1;
+        self.col = self.col.min(self.line_len(self.row));
+        true
+    }
+
+    /// Move to the line below, returns false on the last line
+    pub fn move_down(&mut self) -> bool {
+        if self.row + 1 >= self.lines.len() {
+            return false;
+        }
+        self.row += 1;
+        self.col = self.col.min(self.line_len(self.row));
+        true
+    }
+
+    pub fn move_home(&mut self) {
+        self.col = 0;
+    }
+
+    pub fn move_end(&mut self) {
+        self.col = self.line_len(self.row);
+    }
+
+    /// Apply an editing key, returns whether it was one.
+    /// Enter is left to the caller, it submits the prompt.
+    pub fn handle_key(&mut self, key: &KeyEvent) -> bool {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('w') if ctrl => self.delete_word(),
+            KeyCode::Char('u') if ctrl => self.delete_line_before(),
+            KeyCode::Char('a') if ctrl => self.move_home(),
+            KeyCode::Char('e') if ctrl => self.move_end(),
+            KeyCode::Char(_) if ctrl => return false,
+            KeyCode::Char(c) => self.insert_char(c),
+            KeyCode::Backspace => self.backspace(),
+            KeyCode::Delete => self.delete(),
+            KeyCode::Left => self.move_left(),
+            KeyCode::Right => self.move_right(),
+            KeyCode::Home => self.move_home(),
+            KeyCode::End => self.move_end(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Break the text into rows at most `width` columns wide
+    pub fn wrap(&self, width: usize) -> Wrapped {
+        let width = width.max(1);
+        let mut rows = Vec::new();
+        let mut cursor = (0, 0);
+        for (line_idx, line) in self.lines.iter().enumerate() {
+            let mut row = String::new();
+            let mut x = 0;
+            for (char_idx, c) in line.chars().enumerate() {
+                let w = c.width().unwrap_or(0);
+                if x + w > width && x > 0 {
+                    rows.push(std::mem::take(&mut row));
+                    x = 0;
+                }
+                if line_idx == self.row && char_idx == self.col {
+                    cursor = (rows.len(), x);
+                }
+                row.push(c);
+                x += w;
+            }
+            if line_idx == self.row && self.col >= self.line_len(line_idx) {
+                cursor = (rows.len(), x);
+            }
+            rows.push(row);
+        }
+        Wrapped { rows, cursor }
+    }
+
+    fn line_len(&self, row: usize) -> usize {
+        self.lines[row].chars().count()
+    }
+
+    /// Byte index of the `col`th character of the cursor line
+    fn byte_idx(&self, col: usize) -> usize {
+        let line = &self.lines[self.row];
+        line.char_indices().nth(col).map_or(line.len(), |(idx, _)| idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_lines() {
+        let mut editor = PromptEditor::new("fix this error:".to_string());
+        editor.insert_newline();
+        editor.insert_str("error[E0382]: borrow\r\nof moved value");
+        assert_eq!(editor.value(), "fix this error:\nerror[E0382]: borrow\nof moved value");
+        assert_eq!(editor.cursor(), (2, 14));
+
+        editor.move_home();
+        editor.backspace();
+        assert_eq!(editor.value(), "fix this error:\nerror[E0382]: borrowof moved value");
+        assert_eq!(editor.cursor(), (1, 20));
+        editor.insert_char(' ');
+        assert!(editor.move_up());
+        assert!(!editor.move_up());
+        assert_eq!(editor.cursor(), (0, 15));
+        editor.delete();
+        assert_eq!(editor.line_count(), 1);
+    }
+
+    #[test]
+    fn delete_words() {
+        let mut editor = PromptEditor::new("list big  files".to_string());
+        editor.delete_word();
+        assert_eq!(editor.value(), "list big  ");
+        editor.delete_word();
+        assert_eq!(editor.value(), "list ");
+        editor.delete_line_before();
+        assert_eq!(editor.value(), "");
+    }
+
+    #[test]
+    fn wrap_with_cursor() {
+        let editor = PromptEditor::new("abcdefgh\n文件文件".to_string());
+        let wrapped = editor.wrap(5);
+        assert_eq!(wrapped.rows, ["abcde", "fgh", "文件", "文件"]);
+        assert_eq!(wrapped.cursor, (3, 4));
+
+        let mut editor = editor;
+        assert!(editor.move_up());
+        editor.move_home();
+        editor.move_right();
+        assert_eq!(editor.wrap(5).cursor, (0, 1));
+        // the cursor moves to the next row together with the character it is on
+        for _ in 0..4 {
+            editor.move_right();
+        }
+        assert_eq!(editor.wrap(5).cursor, (1, 0));
+    }
+}
@@ -1,26 +1,946 @@
-use std::fmt;
-
-/// Error type returned from constructing a shell
-///
-/// The `ShellInitError` enum represents the various errors that may occur when
-/// attempting to initialize a shell. This includes errors related to directory
-/// access permissions and existence.
-#[derive(Debug)]
-pub enum ShellInitError {
-    /// This variant indicates that an error occurred related to a directory.
-    /// It can occur when trying to construct an `IShell` inside a directory that does not exist.
-    ///
-    /// The associated `String` contains a message that provides more details about the error,
-    /// such as the directory (or variations of the directory) that could not be found.
-    ///
-    /// Display trait included.
-    DirectoryError(String),
-}
-
-impl fmt::Display for ShellInitError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            ShellInitError::DirectoryError(msg) => write!(f, "IShell directory error: {}", msg),
-        }
-    }
-}
\ No newline at end of file
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+#[cfg(feature = "cli")]
+use rustyline::error::ReadlineError;
+
+/// Error type returned from constructing a shell
+///
+/// The `ShellInitError` enum represents the various errors that may occur when
+/// attempting to initialize a shell. This includes errors related to directory
+/// access permissions and existence.
+#[derive(Debug)]
+pub enum ShellInitError {
+    /// This variant indicates that an error occurred related to a directory.
+    /// It can occur when trying to construct an `IShell` inside a directory that does not exist.
+    ///
+    /// The associated `String` contains a message that provides more details about the error,
+    /// such as the directory (or variations of the directory) that could not be found.
+    ///
+    /// Display trait included.
+    DirectoryError(String),
+}
+
+impl fmt::Display for ShellInitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShellInitError::DirectoryError(msg) => write!(f, "IShell directory error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ShellInitError {}
+
+/// Error type returned from parsing the name of a shell, e.g. the `shell` of the config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShellTypeError {
+    /// No shell goes by this name.
+    Unknown(String),
+}
+
+impl fmt::Display for ShellTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShellTypeError::Unknown(name) => write!(
+                f,
+                "unknown shell `{}`, valid shells are powershell (or pwsh), cmd, bash (or sh), fish, zsh and ksh",
+                name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ShellTypeError {}
+
+/// Error type returned from sending a request to the Ollama server
+///
+/// The `BackendError` enum represents the ways a request to the model can fail,
+/// either on the way to the server or while making sense of its answer.
+#[cfg(feature = "backend-async")]
+#[derive(Debug)]
+pub enum BackendError {
+    /// The server could not be reached, or its response could not be read.
+    ///
+    /// Wraps the underlying `reqwest::Error`, e.g. a refused connection or a timeout.
+    RequestError(reqwest::Error),
+    /// The response was not the JSON Aurish expects.
+    ///
+    /// This covers both the Ollama response envelope and the `commands` object the model
+    /// is asked to produce.
+    ParseError(serde_json::Error),
+    /// The async runtime the blocking client runs its requests on could not be started.
+    Runtime(io::Error),
+    /// The server answered with an error status, e.g. 404 for a model that is not installed.
+    ///
+    /// `message` is the `error` Ollama sent along, or the start of the body if there was none.
+    Server { status: reqwest::StatusCode, message: String },
+    /// A request for commands was refused, since the client is still waiting for the
+    /// answer to another one.
+    Busy,
+    /// A request for commands was refused, since `limit` were sent in the last minute
+    /// already; the next one can go out after `retry_in`.
+    RateLimited { limit: u32, retry_in: std::time::Duration },
+}
+
+#[cfg(feature = "backend-async")]
+impl BackendError {
+    /// Whether the model asked for is not on the server, which Ollama answers with a 404
+    /// naming the model. A wrong endpoint path also gets a 404, without the model.
+    pub fn is_missing_model(&self) -> bool {
+        matches!(self, BackendError::Server { status, message } if *status == reqwest::StatusCode::NOT_FOUND && message.contains("model"))
+    }
+}
+
+#[cfg(feature = "backend-async")]
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendError::RequestError(err) => write!(f, "Ollama request error: {}", err),
+            BackendError::ParseError(err) => write!(f, "Ollama response error: {}", err),
+            BackendError::Runtime(err) => write!(f, "could not start the async runtime: {}", err),
+            BackendError::Server { status, message } if message.is_empty() => write!(f, "Ollama answered {}", status),
+            BackendError::Server { status, message } => write!(f, "Ollama answered {}: {}", status, message),
+            BackendError::Busy => write!(f, "still waiting for the answer to the previous request"),
+            BackendError::RateLimited { limit, retry_in } => write!(
+                f,
+                "{} requests sent in the last minute already, try again in {}s",
+                limit,
+                retry_in.as_secs_f64().ceil(),
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "backend-async")]
+impl std::error::Error for BackendError {}
+
+#[cfg(feature = "backend-async")]
+impl From<reqwest::Error> for BackendError {
+    fn from(err: reqwest::Error) -> Self {
+        BackendError::RequestError(err)
+    }
+}
+
+#[cfg(feature = "backend-async")]
+impl From<serde_json::Error> for BackendError {
+    fn from(err: serde_json::Error) -> Self {
+        BackendError::ParseError(err)
+    }
+}
+
+/// Error type returned from copying to the system clipboard
+///
+/// Copying is best effort: over SSH or on a headless machine there is usually no clipboard
+/// to copy to, and the TUI reports that instead of failing.
+#[derive(Debug)]
+pub enum ClipboardError {
+    /// Aurish was built without the `clipboard` feature.
+    NotSupported,
+    /// The clipboard could not be opened or written to.
+    ///
+    /// The associated `String` is the message of the platform clipboard library.
+    Unavailable(String),
+}
+
+impl fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipboardError::NotSupported => write!(f, "Clipboard support is not compiled in"),
+            ClipboardError::Unavailable(msg) => write!(f, "Clipboard unavailable: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ClipboardError {}
+
+/// Error type returned from building the TUI theme out of the config
+///
+/// The message lists what would have been accepted, so a typo in `config.json` can be fixed
+/// without looking up the documentation.
+#[derive(Debug)]
+pub enum ThemeError {
+    /// The `theme` or `theme.preset` value is not one of `theme::PRESETS`.
+    UnknownPreset(String),
+    /// A color override could not be parsed.
+    ///
+    /// `field` is the name of the override, e.g. `stderr`, and `value` what it was set to.
+    UnknownColor { field: String, value: String },
+}
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThemeError::UnknownPreset(name) => write!(
+                f,
+                "unknown theme `{}`, valid themes are: {}",
+                name,
+                crate::theme::PRESETS.join(", "),
+            ),
+            ThemeError::UnknownColor { field, value } => write!(
+                f,
+                "unknown color `{}` for theme.{}, valid colors are: {}, `#rrggbb` or a number from 0 to 255",
+                value,
+                field,
+                crate::theme::COLOR_NAMES.join(", "),
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ThemeError {}
+
+/// Error type returned when the TUI cannot be drawn, see `terminal_caps`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TerminalError {
+    /// stdin or stdout is not a terminal, e.g. output is piped.
+    NotATerminal,
+    /// `TERM` names a terminal that cannot move the cursor, like `dumb`, or is not set (`None`).
+    Incapable(Option<String>),
+}
+
+impl fmt::Display for TerminalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TerminalError::NotATerminal => write!(
+                f,
+                "aurish needs a terminal, but its input or output is redirected; use `aurish-cli run` or `aurish-cli ask` instead",
+            ),
+            TerminalError::Incapable(Some(term)) => write!(
+                f,
+                "the terminal (TERM={}) cannot draw aurish's interface; use `aurish-cli run` instead",
+                term,
+            ),
+            TerminalError::Incapable(None) => write!(
+                f,
+                "TERM is not set, so aurish cannot tell what the terminal can draw; set it, e.g. TERM=xterm-256color, or use `aurish-cli run`",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TerminalError {}
+
+/// Error type returned from parsing a key spec like `ctrl+a`, `F2` or `q`
+#[cfg(feature = "tui")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeySpecError {
+    /// The spec, or the key after its modifiers, is empty.
+    Empty,
+    /// The key is neither a single character nor one of `keymap::KEY_NAMES`.
+    UnknownKey(String),
+    /// `shift` was combined with a character that is not a letter, like `shift+1`.
+    ///
+    /// Terminals report the shifted character itself, `!`, which is what has to be given.
+    ShiftedChar(char),
+}
+
+#[cfg(feature = "tui")]
+impl fmt::Display for KeySpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySpecError::Empty => write!(f, "no key given"),
+            KeySpecError::UnknownKey(key) => write!(
+                f,
+                "unknown key `{}`, valid keys are single characters, F1 to F24 and: {}",
+                key,
+                crate::keymap::KEY_NAMES.join(", "),
+            ),
+            KeySpecError::ShiftedChar(c) => write!(f, "shift only goes with letters, give the shifted character instead of shift+{}", c),
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+impl std::error::Error for KeySpecError {}
+
+/// Error type returned from applying the `keybindings` of the config to a keymap
+///
+/// Every variant carries the action and the key spec as written, so `Config::validate` can
+/// point at the entry to fix.
+#[cfg(feature = "tui")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeymapError {
+    /// `action` is not one of `keymap::ACTION_NAMES`.
+    UnknownAction { action: String, spec: String },
+    /// `spec` could not be parsed.
+    InvalidKey { action: String, spec: String, reason: KeySpecError },
+    /// The keys of `spec` are bound to `other` in `mode` too, or one begins the other.
+    ///
+    /// `mode` is its heading in the help overlay, e.g. `Normal` or `Asking AI`.
+    Conflict { action: String, spec: String, mode: &'static str, other: &'static str },
+    /// `spec` is a plain character, which could no longer be typed into the text box of `mode`.
+    TypingKey { action: String, spec: String, mode: &'static str },
+}
+
+#[cfg(feature = "tui")]
+impl KeymapError {
+    /// Name of the action the entry is for, as written in the config
+    pub fn action(&self) -> &str {
+        match self {
+            KeymapError::UnknownAction { action, .. }
+            | KeymapError::InvalidKey { action, .. }
+            | KeymapError::Conflict { action, .. }
+            | KeymapError::TypingKey { action, .. } => action,
+        }
+    }
+
+    /// The key spec of the entry
+    pub fn spec(&self) -> &str {
+        match self {
+            KeymapError::UnknownAction { spec, .. }
+            | KeymapError::InvalidKey { spec, .. }
+            | KeymapError::Conflict { spec, .. }
+            | KeymapError::TypingKey { spec, .. } => spec,
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeymapError::UnknownAction { .. } => write!(
+                f,
+                "unknown action, valid actions are: {}",
+                crate::keymap::ACTION_NAMES.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", "),
+            ),
+            KeymapError::InvalidKey { reason, .. } => write!(f, "{}", reason),
+            KeymapError::Conflict { mode, other, .. } => write!(f, "conflicts with `{}` among the {} keys", other, mode),
+            KeymapError::TypingKey { mode, .. } => write!(f, "could no longer be typed in {}, add ctrl or alt", mode),
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+impl std::error::Error for KeymapError {}
+
+/// Error type returned from loading, changing or saving the config
+///
+/// Variants about the file carry its path, so the message says which file is meant.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// None of the places a config file is looked for has one, see `config::search_paths`.
+    NotFound { searched: Vec<PathBuf> },
+    /// The config file exists but could not be read.
+    Read { path: PathBuf, source: io::Error },
+    /// The config file is not valid JSON, or lacks required fields.
+    Parse { path: PathBuf, source: serde_json::Error },
+    /// The config file is not valid TOML, or lacks required fields.
+    ParseToml { path: PathBuf, source: toml::de::Error },
+    /// Writing the temporary file, or moving it over the config, failed.
+    Write { path: PathBuf, source: io::Error },
+    /// A value given on the command line was rejected.
+    ///
+    /// `setting` is the name of the flag, e.g. `--set-proxy`, and `reason` why the value is wrong.
+    Invalid { setting: &'static str, value: String, reason: String },
+    /// There is no profile of the given name.
+    UnknownProfile(String),
+    /// A profile of the given name exists already, `default` always does.
+    ProfileExists(String),
+    /// A value in the config file is wrong, found by `Config::validate`.
+    ///
+    /// `field` is its path in the file, e.g. `profiles.remote.ollama_api`.
+    Field { field: String, value: String, reason: String },
+    /// The config file has the listed `Field` problems.
+    Problems { path: PathBuf, problems: Vec<ConfigError> },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NotFound { searched } => {
+                let paths: Vec<_> = searched.iter().map(|path| path.display().to_string()).collect();
+                write!(f, "no config found at {}, run `aurish-cli` to create one or pass --config <path>", paths.join(" or "))
+            },
+            ConfigError::Read { path, source } => write!(f, "could not read the config {}: {}", path.display(), source),
+            ConfigError::Parse { path, source } => write!(f, "{} is not a valid config: {}", path.display(), source),
+            ConfigError::ParseToml { path, source } => write!(f, "{} is not a valid config: {}", path.display(), source),
+            ConfigError::Write { path, source } => write!(f, "could not write the config to {}: {}", path.display(), source),
+            ConfigError::Invalid { setting, value, reason } => write!(f, "invalid value `{}` for {}: {}", value, setting, reason),
+            ConfigError::UnknownProfile(name) => write!(f, "no profile named `{}`, see `aurish-cli profile list`", name),
+            ConfigError::ProfileExists(name) => write!(f, "a profile named `{}` exists already", name),
+            ConfigError::Field { field, value, reason } => write!(f, "invalid value `{}` for {}: {}", value, field, reason),
+            ConfigError::Problems { path, problems } => {
+                write!(f, "{} has {} problem(s):", path.display(), problems.len())?;
+                problems.iter().try_for_each(|problem| write!(f, "\n  {}", problem))
+            },
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::NotFound { .. } | ConfigError::Invalid { .. } => None,
+            ConfigError::UnknownProfile(_) | ConfigError::ProfileExists(_) => None,
+            ConfigError::Field { .. } | ConfigError::Problems { .. } => None,
+            ConfigError::Read { source, .. } | ConfigError::Write { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source),
+            ConfigError::ParseToml { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Error type returned from reading a prompt from a file or stdin
+///
+/// The messages do not say where the prompt came from, callers add the file name.
+#[derive(Debug)]
+pub enum PromptError {
+    /// The input is larger than `limit` bytes.
+    TooLarge { limit: usize },
+    /// The input contains NUL bytes, so it is most likely not text.
+    Binary,
+    /// The input is not valid UTF-8.
+    NotUtf8,
+    /// The input could not be read.
+    Read(io::Error),
+}
+
+impl fmt::Display for PromptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PromptError::TooLarge { limit } => write!(f, "the prompt is larger than {} KB", limit / 1024),
+            PromptError::Binary => write!(f, "the prompt looks like a binary file"),
+            PromptError::NotUtf8 => write!(f, "the prompt is not valid UTF-8 text"),
+            PromptError::Read(err) => write!(f, "could not read the prompt: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PromptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PromptError::Read(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Error type returned from reading the queue an earlier session left, see `queue_file`
+///
+/// The frontends warn about it and remove the file, it never stops them from starting.
+#[derive(Debug)]
+pub enum QueueFileError {
+    /// The file exists but could not be read.
+    Read(io::Error),
+    /// The file is not a saved queue, e.g. cut short by a full disk.
+    Corrupt(serde_json::Error),
+    /// The file was written in another format, by a newer version of aurish.
+    Version(u32),
+}
+
+impl fmt::Display for QueueFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueueFileError::Read(err) => write!(f, "could not read the saved queue: {}", err),
+            QueueFileError::Corrupt(err) => write!(f, "the saved queue is corrupted: {}", err),
+            QueueFileError::Version(version) => write!(
+                f,
+                "the saved queue has format version {}, this version of aurish reads {}",
+                version,
+                crate::queue_file::VERSION,
+            ),
+        }
+    }
+}
+
+impl std::error::Error for QueueFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QueueFileError::Read(err) => Some(err),
+            QueueFileError::Corrupt(err) => Some(err),
+            QueueFileError::Version(_) => None,
+        }
+    }
+}
+
+/// Error type returned from reading or changing the favorites, see `favorites::Favorites`
+#[derive(Debug)]
+pub enum FavoritesError {
+    /// There is no data directory to keep the favorites in.
+    NoDataDir,
+    /// The name is empty or contains control characters.
+    InvalidName(String),
+    /// The command to save is empty.
+    EmptyCommand,
+    /// A favorite of this name exists, and replacing it was not asked for.
+    Exists(String),
+    /// There is no favorite of this name.
+    NotFound(String),
+    /// The file exists but could not be read.
+    Read(io::Error),
+    /// The file is not a list of favorites, e.g. after editing it by hand.
+    Corrupt(serde_json::Error),
+    /// The file was written in another format, by a newer version of aurish.
+    Version(u32),
+    /// The file could not be locked or written.
+    Write(io::Error),
+}
+
+impl fmt::Display for FavoritesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FavoritesError::NoDataDir => write!(f, "there is no data directory to keep the favorites in"),
+            FavoritesError::InvalidName(name) => write!(f, "`{}` cannot name a favorite, names must not be empty or contain control characters", name.escape_debug()),
+            FavoritesError::EmptyCommand => write!(f, "there is no command to save"),
+            FavoritesError::Exists(name) => write!(f, "there is already a favorite named `{}`", name),
+            FavoritesError::NotFound(name) => write!(f, "there is no favorite named `{}`", name),
+            FavoritesError::Read(err) => write!(f, "could not read the favorites: {}", err),
+            FavoritesError::Corrupt(err) => write!(f, "the favorites file is corrupted: {}", err),
+            FavoritesError::Version(version) => write!(
+                f,
+                "the favorites file has format version {}, this version of aurish reads {}",
+                version,
+                crate::favorites::VERSION,
+            ),
+            FavoritesError::Write(err) => write!(f, "could not save the favorites: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for FavoritesError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FavoritesError::Read(err) | FavoritesError::Write(err) => Some(err),
+            FavoritesError::Corrupt(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Error type returned from reading the saved session transcripts, see `transcripts`
+#[derive(Debug)]
+pub enum HistoryError {
+    /// There is no data directory the sessions could be in.
+    NoDataDir,
+    /// There is no session of this id.
+    NotFound(String),
+    /// A transcript or the index exists but could not be read.
+    Read { path: PathBuf, source: io::Error },
+    /// A transcript or the index is not valid JSON, e.g. after a crash or editing it by hand.
+    Corrupt { path: PathBuf, source: serde_json::Error },
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryError::NoDataDir => write!(f, "there is no data directory to keep the sessions in, set `session_dir` in the config"),
+            HistoryError::NotFound(id) => write!(f, "there is no session `{}`, see `aurish-cli history`", id),
+            HistoryError::Read { path, source } => write!(f, "could not read {}: {}", path.display(), source),
+            HistoryError::Corrupt { path, source } => write!(f, "{} is corrupted: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HistoryError::Read { source, .. } => Some(source),
+            HistoryError::Corrupt { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Error type returned from reading or using prompt templates, see `templates::Templates`
+#[derive(Debug)]
+pub enum TemplateError {
+    /// The templates file exists but could not be read.
+    Read(io::Error),
+    /// The templates file is not a TOML table of names and strings.
+    Corrupt(toml::de::Error),
+    /// A name in the templates file has characters other than letters, digits, `-` and `_`.
+    InvalidName(String),
+    /// The template of this name has a `{0}` placeholder, they start at `{1}`.
+    ZeroPlaceholder(String),
+    /// There is no template of this name.
+    ///
+    /// `available` are the names there are, and `path` the file to add more to.
+    Unknown { name: String, available: Vec<String>, path: Option<PathBuf> },
+    /// Fewer arguments were given than the template has placeholders.
+    MissingArgs { name: String, needed: usize, given: usize, template: String },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Read(err) => write!(f, "could not read the templates: {}", err),
+            TemplateError::Corrupt(err) => write!(f, "the templates file is not a table of names and prompts: {}", err),
+            TemplateError::InvalidName(name) => write!(f, "`{}` cannot name a template, use letters, digits, `-` and `_`", name.escape_debug()),
+            TemplateError::ZeroPlaceholder(name) => write!(f, "template `{}` uses {{0}}, placeholders start at {{1}}", name),
+            TemplateError::Unknown { name, available, .. } if !available.is_empty() => {
+                write!(f, "there is no template named `{}`, the templates are: {}", name, available.join(", "))
+            },
+            TemplateError::Unknown { name, path: Some(path), .. } => {
+                write!(f, "there is no template named `{}`, add templates to {}", name, path.display())
+            },
+            TemplateError::Unknown { name, .. } => write!(f, "there is no template named `{}`, and no config directory for templates", name),
+            TemplateError::MissingArgs { name, needed, given, template } => {
+                write!(f, "template `{}` needs {} argument{}, got {}: {}", name, needed, if *needed == 1 { "" } else { "s" }, given, template)
+            },
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TemplateError::Read(err) => Some(err),
+            TemplateError::Corrupt(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Error type returned from saving suggested commands as a script
+#[derive(Debug)]
+pub enum ScriptError {
+    /// A file already exists at the path, and replacing it was not asked for.
+    Exists(PathBuf),
+    /// The script could not be written or made executable.
+    Io(io::Error),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Exists(path) => write!(f, "{} already exists, add --force to replace it", path.display()),
+            ScriptError::Io(err) => write!(f, "could not write the script: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScriptError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ScriptError {
+    fn from(err: io::Error) -> Self {
+        ScriptError::Io(err)
+    }
+}
+
+/// Error type returned from the library's entry points and the frontends' `run`
+///
+/// Wraps the error of the layer that failed, so `?` works across config, backend and
+/// shell code. Each binary turns it into a message and an exit code in one place.
+#[derive(Debug)]
+pub enum AurishError {
+    /// The config could not be loaded, is invalid, or could not be saved.
+    Config(ConfigError),
+    /// The request to Ollama failed or its answer made no sense.
+    #[cfg(feature = "backend-async")]
+    Backend(BackendError),
+    /// The shell could not be set up.
+    Shell(ShellInitError),
+    /// The terminal cannot show the TUI.
+    Terminal(TerminalError),
+    /// Reading from or drawing to the terminal failed, or another I/O error.
+    Io(io::Error),
+}
+
+impl fmt::Display for AurishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AurishError::Config(err) => write!(f, "{}", err),
+            #[cfg(feature = "backend-async")]
+            AurishError::Backend(err) => write!(f, "{}", err),
+            AurishError::Shell(err) => write!(f, "could not start the shell: {}", err),
+            AurishError::Terminal(err) => write!(f, "{}", err),
+            AurishError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for AurishError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AurishError::Config(err) => Some(err),
+            #[cfg(feature = "backend-async")]
+            AurishError::Backend(err) => Some(err),
+            AurishError::Shell(err) => Some(err),
+            AurishError::Terminal(err) => Some(err),
+            AurishError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<ConfigError> for AurishError {
+    fn from(err: ConfigError) -> Self {
+        AurishError::Config(err)
+    }
+}
+
+#[cfg(feature = "backend-async")]
+impl From<BackendError> for AurishError {
+    fn from(err: BackendError) -> Self {
+        AurishError::Backend(err)
+    }
+}
+
+impl From<ShellInitError> for AurishError {
+    fn from(err: ShellInitError) -> Self {
+        AurishError::Shell(err)
+    }
+}
+
+impl From<TerminalError> for AurishError {
+    fn from(err: TerminalError) -> Self {
+        AurishError::Terminal(err)
+    }
+}
+
+impl From<io::Error> for AurishError {
+    fn from(err: io::Error) -> Self {
+        AurishError::Io(err)
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<ReadlineError> for AurishError {
+    fn from(err: ReadlineError) -> Self {
+        match err {
+            ReadlineError::Io(err) => AurishError::Io(err),
+            err => AurishError::Io(io::Error::other(err)),
+        }
+    }
+}
+
+/// What to do about `BackendError::is_missing_model` in `aurish-cli`
+#[cfg(feature = "cli")]
+pub const MISSING_MODEL_HINT: &str = "run `aurish-cli models` to list the available models";
+
+#[cfg(feature = "cli")]
+/// Error type returned from an `aurish-cli` command
+///
+/// Each variant maps to the exit code of `aurish-cli` with `exit_code`, so scripts can tell
+/// a broken setup from a wrong invocation or a failing suggested command.
+#[derive(Debug)]
+pub enum CliError {
+    /// The config could not be loaded, changed or saved. Exit code 1, or 2 for a rejected
+    /// `--set-*` value.
+    Config(ConfigError),
+    /// The request to Ollama failed. Exit code 1.
+    Backend(BackendError),
+    /// The model answered, but without any commands. Exit code 1.
+    NoCommands,
+    /// Reading from the terminal failed. Exit code 1.
+    Terminal(ReadlineError),
+    /// The user pressed Ctrl-C or Ctrl-D at a question. Exit code 1.
+    Cancelled,
+    /// The arguments or stdin do not make sense for the command. Exit code 2.
+    ///
+    /// The associated `String` says what is wrong and how to fix it.
+    Usage(String),
+    /// A command run by `ask --execute` failed.
+    ///
+    /// The exit code is the command's own, or 1 if it has none that fits, e.g. when it
+    /// was killed by a signal.
+    CommandFailed { command: String, code: Option<i32> },
+    /// The suggested commands could not be saved as a script. Exit code 1.
+    Script(ScriptError),
+    /// Every request sent by `bench` failed. Exit code 1.
+    ///
+    /// The associated `usize` is the number of requests sent.
+    BenchFailed(usize),
+    /// The shell could not be set up. Exit code 1.
+    Shell(ShellInitError),
+    /// A favorite could not be saved, found or removed. Exit code 2 for a name that is
+    /// taken, unknown or invalid, 1 otherwise.
+    Favorites(FavoritesError),
+    /// The templates could not be read, or the one asked for could not be used. Exit code 2
+    /// for an unknown template or missing arguments, 1 otherwise.
+    Template(TemplateError),
+    /// A saved session could not be found or read. Exit code 2 for an unknown id, 1 otherwise.
+    History(HistoryError),
+    /// The prompt file given to `ask --watch` could not be watched. Exit code 1.
+    #[cfg(feature = "watch")]
+    Watch { path: PathBuf, source: notify::Error },
+}
+
+#[cfg(feature = "cli")]
+impl CliError {
+    /// Exit code of `aurish-cli` for this error, never 0
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Usage(_) | CliError::Config(ConfigError::Invalid { .. }) => 2,
+            CliError::Config(ConfigError::UnknownProfile(_) | ConfigError::ProfileExists(_)) => 2,
+            CliError::Favorites(FavoritesError::Exists(_) | FavoritesError::NotFound(_) | FavoritesError::InvalidName(_)) => 2,
+            CliError::Template(TemplateError::Unknown { .. } | TemplateError::MissingArgs { .. }) => 2,
+            CliError::History(HistoryError::NotFound(_)) => 2,
+            CliError::CommandFailed { code, .. } => code
+                .and_then(|code| u8::try_from(code).ok())
+                .filter(|code| *code != 0)
+                .unwrap_or(1),
+            _ => 1,
+        }
+    }
+
+    /// What to try next, printed after the error
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            CliError::Backend(BackendError::RequestError(_)) | CliError::BenchFailed(_) => {
+                Some("is Ollama running? `aurish-cli init --force` checks the endpoint step by step")
+            },
+            CliError::Backend(err) if err.is_missing_model() => Some(MISSING_MODEL_HINT),
+            CliError::Favorites(FavoritesError::Exists(_)) => Some("add --force to replace it"),
+            CliError::Favorites(FavoritesError::NotFound(_)) => Some("`aurish-cli fav list` shows the saved ones"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "cli")]
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Config(err) => write!(f, "{}", err),
+            CliError::Backend(err) => write!(f, "{}", err),
+            CliError::NoCommands => write!(f, "the model returned no commands"),
+            CliError::Terminal(err) => write!(f, "terminal error: {}", err),
+            CliError::Cancelled => write!(f, "cancelled"),
+            CliError::Usage(msg) => write!(f, "{}", msg),
+            CliError::CommandFailed { command, code: Some(code) } => write!(f, "`{}` failed with exit code {}", command, code),
+            CliError::CommandFailed { command, code: None } => write!(f, "`{}` was terminated by a signal", command),
+            CliError::Script(err) => write!(f, "{}", err),
+            CliError::BenchFailed(count) => write!(f, "all {} requests failed", count),
+            CliError::Shell(err) => write!(f, "could not start the shell: {}", err),
+            CliError::Favorites(err) => write!(f, "{}", err),
+            CliError::Template(err) => write!(f, "{}", err),
+            CliError::History(err) => write!(f, "{}", err),
+            #[cfg(feature = "watch")]
+            CliError::Watch { path, source } => write!(f, "could not watch {}: {}", path.display(), source),
+        }
+    }
+}
+
+#[cfg(feature = "cli")]
+impl std::error::Error for CliError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CliError::Config(err) => Some(err),
+            CliError::Backend(err) => Some(err),
+            CliError::Terminal(err) => Some(err),
+            CliError::Script(err) => Some(err),
+            CliError::Shell(err) => Some(err),
+            CliError::Favorites(err) => Some(err),
+            CliError::Template(err) => Some(err),
+            CliError::History(err) => Some(err),
+            #[cfg(feature = "watch")]
+            CliError::Watch { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<ConfigError> for CliError {
+    fn from(err: ConfigError) -> Self {
+        CliError::Config(err)
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<ShellInitError> for CliError {
+    fn from(err: ShellInitError) -> Self {
+        CliError::Shell(err)
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<BackendError> for CliError {
+    fn from(err: BackendError) -> Self {
+        CliError::Backend(err)
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<ScriptError> for CliError {
+    fn from(err: ScriptError) -> Self {
+        CliError::Script(err)
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<FavoritesError> for CliError {
+    fn from(err: FavoritesError) -> Self {
+        CliError::Favorites(err)
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<HistoryError> for CliError {
+    fn from(err: HistoryError) -> Self {
+        CliError::History(err)
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<TemplateError> for CliError {
+    fn from(err: TemplateError) -> Self {
+        CliError::Template(err)
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<AurishError> for CliError {
+    fn from(err: AurishError) -> Self {
+        match err {
+            AurishError::Config(err) => CliError::Config(err),
+            AurishError::Backend(err) => CliError::Backend(err),
+            AurishError::Shell(err) => CliError::Shell(err),
+            AurishError::Terminal(err) => CliError::Terminal(ReadlineError::Io(io::Error::other(err))),
+            AurishError::Io(err) => CliError::Terminal(ReadlineError::Io(err)),
+        }
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<ReadlineError> for CliError {
+    fn from(err: ReadlineError) -> Self {
+        match err {
+            ReadlineError::Interrupted | ReadlineError::Eof => CliError::Cancelled,
+            err => CliError::Terminal(err),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "cli"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_exit_codes() {
+        let failed = |code| CliError::CommandFailed { command: "make".to_string(), code };
+        assert_eq!(CliError::Config(ConfigError::NotFound { searched: vec![PathBuf::from("config.json")] }).exit_code(), 1);
+        let invalid = ConfigError::Invalid { setting: "--set-proxy", value: "proxy".to_string(), reason: "relative URL".to_string() };
+        assert_eq!(CliError::Config(invalid).exit_code(), 2);
+        assert_eq!(CliError::NoCommands.exit_code(), 1);
+        assert_eq!(CliError::Usage("no prompt given".to_string()).exit_code(), 2);
+        assert_eq!(failed(Some(3)).exit_code(), 3);
+        // codes that do not fit an exit status still fail
+        assert_eq!(failed(Some(256)).exit_code(), 1);
+        assert_eq!(failed(Some(-1)).exit_code(), 1);
+        assert_eq!(failed(None).exit_code(), 1);
+        assert_eq!(failed(None).to_string(), "`make` was terminated by a signal");
+        assert!(matches!(CliError::from(ReadlineError::Interrupted), CliError::Cancelled));
+        assert_eq!(CliError::NoCommands.hint(), None);
+        assert_eq!(CliError::BenchFailed(3).exit_code(), 1);
+        assert!(CliError::BenchFailed(3).hint().is_some());
+        let shell = AurishError::from(ShellInitError::DirectoryError("/gone".to_string()));
+        assert_eq!(CliError::from(shell).exit_code(), 1);
+        let cancelled = AurishError::from(ReadlineError::Interrupted);
+        assert!(matches!(CliError::from(cancelled), CliError::Terminal(ReadlineError::Io(_))));
+        assert_eq!(CliError::from(FavoritesError::NotFound("gif".to_string())).exit_code(), 2);
+        assert_eq!(CliError::from(FavoritesError::NoDataDir).exit_code(), 1);
+        let unknown = TemplateError::Unknown { name: "x".to_string(), available: Vec::new(), path: None };
+        assert_eq!(CliError::from(unknown).exit_code(), 2);
+        let missing = BackendError::Server { status: reqwest::StatusCode::NOT_FOUND, message: "model 'x' not found".to_string() };
+        assert_eq!(CliError::from(missing).hint(), Some(MISSING_MODEL_HINT));
+    }
+}
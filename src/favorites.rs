@@ -0,0 +1,289 @@
+//! Commands saved under a name, to run them again later
+//!
+//! Favorites are kept in `<data dir>/aurish/favorites.json`, in the order they were saved.
+//! The TUI saves the selected command with `f` and lists the favorites with `F`,
+//! `aurish-cli fav` manages them from the command line. Every change reads the file, changes
+//! it and writes it to a temporary file that is renamed over it while holding a lock on
+//! `.favorites.json.lock`, so aurish processes saving at the same time neither lose each
+//! other's favorites nor leave half a file.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::error::FavoritesError;
+use crate::utils;
+
+/// Format of the file written by this version, files of other versions are left alone
+pub const VERSION: u32 = 1;
+
+/// A command saved under a name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Favorite {
+    pub name: String,
+    pub command: String,
+    /// When it was saved, in seconds since the Unix epoch
+    pub added: u64,
+}
+
+/// Contents of the file
+#[derive(Serialize, Deserialize)]
+struct FavoritesFile {
+    version: u32,
+    favorites: Vec<Favorite>,
+}
+
+/// Only the version, read before the rest so a newer format is told apart from a broken file
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+/// `<data dir>/aurish/favorites.json`; unit tests never touch the real file
+pub fn default_path() -> Option<PathBuf> {
+    if cfg!(test) {
+        return None;
+    }
+    dirs::data_dir().map(|dir| dir.join("aurish").join("favorites.json"))
+}
+
+/// Favorites kept in a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Favorites {
+    path: PathBuf,
+}
+
+impl Favorites {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Favorites { path: path.into() }
+    }
+
+    /// The favorites in the data directory, see `default_path`
+    pub fn open_default() -> Result<Favorites, FavoritesError> {
+        default_path().map(Favorites::new).ok_or(FavoritesError::NoDataDir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every favorite, oldest first. There are none until the first one is saved.
+    pub fn list(&self) -> Result<Vec<Favorite>, FavoritesError> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(FavoritesError::Read(e)),
+        };
+        parse(&text)
+    }
+
+    /// The favorite called `name`, if there is one
+    pub fn get(&self, name: &str) -> Result<Option<Favorite>, FavoritesError> {
+        let name = name.trim();
+        Ok(self.list()?.into_iter().find(|favorite| favorite.name == name))
+    }
+
+    /// Save `command` as `name`. Fails with `FavoritesError::Exists` if the name is taken,
+    /// see `replace` to overwrite it.
+    pub fn add(&self, name: &str, command: &str) -> Result<Favorite, FavoritesError> {
+        self.save(name, command, false).map(|(favorite, _)| favorite)
+    }
+
+    /// Save `command` as `name`, in place of the favorite of that name if there is one.
+    /// Returns the replaced favorite.
+    pub fn replace(&self, name: &str, command: &str) -> Result<Option<Favorite>, FavoritesError> {
+        self.save(name, command, true).map(|(_, replaced)| replaced)
+    }
+
+    fn save(&self, name: &str, command: &str, replace: bool) -> Result<(Favorite, Option<Favorite>), FavoritesError> {
+        let name = check_name(name)?;
+        let command = command.trim();
+        if command.is_empty() {
+            return Err(FavoritesError::EmptyCommand);
+        }
+        let favorite = Favorite {
+            name: name.to_string(),
+            command: command.to_string(),
+            added: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()),
+        };
+        let replaced = self.change(|favorites| match favorites.iter().position(|saved| saved.name == favorite.name) {
+            Some(_) if !replace => Err(FavoritesError::Exists(favorite.name.clone())),
+            Some(idx) => Ok(Some(std::mem::replace(&mut favorites[idx], favorite.clone()))),
+            None => {
+                favorites.push(favorite.clone());
+                Ok(None)
+            },
+        })?;
+        Ok((favorite, replaced))
+    }
+
+    /// Remove the favorite called `name` and return it
+    pub fn remove(&self, name: &str) -> Result<Favorite, FavoritesError> {
+        let name = name.trim();
+        self.change(|favorites| {
+            let idx = favorites.iter()
+                .position(|favorite| favorite.name == name)
+                .ok_or_else(|| FavoritesError::NotFound(name.to_string()))?;
+            Ok(favorites.remove(idx))
+        })
+    }
+
+    /// Let `change` edit the favorites and write them back if it succeeds, holding the lock
+    /// from reading to writing. A file that cannot be read is never overwritten.
+    fn change<T>(&self, change: impl FnOnce(&mut Vec<Favorite>) -> Result<T, FavoritesError>) -> Result<T, FavoritesError> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(FavoritesError::Write)?;
+        }
+        // released when dropped
+        let _lock = utils::lock_beside(&self.path).map_err(FavoritesError::Write)?;
+        let mut favorites = self.list()?;
+        let changed = change(&mut favorites)?;
+        self.write(favorites).map_err(FavoritesError::Write)?;
+        Ok(changed)
+    }
+
+    /// Write `favorites` to a temporary file next to the file, then move it over the file
+    fn write(&self, favorites: Vec<Favorite>) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(&FavoritesFile { version: VERSION, favorites })?;
+        utils::write_atomically(&self.path, &text)
+    }
+}
+
+/// Parse the contents of a favorites file
+pub fn parse(text: &str) -> Result<Vec<Favorite>, FavoritesError> {
+    let header: Header = serde_json::from_str(text).map_err(FavoritesError::Corrupt)?;
+    if header.version != VERSION {
+        return Err(FavoritesError::Version(header.version));
+    }
+    let file: FavoritesFile = serde_json::from_str(text).map_err(FavoritesError::Corrupt)?;
+    Ok(file.favorites)
+}
+
+/// `name` without the whitespace around it, if it can name a favorite: it must not be empty
+/// or contain control characters like line breaks
+pub fn check_name(name: &str) -> Result<&str, FavoritesError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().any(char::is_control) {
+        return Err(FavoritesError::InvalidName(name.to_string()));
+    }
+    Ok(name)
+}
+
+/// Save `command` as `name` in the data directory, see `Favorites::add`
+pub fn add(name: &str, command: &str) -> Result<Favorite, FavoritesError> {
+    Favorites::open_default()?.add(name, command)
+}
+
+/// Every favorite in the data directory, oldest first
+pub fn list() -> Result<Vec<Favorite>, FavoritesError> {
+    Favorites::open_default()?.list()
+}
+
+/// Remove the favorite called `name` from the data directory
+pub fn remove(name: &str) -> Result<Favorite, FavoritesError> {
+    Favorites::open_default()?.remove(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aurish-favorites-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("favorites.json")
+    }
+
+    fn names(favorites: &[Favorite]) -> Vec<&str> {
+        favorites.iter().map(|favorite| favorite.name.as_str()).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_file("round-trip");
+        let store = Favorites::new(&path);
+        assert!(store.list().unwrap().is_empty());
+        let saved = store.add("gif", "  ffmpeg -i in.mp4 out.gif\n").unwrap();
+        assert_eq!(saved.command, "ffmpeg -i in.mp4 out.gif");
+        store.add(" ports ", "ss -tlnp").unwrap();
+
+        let reopened = Favorites::new(&path);
+        let favorites = reopened.list().unwrap();
+        assert_eq!(names(&favorites), ["gif", "ports"]);
+        assert_eq!(favorites[0], saved);
+        assert_eq!(reopened.get("ports").unwrap().unwrap().command, "ss -tlnp");
+        assert!(reopened.get("disk").unwrap().is_none());
+
+        assert_eq!(reopened.remove("gif").unwrap().command, "ffmpeg -i in.mp4 out.gif");
+        assert_eq!(names(&store.list().unwrap()), ["ports"]);
+        assert!(matches!(store.remove("gif"), Err(FavoritesError::NotFound(name)) if name == "gif"));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn names_are_unique() {
+        let path = temp_file("unique");
+        let store = Favorites::new(&path);
+        store.add("ports", "ss -tlnp").unwrap();
+        store.add("disk", "df -h").unwrap();
+        assert!(matches!(store.add("ports", "netstat -tlnp"), Err(FavoritesError::Exists(name)) if name == "ports"));
+        assert_eq!(store.get("ports").unwrap().unwrap().command, "ss -tlnp");
+
+        let replaced = store.replace("ports", "netstat -tlnp").unwrap().unwrap();
+        assert_eq!(replaced.command, "ss -tlnp");
+        // replacing keeps the place in the list
+        let favorites = store.list().unwrap();
+        assert_eq!(names(&favorites), ["ports", "disk"]);
+        assert_eq!(favorites[0].command, "netstat -tlnp");
+        assert!(store.replace("uptime", "uptime").unwrap().is_none());
+
+        for name in ["", "  ", "two\nlines", "tab\there"] {
+            assert!(matches!(store.add(name, "ls"), Err(FavoritesError::InvalidName(_))), "{:?}", name);
+        }
+        assert!(matches!(store.add("nothing", " \n"), Err(FavoritesError::EmptyCommand)));
+        assert_eq!(store.list().unwrap().len(), 3);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn concurrent_writes_keep_every_favorite() {
+        let path = temp_file("concurrent");
+        let threads: Vec<_> = (0..8)
+            .map(|thread| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let store = Favorites::new(path);
+                    for n in 0..10 {
+                        store.add(&format!("fav-{}-{}", thread, n), "true").unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let favorites = Favorites::new(&path).list().unwrap();
+        assert_eq!(favorites.len(), 80);
+        // only the file and its lock are left, no temporary files
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 2);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn bad_files_are_not_overwritten() {
+        assert!(matches!(parse("{\"version\": 1, \"favorites\": ["), Err(FavoritesError::Corrupt(_))));
+        assert!(matches!(parse("[]"), Err(FavoritesError::Corrupt(_))));
+        assert!(matches!(parse(r#"{"version": 2, "items": []}"#), Err(FavoritesError::Version(2))));
+        assert!(parse(r#"{"version": 1, "favorites": []}"#).unwrap().is_empty());
+
+        let path = temp_file("corrupt");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "{\"version\": 1, \"favo").unwrap();
+        let store = Favorites::new(&path);
+        let error = store.add("ports", "ss -tlnp").unwrap_err();
+        assert!(error.to_string().starts_with("the favorites file is corrupted: "), "{}", error);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"version\": 1, \"favo");
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -1,139 +1,1145 @@
-use std::env::current_dir;
-use rustyline::{DefaultEditor, Result};
-use rustyline::error::ReadlineError;
-// use ishell::IShell;
-use std::path::PathBuf;
-use std::collections::VecDeque;
-use crate::shared::EditMode;
-use crate::backend::{OllamaReq, ClientInit, BKclient};
-use crate::shell::IShell;
-
-
-pub struct App_cli {
-    shell: Shell_cli,
-    cli: DefaultEditor,
-    edit_mode: EditMode,
-    message: OllamaReq,
-    shell_commands: VecDeque<String>,
-}
-
-struct Shell_cli {
-    shell: IShell,
-    curr_path: PathBuf,
-}
-
-impl Default for Shell_cli {
-    fn default() -> Self {
-        Shell_cli {
-            shell: IShell::new(),
-            curr_path: current_dir().unwrap(),
-        }
-    }
-}
-
-impl Shell_cli {
-    pub fn renew_path(&mut self) {
-        self.curr_path = current_dir().unwrap();
-    }
-
-    /// Showing current path like actual Shell did
-    pub fn get_path(&self) -> String {
-        let path = self.curr_path.to_string_lossy().into_owned();
-        path
-    }
-}
-
-impl App_cli {
-    pub fn new(model: &str) -> App_cli {
-        App_cli {
-            shell: Shell_cli::default(),
-            cli: DefaultEditor::new().unwrap(),
-            edit_mode: EditMode::Input,
-            message: OllamaReq::new(model),
-            shell_commands: VecDeque::new(),
-        }
-    }
-
-    /// Using Blocking Client to reduce overhead
-    pub fn run(&mut self, client: BKclient) -> Result<()> {
-        loop {
-            match self.edit_mode {
-                EditMode::Input => {
-                    let title = "Asking AI >> ";
-                    let readline = self.cli.readline(title);
-                    match readline {
-                        Ok(line) => {
-                            self.message.prompt(line.as_str());
-                            println!("Generating...");
-                            let res = client.send_ollama(&self.message).unwrap();
-                            self.recv_from(res);
-                            self.edit_mode = EditMode::Shell;
-                        },
-                        Err(ReadlineError::Interrupted) => {
-                            println!("Keyboard Interrupted");
-                            println!("Program Closing...");
-                            break;
-                        },
-                        Err(ReadlineError::Eof) => {
-                            println!("CTRL-D");
-                            break;
-                        },
-                        Err(err) => {
-                            println!("Error: {:?}", err);
-                            break;
-                        }
-                    }
-                },
-                EditMode::Shell => {
-                    if self.shell_commands.is_empty() {
-                        println!("No pending commands, return to Input Mode");
-                        self.edit_mode = EditMode::Input;
-                    } else {
-                        self.shell.renew_path();
-                        let prompt = format!("{}>> ", self.shell.get_path());
-                        let command = self.shell_commands.front().unwrap().as_str();
-                        let readline = self.cli.readline_with_initial(prompt.as_str(), (command, ""));
-                        match readline {
-                            Ok(line) => {
-                                // execute on-screen command
-                                let sh_result = self.shell.shell.run_command(line.as_str());
-                                let result: String = if sh_result.is_success() {
-                                    String::from_utf8(sh_result.stdout).expect("Stdout contained invalid UTF-8!")
-                                } else {
-                                    String::from_utf8(sh_result.stderr).expect("Stdout contained invalid UTF-8!")
-                                };
-                                println!("Shell output: {}", result);
-                                // delete executed command
-                                let _ = self.shell_commands.pop_front();
-                            },
-                            Err(ReadlineError::Interrupted) => {
-                                println!("Keyboard Interrupted");
-                                println!("Program Closing...");
-                                break;
-                            },
-                            Err(ReadlineError::Eof) => {
-                                println!("CTRL-D");
-                                break;
-                            },
-                            Err(err) => {
-                                println!("Error: {:?}", err);
-                                break;
-                            }
-                        }
-                    }
-                },
-                _ => {
-                    println!("Unknown Error, quitting...");
-                    println!("Debug Info:\n  Ollama msg: {:?}  \n Pending Commands: {:?}", self.message, self.shell_commands);
-                    break;
-                }
-            }
-        }
-
-        Ok(())
-    }
-
-    pub fn recv_from(&mut self, rece_vec: Vec<String>) {
-        self.shell_commands = VecDeque::from(rece_vec);
-    }
-}
+use std::env::current_dir;
+use rustyline::{DefaultEditor, Result};
+use rustyline::error::ReadlineError;
+// use ishell::IShell;
+use std::path::{Path, PathBuf};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use crate::autofix::{self, AutoFix, Correction};
+use crate::config::DEFAULT_MAX_PROMPT_TOKENS;
+use crate::shared::{new_seed, EditMode};
+use crate::backend::{OllamaReq, BKclient};
+use crate::error::{AurishError, BackendError, ShellInitError, MISSING_MODEL_HINT};
+use crate::interactive;
+use crate::output;
+use crate::queue;
+use crate::queue_file::{QueueFile, SavedQueue};
+use crate::script;
+use crate::session::{self, Outcome, Session};
+use crate::suggestions::SuggestionMode;
+use crate::shell::{strip_background, IShell, ShellOutput, ShellType};
+use crate::shell_history::ShellHistory;
+use crate::utils::{display_path, read_commands, sanitize_commands, MultilineCommands};
+use crate::workdir::{self, ContextLevel};
+use crate::project_context::ProjectContext;
+use crate::i18n::Language;
+
+
+#[allow(non_camel_case_types)]
+pub struct App_cli {
+    shell: Shell_cli,
+    cli: DefaultEditor,
+    edit_mode: EditMode,
+    message: OllamaReq,
+    shell_commands: VecDeque<String>,
+    /// Where the queue is kept across restarts
+    queue_file: QueueFile,
+    /// Ask before running each command
+    confirm: bool,
+    /// Ask which of several suggested commands to queue
+    select: bool,
+    /// What `recv_from` does with commands of several lines
+    multiline: MultilineCommands,
+    /// What prompts tell about the directory the commands run in
+    context_level: ContextLevel,
+    /// Notes on the project of the shell's directory, see `project_context`
+    project_context: ProjectContext,
+    /// Language of the questions asked and the system prompt, see `i18n`
+    language: Language,
+    /// Prompt asked without typing it, before the first one read from the terminal
+    initial_prompt: Option<String>,
+    /// Answers received in this conversation, `:new` starts over
+    turn: usize,
+    /// What happened so far, tells the model what became of the commands of the last answer
+    session: Session,
+    /// Prompt typed for the pending commands, and the request that answered it with them,
+    /// for `:refine` and `:regen`
+    last_prompt: Option<String>,
+    last_request: Option<OllamaReq>,
+    /// Estimated prompt tokens above which context is left out of a request
+    max_prompt_tokens: usize,
+    /// Attempts at correcting a failed command, `None` when auto-fix is off
+    auto_fix: Option<u32>,
+    /// Patterns marking a correction as dangerous, besides the built-in rules
+    dangerous_patterns: Vec<String>,
+    /// Where the session is exported to when quitting, `None` to not export it
+    export_dir: Option<PathBuf>,
+}
+
+/// `autofix::fix_prompt` for `command`, which printed `result`
+fn fix_prompt_for(command: &str, result: &ShellOutput) -> String {
+    let (stdout, stderr) = (String::from_utf8_lossy(&result.stdout), String::from_utf8_lossy(&result.stderr));
+    autofix::fix_prompt(command, result.code, &stdout, &stderr)
+}
+
+/// What happened to the commands of the previous answer, attached before the next prompt so
+/// the model can build on them
+fn outcomes_preamble(outcomes: &[(String, Outcome)]) -> String {
+    if outcomes.is_empty() {
+        return String::new();
+    }
+    let mut text = String::from("What happened to the commands of your last answer:\n");
+    for (command, outcome) in outcomes {
+        let line = match outcome {
+            Outcome::Exited(Some(code)) => format!("`{}` exited with code {}", command, code),
+            Outcome::Exited(None) => format!("`{}` was terminated by a signal", command),
+            Outcome::Background => format!("`{}` was started in the background", command),
+            Outcome::Skipped => format!("`{}` was skipped", command),
+        };
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text.push_str("\nNext request: ");
+    text
+}
+
+/// Print that a request is being answered, and that the model is loaded into memory first
+/// if it is not there, since that can take half a minute
+fn announce_generation(client: &BKclient, model: &str) {
+    match client.model_loaded(model) {
+        Ok(false) => output::message(format!("Generating... loading {} into memory, this can take a while…", model)),
+        _ => output::message("Generating..."),
+    }
+}
+
+/// Print why a request failed, and how to find an installed model if it is missing
+fn report_request_error(e: &BackendError) {
+    output::error(e);
+    if e.is_missing_model() {
+        output::hint(MISSING_MODEL_HINT);
+    }
+}
+
+/// Indices of the commands picked by `input` out of `count`, in the order given: numbers
+/// and ranges from 1, like `1,3-5`, or `all` or `none`. An empty input picks all, a command
+/// picked twice is kept at its first place.
+fn parse_selection(input: &str, count: usize) -> std::result::Result<Vec<usize>, String> {
+    match input.trim() {
+        "" | "all" => return Ok((0..count).collect()),
+        "none" => return Ok(Vec::new()),
+        _ => {},
+    }
+    let number = |text: &str| -> std::result::Result<usize, String> {
+        let text = text.trim();
+        match text.parse::<usize>() {
+            Ok(n) if (1..=count).contains(&n) => Ok(n - 1),
+            Ok(n) => Err(format!("there is no command {}, pick from 1 to {}", n, count)),
+            Err(_) => Err(format!("`{}` is not a command number", text)),
+        }
+    };
+    let mut selection = Vec::new();
+    for part in input.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        let picked = match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (number(first)?, number(last)?);
+                if first > last {
+                    return Err(format!("`{}` is backwards, write it as {}-{}", part, last + 1, first + 1));
+                }
+                (first..=last).collect()
+            },
+            None => vec![number(part)?],
+        };
+        for idx in picked {
+            if !selection.contains(&idx) {
+                selection.push(idx);
+            }
+        }
+    }
+    Ok(selection)
+}
+
+/// Prompt asking the model to change the `pending` commands it suggested for `prompt`
+/// as told by `feedback`
+fn refine_prompt<S: AsRef<str>>(prompt: &str, pending: &[S], feedback: &str) -> String {
+    let mut text = format!("My request was: {}\nThese commands of your answer have not run yet:\n", prompt.trim());
+    for command in pending {
+        text.push_str(command.as_ref());
+        text.push('\n');
+    }
+    text.push_str("\nGive the commands again, changed as follows: ");
+    text.push_str(feedback);
+    text
+}
+
+/// What a line typed in Shell mode asks for
+#[derive(Debug, PartialEq, Eq)]
+enum ShellInput<'a> {
+    Run(&'a str),
+    /// Run a command attached to the terminal, `:tty [command]`; the pending command if
+    /// empty
+    Tty(&'a str),
+    /// Show what a command would do without running it, `:plan [command]`; the pending
+    /// command if empty
+    Plan(&'a str),
+    /// Drop the current command, also an empty line
+    Skip,
+    /// Drop every queued command and go back to asking
+    SkipAll,
+    /// Save the pending commands as a script, `:save [--force] <path>`; the path is empty
+    /// if none was given
+    Save { path: &'a str, force: bool },
+    /// Ask the model to change the pending commands, `:refine <feedback>`
+    Refine(&'a str),
+    /// Ask the last prompt again with a new seed
+    Regen,
+    /// Type new values for the words shared by the pending commands
+    Params,
+    /// Queue the commands in a file after the pending ones, `:load <path>`; the path is
+    /// empty if none was given
+    Load(&'a str),
+    Quit,
+}
+
+impl<'a> ShellInput<'a> {
+    fn parse(line: &'a str) -> ShellInput<'a> {
+        if let Some(command) = interactive::strip_tty_prefix(line) {
+            return ShellInput::Tty(command);
+        }
+        match line.trim() {
+            "" | ":skip" => ShellInput::Skip,
+            ":skipall" => ShellInput::SkipAll,
+            ":quit" => ShellInput::Quit,
+            ":save" => ShellInput::Save { path: "", force: false },
+            ":regen" => ShellInput::Regen,
+            ":params" => ShellInput::Params,
+            ":refine" => ShellInput::Refine(""),
+            ":plan" => ShellInput::Plan(""),
+            ":load" => ShellInput::Load(""),
+            trimmed if trimmed.starts_with(":load ") => ShellInput::Load(trimmed[":load ".len()..].trim()),
+            trimmed if trimmed.starts_with(":plan ") => ShellInput::Plan(trimmed[":plan ".len()..].trim()),
+            trimmed if trimmed.starts_with(":refine ") => ShellInput::Refine(trimmed[":refine ".len()..].trim()),
+            trimmed => match trimmed.strip_prefix(":save ") {
+                Some(args) => {
+                    let args = args.trim_start();
+                    match args.strip_prefix("--force") {
+                        Some(path) if path.is_empty() || path.starts_with(char::is_whitespace) => {
+                            ShellInput::Save { path: path.trim(), force: true }
+                        },
+                        _ => ShellInput::Save { path: args, force: false },
+                    }
+                },
+                None => ShellInput::Run(line),
+            },
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+struct Shell_cli {
+    shell: IShell,
+    curr_path: PathBuf,
+}
+
+impl Default for Shell_cli {
+    fn default() -> Self {
+        Shell_cli {
+            shell: IShell::new(),
+            curr_path: current_dir().unwrap(),
+        }
+    }
+}
+
+impl Shell_cli {
+    /// Follow the directory the IShell moved to with `cd`
+    pub fn renew_path(&mut self) {
+        self.curr_path = self.shell.current_dir();
+    }
+
+    /// Showing current path like actual Shell did, with the home directory shown as `~`
+    pub fn get_path(&self) -> String {
+        display_path(&self.curr_path, None)
+    }
+}
+
+impl App_cli {
+    pub fn new(model: &str) -> App_cli {
+        // detected once, for the shell and the system prompt
+        let shell = Shell_cli::default();
+        let message = OllamaReq::for_shell(model, shell.shell.shell_type());
+        App_cli {
+            shell,
+            cli: DefaultEditor::new().unwrap(),
+            edit_mode: EditMode::Input,
+            message,
+            shell_commands: VecDeque::new(),
+            queue_file: QueueFile::default(),
+            confirm: false,
+            select: true,
+            multiline: MultilineCommands::default(),
+            context_level: ContextLevel::default(),
+            project_context: ProjectContext::default(),
+            language: Language::default(),
+            initial_prompt: None,
+            turn: 0,
+            session: Session::default(),
+            last_prompt: None,
+            last_request: None,
+            max_prompt_tokens: DEFAULT_MAX_PROMPT_TOKENS,
+            auto_fix: None,
+            dangerous_patterns: Vec::new(),
+            export_dir: None,
+        }
+    }
+
+    /// Leave context out of requests estimated over `max_tokens`, see `OllamaReq::fit`
+    pub fn with_max_prompt_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_prompt_tokens = max_tokens;
+        self
+    }
+
+    /// Ask `run this? [y/N/e(dit)]` before running each command
+    pub fn with_confirm(mut self, confirm: bool) -> Self {
+        self.confirm = confirm;
+        self
+    }
+
+    /// Ask which commands to queue when the model suggests more than one, `select` is on by default
+    pub fn with_select(mut self, select: bool) -> Self {
+        self.select = select;
+        self
+    }
+
+    /// Describe the system in the system prompt, see `system_info`. On by default.
+    pub fn with_system_info(mut self, include: bool) -> Self {
+        self.message.set_system_info(include);
+        self
+    }
+
+    /// Ask the questions and the model in `language`, see `i18n`. English by default.
+    pub fn with_language(mut self, language: Language) -> Self {
+        self.language = language;
+        self.message.set_language(language);
+        self
+    }
+
+    /// Ask for as many commands as `mode` wants, see `suggestions`
+    pub fn with_suggestion_mode(mut self, mode: SuggestionMode) -> Self {
+        self.message.set_suggestion_mode(mode);
+        self
+    }
+
+    /// Split suggested commands of several lines, the default, or join them with ` && `
+    pub fn with_multiline_commands(mut self, multiline: MultilineCommands) -> Self {
+        self.multiline = multiline;
+        self
+    }
+
+    /// Tell the model about the current directory with each prompt, see `workdir`
+    pub fn with_context_level(mut self, level: ContextLevel) -> Self {
+        self.context_level = level;
+        self
+    }
+
+    /// Add the commands that succeed to the user's shell history, see `shell_history`
+    pub fn with_shell_history(self, enabled: bool) -> Self {
+        if enabled {
+            self.shell.shell.set_history(ShellHistory::for_shell(self.shell.shell.shell_type()));
+        }
+        self
+    }
+
+    /// Run and ask for commands of `shell` instead of the detected one, if any
+    pub fn with_shell_type(mut self, shell: Option<ShellType>) -> Self {
+        if let Some(shell) = shell {
+            self.shell.shell.set_shell_type(shell);
+            self.message.set_shell(shell);
+        }
+        self
+    }
+
+    /// Run commands in the user's locale, see `IShell::set_inherit_locale`
+    pub fn with_inherited_locale(self, inherit: bool) -> Self {
+        self.shell.shell.set_inherit_locale(inherit);
+        self
+    }
+
+    /// Keep `cd` inside `root`, see `IShell::set_sandbox_root`
+    pub fn with_sandbox_root(mut self, root: Option<&Path>) -> std::result::Result<Self, ShellInitError> {
+        self.shell.shell.set_sandbox_root(root)?;
+        self.shell.renew_path();
+        Ok(self)
+    }
+
+    /// Keep the queue in the file at `path` instead of the data directory, see `queue_file`
+    pub fn with_queue_file(mut self, path: PathBuf) -> Self {
+        self.queue_file = QueueFile::new(Some(path));
+        self
+    }
+
+    /// Ask the model for a corrected command when one fails, up to `attempts` times in a row
+    pub fn with_auto_fix(mut self, attempts: Option<u32>) -> Self {
+        self.auto_fix = attempts;
+        self
+    }
+
+    /// Extra patterns of commands auto-fix never runs, see `risk::danger_reason`
+    pub fn with_dangerous_patterns(mut self, patterns: Vec<String>) -> Self {
+        self.dangerous_patterns = patterns;
+        self
+    }
+
+    /// Start the session by asking `prompt`, e.g. one read from a file
+    /// Export the session when quitting if `on_quit` is set, to `dir` instead of the data
+    /// directory if given
+    pub fn with_session_export(mut self, dir: Option<PathBuf>, on_quit: bool) -> Self {
+        self.export_dir = on_quit.then(|| dir.or_else(session::default_dir)).flatten();
+        self
+    }
+
+    pub fn with_initial_prompt(mut self, prompt: Option<String>) -> Self {
+        self.initial_prompt = prompt;
+        self
+    }
+
+    /// Start in Shell mode with `commands` pending, e.g. read from a file, instead of asking.
+    /// The queue a previous session left is not offered then.
+    pub fn with_queued_commands(mut self, commands: Vec<String>) -> Self {
+        if !commands.is_empty() {
+            self.shell_commands = VecDeque::from(commands);
+            self.edit_mode = EditMode::Shell;
+        }
+        self
+    }
+
+    /// One line about the special inputs, printed when entering Shell mode
+    fn shell_hint(&self) -> String {
+        let hint = "Enter runs the command, an empty line or :skip skips it, :skipall goes back to asking, \
+            :refine <feedback> or :regen asks for other commands, :params changes a value they share, \
+            :save <path> saves the pending commands as a script, :load <path> queues the commands in a file, \
+            :tty [command] runs it on the terminal for password prompts, :plan [command] shows what it would \
+            do without running it, :quit exits";
+        if self.confirm {
+            format!("{} (each command is confirmed first)", hint)
+        } else {
+            hint.to_string()
+        }
+    }
+
+    /// Using Blocking Client to reduce overhead
+    pub fn run(&mut self, client: BKclient) -> std::result::Result<(), AurishError> {
+        if self.shell_commands.is_empty() {
+            match self.offer_saved_queue() {
+                Ok(()) => {},
+                // the saved queue is offered again next time
+                Err(ReadlineError::Interrupted | ReadlineError::Eof) => return Ok(()),
+                Err(err) => return Err(err.into()),
+            }
+        } else {
+            output::message(self.shell_hint());
+        }
+        loop {
+            self.save_queue();
+            match self.edit_mode {
+                EditMode::Input => {
+                    // the turn shows that the model remembers the previous prompts
+                    let title = self.language.strings().asking_prompt(self.turn);
+                    let readline = match self.initial_prompt.take() {
+                        Some(prompt) => {
+                            output::message(format!("{}{}", title, prompt));
+                            Ok(prompt)
+                        },
+                        None => self.cli.readline(&title),
+                    };
+                    match readline {
+                        Ok(line) => {
+                            if line.trim().is_empty() || self.handle_jobs_input(line.trim()) {
+                                continue;
+                            }
+                            if line.trim() == ":new" {
+                                self.new_conversation();
+                                output::message("Started a new conversation");
+                                continue;
+                            }
+                            if let ShellInput::Load(path) = ShellInput::parse(&line) {
+                                if self.load_commands(path) {
+                                    self.edit_mode = EditMode::Shell;
+                                    output::message(self.shell_hint());
+                                }
+                                continue;
+                            }
+                            self.refresh_project_context();
+                            let attached = workdir::context(&self.shell.shell.current_dir(), self.context_level) + &outcomes_preamble(&self.session.outcomes());
+                            self.message.prompt_with(&attached, &line);
+                            self.session.prompt_sent(self.message.model(), &line);
+                            let mut request = self.message.clone();
+                            if let Some(warning) = request.fit(self.max_prompt_tokens).warning() {
+                                output::warning(warning);
+                            }
+                            announce_generation(&client, self.message.model());
+                            let generation = match client.generate(&request) {
+                                Ok(generation) => generation,
+                                Err(e) => {
+                                    // stay here, so the prompt can be sent again
+                                    report_request_error(&e);
+                                    self.session.error(&e.to_string());
+                                    continue;
+                                },
+                            };
+                            self.turn += 1;
+                            self.session.commands_received(&generation.commands);
+                            if let Some(notice) = generation.notice() {
+                                output::message(notice);
+                            }
+                            self.last_prompt = Some(line);
+                            self.last_request = Some(request);
+                            self.message.set_context(generation.context);
+                            if self.recv_from(generation.commands) == 0 {
+                                output::error("Model returned 0 usable commands, try rephrasing the request");
+                                continue;
+                            }
+                            match self.select_if_several() {
+                                Ok(()) => {},
+                                Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+                                Err(err) => {
+                                    output::error(err);
+                                    break;
+                                },
+                            }
+                            self.edit_mode = EditMode::Shell;
+                            if !self.shell_commands.is_empty() {
+                                output::message(self.shell_hint());
+                            }
+                        },
+                        Err(ReadlineError::Interrupted) => {
+                            output::message("Keyboard Interrupted");
+                            output::message("Program Closing...");
+                            break;
+                        },
+                        Err(ReadlineError::Eof) => {
+                            output::message("CTRL-D");
+                            break;
+                        },
+                        Err(err) => {
+                            output::error(err);
+                            break;
+                        }
+                    }
+                },
+                EditMode::Shell => {
+                    if self.shell_commands.is_empty() {
+                        output::message("No pending commands, return to Input Mode");
+                        self.edit_mode = EditMode::Input;
+                    } else {
+                        self.shell.renew_path();
+                        let prompt = format!("{}>> ", self.shell.get_path());
+                        let command = self.shell_commands.front().unwrap().as_str();
+                        let readline = self.cli.readline_with_initial(prompt.as_str(), (command, ""));
+                        match readline {
+                            Ok(line) => {
+                                let (line, mut on_tty) = match ShellInput::parse(&line) {
+                                    ShellInput::Run(line) => (line.to_string(), false),
+                                    ShellInput::Tty("") => (command.to_string(), true),
+                                    ShellInput::Tty(line) => (line.to_string(), true),
+                                    ShellInput::Plan(line) => {
+                                        let line = if line.is_empty() { command } else { line };
+                                        let plan = self.shell.shell.explain_command_with_patterns(line, &self.dangerous_patterns);
+                                        for line in plan.lines() {
+                                            output::message(line);
+                                        }
+                                        self.session.plan_shown(&plan);
+                                        continue;
+                                    },
+                                    ShellInput::Skip => {
+                                        output::message("Skipped");
+                                        self.skip_command();
+                                        continue;
+                                    },
+                                    ShellInput::SkipAll => {
+                                        output::message(format!("Skipped {} command(s), return to Input Mode", self.shell_commands.len()));
+                                        while !self.shell_commands.is_empty() {
+                                            self.skip_command();
+                                        }
+                                        self.edit_mode = EditMode::Input;
+                                        continue;
+                                    },
+                                    ShellInput::Save { path: "", .. } => {
+                                        output::message("Usage: :save [--force] <path>");
+                                        continue;
+                                    },
+                                    ShellInput::Save { path, force } => {
+                                        self.save_script(Path::new(path), force);
+                                        continue;
+                                    },
+                                    ShellInput::Refine("") => {
+                                        output::message("Usage: :refine <what to change>");
+                                        continue;
+                                    },
+                                    ShellInput::Refine(feedback) => {
+                                        let feedback = feedback.to_string();
+                                        match self.refine(&client, &feedback) {
+                                            Ok(()) => continue,
+                                            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+                                            Err(err) => {
+                                                output::error(err);
+                                                break;
+                                            },
+                                        }
+                                    },
+                                    ShellInput::Regen => match self.regenerate(&client) {
+                                        Ok(()) => continue,
+                                        Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+                                        Err(err) => {
+                                            output::error(err);
+                                            break;
+                                        },
+                                    },
+                                    ShellInput::Params => match self.set_params() {
+                                        Ok(()) => continue,
+                                        Err(ReadlineError::Eof) => break,
+                                        Err(err) => {
+                                            output::error(err);
+                                            break;
+                                        },
+                                    },
+                                    ShellInput::Load(path) => {
+                                        self.load_commands(path);
+                                        continue;
+                                    },
+                                    ShellInput::Quit => break,
+                                };
+                                let mut line = line;
+                                if !on_tty && self.shell.shell.shell_type().is_posix()
+                                    && interactive::needs_sudo_password(&line, interactive::sudo_cached)
+                                {
+                                    match self.cli.readline(self.language.strings().sudo_password)
+                                        .map(|answer| answer.trim().to_lowercase())
+                                    {
+                                        Ok(answer) if answer == "s" || answer == "skip" => {
+                                            output::message("Skipped");
+                                            self.skip_command();
+                                            continue;
+                                        },
+                                        Ok(answer) if answer == "n" || answer == "no" => line = interactive::non_interactive_sudo(&line),
+                                        Ok(_) => on_tty = true,
+                                        Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+                                        Err(err) => {
+                                            output::error(err);
+                                            break;
+                                        },
+                                    }
+                                }
+                                let line = line.as_str();
+                                if self.confirm {
+                                    match self.cli.readline(self.language.strings().run_this).map(|answer| answer.trim().to_lowercase()) {
+                                        Ok(answer) if answer == "y" || answer == "yes" => {},
+                                        Ok(answer) if answer == "e" || answer == "edit" => {
+                                            // edit it again, starting from what was typed
+                                            if let Some(command) = self.shell_commands.front_mut() {
+                                                *command = line.to_string();
+                                            }
+                                            continue;
+                                        },
+                                        Ok(_) => {
+                                            output::message("Skipped");
+                                            self.skip_command();
+                                            continue;
+                                        },
+                                        Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+                                        Err(err) => {
+                                            output::error(err);
+                                            break;
+                                        },
+                                    }
+                                }
+                                if on_tty {
+                                    let suggested = self.shell_commands.pop_front().filter(|suggested| suggested != line);
+                                    if let Some(suggested) = &suggested {
+                                        self.session.command_edited(suggested, line);
+                                    }
+                                    let started = Instant::now();
+                                    let sh_result = self.shell.shell.run_interactive(line);
+                                    let duration = started.elapsed();
+                                    output::executed(line, sh_result.code, false);
+                                    if let Some(suggested) = &suggested {
+                                        output::edited_from(suggested);
+                                    }
+                                    output::ran_interactively();
+                                    self.session.command_ran_interactively(line, suggested.as_deref(), sh_result.code, duration);
+                                    continue;
+                                }
+                                if let Some(job_line) = strip_background(line) {
+                                    let suggested = self.shell_commands.pop_front().filter(|suggested| suggested != line);
+                                    match self.shell.shell.spawn_job(job_line) {
+                                        Ok(job) => {
+                                            output::message(format!("[{}] started in background", job.id()));
+                                            self.session.command_started(line, suggested.as_deref());
+                                        },
+                                        Err(e) => {
+                                            let message = format!("failed to start job: {}", e);
+                                            output::error(&message);
+                                            self.session.command_executed(line, suggested.as_deref(), Some(-1), Duration::ZERO, "", &message);
+                                        },
+                                    }
+                                    continue;
+                                }
+                                // delete the command and execute the on-screen one
+                                let suggested = self.shell_commands.pop_front().filter(|suggested| suggested != line);
+                                if let Some(suggested) = &suggested {
+                                    self.session.command_edited(suggested, line);
+                                }
+                                let started = Instant::now();
+                                let sh_result = self.shell.shell.run_command(line);
+                                let duration = started.elapsed();
+                                output::shell_output(&sh_result);
+                                output::executed(line, sh_result.code, false);
+                                if let Some(suggested) = &suggested {
+                                    output::edited_from(suggested);
+                                }
+                                self.record_executed(line, suggested.as_deref(), &sh_result, duration);
+                                if sh_result.code.is_some_and(|code| code != 0) {
+                                    match self.fix_failed(&client, line, &sh_result) {
+                                        Ok(()) => {},
+                                        Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+                                        Err(err) => {
+                                            output::error(err);
+                                            break;
+                                        },
+                                    }
+                                }
+                            },
+                            Err(ReadlineError::Interrupted) => {
+                                output::message("Keyboard Interrupted");
+                                output::message("Program Closing...");
+                                break;
+                            },
+                            Err(ReadlineError::Eof) => {
+                                output::message("CTRL-D");
+                                break;
+                            },
+                            Err(err) => {
+                                output::error(err);
+                                break;
+                            }
+                        }
+                    }
+                },
+                _ => {
+                    output::error("unknown error, quitting...");
+                    output::message(format!("Debug Info:\n  Ollama msg: {:?}  \n Pending Commands: {:?}", self.message, self.shell_commands));
+                    break;
+                }
+            }
+        }
+
+        self.save_queue();
+        self.export_session();
+        Ok(())
+    }
+
+    /// Save the session for `aurish-cli history` if it is to be exported and not empty
+    fn export_session(&self) {
+        let Some(dir) = self.export_dir.as_deref().filter(|_| !self.session.is_empty()) else {
+            return;
+        };
+        match self.session.export(dir) {
+            Ok(path) => log::info!("session saved to {}", path.display()),
+            Err(e) => output::warning(format!("could not save the session to {}: {}", dir.display(), e)),
+        }
+    }
+
+    /// Ask whether to queue the commands an earlier session left, and go on with them in
+    /// Shell mode if so. A file that cannot be read is removed with a warning.
+    fn offer_saved_queue(&mut self) -> Result<()> {
+        let saved = match self.queue_file.load() {
+            Ok(Some(saved)) => saved,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                log::warn!("{}", e);
+                output::warning(format!("ignored the queue of the previous session: {}", e));
+                self.queue_file.clear();
+                return Ok(());
+            },
+        };
+        output::message(match &saved.prompt {
+            Some(prompt) => format!("{} pending command(s) from the previous session, asked for: {}", saved.commands.len(), prompt.trim()),
+            None => format!("{} pending command(s) from the previous session:", saved.commands.len()),
+        });
+        for (idx, command) in saved.commands.iter().enumerate() {
+            output::command(Some(idx + 1), command);
+        }
+        let answer = self.cli.readline(&format!("Restore them in {}? [y/N] ", display_path(&saved.directory, None)))?;
+        if matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            self.restore_queue(saved);
+            output::message(self.shell_hint());
+        } else {
+            self.queue_file.clear();
+            output::message("Discarded the commands of the previous session");
+        }
+        Ok(())
+    }
+
+    /// Queue the commands of `saved`, in the directory they were going to run in
+    fn restore_queue(&mut self, saved: SavedQueue) {
+        if !self.shell.shell.set_current_dir(&saved.directory) {
+            output::warning(format!("{} is gone, the commands run in {}", saved.directory.display(), self.shell.get_path()));
+        }
+        self.shell.renew_path();
+        self.last_prompt = saved.prompt;
+        self.shell_commands = VecDeque::from(saved.commands);
+        self.edit_mode = EditMode::Shell;
+    }
+
+    /// Keep the queue file in step with the queue
+    fn save_queue(&mut self) {
+        self.queue_file.update(self.last_prompt.as_deref(), &self.shell.shell.current_dir(), &self.shell_commands);
+    }
+
+    /// Queue the commands in the file at `path` after the pending ones, a line each. They
+    /// are reviewed and run like suggested ones. Returns whether any were queued.
+    fn load_commands(&mut self, path: &str) -> bool {
+        if path.is_empty() {
+            output::message("Usage: :load <path>");
+            return false;
+        }
+        match read_commands(Path::new(path)) {
+            Ok(commands) if commands.is_empty() => output::error(format!("no commands in {}", path)),
+            Ok(commands) => {
+                output::message(format!("Queued {} command(s) from {}", commands.len(), path));
+                self.shell_commands.extend(commands);
+                return true;
+            },
+            Err(e) => output::error(format!("could not read {}: {}", path, e)),
+        }
+        false
+    }
+
+    /// Save the pending commands as a script for the shell they would run in
+    fn save_script(&self, path: &Path, force: bool) {
+        let commands: Vec<_> = self.shell_commands.iter().map(|command| (command.as_str(), None)).collect();
+        match script::save(path, self.shell.shell.shell_type(), &commands, force) {
+            Ok(path) => output::message(format!("Saved {} command(s) to {}", commands.len(), path.display())),
+            Err(e) => output::error(e),
+        }
+    }
+
+    /// Ask the model to change the pending commands as told by `feedback`
+    fn refine(&mut self, client: &BKclient, feedback: &str) -> Result<()> {
+        let Some(prompt) = self.last_prompt.as_deref() else {
+            output::message("Nothing to refine yet");
+            return Ok(());
+        };
+        let pending: Vec<&String> = self.shell_commands.iter().collect();
+        let mut request = self.message.clone();
+        request.prompt(&refine_prompt(prompt, &pending, feedback));
+        if self.replace_commands(client, &request) {
+            self.turn += 1;
+            self.last_request = Some(request);
+            self.select_if_several()?;
+        }
+        Ok(())
+    }
+
+    /// Ask the request of the pending commands again with a new seed
+    fn regenerate(&mut self, client: &BKclient) -> Result<()> {
+        let Some(mut request) = self.last_request.clone() else {
+            output::message("Nothing to regenerate yet");
+            return Ok(());
+        };
+        request.set_seed(Some(new_seed()));
+        if self.replace_commands(client, &request) {
+            self.select_if_several()?;
+        }
+        Ok(())
+    }
+
+    /// Replace the pending commands with the answer to `request`, returns whether it did.
+    /// The pending commands stay as they are if the request fails.
+    fn replace_commands(&mut self, client: &BKclient, request: &OllamaReq) -> bool {
+        announce_generation(client, request.model());
+        match client.generate(request) {
+            Ok(generation) => {
+                if let Some(notice) = generation.notice() {
+                    output::message(notice);
+                }
+                let commands = sanitize_commands(generation.commands, self.multiline);
+                if commands.is_empty() {
+                    output::error("Model returned 0 usable commands");
+                    output::message("Kept the pending commands");
+                    return false;
+                }
+                output::message(format!(
+                    "Replaced {} pending command(s) with {} new one(s)",
+                    self.shell_commands.len(),
+                    commands.len(),
+                ));
+                self.message.set_context(generation.context);
+                self.session.commands_received(&commands);
+                self.recv_from(commands);
+                true
+            },
+            Err(e) => {
+                report_request_error(&e);
+                self.session.error(&e.to_string());
+                output::message("Kept the pending commands");
+                false
+            },
+        }
+    }
+
+    /// With auto-fix on, ask the model for a correction of the failed `command` and run it
+    /// once confirmed, again while the corrections fail and attempts are left. A correction
+    /// that is declined or looks dangerous becomes the next pending command instead.
+    fn fix_failed(&mut self, client: &BKclient, command: &str, result: &ShellOutput) -> Result<()> {
+        let Some(attempts) = self.auto_fix else {
+            return Ok(());
+        };
+        let mut fix = AutoFix::new(command, attempts);
+        let mut failed = command.to_string();
+        let mut prompt = fix_prompt_for(&failed, result);
+        loop {
+            if !fix.next_attempt() {
+                output::warning(format!("auto-fix gave up after {} attempt(s) at `{}`", fix.attempt(), fix.original));
+                return Ok(());
+            }
+            let mut request = self.message.clone();
+            request.prompt(&prompt);
+            output::message(format!("{}: asking for a correction of `{}`", fix.label(), failed));
+            announce_generation(client, request.model());
+            let commands = match client.generate(&request) {
+                Ok(generation) => generation.commands,
+                Err(e) => {
+                    report_request_error(&e);
+                    self.session.error(&e.to_string());
+                    return Ok(());
+                },
+            };
+            self.session.commands_received(&commands);
+            let correction = match autofix::correction(commands, self.multiline, &self.dangerous_patterns) {
+                Correction::Run(correction) => correction,
+                Correction::Dangerous { command, reason } => {
+                    output::warning(format!("{}: not run, `{}` {}", fix.label(), command, reason));
+                    output::message("It is the next pending command, check it before running it");
+                    self.shell_commands.push_front(command);
+                    return Ok(());
+                },
+                Correction::Nothing => {
+                    output::message(format!("{}: the model suggested no command, auto-fix stopped", fix.label()));
+                    return Ok(());
+                },
+            };
+            let answer = self.cli.readline(&format!("run `{}`? [y/N] ", correction))?.trim().to_lowercase();
+            if answer != "y" && answer != "yes" {
+                output::message("Auto-fix stopped, the correction is the next pending command");
+                self.shell_commands.push_front(correction);
+                return Ok(());
+            }
+            let started = Instant::now();
+            let sh_result = self.shell.shell.run_command(&correction);
+            let duration = started.elapsed();
+            output::shell_output(&sh_result);
+            output::executed(&correction, sh_result.code, false);
+            self.record_executed(&correction, None, &sh_result, duration);
+            match sh_result.code {
+                Some(0) => {
+                    output::message(format!("Fixed by {}", fix.label()));
+                    return Ok(());
+                },
+                Some(_) => {
+                    prompt = fix_prompt_for(&correction, &sh_result);
+                    failed = correction;
+                },
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Ask for a new value of each word shared by the pending commands and put it into all
+    /// of them, see `queue::parameterize`. An empty answer keeps the word, Ctrl-C the commands.
+    fn set_params(&mut self) -> Result<()> {
+        let commands: Vec<String> = self.shell_commands.iter().cloned().collect();
+        let (templates, mut params) = queue::parameterize(&commands);
+        if params.is_empty() {
+            output::message("The pending commands share no value to change");
+            return Ok(());
+        }
+        for param in params.iter_mut() {
+            let prompt = format!("value for `{}`, used by {} commands: ", param.value, param.uses);
+            match self.cli.readline_with_initial(&prompt, (&param.value, "")) {
+                Ok(value) if value.trim().is_empty() => {},
+                Ok(value) => param.value = value,
+                Err(ReadlineError::Interrupted) => {
+                    output::message("Kept the pending commands");
+                    return Ok(());
+                },
+                Err(err) => return Err(err),
+            }
+        }
+        self.shell_commands = queue::substitute(&templates, &params).into();
+        for (idx, command) in self.shell_commands.iter().enumerate() {
+            output::command(Some(idx + 1), command);
+        }
+        Ok(())
+    }
+
+    /// `select_commands` if that is on and there is something to choose from
+    fn select_if_several(&mut self) -> Result<()> {
+        if self.select && self.shell_commands.len() > 1 {
+            self.select_commands()?;
+        }
+        Ok(())
+    }
+
+    /// List the pending commands and keep the ones picked, see `parse_selection`
+    fn select_commands(&mut self) -> Result<()> {
+        for (idx, command) in self.shell_commands.iter().enumerate() {
+            output::command(Some(idx + 1), command);
+        }
+        let selection = loop {
+            let line = self.cli.readline(self.language.strings().run_which)?;
+            match parse_selection(&line, self.shell_commands.len()) {
+                Ok(selection) => break selection,
+                Err(e) => output::error(e),
+            }
+        };
+        let mut commands: Vec<Option<String>> = self.shell_commands.drain(..).map(Some).collect();
+        self.shell_commands = selection.iter().filter_map(|&idx| commands[idx].take()).collect();
+        // told to the model with the next prompt like any other skipped command
+        for command in commands.into_iter().flatten() {
+            self.session.command_skipped(&command);
+        }
+        Ok(())
+    }
+
+    /// Drop the current command without running it
+    fn skip_command(&mut self) {
+        if let Some(command) = self.shell_commands.pop_front() {
+            self.session.command_skipped(&command);
+        }
+    }
+
+    /// Record that `command` ran, printing `result` in `duration`
+    fn record_executed(&mut self, command: &str, suggested: Option<&str>, result: &ShellOutput, duration: Duration) {
+        let (stdout, stderr) = (String::from_utf8_lossy(&result.stdout), String::from_utf8_lossy(&result.stderr));
+        self.session.command_executed(command, suggested, result.code, duration, &stdout, &stderr);
+    }
+
+    /// Start the system prompt with the notes of the project the shell is in, telling
+    /// when they come from another file than before
+    fn refresh_project_context(&mut self) {
+        let before = self.project_context.cached().map(|file| file.path.clone());
+        let file = self.project_context.current(&self.shell.shell.current_dir());
+        if let Some(file) = file.filter(|file| before.as_ref() != Some(&file.path)) {
+            output::message(format!("Using the project notes in {}", display_path(&file.path, None)));
+        }
+        self.message.set_project_context(file);
+    }
+
+    /// Forget the previous prompts, answers and commands
+    fn new_conversation(&mut self) {
+        self.turn = 0;
+        self.session.new_conversation();
+        self.message.set_context(Vec::new());
+    }
+
+    /// Handle the `jobs` special input: `jobs` lists running jobs, `jobs kill <id>` kills one.
+    /// Returns false if the line is not a jobs command.
+    fn handle_jobs_input(&self, line: &str) -> bool {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("jobs") {
+            return false;
+        }
+        match (words.next(), words.next()) {
+            (None, _) => {
+                let jobs = self.shell.shell.jobs();
+                if jobs.is_empty() {
+                    output::message("No running jobs");
+                }
+                for job in jobs {
+                    output::message(format!("[{}] {}", job.id(), job.command()));
+                }
+            },
+            (Some("kill"), Some(id)) => match id.parse().ok().and_then(|id| self.shell.shell.job(id)) {
+                Some(job) => match job.kill() {
+                    Ok(()) => output::message(format!("[{}] killed", job.id())),
+                    Err(e) => output::error(format!("failed to kill job {}: {}", job.id(), e)),
+                },
+                None => output::message(format!("No running job with id {}", id)),
+            },
+            _ => output::message("Usage: jobs | jobs kill <id>"),
+        }
+        true
+    }
+
+    /// Queue received commands, cleaned up by `sanitize_commands`, returns how many are usable
+    pub fn recv_from(&mut self, rece_vec: Vec<String>) -> usize {
+        self.shell_commands = VecDeque::from(sanitize_commands(rece_vec, self.multiline));
+        self.shell_commands.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previous_outcomes() {
+        assert_eq!(outcomes_preamble(&[]), "");
+        let outcomes = [
+            ("rm -r build".to_string(), Outcome::Exited(Some(1))),
+            ("npm run dev &".to_string(), Outcome::Background),
+            ("make".to_string(), Outcome::Skipped),
+        ];
+        assert_eq!(outcomes_preamble(&outcomes), "What happened to the commands of your last answer:\n\
+            `rm -r build` exited with code 1\n\
+            `npm run dev &` was started in the background\n\
+            `make` was skipped\n\
+            \n\
+            Next request: ");
+    }
+
+    #[test]
+    fn refinement_prompt() {
+        let prompt = refine_prompt(" back up my photos\n", &["cp -r ~/Pictures /mnt/backup", "sync"], "use rsync");
+        assert_eq!(
+            prompt,
+            "My request was: back up my photos\nThese commands of your answer have not run yet:\n\
+             cp -r ~/Pictures /mnt/backup\nsync\n\nGive the commands again, changed as follows: use rsync"
+        );
+    }
+
+    #[test]
+    fn command_selection() {
+        assert_eq!(parse_selection("1,3-5", 6), Ok(vec![0, 2, 3, 4]));
+        assert_eq!(parse_selection(" 4 , 2 - 3 ", 6), Ok(vec![3, 1, 2]));
+        assert_eq!(parse_selection("2,1-3,2", 6), Ok(vec![1, 0, 2]));
+        assert_eq!(parse_selection("1,,2,", 6), Ok(vec![0, 1]));
+        assert_eq!(parse_selection("", 3), Ok(vec![0, 1, 2]));
+        assert_eq!(parse_selection(" all ", 3), Ok(vec![0, 1, 2]));
+        assert_eq!(parse_selection("none", 3), Ok(vec![]));
+        assert_eq!(parse_selection("3-3", 3), Ok(vec![2]));
+
+        assert!(parse_selection("0", 3).is_err());
+        assert!(parse_selection("4", 3).unwrap_err().contains("from 1 to 3"));
+        assert!(parse_selection("2-7", 3).is_err());
+        assert!(parse_selection("3-1", 3).unwrap_err().contains("1-3"));
+        assert!(parse_selection("1 2", 3).is_err());
+        assert!(parse_selection("-1", 3).is_err());
+        assert!(parse_selection("one", 3).is_err());
+    }
+
+    #[test]
+    fn shell_inputs() {
+        assert_eq!(ShellInput::parse("ls -la"), ShellInput::Run("ls -la"));
+        assert_eq!(ShellInput::parse("  "), ShellInput::Skip);
+        assert_eq!(ShellInput::parse(":skip"), ShellInput::Skip);
+        assert_eq!(ShellInput::parse(" :skipall "), ShellInput::SkipAll);
+        assert_eq!(ShellInput::parse(":quit"), ShellInput::Quit);
+        assert_eq!(ShellInput::parse(" :params "), ShellInput::Params);
+        assert_eq!(ShellInput::parse(":save plan.sh"), ShellInput::Save { path: "plan.sh", force: false });
+        assert_eq!(ShellInput::parse(" :save --force  my plan.sh "), ShellInput::Save { path: "my plan.sh", force: true });
+        assert_eq!(ShellInput::parse(":save"), ShellInput::Save { path: "", force: false });
+        assert_eq!(ShellInput::parse(":save --force"), ShellInput::Save { path: "", force: true });
+        assert_eq!(ShellInput::parse(":saved"), ShellInput::Run(":saved"));
+        assert_eq!(ShellInput::parse(" :regen "), ShellInput::Regen);
+        assert_eq!(ShellInput::parse(":refine  use rsync instead "), ShellInput::Refine("use rsync instead"));
+        assert_eq!(ShellInput::parse(":refine"), ShellInput::Refine(""));
+        assert_eq!(ShellInput::parse(" :tty sudo apt update "), ShellInput::Tty("sudo apt update"));
+        assert_eq!(ShellInput::parse(":tty"), ShellInput::Tty(""));
+        assert_eq!(ShellInput::parse(":ttyd"), ShellInput::Run(":ttyd"));
+        assert_eq!(ShellInput::parse(" :plan  rm -rf build "), ShellInput::Plan("rm -rf build"));
+        assert_eq!(ShellInput::parse(":plan"), ShellInput::Plan(""));
+        assert_eq!(ShellInput::parse(":planet"), ShellInput::Run(":planet"));
+        assert_eq!(ShellInput::parse(" :load  wiki steps.txt "), ShellInput::Load("wiki steps.txt"));
+        assert_eq!(ShellInput::parse(":load"), ShellInput::Load(""));
+    }
+}
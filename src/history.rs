@@ -0,0 +1,195 @@
+//! Prompt history for the Asking AI box
+//!
+//! Submitted prompts are kept in memory and persisted to a file in the user's data
+//! directory, one JSON string per line, so they survive restarts. The file is only read
+//! the first time the history is used.
+
+use std::fs;
+use std::path::PathBuf;
+
+/// Number of prompts kept, older ones are dropped
+pub const MAX_ENTRIES: usize = 500;
+
+/// Submitted prompts, navigable like a readline history
+pub struct PromptHistory {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+    loaded: bool,
+    /// Entry currently shown while navigating, `None` when editing the draft
+    cursor: Option<usize>,
+    /// What was in the input box before navigation started
+    draft: String,
+}
+
+impl Default for PromptHistory {
+    fn default() -> Self {
+        Self::with_path(default_path())
+    }
+}
+
+/// `<data dir>/aurish/prompt_history.jsonl`; unit tests never touch the real file
+fn default_path() -> Option<PathBuf> {
+    if cfg!(test) {
+        return None;
+    }
+    dirs::data_dir().map(|dir| dir.join("aurish").join("prompt_history.jsonl"))
+}
+
+impl PromptHistory {
+    /// History persisted at `path`, or kept in memory only if `path` is `None`
+    pub fn with_path(path: Option<PathBuf>) -> Self {
+        PromptHistory {
+            entries: Vec::new(),
+            path,
+            loaded: false,
+            cursor: None,
+            draft: String::new(),
+        }
+    }
+
+    /// Stored prompts, oldest first
+    pub fn entries(&mut self) -> &[String] {
+        self.ensure_loaded();
+        &self.entries
+    }
+
+    /// Record a submitted prompt and end navigation.
+    /// Blank prompts and repeats of the newest entry are not stored twice.
+    pub fn push(&mut self, prompt: &str) {
+        self.ensure_loaded();
+        self.reset_navigation();
+        if prompt.trim().is_empty() || self.entries.last().is_some_and(|last| last == prompt) {
+            return;
+        }
+        self.entries.push(prompt.to_string());
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.drain(..self.entries.len() - MAX_ENTRIES);
+        }
+        self.save();
+    }
+
+    /// Step back to an older prompt. `current` is the input box content,
+    /// remembered as the draft when navigation starts.
+    pub fn older(&mut self, current: &str) -> Option<&str> {
+        self.ensure_loaded();
+        let idx = match self.cursor {
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = current.to_string();
+                self.entries.len() - 1
+            },
+            Some(idx) => idx.saturating_sub(1),
+        };
+        self.cursor = Some(idx);
+        Some(&self.entries[idx])
+    }
+
+    /// Step forward to a newer prompt, ending with the draft
+    pub fn newer(&mut self) -> Option<&str> {
+        let idx = self.cursor?;
+        if idx + 1 < self.entries.len() {
+            self.cursor = Some(idx + 1);
+            Some(&self.entries[idx + 1])
+        } else {
+            self.cursor = None;
+            Some(&self.draft)
+        }
+    }
+
+    /// Forget the navigation position, e.g. after the input was edited
+    pub fn reset_navigation(&mut self) {
+        self.cursor = None;
+        self.draft.clear();
+    }
+
+    fn ensure_loaded(&mut self) {
+        if self.loaded {
+            return;
+        }
+        self.loaded = true;
+        let Some(contents) = self.path.as_ref().and_then(|path| fs::read_to_string(path).ok()) else {
+            return;
+        };
+        let mut loaded: Vec<String> = contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        loaded.dedup();
+        // anything pushed before loading is newer than the file
+        loaded.append(&mut self.entries);
+        self.entries = loaded;
+    }
+
+    /// Write the history file; failing to persist is not worth interrupting the user for
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let contents: String = self.entries
+            .iter()
+            .filter_map(|entry| serde_json::to_string(entry).ok())
+            .map(|line| line + "\n")
+            .collect();
+        let _ = fs::write(path, contents);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_history_path() -> PathBuf {
+        std::env::temp_dir().join(format!("aurish_history_{}.jsonl", rand::random::<u32>()))
+    }
+
+    #[test]
+    fn navigation_keeps_draft() {
+        let mut history = PromptHistory::with_path(None);
+        history.push("first");
+        history.push("second");
+
+        assert_eq!(history.older("half typed"), Some("second"));
+        assert_eq!(history.older("second"), Some("first"));
+        assert_eq!(history.older("first"), Some("first"));
+        assert_eq!(history.newer(), Some("second"));
+        assert_eq!(history.newer(), Some("half typed"));
+        assert_eq!(history.newer(), None);
+    }
+
+    #[test]
+    fn collapses_duplicates() {
+        let mut history = PromptHistory::with_path(None);
+        history.push("ls");
+        history.push("ls");
+        history.push("  ");
+        history.push("pwd");
+        history.push("ls");
+        assert_eq!(history.entries(), ["ls", "pwd", "ls"]);
+    }
+
+    #[test]
+    fn bounded() {
+        let mut history = PromptHistory::with_path(None);
+        for i in 0..MAX_ENTRIES + 10 {
+            history.push(&i.to_string());
+        }
+        assert_eq!(history.entries().len(), MAX_ENTRIES);
+        assert_eq!(history.entries()[0], "10");
+    }
+
+    #[test]
+    fn persisted() {
+        let path = temp_history_path();
+        let mut history = PromptHistory::with_path(Some(path.clone()));
+        history.push("list files");
+        history.push("line one\nline two");
+
+        let mut reloaded = PromptHistory::with_path(Some(path.clone()));
+        assert_eq!(reloaded.entries(), ["list files", "line one\nline two"]);
+
+        fs::remove_file(path).unwrap();
+    }
+}
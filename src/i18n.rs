@@ -0,0 +1,335 @@
+//! Translations of the system prompt and the fixed strings of the interfaces
+//!
+//! `language` in the config picks one of the `Strings` tables below. The model is asked in
+//! that language, which helps when the prompts are written in it too, and the status line
+//! of the TUI and the questions of `aurish-cli` are shown in it. Every table is a `Strings`
+//! value, so a key missing from one of them does not compile. Text in `{braces}` is filled
+//! in, and has to be kept as is by a translation.
+
+use std::fmt;
+use std::str::FromStr;
+use crate::shell::ShellType;
+use crate::suggestions::SuggestionMode;
+
+/// Language of the system prompt and the interfaces
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    English,
+    Spanish,
+    Chinese,
+}
+
+impl Language {
+    /// Every language there is a table for
+    pub const ALL: [Language; 3] = [Language::English, Language::Spanish, Language::Chinese];
+
+    /// Code of the language in the config, like `es`
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::Spanish => "es",
+            Language::Chinese => "zh",
+        }
+    }
+
+    pub fn strings(self) -> &'static Strings {
+        match self {
+            Language::English => &ENGLISH,
+            Language::Spanish => &SPANISH,
+            Language::Chinese => &CHINESE,
+        }
+    }
+
+    /// The codes of `ALL`, like `en, es and zh`, for messages
+    pub fn codes() -> String {
+        let codes: Vec<&str> = Language::ALL.iter().map(|language| language.code()).collect();
+        match codes.split_last() {
+            Some((last, [])) => last.to_string(),
+            Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+            None => String::new(),
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl FromStr for Language {
+    type Err = ();
+
+    /// A code like `es`, in any case, with a region like `es-MX` or `zh_CN` ignored
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        let base = code.trim().split(['-', '_']).next().unwrap_or_default().to_lowercase();
+        Language::ALL.into_iter().find(|language| language.code() == base).ok_or(())
+    }
+}
+
+/// Declares `Strings` with a field per key, and `Strings::entries` listing them
+macro_rules! strings {
+    ($($(#[doc = $doc:expr])* $key:ident,)*) => {
+        /// The fixed strings of one language, see `Language::strings`
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub struct Strings {
+            $($(#[doc = $doc])* pub $key: &'static str,)*
+        }
+
+        impl Strings {
+            /// Every string with its key, for checking the tables
+            pub fn entries(&self) -> Vec<(&'static str, &'static str)> {
+                vec![$((stringify!($key), self.$key)),*]
+            }
+        }
+    };
+}
+
+strings! {
+    /// System prompt of a request for commands, with `{shell}` and `{instruction}`
+    system_commands,
+    /// `{instruction}` of `SuggestionMode::Auto`
+    instruction_auto,
+    /// `{instruction}` of `SuggestionMode::Single`
+    instruction_single,
+    /// `{instruction}` of `SuggestionMode::Plan`
+    instruction_plan,
+    /// System prompt of a request explaining a command, with `{shell}`
+    system_explain,
+    /// Start of most status lines, before a key
+    press,
+    to_exit,
+    to_ask_ai,
+    to_interact_with_shell,
+    to_read_responses,
+    to_switch_model,
+    for_all_keys,
+    select,
+    reorder,
+    delete,
+    skip,
+    discard_queue,
+    run_queue,
+    scroll_output,
+    /// Start of the status line while the prompt is answered, before a key
+    waiting,
+    to_cancel_and_edit,
+    stop_asking,
+    to_send,
+    for_new_line,
+    stop_reading,
+    to_scroll,
+    stop_shell,
+    to_execute,
+    to_see_plan,
+    to_skip,
+    /// Prompt of `aurish-cli` for the first question
+    asking,
+    /// Prompt of `aurish-cli` for the next questions, with `{turn}`
+    asking_turn,
+    /// Question of `aurish-cli` before running a command, answered with y, n or e
+    run_this,
+    /// Question of `aurish-cli` for several commands, answered like `1,3-5`, `all` or `none`
+    run_which,
+    /// Question of `aurish-cli` before a `sudo` that would ask for a password, answered
+    /// with y, n or s
+    sudo_password,
+}
+
+impl Strings {
+    /// System prompt of a request for `mode` commands of `shell`
+    pub fn commands_system(&self, shell: ShellType, mode: SuggestionMode) -> String {
+        let shell = shell.to_string();
+        fill(self.system_commands, &[("shell", &shell), ("instruction", self.instruction(mode))])
+    }
+
+    /// What the system prompt asks for in `mode`
+    pub fn instruction(&self, mode: SuggestionMode) -> &'static str {
+        match mode {
+            SuggestionMode::Auto => self.instruction_auto,
+            SuggestionMode::Single => self.instruction_single,
+            SuggestionMode::Plan => self.instruction_plan,
+        }
+    }
+
+    /// System prompt of a request explaining a command of `shell`
+    pub fn explain_system(&self, shell: ShellType) -> String {
+        fill(self.system_explain, &[("shell", &shell.to_string())])
+    }
+
+    /// Prompt of `aurish-cli` for the question of `turn`, counted from 0
+    pub fn asking_prompt(&self, turn: usize) -> String {
+        match turn {
+            0 => self.asking.to_string(),
+            turn => fill(self.asking_turn, &[("turn", &(turn + 1).to_string())]),
+        }
+    }
+}
+
+/// `template` with each `{name}` replaced by its value
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    values.iter().fold(template.to_string(), |text, (name, value)| text.replace(&format!("{{{}}}", name), value))
+}
+
+pub static ENGLISH: Strings = Strings {
+    system_commands: "You are {shell} expert, your task is give {shell} commands that meets user requirements. {instruction} Your answer should only contains commands. Respond using JSON.",
+    instruction_auto: "Give a single command when one is enough, and the commands in the order to run them when the task needs several.",
+    instruction_single: "Give exactly one command, the best one for the task.",
+    instruction_plan: "Give every command the task needs, one step per command, in the order to run them.",
+    system_explain: "You are {shell} expert. Explain what this {shell} command does, part by part, in plain text without markdown. Clearly flag any dangerous effects, like deleting or overwriting data, changing permissions or system settings, or sending data over the network.",
+    press: "Press ",
+    to_exit: " to exit, ",
+    to_ask_ai: " to ask AI, ",
+    to_interact_with_shell: " to interact with Shell, ",
+    to_read_responses: " to read responses, ",
+    to_switch_model: " to switch model, ",
+    for_all_keys: " for all keys, ",
+    select: " select, ",
+    reorder: " reorder, ",
+    delete: " delete, ",
+    skip: " skip, ",
+    discard_queue: " discard all queued commands, ",
+    run_queue: " run them all, ",
+    scroll_output: " scroll output.",
+    waiting: "Waiting for the answer, press ",
+    to_cancel_and_edit: " to cancel and edit the prompt",
+    stop_asking: " stop asking AI, ",
+    to_send: " to send, ",
+    for_new_line: " for a new line",
+    stop_reading: " stop reading responses, ",
+    to_scroll: " to scroll",
+    stop_shell: " stop Shell interaction, ",
+    to_execute: " to execute shell command, ",
+    to_see_plan: " to see what it does, ",
+    to_skip: " to skip it",
+    asking: "Asking AI >> ",
+    asking_turn: "Asking AI [turn {turn}] >> ",
+    run_this: "run this? [y/N/e(dit)] ",
+    run_which: "Run which commands? (e.g. 1,3-5, all or none) [all] ",
+    sudo_password: "sudo would ask for a password, run it on the terminal? [Y/n(o password, sudo -n)/s(kip)] ",
+};
+
+pub static SPANISH: Strings = Strings {
+    system_commands: "Eres experto en {shell}, tu tarea es dar comandos de {shell} que cumplan lo que pide el usuario. {instruction} Tu respuesta solo debe contener comandos. Responde usando JSON.",
+    instruction_auto: "Da un solo comando cuando baste con uno, y los comandos en el orden en que se ejecutan cuando la tarea necesite varios.",
+    instruction_single: "Da exactamente un comando, el mejor para la tarea.",
+    instruction_plan: "Da todos los comandos que necesita la tarea, un paso por comando, en el orden en que se ejecutan.",
+    system_explain: "Eres experto en {shell}. Explica qué hace este comando de {shell}, parte por parte, en texto plano sin markdown. Señala claramente cualquier efecto peligroso, como borrar o sobrescribir datos, cambiar permisos o ajustes del sistema, o enviar datos por la red.",
+    press: "Pulsa ",
+    to_exit: " para salir, ",
+    to_ask_ai: " para preguntar a la IA, ",
+    to_interact_with_shell: " para usar la Shell, ",
+    to_read_responses: " para leer las respuestas, ",
+    to_switch_model: " para cambiar de modelo, ",
+    for_all_keys: " para ver todas las teclas, ",
+    select: " seleccionar, ",
+    reorder: " reordenar, ",
+    delete: " borrar, ",
+    skip: " saltar, ",
+    discard_queue: " descartar los comandos en cola, ",
+    run_queue: " ejecutarlos todos, ",
+    scroll_output: " desplazar la salida.",
+    waiting: "Esperando la respuesta, pulsa ",
+    to_cancel_and_edit: " para cancelar y editar la pregunta",
+    stop_asking: " deja de preguntar a la IA, ",
+    to_send: " para enviar, ",
+    for_new_line: " para una línea nueva",
+    stop_reading: " deja de leer las respuestas, ",
+    to_scroll: " para desplazar",
+    stop_shell: " deja de usar la Shell, ",
+    to_execute: " para ejecutar el comando, ",
+    to_see_plan: " para ver qué hace, ",
+    to_skip: " para saltarlo",
+    asking: "Preguntar a la IA >> ",
+    asking_turn: "Preguntar a la IA [turno {turn}] >> ",
+    run_this: "¿ejecutar esto? [y/N/e(ditar)] ",
+    run_which: "¿Qué comandos ejecutar? (p. ej. 1,3-5, all o none) [all] ",
+    sudo_password: "sudo pediría una contraseña, ¿ejecutarlo en la terminal? [Y/n(o, sin contraseña, sudo -n)/s(altar)] ",
+};
+
+pub static CHINESE: Strings = Strings {
+    system_commands: "你是 {shell} 专家，你的任务是给出满足用户需求的 {shell} 命令。{instruction} 你的回答只能包含命令。请使用 JSON 回答。",
+    instruction_auto: "一个命令就够时只给一个命令，任务需要多个命令时按执行顺序给出。",
+    instruction_single: "只给出一个命令，即最适合该任务的命令。",
+    instruction_plan: "给出任务需要的所有命令，每个命令一步，按执行顺序排列。",
+    system_explain: "你是 {shell} 专家。请逐部分解释这个 {shell} 命令的作用，使用纯文本，不要使用 markdown。清楚标出任何危险的影响，例如删除或覆盖数据、更改权限或系统设置、或通过网络发送数据。",
+    press: "按 ",
+    to_exit: " 退出，",
+    to_ask_ai: " 询问 AI，",
+    to_interact_with_shell: " 使用 Shell，",
+    to_read_responses: " 阅读回答，",
+    to_switch_model: " 切换模型，",
+    for_all_keys: " 查看所有按键，",
+    select: " 选择，",
+    reorder: " 调整顺序，",
+    delete: " 删除，",
+    skip: " 跳过，",
+    discard_queue: " 丢弃所有排队的命令，",
+    run_queue: " 全部运行，",
+    scroll_output: " 滚动输出。",
+    waiting: "正在等待回答，按 ",
+    to_cancel_and_edit: " 取消并编辑提示",
+    stop_asking: " 停止询问 AI，",
+    to_send: " 发送，",
+    for_new_line: " 换行",
+    stop_reading: " 停止阅读回答，",
+    to_scroll: " 滚动",
+    stop_shell: " 停止使用 Shell，",
+    to_execute: " 执行命令，",
+    to_see_plan: " 查看它会做什么，",
+    to_skip: " 跳过它",
+    asking: "询问 AI >> ",
+    asking_turn: "询问 AI [第 {turn} 轮] >> ",
+    run_this: "运行这个命令？[y/N/e(编辑)] ",
+    run_which: "运行哪些命令？（例如 1,3-5、all 或 none）[all] ",
+    sudo_password: "sudo 会要求输入密码，在终端中运行吗？[Y/n(不输入密码，sudo -n)/s(跳过)] ",
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `{names}` in `text`, in order
+    fn placeholders(text: &str) -> Vec<&str> {
+        text.split('{').skip(1).filter_map(|rest| rest.split_once('}').map(|(name, _)| name)).collect()
+    }
+
+    #[test]
+    fn every_language_has_every_key() {
+        let keys: Vec<&str> = ENGLISH.entries().into_iter().map(|(key, _)| key).collect();
+        for language in Language::ALL {
+            let entries = language.strings().entries();
+            assert_eq!(entries.iter().map(|(key, _)| *key).collect::<Vec<_>>(), keys, "{}", language);
+            for ((key, text), (_, english)) in entries.into_iter().zip(ENGLISH.entries()) {
+                assert!(!text.trim().is_empty(), "{} of {} is empty", key, language);
+                let (mut found, mut expected) = (placeholders(text), placeholders(english));
+                found.sort();
+                expected.sort();
+                assert_eq!(found, expected, "placeholders of {} in {}", key, language);
+            }
+        }
+    }
+
+    #[test]
+    fn language_codes() {
+        assert_eq!("es".parse(), Ok(Language::Spanish));
+        assert_eq!(" ZH_cn ".parse(), Ok(Language::Chinese));
+        assert_eq!("en-GB".parse(), Ok(Language::English));
+        assert_eq!("fr".parse::<Language>(), Err(()));
+        assert_eq!("".parse::<Language>(), Err(()));
+        for language in Language::ALL {
+            assert_eq!(language.to_string().parse(), Ok(language));
+        }
+        assert_eq!(Language::codes(), "en, es and zh");
+    }
+
+    #[test]
+    fn filled_templates() {
+        let system = SPANISH.commands_system(ShellType::Zsh, SuggestionMode::Single);
+        assert_eq!(system, format!("Eres experto en Zsh, tu tarea es dar comandos de Zsh que cumplan lo que pide el usuario. {} Tu respuesta solo debe contener comandos. Responde usando JSON.", SPANISH.instruction_single));
+        assert!(!CHINESE.explain_system(ShellType::Bash).contains('{'));
+        assert_eq!(ENGLISH.asking_prompt(0), "Asking AI >> ");
+        assert_eq!(CHINESE.asking_prompt(2), "询问 AI [第 3 轮] >> ");
+    }
+}
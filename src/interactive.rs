@@ -0,0 +1,97 @@
+//! Commands that need the terminal, like `sudo` asking for a password or interactive installers
+//!
+//! Commands normally run with their output captured through pipes, and in the TUI in their
+//! own process group, so one that asks for input waits for an answer nobody can give. Any
+//! command can instead be run attached to the terminal with `IShell::run_interactive`, only
+//! its exit code is kept then. `sudo` is caught before it runs: without cached credentials
+//! (`sudo -n true` fails) the user is offered to type the password first, with `sudo -v` on
+//! the terminal, or to run the command with `sudo -n`, which fails at once instead of asking.
+
+use std::process::{Command, Stdio};
+
+/// Prefix of a line in `aurish-cli run` that runs the command after it on the terminal
+pub const TTY_PREFIX: &str = ":tty";
+
+/// Command asking for the password of `sudo`, which then needs none for a while
+pub const SUDO_VALIDATE: &str = "sudo -v";
+
+/// Note of a command run on the terminal, whose output was not captured
+pub const INTERACTIVE_NOTE: &str = "ran interactively, output not captured";
+
+/// Whether `command` starts with `sudo` in a way that may ask for a password, which
+/// `sudo -n` never does
+pub fn starts_with_sudo(command: &str) -> bool {
+    let mut words = command.split_whitespace();
+    if words.next() != Some("sudo") {
+        return false;
+    }
+    // options come before the command sudo runs
+    !words
+        .take_while(|word| word.starts_with('-'))
+        .any(|option| option == "--non-interactive" || (!option.starts_with("--") && option[1..].contains('n')))
+}
+
+/// `command` with `sudo -n` in place of its leading `sudo`
+pub fn non_interactive_sudo(command: &str) -> String {
+    let command = command.trim_start();
+    match command.strip_prefix("sudo") {
+        Some(rest) if starts_with_sudo(command) => format!("sudo -n{}", rest),
+        _ => command.to_string(),
+    }
+}
+
+/// Whether `sudo` has cached credentials, so that it runs without asking for a password
+pub fn sudo_cached() -> bool {
+    Command::new("sudo")
+        .args(["-n", "true"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Whether `command` would stop at the password prompt of `sudo`. `cached` tells whether
+/// credentials are cached, and is only asked for commands starting with `sudo`.
+pub fn needs_sudo_password(command: &str, cached: impl FnOnce() -> bool) -> bool {
+    starts_with_sudo(command) && !cached()
+}
+
+/// The command of a `:tty <command>` line, empty for a bare `:tty`; `None` for other lines
+pub fn strip_tty_prefix(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix(TTY_PREFIX)?;
+    (rest.is_empty() || rest.starts_with(char::is_whitespace)).then(|| rest.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sudo_commands() {
+        assert!(starts_with_sudo("sudo apt install ripgrep"));
+        assert!(starts_with_sudo("  sudo -E make install"));
+        assert!(starts_with_sudo("sudo -u postgres psql -n"));
+        assert!(!starts_with_sudo("sudo -n apt update"));
+        assert!(!starts_with_sudo("sudo -En apt update"));
+        assert!(!starts_with_sudo("sudo --non-interactive apt update"));
+        assert!(!starts_with_sudo("sudoku"));
+        assert!(!starts_with_sudo("echo sudo"));
+
+        assert_eq!(non_interactive_sudo("sudo apt install ripgrep"), "sudo -n apt install ripgrep");
+        assert_eq!(non_interactive_sudo("sudo -n true"), "sudo -n true");
+        assert_eq!(non_interactive_sudo("ls"), "ls");
+
+        assert!(needs_sudo_password("sudo apt update", || false));
+        assert!(!needs_sudo_password("sudo apt update", || true));
+        assert!(!needs_sudo_password("apt list", || panic!("only asked for sudo commands")));
+    }
+
+    #[test]
+    fn tty_prefix() {
+        assert_eq!(strip_tty_prefix(":tty htop"), Some("htop"));
+        assert_eq!(strip_tty_prefix("  :tty  "), Some(""));
+        assert_eq!(strip_tty_prefix(":ttyhtop"), None);
+        assert_eq!(strip_tty_prefix("htop"), None);
+    }
+}
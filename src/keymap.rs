@@ -0,0 +1,1000 @@
+//! Keybindings of the TUI
+//!
+//! Every key the TUI reacts to is listed in one table of `Binding`s. The event loop looks
+//! keys up in that table and the help overlay is rendered from it, so the two cannot
+//! disagree. Keys without a binding go to the text box of the current mode, if any.
+//!
+//! There are two tables, picked with `keymap` in the config: the built-in keys, and the
+//! same keys with Vim-style ones added on top. `keybindings` in the config then gives
+//! actions other keys, written as specs like `ctrl+a`, `F2` or `g g`, see `with_overrides`.
+
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use crate::error::{KeySpecError, KeymapError};
+use crate::shared::EditMode;
+pub use crate::config::KeySpecs;
+
+/// Something the user can ask the TUI to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    AskAi,
+    EditShell,
+    ReadResponses,
+    SwitchModel,
+    ToggleHelp,
+    /// Cancel the pending request, or go back to Normal mode
+    Back,
+    SelectPrev,
+    SelectNext,
+    MoveSelectedUp,
+    MoveSelectedDown,
+    DeleteSelected,
+    SkipSelected,
+    DiscardQueue,
+    /// Run the whole queue, after confirmation
+    RunAll,
+    /// Scroll the Output block by this many lines, positive goes back in history
+    ScrollOutput(i32),
+    /// Select entries of the Output history
+    FocusOutput,
+    /// Move the Output selection by this many entries
+    SelectOutput(i32),
+    /// Copy what the selected (or newest) Output entry printed
+    CopyOutput,
+    /// Copy the selected command text
+    CopyCommand,
+    /// Queue the commands on the clipboard, a line each
+    PasteCommands,
+    /// Suspend the TUI to read the selected Output entry's long output in `$PAGER`
+    OpenPager,
+    /// Type a query to search the Output history
+    StartSearch,
+    /// Jump to the next line matching the search
+    NextMatch,
+    /// Jump to the previous line matching the search
+    PrevMatch,
+    /// Keep the search and go back to the mode it was started from
+    ConfirmSearch,
+    /// Clear the search and go back to the mode it was started from
+    ClearSearch,
+    /// Run the next commands in the directory aurish was started in, undoing `cd`s
+    ResetDirectory,
+    /// Write the session transcript to a file
+    ExportSession,
+    /// Scroll the Response block by this many lines, positive goes back in history
+    ScrollResponse(i32),
+    /// Scroll the help overlay by this many lines, positive goes down
+    ScrollHelp(i32),
+    SendPrompt,
+    /// Put the last prompt back into the Asking AI box
+    EditLastPrompt,
+    /// Ask the last prompt again with a different seed
+    Regenerate,
+    /// Start a new line in the prompt
+    NewLine,
+    /// Move to the line above, or recall the previous prompt on the first line
+    PrevPrompt,
+    /// Move to the line below, or recall the next prompt on the last line
+    NextPrompt,
+    ExecuteCommand,
+    /// Suspend the TUI and run the command attached to the terminal, see `interactive`
+    ExecuteInteractive,
+    /// Show or hide what the command in the Shell box would do, see `IShell::explain_command`
+    TogglePlan,
+    /// Complete the file name before the cursor, or cycle through the matches
+    Complete,
+    /// Complete the template name after `/` at the start of the prompt
+    CompleteTemplate,
+    ConfirmRun,
+    ConfirmCancel,
+    /// The other choice a popup offers besides `ConfirmRun`, like running `sudo -n`
+    ConfirmAlternative,
+    /// Move the model switcher selection by this many entries
+    SelectModel(i32),
+    UseModel,
+    SaveModel,
+    CloseModelPicker,
+    /// Ask the last prompt of the current model and `compare_model` side by side
+    CompareModels,
+    /// Move the Compare selection by this many answers
+    SelectAnswer(i32),
+    /// Queue the commands of the selected answer
+    QueueAnswer,
+    CloseComparison,
+    /// Type a name to save the selected command as a favorite under
+    SaveFavorite,
+    /// Open the list of favorites
+    BrowseFavorites,
+    /// Save the favorite under the typed name, asking before replacing one
+    NameFavorite,
+    /// Move the favorites selection by this many entries
+    SelectFavorite(i32),
+    /// Insert the selected favorite into the Shell box
+    InsertFavorite,
+    RemoveFavorite,
+    CloseFavorites,
+    /// Turn auto-fix of failed commands on or off, see `autofix`
+    ToggleAutoFix,
+    /// Go to the next suggestion mode: auto, single or plan, see `suggestions`
+    CycleSuggestionMode,
+    /// Type new values for the words shared by the queued commands
+    EditParams,
+    /// Keep the typed value and go to the next one, or change the queue after the last
+    SetParam,
+    CloseParams,
+}
+
+/// Key press a binding reacts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl Key {
+    pub const fn new(code: KeyCode) -> Key {
+        Key { code, modifiers: KeyModifiers::NONE }
+    }
+
+    pub const fn char(c: char) -> Key {
+        Key::new(KeyCode::Char(c))
+    }
+
+    pub const fn ctrl(c: char) -> Key {
+        Key { code: KeyCode::Char(c), modifiers: KeyModifiers::CONTROL }
+    }
+
+    pub const fn with(code: KeyCode, modifiers: KeyModifiers) -> Key {
+        Key { code, modifiers }
+    }
+
+    /// Shift is ignored for characters, it is already part of them (`K`, `?`, ...)
+    pub fn matches(&self, event: &KeyEvent) -> bool {
+        let modifiers = match event.code {
+            KeyCode::Char(_) => event.modifiers - KeyModifiers::SHIFT,
+            _ => event.modifiers,
+        };
+        self.code == event.code && self.modifiers == modifiers
+    }
+
+    /// Short name shown in the help overlay, e.g. `Ctrl-S` or `PgUp`
+    pub fn label(&self) -> String {
+        let name = match self.code {
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char(c) if self.modifiers.contains(KeyModifiers::CONTROL) => c.to_ascii_uppercase().to_string(),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::Up => "↑".to_string(),
+            KeyCode::Down => "↓".to_string(),
+            KeyCode::Left => "←".to_string(),
+            KeyCode::Right => "→".to_string(),
+            KeyCode::PageUp => "PgUp".to_string(),
+            KeyCode::PageDown => "PgDn".to_string(),
+            KeyCode::Esc => "Esc".to_string(),
+            // reported with shift, which makes it `Shift-Tab`
+            KeyCode::BackTab => "Tab".to_string(),
+            KeyCode::F(n) => format!("F{}", n),
+            other => format!("{:?}", other),
+        };
+        let mut label = String::new();
+        for (modifier, prefix) in [(KeyModifiers::CONTROL, "Ctrl-"), (KeyModifiers::ALT, "Alt-"), (KeyModifiers::SHIFT, "Shift-")] {
+            if self.modifiers.contains(modifier) {
+                label.push_str(prefix);
+            }
+        }
+        label + &name
+    }
+}
+
+impl FromStr for Key {
+    type Err = KeySpecError;
+
+    /// Parse a single key like `q`, `ctrl+a`, `Alt-Enter` or `F2`. Modifiers (`ctrl`, `alt`,
+    /// `shift`) and key names are case-insensitive and end with `+` or `-`, a single
+    /// character is taken as is, so `ctrl++` is Ctrl and the plus key.
+    fn from_str(spec: &str) -> Result<Key, KeySpecError> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = spec.trim();
+        while let Some((modifier, key)) = split_modifier(rest) {
+            modifiers |= modifier;
+            rest = key;
+        }
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (None, _) => return Err(KeySpecError::Empty),
+            (Some(c), None) => KeyCode::Char(c),
+            _ => named_key(rest).ok_or_else(|| KeySpecError::UnknownKey(rest.to_string()))?,
+        };
+        let shift = modifiers.contains(KeyModifiers::SHIFT);
+        Ok(match code {
+            // terminals report the shifted character, which `matches` compares without shift
+            KeyCode::Char(c) if shift && c.is_alphabetic() => {
+                Key::with(KeyCode::Char(c.to_uppercase().next().unwrap_or(c)), modifiers - KeyModifiers::SHIFT)
+            },
+            KeyCode::Char(c) if shift => return Err(KeySpecError::ShiftedChar(c)),
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => Key::with(KeyCode::Char(c.to_ascii_lowercase()), modifiers),
+            KeyCode::Tab if shift => Key::with(KeyCode::BackTab, modifiers),
+            KeyCode::BackTab => Key::with(KeyCode::BackTab, modifiers | KeyModifiers::SHIFT),
+            code => Key::with(code, modifiers),
+        })
+    }
+}
+
+/// The modifier `spec` starts with and the rest of it, `None` if it starts with a key
+fn split_modifier(spec: &str) -> Option<(KeyModifiers, &str)> {
+    let (name, rest) = spec.split_once(['+', '-'])?;
+    let modifier = match name.to_ascii_lowercase().as_str() {
+        "ctrl" | "control" => KeyModifiers::CONTROL,
+        "alt" | "meta" => KeyModifiers::ALT,
+        "shift" => KeyModifiers::SHIFT,
+        _ => return None,
+    };
+    Some((modifier, rest))
+}
+
+/// Key called `name`, one of `KEY_NAMES`, an alias of one or `F1` to `F24`
+fn named_key(name: &str) -> Option<KeyCode> {
+    let name = name.to_ascii_lowercase();
+    let code = match name.as_str() {
+        "enter" | "return" => KeyCode::Enter,
+        "esc" | "escape" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "insert" | "ins" => KeyCode::Insert,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" | "pgup" => KeyCode::PageUp,
+        "pagedown" | "pgdn" => KeyCode::PageDown,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "space" => KeyCode::Char(' '),
+        _ => {
+            let number = name.strip_prefix('f').filter(|number| number.bytes().all(|b| b.is_ascii_digit()))?;
+            return number.parse().ok().filter(|n| (1..=24).contains(n)).map(KeyCode::F);
+        },
+    };
+    Some(code)
+}
+
+/// Parse a key spec: keys separated by spaces, pressed one after another, e.g. `ctrl+x s`
+/// or `g g`. See `Key::from_str` for a single key.
+pub fn parse_keys(spec: &str) -> Result<Vec<Key>, KeySpecError> {
+    let keys = spec.split_whitespace().map(str::parse).collect::<Result<Vec<Key>, _>>()?;
+    if keys.is_empty() {
+        return Err(KeySpecError::Empty);
+    }
+    Ok(keys)
+}
+
+/// `keys`, pressed one after another, trigger `action` while the TUI is in `mode`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    pub mode: EditMode,
+    pub keys: Vec<Key>,
+    pub action: Action,
+    pub description: &'static str,
+}
+
+impl Binding {
+    pub fn new(mode: EditMode, key: Key, action: Action, description: &'static str) -> Binding {
+        Binding { mode, keys: vec![key], action, description }
+    }
+
+    /// Binding to several keys in a row, like `gg`
+    pub fn sequence(mode: EditMode, keys: &[Key], action: Action, description: &'static str) -> Binding {
+        Binding { mode, keys: keys.to_vec(), action, description }
+    }
+
+    /// Keys as shown in the help overlay, e.g. `Ctrl-S`, `gg` or `Ctrl-X s`
+    pub fn label(&self) -> String {
+        let labels: Vec<String> = self.keys.iter().map(Key::label).collect();
+        if labels.iter().all(|label| label.chars().count() == 1) {
+            labels.concat()
+        } else {
+            labels.join(" ")
+        }
+    }
+}
+
+/// Result of looking up the keys pressed so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lookup {
+    Action(Action),
+    /// The keys begin a longer binding, like the first `g` of `gg`
+    Prefix,
+    Unbound,
+}
+
+/// Names accepted for `keymap` in the config
+pub const KEYMAPS: [&str; 2] = ["default", "vim"];
+
+/// Keys a spec can name besides single characters and `F1` to `F24`, see `Key::from_str`
+pub const KEY_NAMES: [&str; 15] = [
+    "enter", "esc", "tab", "backspace", "delete", "insert", "home", "end",
+    "pageup", "pagedown", "up", "down", "left", "right", "space",
+];
+
+/// Lines scrolled by the actions going to the top or bottom
+const FULL: i32 = u16::MAX as i32;
+
+/// Names of the actions in the `keybindings` of the config. Scrolling `up` and `down` moves
+/// a line, `page_up` and `page_down` ten.
+pub const ACTION_NAMES: &[(&str, Action)] = &[
+    ("quit", Action::Quit),
+    ("ask_ai", Action::AskAi),
+    ("edit_shell", Action::EditShell),
+    ("read_responses", Action::ReadResponses),
+    ("switch_model", Action::SwitchModel),
+    ("toggle_help", Action::ToggleHelp),
+    ("back", Action::Back),
+    ("select_prev", Action::SelectPrev),
+    ("select_next", Action::SelectNext),
+    ("move_selected_up", Action::MoveSelectedUp),
+    ("move_selected_down", Action::MoveSelectedDown),
+    ("delete_selected", Action::DeleteSelected),
+    ("skip_selected", Action::SkipSelected),
+    ("discard_queue", Action::DiscardQueue),
+    ("run_all", Action::RunAll),
+    ("scroll_output_up", Action::ScrollOutput(1)),
+    ("scroll_output_down", Action::ScrollOutput(-1)),
+    ("scroll_output_page_up", Action::ScrollOutput(10)),
+    ("scroll_output_page_down", Action::ScrollOutput(-10)),
+    ("scroll_output_top", Action::ScrollOutput(FULL)),
+    ("scroll_output_bottom", Action::ScrollOutput(-FULL)),
+    ("focus_output", Action::FocusOutput),
+    ("select_prev_output", Action::SelectOutput(-1)),
+    ("select_next_output", Action::SelectOutput(1)),
+    ("copy_output", Action::CopyOutput),
+    ("copy_command", Action::CopyCommand),
+    ("paste_commands", Action::PasteCommands),
+    ("open_pager", Action::OpenPager),
+    ("start_search", Action::StartSearch),
+    ("next_match", Action::NextMatch),
+    ("prev_match", Action::PrevMatch),
+    ("confirm_search", Action::ConfirmSearch),
+    ("clear_search", Action::ClearSearch),
+    ("reset_directory", Action::ResetDirectory),
+    ("export_session", Action::ExportSession),
+    ("scroll_response_up", Action::ScrollResponse(1)),
+    ("scroll_response_down", Action::ScrollResponse(-1)),
+    ("scroll_response_page_up", Action::ScrollResponse(10)),
+    ("scroll_response_page_down", Action::ScrollResponse(-10)),
+    ("scroll_response_top", Action::ScrollResponse(FULL)),
+    ("scroll_response_bottom", Action::ScrollResponse(-FULL)),
+    ("scroll_help_up", Action::ScrollHelp(-1)),
+    ("scroll_help_down", Action::ScrollHelp(1)),
+    ("scroll_help_page_up", Action::ScrollHelp(-10)),
+    ("scroll_help_page_down", Action::ScrollHelp(10)),
+    ("scroll_help_top", Action::ScrollHelp(-FULL)),
+    ("scroll_help_bottom", Action::ScrollHelp(FULL)),
+    ("send_prompt", Action::SendPrompt),
+    ("edit_last_prompt", Action::EditLastPrompt),
+    ("regenerate", Action::Regenerate),
+    ("new_line", Action::NewLine),
+    ("prev_prompt", Action::PrevPrompt),
+    ("next_prompt", Action::NextPrompt),
+    ("execute_command", Action::ExecuteCommand),
+    ("execute_interactive", Action::ExecuteInteractive),
+    ("toggle_plan", Action::TogglePlan),
+    ("complete", Action::Complete),
+    ("complete_template", Action::CompleteTemplate),
+    ("confirm_run", Action::ConfirmRun),
+    ("confirm_cancel", Action::ConfirmCancel),
+    ("confirm_alternative", Action::ConfirmAlternative),
+    ("select_prev_model", Action::SelectModel(-1)),
+    ("select_next_model", Action::SelectModel(1)),
+    ("use_model", Action::UseModel),
+    ("save_model", Action::SaveModel),
+    ("close_model_picker", Action::CloseModelPicker),
+    ("compare_models", Action::CompareModels),
+    ("select_prev_answer", Action::SelectAnswer(-1)),
+    ("select_next_answer", Action::SelectAnswer(1)),
+    ("queue_answer", Action::QueueAnswer),
+    ("close_comparison", Action::CloseComparison),
+    ("save_favorite", Action::SaveFavorite),
+    ("browse_favorites", Action::BrowseFavorites),
+    ("name_favorite", Action::NameFavorite),
+    ("select_prev_favorite", Action::SelectFavorite(-1)),
+    ("select_next_favorite", Action::SelectFavorite(1)),
+    ("insert_favorite", Action::InsertFavorite),
+    ("remove_favorite", Action::RemoveFavorite),
+    ("close_favorites", Action::CloseFavorites),
+    ("toggle_auto_fix", Action::ToggleAutoFix),
+    ("cycle_suggestion_mode", Action::CycleSuggestionMode),
+    ("edit_params", Action::EditParams),
+    ("set_param", Action::SetParam),
+    ("close_params", Action::CloseParams),
+];
+
+/// Action called `name` in `ACTION_NAMES`
+pub fn action_by_name(name: &str) -> Option<Action> {
+    ACTION_NAMES.iter().find(|(action_name, _)| *action_name == name).map(|(_, action)| *action)
+}
+
+/// Name of `action` in `ACTION_NAMES`
+pub fn action_name(action: Action) -> Option<&'static str> {
+    ACTION_NAMES.iter().find(|(_, named)| *named == action).map(|(name, _)| *name)
+}
+
+/// Modes with a text box, where plain characters are typed rather than bound
+const TEXT_MODES: [EditMode; 5] = [EditMode::Input, EditMode::Shell, EditMode::Search, EditMode::FavoriteName, EditMode::Params];
+
+/// Modes in the order the help overlay lists them, with their headings
+const MODES: [(EditMode, &str); 13] = [
+    (EditMode::Normal, "Normal"),
+    (EditMode::Output, "Output"),
+    (EditMode::Search, "Search output"),
+    (EditMode::Input, "Asking AI"),
+    (EditMode::Shell, "Shell"),
+    (EditMode::Response, "Response"),
+    (EditMode::Confirm, "Confirm dangerous command"),
+    (EditMode::Models, "Switch model"),
+    (EditMode::Compare, "Compare models"),
+    (EditMode::FavoriteName, "Save favorite"),
+    (EditMode::Favorites, "Favorites"),
+    (EditMode::Params, "Shared values"),
+    (EditMode::Help, "Help"),
+];
+
+/// Notes shown under the keys of a mode, like how its text box treats pasted text
+const MODE_NOTES: [(EditMode, &str); 3] = [
+    (EditMode::Search, "Case-insensitive, unless the query contains capitals"),
+    (EditMode::Input, "Pasted text is inserted as is, newlines included. /name args asks the template `name` of templates.toml"),
+    (EditMode::Shell, "Pasting several lines puts the first one in the box and queues the others after it"),
+];
+
+/// The built-in keybindings
+pub fn default_bindings() -> Vec<Binding> {
+    use Action::*;
+    use EditMode::*;
+
+    vec![
+        Binding::new(Normal, Key::char('q'), Quit, "quit, asking first if commands are queued or running"),
+        Binding::new(Normal, Key::char('a'), AskAi, "ask AI"),
+        Binding::new(Normal, Key::char('s'), EditShell, "interact with the Shell"),
+        Binding::new(Normal, Key::char('v'), ReadResponses, "read responses"),
+        Binding::new(Normal, Key::char('r'), EditLastPrompt, "edit the last prompt and ask again"),
+        Binding::new(Normal, Key::ctrl('r'), Regenerate, "regenerate the answer to the last prompt, asking before replacing the queue"),
+        Binding::new(Normal, Key::char('m'), SwitchModel, "switch model"),
+        Binding::new(Normal, Key::char('c'), CompareModels, "compare the answers of this model and `compare_model` to the last prompt"),
+        Binding::new(Normal, Key::char('?'), ToggleHelp, "show this help"),
+        Binding::new(Normal, Key::new(KeyCode::Esc), Back, "cancel the pending request, or stop running the queue"),
+        Binding::new(Normal, Key::new(KeyCode::Up), SelectPrev, "select previous queued command"),
+        Binding::new(Normal, Key::new(KeyCode::Down), SelectNext, "select next queued command"),
+        Binding::new(Normal, Key::char('K'), MoveSelectedUp, "move selected command up"),
+        Binding::new(Normal, Key::char('J'), MoveSelectedDown, "move selected command down"),
+        Binding::new(Normal, Key::char('d'), DeleteSelected, "delete selected command"),
+        Binding::new(Normal, Key::char('x'), SkipSelected, "skip selected command"),
+        Binding::new(Normal, Key::char('X'), DiscardQueue, "discard all queued commands"),
+        Binding::new(Normal, Key::char('R'), RunAll, "run all queued commands, stopping at the first failure"),
+        Binding::new(Normal, Key::new(KeyCode::PageUp), ScrollOutput(10), "scroll output up"),
+        Binding::new(Normal, Key::new(KeyCode::PageDown), ScrollOutput(-10), "scroll output down"),
+        Binding::new(Normal, Key::new(KeyCode::Home), ScrollOutput(FULL), "scroll to oldest output"),
+        Binding::new(Normal, Key::new(KeyCode::End), ScrollOutput(-FULL), "scroll to newest output"),
+        Binding::new(Normal, Key::char('o'), FocusOutput, "select output entries"),
+        Binding::new(Normal, Key::char('y'), CopyOutput, "copy the newest output"),
+        Binding::new(Normal, Key::char('Y'), CopyCommand, "copy the selected command"),
+        Binding::new(Normal, Key::char('P'), PasteCommands, "queue the commands on the clipboard, a line each"),
+        Binding::new(Normal, Key::char('e'), ExportSession, "export the session transcript"),
+        Binding::new(Normal, Key::char('~'), ResetDirectory, "go back to the directory aurish was started in"),
+        Binding::new(Normal, Key::char('/'), StartSearch, "search the output"),
+        Binding::new(Normal, Key::char('n'), NextMatch, "next search match"),
+        Binding::new(Normal, Key::char('N'), PrevMatch, "previous search match"),
+        Binding::new(Normal, Key::char('f'), SaveFavorite, "save the selected command as a favorite"),
+        Binding::new(Normal, Key::char('F'), BrowseFavorites, "browse the favorites"),
+        Binding::new(Normal, Key::char('p'), EditParams, "change a value shared by the queued commands in all of them"),
+        Binding::new(Normal, Key::char('A'), ToggleAutoFix, "turn auto-fix on or off: a failed command is sent back to the model for a correction"),
+        Binding::new(Normal, Key::char('S'), CycleSuggestionMode, "ask for commands in auto, single or plan mode"),
+        Binding::new(Output, Key::new(KeyCode::Up), SelectOutput(-1), "select previous entry"),
+        Binding::new(Output, Key::new(KeyCode::Down), SelectOutput(1), "select next entry"),
+        Binding::new(Output, Key::new(KeyCode::PageUp), ScrollOutput(10), "scroll output up"),
+        Binding::new(Output, Key::new(KeyCode::PageDown), ScrollOutput(-10), "scroll output down"),
+        Binding::new(Output, Key::char('y'), CopyOutput, "copy the entry's output"),
+        Binding::new(Output, Key::char('Y'), CopyCommand, "copy the entry's command"),
+        Binding::new(Output, Key::char('o'), OpenPager, "read the entry's omitted lines in $PAGER"),
+        Binding::new(Output, Key::char('/'), StartSearch, "search the output"),
+        Binding::new(Output, Key::char('n'), NextMatch, "next search match, selecting its entry"),
+        Binding::new(Output, Key::char('N'), PrevMatch, "previous search match, selecting its entry"),
+        Binding::new(Output, Key::new(KeyCode::Esc), Back, "go back to Normal mode"),
+        Binding::new(Search, Key::new(KeyCode::Enter), ConfirmSearch, "keep the search, then n/N jump between matches"),
+        Binding::new(Search, Key::new(KeyCode::Down), NextMatch, "next match"),
+        Binding::new(Search, Key::new(KeyCode::Up), PrevMatch, "previous match"),
+        Binding::new(Search, Key::new(KeyCode::Esc), ClearSearch, "clear the search"),
+        Binding::new(Input, Key::new(KeyCode::Enter), SendPrompt, "send the prompt"),
+        Binding::new(Input, Key::with(KeyCode::Enter, KeyModifiers::SHIFT), NewLine, "start a new line"),
+        Binding::new(Input, Key::with(KeyCode::Enter, KeyModifiers::ALT), NewLine, "start a new line"),
+        Binding::new(Input, Key::new(KeyCode::Up), PrevPrompt, "line above, or previous prompt"),
+        Binding::new(Input, Key::new(KeyCode::Down), NextPrompt, "line below, or next prompt"),
+        Binding::new(Input, Key::new(KeyCode::Tab), CompleteTemplate, "complete the template name after /"),
+        Binding::new(Input, Key::new(KeyCode::Esc), Back, "cancel the pending request, or stop asking AI"),
+        Binding::new(Shell, Key::new(KeyCode::Enter), ExecuteCommand, "execute the command"),
+        Binding::new(Shell, Key::ctrl('t'), ExecuteInteractive, "run the command on the terminal, for password prompts and interactive programs"),
+        Binding::new(Shell, Key::ctrl('p'), TogglePlan, "show or hide what the command would do, without running it"),
+        Binding::new(Shell, Key::ctrl('s'), SkipSelected, "skip the command"),
+        Binding::new(Shell, Key::new(KeyCode::Tab), Complete, "complete a file name, again to cycle through matches"),
+        Binding::new(Shell, Key::new(KeyCode::Esc), Back, "cancel the pending request, or stop Shell interaction"),
+        Binding::new(Response, Key::new(KeyCode::Up), ScrollResponse(1), "scroll up"),
+        Binding::new(Response, Key::new(KeyCode::Down), ScrollResponse(-1), "scroll down"),
+        Binding::new(Response, Key::new(KeyCode::PageUp), ScrollResponse(10), "scroll a page up"),
+        Binding::new(Response, Key::new(KeyCode::PageDown), ScrollResponse(-10), "scroll a page down"),
+        Binding::new(Response, Key::new(KeyCode::Esc), Back, "cancel the pending request, or stop reading responses"),
+        Binding::new(Confirm, Key::char('y'), ConfirmRun, "run the command"),
+        Binding::new(Confirm, Key::char('Y'), ConfirmRun, "run the command"),
+        Binding::new(Confirm, Key::char('n'), ConfirmCancel, "go back and edit the command"),
+        Binding::new(Confirm, Key::char('N'), ConfirmCancel, "go back and edit the command"),
+        Binding::new(Confirm, Key::new(KeyCode::Esc), ConfirmCancel, "go back and edit the command"),
+        Binding::new(Confirm, Key::char('s'), ConfirmAlternative, "run with `sudo -n` instead of typing the password, when sudo asks for it"),
+        Binding::new(Confirm, Key::char('q'), Quit, "quit, when asked whether to quit"),
+        Binding::new(Models, Key::new(KeyCode::Up), SelectModel(-1), "select previous model"),
+        Binding::new(Models, Key::new(KeyCode::Down), SelectModel(1), "select next model"),
+        Binding::new(Models, Key::new(KeyCode::Enter), UseModel, "use the model"),
+        Binding::new(Models, Key::ctrl('s'), SaveModel, "use the model and save it to the config"),
+        Binding::new(Models, Key::new(KeyCode::Esc), CloseModelPicker, "cancel"),
+        Binding::new(Compare, Key::new(KeyCode::Up), SelectAnswer(-1), "select the previous answer"),
+        Binding::new(Compare, Key::new(KeyCode::Down), SelectAnswer(1), "select the next answer"),
+        Binding::new(Compare, Key::new(KeyCode::Enter), QueueAnswer, "queue the commands of the selected answer"),
+        Binding::new(Compare, Key::new(KeyCode::Esc), CloseComparison, "keep the queue as it is"),
+        Binding::new(FavoriteName, Key::new(KeyCode::Enter), NameFavorite, "save the favorite, asking before replacing one of that name"),
+        Binding::new(FavoriteName, Key::new(KeyCode::Esc), CloseFavorites, "cancel"),
+        Binding::new(Favorites, Key::new(KeyCode::Up), SelectFavorite(-1), "select previous favorite"),
+        Binding::new(Favorites, Key::new(KeyCode::Down), SelectFavorite(1), "select next favorite"),
+        Binding::new(Favorites, Key::new(KeyCode::Enter), InsertFavorite, "insert the command into the Shell box"),
+        Binding::new(Favorites, Key::new(KeyCode::Delete), RemoveFavorite, "remove the favorite"),
+        Binding::new(Favorites, Key::new(KeyCode::Esc), CloseFavorites, "close"),
+        Binding::new(Params, Key::new(KeyCode::Enter), SetParam, "keep the value, after the last one change the queued commands"),
+        Binding::new(Params, Key::new(KeyCode::Esc), CloseParams, "keep the commands as they are"),
+        Binding::new(Help, Key::new(KeyCode::Up), ScrollHelp(-1), "scroll up"),
+        Binding::new(Help, Key::new(KeyCode::Down), ScrollHelp(1), "scroll down"),
+        Binding::new(Help, Key::new(KeyCode::PageUp), ScrollHelp(-10), "scroll a page up"),
+        Binding::new(Help, Key::new(KeyCode::PageDown), ScrollHelp(10), "scroll a page down"),
+        Binding::new(Help, Key::char('?'), ToggleHelp, "close this help"),
+        Binding::new(Help, Key::new(KeyCode::Esc), ToggleHelp, "close this help"),
+    ]
+}
+
+/// Vim-style keys in front of the built-in ones, which stay available
+pub fn vim_bindings() -> Vec<Binding> {
+    use Action::*;
+    use EditMode::*;
+
+    let (gg, quit, write) = ([Key::char('g'); 2], [Key::char(':'), Key::char('q')], [Key::char(':'), Key::char('w')]);
+    let mut bindings = vec![
+        Binding::new(Normal, Key::char('i'), AskAi, "ask AI"),
+        Binding::new(Normal, Key::char('j'), ScrollOutput(-1), "scroll output down"),
+        Binding::new(Normal, Key::char('k'), ScrollOutput(1), "scroll output up"),
+        Binding::new(Normal, Key::ctrl('d'), ScrollOutput(-10), "scroll output a page down"),
+        Binding::new(Normal, Key::ctrl('u'), ScrollOutput(10), "scroll output a page up"),
+        Binding::sequence(Normal, &gg, ScrollOutput(FULL), "scroll to oldest output"),
+        Binding::new(Normal, Key::char('G'), ScrollOutput(-FULL), "scroll to newest output"),
+        Binding::sequence(Normal, &quit, Quit, "quit"),
+        Binding::sequence(Normal, &write, ExportSession, "export the session transcript"),
+        Binding::new(Output, Key::char('j'), SelectOutput(1), "select next entry"),
+        Binding::new(Output, Key::char('k'), SelectOutput(-1), "select previous entry"),
+        Binding::new(Output, Key::ctrl('d'), ScrollOutput(-10), "scroll output down"),
+        Binding::new(Output, Key::ctrl('u'), ScrollOutput(10), "scroll output up"),
+        Binding::new(Response, Key::char('j'), ScrollResponse(-1), "scroll down"),
+        Binding::new(Response, Key::char('k'), ScrollResponse(1), "scroll up"),
+        Binding::new(Response, Key::ctrl('d'), ScrollResponse(-10), "scroll a page down"),
+        Binding::new(Response, Key::ctrl('u'), ScrollResponse(10), "scroll a page up"),
+        Binding::sequence(Response, &gg, ScrollResponse(FULL), "scroll to the first response"),
+        Binding::new(Response, Key::char('G'), ScrollResponse(-FULL), "scroll to the last response"),
+        Binding::new(Help, Key::char('j'), ScrollHelp(1), "scroll down"),
+        Binding::new(Help, Key::char('k'), ScrollHelp(-1), "scroll up"),
+        Binding::new(Help, Key::ctrl('d'), ScrollHelp(10), "scroll a page down"),
+        Binding::new(Help, Key::ctrl('u'), ScrollHelp(-10), "scroll a page up"),
+        Binding::sequence(Help, &gg, ScrollHelp(-FULL), "scroll to the top"),
+        Binding::new(Help, Key::char('G'), ScrollHelp(FULL), "scroll to the bottom"),
+    ];
+    bindings.extend(default_bindings());
+    bindings
+}
+
+/// Keybinding table called `name`, one of `KEYMAPS`
+pub fn by_name(name: &str) -> Option<Vec<Binding>> {
+    match name {
+        "default" => Some(default_bindings()),
+        "vim" => Some(vim_bindings()),
+        _ => None,
+    }
+}
+
+/// `bindings` with the keys of the actions in `overrides`, named as in `ACTION_NAMES`,
+/// replaced. The new keys go to every mode the action has keys in, or would have with the
+/// vim keymap, taking the place and description of the old ones. Other actions keep their
+/// keys, so the result is checked for keys bound to two actions of a mode, and for plain
+/// characters taken from a text box.
+pub fn with_overrides(bindings: &[Binding], overrides: &BTreeMap<String, KeySpecs>) -> Result<Vec<Binding>, Vec<KeymapError>> {
+    let vim = vim_bindings();
+    // the bindings, with the config entry of those that come from one
+    let mut table: Vec<(Binding, Option<(&str, &str)>)> = bindings.iter().map(|binding| (binding.clone(), None)).collect();
+    let mut errors = Vec::new();
+    for (name, specs) in overrides {
+        let Some(action) = action_by_name(name) else {
+            errors.push(KeymapError::UnknownAction { action: name.clone(), spec: specs.specs().join(", ") });
+            continue;
+        };
+        let mut keys = Vec::new();
+        for spec in specs.specs() {
+            match parse_keys(spec) {
+                Ok(parsed) => keys.push((spec.as_str(), parsed)),
+                Err(reason) => errors.push(KeymapError::InvalidKey { action: name.clone(), spec: spec.clone(), reason }),
+            }
+        }
+        if keys.len() < specs.specs().len() {
+            continue;
+        }
+
+        let mut modes: Vec<(EditMode, &'static str)> = Vec::new();
+        for binding in bindings.iter().chain(&vim).filter(|binding| binding.action == action) {
+            if !modes.iter().any(|(mode, _)| *mode == binding.mode) {
+                modes.push((binding.mode, binding.description));
+            }
+        }
+        let bind = |(mode, description): (EditMode, &'static str)| {
+            keys.iter().map(move |(spec, keys)| (Binding::sequence(mode, keys, action, description), Some((name.as_str(), *spec))))
+        };
+        let mut replaced = Vec::with_capacity(table.len());
+        for (binding, origin) in table {
+            if binding.action != action {
+                replaced.push((binding, origin));
+            } else if let Some(i) = modes.iter().position(|(mode, _)| *mode == binding.mode) {
+                replaced.extend(bind(modes.remove(i)));
+            }
+        }
+        // modes only the vim keymap has keys for
+        replaced.extend(modes.into_iter().flat_map(bind));
+        table = replaced;
+    }
+
+    for (binding, origin) in &table {
+        let Some((name, spec)) = origin else {
+            continue;
+        };
+        let mode = mode_title(binding.mode);
+        let typed = matches!(binding.keys[0].code, KeyCode::Char(_))
+            && !binding.keys[0].modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+        if typed && TEXT_MODES.contains(&binding.mode) {
+            errors.push(KeymapError::TypingKey { action: name.to_string(), spec: spec.to_string(), mode });
+        }
+        for (other, _) in &table {
+            let overlap = other.keys.starts_with(&binding.keys) || binding.keys.starts_with(&other.keys);
+            if other.mode == binding.mode && other.action != binding.action && overlap {
+                let other = action_name(other.action).unwrap_or("?");
+                errors.push(KeymapError::Conflict { action: name.to_string(), spec: spec.to_string(), mode, other });
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(table.into_iter().map(|(binding, _)| binding).collect())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Heading of `mode` in the help overlay
+fn mode_title(mode: EditMode) -> &'static str {
+    MODES.iter().find(|(titled, _)| *titled == mode).map_or("", |(_, title)| title)
+}
+
+/// What the keys pressed so far in `mode` mean. A complete binding wins over a longer one
+/// starting with the same keys.
+pub fn lookup_keys(bindings: &[Binding], mode: EditMode, events: &[KeyEvent]) -> Lookup {
+    let mut prefix = false;
+    for binding in bindings.iter().filter(|binding| binding.mode == mode && binding.keys.len() >= events.len()) {
+        if binding.keys.iter().zip(events).all(|(key, event)| key.matches(event)) {
+            if binding.keys.len() == events.len() {
+                return Lookup::Action(binding.action);
+            }
+            prefix = true;
+        }
+    }
+    if prefix {
+        Lookup::Prefix
+    } else {
+        Lookup::Unbound
+    }
+}
+
+/// Action bound to the single key `event` in `mode`
+pub fn lookup(bindings: &[Binding], mode: EditMode, event: &KeyEvent) -> Option<Action> {
+    match lookup_keys(bindings, mode, std::slice::from_ref(event)) {
+        Lookup::Action(action) => Some(action),
+        Lookup::Prefix | Lookup::Unbound => None,
+    }
+}
+
+/// Help overlay content: every binding grouped by mode.
+/// Keys of the same mode and action are merged into one row, e.g. `y/Y`.
+pub fn help_text(bindings: &[Binding]) -> Text<'static> {
+    let mut lines = Vec::new();
+    for (mode, title) in MODES {
+        let mut rows: Vec<(String, &str)> = Vec::new();
+        let mut last_action = None;
+        for binding in bindings.iter().filter(|binding| binding.mode == mode) {
+            match rows.last_mut() {
+                Some((keys, _)) if last_action == Some(binding.action) => {
+                    keys.push('/');
+                    keys.push_str(&binding.label());
+                },
+                _ => rows.push((binding.label(), binding.description)),
+            }
+            last_action = Some(binding.action);
+        }
+        if rows.is_empty() {
+            continue;
+        }
+
+        if !lines.is_empty() {
+            lines.push(Line::raw(""));
+        }
+        lines.push(Line::styled(title, Style::default().add_modifier(Modifier::UNDERLINED)));
+        let key_width = rows.iter().map(|(keys, _)| keys.chars().count()).max().unwrap_or(0);
+        for (keys, description) in rows {
+            lines.push(Line::from(vec![
+                Span::styled(format!("  {:<width$}", keys, width = key_width), Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw(format!("  {}", description)),
+            ]));
+        }
+        if let Some((_, note)) = MODE_NOTES.iter().find(|(note_mode, _)| *note_mode == mode) {
+            lines.push(Line::styled(format!("  {}", note), Style::default().add_modifier(Modifier::ITALIC)));
+        }
+    }
+    Text::from(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn lookup_by_mode() {
+        let bindings = default_bindings();
+        let up = press(KeyCode::Up, KeyModifiers::NONE);
+        assert_eq!(lookup(&bindings, EditMode::Normal, &up), Some(Action::SelectPrev));
+        assert_eq!(lookup(&bindings, EditMode::Input, &up), Some(Action::PrevPrompt));
+        assert_eq!(lookup(&bindings, EditMode::Input, &press(KeyCode::Char('q'), KeyModifiers::NONE)), None);
+    }
+
+    #[test]
+    fn modifiers() {
+        let bindings = default_bindings();
+        let ctrl_s = press(KeyCode::Char('s'), KeyModifiers::CONTROL);
+        assert_eq!(lookup(&bindings, EditMode::Shell, &ctrl_s), Some(Action::SkipSelected));
+        assert_eq!(lookup(&bindings, EditMode::Normal, &ctrl_s), None);
+        // terminals report shifted characters with the shift modifier set
+        let shift_k = press(KeyCode::Char('K'), KeyModifiers::SHIFT);
+        assert_eq!(lookup(&bindings, EditMode::Normal, &shift_k), Some(Action::MoveSelectedUp));
+        let question = press(KeyCode::Char('?'), KeyModifiers::SHIFT);
+        assert_eq!(lookup(&bindings, EditMode::Normal, &question), Some(Action::ToggleHelp));
+        // but not for other keys
+        let shift_enter = press(KeyCode::Enter, KeyModifiers::SHIFT);
+        assert_eq!(lookup(&bindings, EditMode::Input, &shift_enter), Some(Action::NewLine));
+        assert_eq!(lookup(&bindings, EditMode::Shell, &shift_enter), None);
+    }
+
+    #[test]
+    fn vim_sequences() {
+        let bindings = vim_bindings();
+        let key = |c| press(KeyCode::Char(c), KeyModifiers::NONE);
+        assert_eq!(lookup_keys(&bindings, EditMode::Normal, &[key('g')]), Lookup::Prefix);
+        assert_eq!(lookup_keys(&bindings, EditMode::Normal, &[key('g'), key('g')]), Lookup::Action(Action::ScrollOutput(u16::MAX as i32)));
+        assert_eq!(lookup_keys(&bindings, EditMode::Normal, &[key(':'), key('q')]), Lookup::Action(Action::Quit));
+        assert_eq!(lookup_keys(&bindings, EditMode::Normal, &[key('g'), key('x')]), Lookup::Unbound);
+        // the built-in keys keep working
+        assert_eq!(lookup(&bindings, EditMode::Normal, &key('q')), Some(Action::Quit));
+        assert_eq!(lookup(&bindings, EditMode::Response, &key('j')), Some(Action::ScrollResponse(-1)));
+        // and typing is not affected
+        assert_eq!(lookup(&bindings, EditMode::Input, &key('j')), None);
+        assert_eq!(lookup(&default_bindings(), EditMode::Normal, &key('j')), None);
+
+        let help: Vec<String> = help_text(&bindings)
+            .lines
+            .iter()
+            .map(|line| line.spans.iter().map(|span| span.content.as_ref()).collect())
+            .collect();
+        assert!(help.iter().any(|line| line.starts_with("  gg") && line.ends_with("scroll to oldest output")));
+    }
+
+    #[test]
+    fn key_specs() {
+        let key = |spec: &str| spec.parse::<Key>();
+        let with = |code, modifiers| Ok(Key::with(code, modifiers));
+        assert_eq!(key("q"), Ok(Key::char('q')));
+        assert_eq!(key("Q"), Ok(Key::char('Q')));
+        assert_eq!(key(" ? "), Ok(Key::char('?')));
+        assert_eq!(key("ctrl+a"), Ok(Key::ctrl('a')));
+        assert_eq!(key("Ctrl-S"), Ok(Key::ctrl('s')));
+        assert_eq!(key("CONTROL+A"), Ok(Key::ctrl('a')));
+        assert_eq!(key("alt+enter"), with(KeyCode::Enter, KeyModifiers::ALT));
+        assert_eq!(key("meta-x"), with(KeyCode::Char('x'), KeyModifiers::ALT));
+        assert_eq!(key("ctrl+alt+delete"), with(KeyCode::Delete, KeyModifiers::CONTROL | KeyModifiers::ALT));
+        assert_eq!(key("shift+enter"), with(KeyCode::Enter, KeyModifiers::SHIFT));
+        // the modifiers and key names are case-insensitive, single characters are not
+        assert_eq!(key("PgUp"), Ok(Key::new(KeyCode::PageUp)));
+        assert_eq!(key("pagedown"), Ok(Key::new(KeyCode::PageDown)));
+        assert_eq!(key("ESCAPE"), Ok(Key::new(KeyCode::Esc)));
+        assert_eq!(key("space"), Ok(Key::char(' ')));
+        assert_eq!(key("ctrl+space"), Ok(Key::ctrl(' ')));
+        // function keys
+        assert_eq!(key("F2"), Ok(Key::new(KeyCode::F(2))));
+        assert_eq!(key("f12"), Ok(Key::new(KeyCode::F(12))));
+        assert_eq!(key("alt+F24"), with(KeyCode::F(24), KeyModifiers::ALT));
+        assert_eq!(key("F0"), Err(KeySpecError::UnknownKey("F0".to_string())));
+        assert_eq!(key("F25"), Err(KeySpecError::UnknownKey("F25".to_string())));
+        assert_eq!(key("f+1"), Err(KeySpecError::UnknownKey("f+1".to_string())));
+        // `+` and `-` are keys too
+        assert_eq!(key("+"), Ok(Key::char('+')));
+        assert_eq!(key("-"), Ok(Key::char('-')));
+        assert_eq!(key("ctrl++"), Ok(Key::ctrl('+')));
+        assert_eq!(key("alt--"), with(KeyCode::Char('-'), KeyModifiers::ALT));
+        // shift turns letters into capitals, as terminals report them
+        assert_eq!(key("shift+k"), Ok(Key::char('K')));
+        assert_eq!(key("ctrl+shift+k"), Ok(Key::ctrl('K')));
+        assert_eq!(key("ctrl+K"), Ok(Key::ctrl('k')));
+        assert_eq!(key("shift+1"), Err(KeySpecError::ShiftedChar('1')));
+        assert_eq!(key("shift+tab"), with(KeyCode::BackTab, KeyModifiers::SHIFT));
+        assert_eq!(key("backtab"), with(KeyCode::BackTab, KeyModifiers::SHIFT));
+        // mistakes
+        assert_eq!(key(""), Err(KeySpecError::Empty));
+        assert_eq!(key("ctrl+"), Err(KeySpecError::Empty));
+        assert_eq!(key("hyper+a"), Err(KeySpecError::UnknownKey("hyper+a".to_string())));
+        assert_eq!(key("ctrl+enterr"), Err(KeySpecError::UnknownKey("enterr".to_string())));
+        assert!(KeySpecError::UnknownKey("x".to_string()).to_string().contains("F1 to F24 and: enter, esc"));
+
+        assert_eq!(parse_keys("g g"), Ok(vec![Key::char('g'); 2]));
+        assert_eq!(parse_keys(" ctrl+x  s "), Ok(vec![Key::ctrl('x'), Key::char('s')]));
+        assert_eq!(parse_keys("  "), Err(KeySpecError::Empty));
+        assert_eq!(parse_keys("g nope"), Err(KeySpecError::UnknownKey("nope".to_string())));
+    }
+
+    #[test]
+    fn key_labels() {
+        // every label parses back to its key
+        for binding in vim_bindings() {
+            for key in &binding.keys {
+                assert_eq!(key.label().replace('↑', "up").replace('↓', "down").parse::<Key>(), Ok(*key), "{}", key.label());
+            }
+        }
+        assert_eq!(Key::new(KeyCode::F(5)).label(), "F5");
+        assert_eq!(Key::char(' ').label(), "Space");
+        assert_eq!(Key::with(KeyCode::BackTab, KeyModifiers::SHIFT).label(), "Shift-Tab");
+        assert_eq!(Key::with(KeyCode::Char('x'), KeyModifiers::CONTROL | KeyModifiers::ALT).label(), "Ctrl-Alt-X");
+        assert_eq!(Binding::sequence(EditMode::Normal, &[Key::ctrl('x'), Key::char('s')], Action::Quit, "quit").label(), "Ctrl-X s");
+    }
+
+    #[test]
+    fn action_names() {
+        for (name, action) in ACTION_NAMES {
+            assert_eq!(action_by_name(name), Some(*action));
+            assert_eq!(action_name(*action), Some(*name), "{} is named twice", name);
+            assert!(vim_bindings().iter().any(|binding| binding.action == *action), "{} has no keys", name);
+        }
+        for binding in vim_bindings() {
+            assert!(action_name(binding.action).is_some(), "{:?} has no name", binding.action);
+        }
+        assert_eq!(action_by_name("fly"), None);
+    }
+
+    #[test]
+    fn built_in_keys_do_not_conflict() {
+        for bindings in [default_bindings(), vim_bindings()] {
+            for (i, binding) in bindings.iter().enumerate() {
+                for other in &bindings[i + 1..] {
+                    let overlap = other.keys.starts_with(&binding.keys) || binding.keys.starts_with(&other.keys);
+                    assert!(other.mode != binding.mode || other.action == binding.action || !overlap, "{:?} {:?}", binding, other);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn overrides() {
+        let specs = |entries: &[(&str, &[&str])]| -> BTreeMap<String, KeySpecs> {
+            entries
+                .iter()
+                .map(|(name, specs)| match specs {
+                    [spec] => (name.to_string(), KeySpecs::One(spec.to_string())),
+                    specs => (name.to_string(), KeySpecs::Many(specs.iter().map(ToString::to_string).collect())),
+                })
+                .collect()
+        };
+        let key = |code, modifiers| press(code, modifiers);
+        let plain = |c| key(KeyCode::Char(c), KeyModifiers::NONE);
+
+        // `back` has keys in several modes, all of which change
+        let bindings = with_overrides(&default_bindings(), &specs(&[("quit", &["ctrl+q"]), ("back", &["F10", "ctrl+c"])])).unwrap();
+        assert_eq!(lookup(&bindings, EditMode::Normal, &key(KeyCode::Char('q'), KeyModifiers::CONTROL)), Some(Action::Quit));
+        assert_eq!(lookup(&bindings, EditMode::Confirm, &key(KeyCode::Char('q'), KeyModifiers::CONTROL)), Some(Action::Quit));
+        assert_eq!(lookup(&bindings, EditMode::Normal, &plain('q')), None);
+        for mode in [EditMode::Normal, EditMode::Input, EditMode::Shell, EditMode::Response, EditMode::Output] {
+            assert_eq!(lookup(&bindings, mode, &key(KeyCode::F(10), KeyModifiers::NONE)), Some(Action::Back));
+            assert_eq!(lookup(&bindings, mode, &key(KeyCode::Char('c'), KeyModifiers::CONTROL)), Some(Action::Back));
+            assert_eq!(lookup(&bindings, mode, &key(KeyCode::Esc, KeyModifiers::NONE)), None);
+        }
+        // the other keys stay as they are
+        assert_eq!(lookup(&bindings, EditMode::Normal, &plain('a')), Some(Action::AskAi));
+        assert_eq!(bindings.len(), default_bindings().len() + 5);
+
+        // actions only the vim keymap has keys for, a sequence, and an action without keys
+        let bindings = with_overrides(&default_bindings(), &specs(&[("scroll_output_up", &["ctrl+y"]), ("scroll_help_top", &["g g"]), ("discard_queue", &[])])).unwrap();
+        assert_eq!(lookup(&bindings, EditMode::Normal, &key(KeyCode::Char('y'), KeyModifiers::CONTROL)), Some(Action::ScrollOutput(1)));
+        assert_eq!(lookup_keys(&bindings, EditMode::Help, &[plain('g'), plain('g')]), Lookup::Action(Action::ScrollHelp(-FULL)));
+        assert!(!bindings.iter().any(|binding| binding.action == Action::DiscardQueue));
+
+        // keys of the vim keymap are replaced too
+        let bindings = with_overrides(&vim_bindings(), &specs(&[("quit", &["Z Q"])])).unwrap();
+        assert_eq!(lookup_keys(&bindings, EditMode::Normal, &[plain(':'), plain('q')]), Lookup::Unbound);
+        assert_eq!(lookup_keys(&bindings, EditMode::Normal, &[plain('Z'), plain('Q')]), Lookup::Action(Action::Quit));
+
+        // the help overlay shows the new keys
+        let bindings = with_overrides(&default_bindings(), &specs(&[("toggle_help", &["F1"]), ("send_prompt", &["ctrl+enter"])])).unwrap();
+        let help: Vec<String> = help_text(&bindings)
+            .lines
+            .iter()
+            .map(|line| line.spans.iter().map(|span| span.content.as_ref()).collect())
+            .collect();
+        assert!(help.iter().any(|line| line.starts_with("  F1 ") && line.ends_with("show this help")), "{:?}", help);
+        assert!(help.iter().any(|line| line.starts_with("  Ctrl-Enter ") && line.ends_with("send the prompt")));
+        assert!(!help.iter().any(|line| line.starts_with("  ? ")));
+    }
+
+    #[test]
+    fn override_problems() {
+        let one = |entries: &[(&str, &str)]| -> BTreeMap<String, KeySpecs> {
+            entries.iter().map(|(name, spec)| (name.to_string(), KeySpecs::One(spec.to_string()))).collect()
+        };
+        let errors = |entries: &[(&str, &str)]| with_overrides(&default_bindings(), &one(entries)).unwrap_err();
+
+        assert_eq!(errors(&[("fly", "f")]), [KeymapError::UnknownAction { action: "fly".to_string(), spec: "f".to_string() }]);
+        assert_eq!(
+            errors(&[("quit", "ctrl+")]),
+            [KeymapError::InvalidKey { action: "quit".to_string(), spec: "ctrl+".to_string(), reason: KeySpecError::Empty }],
+        );
+        // `a` asks AI in Normal mode
+        let conflict = KeymapError::Conflict { action: "quit".to_string(), spec: "a".to_string(), mode: "Normal", other: "ask_ai" };
+        assert_eq!(errors(&[("quit", "a")]), [conflict]);
+        assert_eq!(errors(&[("quit", "a")])[0].to_string(), "conflicts with `ask_ai` among the Normal keys");
+        // unless that moves too
+        assert!(with_overrides(&default_bindings(), &one(&[("quit", "a"), ("ask_ai", "i")])).is_ok());
+        // a sequence starting with a bound key would never be reached, and the other way round
+        assert!(matches!(&errors(&[("export_session", "y y")])[..], [KeymapError::Conflict { other: "copy_output", .. }]));
+        let prefix = with_overrides(&vim_bindings(), &one(&[("ask_ai", "g")])).unwrap_err();
+        assert!(matches!(&prefix[..], [KeymapError::Conflict { other: "scroll_output_top", .. }]), "{:?}", prefix);
+        // two overrides sharing a key are both reported
+        assert_eq!(errors(&[("ask_ai", "F2"), ("edit_shell", "F2")]).len(), 2);
+
+        // plain characters would stop being typed into the Asking AI box
+        let typing = errors(&[("send_prompt", "s")]);
+        assert_eq!(typing, [KeymapError::TypingKey { action: "send_prompt".to_string(), spec: "s".to_string(), mode: "Asking AI" }]);
+        assert!(with_overrides(&default_bindings(), &one(&[("send_prompt", "alt+s")])).is_ok());
+        assert!(matches!(&errors(&[("execute_command", "shift+x")])[..], [KeymapError::TypingKey { mode: "Shell", .. }]));
+    }
+
+    #[test]
+    fn help_lists_every_mode() {
+        let bindings = default_bindings();
+        let text: Vec<String> = help_text(&bindings)
+            .lines
+            .iter()
+            .map(|line| line.spans.iter().map(|span| span.content.as_ref()).collect())
+            .collect();
+
+        for (_, title) in MODES {
+            assert!(text.iter().any(|line| line == title), "missing {}", title);
+        }
+        assert!(text.iter().any(|line| line.starts_with("  y/Y") && line.ends_with("run the command")));
+        assert!(text.iter().any(|line| line.contains("Ctrl-S") && line.ends_with("skip the command")));
+        assert!(text.iter().any(|line| line.contains("queues the others")));
+    }
+}
@@ -1,5 +1,136 @@
-pub mod frontend;
-pub mod backend;
-pub mod shared;
-mod shell;
-mod error;
\ No newline at end of file
+//! Shell commands from a local Ollama model, as a TUI (`aurish`), a CLI (`aurish-cli`) and
+//! this library
+//!
+//! Embedding tools mostly need `generate_commands` (or `generate_commands_blocking`) and
+//! `execute`, with a `Config` from `config::load_config`:
+//!
+//! ```no_run
+//! use aurish::shell::IShell;
+//!
+//! # #[cfg(feature = "backend-blocking")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let (config, _) = aurish::config::load_config(None, false)?;
+//! let shell = IShell::new();
+//! for suggestion in aurish::generate_commands_blocking(&config, "show the largest files")? {
+//!     if suggestion.danger.is_none() {
+//!         let output = aurish::execute(&shell, &suggestion.command);
+//!         print!("{}", output.stdout_str());
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "backend-blocking"))]
+//! # fn main() {}
+//! ```
+//!
+//! Features, all on by default, keep what an embedding tool does not use out of its build:
+//!
+//! - `backend-async`: the Ollama client `backend::Bclient` and `generate_commands`
+//! - `backend-blocking`: `backend::BKclient` and `generate_commands_blocking`, without an
+//!   async runtime of your own
+//! - `tui`: the TUI of `aurish`, `shared::App`
+//! - `cli`: the readline-based `frontend` of `aurish-cli`
+//! - `clap`: the command line arguments the binaries share, like `config::Overrides`
+//! - `clipboard`: copying in the TUI
+//! - `watch`: `aurish-cli ask --watch`, asking again whenever the prompt file changes
+//!
+//! `Config`, `IShell` and the modules around them are always there, e.g. with
+//! `default-features = false, features = ["backend-blocking"]`.
+
+#[cfg(feature = "cli")]
+pub mod frontend;
+#[cfg(feature = "backend-async")]
+pub mod backend;
+pub mod shared;
+pub mod shell;
+pub mod shell_history;
+pub mod interactive;
+pub mod error;
+pub mod ansi;
+pub mod utils;
+pub mod history;
+pub mod risk;
+#[cfg(feature = "tui")]
+pub mod keymap;
+pub mod clipboard;
+pub mod theme;
+pub mod terminal_caps;
+#[cfg(feature = "tui")]
+pub mod editor;
+#[cfg(feature = "tui")]
+pub mod pager;
+pub mod session;
+pub mod transcripts;
+pub mod suggestions;
+pub mod report;
+pub mod queue;
+pub mod autofix;
+pub mod queue_file;
+pub mod favorites;
+pub mod templates;
+pub mod complete;
+pub mod search;
+pub mod diff;
+pub mod config;
+pub mod logging;
+#[cfg(feature = "backend-blocking")]
+pub mod bench;
+pub mod script;
+pub mod system_info;
+pub mod workdir;
+pub mod project_context;
+pub mod i18n;
+#[cfg(feature = "watch")]
+pub mod watch;
+#[cfg(feature = "cli")]
+pub mod output;
+pub mod api;
+
+pub use api::{execute, SuggestedCommand};
+#[cfg(feature = "backend-async")]
+pub use api::generate_commands;
+#[cfg(feature = "backend-blocking")]
+pub use api::generate_commands_blocking;
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    fn manifest() -> toml::Table {
+        toml::from_str(include_str!("../Cargo.toml")).unwrap()
+    }
+
+    #[test]
+    fn frontend_dependencies_are_optional() {
+        let manifest = manifest();
+        let dependencies = manifest["dependencies"].as_table().unwrap();
+        for name in ["ratatui", "crossterm", "tui-input", "rustyline", "clap", "reqwest", "tokio"] {
+            assert_eq!(dependencies[name].get("optional").and_then(|optional| optional.as_bool()), Some(true), "{}", name);
+        }
+
+        let default = manifest["features"]["default"].as_array().unwrap();
+        assert!(default.iter().any(|feature| feature.as_str() == Some("tui")));
+        assert!(default.iter().any(|feature| feature.as_str() == Some("cli")));
+
+        let bins = manifest["bin"].as_array().unwrap();
+        for (name, feature) in [("aurish", "tui"), ("aurish-cli", "cli")] {
+            let bin = bins.iter().find(|bin| bin["name"].as_str() == Some(name)).unwrap();
+            assert_eq!(bin["required-features"].as_array().unwrap(), &[toml::Value::from(feature)], "{}", name);
+        }
+    }
+
+    #[test]
+    #[ignore = "runs cargo check once per feature set, run with --ignored"]
+    fn feature_sets_build() {
+        let target_dir = std::env::temp_dir().join("aurish-feature-sets");
+        for features in ["", "backend-async", "backend-blocking", "tui", "cli"] {
+            let status = Command::new(env!("CARGO"))
+                .args(["check", "--offline", "--lib", "--no-default-features", "--features", features])
+                .env("CARGO_TARGET_DIR", &target_dir)
+                .current_dir(env!("CARGO_MANIFEST_DIR"))
+                .status()
+                .unwrap();
+            assert!(status.success(), "--features {:?}", features);
+        }
+    }
+}
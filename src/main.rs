@@ -1,39 +1,155 @@
-use aurish::{shared::{App, Config}, backend::{OllamaReq, Bclient, ClientInit}};
-use tokio;
-use std::{fs, io};
-use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+use aurish::{shared::{install_panic_hook, shutdown_on_signals, App, ConfigReload}, backend::{self, Bclient}, config::{self, load_config, Config, Overrides}, logging::{self, LogArgs}, theme::Theme};
+use aurish::error::{AurishError, ConfigError};
+use aurish::terminal_caps::TerminalCaps;
+use aurish::i18n::Language;
+use clap::Parser;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::Instant;
+use tokio::sync::mpsc;
 use crossterm::execute;
+use crossterm::event::{DisableBracketedPaste, EnableBracketedPaste};
+use crossterm::style::Print;
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
 use ratatui::backend::CrosstermBackend;
-use ratatui::Terminal;
-use serde::de::Error;
+use ratatui::{DefaultTerminal, Terminal};
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Config file to use, instead of $AURISH_CONFIG or the user config directory
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    #[command(flatten)]
+    overrides: Overrides,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
 
 #[tokio::main]
-async fn main() -> io::Result<()> {
-    // setup terminal
-    enable_raw_mode()?;
-    // execute!(EnterAlternateScreen, EnableMouseCapture)?;
-    let mut terminal = ratatui::init();
+async fn main() -> ExitCode {
+    match run(Args::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // the terminal is restored by now, so this is readable
+            log::error!("{}", e);
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        },
+    }
+}
 
-    // create app from config file and run it
-    let config = get_config().unwrap();
-    let mut app = App::new(config.get_model());
-    let client = if config.uses_proxy() {
-        Bclient::new_with_proxy(config.get_ollama_api(), config.get_proxy())
-    } else { Bclient::new(config.get_ollama_api()) };
-    let res = app.run(&mut terminal, client);
+async fn run(args: Args) -> Result<(), AurishError> {
+    let started = Instant::now();
+    let caps = TerminalCaps::detect();
+    if let Some(problem) = caps.tui_problem() {
+        return Err(problem.into());
+    }
+    // never to the terminal, the TUI is drawn there
+    logging::init(&args.log, logging::default_file())?;
+    // create app from config file, before touching the terminal
+    let (mut config, config_path) = load_config(args.config.as_deref(), false)?;
+    for field in config.unknown_fields() {
+        log::warn!("unknown field `{}` in {}, ignored", field, config_path.display());
+        eprintln!("warning: unknown field `{}` in {}, ignored", field, config_path.display());
+    }
+    if let Some(code) = config.unknown_language() {
+        log::warn!("unknown language `{}` in {}, using English", code, config_path.display());
+        eprintln!("warning: unknown language `{}` in {}, using English; known languages are {}", code, config_path.display(), Language::codes());
+    }
+    if let Err(problems) = config.validate() {
+        return Err(ConfigError::Problems { path: config_path, problems }.into());
+    }
+    args.overrides.apply(&mut config)?;
+    log::info!("config {}: model={} api={}", config_path.display(), config.get_model(), config.get_ollama_api());
+    log::debug!("{:?}", config);
+    log::trace!("config loaded {:?} after start", started.elapsed());
+    // both were checked by `validate`
+    let theme = theme_for(&config, &caps);
+    // the TUI takes up changes to the file, e.g. by `aurish-cli --set-model`, while it runs
+    let (reload_tx, reload_rx) = mpsc::unbounded_channel();
+    let (overrides, path, caps) = (args.overrides.clone(), config_path.clone(), caps.clone());
+    let _watch = Config::watch(&config_path, config::RELOAD_INTERVAL, move |loaded| {
+        let reload = loaded
+            .and_then(|config| match config.validate() {
+                Ok(()) => Ok(config),
+                Err(problems) => Err(ConfigError::Problems { path: path.clone(), problems }),
+            })
+            .and_then(|mut config| {
+                overrides.apply(&mut config)?;
+                let theme = theme_for(&config, &caps);
+                Ok(ConfigReload { config, theme })
+            });
+        let _ = reload_tx.send(reload);
+    });
+    // SIGTERM and SIGHUP quit like `q`, so the terminal is restored and jobs are stopped
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let mut app = App::new(config.get_model())
+        .with_theme(theme)
+        .with_keymap(config.keybinding_table())
+        .with_dangerous_patterns(config.get_dangerous_patterns().to_vec())
+        .with_compare_model(config.get_compare_model().map(String::from))
+        .with_system_info(!config.skips_system_info())
+        .with_language(config.get_language())
+        .with_context_level(config.get_context_level())
+        .with_suggestion_mode(config.get_suggestion_mode())
+        .with_multiline_commands(config.get_multiline_commands())
+        .with_shell_type(config.get_shell_override())
+        .with_shell_history(config.appends_to_shell_history())
+        .with_inherited_locale(config.inherits_locale())
+        .with_sandbox_root(config.get_sandbox_root())?
+        .with_welcome((!config.hides_welcome()).then(|| config.get_ollama_api().to_string()))
+        .with_max_prompt_tokens(config.get_max_prompt_tokens())
+        .with_auto_fix(config.auto_fixes(), config.get_auto_fix_attempts())
+        .with_config_path(config_path)
+        .with_config_reloads(reload_rx)
+        .with_session_export(config.get_session_dir().map(PathBuf::from), config.exports_on_quit())
+        .with_shutdown(Arc::clone(&shutdown))
+        .with_startup_timer(started);
+    shutdown_on_signals(Arc::clone(&shutdown), app.shell())?;
+    // builds the HTTP client with the first request, in the background
+    // asking the same again right after the answer shows that answer, see `with_dedup`
+    let client = Bclient::from_config(&config).with_dedup(Some(backend::DEDUP_WINDOW));
+    log::trace!("app set up {:?} after start", started.elapsed());
+
+    // setup terminal, a panic anywhere below leaves it usable again
+    install_panic_hook(restore_terminal);
+    let mut terminal = init_terminal().inspect_err(|_| restore_terminal())?;
 
-    // disable_raw_mode()?;
-    ratatui::restore();
+    let res = app.run(&mut terminal, client).await;
 
-    res.await  // Is the futures here ended program unexpectedly?
+    restore_terminal();
+    res
 }
 
-fn get_config() -> Result<Config, Box<dyn std::error::Error>> {
-    if let Ok(contents) = fs::read_to_string("config.json") {
-        let config: Config = serde_json::from_str(&contents).unwrap();
-        Ok(config)
-    } else {
-        panic!("config.json not found. Please set it up with aurish-cli")
+/// Theme of `config`, or the monochrome one for a terminal without colors
+fn theme_for(config: &Config, caps: &TerminalCaps) -> Theme {
+    match caps.color() {
+        true => Theme::from_config(config.get_theme()).unwrap_or_default(),
+        false => Theme::monochrome(),
     }
-}
\ No newline at end of file
+}
+
+/// Save the window title on the terminal's title stack (xterm `CSI 22;0t`), as the app
+/// changes it to show the current directory
+const PUSH_TITLE: &str = "\x1b[22;0t";
+/// Restore the title saved with `PUSH_TITLE`
+const POP_TITLE: &str = "\x1b[23;0t";
+
+fn init_terminal() -> io::Result<DefaultTerminal> {
+    enable_raw_mode()?;
+    execute!(io::stdout(), Print(PUSH_TITLE), EnterAlternateScreen, EnableBracketedPaste)?;
+    Terminal::new(CrosstermBackend::new(io::stdout()))
+}
+
+/// Leave raw mode and the alternate screen and restore the window title, ignoring errors
+/// since this also runs while panicking
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), DisableBracketedPaste, LeaveAlternateScreen, Print(POP_TITLE));
+}
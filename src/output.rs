@@ -0,0 +1,196 @@
+//! Styled terminal output of `aurish-cli`
+//!
+//! Everything `aurish-cli` prints goes through these helpers, so the same kind of output
+//! always looks the same. Styles are written with `anstream`, which drops them when the
+//! stream is not a terminal or `NO_COLOR` is set; `disable_color` drops them everywhere.
+
+use std::fmt::Display;
+use std::io::Write;
+use anstream::{eprintln, print, println, ColorChoice};
+use anstyle::{AnsiColor, Style};
+use crate::backend::ModelAnswer;
+use crate::shell::ShellOutput;
+use crate::utils::{output_parts, OutputStream};
+
+const COMMAND: Style = AnsiColor::Cyan.on_default().bold();
+const EXPLANATION: Style = Style::new().dimmed();
+const SUCCEEDED: Style = AnsiColor::Green.on_default();
+const FAILED: Style = AnsiColor::Red.on_default();
+const STDERR: Style = AnsiColor::Red.on_default().dimmed();
+const ERROR: Style = AnsiColor::Red.on_default().bold();
+const WARNING: Style = AnsiColor::Yellow.on_default().bold();
+const HINT: Style = AnsiColor::Yellow.on_default().bold();
+const HEADING: Style = Style::new().bold();
+
+/// Print without styles, for `--no-color`
+pub fn disable_color() {
+    ColorChoice::Never.write_global();
+}
+
+fn styled(style: Style, text: impl Display) -> String {
+    format!("{}{}{:#}", style, text, style)
+}
+
+/// A line of plain output
+pub fn message(text: impl Display) {
+    println!("{}", text);
+}
+
+/// A line of plain output on stderr, for commands keeping stdout for their result
+pub fn note(text: impl Display) {
+    eprintln!("{}", text);
+}
+
+/// Start a line to be finished by `message` once the work is done, like `Connecting... ok`
+pub fn progress(text: impl Display) {
+    print!("{}", text);
+    let _ = anstream::stdout().flush();
+}
+
+/// A suggested command, numbered from 1 if `index` is given
+pub fn command(index: Option<usize>, command: &str) {
+    match index {
+        Some(index) => println!("  {}) {}", index, styled(COMMAND, command)),
+        None => println!("{}", styled(COMMAND, command)),
+    }
+}
+
+/// What a command does, as told by the model
+pub fn explanation(text: impl Display) {
+    println!("{}", styled(EXPLANATION, text));
+}
+
+fn executed_line(command: &str, code: Option<i32>) -> String {
+    match code {
+        Some(0) => styled(SUCCEEDED, format!("$ {}", command)),
+        Some(code) => styled(FAILED, format!("$ {}  [exit {}]", command, code)),
+        None => styled(FAILED, format!("$ {}  [terminated by a signal]", command)),
+    }
+}
+
+/// A command that was run, green if it succeeded, red with its exit code otherwise.
+/// On stderr with `to_stderr`, for `ask --execute` whose stdout is the commands' output.
+pub fn executed(command: &str, code: Option<i32>, to_stderr: bool) {
+    if to_stderr {
+        eprintln!("{}", executed_line(command, code));
+    } else {
+        println!("{}", executed_line(command, code));
+    }
+}
+
+/// The suggested command an executed one was edited from, dimmed
+pub fn edited_from(suggested: &str) {
+    println!("{}", styled(EXPLANATION, format!("edited from: {}", suggested)));
+}
+
+/// Note under a command run on the terminal, dimmed
+pub fn ran_interactively() {
+    println!("{}", styled(EXPLANATION, crate::interactive::INTERACTIVE_NOTE));
+}
+
+/// Both streams of a command's output, stderr in red
+pub fn shell_output(output: &ShellOutput) {
+    let (stdout, stderr) = (String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    for (stream, text) in output_parts(&stdout, &stderr) {
+        match stream {
+            OutputStream::Stdout => println!("{}", text),
+            OutputStream::Stderr => eprintln!("{}", styled(STDERR, text)),
+        }
+    }
+}
+
+/// Lines of the answers of several models side by side, each column headed by the model
+/// and how long it took
+fn comparison_lines(answers: &[ModelAnswer]) -> Vec<String> {
+    let columns: Vec<Vec<String>> = answers.iter()
+        .map(|answer| {
+            let mut column = vec![format!("{} ({:.2}s)", answer.model, answer.latency.as_secs_f64())];
+            match &answer.commands {
+                Ok(commands) if commands.is_empty() => column.push("(no commands)".to_string()),
+                Ok(commands) => column.extend(commands.iter().enumerate().map(|(idx, command)| format!("{}) {}", idx + 1, command))),
+                Err(e) => column.push(format!("error: {}", e)),
+            }
+            column
+        })
+        .collect();
+    let widths: Vec<usize> = columns.iter()
+        .map(|column| column.iter().map(|line| line.chars().count()).max().unwrap_or(0))
+        .collect();
+    let row = |cell: &dyn Fn(&Vec<String>, usize) -> String| {
+        let cells: Vec<String> = columns.iter().zip(&widths).map(|(column, width)| cell(column, *width)).collect();
+        cells.join("   ").trim_end().to_string()
+    };
+    let mut lines = vec![
+        row(&|column, width| format!("{:<width$}", column[0], width = width)),
+        row(&|_, width| "-".repeat(width)),
+    ];
+    let rows = columns.iter().map(Vec::len).max().unwrap_or(0);
+    for idx in 1..rows {
+        lines.push(row(&|column, width| format!("{:<width$}", column.get(idx).map_or("", String::as_str), width = width)));
+    }
+    lines
+}
+
+/// The answers of several models in columns, for `ask --compare`
+pub fn comparison(answers: &[ModelAnswer]) {
+    for (idx, line) in comparison_lines(answers).iter().enumerate() {
+        match idx {
+            0 => println!("{}", styled(HEADING, line)),
+            _ => println!("{}", line),
+        }
+    }
+}
+
+/// An error, on stderr
+pub fn error(message: impl Display) {
+    eprintln!("{} {}", styled(ERROR, "error:"), message);
+}
+
+/// Something that may not work as expected, on stderr
+pub fn warning(message: impl Display) {
+    eprintln!("{} {}", styled(WARNING, "warning:"), message);
+}
+
+/// What to try after an error, on stderr
+pub fn hint(message: impl Display) {
+    eprintln!("{} {}", styled(HINT, "hint:"), message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn executed_lines() {
+        assert_eq!(executed_line("ls", Some(0)), "\x1b[32m$ ls\x1b[0m");
+        assert_eq!(executed_line("false", Some(1)), "\x1b[31m$ false  [exit 1]\x1b[0m");
+        assert_eq!(executed_line("sleep 9", None), "\x1b[31m$ sleep 9  [terminated by a signal]\x1b[0m");
+        assert_eq!(styled(COMMAND, "ls"), "\x1b[1m\x1b[36mls\x1b[0m");
+    }
+
+    #[test]
+    fn comparison_columns() {
+        use std::time::Duration;
+        use crate::error::BackendError;
+
+        let answers = [
+            ModelAnswer {
+                model: "llama3".to_string(),
+                latency: Duration::from_millis(1250),
+                commands: Ok(vec!["ls -a".to_string(), "find . -maxdepth 1".to_string()]),
+            },
+            ModelAnswer {
+                model: "mistral".to_string(),
+                latency: Duration::from_millis(300),
+                commands: Err(BackendError::ParseError(serde_json::from_str::<u8>("x").unwrap_err())),
+            },
+            ModelAnswer { model: "phi3".to_string(), latency: Duration::from_secs(2), commands: Ok(Vec::new()) },
+        ];
+        assert_eq!(comparison_lines(&answers), [
+            "llama3 (1.25s)          mistral (0.30s)                                                   phi3 (2.00s)",
+            "---------------------   ---------------------------------------------------------------   -------------",
+            "1) ls -a                error: Ollama response error: expected value at line 1 column 1   (no commands)",
+            "2) find . -maxdepth 1",
+        ]);
+    }
+}
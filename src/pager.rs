@@ -0,0 +1,136 @@
+//! Very long command output, read in `$PAGER` instead of the Output block
+//!
+//! Output of more than `MAX_INLINE_LINES` lines, like that of `find / -name x`, is written
+//! to a temporary file when it is logged. The Output block only shows its first and last
+//! `EXCERPT_LINES` lines with a notice in between, and `o` in Output mode suspends the TUI
+//! to run `$PAGER` (`less -R` if unset) on the file. The file is removed with its entry.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::utils::output_parts;
+
+/// Output longer than this is paged
+pub const MAX_INLINE_LINES: usize = 500;
+
+/// Lines shown in the Output block before and after the omitted ones
+pub const EXCERPT_LINES: usize = 20;
+
+/// Number of the next file, so entries of the same process get their own
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// Output of one command written to a temporary file, with the lines shown in its place
+#[derive(Debug)]
+pub struct PagedOutput {
+    path: PathBuf,
+    /// First `EXCERPT_LINES` lines, with their line breaks
+    pub head: String,
+    /// Last `EXCERPT_LINES` lines
+    pub tail: String,
+    /// Lines between `head` and `tail`
+    pub omitted: usize,
+}
+
+impl PagedOutput {
+    /// Write stdout and then stderr to a new temporary file if together they are longer
+    /// than `MAX_INLINE_LINES`, `None` if they are not
+    pub fn write(stdout: &str, stderr: &str) -> io::Result<Option<PagedOutput>> {
+        let text: String = output_parts(stdout, stderr).into_iter().map(|(_, text)| text).collect();
+        let lines: Vec<&str> = text.split_inclusive('\n').collect();
+        if lines.len() <= MAX_INLINE_LINES {
+            return Ok(None);
+        }
+        let name = format!("aurish-output-{}-{}.txt", std::process::id(), NEXT_FILE.fetch_add(1, Ordering::Relaxed));
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, &text)?;
+        Ok(Some(PagedOutput {
+            path,
+            head: lines[..EXCERPT_LINES].concat(),
+            tail: lines[lines.len() - EXCERPT_LINES..].concat(),
+            omitted: lines.len() - 2 * EXCERPT_LINES,
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Line shown between `head` and `tail`
+    pub fn notice(&self) -> String {
+        format!("… {} lines omitted — press o to open in pager", group_digits(self.omitted))
+    }
+}
+
+impl Drop for PagedOutput {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Program and arguments of `$PAGER`, `less -R` (`more` on Windows) if it is unset or empty
+pub fn pager_command() -> (String, Vec<String>) {
+    let pager = std::env::var("PAGER").unwrap_or_default();
+    let mut words = pager.split_whitespace().map(String::from);
+    match words.next() {
+        Some(program) => (program, words.collect()),
+        None if cfg!(windows) => ("more".to_string(), Vec::new()),
+        None => ("less".to_string(), vec!["-R".to_string()]),
+    }
+}
+
+/// Run the pager on `path` and wait for it to exit. The terminal must be handed to it
+/// first, see `App::open_pager`.
+pub fn open(path: &Path) -> io::Result<ExitStatus> {
+    let (program, args) = pager_command();
+    Command::new(program).args(args).arg(path).status()
+}
+
+/// `4213` as `4,213`
+fn group_digits(n: usize) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (idx, digit) in digits.chars().enumerate() {
+        if idx > 0 && (digits.len() - idx).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(from: usize, to: usize) -> String {
+        (from..=to).map(|n| format!("{}\n", n)).collect()
+    }
+
+    #[test]
+    fn long_output_is_paged() {
+        assert!(PagedOutput::write(&numbered(1, MAX_INLINE_LINES), "").unwrap().is_none());
+
+        let stdout = numbered(1, 4000);
+        let stderr = numbered(4001, 4253);
+        let paged = PagedOutput::write(&stdout, &stderr).unwrap().unwrap();
+        assert_eq!(paged.head, numbered(1, EXCERPT_LINES));
+        assert_eq!(paged.tail, numbered(4254 - EXCERPT_LINES, 4253));
+        assert_eq!(paged.omitted, 4213);
+        assert_eq!(paged.notice(), "… 4,213 lines omitted — press o to open in pager");
+        assert_eq!(fs::read_to_string(paged.path()).unwrap(), stdout + &stderr);
+
+        let path = paged.path().to_path_buf();
+        drop(paged);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn digit_groups() {
+        assert_eq!(group_digits(7), "7");
+        assert_eq!(group_digits(999), "999");
+        assert_eq!(group_digits(1000), "1,000");
+        assert_eq!(group_digits(1234567), "1,234,567");
+    }
+}
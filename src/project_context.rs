@@ -0,0 +1,182 @@
+//! Notes on a project, put before the system prompt while commands run in it
+//!
+//! An `AURISH.md` or `.aurish-context` file in the shell's current directory, or in one of
+//! the `MAX_DEPTH` directories above it, tells the model about the project: its build tool,
+//! where things are, what not to touch. The closest file wins, and only its first
+//! `MAX_BYTES` are read. `ProjectContext` keeps the file read until the directory changes
+//! or the file is modified.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Names of a context file, looked for in this order in each directory
+pub const FILE_NAMES: [&str; 2] = ["AURISH.md", ".aurish-context"];
+
+/// Directories above the current one looked into
+pub const MAX_DEPTH: usize = 8;
+
+/// Bytes of a context file read at most
+pub const MAX_BYTES: usize = 8 * 1024;
+
+/// A context file as read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextFile {
+    pub path: PathBuf,
+    pub text: String,
+    /// Whether the file goes on after `text`
+    pub truncated: bool,
+}
+
+impl ContextFile {
+    /// Text put before the system prompt, see `OllamaReq::set_project_context`
+    pub fn preamble(&self) -> String {
+        let cut = if self.truncated { "\n(the rest of the file is left out)" } else { "" };
+        format!("Notes on the project the commands run in, from {}:\n{}{}\n\n", self.path.display(), self.text.trim_end(), cut)
+    }
+}
+
+/// The context file closest to `dir`, looking into `dir` and at most `max_depth`
+/// directories above it
+pub fn find(dir: &Path, max_depth: usize) -> Option<PathBuf> {
+    dir.ancestors()
+        .take(max_depth + 1)
+        .flat_map(|ancestor| FILE_NAMES.iter().map(move |name| ancestor.join(name)))
+        .find(|path| path.is_file())
+}
+
+/// The first `max_bytes` of the file at `path`, a character cut in half left out
+pub fn read(path: &Path, max_bytes: usize) -> io::Result<ContextFile> {
+    let mut bytes = Vec::new();
+    File::open(path)?.take(max_bytes as u64 + 1).read_to_end(&mut bytes)?;
+    let truncated = bytes.len() > max_bytes;
+    bytes.truncate(max_bytes);
+    if truncated {
+        if let Err(e) = std::str::from_utf8(&bytes) {
+            if e.error_len().is_none() {
+                bytes.truncate(e.valid_up_to());
+            }
+        }
+    }
+    Ok(ContextFile { path: path.to_path_buf(), text: String::from_utf8_lossy(&bytes).into_owned(), truncated })
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// The context file of the last directory asked about, read again only when the directory
+/// changes or the file is modified
+#[derive(Debug, Default)]
+pub struct ProjectContext {
+    dir: Option<PathBuf>,
+    file: Option<(ContextFile, Option<SystemTime>)>,
+}
+
+impl ProjectContext {
+    /// The context file for commands run in `dir`. Without one, the directories are looked
+    /// into again each time, so a file created meanwhile is found.
+    pub fn current(&mut self, dir: &Path) -> Option<&ContextFile> {
+        let fresh = self.dir.as_deref() == Some(dir) && match &self.file {
+            Some((file, when)) => when.is_some() && modified(&file.path) == *when,
+            None => false,
+        };
+        if !fresh {
+            self.dir = Some(dir.to_path_buf());
+            self.file = find(dir, MAX_DEPTH).and_then(|path| {
+                let when = modified(&path);
+                read(&path, MAX_BYTES).ok().map(|file| (file, when))
+            });
+        }
+        self.cached()
+    }
+
+    /// The context file found by the last `current`
+    pub fn cached(&self) -> Option<&ContextFile> {
+        self.file.as_ref().map(|(file, _)| file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn temp_tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("aurish-project-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("a/b/c")).unwrap();
+        root
+    }
+
+    #[test]
+    fn ancestor_lookup() {
+        let root = temp_tree("ancestor");
+        let deep = root.join("a/b/c");
+        assert_eq!(find(&deep, MAX_DEPTH).filter(|path| path.starts_with(&root)), None);
+        fs::write(root.join("a/.aurish-context"), "uses make").unwrap();
+        assert_eq!(find(&deep, MAX_DEPTH), Some(root.join("a/.aurish-context")));
+        // the closest directory wins, and AURISH.md before .aurish-context in it
+        fs::write(root.join("a/AURISH.md"), "uses cargo").unwrap();
+        assert_eq!(find(&deep, MAX_DEPTH), Some(root.join("a/AURISH.md")));
+        fs::write(root.join("a/b/.aurish-context"), "the b part").unwrap();
+        assert_eq!(find(&deep, MAX_DEPTH), Some(root.join("a/b/.aurish-context")));
+        // a directory of that name is no context file
+        fs::create_dir(deep.join("AURISH.md")).unwrap();
+        assert_eq!(find(&deep, MAX_DEPTH), Some(root.join("a/b/.aurish-context")));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn depth_limit() {
+        let root = temp_tree("depth");
+        let deep = root.join("a/b/c");
+        fs::write(root.join("AURISH.md"), "notes").unwrap();
+        assert_eq!(find(&deep, 3), Some(root.join("AURISH.md")));
+        assert_eq!(find(&deep, 2), None);
+        assert_eq!(find(&root, 0), Some(root.join("AURISH.md")));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn size_cap() {
+        let root = temp_tree("size");
+        let path = root.join("AURISH.md");
+        fs::write(&path, "0123456789").unwrap();
+        let file = read(&path, 10).unwrap();
+        assert_eq!((file.text.as_str(), file.truncated), ("0123456789", false));
+        let file = read(&path, 4).unwrap();
+        assert_eq!((file.text.as_str(), file.truncated), ("0123", true));
+        assert!(file.preamble().contains("0123\n(the rest of the file is left out)"));
+        // é takes two bytes, the half at the cut is dropped
+        fs::write(&path, "abcé").unwrap();
+        let file = read(&path, 4).unwrap();
+        assert_eq!((file.text.as_str(), file.truncated), ("abc", true));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn cache_follows_directory_and_mtime() {
+        let root = temp_tree("cache");
+        let path = root.join("a/AURISH.md");
+        fs::write(&path, "first").unwrap();
+        let mut context = ProjectContext::default();
+        assert_eq!(context.current(&root.join("a/b")).map(|file| file.text.clone()), Some("first".to_string()));
+        // not read again while the file stays as it was
+        let file = File::options().write(true).open(&path).unwrap();
+        let before = modified(&path).unwrap();
+        fs::write(&path, "second").unwrap();
+        file.set_modified(before).unwrap();
+        assert_eq!(context.current(&root.join("a/b")).map(|file| file.text.clone()), Some("first".to_string()));
+        // another directory, or a newer file, reads it again
+        assert_eq!(context.current(&root.join("a")).map(|file| file.text.clone()), Some("second".to_string()));
+        fs::write(&path, "third").unwrap();
+        file.set_modified(before + Duration::from_secs(5)).unwrap();
+        assert_eq!(context.current(&root.join("a")).map(|file| file.text.clone()), Some("third".to_string()));
+        assert_eq!(context.cached().map(|file| file.path.clone()), Some(path.clone()));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(context.current(&root.join("a")).filter(|file| file.path.starts_with(&root)), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
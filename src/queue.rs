@@ -0,0 +1,208 @@
+//! Values shared by the queued commands
+//!
+//! Plans of several steps often repeat a value: `mkdir demo`, `cd demo`, `git init demo`.
+//! `parameterize` finds the words used by more than one command and puts placeholders like
+//! `{1}` in their place, so a new value can be asked for once and `substitute`d into every
+//! command. Programs and wrappers like `sudo`, flags, `$VARIABLES`, numbers and common
+//! words (subcommands like `install`, path parts like `usr`) are never taken for values.
+
+use crate::risk;
+
+/// Words too common in commands to be a value of the plan
+const COMMON_WORDS: [&str; 64] = [
+    "add", "all", "and", "apply", "bin", "build", "check", "clean", "clone", "commit", "config", "create",
+    "delete", "dev", "disable", "enable", "etc", "exec", "false", "fetch", "for", "from", "get", "head",
+    "help", "home", "init", "install", "into", "latest", "lib", "list", "local", "localhost", "logs", "main",
+    "master", "merge", "new", "none", "null", "opt", "origin", "proc", "pull", "push", "remove", "restart",
+    "run", "set", "share", "show", "src", "start", "status", "stop", "sys", "test", "the", "tmp",
+    "true", "update", "usr", "var",
+];
+
+/// Shortest word taken for a value
+const MIN_LEN: usize = 3;
+
+/// A value shared by several commands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    /// What is between the braces of the placeholder, `1` for `{1}`
+    pub name: String,
+    /// The value put in place of the placeholder by `substitute`, at first the word found
+    pub value: String,
+    /// Number of commands using it
+    pub uses: usize,
+}
+
+impl Param {
+    pub fn placeholder(&self) -> String {
+        format!("{{{}}}", self.name)
+    }
+}
+
+/// The commands with the words shared by several of them replaced by placeholders, and
+/// these words in the order they first appear. Commands sharing nothing come back as they are.
+pub fn parameterize(commands: &[String]) -> (Vec<String>, Vec<Param>) {
+    let programs: Vec<String> = commands.iter().flat_map(|command| risk::programs(command)).collect();
+    let mut params: Vec<Param> = Vec::new();
+    for (idx, command) in commands.iter().enumerate() {
+        let mut seen: Vec<&str> = Vec::new();
+        for (_, word) in words(command) {
+            if seen.contains(&word) || !is_candidate(word, &programs) {
+                continue;
+            }
+            seen.push(word);
+            if params.iter().any(|param| param.value == word) {
+                continue;
+            }
+            let uses = 1 + commands[idx + 1..].iter()
+                .filter(|other| words(other).iter().any(|(_, other_word)| *other_word == word))
+                .count();
+            if uses > 1 {
+                params.push(Param { name: String::new(), value: word.to_string(), uses });
+            }
+        }
+    }
+
+    // names whose placeholder is already written in a command are left out
+    let mut names = (1..).map(|n| n.to_string()).filter(|name| {
+        let placeholder = format!("{{{}}}", name);
+        !commands.iter().any(|command| command.contains(&placeholder))
+    });
+    for param in params.iter_mut() {
+        param.name = names.next().unwrap_or_default();
+    }
+
+    let templates = commands.iter().map(|command| {
+        let mut template = String::new();
+        let mut end = 0;
+        for (start, word) in words(command) {
+            if let Some(param) = params.iter().find(|param| param.value == word) {
+                template.push_str(&command[end..start]);
+                template.push_str(&param.placeholder());
+                end = start + word.len();
+            }
+        }
+        template.push_str(&command[end..]);
+        template
+    }).collect();
+    (templates, params)
+}
+
+/// The commands given by `parameterize` with the placeholders replaced by the values of
+/// `params`, in one pass so values that look like placeholders stay as they are. Values
+/// are put in as they are, quoting them is left to whoever types them.
+pub fn substitute(templates: &[String], params: &[Param]) -> Vec<String> {
+    templates.iter().map(|template| {
+        let mut command = String::new();
+        let mut rest = template.as_str();
+        while let Some(idx) = rest.find('{') {
+            command.push_str(&rest[..idx]);
+            rest = &rest[idx..];
+            let param = rest[1..].split_once('}')
+                .and_then(|(name, _)| params.iter().find(|param| param.name == name));
+            match param {
+                Some(param) => {
+                    command.push_str(&param.value);
+                    rest = &rest[param.name.len() + 2..];
+                },
+                None => {
+                    command.push('{');
+                    rest = &rest[1..];
+                },
+            }
+        }
+        command.push_str(rest);
+        command
+    }).collect()
+}
+
+/// Runs of letters, digits, `_`, `.` and `-` of `command` with where they start, leaving
+/// out the names of `$VARIABLES` and `${VARIABLES}`
+fn words(command: &str) -> Vec<(usize, &str)> {
+    let is_word_char = |c: char| c.is_alphanumeric() || matches!(c, '_' | '.' | '-');
+    let mut words = Vec::new();
+    let mut start = None;
+    for (idx, c) in command.char_indices().chain(std::iter::once((command.len(), ' '))) {
+        match (start, is_word_char(c)) {
+            (None, true) => start = Some(idx),
+            (Some(begin), false) => {
+                let before = &command[..begin];
+                if !before.ends_with('$') && !before.ends_with("${") {
+                    words.push((begin, &command[begin..idx]));
+                }
+                start = None;
+            },
+            _ => {},
+        }
+    }
+    words
+}
+
+/// Whether `word` can be a value rather than part of the command itself
+fn is_candidate(word: &str, programs: &[String]) -> bool {
+    word.chars().count() >= MIN_LEN
+        && !word.starts_with('-')
+        && word.chars().any(char::is_alphabetic)
+        && !COMMON_WORDS.iter().any(|common| common.eq_ignore_ascii_case(word))
+        && !programs.iter().map(String::as_str).chain(risk::WRAPPERS).any(|program| program.eq_ignore_ascii_case(word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(commands: &[&str]) -> Vec<String> {
+        commands.iter().map(|command| command.to_string()).collect()
+    }
+
+    fn values(commands: &[String]) -> Vec<String> {
+        parameterize(commands).1.into_iter().map(|param| param.value).collect()
+    }
+
+    #[test]
+    fn shared_values() {
+        let plan = commands(&["mkdir -p demo/src", "cd demo", "git init", "echo '# demo' > README.md", "git add README.md"]);
+        let (templates, params) = parameterize(&plan);
+        assert_eq!(templates, [
+            "mkdir -p {1}/src", "cd {1}", "git init", "echo '# {1}' > {2}", "git add {2}",
+        ]);
+        assert_eq!(params, [
+            Param { name: "1".to_string(), value: "demo".to_string(), uses: 3 },
+            Param { name: "2".to_string(), value: "README.md".to_string(), uses: 2 },
+        ]);
+        assert_eq!(substitute(&templates, &params), plan);
+
+        let mut params = params;
+        params[0].value = "my-app".to_string();
+        assert_eq!(substitute(&templates, &params)[..2], ["mkdir -p my-app/src", "cd my-app"]);
+        // a value looking like a placeholder is not replaced again
+        params[0].value = "{2}".to_string();
+        assert_eq!(substitute(&templates, &params)[1], "cd {2}");
+    }
+
+    #[test]
+    fn common_words_are_not_values() {
+        // programs, subcommands, flags and path parts
+        assert!(values(&commands(&["git init", "git add .", "git commit -m first"])).is_empty());
+        assert!(values(&commands(&["sudo apt install --yes nginx", "sudo systemctl enable nginx", "nginx -t"])).is_empty());
+        assert!(values(&commands(&["ls /usr/local/bin", "cp tool /usr/local/bin"])).is_empty());
+        // variables, numbers and short words
+        assert!(values(&commands(&["echo $HOME", "cd ${HOME}/x", "chmod 755 a", "chmod 755 b", "touch ab", "rm ab"])).is_empty());
+        // a word repeated in one command only
+        assert!(values(&commands(&["mkdir photos && cd photos", "ls"])).is_empty());
+    }
+
+    #[test]
+    fn existing_braces_are_kept() {
+        let plan = commands(&["find build -name '*.o' -exec rm {} \\;", "echo {1}", "tar czf build.tgz build"]);
+        let (templates, params) = parameterize(&plan);
+        // `build` is a common word, `{1}` already written in a command
+        assert!(params.is_empty());
+        assert_eq!(templates, plan);
+
+        let plan = commands(&["echo {1} > notes.txt", "cat notes.txt"]);
+        let (templates, params) = parameterize(&plan);
+        assert_eq!(params[0].placeholder(), "{2}");
+        assert_eq!(templates, ["echo {1} > {2}", "cat {2}"]);
+        assert_eq!(substitute(&templates, &params), plan);
+    }
+}
@@ -0,0 +1,237 @@
+//! The command queue, kept in a file so it survives quitting and crashes
+//!
+//! Both frontends write the pending commands, the prompt they answer and the directory they
+//! would run in to `<data dir>/aurish/queue.json` whenever the queue changes, and remove the
+//! file once the queue is empty. The next start offers to restore them. The file is replaced
+//! with a rename, so a crash while writing it leaves the previous one intact.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::error::QueueFileError;
+use crate::utils;
+
+/// Format of the file written by this version, files of other versions are ignored
+pub const VERSION: u32 = 1;
+
+/// Queue left behind by a session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedQueue {
+    /// Format of the file, see `VERSION`
+    pub version: u32,
+    /// Seconds since the Unix epoch
+    pub saved_at: u64,
+    /// Prompt the model suggested the commands for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// Directory the first command would have run in
+    pub directory: PathBuf,
+    pub commands: Vec<String>,
+}
+
+/// Only the version, read before the rest so a newer format is told apart from a broken file
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+impl SavedQueue {
+    pub fn new(prompt: Option<&str>, directory: &Path, commands: Vec<String>) -> Self {
+        SavedQueue {
+            version: VERSION,
+            saved_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()),
+            prompt: prompt.map(String::from),
+            directory: directory.to_path_buf(),
+            commands,
+        }
+    }
+
+    /// Parse the contents of a queue file
+    pub fn parse(text: &str) -> Result<SavedQueue, QueueFileError> {
+        let header: Header = serde_json::from_str(text).map_err(QueueFileError::Corrupt)?;
+        if header.version != VERSION {
+            return Err(QueueFileError::Version(header.version));
+        }
+        serde_json::from_str(text).map_err(QueueFileError::Corrupt)
+    }
+}
+
+/// `<data dir>/aurish/queue.json`; unit tests never touch the real file
+pub fn default_path() -> Option<PathBuf> {
+    if cfg!(test) {
+        return None;
+    }
+    dirs::data_dir().map(|dir| dir.join("aurish").join("queue.json"))
+}
+
+/// The queue file of a frontend, written only when the queue changed
+pub struct QueueFile {
+    path: Option<PathBuf>,
+    /// Commands and directory in the file, `None` when there is none
+    saved: Option<(Vec<String>, PathBuf)>,
+}
+
+impl Default for QueueFile {
+    fn default() -> Self {
+        Self::new(default_path())
+    }
+}
+
+impl QueueFile {
+    /// Queue kept at `path`, or nowhere if `path` is `None`
+    pub fn new(path: Option<PathBuf>) -> Self {
+        QueueFile { path, saved: None }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The queue an earlier session left, `None` if there is none or it is empty. The file
+    /// stays until `update` or `clear` is called, so it is still there if this session ends
+    /// before deciding what to do with it.
+    pub fn load(&mut self) -> Result<Option<SavedQueue>, QueueFileError> {
+        let Some(path) = &self.path else {
+            return Ok(None);
+        };
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(QueueFileError::Read(e)),
+        };
+        let queue = SavedQueue::parse(&text)?;
+        if queue.commands.is_empty() {
+            return Ok(None);
+        }
+        self.saved = Some((queue.commands.clone(), queue.directory.clone()));
+        Ok(Some(queue))
+    }
+
+    /// Write the queue if it changed since the last call, or remove the file once it is
+    /// empty. Failing to is only logged.
+    pub fn update(&mut self, prompt: Option<&str>, directory: &Path, commands: &VecDeque<String>) {
+        let unchanged = match &self.saved {
+            Some((saved, saved_dir)) => saved.iter().eq(commands) && saved_dir == directory,
+            None => commands.is_empty(),
+        };
+        if unchanged {
+            return;
+        }
+        if commands.is_empty() {
+            return self.clear();
+        }
+        let queue = SavedQueue::new(prompt, directory, commands.iter().cloned().collect());
+        if let Some(path) = &self.path {
+            if let Err(e) = write(path, &queue) {
+                warn!("could not save the queue to {}: {}", path.display(), e);
+            }
+        }
+        self.saved = Some((queue.commands, queue.directory));
+    }
+
+    /// Remove the file, e.g. when the saved queue is not wanted or could not be read
+    pub fn clear(&mut self) {
+        self.saved = None;
+        let Some(path) = &self.path else {
+            return;
+        };
+        match fs::remove_file(path) {
+            Ok(()) => {},
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => warn!("could not remove the saved queue {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Write `queue` to a temporary file next to `path`, then move it over `path`
+fn write(path: &Path, queue: &SavedQueue) -> io::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    utils::write_atomically(path, &serde_json::to_string_pretty(queue)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aurish-queue-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("queue.json")
+    }
+
+    fn queue(commands: &[&str]) -> VecDeque<String> {
+        commands.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn saved_and_restored() {
+        let path = temp_file("restore");
+        let mut file = QueueFile::new(Some(path.clone()));
+        assert!(file.load().unwrap().is_none());
+        file.update(Some("build it"), Path::new("/src"), &queue(&[]));
+        assert!(!path.exists());
+
+        file.update(Some("build it"), Path::new("/src"), &queue(&["make", "make install"]));
+        let mut next = QueueFile::new(Some(path.clone()));
+        let saved = next.load().unwrap().unwrap();
+        assert_eq!(saved.version, VERSION);
+        assert_eq!(saved.prompt.as_deref(), Some("build it"));
+        assert_eq!(saved.directory, Path::new("/src"));
+        assert_eq!(saved.commands, ["make", "make install"]);
+        // loading leaves the file for a session that ends before deciding
+        assert!(path.exists());
+
+        // a command ran
+        file.update(Some("build it"), Path::new("/src"), &queue(&["make install"]));
+        assert_eq!(QueueFile::new(Some(path.clone())).load().unwrap().unwrap().commands, ["make install"]);
+        // the queue emptied
+        file.update(Some("build it"), Path::new("/src"), &queue(&[]));
+        assert!(!path.exists());
+        assert!(QueueFile::new(Some(path.clone())).load().unwrap().is_none());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn only_written_when_changed() {
+        let path = temp_file("changes");
+        let mut file = QueueFile::new(Some(path.clone()));
+        file.update(None, Path::new("/src"), &queue(&["ls"]));
+        fs::remove_file(&path).unwrap();
+        file.update(None, Path::new("/src"), &queue(&["ls"]));
+        assert!(!path.exists());
+        // a `cd` changes where the commands would run
+        file.update(None, Path::new("/tmp"), &queue(&["ls"]));
+        assert_eq!(QueueFile::new(Some(path.clone())).load().unwrap().unwrap().directory, Path::new("/tmp"));
+        // nothing to remove is fine
+        file.clear();
+        file.clear();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn bad_files_are_reported() {
+        assert!(matches!(SavedQueue::parse("{\"version\": 1, \"commands\": [\"ls\"]"), Err(QueueFileError::Corrupt(_))));
+        assert!(matches!(SavedQueue::parse("[\"ls\"]"), Err(QueueFileError::Corrupt(_))));
+        assert!(matches!(SavedQueue::parse("{\"commands\": [\"ls\"]}"), Err(QueueFileError::Corrupt(_))));
+        let newer = r#"{"version": 2, "queue": {"commands": ["ls"]}}"#;
+        assert!(matches!(SavedQueue::parse(newer), Err(QueueFileError::Version(2))));
+        let saved = SavedQueue::new(None, Path::new("/"), vec!["ls".to_string()]);
+        assert_eq!(SavedQueue::parse(&serde_json::to_string(&saved).unwrap()).unwrap(), saved);
+
+        let path = temp_file("corrupt");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "{\"version\": 1, \"comm").unwrap();
+        let mut file = QueueFile::new(Some(path.clone()));
+        let error = file.load().unwrap_err();
+        assert!(error.to_string().starts_with("the saved queue is corrupted: "), "{}", error);
+        file.clear();
+        assert!(!path.exists());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+//! Machine-readable report of commands run without the TUI
+//!
+//! `aurish-cli ask --execute --report <path>` writes one as JSON: what each command that ran
+//! printed, how long it took and how it exited, the commands left out after a failure and
+//! whether everything succeeded. The output itself is only sampled, scripts needing all of
+//! it should redirect stdout. It is built from the `Session` recorded while running them.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::session::{Event, OutputSummary, Session};
+
+/// Format of the reports written by this version
+pub const VERSION: u32 = 1;
+
+/// Output longer than this is cut in the samples
+pub const SAMPLE_BYTES: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunReport {
+    /// Format of the report, see `VERSION`
+    pub version: u32,
+    pub prompt: String,
+    pub model: String,
+    /// Whether every command ran and exited with 0
+    pub success: bool,
+    /// Commands that ran, in order
+    pub commands: Vec<CommandReport>,
+    /// Commands not run because an earlier one failed
+    #[serde(default)]
+    pub not_run: Vec<String>,
+}
+
+/// One command that ran
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandReport {
+    pub command: String,
+    /// `None` if the command was killed by a signal
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    pub stdout_bytes: usize,
+    pub stderr_bytes: usize,
+    /// Start of stdout, see `SAMPLE_BYTES`
+    pub stdout_sample: String,
+    /// Start of stderr, see `SAMPLE_BYTES`
+    pub stderr_sample: String,
+}
+
+impl CommandReport {
+    pub fn new(command: &str, exit_code: Option<i32>, duration_ms: u64, output: &OutputSummary) -> Self {
+        CommandReport {
+            command: command.to_string(),
+            exit_code,
+            duration_ms,
+            stdout_bytes: output.stdout_bytes,
+            stderr_bytes: output.stderr_bytes,
+            stdout_sample: output.stdout_sample(SAMPLE_BYTES),
+            stderr_sample: output.stderr_sample(SAMPLE_BYTES),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+impl RunReport {
+    /// Report of what became of the commands suggested for the last prompt of `session`
+    pub fn from_session(session: &Session) -> Self {
+        let mut report = RunReport {
+            version: VERSION,
+            prompt: String::new(),
+            model: String::new(),
+            success: true,
+            commands: Vec::new(),
+            not_run: Vec::new(),
+        };
+        for event in &session.events {
+            match event {
+                Event::PromptSent { model, prompt, .. } => {
+                    report.prompt = prompt.clone();
+                    report.model = model.clone();
+                    report.commands.clear();
+                    report.not_run.clear();
+                },
+                Event::CommandExecuted { command, exit_code, duration_ms, output, .. } => {
+                    report.commands.push(CommandReport::new(command, *exit_code, *duration_ms, output));
+                },
+                Event::CommandSkipped { command, .. } => report.not_run.push(command.clone()),
+                _ => {},
+            }
+        }
+        report.success = report.not_run.is_empty() && report.commands.iter().all(CommandReport::is_success);
+        report
+    }
+
+    /// Write the report as JSON to `path`, replacing what is there
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let mut session = Session::default();
+        session.prompt_sent("llama3:latest", "free disk space");
+        let commands = ["df -h", "du -sh /root", "rm -r /tmp/cache"].map(String::from);
+        session.commands_received(&commands);
+        session.command_executed("df -h", None, Some(0), Duration::from_millis(12), "Filesystem  Size\n/dev/sda1  100G", "");
+        assert!(RunReport::from_session(&session).success);
+        session.command_executed("du -sh /root", None, Some(1), Duration::from_millis(3), "", "du: cannot read directory '/root'");
+        session.command_skipped("rm -r /tmp/cache");
+        let report = RunReport::from_session(&session);
+        assert!(!report.success);
+        assert_eq!((report.prompt.as_str(), report.model.as_str()), ("free disk space", "llama3:latest"));
+        assert_eq!(report.commands.len(), 2);
+        assert_eq!(report.not_run, ["rm -r /tmp/cache"]);
+
+        let json = serde_json::to_string(&report).unwrap();
+        assert!(json.contains(r#""exit_code":1,"duration_ms":3,"stdout_bytes":0,"stderr_bytes":33"#), "{}", json);
+        assert_eq!(serde_json::from_str::<RunReport>(&json).unwrap(), report);
+
+        let path = std::env::temp_dir().join(format!("aurish-report-{}.json", std::process::id()));
+        report.write(&path).unwrap();
+        assert_eq!(serde_json::from_str::<RunReport>(&fs::read_to_string(&path).unwrap()).unwrap(), report);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn samples_are_cut() {
+        let long = "x".repeat(SAMPLE_BYTES * 3);
+        let command = CommandReport::new("yes x", None, 0, &OutputSummary::new(&long, ""));
+        assert_eq!(command.stdout_bytes, SAMPLE_BYTES * 3);
+        assert!(command.stdout_sample.starts_with(&long[..SAMPLE_BYTES]));
+        assert!(command.stdout_sample.ends_with(&format!("({} more bytes)", SAMPLE_BYTES * 2)));
+        assert!(!command.is_success());
+    }
+}
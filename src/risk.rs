@@ -0,0 +1,202 @@
+//! Detection of shell commands that can destroy data or take the machine down
+//!
+//! The check is a heuristic on the command text, it does not try to understand shell syntax
+//! beyond splitting on `;`, `&&`, `||` and `|`. It is meant to catch the suggestions that
+//! deserve a second look before running, not to be a sandbox.
+
+/// Commands that are only prefixes to the command actually run
+pub(crate) const WRAPPERS: [&str; 8] = ["sudo", "doas", "env", "nohup", "time", "exec", "nice", "timeout"];
+
+/// Why `command` is considered dangerous, or `None` if it looks harmless.
+/// `patterns` are user configured substrings that always require confirmation.
+pub fn danger_reason(command: &str, patterns: &[String]) -> Option<String> {
+    if let Some(pattern) = patterns.iter().find(|pattern| !pattern.is_empty() && command.contains(pattern.as_str())) {
+        return Some(format!("matches the configured pattern `{}`", pattern));
+    }
+
+    let compact: String = command.split_whitespace().collect();
+    if compact.contains(":(){") {
+        return Some("looks like a fork bomb".to_string());
+    }
+    if [">/dev/sd", ">/dev/nvme", ">/dev/hd", ">/dev/mmcblk"].iter().any(|device| compact.contains(device)) {
+        return Some("writes directly to a disk device".to_string());
+    }
+
+    let segments = split_segments(command);
+    for (idx, segment) in segments.iter().enumerate() {
+        let words = program_words(segment);
+        let Some(program) = words.first() else {
+            continue;
+        };
+        if idx > 0 && matches!(program.as_str(), "sh" | "bash" | "zsh" | "fish" | "ksh" | "dash") {
+            let downloads = program_words(segments[idx - 1]).first()
+                .is_some_and(|prev| prev == "curl" || prev == "wget");
+            if downloads {
+                return Some("pipes a download into a shell".to_string());
+            }
+        }
+        if let Some(reason) = program_reason(program, &words[1..]) {
+            return Some(reason.to_string());
+        }
+    }
+
+    None
+}
+
+/// Whether `command` needs confirmation before being run
+pub fn is_dangerous(command: &str, patterns: &[String]) -> bool {
+    danger_reason(command, patterns).is_some()
+}
+
+/// Names of the programs `command` runs, one per segment, wrappers like `sudo` left out
+pub(crate) fn programs(command: &str) -> Vec<String> {
+    split_segments(command).into_iter()
+        .filter_map(|segment| program_words(segment).into_iter().next())
+        .collect()
+}
+
+fn split_segments(command: &str) -> Vec<&str> {
+    command
+        .split([';', '\n'])
+        .flat_map(|part| part.split("&&"))
+        .flat_map(|part| part.split("||"))
+        .flat_map(|part| part.split('|'))
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+/// Words of a simple command, starting at the program after any wrappers, their options and
+/// `VAR=value` assignments. The program is reduced to its lowercase file name.
+fn program_words(segment: &str) -> Vec<String> {
+    let mut words = segment.split_whitespace();
+    let mut wrapper = None;
+    // `timeout` takes the duration before the program
+    let mut duration_due = false;
+    let program = loop {
+        let Some(word) = words.next() else {
+            return Vec::new();
+        };
+        if WRAPPERS.contains(&word) {
+            wrapper = Some(word);
+            duration_due = word == "timeout";
+        } else if word.starts_with('-') && !word.contains('/') {
+            if wrapper.is_some_and(|wrapper| option_takes_value(wrapper, word)) {
+                words.next();
+            }
+        } else if duration_due {
+            duration_due = false;
+        } else if !word.contains('=') {
+            break word;
+        }
+    };
+    let program = program.rsplit(['/', '\\']).next().unwrap_or(program).to_lowercase();
+    let program = program.strip_suffix(".exe").unwrap_or(&program).to_string();
+    std::iter::once(program).chain(words.map(str::to_string)).collect()
+}
+
+/// Whether `option` of `wrapper` is followed by its value, like the user in `sudo -u root`
+fn option_takes_value(wrapper: &str, option: &str) -> bool {
+    matches!(
+        (wrapper, option),
+        ("sudo", "-u" | "-g" | "-C" | "-h" | "-p")
+            | ("doas", "-u" | "-C")
+            | ("env", "-u" | "-C")
+            | ("nice", "-n")
+            | ("timeout", "-s" | "-k"),
+    )
+}
+
+fn program_reason(program: &str, args: &[String]) -> Option<&'static str> {
+    let has_flag = |short: char, long: &str| {
+        args.iter().any(|arg| {
+            arg == long
+                || (arg.starts_with('-') && !arg.starts_with("--") && arg.contains(short))
+        })
+    };
+    let has_arg = |wanted: &str| args.iter().any(|arg| arg.eq_ignore_ascii_case(wanted));
+
+    match program {
+        "rm" if has_flag('r', "--recursive") || has_flag('R', "--recursive") || has_flag('f', "--force") => {
+            Some("deletes files recursively or without asking")
+        },
+        "shred" | "wipefs" | "fdisk" | "sfdisk" | "parted" => Some("modifies disks or destroys file contents"),
+        program if program.starts_with("mkfs") => Some("formats a file system"),
+        "dd" if args.iter().any(|arg| arg.starts_with("of=")) => Some("writes raw data to a file or device"),
+        "shutdown" | "reboot" | "halt" | "poweroff" => Some("shuts down or reboots the machine"),
+        "init" if has_arg("0") || has_arg("6") => Some("shuts down or reboots the machine"),
+        "chmod" | "chown" | "chgrp" if has_flag('R', "--recursive") => Some("changes permissions recursively"),
+        "git" => match args.first().map(String::as_str) {
+            Some("push") if has_flag('f', "--force") || args.iter().any(|arg| arg.starts_with("--force")) => {
+                Some("force pushes over remote history")
+            },
+            Some("reset") if has_arg("--hard") => Some("discards uncommitted changes"),
+            Some("clean") if has_flag('f', "--force") => Some("deletes untracked files"),
+            _ => None,
+        },
+        // cmd.exe and PowerShell
+        "del" | "erase" if has_arg("/s") || has_arg("/q") => Some("deletes files recursively or without asking"),
+        "rd" | "rmdir" if has_arg("/s") => Some("deletes directories recursively"),
+        "format" | "diskpart" => Some("modifies disks or destroys file contents"),
+        "remove-item" if has_arg("-recurse") || has_arg("-force") => Some("deletes files recursively or without asking"),
+        "stop-computer" | "restart-computer" => Some("shuts down or reboots the machine"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dangerous(command: &str) -> bool {
+        is_dangerous(command, &[])
+    }
+
+    #[test]
+    fn destructive_commands() {
+        assert!(dangerous("rm -rf /tmp/build"));
+        assert!(dangerous("sudo rm -r ~/old"));
+        assert!(dangerous("cd /tmp && /bin/rm --recursive cache"));
+        assert!(dangerous("mkfs.ext4 /dev/sdb1"));
+        assert!(dangerous("dd if=/dev/zero of=/dev/sda bs=1M"));
+        assert!(dangerous("echo hi > /dev/sda"));
+        assert!(dangerous("curl -fsSL https://example.com/install.sh | sh"));
+        assert!(dangerous("git push --force origin main"));
+        assert!(dangerous("git reset --hard HEAD~3"));
+        assert!(dangerous("sudo shutdown -h now"));
+        assert!(dangerous(":(){ :|:& };:"));
+        assert!(dangerous("chmod -R 777 /"));
+        assert!(dangerous("Remove-Item C:\\temp -Recurse"));
+        assert!(dangerous("rd /s /q C:\\build"));
+        // options of wrappers that take a value
+        assert!(dangerous("sudo -u root rm -rf /"));
+        assert!(dangerous("sudo -g wheel rm -rf ~"));
+        assert!(dangerous("env -u X rm -rf /"));
+        assert!(dangerous("nice -n 10 rm -rf build"));
+        assert!(dangerous("timeout 10s rm -rf build"));
+        assert!(dangerous("timeout -k 5 60 sudo -u admin shred disk.img"));
+    }
+
+    #[test]
+    fn harmless_commands() {
+        assert!(!dangerous("ls -la"));
+        assert!(!dangerous("rm notes.txt"));
+        assert!(!dangerous("git push origin main"));
+        assert!(!dangerous("grep -r format src"));
+        assert!(!dangerous("dd if=/dev/urandom bs=16 count=1"));
+        assert!(!dangerous("curl https://example.com | grep title"));
+        assert!(!dangerous("chmod 644 file"));
+        assert!(!dangerous("sudo -u rm ls"));
+        assert!(!dangerous("timeout 5 ping example.com"));
+    }
+
+    #[test]
+    fn configured_patterns() {
+        let patterns = vec!["kubectl delete".to_string(), String::new()];
+        assert_eq!(
+            danger_reason("kubectl delete pod web", &patterns).as_deref(),
+            Some("matches the configured pattern `kubectl delete`"),
+        );
+        assert!(!is_dangerous("kubectl get pods", &patterns));
+    }
+}
@@ -0,0 +1,159 @@
+//! Suggested commands saved as a script to review and run later
+//!
+//! The script is written for the shell the commands were suggested for: a shebang for
+//! the Unix shells, none for PowerShell and `cmd.exe`, which go by the file extension.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crate::error::ScriptError;
+use crate::shell::ShellType;
+
+/// File extension of scripts for `shell`, added to paths without one
+pub fn extension(shell: ShellType) -> &'static str {
+    match shell {
+        ShellType::PowerShell => "ps1",
+        ShellType::Cmd => "bat",
+        ShellType::Fish => "fish",
+        ShellType::Zsh => "zsh",
+        ShellType::Ksh => "ksh",
+        ShellType::Bash => "sh",
+        ShellType::Unknown => "sh",
+    }
+}
+
+fn shebang(shell: ShellType) -> Option<&'static str> {
+    match shell {
+        ShellType::PowerShell | ShellType::Cmd => None,
+        ShellType::Bash => Some("#!/usr/bin/env bash"),
+        ShellType::Fish => Some("#!/usr/bin/env fish"),
+        ShellType::Zsh => Some("#!/usr/bin/env zsh"),
+        ShellType::Ksh => Some("#!/usr/bin/env ksh"),
+        ShellType::Unknown => Some("#!/bin/sh"),
+    }
+}
+
+fn comment(shell: ShellType) -> &'static str {
+    match shell {
+        ShellType::Cmd => "REM",
+        _ => "#",
+    }
+}
+
+/// Text of the script running `commands` in order, each preceded by its explanation
+/// as a comment if it has one
+pub fn render(shell: ShellType, commands: &[(&str, Option<&str>)]) -> String {
+    let mut script = String::new();
+    if let Some(shebang) = shebang(shell) {
+        script.push_str(shebang);
+        script.push('\n');
+    }
+    if shell == ShellType::Cmd {
+        script.push_str("@echo off\n");
+    }
+    for (command, explanation) in commands {
+        for line in explanation.iter().flat_map(|explanation| explanation.lines()) {
+            script.push_str(comment(shell));
+            if !line.is_empty() {
+                script.push(' ');
+                script.push_str(line);
+            }
+            script.push('\n');
+        }
+        script.push_str(command);
+        script.push('\n');
+    }
+    script
+}
+
+/// `path`, with the extension for `shell` if it has none
+pub fn script_path(path: &Path, shell: ShellType) -> PathBuf {
+    if path.extension().is_some() {
+        path.to_path_buf()
+    } else {
+        path.with_extension(extension(shell))
+    }
+}
+
+/// Write the script of `commands` to `path`, see `script_path`, and make it executable.
+/// An existing file is only replaced with `force`. Returns the path written to.
+pub fn save(path: &Path, shell: ShellType, commands: &[(&str, Option<&str>)], force: bool) -> Result<PathBuf, ScriptError> {
+    let path = script_path(path, shell);
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    let mut file = options.open(&path).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => ScriptError::Exists(path.clone()),
+        _ => ScriptError::Io(e),
+    })?;
+    file.write_all(render(shell, commands).as_bytes())?;
+    make_executable(&file)?;
+    Ok(path)
+}
+
+#[cfg(unix)]
+fn make_executable(file: &fs::File) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = file.metadata()?.permissions();
+    // executable by whoever may read it
+    permissions.set_mode(permissions.mode() | (permissions.mode() & 0o444) >> 2);
+    file.set_permissions(permissions)
+}
+
+#[cfg(not(unix))]
+fn make_executable(_file: &fs::File) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aurish-script-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn script_text() {
+        let commands = [("ls -a", Some("list every file\nhidden ones too")), ("mkdir test", None)];
+        assert_eq!(
+            render(ShellType::Bash, &commands),
+            "#!/usr/bin/env bash\n# list every file\n# hidden ones too\nls -a\nmkdir test\n"
+        );
+        assert_eq!(render(ShellType::Fish, &commands[1..]), "#!/usr/bin/env fish\nmkdir test\n");
+        assert_eq!(render(ShellType::PowerShell, &[("dir", Some("list files"))]), "# list files\ndir\n");
+        assert_eq!(render(ShellType::Cmd, &[("dir", Some("list files"))]), "@echo off\nREM list files\ndir\n");
+        assert_eq!(script_path(Path::new("plan"), ShellType::PowerShell), PathBuf::from("plan.ps1"));
+        assert_eq!(script_path(Path::new("plan.txt"), ShellType::Bash), PathBuf::from("plan.txt"));
+    }
+
+    #[test]
+    fn saved_script() {
+        let dir = temp_dir("save");
+        let path = save(&dir.join("plan"), ShellType::Bash, &[("echo hi", None)], false).unwrap();
+        assert_eq!(path, dir.join("plan.sh"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "#!/usr/bin/env bash\necho hi\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o100, 0o100, "{:o}", mode);
+        }
+
+        // not replaced unless forced
+        let err = save(&path, ShellType::Bash, &[("echo bye", None)], false).unwrap_err();
+        assert!(matches!(err, ScriptError::Exists(ref existing) if *existing == path), "{}", err);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "#!/usr/bin/env bash\necho hi\n");
+        save(&path, ShellType::Bash, &[("echo bye", None)], true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "#!/usr/bin/env bash\necho bye\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
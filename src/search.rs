@@ -0,0 +1,121 @@
+//! Search in the Output history
+//!
+//! Matching is a case-insensitive substring search, unless the query contains capitals
+//! (smart case). `Search` only knows line numbers, rendering and scrolling to them is
+//! left to the TUI.
+
+/// A query and the lines of the Output history matching it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Search {
+    query: String,
+    /// Indices of the matching lines, in order
+    matches: Vec<usize>,
+    /// Line of the match jumped to
+    current: Option<usize>,
+}
+
+impl Search {
+    pub fn new(query: &str) -> Search {
+        Search { query: query.to_string(), ..Search::default() }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        if self.query.is_empty() {
+            return false;
+        }
+        if self.query.chars().any(char::is_uppercase) {
+            line.contains(&self.query)
+        } else {
+            line.to_lowercase().contains(&self.query.to_lowercase())
+        }
+    }
+
+    /// Find the matches in `lines`. The current match is kept if it still matches,
+    /// otherwise it becomes the last one, the newest output.
+    pub fn update<S: AsRef<str>>(&mut self, lines: &[S]) {
+        self.matches = lines.iter()
+            .enumerate()
+            .filter(|(_, line)| self.is_match(line.as_ref()))
+            .map(|(idx, _)| idx)
+            .collect();
+        if self.current.is_none_or(|line| !self.matches.contains(&line)) {
+            self.current = self.matches.last().copied();
+        }
+    }
+
+    /// Jump to the following match, wrapping around after the last one
+    pub fn next_match(&mut self) -> Option<usize> {
+        self.current = match self.current {
+            Some(line) => self.matches.iter().find(|idx| **idx > line).or(self.matches.first()).copied(),
+            None => self.matches.first().copied(),
+        };
+        self.current
+    }
+
+    /// Jump to the preceding match, wrapping around before the first one
+    pub fn prev_match(&mut self) -> Option<usize> {
+        self.current = match self.current {
+            Some(line) => self.matches.iter().rev().find(|idx| **idx < line).or(self.matches.last()).copied(),
+            None => self.matches.last().copied(),
+        };
+        self.current
+    }
+
+    /// Line of the match jumped to
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    /// Position of the current match and the number of matches, e.g. `(2, 7)` for the second
+    pub fn position(&self) -> (usize, usize) {
+        let idx = self.current.and_then(|line| self.matches.iter().position(|idx| *idx == line));
+        (idx.map_or(0, |idx| idx + 1), self.matches.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINES: [&str; 5] = ["$ pwgen 16", "Password: hunter2", "$ ls", "password.txt", "PASSWORD_FILE"];
+
+    #[test]
+    fn smart_case() {
+        let mut search = Search::new("password");
+        search.update(&LINES);
+        assert_eq!(search.position(), (3, 3));
+        assert!(search.is_match("PASSWORD"));
+
+        let mut search = Search::new("Password");
+        search.update(&LINES);
+        assert_eq!(search.current(), Some(1));
+        assert_eq!(search.position(), (1, 1));
+        assert!(!Search::new("").is_match("anything"));
+    }
+
+    #[test]
+    fn navigation() {
+        let mut search = Search::new("pass");
+        search.update(&LINES);
+        // starts at the newest match
+        assert_eq!(search.current(), Some(4));
+        assert_eq!(search.next_match(), Some(1));
+        assert_eq!(search.next_match(), Some(3));
+        assert_eq!(search.prev_match(), Some(1));
+        assert_eq!(search.prev_match(), Some(4));
+
+        // the current match survives new output
+        search.next_match();
+        search.update(&[&LINES[..], &["password again"]].concat());
+        assert_eq!(search.current(), Some(1));
+        assert_eq!(search.position(), (1, 4));
+
+        let mut search = Search::new("missing");
+        search.update(&LINES);
+        assert_eq!((search.next_match(), search.position()), (None, (0, 0)));
+    }
+}
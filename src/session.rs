@@ -0,0 +1,358 @@
+//! Record of what happened in a session
+//!
+//! Both frontends push an `Event` into their `Session` as things happen: prompts, the
+//! commands the model answered, and which of them were edited, skipped or executed. The
+//! TUI writes it as a JSON transcript on request, and on quit if enabled in the config;
+//! `aurish-cli` tells the model what became of its last commands from it (see
+//! `Session::outcomes`), and `ask --execute --report` writes its report from it.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::shell::ExecutionPlan;
+use crate::transcripts::Transcripts;
+
+/// Command output longer than this is cut in the transcript
+pub const MAX_OUTPUT_BYTES: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    /// Seconds since the Unix epoch
+    pub started: u64,
+    pub events: Vec<Event>,
+}
+
+/// Something that happened in the session, `at` is in seconds since the Unix epoch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    PromptSent { at: u64, model: String, prompt: String },
+    /// The answer to the last prompt, or other commands for it, e.g. after `:refine`
+    CommandsReceived { at: u64, commands: Vec<String> },
+    /// A suggested command was dropped without running it
+    CommandSkipped { at: u64, command: String },
+    /// A suggested command was changed before running it
+    CommandEdited { at: u64, suggested: String, command: String },
+    CommandExecuted {
+        at: u64,
+        command: String,
+        /// The suggested command, if `command` is an edit of it
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        suggested: Option<String>,
+        /// `None` if the command did not run to completion, e.g. it was killed or started as a job
+        exit_code: Option<i32>,
+        /// Started as a background job, its output is not known
+        #[serde(default, skip_serializing_if = "is_false")]
+        background: bool,
+        /// Ran attached to the terminal, its output was not captured
+        #[serde(default, skip_serializing_if = "is_false")]
+        interactive: bool,
+        duration_ms: u64,
+        output: OutputSummary,
+    },
+    /// What a command would do was shown before running it, see `IShell::explain_command`
+    PlanShown { at: u64, plan: ExecutionPlan },
+    /// A request failed, with the message shown for it
+    Error { at: u64, message: String },
+    /// The conversation was started over, the model forgets the earlier prompts
+    NewConversation { at: u64 },
+}
+
+/// Size and start of what a command printed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputSummary {
+    pub stdout_bytes: usize,
+    pub stderr_bytes: usize,
+    /// Start of stdout, see `MAX_OUTPUT_BYTES`
+    pub stdout: String,
+    /// Start of stderr, see `MAX_OUTPUT_BYTES`
+    pub stderr: String,
+}
+
+impl OutputSummary {
+    pub fn new(stdout: &str, stderr: &str) -> Self {
+        OutputSummary {
+            stdout_bytes: stdout.len(),
+            stderr_bytes: stderr.len(),
+            stdout: truncate(stdout, MAX_OUTPUT_BYTES),
+            stderr: truncate(stderr, MAX_OUTPUT_BYTES),
+        }
+    }
+
+    /// The first `max_bytes` of stdout, which must be at most `MAX_OUTPUT_BYTES`
+    pub fn stdout_sample(&self, max_bytes: usize) -> String {
+        cut(&self.stdout, self.stdout_bytes, max_bytes)
+    }
+
+    /// The first `max_bytes` of stderr, which must be at most `MAX_OUTPUT_BYTES`
+    pub fn stderr_sample(&self, max_bytes: usize) -> String {
+        cut(&self.stderr, self.stderr_bytes, max_bytes)
+    }
+}
+
+/// What became of a suggested command, see `Session::outcomes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Ran to the end, with its exit code if it has one
+    Exited(Option<i32>),
+    Background,
+    Skipped,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session { started: now(), events: Vec::new() }
+    }
+}
+
+impl Session {
+    pub fn prompt_sent(&mut self, model: &str, prompt: &str) {
+        self.events.push(Event::PromptSent { at: now(), model: model.to_string(), prompt: prompt.to_string() });
+    }
+
+    pub fn commands_received(&mut self, commands: &[String]) {
+        self.events.push(Event::CommandsReceived { at: now(), commands: commands.to_vec() });
+    }
+
+    pub fn command_skipped(&mut self, command: &str) {
+        self.events.push(Event::CommandSkipped { at: now(), command: command.to_string() });
+    }
+
+    pub fn command_edited(&mut self, suggested: &str, command: &str) {
+        self.events.push(Event::CommandEdited { at: now(), suggested: suggested.to_string(), command: command.to_string() });
+    }
+
+    pub fn command_executed(&mut self, command: &str, suggested: Option<&str>, exit_code: Option<i32>, duration: Duration, stdout: &str, stderr: &str) {
+        self.events.push(Event::CommandExecuted {
+            at: now(),
+            command: command.to_string(),
+            suggested: suggested.map(str::to_string),
+            exit_code,
+            background: false,
+            interactive: false,
+            duration_ms: duration.as_millis() as u64,
+            output: OutputSummary::new(stdout, stderr),
+        });
+    }
+
+    /// `command` ran attached to the terminal, see `IShell::run_interactive`
+    pub fn command_ran_interactively(&mut self, command: &str, suggested: Option<&str>, exit_code: Option<i32>, duration: Duration) {
+        self.events.push(Event::CommandExecuted {
+            at: now(),
+            command: command.to_string(),
+            suggested: suggested.map(str::to_string),
+            exit_code,
+            background: false,
+            interactive: true,
+            duration_ms: duration.as_millis() as u64,
+            output: OutputSummary::default(),
+        });
+    }
+
+    /// `command` was started as a background job
+    pub fn command_started(&mut self, command: &str, suggested: Option<&str>) {
+        self.events.push(Event::CommandExecuted {
+            at: now(),
+            command: command.to_string(),
+            suggested: suggested.map(str::to_string),
+            exit_code: None,
+            background: true,
+            interactive: false,
+            duration_ms: 0,
+            output: OutputSummary::default(),
+        });
+    }
+
+    pub fn plan_shown(&mut self, plan: &ExecutionPlan) {
+        self.events.push(Event::PlanShown { at: now(), plan: plan.clone() });
+    }
+
+    pub fn error(&mut self, message: &str) {
+        self.events.push(Event::Error { at: now(), message: message.to_string() });
+    }
+
+    pub fn new_conversation(&mut self) {
+        self.events.push(Event::NewConversation { at: now() });
+    }
+
+    /// What became of the commands run or skipped since the last prompt was answered, for
+    /// telling the model with the next one. A new conversation starts with none.
+    pub fn outcomes(&self) -> Vec<(String, Outcome)> {
+        let mut outcomes = Vec::new();
+        let mut asking = false;
+        for event in &self.events {
+            match event {
+                Event::PromptSent { .. } => asking = true,
+                Event::Error { .. } => asking = false,
+                Event::CommandsReceived { .. } if asking => {
+                    asking = false;
+                    outcomes.clear();
+                },
+                Event::NewConversation { .. } => outcomes.clear(),
+                Event::CommandSkipped { command, .. } => outcomes.push((command.clone(), Outcome::Skipped)),
+                Event::CommandExecuted { command, background: true, .. } => outcomes.push((command.clone(), Outcome::Background)),
+                Event::CommandExecuted { command, exit_code, .. } => outcomes.push((command.clone(), Outcome::Exited(*exit_code))),
+                Event::CommandsReceived { .. } | Event::CommandEdited { .. } | Event::PlanShown { .. } => {},
+            }
+        }
+        outcomes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Write the session to a file in `dir` named after the time it started, and add it to
+    /// the index there, see `transcripts`
+    pub fn export(&self, dir: &Path) -> io::Result<PathBuf> {
+        Transcripts::new(dir).save(self)
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// Directory sessions are exported to when the config does not name one
+pub fn default_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("aurish").join("sessions"))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Keep the first `max_bytes` of `output`, cut at a character boundary
+pub(crate) fn truncate(output: &str, max_bytes: usize) -> String {
+    cut(output, output.len(), max_bytes)
+}
+
+/// `truncate` for the start of an output of `total_bytes`
+fn cut(start: &str, total_bytes: usize, max_bytes: usize) -> String {
+    if total_bytes <= max_bytes {
+        return start.to_string();
+    }
+    let mut end = max_bytes.min(start.len());
+    while !start.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}\n… ({} more bytes)", &start[..end], total_bytes - end)
+}
+
+/// UTC time as `YYYYMMDD-HHMMSS`
+pub(crate) fn file_timestamp(secs: u64) -> String {
+    let (year, month, day) = civil_date(secs);
+    let rem = secs % 86_400;
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, rem / 3_600, rem % 3_600 / 60, rem % 60)
+}
+
+/// UTC time as `YYYY-MM-DD HH:MM`
+pub fn date_time(secs: u64) -> String {
+    let (year, month, day) = civil_date(secs);
+    let rem = secs % 86_400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, rem / 3_600, rem % 3_600 / 60)
+}
+
+/// UTC time of day as `HH:MM:SS`
+pub(crate) fn clock_time(secs: u64) -> String {
+    let rem = secs % 86_400;
+    format!("{:02}:{:02}:{:02}", rem / 3_600, rem % 3_600 / 60, rem % 60)
+}
+
+/// Year, month and day in UTC
+fn civil_date(secs: u64) -> (i64, i64, i64) {
+    let days = secs / 86_400;
+    // civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let mut session = Session::default();
+        session.prompt_sent("llama3:latest", "free disk space");
+        session.commands_received(&["df -h".to_string(), "du -sh *".to_string()]);
+        session.command_edited("du -sh *", "du -sh * | sort -h");
+        session.command_executed("du -sh * | sort -h", Some("du -sh *"), Some(0), Duration::from_millis(42), "4.0K\tsrc\n", "");
+        session.command_started("sleep 10 &", None);
+        session.command_skipped("df -h");
+        session.error("Ollama answered 500 Internal Server Error: out of memory");
+        session.new_conversation();
+
+        let json = serde_json::to_string(&session).unwrap();
+        assert!(json.contains(r#""type":"command_edited""#));
+        assert!(json.contains(r#""type":"command_executed","at":"#));
+        assert!(json.contains(r#""command":"du -sh * | sort -h","suggested":"du -sh *","exit_code":0,"duration_ms":42"#), "{}", json);
+        assert!(json.contains(r#""exit_code":null,"background":true"#));
+        // unedited commands leave it out
+        assert!(!json.contains(r#""suggested":null"#));
+        assert_eq!(serde_json::from_str::<Session>(&json).unwrap(), session);
+    }
+
+    #[test]
+    fn scripted_interaction() {
+        let mut session = Session::default();
+        session.prompt_sent("llama3:latest", "clean the build");
+        session.commands_received(&["rm -r build".to_string(), "npm run dev &".to_string(), "make".to_string()]);
+        session.command_executed("rm -r build", None, Some(1), Duration::ZERO, "", "rm: build: No such file or directory");
+        session.command_started("npm run dev &", None);
+        session.command_skipped("make");
+        let kinds: Vec<_> = session.events.iter().map(|event| serde_json::to_value(event).unwrap()["type"].as_str().unwrap().to_string()).collect();
+        assert_eq!(kinds, ["prompt_sent", "commands_received", "command_executed", "command_executed", "command_skipped"]);
+        let outcomes = [
+            ("rm -r build".to_string(), Outcome::Exited(Some(1))),
+            ("npm run dev &".to_string(), Outcome::Background),
+            ("make".to_string(), Outcome::Skipped),
+        ];
+        assert_eq!(session.outcomes(), outcomes);
+
+        // kept while the next prompt fails, and through other commands for the same one
+        session.prompt_sent("llama3:latest", "now rebuild");
+        session.error("connection refused");
+        session.commands_received(&["make clean".to_string()]);
+        assert_eq!(session.outcomes(), outcomes);
+        // gone once the next prompt is answered
+        session.prompt_sent("llama3:latest", "now rebuild");
+        session.commands_received(&["make".to_string()]);
+        assert!(session.outcomes().is_empty());
+        session.command_executed("make", None, Some(0), Duration::ZERO, "", "");
+        assert_eq!(session.outcomes(), [("make".to_string(), Outcome::Exited(Some(0)))]);
+        session.new_conversation();
+        assert!(session.outcomes().is_empty());
+    }
+
+    #[test]
+    fn long_output_is_truncated() {
+        let output = "é".repeat(MAX_OUTPUT_BYTES);
+        let kept = truncate(&output, MAX_OUTPUT_BYTES);
+        assert!(kept.starts_with(&"é".repeat(MAX_OUTPUT_BYTES / 2)));
+        assert!(kept.ends_with(&format!("({} more bytes)", MAX_OUTPUT_BYTES)));
+        assert_eq!(truncate("short", MAX_OUTPUT_BYTES), "short");
+
+        let summary = OutputSummary::new(&"x".repeat(MAX_OUTPUT_BYTES * 2), "denied");
+        assert_eq!(summary.stdout_bytes, MAX_OUTPUT_BYTES * 2);
+        assert_eq!(summary.stdout_sample(10), format!("xxxxxxxxxx\n… ({} more bytes)", MAX_OUTPUT_BYTES * 2 - 10));
+        assert_eq!(summary.stderr_sample(10), "denied");
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(file_timestamp(0), "19700101-000000");
+        assert_eq!(file_timestamp(1_709_251_199), "20240229-235959");
+        assert_eq!(date_time(1_709_251_199), "2024-02-29 23:59");
+        assert_eq!(clock_time(1_709_251_199), "23:59:59");
+    }
+}
@@ -1,363 +1,5985 @@
-use tui_input::Input;
-use ratatui::prelude::*;
-use ratatui::{
-    crossterm::{
-        event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
-        execute,
-        terminal::{
-            disable_raw_mode, enable_raw_mode, EnterAlternateScreen,
-            LeaveAlternateScreen,
-        },
-    },
-    widgets::{Block, Borders, List, ListItem, Paragraph},
-    DefaultTerminal, Frame,
-};
-use std::{error::Error, io};
-use std::any::TypeId;
-use std::cell::RefCell;
-use std::rc::Rc;
-use ratatui::text::Line;
-use tui_input::backend::crossterm::EventHandler;
-use serde::{Serialize, Deserialize};
-use std::env::current_dir;
-use std::path::PathBuf;
-use std::collections::VecDeque;
-use crate::backend::{Bclient, OllamaReq};
-use crate::shell::IShell;
-
-pub enum EditMode {
-    Input,  // In this mode, user interact with input box
-    Normal,  // This is the default mode, where user can exit or start editing
-    Shell,  // In this mode, user interact with spawned shell
-}
-
-pub struct App {
-    /// Current value of input box
-    input: Input,
-    input_mode: EditMode,
-    messages: OllamaReq,
-    /// Shell commands from LLM
-    shell_commands: VecDeque<String>,
-    shell: DummyShell,
-}
-
-pub struct DummyShell {
-    curr_path: PathBuf,
-    shell: IShell,
-    executed_command: String,
-    current_command: String,
-    sh_input: Rc<RefCell<Input>>,
-    sh_output: String,
-    executed: bool,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Config {
-    ollama_api: String,
-    model: String,
-    proxy: String,
-}
-
-impl Default for App {
-    fn default() -> Self {
-        App {
-            input: Input::default(),
-            input_mode: EditMode::Normal,
-            messages: OllamaReq::new("llama3:latest"),
-            shell_commands: VecDeque::new(),
-            shell: DummyShell::default(),
-        }
-    }
-}
-
-impl Default for DummyShell {
-    fn default() -> Self {
-        DummyShell {
-            curr_path: current_dir().unwrap(),
-            shell: IShell::new(),
-            executed_command: String::new(),
-            current_command: String::new(),
-            sh_input: Rc::new(RefCell::new(Input::default())),
-            sh_output: String::new(),
-            executed: false,
-        }
-    }
-}
-
-impl Default for Config {
-    fn default() -> Self {
-        Config {
-            ollama_api: String::from("http://localhost:11434/api/generate"),
-            model: String::from("llama3:latest"),
-            proxy: String::from(""),
-        }
-    }
-}
-
-impl DummyShell {
-    pub fn renew_path(&mut self) {
-        self.curr_path = current_dir().unwrap();
-    }
-
-    /// Showing current path like actual Shell did
-    pub fn get_path(&self) -> String {
-        let path = self.curr_path.to_string_lossy().into_owned();
-        path
-    }
-
-    fn input_reset(&self) {
-        self.sh_input.borrow_mut().reset();
-    }
-
-    
-}
-
-impl Config {
-    pub fn set_proxy(&mut self, proxy: String) {
-        self.proxy = proxy;
-    }
-
-    pub fn set_ollama_api(&mut self, api: String) {
-        self.ollama_api = api;
-    }
-
-    pub fn set_model(&mut self, model: String) {
-        self.model = model;
-    }
-
-    pub fn get_model(&self) -> &str {
-        self.model.as_str()
-    }
-
-    pub fn get_ollama_api(&self) -> &str {
-        self.ollama_api.as_str()
-    }
-
-    pub fn get_proxy(&self) -> &str {
-        self.proxy.as_str()
-    }
-
-    /// Check whether proxy in Config is set
-    pub fn uses_proxy(&self) -> bool {
-        if self.proxy == "".to_string() {
-            false
-        } else { true }
-    }
-}
-
-impl App {
-
-    pub fn new(model: &str) -> App {
-        App {
-            input: Input::default(),
-            input_mode: EditMode::Normal,
-            messages: OllamaReq::new(model),
-            shell_commands: VecDeque::new(),
-            shell: DummyShell::default(),
-        }
-    }
-
-    pub async fn run(&mut self, terminal: &mut DefaultTerminal, client: Bclient) -> io::Result<()> {
-        loop {
-            terminal.draw(|f| self.ui(f))?;
-
-            if let Event::Key(key) = event::read()? {
-                match self.input_mode {
-                    EditMode::Normal => match key.code {
-                        KeyCode::Char('q') => {
-                            return Ok(())
-                        },
-                        KeyCode::Char('a') => {
-                            self.input_mode = EditMode::Input;
-                        },
-                        KeyCode::Char('s') => {
-                            self.input_mode = EditMode::Shell;
-                        },
-                        _ => {}
-                    },
-                    EditMode::Input => match key.code {
-                        KeyCode::Enter => {
-                            self.messages.prompt(&self.input.value());
-                            let res = client.send_ollama(&self.messages).await.unwrap();
-                            self.recv_from(res);
-                            self.input.reset();
-                            let mut input_ref = self.shell.sh_input.borrow_mut();
-                            let comm = self.shell_commands.front().unwrap().clone();
-                            *input_ref = input_ref.clone().with_value(comm);
-                            drop(input_ref);
-                            self.input_mode = EditMode::Normal;  // return to normal mode to avoid sends empty msg
-                        },
-                        KeyCode::Esc => {
-                            self.input_mode = EditMode::Normal;
-                        },
-                        _ => {
-                            self.input.handle_event(&Event::Key(key));
-                        }
-                    },
-                    EditMode::Shell => match key.code {
-                        KeyCode::Enter => {
-                            let mut input_ref = self.shell.sh_input.borrow_mut();
-                            let comm = input_ref.value();
-                            self.shell.executed_command = comm.to_string();
-                            let out_msg = self.shell.shell.run_command(comm);
-                            self.shell.sh_output = match out_msg.code {
-                                Some(0) => { String::from_utf8(out_msg.stdout).unwrap() },
-                                None => { "This command has no output".to_string() },
-                                _ => { String::from_utf8(out_msg.stderr).unwrap() },
-                            };
-                            // println!("current output: {}", &self.shell.sh_output);
-                            let _ = if self.shell_commands.is_empty() { None }
-                                else { Some(self.shell_commands.pop_front().unwrap()) };
-                            if self.shell_commands.is_empty() {
-                                drop(input_ref);
-                                self.shell.input_reset();  // borrow mut here
-                            } else {
-                                let command = self.shell_commands.front().unwrap().clone();
-                                *input_ref = input_ref.clone().with_value(command);
-                            }
-                            self.input_mode = EditMode::Normal;
-                        },
-                        KeyCode::Esc => {
-                            self.input_mode = EditMode::Normal;
-                        }
-                        _ => {
-                            let mut input_ref = self.shell.sh_input.borrow_mut();
-                            input_ref.handle_event(&Event::Key(key));
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    fn ui(&mut self, frame: &mut Frame) {
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints(
-                [
-                    Constraint::Length(1),
-                    Constraint::Length(3),
-                    Constraint::Length(3),
-                    Constraint::Length(24),
-                ].as_ref(),
-            )
-            .split(frame.area());
-
-        let (msg, style) = match self.input_mode {
-            EditMode::Normal => (
-                vec![
-                    Span::raw("Press "),
-                    Span::styled("q", Style::default().add_modifier(Modifier::BOLD)),
-                    Span::raw(" to exit, "),
-                    Span::styled("a", Style::default().add_modifier(Modifier::BOLD)),
-                    Span::raw(" to ask AI, "),
-                    Span::styled("s", Style::default().add_modifier(Modifier::BOLD)),
-                    Span::raw(" to interact with Shell."),
-                ],
-                Style::default().add_modifier(Modifier::RAPID_BLINK),
-            ),
-            EditMode::Input => (
-                vec![
-                    Span::raw("Press "),
-                    Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
-                    Span::raw(" stop asking AI, "),
-                    Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
-                    Span::raw(" to send the message"),
-                ],
-                Style::default(),
-            ),
-            EditMode::Shell => (
-                vec![
-                    Span::raw("Press "),
-                    Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
-                    Span::raw(" stop Shell interaction, "),
-                    Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
-                    Span::raw(" to execute shell command"),
-                ],
-                Style::default(),
-            ),
-        };
-        let text = Text::from(Line::from(msg)).style(style);
-        let help_msg = Paragraph::new(text);
-        frame.render_widget(help_msg, chunks[0]);
-
-        /// Asking AI block
-        let width = chunks[0].width.max(3) - 1;  // 2 for boarders and 1 for cursor
-        let scroll = self.input.visual_scroll(width as usize);
-        let input = Paragraph::new(self.input.value())
-            .style(match self.input_mode {
-                EditMode::Normal => Style::default(),
-                EditMode::Input => Style::default().fg(Color::Yellow),
-                EditMode::Shell => Style::default().fg(Color::Blue),
-            })
-            .scroll((0, scroll as u16))
-            .block(Block::default().borders(Borders::ALL).title("Asking AI"));
-        frame.render_widget(input, chunks[1]);
-
-
-        /// Shell interact block
-        let path = self.shell.get_path();
-        /*
-        let sh_to_render = if self.shell_commands.is_empty() {
-            let input_ref = self.shell.sh_input.borrow_mut();
-            format!("{} > {}", path, input_ref.value())
-        } else {
-            let command = self.shell_commands.front().unwrap().clone();
-            let mut input_ref = self.shell.sh_input.borrow_mut();
-            *input_ref = input_ref.clone().with_value(command);
-            drop(input_ref);
-            format!("{} > {}", path, self.shell.sh_input.borrow().value())
-        };
-        */
-        let input_ref_val = self.shell.sh_input.borrow();
-        let sh_to_render = format!("{} > {}", path, input_ref_val.value());
-        drop(input_ref_val);
-        let sh_para = Paragraph::new(sh_to_render.clone())
-            .style(match self.input_mode {
-                EditMode::Normal => Style::default(),
-                EditMode::Input => Style::default().fg(Color::Blue),
-                EditMode::Shell => Style::default().fg(Color::Yellow),
-            })
-            .scroll((0, scroll as u16))
-            .block(Block::default().borders(Borders::ALL).title("Shell"));
-        frame.render_widget(sh_para, chunks[2]);
-
-        /// Shell output block
-        let binding = self.shell.sh_input.clone();
-        let val_ref = binding.borrow();
-        let sh_msg = format!("Command: {}, Output: {}", self.shell.executed_command, self.shell.sh_output);
-        let sh_output = Paragraph::new(sh_msg)
-            .style(match self.input_mode {
-                EditMode::Normal => Style::default(),
-                _ => Style::default().fg(Color::White),
-            })
-            .block(Block::default().borders(Borders::ALL).title("Output"));
-        frame.render_widget(sh_output, chunks[3]);
-
-        match self.input_mode {
-            EditMode::Normal => {},
-            // Hide cursor in normal mode
-            EditMode::Input => {
-                frame.set_cursor_position((
-                    chunks[1].x
-                        + (self.input.visual_cursor().max(scroll) - scroll) as u16
-                        + 1,
-                    chunks[1].y + 1
-                ))
-            },
-            EditMode::Shell => {
-                frame.set_cursor_position((
-                    chunks[2].x
-                        + (val_ref.visual_cursor().max(scroll + sh_to_render.len()) - scroll) as u16
-                        + 1,
-                    chunks[2].y + 1
-                ));
-            }
-        }
-    }
-
-    /// Store received commands
-    pub fn recv_from(&mut self, rece_vec: Vec<String>) {
-        self.shell_commands = VecDeque::from(rece_vec);
-    }
-}
+#[cfg(any(feature = "tui", feature = "cli"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+// kept here for code written before `Config` moved to `config`
+pub use crate::config::{Config, ProfileConfig, DEFAULT_MAX_PROMPT_TOKENS, DEFAULT_PROFILE};
+#[cfg(feature = "tui")]
+use {
+    std::io,
+    std::path::{Path, PathBuf},
+    std::time::Duration,
+    crate::autofix,
+    crate::shell::ShellType,
+    crate::suggestions::SuggestionMode,
+    crate::utils::MultilineCommands,
+    crate::workdir::ContextLevel,
+    tui_input::{Input, InputRequest},
+    tui_input::backend::crossterm::EventHandler,
+    ratatui::prelude::*,
+    ratatui::{
+        crossterm::event::{self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyEvent},
+        crossterm::execute,
+        crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen, SetTitle},
+        widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
+        DefaultTerminal, Frame,
+    },
+    ratatui::text::Line,
+    std::cell::RefCell,
+    std::rc::Rc,
+    std::sync::Arc,
+    std::sync::atomic::{AtomicBool, Ordering},
+    std::env::current_dir,
+    std::collections::VecDeque,
+    std::time::Instant,
+    tokio::sync::mpsc,
+    tokio::task::JoinHandle,
+    crate::ansi,
+    crate::interactive,
+    crate::utils::{commands_from_text, display_path, output_parts, sanitize_commands, OutputStream},
+    crate::backend::{self, Bclient, Generation, GenerationStats, ModelAnswer, OllamaReq},
+    crate::error::{AurishError, BackendError, ConfigError, FavoritesError, ShellInitError, TemplateError},
+    crate::history::PromptHistory,
+    crate::risk::danger_reason,
+    crate::keymap::{self, Action, Binding, Lookup},
+    crate::clipboard::Clipboard,
+    crate::theme::Theme,
+    crate::editor::PromptEditor,
+    crate::autofix::{AutoFix, Correction},
+    crate::pager::{self, PagedOutput},
+    crate::session::{self, Session},
+    crate::queue::{self, Param},
+    crate::queue_file::{QueueFile, SavedQueue},
+    crate::favorites::{self, Favorite, Favorites},
+    crate::templates::{self, Templates},
+    crate::complete::Completion,
+    crate::search::Search,
+    crate::diff::{diff_chars, Change},
+    crate::suggestions,
+    crate::workdir,
+    crate::project_context::ProjectContext,
+    crate::i18n::Language,
+    crate::shell::{strip_background, ExecutionPlan, IShell, ShellOutput},
+    crate::shell_history::ShellHistory,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditMode {
+    Input,  // In this mode, user interact with input box
+    Normal,  // This is the default mode, where user can exit or start editing
+    Shell,  // In this mode, user interact with spawned shell
+    Response,  // In this mode, user scrolls through the AI responses
+    Confirm,  // In this mode, a dangerous command waits for y/n before it runs
+    Models,  // In this mode, user picks the model prompts are sent to
+    Help,  // In this mode, the keybinding overlay is shown
+    Output,  // In this mode, user selects entries of the Output history
+    Search,  // In this mode, user types a query to search the Output history
+    Compare,  // In this mode, user picks which model's answer to queue
+    FavoriteName,  // In this mode, user types the name to save a command as a favorite under
+    Favorites,  // In this mode, user picks a favorite to insert into the Shell box
+    Params,  // In this mode, user types the values shared by the queued commands
+}
+
+#[cfg(feature = "tui")]
+/// How long to wait for a key press before redrawing
+const TICK_RATE: Duration = Duration::from_millis(100);
+#[cfg(feature = "tui")]
+/// Rows the Asking AI box grows to before it scrolls
+const MAX_PROMPT_ROWS: usize = 6;
+
+#[cfg(feature = "tui")]
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+#[cfg(feature = "tui")]
+/// Answer of the model to one prompt, sent back from the request task
+struct RequestResult {
+    id: u64,
+    prompt: String,
+    res: Result<Generation, BackendError>,
+}
+
+#[cfg(feature = "tui")]
+/// Answers of several models to the same prompt, sent back from the comparison task
+struct ComparisonResult {
+    id: u64,
+    prompt: String,
+    answers: Vec<ModelAnswer>,
+}
+
+#[cfg(feature = "tui")]
+type ResponseSender = mpsc::UnboundedSender<RequestResult>;
+#[cfg(feature = "tui")]
+type ResponseReceiver = mpsc::UnboundedReceiver<RequestResult>;
+#[cfg(feature = "tui")]
+type ComparisonSender = mpsc::UnboundedSender<ComparisonResult>;
+#[cfg(feature = "tui")]
+type ComparisonReceiver = mpsc::UnboundedReceiver<ComparisonResult>;
+#[cfg(feature = "tui")]
+type ModelsSender = mpsc::UnboundedSender<Result<Vec<String>, BackendError>>;
+#[cfg(feature = "tui")]
+type ModelsReceiver = mpsc::UnboundedReceiver<Result<Vec<String>, BackendError>>;
+#[cfg(feature = "tui")]
+type CommandSender = mpsc::UnboundedSender<CommandResult>;
+#[cfg(feature = "tui")]
+type CommandReceiver = mpsc::UnboundedReceiver<CommandResult>;
+/// Configs read again after their file changed, see `App::with_config_reloads`
+#[cfg(feature = "tui")]
+pub type ConfigReloads = mpsc::UnboundedReceiver<Result<ConfigReload, ConfigError>>;
+
+/// A config file changed under the TUI, with the overrides of the command line applied
+#[cfg(feature = "tui")]
+pub struct ConfigReload {
+    pub config: Config,
+    /// Theme of `config` as the terminal can show it
+    pub theme: Theme,
+}
+
+#[cfg(feature = "tui")]
+/// Request to the model that has not been answered yet
+struct PendingRequest {
+    id: u64,
+    started: Instant,
+    handle: JoinHandle<()>,
+    /// Asks the last prompt again, the answer must not replace the queue unasked
+    regenerate: bool,
+    /// Asks for a corrected command, see `autofix`
+    fix: bool,
+    /// Set once `/api/ps` tells the model is not in memory, so Ollama loads it first
+    loading: Arc<AtomicBool>,
+}
+
+#[cfg(feature = "tui")]
+impl PendingRequest {
+    /// Spinner and elapsed time, e.g. `⠹ 2.3s (Esc to cancel)`, and whether the model is
+    /// being loaded
+    fn status(&self) -> String {
+        let loading = match self.loading.load(Ordering::Relaxed) {
+            true => ", loading the model into memory, this can take a while…",
+            false => "",
+        };
+        format!("{} {}{} (Esc to cancel)", spinner(self.started), format_elapsed(self.started.elapsed()), loading)
+    }
+}
+
+#[cfg(feature = "tui")]
+/// How long the last answer took, shown in the status line until the next request
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Timing {
+    /// From sending the prompt to receiving the answer
+    elapsed: Duration,
+    stats: GenerationStats,
+}
+
+#[cfg(feature = "tui")]
+impl Timing {
+    /// e.g. `answered in 3.2s, 41.5 tok/s`, and how long loading the model took if it was
+    /// not in memory
+    fn status(&self) -> String {
+        let mut status = format!("answered in {}", format_elapsed(self.elapsed));
+        if self.stats.load_duration >= Duration::from_secs(1) {
+            status += &format!(", {} of it loading the model", format_elapsed(self.stats.load_duration));
+        }
+        if let Some(speed) = self.stats.tokens_per_sec() {
+            status += &format!(", {:.1} tok/s", speed);
+        }
+        status
+    }
+}
+
+/// Seed for a regenerated answer, different on every call
+#[cfg(any(feature = "tui", feature = "cli"))]
+pub(crate) fn new_seed() -> u64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos() as u64);
+    // splitmix64 finalizer, so calls close in time still get unrelated seeds
+    let mut seed = nanos.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    seed = (seed ^ (seed >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    seed = (seed ^ (seed >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (seed ^ (seed >> 31)) % 1_000_000_000
+}
+
+#[cfg(feature = "tui")]
+/// Spinner frame for something that started at `started`
+fn spinner(started: Instant) -> char {
+    SPINNER[(started.elapsed().as_millis() / TICK_RATE.as_millis()) as usize % SPINNER.len()]
+}
+
+#[cfg(feature = "tui")]
+/// Duration for status lines: `0.4s`, `12.3s`, `2m05s`
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 60 {
+        format!("{:.1}s", elapsed.as_secs_f32())
+    } else {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(feature = "tui")]
+/// A command run through the Shell box and what it printed
+pub struct ExecutedCommand {
+    pub command: String,
+    /// `None` if the command was not run to completion (skipped, started as a job, ...)
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration: Duration,
+    /// Where the output went if it was too long to show in full, see `pager`
+    pub paged: Option<PagedOutput>,
+    /// The suggested command, if `command` is an edit of it
+    pub suggested: Option<String>,
+    /// Ran attached to the terminal, so `stdout` and `stderr` are empty
+    pub interactive: bool,
+}
+
+#[cfg(feature = "tui")]
+impl ExecutedCommand {
+    /// Entry for something that happened to a command without running it
+    fn note(command: &str, message: String) -> Self {
+        ExecutedCommand {
+            command: command.to_string(),
+            exit_code: None,
+            stdout: message,
+            stderr: String::new(),
+            duration: Duration::ZERO,
+            paged: None,
+            suggested: None,
+            interactive: false,
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+/// Something waiting for the user to press y or n
+enum Confirmation {
+    /// Command that looks dangerous, with the reason why, run on the terminal if `interactive`
+    Dangerous { command: String, reason: String, interactive: bool },
+    /// Command starting with `sudo`, which would wait for a password nobody can type
+    SudoPassword { command: String },
+    /// Run every queued command
+    RunAll { count: usize },
+    /// Quit although work would be lost, listing what
+    Quit { reasons: Vec<String> },
+    /// Replace the `queued` commands with regenerated ones
+    ReplaceQueue { queued: usize, commands: Vec<String> },
+    /// Run the correction auto-fix asked for, the selected command by now
+    AutoFix { command: String, failed: String, attempt: String },
+    /// Queue the commands an earlier session left
+    RestoreQueue { saved: SavedQueue },
+    /// Save `command` as the favorite `name`, in place of the `replaced` command
+    ReplaceFavorite { name: String, command: String, replaced: String },
+}
+
+#[cfg(feature = "tui")]
+/// Something to run with the TUI suspended, see `interactive`
+enum TtyRun {
+    /// Run the command attached to the terminal
+    Command(String),
+    /// Let sudo ask for the password with `sudo -v`, then run the command as usual
+    SudoThen(String),
+}
+
+#[cfg(feature = "tui")]
+/// Command run in the background by "run all" and what it printed
+struct CommandResult {
+    command: String,
+    output: ShellOutput,
+    duration: Duration,
+}
+
+#[cfg(feature = "tui")]
+/// Progress of running the whole queue
+struct RunAll {
+    total: usize,
+    done: usize,
+    started: Instant,
+    /// When the command running in the background started
+    command_started: Option<Instant>,
+    /// Stop once the running command finishes
+    stopping: bool,
+}
+
+#[cfg(feature = "tui")]
+impl RunAll {
+    /// Spinner and progress, e.g. `⠹ running 2/5`
+    fn status(&self) -> String {
+        let stopping = if self.stopping { ", stopping" } else { "" };
+        format!("{} running {}/{}{}", spinner(self.started), (self.done + 1).min(self.total), self.total, stopping)
+    }
+}
+
+#[cfg(feature = "tui")]
+/// State of the model switcher popup
+/// Panel shown in the Output box until something is asked or run
+struct Welcome {
+    /// Endpoint of the config, where the startup check is sent
+    endpoint: String,
+}
+
+#[cfg(feature = "tui")]
+struct ModelPicker {
+    /// Models on the server, `None` while the list is loading
+    models: Option<Result<Vec<String>, String>>,
+    list_state: ListState,
+    /// Model name typed by hand, used when the list could not be fetched
+    input: Input,
+    handle: JoinHandle<()>,
+}
+
+#[cfg(feature = "tui")]
+impl ModelPicker {
+    /// Fetched model list, if it loaded and is not empty
+    fn listed(&self) -> Option<&[String]> {
+        match &self.models {
+            Some(Ok(models)) if !models.is_empty() => Some(models),
+            _ => None,
+        }
+    }
+
+    /// Model the user would switch to by pressing Enter
+    fn chosen(&self) -> Option<String> {
+        match self.listed() {
+            Some(models) => self.list_state.selected().and_then(|idx| models.get(idx)).cloned(),
+            None if self.models.is_some() && !self.input.value().trim().is_empty() => {
+                Some(self.input.value().trim().to_string())
+            },
+            None => None,
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+/// Command being saved as a favorite and the name typed for it
+struct FavoriteDraft {
+    command: String,
+    name: Input,
+}
+
+#[cfg(feature = "tui")]
+/// Values shared by the queued commands, typed one after the other in the Shared values popup
+struct ParamsDraft {
+    /// The queue with placeholders, see `queue::parameterize`
+    templates: Vec<String>,
+    params: Vec<Param>,
+    /// Index in `params` of the value being typed
+    current: usize,
+    value: Input,
+}
+
+#[cfg(feature = "tui")]
+impl ParamsDraft {
+    /// Start typing the value at `current`, from the word found
+    fn edit(&mut self, current: usize) {
+        self.current = current;
+        self.value = Input::new(self.params[current].value.clone());
+    }
+}
+
+#[cfg(feature = "tui")]
+/// State of the favorites popup
+struct FavoritesList {
+    /// Saved favorites, or why they could not be read
+    favorites: Result<Vec<Favorite>, String>,
+    list_state: ListState,
+}
+
+#[cfg(feature = "tui")]
+impl FavoritesList {
+    fn new(favorites: Result<Vec<Favorite>, String>) -> Self {
+        let mut list_state = ListState::default();
+        list_state.select(favorites.as_ref().ok().filter(|favorites| !favorites.is_empty()).map(|_| 0));
+        FavoritesList { favorites, list_state }
+    }
+
+    fn selected(&self) -> Option<&Favorite> {
+        let favorites = self.favorites.as_ref().ok()?;
+        self.list_state.selected().and_then(|idx| favorites.get(idx))
+    }
+}
+
+#[cfg(feature = "tui")]
+/// Answers of the current model and `compare_model` to the last prompt, shown in the
+/// Response block until one of them is queued or the comparison is dismissed
+struct Comparison {
+    prompt: String,
+    answers: Vec<ModelAnswer>,
+    /// Answer whose commands Enter queues
+    selected: usize,
+}
+
+#[cfg(feature = "tui")]
+/// One prompt sent to the model and what came back
+pub struct Exchange {
+    pub prompt: String,
+    pub commands: Vec<String>,
+}
+
+#[cfg(feature = "tui")]
+pub struct App {
+    /// Current value of input box
+    input: PromptEditor,
+    input_mode: EditMode,
+    messages: OllamaReq,
+    /// Shell commands from LLM
+    shell_commands: VecDeque<String>,
+    /// Where the queue is kept across restarts
+    queue_file: QueueFile,
+    /// Where favorites are saved, `None` without a data directory
+    favorites: Option<Favorites>,
+    /// Command waiting for a name in the Save favorite popup
+    favorite_draft: Option<FavoriteDraft>,
+    /// Open favorites popup
+    favorites_list: Option<FavoritesList>,
+    /// Open Shared values popup
+    params_draft: Option<ParamsDraft>,
+    /// File of the prompt templates, `None` without a config directory
+    templates_path: Option<PathBuf>,
+    /// Estimated tokens a prompt may take with its context, see `OllamaReq::fit`
+    max_prompt_tokens: usize,
+    /// Selected entry of `shell_commands`, the one shown in the Shell box
+    queue_state: ListState,
+    /// Every prompt and response of this session, oldest first
+    conversation: Vec<Exchange>,
+    /// Prompts submitted in this and earlier sessions, recalled with Up/Down
+    prompt_history: PromptHistory,
+    /// How many lines the Response block is scrolled up from the newest entry
+    response_scroll: u16,
+    /// Request currently waiting for the model
+    pending: Option<PendingRequest>,
+    /// Model the `c` key compares the current one with
+    compare_model: Option<String>,
+    /// What `recv_from` does with commands of several lines
+    multiline: MultilineCommands,
+    /// What prompts tell about the directory the commands run in
+    context_level: ContextLevel,
+    /// Notes on the project of the shell's directory, see `project_context`
+    project_context: ProjectContext,
+    /// Send commands that fail back to the model, see `with_auto_fix`
+    auto_fix: bool,
+    /// Show what the command in the Shell box would do under it, see `toggle_plan`
+    show_plan: bool,
+    /// Language of the status line and the system prompt, see `i18n`
+    language: Language,
+    auto_fix_attempts: u32,
+    /// Attempts at fixing the last command that failed
+    fixing: Option<AutoFix>,
+    /// Prompt of the auto-fix request to send once `perform` returns
+    fix_due: Option<String>,
+    /// Comparison waiting for the models
+    comparing: Option<PendingRequest>,
+    /// Answers being compared in Compare mode
+    comparison: Option<Comparison>,
+    /// Prompt of the last request, for editing or regenerating it
+    last_prompt: Option<String>,
+    /// Timing of the last answer
+    timing: Option<Timing>,
+    request_id: u64,
+    /// Last failure, shown as a banner until the next key press
+    error: Option<String>,
+    /// Confirmation of the last action, shown like `error` but not as a failure
+    notice: Option<String>,
+    /// Question shown in the confirmation popup
+    confirm: Option<Confirmation>,
+    /// Welcome panel, `None` if it is turned off
+    welcome: Option<Welcome>,
+    /// Models on the server, `None` while the startup check is running
+    health: Option<Result<Vec<String>, String>>,
+    /// When aurish started, until the first frame is drawn, see `with_startup_timer`
+    startup: Option<Instant>,
+    /// Queue being run by "run all"
+    run_all: Option<RunAll>,
+    /// Extra substrings that make a command require confirmation
+    dangerous_patterns: Vec<String>,
+    /// Open model switcher popup
+    model_picker: Option<ModelPicker>,
+    /// File the chosen model is saved to, see `with_config_path`
+    config_path: Option<PathBuf>,
+    /// Configs read again after the config file changed
+    config_reloads: Option<ConfigReloads>,
+    /// Every key the TUI reacts to, also shown by the help overlay
+    keymap: Vec<Binding>,
+    /// Keys of a sequence typed so far, like the first `g` of `gg`
+    pending_keys: Vec<KeyEvent>,
+    /// Hand the terminal to the pager before the next draw, see `open_pager`
+    pager_requested: bool,
+    /// Command to run with the TUI suspended before the next draw, see `run_on_terminal`
+    tty_due: Option<TtyRun>,
+    /// Whether sudo has cached credentials, asked before running a `sudo` command
+    sudo_cached: fn() -> bool,
+    /// How many lines the help overlay is scrolled down
+    help_scroll: u16,
+    /// Entry of the Output history selected in Output mode
+    output_selected: Option<usize>,
+    clipboard: Clipboard,
+    theme: Theme,
+    /// Transcript of everything asked and run so far
+    session: Session,
+    /// Where sessions are exported to, see `with_session_export`
+    session_dir: Option<PathBuf>,
+    export_on_quit: bool,
+    /// Set by a signal to end the program, see `with_shutdown`
+    shutdown: Arc<AtomicBool>,
+    /// Whether `quit` ran already
+    quit_done: bool,
+    /// Last file name completion in the Shell box, continued by the next Tab
+    completion: Option<Completion>,
+    /// Search in the Output history, kept after the query is typed for n/N
+    search: Option<Search>,
+    search_input: Input,
+    /// Mode to go back to once the query is typed
+    search_return: EditMode,
+    /// Scroll the current match into view on the next draw
+    search_jump: bool,
+    shell: DummyShell,
+}
+
+#[cfg(feature = "tui")]
+pub struct DummyShell {
+    curr_path: PathBuf,
+    /// Shared with the tasks "run all" executes commands on
+    shell: Arc<IShell>,
+    sh_input: Rc<RefCell<Input>>,
+    /// Every command executed in this session, oldest first
+    history: Vec<ExecutedCommand>,
+    /// How many lines the Output block is scrolled up from the newest entry
+    history_scroll: u16,
+}
+
+#[cfg(feature = "tui")]
+impl Default for App {
+    fn default() -> Self {
+        // detected once, for the shell and the system prompt
+        let shell = DummyShell::default();
+        App {
+            input: PromptEditor::default(),
+            input_mode: EditMode::Normal,
+            messages: OllamaReq::for_shell("llama3:latest", shell.shell.shell_type()),
+            shell_commands: VecDeque::new(),
+            queue_file: QueueFile::default(),
+            favorites: favorites::default_path().map(Favorites::new),
+            favorite_draft: None,
+            favorites_list: None,
+            params_draft: None,
+            templates_path: templates::default_path(),
+            max_prompt_tokens: DEFAULT_MAX_PROMPT_TOKENS,
+            queue_state: ListState::default(),
+            conversation: Vec::new(),
+            prompt_history: PromptHistory::default(),
+            response_scroll: 0,
+            pending: None,
+            compare_model: None,
+            multiline: MultilineCommands::default(),
+            context_level: ContextLevel::default(),
+            project_context: ProjectContext::default(),
+            auto_fix: false,
+            show_plan: false,
+            language: Language::default(),
+            auto_fix_attempts: autofix::DEFAULT_ATTEMPTS,
+            fixing: None,
+            fix_due: None,
+            comparing: None,
+            comparison: None,
+            last_prompt: None,
+            timing: None,
+            request_id: 0,
+            error: None,
+            notice: None,
+            confirm: None,
+            welcome: None,
+            health: None,
+            startup: None,
+            run_all: None,
+            dangerous_patterns: Vec::new(),
+            model_picker: None,
+            config_path: None,
+            config_reloads: None,
+            keymap: keymap::default_bindings(),
+            pending_keys: Vec::new(),
+            pager_requested: false,
+            tty_due: None,
+            sudo_cached: interactive::sudo_cached,
+            help_scroll: 0,
+            output_selected: None,
+            clipboard: Clipboard::default(),
+            theme: Theme::default(),
+            session: Session::default(),
+            session_dir: session::default_dir(),
+            export_on_quit: false,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            quit_done: false,
+            completion: None,
+            search: None,
+            search_input: Input::default(),
+            search_return: EditMode::Normal,
+            search_jump: false,
+            shell,
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+impl Default for DummyShell {
+    fn default() -> Self {
+        DummyShell {
+            curr_path: current_dir().unwrap(),
+            // the TUI runs in raw mode, where commands never got Ctrl-C anyway
+            shell: Arc::new(IShell::new().with_force_color(true).with_process_groups(true)),
+            sh_input: Rc::new(RefCell::new(Input::default())),
+            history: Vec::new(),
+            history_scroll: 0,
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+impl DummyShell {
+    /// Follow the directory the IShell moved to with `cd`
+    pub fn renew_path(&mut self) {
+        self.curr_path = self.shell.current_dir();
+    }
+
+    /// Showing current path like actual Shell did, with the home directory shown as `~`
+    pub fn get_path(&self) -> String {
+        display_path(&self.curr_path, None)
+    }
+
+    /// Like `get_path`, collapsing middle directories so the path fits in `max_width` columns
+    pub fn get_path_within(&self, max_width: usize) -> String {
+        display_path(&self.curr_path, Some(max_width))
+    }
+
+    fn input_reset(&self) {
+        self.sh_input.borrow_mut().reset();
+    }
+
+    
+}
+
+#[cfg(feature = "tui")]
+impl App {
+
+    pub fn new(model: &str) -> App {
+        // detected once, for the shell and the system prompt
+        let shell = DummyShell::default();
+        App {
+            input: PromptEditor::default(),
+            input_mode: EditMode::Normal,
+            messages: OllamaReq::for_shell(model, shell.shell.shell_type()),
+            shell_commands: VecDeque::new(),
+            queue_file: QueueFile::default(),
+            favorites: favorites::default_path().map(Favorites::new),
+            favorite_draft: None,
+            favorites_list: None,
+            params_draft: None,
+            templates_path: templates::default_path(),
+            max_prompt_tokens: DEFAULT_MAX_PROMPT_TOKENS,
+            queue_state: ListState::default(),
+            conversation: Vec::new(),
+            prompt_history: PromptHistory::default(),
+            response_scroll: 0,
+            pending: None,
+            compare_model: None,
+            multiline: MultilineCommands::default(),
+            context_level: ContextLevel::default(),
+            project_context: ProjectContext::default(),
+            auto_fix: false,
+            show_plan: false,
+            language: Language::default(),
+            auto_fix_attempts: autofix::DEFAULT_ATTEMPTS,
+            fixing: None,
+            fix_due: None,
+            comparing: None,
+            comparison: None,
+            last_prompt: None,
+            timing: None,
+            request_id: 0,
+            error: None,
+            notice: None,
+            confirm: None,
+            welcome: None,
+            health: None,
+            startup: None,
+            run_all: None,
+            dangerous_patterns: Vec::new(),
+            model_picker: None,
+            config_path: None,
+            config_reloads: None,
+            keymap: keymap::default_bindings(),
+            pending_keys: Vec::new(),
+            pager_requested: false,
+            tty_due: None,
+            sudo_cached: interactive::sudo_cached,
+            help_scroll: 0,
+            output_selected: None,
+            clipboard: Clipboard::default(),
+            theme: Theme::default(),
+            session: Session::default(),
+            session_dir: session::default_dir(),
+            export_on_quit: false,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            quit_done: false,
+            completion: None,
+            search: None,
+            search_input: Input::default(),
+            search_return: EditMode::Normal,
+            search_jump: false,
+            shell,
+        }
+    }
+
+    /// Require confirmation for commands containing any of `patterns`,
+    /// on top of the built-in dangerous command checks
+    pub fn with_dangerous_patterns(mut self, patterns: Vec<String>) -> Self {
+        self.dangerous_patterns = patterns;
+        self
+    }
+
+    /// React to `bindings` instead of the built-in keys, see `keymap::by_name`
+    pub fn with_keymap(mut self, bindings: Vec<Binding>) -> Self {
+        self.keymap = bindings;
+        self
+    }
+
+    pub fn with_theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    /// Let the model switcher save the chosen model to the config file at `path`
+    pub fn with_config_path(mut self, path: PathBuf) -> Self {
+        self.config_path = Some(path);
+        self
+    }
+
+    /// Take up the model, endpoint, theme and keybindings of the configs sent on `reloads`,
+    /// see `Config::watch`
+    pub fn with_config_reloads(mut self, reloads: ConfigReloads) -> Self {
+        self.config_reloads = Some(reloads);
+        self
+    }
+
+    /// Quit like `q` without asking once `flag` is set, checked every tick, see
+    /// `shutdown_on_signals`
+    pub fn with_shutdown(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.shutdown = flag;
+        self
+    }
+
+    /// The shell commands run in, shared with the tasks that run them
+    pub fn shell(&self) -> Arc<IShell> {
+        Arc::clone(&self.shell.shell)
+    }
+
+    /// Model whose answer `c` shows next to the current model's, nothing to compare if `None`
+    pub fn with_compare_model(mut self, model: Option<String>) -> Self {
+        self.compare_model = model;
+        self
+    }
+
+    /// Describe the system in the system prompt, see `system_info`. On by default.
+    pub fn with_system_info(mut self, include: bool) -> Self {
+        self.messages.set_system_info(include);
+        self
+    }
+
+    /// Show the status line and ask the model in `language`, see `i18n`. English by default.
+    pub fn with_language(mut self, language: Language) -> Self {
+        self.language = language;
+        self.messages.set_language(language);
+        self
+    }
+
+    /// Ask for as many commands as `mode` wants, see `suggestions`
+    pub fn with_suggestion_mode(mut self, mode: SuggestionMode) -> Self {
+        self.messages.set_suggestion_mode(mode);
+        self
+    }
+
+    /// Split suggested commands of several lines, the default, or join them with ` && `
+    pub fn with_multiline_commands(mut self, multiline: MultilineCommands) -> Self {
+        self.multiline = multiline;
+        self
+    }
+
+    /// Tell the model about the current directory with each prompt, see `workdir`
+    pub fn with_context_level(mut self, level: ContextLevel) -> Self {
+        self.context_level = level;
+        self
+    }
+
+    /// Add the commands that succeed to the user's shell history, see `shell_history`
+    pub fn with_shell_history(self, enabled: bool) -> Self {
+        if enabled {
+            self.shell.shell.set_history(ShellHistory::for_shell(self.shell.shell.shell_type()));
+        }
+        self
+    }
+
+    /// Run and ask for commands of `shell` instead of the detected one, if any
+    pub fn with_shell_type(mut self, shell: Option<ShellType>) -> Self {
+        if let Some(shell) = shell {
+            self.shell.shell.set_shell_type(shell);
+            self.messages.set_shell(shell);
+        }
+        self
+    }
+
+    /// Run commands in the user's locale, see `IShell::set_inherit_locale`
+    pub fn with_inherited_locale(self, inherit: bool) -> Self {
+        self.shell.shell.set_inherit_locale(inherit);
+        self
+    }
+
+    /// Keep `cd` inside `root`, see `IShell::set_sandbox_root`
+    pub fn with_sandbox_root(self, root: Option<&Path>) -> Result<Self, ShellInitError> {
+        self.shell.shell.set_sandbox_root(root)?;
+        Ok(self)
+    }
+
+    /// Log at trace level how long after `started` the first frame was drawn, to see
+    /// what slows the startup down
+    pub fn with_startup_timer(mut self, started: Instant) -> Self {
+        self.startup = Some(started);
+        self
+    }
+
+    /// Show the welcome panel for the Ollama endpoint `endpoint` until the first prompt is
+    /// sent or command run, and check at startup whether it answers. Nothing if `None`.
+    pub fn with_welcome(mut self, endpoint: Option<String>) -> Self {
+        self.welcome = endpoint.map(|endpoint| Welcome { endpoint });
+        self
+    }
+
+    /// Keep the queue in the file at `path` instead of the data directory, see `queue_file`
+    pub fn with_queue_file(mut self, path: PathBuf) -> Self {
+        self.queue_file = QueueFile::new(Some(path));
+        self
+    }
+
+    /// Keep favorites in the file at `path` instead of the data directory, see `favorites`
+    pub fn with_favorites(mut self, path: PathBuf) -> Self {
+        self.favorites = Some(Favorites::new(path));
+        self
+    }
+
+    /// Leave context out of prompts estimated to take more than `max_tokens`, see `OllamaReq::fit`
+    pub fn with_max_prompt_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_prompt_tokens = max_tokens;
+        self
+    }
+
+    /// Ask the model for a corrected command when one run from the Shell box fails, up to
+    /// `attempts` times in a row. Off by default, `A` turns it on and off.
+    pub fn with_auto_fix(mut self, enabled: bool, attempts: u32) -> Self {
+        self.auto_fix = enabled;
+        self.auto_fix_attempts = attempts;
+        self
+    }
+
+    /// Read prompt templates from the file at `path` instead of the config directory, see `templates`
+    pub fn with_templates(mut self, path: PathBuf) -> Self {
+        self.templates_path = Some(path);
+        self
+    }
+
+    /// Export session transcripts to `dir` instead of the data directory,
+    /// and also when quitting if `on_quit` is set
+    pub fn with_session_export(mut self, dir: Option<PathBuf>, on_quit: bool) -> Self {
+        if dir.is_some() {
+            self.session_dir = dir;
+        }
+        self.export_on_quit = on_quit;
+        self
+    }
+
+    pub async fn run(&mut self, terminal: &mut DefaultTerminal, client: Bclient) -> Result<(), AurishError> {
+        let res = self.event_loop(terminal, client).await;
+        if !self.shutdown.load(Ordering::SeqCst) {
+            return res;
+        }
+        // a closed terminal window can fail the drawing before the loop sees the signal
+        if let Err(e) = res {
+            log::debug!("event loop ended by shutdown: {}", e);
+        }
+        Ok(self.quit()?)
+    }
+
+    async fn event_loop(&mut self, terminal: &mut DefaultTerminal, mut client: Bclient) -> Result<(), AurishError> {
+        let (res_tx, mut res_rx) = mpsc::unbounded_channel();
+        let (models_tx, mut models_rx) = mpsc::unbounded_channel();
+        let (commands_tx, mut commands_rx) = mpsc::unbounded_channel();
+        let (compare_tx, mut compare_rx) = mpsc::unbounded_channel();
+        let (health_tx, mut health_rx) = mpsc::unbounded_channel();
+        let mut window_title = String::new();
+        self.offer_saved_queue();
+        self.refresh_project_context();
+        self.check_health(&client, &health_tx);
+        loop {
+            if self.shutdown.load(Ordering::SeqCst) {
+                return Ok(self.quit()?);
+            }
+            self.save_queue();
+            self.poll_responses(&mut res_rx);
+            self.poll_comparisons(&mut compare_rx);
+            self.poll_models(&mut models_rx);
+            self.poll_health(&mut health_rx);
+            self.poll_config_reloads(&mut client, &health_tx);
+            self.poll_commands(&mut commands_rx, &commands_tx);
+            terminal.draw(|f| self.ui(f))?;
+            if let Some(startup) = self.startup.take() {
+                log::trace!("first frame drawn {:?} after start", startup.elapsed());
+            }
+            if self.window_title() != window_title {
+                window_title = self.window_title();
+                execute!(terminal.backend_mut(), SetTitle(&window_title))?;
+            }
+
+            // poll instead of blocking on read, so pending requests keep being redrawn
+            if !event::poll(TICK_RATE)? {
+                continue;
+            }
+            match event::read()? {
+                Event::Key(key) => {
+                    // a key press acknowledges the error banner
+                    self.error = None;
+                    self.notice = None;
+                    match self.resolve_key(key) {
+                        Lookup::Action(action) if self.confirms_quit(action) => return Ok(self.quit()?),
+                        Lookup::Action(action) => {
+                            self.perform(action, &client, &res_tx, &models_tx, &commands_tx, &compare_tx);
+                            if std::mem::take(&mut self.pager_requested) {
+                                self.open_pager(terminal)?;
+                            }
+                            if let Some(run) = self.tty_due.take() {
+                                self.run_on_terminal(terminal, run)?;
+                            }
+                        },
+                        Lookup::Prefix => {},
+                        Lookup::Unbound => self.type_key(Event::Key(key)),
+                    }
+                },
+                Event::Paste(text) => self.paste(&text),
+                _ => {},
+            }
+        }
+    }
+
+    /// Look `key` up together with the keys of an unfinished sequence before it.
+    /// A key that breaks a sequence off counts on its own.
+    fn resolve_key(&mut self, key: KeyEvent) -> Lookup {
+        self.pending_keys.push(key);
+        let found = keymap::lookup_keys(&self.keymap, self.input_mode, &self.pending_keys);
+        if found == Lookup::Prefix {
+            return found;
+        }
+        let broken_off = found == Lookup::Unbound && self.pending_keys.len() > 1;
+        self.pending_keys.clear();
+        if broken_off {
+            self.resolve_key(key)
+        } else {
+            found
+        }
+    }
+
+    /// Carry out a bound action
+    fn perform(
+        &mut self,
+        action: Action,
+        client: &Bclient,
+        res_tx: &ResponseSender,
+        models_tx: &ModelsSender,
+        commands_tx: &CommandSender,
+        compare_tx: &ComparisonSender,
+    ) {
+        match action {
+            // handled by the event loop
+            Action::Quit => {},
+            Action::AskAi => self.input_mode = EditMode::Input,
+            Action::EditShell => self.input_mode = EditMode::Shell,
+            Action::ReadResponses => self.input_mode = EditMode::Response,
+            Action::SwitchModel => self.open_model_picker(client, models_tx),
+            Action::ToggleHelp => self.toggle_help(),
+            Action::Back => {
+                if self.pending.is_some() || self.comparing.is_some() {
+                    self.cancel_request();
+                } else if let Some(run) = self.run_all.as_mut() {
+                    run.stopping = true;
+                } else if self.input_mode == EditMode::Normal && self.search.is_some() {
+                    self.search = None;
+                } else {
+                    self.input_mode = EditMode::Normal;
+                }
+            },
+            Action::SelectPrev => self.select_prev(),
+            Action::SelectNext => self.select_next(),
+            Action::MoveSelectedUp => self.move_selected_up(),
+            Action::MoveSelectedDown => self.move_selected_down(),
+            Action::DeleteSelected => { self.advance_queue(); },
+            Action::SkipSelected => self.skip_selected(),
+            Action::DiscardQueue => self.discard_queue(),
+            Action::RunAll => self.ask_run_all(),
+            Action::ScrollOutput(lines) => self.scroll_history(lines),
+            Action::FocusOutput => self.focus_output(),
+            Action::SelectOutput(step) => self.select_output(step),
+            Action::CopyOutput => self.copy_output(),
+            Action::CopyCommand => self.copy_command(),
+            Action::PasteCommands => self.paste_commands(),
+            Action::OpenPager => self.request_pager(),
+            Action::ResetDirectory => self.reset_directory(),
+            Action::StartSearch => self.start_search(),
+            Action::NextMatch => self.jump_to_match(true),
+            Action::PrevMatch => self.jump_to_match(false),
+            Action::ConfirmSearch => self.end_search(true),
+            Action::ClearSearch => self.end_search(false),
+            Action::ExportSession => {
+                match self.export_session() {
+                    Ok(path) => self.notice = Some(format!("Session saved to {}", path.display())),
+                    Err(e) => self.error = Some(format!("Could not save the session: {}", e)),
+                }
+            },
+            Action::ScrollResponse(lines) => self.scroll_response(lines),
+            Action::ScrollHelp(lines) => {
+                self.help_scroll = (self.help_scroll as i32 + lines).clamp(0, u16::MAX as i32) as u16;
+            },
+            Action::EditLastPrompt => self.edit_last_prompt(),
+            Action::Regenerate => self.regenerate(client, res_tx),
+            Action::SendPrompt => {
+                // Input mode is left once the answer is queued, see `finish_request`
+                if self.pending.is_none() && self.comparing.is_none() {
+                    self.send_request(client, res_tx);
+                }
+            },
+            // the prompt being answered stays as it was sent
+            Action::NewLine | Action::PrevPrompt | Action::NextPrompt if self.prompt_in_flight() => {},
+            Action::NewLine => self.input.insert_newline(),
+            Action::PrevPrompt => {
+                if !self.input.move_up() {
+                    self.recall_prev_prompt();
+                }
+            },
+            Action::NextPrompt => {
+                if !self.input.move_down() {
+                    self.recall_next_prompt();
+                }
+            },
+            Action::ExecuteCommand => {
+                if self.run_all.is_some() {
+                    self.error = Some("Wait for the queued commands to finish".to_string());
+                    return;
+                }
+                let comm = self.shell.sh_input.borrow().value().to_string();
+                self.submit_command(comm);
+            },
+            Action::ExecuteInteractive => {
+                if self.run_all.is_some() {
+                    self.error = Some("Wait for the queued commands to finish".to_string());
+                    return;
+                }
+                let comm = self.shell.sh_input.borrow().value().to_string();
+                self.submit_interactive(comm);
+            },
+            Action::TogglePlan => self.toggle_plan(),
+            Action::Complete => self.complete_path(),
+            Action::CompleteTemplate => self.complete_template(),
+            Action::ConfirmRun => self.accept_confirmation(commands_tx),
+            Action::ConfirmCancel => self.reject_confirmation(),
+            Action::ConfirmAlternative => self.run_without_password(),
+            Action::SelectModel(step) => self.select_model(step),
+            Action::UseModel => self.choose_model(false),
+            Action::SaveModel => self.choose_model(true),
+            Action::CloseModelPicker => self.close_model_picker(),
+            Action::CompareModels => self.compare(client, compare_tx),
+            Action::SelectAnswer(step) => self.select_answer(step),
+            Action::QueueAnswer => self.queue_answer(),
+            Action::CloseComparison => self.close_comparison(),
+            Action::SaveFavorite => self.start_favorite(),
+            Action::BrowseFavorites => self.open_favorites(),
+            Action::NameFavorite => self.name_favorite(),
+            Action::SelectFavorite(step) => self.select_favorite(step),
+            Action::InsertFavorite => self.insert_favorite(),
+            Action::RemoveFavorite => self.remove_favorite(),
+            Action::CloseFavorites => self.close_favorites(),
+            Action::ToggleAutoFix => self.toggle_auto_fix(),
+            Action::CycleSuggestionMode => self.cycle_suggestion_mode(),
+            Action::EditParams => self.start_params(),
+            Action::SetParam => self.set_param(),
+            Action::CloseParams => self.close_params(),
+        }
+        if let Some(prompt) = self.fix_due.take() {
+            self.send_fix(prompt, client, res_tx);
+        }
+    }
+
+    /// Complete the file name before the cursor in the Shell box. Tab right after a
+    /// completion with several matches puts the next one in instead.
+    fn complete_path(&mut self) {
+        let mut input = self.shell.sh_input.borrow_mut();
+        match self.completion.as_mut().filter(|completion| completion.is_current(input.value(), input.cursor())) {
+            Some(completion) => completion.cycle(),
+            None => {
+                let shell = &self.shell.shell;
+                self.completion = Completion::start(input.value(), input.cursor(), &shell.current_dir(), shell.shell_type());
+            },
+        }
+        if let Some(completion) = &self.completion {
+            *input = Input::new(completion.line()).with_cursor(completion.cursor());
+        }
+    }
+
+    /// The prompt templates, read each time so edits to the file apply right away
+    fn load_templates(&self) -> Result<Templates, TemplateError> {
+        self.templates_path.as_deref().map_or(Ok(Templates::default()), Templates::load)
+    }
+
+    /// Complete the template name typed after `/` at the start of the prompt, as far as the
+    /// matching names agree, and list them if there are several
+    fn complete_template(&mut self) {
+        let (row, col) = self.input.cursor();
+        let value = self.input.value();
+        let head: String = value.chars().take(col).collect();
+        let Some(prefix) = head.strip_prefix('/').filter(|prefix| {
+            row == 0 && (prefix.is_empty() || templates::is_valid_name(prefix))
+        }) else {
+            return;
+        };
+        if self.prompt_in_flight() {
+            return;
+        }
+        let templates = match self.load_templates() {
+            Ok(templates) => templates,
+            Err(e) => {
+                self.error = Some(format!("Could not complete the template: {}", e));
+                return;
+            },
+        };
+        let names = templates.complete(prefix);
+        let Some(first) = names.first() else {
+            self.notice = Some(match templates.path() {
+                _ if !templates.is_empty() => format!("No template starts with `{}`, the templates are: {}", prefix, templates.names().join(", ")),
+                Some(path) => format!("No templates yet, add them to {}", path.display()),
+                None => "No templates, there is no config directory".to_string(),
+            });
+            return;
+        };
+        let common = names.iter().fold(*first, |common, name| {
+            let len = common.chars().zip(name.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a.len_utf8()).sum();
+            &common[..len]
+        });
+        self.input.insert_str(&common[prefix.len()..]);
+        match names.len() {
+            1 if !value[head.len()..].starts_with(char::is_whitespace) => self.input.insert_char(' '),
+            1 => {},
+            _ => self.notice = Some(format!("Templates: {}", names.join(", "))),
+        }
+    }
+
+    /// Write the session transcript to a new file in the session directory
+    fn export_session(&self) -> io::Result<PathBuf> {
+        let dir = self.session_dir.as_deref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data directory, set `session_dir` in config.json"))?;
+        self.session.export(dir)
+    }
+
+    /// Whether `action` ends the program. `q` quits right away when nothing would be lost,
+    /// otherwise it asks first and a second `q` or `y` confirms.
+    fn confirms_quit(&mut self, action: Action) -> bool {
+        match (action, &self.confirm) {
+            (Action::Quit | Action::ConfirmRun, Some(Confirmation::Quit { .. })) => true,
+            (Action::Quit, None) => {
+                let reasons = self.unfinished_work();
+                if reasons.is_empty() {
+                    return true;
+                }
+                self.confirm = Some(Confirmation::Quit { reasons });
+                self.input_mode = EditMode::Confirm;
+                false
+            },
+            _ => false,
+        }
+    }
+
+    /// What quitting now would throw away
+    fn unfinished_work(&self) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if !self.shell_commands.is_empty() {
+            reasons.push(format!("{} command(s) still queued", self.shell_commands.len()));
+        }
+        if self.pending.is_some() {
+            reasons.push("a request to the model is pending".to_string());
+        }
+        if self.run_all.as_ref().is_some_and(|run| run.command_started.is_some()) {
+            reasons.push("a command is running".to_string());
+        }
+        let jobs = self.shell.shell.jobs().len();
+        if jobs > 0 {
+            reasons.push(format!("{} background job(s) running", jobs));
+        }
+        reasons
+    }
+
+    /// End the event loop: stop every running command, save the queue and export the session
+    /// if enabled. Only the first call does anything, so a signal during `q` does not export
+    /// the session twice.
+    fn quit(&mut self) -> io::Result<()> {
+        if std::mem::replace(&mut self.quit_done, true) {
+            return Ok(());
+        }
+        self.shell.shell.kill_all();
+        self.save_queue();
+        if self.export_on_quit && !self.session.is_empty() {
+            self.export_session()?;
+        }
+        Ok(())
+    }
+
+    /// Give a key without binding to the text box of the current mode
+    fn type_key(&mut self, event: Event) {
+        match self.input_mode {
+            // read-only while it is being answered
+            EditMode::Input if self.prompt_in_flight() => {},
+            EditMode::Input => {
+                if let Event::Key(key) = event {
+                    self.input.handle_key(&key);
+                }
+            },
+            EditMode::Shell => {
+                self.shell.sh_input.borrow_mut().handle_event(&event);
+            },
+            EditMode::Search => {
+                self.search_input.handle_event(&event);
+                self.update_search();
+            },
+            EditMode::Models => {
+                // the name can only be typed once listing the models failed
+                let picker = self.model_picker.as_mut().filter(|picker| picker.models.is_some() && picker.listed().is_none());
+                if let Some(picker) = picker {
+                    picker.input.handle_event(&event);
+                }
+            },
+            EditMode::FavoriteName => {
+                if let Some(draft) = self.favorite_draft.as_mut() {
+                    draft.name.handle_event(&event);
+                }
+            },
+            EditMode::Params => {
+                if let Some(draft) = self.params_draft.as_mut() {
+                    draft.value.handle_event(&event);
+                }
+            },
+            // anything else must not reach the boxes behind popups
+            EditMode::Normal | EditMode::Response | EditMode::Confirm | EditMode::Help | EditMode::Output | EditMode::Compare
+            | EditMode::Favorites => {},
+        }
+    }
+
+    /// Insert pasted text into the text box of the current mode, never submitting it.
+    /// The prompt keeps the newlines; in the Shell box the first line is inserted at the
+    /// cursor and the other ones are queued after the selected command.
+    fn paste(&mut self, text: &str) {
+        let lines = paste_lines(text);
+        match self.input_mode {
+            EditMode::Input if self.prompt_in_flight() => {},
+            EditMode::Input => self.input.insert_str(&lines.join("\n")),
+            EditMode::Shell => {
+                let Some((first, rest)) = lines.split_first() else {
+                    return;
+                };
+                insert_str(&mut self.shell.sh_input.borrow_mut(), first);
+                let rest: Vec<&String> = rest.iter().filter(|line| !line.trim().is_empty()).collect();
+                if rest.is_empty() {
+                    return;
+                }
+                let idx = match self.queue_state.selected() {
+                    Some(idx) => idx,
+                    None => {
+                        // the edited command becomes the selected entry, so running it
+                        // consumes it and moves on to the pasted ones
+                        self.shell_commands.push_front(self.shell.sh_input.borrow().value().to_string());
+                        self.queue_state.select(Some(0));
+                        0
+                    },
+                };
+                for (offset, line) in rest.into_iter().enumerate() {
+                    self.shell_commands.insert(idx + 1 + offset, line.clone());
+                }
+            },
+            EditMode::Search => {
+                if let Some(first) = lines.first() {
+                    insert_str(&mut self.search_input, first);
+                    self.update_search();
+                }
+            },
+            EditMode::Models => {
+                let picker = self.model_picker.as_mut().filter(|picker| picker.models.is_some() && picker.listed().is_none());
+                if let (Some(picker), Some(first)) = (picker, lines.first()) {
+                    insert_str(&mut picker.input, first.trim());
+                }
+            },
+            EditMode::FavoriteName => {
+                if let (Some(draft), Some(first)) = (self.favorite_draft.as_mut(), lines.first()) {
+                    insert_str(&mut draft.name, first.trim());
+                }
+            },
+            EditMode::Params => {
+                if let (Some(draft), Some(first)) = (self.params_draft.as_mut(), lines.first()) {
+                    insert_str(&mut draft.value, first);
+                }
+            },
+            EditMode::Normal | EditMode::Response | EditMode::Confirm | EditMode::Help | EditMode::Output | EditMode::Compare
+            | EditMode::Favorites => {},
+        }
+    }
+
+    /// Open or close the keybinding overlay
+    fn toggle_help(&mut self) {
+        if self.input_mode == EditMode::Help {
+            self.input_mode = EditMode::Normal;
+        } else {
+            self.help_scroll = 0;
+            self.input_mode = EditMode::Help;
+        }
+    }
+
+    /// The status line: the keys of the current mode, the model, the timing of the last
+    /// answer and the running jobs
+    fn status_text(&self) -> Text<'static> {
+        let text = self.language.strings();
+        let (mut msg, style) = match self.input_mode {
+            EditMode::Normal => (
+                vec![
+                    Span::raw(text.press),
+                    Span::styled("q", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.to_exit),
+                    Span::styled("a", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.to_ask_ai),
+                    Span::styled("s", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.to_interact_with_shell),
+                    Span::styled("v", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.to_read_responses),
+                    Span::styled("m", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.to_switch_model),
+                    Span::styled("?", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.for_all_keys),
+                    Span::styled("↑/↓", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.select),
+                    Span::styled("K/J", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.reorder),
+                    Span::styled("d", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.delete),
+                    Span::styled("x", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.skip),
+                    Span::styled("X", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.discard_queue),
+                    Span::styled("R", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.run_queue),
+                    Span::styled("PgUp/PgDn Home/End", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.scroll_output),
+                ],
+                Style::default().add_modifier(Modifier::RAPID_BLINK),
+            ),
+            EditMode::Input if self.prompt_in_flight() => (
+                vec![
+                    Span::raw(text.waiting),
+                    Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.to_cancel_and_edit),
+                ],
+                Style::default(),
+            ),
+            EditMode::Input => (
+                vec![
+                    Span::raw(text.press),
+                    Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.stop_asking),
+                    Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.to_send),
+                    Span::styled("Alt-Enter", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.for_new_line),
+                ],
+                Style::default(),
+            ),
+            EditMode::Response => (
+                vec![
+                    Span::raw(text.press),
+                    Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.stop_reading),
+                    Span::styled("↑/↓ PgUp/PgDn", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.to_scroll),
+                ],
+                Style::default(),
+            ),
+            EditMode::Shell => (
+                vec![
+                    Span::raw(text.press),
+                    Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.stop_shell),
+                    Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.to_execute),
+                    Span::styled("Ctrl-P", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.to_see_plan),
+                    Span::styled("Ctrl-S", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(text.to_skip),
+                ],
+                Style::default(),
+            ),
+            EditMode::Confirm if matches!(self.confirm, Some(Confirmation::Quit { .. })) => (
+                vec![
+                    Span::raw("Press "),
+                    Span::styled("y/q", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to quit, "),
+                    Span::styled("n/Esc", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to stay"),
+                ],
+                Style::default(),
+            ),
+            EditMode::Confirm if matches!(self.confirm, Some(Confirmation::ReplaceQueue { .. })) => (
+                vec![
+                    Span::raw("Press "),
+                    Span::styled("y", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to replace the queue, "),
+                    Span::styled("n/Esc", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to keep it"),
+                ],
+                Style::default(),
+            ),
+            EditMode::Confirm => (
+                vec![
+                    Span::raw("Press "),
+                    Span::styled("y", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to run the command, "),
+                    Span::styled("n/Esc", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to go back and edit it"),
+                ],
+                Style::default(),
+            ),
+            EditMode::Models => (
+                vec![
+                    Span::raw("Press "),
+                    Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to use the model, "),
+                    Span::styled("Ctrl-S", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to use it and save it to the config, "),
+                    Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to cancel"),
+                ],
+                Style::default(),
+            ),
+            EditMode::Output => (
+                vec![
+                    Span::raw("Press "),
+                    Span::styled("↑/↓", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" select, "),
+                    Span::styled("y", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" copy output, "),
+                    Span::styled("Y", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" copy command, "),
+                    Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to go back"),
+                ],
+                Style::default(),
+            ),
+            EditMode::Search => (
+                vec![
+                    Span::raw("Press "),
+                    Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to keep the search, "),
+                    Span::styled("↑/↓", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" previous/next match, "),
+                    Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to clear it"),
+                ],
+                Style::default(),
+            ),
+            EditMode::Compare => (
+                vec![
+                    Span::raw("Press "),
+                    Span::styled("↑/↓", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to pick an answer, "),
+                    Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to queue its commands, "),
+                    Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to keep the queue"),
+                ],
+                Style::default(),
+            ),
+            EditMode::Params => (
+                vec![
+                    Span::raw("Type the value and press "),
+                    Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" for the next one, "),
+                    Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to keep the commands as they are"),
+                ],
+                Style::default(),
+            ),
+            EditMode::FavoriteName => (
+                vec![
+                    Span::raw("Type a name and press "),
+                    Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to save the favorite, "),
+                    Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to cancel"),
+                ],
+                Style::default(),
+            ),
+            EditMode::Favorites => (
+                vec![
+                    Span::raw("Press "),
+                    Span::styled("↑/↓", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" select, "),
+                    Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" insert into the Shell box, "),
+                    Span::styled("Del", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" remove, "),
+                    Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to close"),
+                ],
+                Style::default(),
+            ),
+            EditMode::Help => (
+                vec![
+                    Span::raw("Press "),
+                    Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" or "),
+                    Span::styled("?", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(" to close the help"),
+                ],
+                Style::default(),
+            ),
+        };
+        let running_jobs = self.shell.shell.jobs().len();
+        msg.push(Span::styled(
+            format!("  [model: {}]", self.messages.model()),
+            Style::default().fg(self.theme.model),
+        ));
+        if let Some(timing) = &self.timing {
+            msg.push(Span::styled(format!("  [{}]", timing.status()), Style::default().fg(self.theme.status)));
+        }
+        match &self.health {
+            None => msg.push(Span::styled("  [connecting…]", Style::default().fg(self.theme.status))),
+            Some(Err(_)) => msg.push(Span::styled("  [Ollama unreachable]", Style::default().fg(self.theme.error))),
+            Some(Ok(_)) => {},
+        }
+        if let Some(file) = self.project_context.cached() {
+            msg.push(Span::styled(format!("  [context: {}]", display_path(&file.path, None)), Style::default().fg(self.theme.status)));
+        }
+        match self.messages.suggestion_mode() {
+            SuggestionMode::Auto => {},
+            mode => msg.push(Span::styled(format!("  [{}]", mode), Style::default().fg(self.theme.status))),
+        }
+        match &self.fixing {
+            Some(fix) => msg.push(Span::styled(format!("  [{}]", fix.label()), Style::default().fg(self.theme.status))),
+            None if self.auto_fix => msg.push(Span::styled("  [auto-fix on]", Style::default().fg(self.theme.status))),
+            None => {},
+        }
+        if running_jobs > 0 {
+            msg.push(Span::styled(
+                format!("  [{} job(s) running]", running_jobs),
+                Style::default().fg(self.theme.status),
+            ));
+        }
+        Text::from(Line::from(msg)).style(style)
+    }
+
+    /// How the Shell box differs from the selected command, `None` while it does not
+    fn edit_preview(&self) -> Option<Line<'static>> {
+        let input = self.shell.sh_input.borrow();
+        let suggested = self.edited_suggestion(input.value())?;
+        let mut spans = vec![Span::styled("edited: ", Style::default().add_modifier(Modifier::DIM))];
+        spans.extend(diff_chars(suggested, input.value()).into_iter().map(|change| match change {
+            Change::Same(text) => Span::styled(text, Style::default().add_modifier(Modifier::DIM)),
+            Change::Removed(text) => Span::styled(text, Style::default().fg(self.theme.error).add_modifier(Modifier::CROSSED_OUT)),
+            Change::Added(text) => Span::styled(text, Style::default().fg(self.theme.status).add_modifier(Modifier::BOLD)),
+        }));
+        Some(Line::from(spans))
+    }
+
+    /// Show or hide what the command in the Shell box would do, noting the plan shown in
+    /// the session
+    fn toggle_plan(&mut self) {
+        self.show_plan = !self.show_plan;
+        if let Some(plan) = self.execution_plan() {
+            self.session.plan_shown(&plan);
+        }
+    }
+
+    /// What the command in the Shell box would do, while the plan is shown
+    fn execution_plan(&self) -> Option<ExecutionPlan> {
+        let input = self.shell.sh_input.borrow();
+        (self.show_plan && !input.value().trim().is_empty())
+            .then(|| self.shell.shell.explain_command_with_patterns(input.value(), &self.dangerous_patterns))
+    }
+
+    /// Lines of the Plan box under the Shell box, `None` while it is hidden
+    fn plan_text(&self) -> Option<Vec<Line<'static>>> {
+        if !self.show_plan {
+            return None;
+        }
+        let Some(plan) = self.execution_plan() else {
+            return Some(vec![Line::styled("Type a command to see what it would do", Style::default().add_modifier(Modifier::DIM))]);
+        };
+        Some(plan.lines().into_iter().map(|line| match line.starts_with("risk: asked") {
+            true => Line::styled(line, Style::default().fg(self.theme.error)),
+            false => Line::raw(line),
+        }).collect())
+    }
+
+    /// The last error, or else the last notice, shown under the status line
+    fn banner(&self) -> Option<Paragraph<'_>> {
+        match (&self.error, &self.notice) {
+            (Some(error), _) => Some(Paragraph::new(format!("Error: {}", error))
+                .style(Style::default().fg(self.theme.error_text).bg(self.theme.error))),
+            (None, Some(notice)) => Some(Paragraph::new(notice.as_str())
+                .style(Style::default().fg(self.theme.notice_text).bg(self.theme.notice))),
+            (None, None) => None,
+        }
+    }
+
+    fn ui(&mut self, frame: &mut Frame) {
+        // the prompt wraps at the box width, 2 for borders and 1 for cursor
+        let prompt = self.input.wrap(frame.area().width.max(3) as usize - 3);
+        let prompt_rows = prompt.rows.len().min(MAX_PROMPT_ROWS);
+        let prompt_scroll = prompt.cursor.0.saturating_sub(prompt_rows - 1);
+        let edit_preview = self.edit_preview();
+        let plan = self.plan_text();
+        // the plan wraps at the box width, 2 for borders
+        let plan_rows = plan.as_ref().map_or(0, |plan| {
+            let width = frame.area().width.saturating_sub(2).max(1) as usize;
+            plan.iter().map(|line| line.width().max(1).div_ceil(width) as u16).sum::<u16>() + 2
+        });
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(
+                [
+                    Constraint::Length(if self.error.is_some() || self.notice.is_some() { 2 } else { 1 }),
+                    Constraint::Length(prompt_rows as u16 + 2),
+                    // one more row for the preview of an edit
+                    Constraint::Length(if edit_preview.is_some() { 4 } else { 3 }),
+                    Constraint::Length(plan_rows),
+                    Constraint::Min(5),
+                ].as_ref(),
+            )
+            .split(frame.area());
+        let bottom = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(35), Constraint::Percentage(65)])
+            .split(chunks[4]);
+        let left = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(bottom[0]);
+
+        frame.render_widget(Paragraph::new(self.status_text()), Rect { height: chunks[0].height.min(1), ..chunks[0] });
+        // a terminal too short for both rows keeps the status line
+        if let Some(banner) = self.banner().filter(|_| chunks[0].height > 1) {
+            frame.render_widget(banner, Rect { y: chunks[0].y + 1, height: 1, ..chunks[0] });
+        }
+
+        // Asking AI block
+        let input = Paragraph::new(prompt.rows.iter().map(|row| Line::raw(row.as_str())).collect::<Vec<_>>())
+            .style(match self.input_mode {
+                _ if self.prompt_in_flight() => Style::default().fg(self.theme.inactive).add_modifier(Modifier::DIM),
+                EditMode::Normal => Style::default(),
+                EditMode::Input => Style::default().fg(self.theme.active),
+                _ => Style::default().fg(self.theme.inactive),
+            })
+            .scroll((prompt_scroll as u16, 0))
+            .block(Block::default().borders(Borders::ALL).title(match self.pending.as_ref().or(self.comparing.as_ref()) {
+                Some(pending) => Line::from(vec![
+                    Span::raw("Asking AI "),
+                    Span::styled(pending.status(), Style::default().fg(self.theme.status)),
+                ]),
+                None if self.input_mode == EditMode::Input => Line::from(vec![
+                    Span::raw("Asking AI "),
+                    self.token_counter(),
+                ]),
+                None => Line::raw("Asking AI"),
+            }));
+        frame.render_widget(input, chunks[1]);
+
+
+        // Shell interact block
+        // Leave at least half of the box for the command itself
+        let path = self.shell.get_path_within((chunks[2].width / 2) as usize);
+        let sh_style = match self.input_mode {
+            EditMode::Normal => Style::default(),
+            EditMode::Shell => Style::default().fg(self.theme.active),
+            _ => Style::default().fg(self.theme.inactive),
+        };
+        let sh_title = {
+            let input = self.shell.sh_input.borrow();
+            let completion = self.completion.as_ref()
+                .filter(|completion| completion.count() > 1 && completion.is_current(input.value(), input.cursor()));
+            match completion.map(|completion| (completion.selected(), completion.count())) {
+                Some((Some(idx), count)) => format!("Shell [{}/{}]", idx + 1, count),
+                Some((None, count)) => format!("Shell [{} matches]", count),
+                None => "Shell".to_string(),
+            }
+        };
+        let sh_block = Block::default().borders(Borders::ALL).title(sh_title).style(sh_style);
+        let sh_inner = sh_block.inner(chunks[2]);
+        frame.render_widget(sh_block, chunks[2]);
+        // the `path > ` prefix stays put while the command scrolls behind it
+        let sh_cursor = render_line_input(
+            frame,
+            &format!("{} > ", path),
+            &self.shell.sh_input.borrow(),
+            Rect { height: sh_inner.height.min(1), ..sh_inner },
+            sh_style,
+        );
+        if let Some(preview) = edit_preview.filter(|_| sh_inner.height > 1) {
+            frame.render_widget(Paragraph::new(preview), Rect { y: sh_inner.y + 1, height: 1, ..sh_inner });
+        }
+        if let Some(plan) = plan {
+            let block = Block::default().borders(Borders::ALL).title("Plan").style(Style::default().fg(self.theme.status));
+            frame.render_widget(Paragraph::new(plan).block(block).wrap(Wrap { trim: false }), chunks[3]);
+        }
+
+        // Shell output block
+        let welcome = self.welcome_text();
+        let welcoming = welcome.is_some();
+        let (mut history_text, entry_starts) = match welcome {
+            Some(welcome) => (welcome, Vec::new()),
+            None => self.history_lines(),
+        };
+        let plain = plain_lines(&history_text);
+        let search_line = self.search.as_mut().and_then(|search| {
+            search.update(&plain);
+            search.current()
+        });
+        if let Some(search) = &self.search {
+            let matched = Style::default().fg(self.theme.notice_text).bg(self.theme.notice);
+            for (idx, line) in history_text.lines.iter_mut().enumerate() {
+                if Some(idx) == search_line {
+                    *line = std::mem::take(line).patch_style(matched.add_modifier(Modifier::REVERSED));
+                } else if search.is_match(&plain[idx]) {
+                    *line = std::mem::take(line).patch_style(matched);
+                }
+            }
+        }
+        let mut history_title = vec![Span::raw("Output")];
+        if let Some(started) = self.run_all.as_ref().and_then(|run| run.command_started) {
+            history_title.push(Span::styled(
+                format!(" {} {}", spinner(started), format_elapsed(started.elapsed())),
+                Style::default().fg(self.theme.status),
+            ));
+        }
+        if self.input_mode == EditMode::Search || self.search.is_some() {
+            history_title.push(Span::raw(format!(" /{}", self.search_input.value())));
+        }
+        if let Some((current, count)) = self.search.as_ref().map(Search::position) {
+            history_title.push(Span::styled(format!(" [{}/{}]", current, count), Style::default().fg(self.theme.status)));
+        }
+        let history_block = Block::default().borders(Borders::ALL).title(Line::from(history_title));
+        let inner = history_block.inner(bottom[1]);
+        let total = wrapped_height(&history_text, inner.width);
+        let max_scroll = total.saturating_sub(inner.height);
+        self.shell.history_scroll = self.shell.history_scroll.min(max_scroll);
+        // keep the current match in view after a jump, otherwise the selected entry:
+        // its header, or the current match if it is in the entry
+        let jump = std::mem::take(&mut self.search_jump).then_some(search_line).flatten();
+        let selected = self.output_selected.filter(|_| self.input_mode == EditMode::Output).and_then(|idx| {
+            let start = *entry_starts.get(idx)?;
+            let end = entry_starts.get(idx + 1).copied().unwrap_or(plain.len());
+            Some(search_line.filter(|line| (start..end).contains(line)).unwrap_or(start))
+        });
+        if let Some(focus) = jump.or(selected) {
+            let row = wrapped_height(&Text::from(history_text.lines[..focus].to_vec()), inner.width);
+            let top = max_scroll - self.shell.history_scroll;
+            if row < top {
+                self.shell.history_scroll = max_scroll - row;
+            } else if row >= top + inner.height {
+                self.shell.history_scroll = max_scroll - (row + 1 - inner.height).min(max_scroll);
+            }
+        }
+        let sh_output = Paragraph::new(history_text)
+            .style(match self.input_mode {
+                EditMode::Normal => Style::default(),
+                EditMode::Output => Style::default().fg(self.theme.active),
+                _ => Style::default().fg(self.theme.text),
+            })
+            .wrap(Wrap { trim: false })
+            // the welcome panel is read from the top, the history from the newest entry
+            .scroll((if welcoming { 0 } else { max_scroll - self.shell.history_scroll }, 0))
+            .block(history_block);
+        frame.render_widget(sh_output, bottom[1]);
+
+        // Pending commands block
+        let queue_title = match &self.run_all {
+            Some(run) => Line::from(vec![
+                Span::raw(format!("Queue ({}) ", self.shell_commands.len())),
+                Span::styled(run.status(), Style::default().fg(self.theme.status)),
+            ]),
+            None => Line::raw(format!("Queue ({})", self.shell_commands.len())),
+        };
+        let queue_block = Block::default()
+            .borders(Borders::ALL)
+            .title(queue_title);
+        if self.shell_commands.is_empty() {
+            let empty = Paragraph::new("queue empty")
+                .style(Style::default().add_modifier(Modifier::DIM))
+                .block(queue_block);
+            frame.render_widget(empty, left[1]);
+        } else {
+            let items: Vec<ListItem> = self.shell_commands
+                .iter()
+                .map(|command| ListItem::new(command.as_str()))
+                .collect();
+            let queue = List::new(items)
+                .block(queue_block)
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+                .highlight_symbol("> ");
+            frame.render_stateful_widget(queue, left[1], &mut self.queue_state);
+        }
+
+        // AI response block
+        let (response_text, response_title) = match &self.comparison {
+            Some(comparison) => (self.comparison_text(comparison), "Response: compare"),
+            None => (self.conversation_text(), "Response"),
+        };
+        let response_block = Block::default().borders(Borders::ALL).title(response_title);
+        let inner = response_block.inner(left[0]);
+        let total = wrapped_height(&response_text, inner.width);
+        self.response_scroll = self.response_scroll.min(total.saturating_sub(inner.height));
+        let response = Paragraph::new(response_text)
+            .style(match self.input_mode {
+                EditMode::Response | EditMode::Compare => Style::default().fg(self.theme.active),
+                _ => Style::default(),
+            })
+            .wrap(Wrap { trim: false })
+            .scroll((total.saturating_sub(inner.height) - self.response_scroll, 0))
+            .block(response_block);
+        frame.render_widget(response, left[0]);
+
+        if let Some(confirm) = &self.confirm {
+            render_confirmation(frame, confirm, &self.theme);
+        }
+        if let Some(picker) = &mut self.model_picker {
+            if let Some(cursor) = render_model_picker(frame, picker, self.messages.model(), &self.theme) {
+                frame.set_cursor_position(cursor);
+            }
+        }
+        if let Some(list) = &mut self.favorites_list {
+            render_favorites(frame, list, &self.theme);
+        }
+        if let Some(draft) = &self.favorite_draft {
+            // behind the question whether to replace a favorite of the same name
+            if self.input_mode == EditMode::FavoriteName {
+                let cursor = render_favorite_name(frame, draft, &self.theme);
+                frame.set_cursor_position(cursor);
+            }
+        }
+
+        if let Some(draft) = &self.params_draft {
+            let cursor = render_params(frame, draft, &self.theme);
+            frame.set_cursor_position(cursor);
+        }
+
+        if self.input_mode == EditMode::Help {
+            self.help_scroll = render_help(frame, &self.keymap, self.help_scroll, &self.theme);
+        }
+
+        match self.input_mode {
+            EditMode::Normal | EditMode::Response | EditMode::Confirm | EditMode::Models | EditMode::Help | EditMode::Output | EditMode::Compare
+            | EditMode::FavoriteName | EditMode::Favorites | EditMode::Params => {},
+            EditMode::Search => {
+                // right after the `Output /` of the title
+                let x = bottom[1].x + 1 + Line::raw("Output /").width() as u16 + self.search_input.visual_cursor() as u16;
+                frame.set_cursor_position((x.min(bottom[1].right().saturating_sub(2)), bottom[1].y));
+            },
+            // Hide cursor in normal mode, and while the prompt waits for its answer
+            EditMode::Input if self.prompt_in_flight() => {},
+            EditMode::Input => {
+                let (row, col) = prompt.cursor;
+                frame.set_cursor_position((
+                    (chunks[1].x + 1 + col as u16).min(chunks[1].right().saturating_sub(2)),
+                    chunks[1].y + 1 + (row - prompt_scroll) as u16,
+                ))
+            },
+            EditMode::Shell => frame.set_cursor_position(sh_cursor),
+        }
+    }
+
+    /// Store received commands, cleaned up by `sanitize_commands`, returns how many are usable.
+    /// An answer without any leaves the queue and the Shell box alone and shows an error instead.
+    pub fn recv_from(&mut self, rece_vec: Vec<String>) -> usize {
+        let received = rece_vec.len();
+        let commands = sanitize_commands(rece_vec, self.multiline);
+        if commands.is_empty() {
+            self.error = Some(match received {
+                0 => "Model returned no commands".to_string(),
+                _ => "Model returned 0 usable commands".to_string(),
+            });
+            return 0;
+        }
+        self.shell_commands = VecDeque::from(commands);
+        self.queue_state.select(if self.shell_commands.is_empty() { None } else { Some(0) });
+        self.sync_shell_input();
+        self.shell_commands.len()
+    }
+
+    /// Send the Asking AI box content to the model in the background.
+    /// The result arrives through `res_tx` and is picked up by `finish_request`.
+    /// Whitespace-only prompts are not sent, returns whether the request was sent.
+    /// A prompt typed as `/name arguments` is sent as the template `name` expanded.
+    fn send_request(&mut self, client: &Bclient, res_tx: &ResponseSender) -> bool {
+        let typed = self.input.value();
+        if typed.trim().is_empty() {
+            return false;
+        }
+        let expanded = match typed.starts_with('/') {
+            true => self.load_templates().map(|templates| templates.expand_prompt(&typed)),
+            false => Ok(Ok(typed.clone())),
+        };
+        let prompt = match expanded {
+            Ok(Ok(prompt)) => prompt,
+            Ok(Err(e @ TemplateError::Unknown { .. })) => {
+                self.error = Some(format!("Prompt not sent: {}. Start it with // to send a prompt beginning with /", e));
+                return false;
+            },
+            Ok(Err(e)) | Err(e) => {
+                self.error = Some(format!("Prompt not sent: {}", e));
+                return false;
+            },
+        };
+        self.prompt_history.push(&typed);
+        self.messages.set_seed(None);
+        self.send_prompt(prompt, false, client, res_tx);
+        true
+    }
+
+    /// Estimated tokens of the prompt being typed with its context out of the budget, e.g.
+    /// `~120/4096 tokens`, in the error color once over it
+    fn token_counter(&self) -> Span<'static> {
+        let mut req = self.messages.clone();
+        req.prompt_with(&self.directory_context(), &self.input.value());
+        let tokens = req.estimated_tokens();
+        let color = if tokens > self.max_prompt_tokens { self.theme.error } else { self.theme.status };
+        Span::styled(format!("~{}/{} tokens", tokens, self.max_prompt_tokens), Style::default().fg(color))
+    }
+
+    /// Whether the Asking AI box holds the prompt being answered, which is read-only until
+    /// the answer arrives. Regenerating leaves the box alone, so it can be edited meanwhile.
+    fn prompt_in_flight(&self) -> bool {
+        self.pending.as_ref().is_some_and(|pending| !pending.regenerate && !pending.fix)
+    }
+
+    /// Ask the last prompt again with a new seed, for a different answer.
+    /// The Asking AI box is left alone.
+    fn regenerate(&mut self, client: &Bclient, res_tx: &ResponseSender) {
+        if self.pending.is_some() || self.comparing.is_some() {
+            self.error = Some("Wait for the pending request to finish".to_string());
+            return;
+        }
+        let Some(prompt) = self.last_prompt.clone() else {
+            self.notice = Some("No prompt to regenerate yet".to_string());
+            return;
+        };
+        let seed = new_seed();
+        self.messages.set_seed(Some(seed));
+        self.notice = Some(format!("Regenerating with seed {}", seed));
+        self.send_prompt(prompt, true, client, res_tx);
+    }
+
+    fn send_prompt(&mut self, prompt: String, regenerate: bool, client: &Bclient, res_tx: &ResponseSender) {
+        self.session.prompt_sent(self.messages.model(), &prompt);
+        self.last_prompt = Some(prompt.clone());
+        self.refresh_project_context();
+        self.messages.prompt_with(&self.directory_context(), &prompt);
+        self.spawn_request(self.messages.clone(), prompt, regenerate, false, client, res_tx);
+    }
+
+    /// What the next prompt tells about the directory commands run in
+    fn directory_context(&self) -> String {
+        workdir::context(&self.shell.shell.current_dir(), self.context_level)
+    }
+
+    /// Start the system prompt with the notes of the project the shell is in, if any
+    fn refresh_project_context(&mut self) {
+        let file = self.project_context.current(&self.shell.shell.current_dir());
+        self.messages.set_project_context(file);
+    }
+
+    /// Send `req` to the model in the background, the answer arrives through `res_tx`
+    fn spawn_request(&mut self, mut req: OllamaReq, prompt: String, regenerate: bool, fix: bool, client: &Bclient, res_tx: &ResponseSender) {
+        self.timing = None;
+        self.request_id += 1;
+        let id = self.request_id;
+        let loading = Arc::new(AtomicBool::new(false));
+        let (check_client, model, flag) = (client.clone(), self.messages.model().to_string(), loading.clone());
+        // on its own, so an answer is never held up by the check
+        tokio::spawn(async move {
+            if let Ok(false) = check_client.model_loaded(&model).await {
+                flag.store(true, Ordering::Relaxed);
+            }
+        });
+        if let Some(warning) = req.fit(self.max_prompt_tokens).warning() {
+            self.notice = Some(warning);
+        }
+        let (client, res_tx) = (client.clone(), res_tx.clone());
+        let handle = tokio::spawn(async move {
+            let res = client.generate(&req).await;
+            let _ = res_tx.send(RequestResult { id, prompt, res });
+        });
+        self.pending = Some(PendingRequest { id, started: Instant::now(), handle, regenerate, fix, loading });
+    }
+
+    /// Put the last prompt back into the Asking AI box to change it before asking again
+    fn edit_last_prompt(&mut self) {
+        match &self.last_prompt {
+            Some(prompt) => {
+                self.input = PromptEditor::new(prompt.clone());
+                self.input_mode = EditMode::Input;
+            },
+            None => self.notice = Some("No prompt sent yet".to_string()),
+        }
+    }
+
+    /// Replace the Asking AI box content with the previous prompt in history
+    fn recall_prev_prompt(&mut self) {
+        if let Some(prompt) = self.prompt_history.older(&self.input.value()) {
+            self.input = PromptEditor::new(prompt.to_string());
+        }
+    }
+
+    /// Replace the Asking AI box content with the next prompt, or the draft after the newest one
+    fn recall_next_prompt(&mut self) {
+        if let Some(prompt) = self.prompt_history.newer() {
+            self.input = PromptEditor::new(prompt.to_string());
+        }
+    }
+
+    /// Pick up results of finished requests
+    fn poll_responses(&mut self, res_rx: &mut ResponseReceiver) {
+        // checked before draining: a task that finished has already sent its result, if any
+        let finished = self.pending.as_ref().is_some_and(|pending| pending.handle.is_finished());
+        while let Ok(result) = res_rx.try_recv() {
+            self.finish_request(result);
+        }
+        if finished && self.pending.take().is_some() {
+            self.error = Some("Request to the model ended unexpectedly".to_string());
+        }
+    }
+
+    /// Handle the model's answer; answers to cancelled requests are dropped
+    fn finish_request(&mut self, result: RequestResult) {
+        let Some(pending) = self.pending.take_if(|pending| pending.id == result.id) else {
+            return;
+        };
+        let RequestResult { prompt, res, .. } = result;
+        if pending.fix {
+            return self.finish_fix(res);
+        }
+        match res {
+            Ok(Generation { commands, stats, dropped, cached, .. }) => {
+                // Ollama answers after all, e.g. started after aurish
+                if matches!(self.health, Some(Err(_))) {
+                    self.health = Some(Ok(Vec::new()));
+                }
+                self.timing = Some(Timing { elapsed: pending.started.elapsed(), stats });
+                if let Some(notice) = suggestions::dropped_notice(dropped) {
+                    self.notice = Some(notice);
+                }
+                if cached {
+                    self.notice = Some("Same prompt as a moment ago, its answer is shown again; regenerate for a new one".to_string());
+                }
+                self.session.commands_received(&commands);
+                self.record_exchange(prompt, commands.clone());
+                self.error = None;
+                if !pending.regenerate {
+                    // kept for another try unless its commands were queued
+                    if self.recv_from(commands) > 0 {
+                        self.input.reset();
+                        if self.input_mode == EditMode::Input {
+                            self.input_mode = EditMode::Normal;
+                        }
+                    }
+                } else if self.shell_commands.is_empty() || commands.is_empty() {
+                    self.recv_from(commands);
+                } else if self.confirm.is_some() {
+                    self.notice = Some("Regenerated commands dropped, answer the open question first".to_string());
+                } else {
+                    self.confirm = Some(Confirmation::ReplaceQueue { queued: self.shell_commands.len(), commands });
+                    self.input_mode = EditMode::Confirm;
+                }
+            },
+            // e.g. sent again right after cancelling, before the cancelled one let go
+            Err(BackendError::Busy) => self.notice = Some("Still waiting for the previous answer, try again in a moment".to_string()),
+            Err(e) => {
+                let message = self.request_error(&e);
+                self.session.error(&message);
+                self.error = Some(message);
+            },
+        }
+    }
+
+    /// Banner for a failed request, pointing to the model switcher if the model is not
+    /// on the server
+    fn request_error(&self, e: &BackendError) -> String {
+        match self.normal_binding(Action::SwitchModel) {
+            Some(binding) if e.is_missing_model() => format!("{}, press {} to pick an installed model", e, binding.label()),
+            _ => e.to_string(),
+        }
+    }
+
+    /// First key of `action` in Normal mode, `None` if it has none
+    fn normal_binding(&self, action: Action) -> Option<&Binding> {
+        self.keymap.iter().find(|binding| binding.mode == EditMode::Normal && binding.action == action)
+    }
+
+    /// Fetch the model list in the background, which tells the status line and the welcome
+    /// panel whether Ollama answers. The HTTP client is built there too, see `Bclient`.
+    fn check_health(&self, client: &Bclient, health_tx: &ModelsSender) {
+        let (client, health_tx) = (client.clone(), health_tx.clone());
+        tokio::spawn(async move {
+            let started = Instant::now();
+            let models = client.list_models().await;
+            log::trace!("startup check done in {:?}", started.elapsed());
+            let _ = health_tx.send(models);
+        });
+    }
+
+    /// Pick up the result of the startup check
+    fn poll_health(&mut self, health_rx: &mut ModelsReceiver) {
+        while let Ok(models) = health_rx.try_recv() {
+            self.health = Some(models.map_err(|e| e.to_string()));
+        }
+    }
+
+    /// Take up the last config read again, if the file changed
+    fn poll_config_reloads(&mut self, client: &mut Bclient, health_tx: &ModelsSender) {
+        let Some(reloads) = self.config_reloads.as_mut() else {
+            return;
+        };
+        let mut latest = None;
+        while let Ok(reload) = reloads.try_recv() {
+            latest = Some(reload);
+        }
+        if let Some(reload) = latest {
+            self.reload_config(reload, client, health_tx);
+        }
+    }
+
+    /// Switch to the model, theme and keybindings of `reload`. A new endpoint, proxy or
+    /// timeout needs a new client, which is checked like at startup.
+    fn reload_config(&mut self, reload: Result<ConfigReload, ConfigError>, client: &mut Bclient, health_tx: &ModelsSender) {
+        let ConfigReload { config, theme } = match reload {
+            Ok(reload) => reload,
+            Err(e) => {
+                log::warn!("config not reloaded: {}", e);
+                self.error = Some(format!("Config not reloaded: {}", e));
+                return;
+            },
+        };
+        let mut changed = Vec::new();
+        if config.get_model() != self.messages.model() {
+            self.messages.set_model(config.get_model());
+            changed.push("model");
+        }
+        if let Some(rebuilt) = client.for_config(&config) {
+            *client = rebuilt;
+            if let Some(welcome) = &mut self.welcome {
+                welcome.endpoint = config.get_ollama_api().to_string();
+            }
+            self.health = None;
+            self.check_health(client, health_tx);
+            changed.push("endpoint");
+        }
+        if theme != self.theme {
+            self.theme = theme;
+            changed.push("theme");
+        }
+        let keymap = config.keybinding_table();
+        if keymap != self.keymap {
+            self.keymap = keymap;
+            self.pending_keys.clear();
+            changed.push("keybindings");
+        }
+        // saving the model from the switcher changes the file too, but nothing in it
+        if !changed.is_empty() {
+            log::info!("config reloaded: {} changed", changed.join(", "));
+            self.notice = Some(format!("Config reloaded: {} changed", changed.join(", ")));
+        }
+    }
+
+    /// The welcome panel: the model, endpoint and shell, the keys to start with and whether
+    /// Ollama answered. `None` once something was asked or run.
+    fn welcome_text(&self) -> Option<Text<'static>> {
+        let welcome = self.welcome.as_ref()?;
+        let busy = self.pending.is_some() || self.comparing.is_some();
+        if busy || !self.conversation.is_empty() || !self.shell.history.is_empty() {
+            return None;
+        }
+        let (server, model) = (backend::server_name(&welcome.endpoint), self.messages.model());
+        let ok = Style::default().fg(self.theme.status);
+        let failed = Style::default().fg(self.theme.error);
+        let mut lines = vec![Line::styled("Welcome to aurish", Style::default().add_modifier(Modifier::BOLD))];
+        match &self.health {
+            None => lines.push(Line::styled(format!("{} checking {}", SPINNER[0], server), ok)),
+            Some(Ok(models)) if backend::has_model(models, model) => {
+                lines.push(Line::styled(format!("✓ connected to {}, {} is installed", server, model), ok));
+            },
+            Some(Ok(_)) => {
+                lines.push(Line::styled(format!("✗ connected to {}, but {} is not installed", server, model), failed));
+                let switch = self.normal_binding(Action::SwitchModel)
+                    .map_or(String::new(), |binding| format!(", or press {} to pick another model", binding.label()));
+                lines.push(Line::raw(format!("  Download it with `ollama pull {}`{}", model, switch)));
+            },
+            Some(Err(e)) => {
+                lines.push(Line::styled(format!("✗ could not reach {}: {}", server, e), failed));
+                lines.push(Line::raw("  Is Ollama running? Start it with `ollama serve`"));
+                lines.push(Line::raw("  Check `ollama_api` in the config, or try `aurish-cli doctor`"));
+            },
+        }
+        lines.push(Line::default());
+        lines.push(Line::raw(format!("Model     {}", model)));
+        lines.push(Line::raw(format!("Endpoint  {}", welcome.endpoint)));
+        lines.push(Line::raw(format!("Shell     {:?}", self.shell.shell.shell_type())));
+        lines.push(Line::default());
+        for action in [Action::AskAi, Action::EditShell, Action::ReadResponses, Action::ToggleHelp] {
+            if let Some(binding) = self.normal_binding(action) {
+                lines.push(Line::from(vec![
+                    Span::styled(format!("{:<6}", binding.label()), Style::default().fg(self.theme.model)),
+                    Span::raw(binding.description),
+                ]));
+            }
+        }
+        Some(Text::from(lines))
+    }
+
+    /// Open the model switcher and fetch the model list in the background
+    fn open_model_picker(&mut self, client: &Bclient, models_tx: &ModelsSender) {
+        let (client, models_tx) = (client.clone(), models_tx.clone());
+        let handle = tokio::spawn(async move {
+            let _ = models_tx.send(client.list_models().await);
+        });
+        self.close_model_picker();
+        self.model_picker = Some(ModelPicker {
+            models: None,
+            list_state: ListState::default(),
+            input: Input::default(),
+            handle,
+        });
+        self.input_mode = EditMode::Models;
+    }
+
+    /// Pick up the model list once it is fetched
+    fn poll_models(&mut self, models_rx: &mut ModelsReceiver) {
+        while let Ok(models) = models_rx.try_recv() {
+            let Some(picker) = self.model_picker.as_mut() else {
+                continue;
+            };
+            let active = self.messages.model();
+            picker.list_state.select(match &models {
+                Ok(models) if !models.is_empty() => Some(models.iter().position(|m| m == active).unwrap_or(0)),
+                _ => None,
+            });
+            picker.models = Some(models.map_err(|e| e.to_string()));
+        }
+    }
+
+    fn select_model(&mut self, step: i32) {
+        if let Some(picker) = self.model_picker.as_mut() {
+            let count = picker.listed().map_or(0, |models| models.len());
+            if let Some(idx) = picker.list_state.selected().filter(|_| count > 0) {
+                picker.list_state.select(Some((idx as i32 + step).clamp(0, count as i32 - 1) as usize));
+            }
+        }
+    }
+
+    /// Switch to the model selected in the popup, saving it to the config file if `persist`
+    fn choose_model(&mut self, persist: bool) {
+        let Some(model) = self.model_picker.as_ref().and_then(ModelPicker::chosen) else {
+            return;
+        };
+        self.messages.set_model(&model);
+        self.close_model_picker();
+        if persist {
+            if let Err(e) = self.save_model(&model) {
+                self.error = Some(format!("Could not save model to config: {}", e));
+            }
+        }
+    }
+
+    fn save_model(&self, model: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.config_path.as_deref().ok_or("no config file")?;
+        Config::update(path, |config| {
+            config.set_model(model.to_string());
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    fn close_model_picker(&mut self) {
+        if let Some(picker) = self.model_picker.take() {
+            picker.handle.abort();
+        }
+        self.input_mode = EditMode::Normal;
+    }
+
+    /// Ask for the name to save the selected command as a favorite under
+    fn start_favorite(&mut self) {
+        let Some(command) = self.selected_command().map(str::to_string) else {
+            self.error = Some("Select a queued command to save it as a favorite".to_string());
+            return;
+        };
+        self.favorite_draft = Some(FavoriteDraft { command, name: Input::default() });
+        self.input_mode = EditMode::FavoriteName;
+    }
+
+    /// Save the command under the typed name, asking first if a favorite has that name
+    fn name_favorite(&mut self) {
+        let Some(draft) = &self.favorite_draft else {
+            return;
+        };
+        let Some(store) = &self.favorites else {
+            self.error = Some(format!("Could not save the favorite: {}", FavoritesError::NoDataDir));
+            return;
+        };
+        match store.add(draft.name.value(), &draft.command) {
+            Ok(favorite) => {
+                self.notice = Some(format!("Saved as favorite `{}`", favorite.name));
+                self.close_favorites();
+            },
+            Err(FavoritesError::Exists(name)) => {
+                let replaced = store.get(&name).ok().flatten().map(|favorite| favorite.command).unwrap_or_default();
+                self.confirm = Some(Confirmation::ReplaceFavorite { name, command: draft.command.clone(), replaced });
+                self.input_mode = EditMode::Confirm;
+            },
+            Err(e) => self.error = Some(format!("Could not save the favorite: {}", e)),
+        }
+    }
+
+    fn replace_favorite(&mut self, name: &str, command: &str) {
+        let Some(store) = &self.favorites else {
+            return;
+        };
+        match store.replace(name, command) {
+            Ok(_) => self.notice = Some(format!("Replaced favorite `{}`", name)),
+            Err(e) => self.error = Some(format!("Could not save the favorite: {}", e)),
+        }
+        self.close_favorites();
+    }
+
+    /// Open the favorites popup, read from the file each time so favorites saved by other
+    /// aurish processes show up
+    fn open_favorites(&mut self) {
+        let favorites = match &self.favorites {
+            Some(store) => store.list().map_err(|e| e.to_string()),
+            None => Err(FavoritesError::NoDataDir.to_string()),
+        };
+        self.favorites_list = Some(FavoritesList::new(favorites));
+        self.input_mode = EditMode::Favorites;
+    }
+
+    fn select_favorite(&mut self, step: i32) {
+        if let Some(list) = self.favorites_list.as_mut() {
+            let count = list.favorites.as_ref().map_or(0, Vec::len);
+            if let Some(idx) = list.list_state.selected().filter(|_| count > 0) {
+                list.list_state.select(Some((idx as i32 + step).clamp(0, count as i32 - 1) as usize));
+            }
+        }
+    }
+
+    /// Put the command of the selected favorite into the Shell box: in place of nothing,
+    /// otherwise at the cursor
+    fn insert_favorite(&mut self) {
+        let Some(command) = self.favorites_list.as_ref().and_then(FavoritesList::selected).map(|favorite| favorite.command.clone()) else {
+            return;
+        };
+        self.close_favorites();
+        let mut input = self.shell.sh_input.borrow_mut();
+        if input.value().is_empty() {
+            *input = Input::new(command);
+        } else {
+            insert_str(&mut input, &command);
+        }
+        self.input_mode = EditMode::Shell;
+    }
+
+    fn remove_favorite(&mut self) {
+        let (Some(store), Some(list)) = (&self.favorites, self.favorites_list.as_mut()) else {
+            return;
+        };
+        let Some(name) = list.selected().map(|favorite| favorite.name.clone()) else {
+            return;
+        };
+        match store.remove(&name) {
+            Ok(_) => self.notice = Some(format!("Removed favorite `{}`", name)),
+            Err(e) => self.error = Some(format!("Could not remove the favorite: {}", e)),
+        }
+        let selected = list.list_state.selected().unwrap_or(0);
+        *list = FavoritesList::new(store.list().map_err(|e| e.to_string()));
+        let count = list.favorites.as_ref().map_or(0, Vec::len);
+        list.list_state.select((count > 0).then(|| selected.min(count - 1)));
+    }
+
+    fn close_favorites(&mut self) {
+        self.favorite_draft = None;
+        self.favorites_list = None;
+        self.input_mode = EditMode::Normal;
+    }
+
+    /// Open the Shared values popup for the values used by several queued commands
+    fn start_params(&mut self) {
+        if self.run_all.is_some() {
+            self.error = Some("Wait for the queued commands to finish".to_string());
+            return;
+        }
+        let commands: Vec<String> = self.shell_commands.iter().cloned().collect();
+        let (templates, params) = queue::parameterize(&commands);
+        if params.is_empty() {
+            self.notice = Some("The queued commands share no value to change".to_string());
+            return;
+        }
+        let mut draft = ParamsDraft { templates, params, current: 0, value: Input::default() };
+        draft.edit(0);
+        self.params_draft = Some(draft);
+        self.input_mode = EditMode::Params;
+    }
+
+    /// Keep the typed value and go to the next one, after the last put them all into the queue
+    fn set_param(&mut self) {
+        let Some(draft) = self.params_draft.as_mut() else {
+            return;
+        };
+        if draft.value.value().trim().is_empty() {
+            self.error = Some("Type a value, or press Esc to keep the commands as they are".to_string());
+            return;
+        }
+        draft.params[draft.current].value = draft.value.value().to_string();
+        if draft.current + 1 < draft.params.len() {
+            draft.edit(draft.current + 1);
+            return;
+        }
+        let commands = queue::substitute(&draft.templates, &draft.params);
+        let changed = commands.iter().zip(&self.shell_commands).filter(|(new, old)| new != old).count();
+        self.shell_commands = commands.into();
+        self.sync_shell_input();
+        self.notice = Some(format!("Changed {} queued command(s)", changed));
+        self.close_params();
+    }
+
+    fn close_params(&mut self) {
+        self.params_draft = None;
+        self.input_mode = EditMode::Normal;
+    }
+
+    /// Abort the pending request or comparison, the prompt stays in the Asking AI box
+    fn cancel_request(&mut self) {
+        self.fixing = None;
+        if let Some(pending) = self.pending.take().or_else(|| self.comparing.take()) {
+            pending.handle.abort();
+        }
+    }
+
+    /// Ask the last prompt of the current model and `compare_model` at once, to pick one
+    /// of the answers in Compare mode. Shows the last comparison again if it is still open.
+    /// Each model is asked without the conversation so far.
+    fn compare(&mut self, client: &Bclient, compare_tx: &ComparisonSender) {
+        if self.comparison.is_some() {
+            self.input_mode = EditMode::Compare;
+            return;
+        }
+        if self.pending.is_some() || self.comparing.is_some() {
+            self.error = Some("Wait for the pending request to finish".to_string());
+            return;
+        }
+        let Some(other) = self.compare_model.clone() else {
+            self.error = Some("Set `compare_model` in the config to compare the answers of two models".to_string());
+            return;
+        };
+        let Some(prompt) = self.last_prompt.clone() else {
+            self.notice = Some("No prompt to compare yet".to_string());
+            return;
+        };
+        let models = [self.messages.model().to_string(), other];
+        self.refresh_project_context();
+        let mut req = self.messages.clone();
+        req.set_context(Vec::new());
+        req.set_seed(None);
+        req.prompt(&prompt);
+        req.fit(self.max_prompt_tokens);
+        self.request_id += 1;
+        let id = self.request_id;
+        let (client, compare_tx) = (client.clone(), compare_tx.clone());
+        let handle = tokio::spawn(async move {
+            let models = models.each_ref().map(String::as_str);
+            let answers = client.send_ollama_multi(&models, &req).await;
+            let _ = compare_tx.send(ComparisonResult { id, prompt, answers });
+        });
+        self.comparing = Some(PendingRequest { id, started: Instant::now(), handle, regenerate: false, fix: false, loading: Default::default() });
+    }
+
+    /// Pick up the answers of a finished comparison
+    fn poll_comparisons(&mut self, compare_rx: &mut ComparisonReceiver) {
+        // checked before draining, like in `poll_responses`
+        let finished = self.comparing.as_ref().is_some_and(|pending| pending.handle.is_finished());
+        while let Ok(ComparisonResult { id, prompt, answers }) = compare_rx.try_recv() {
+            if self.comparing.take_if(|pending| pending.id == id).is_none() {
+                continue;
+            }
+            self.comparison = Some(Comparison { prompt, answers, selected: 0 });
+            // the comparison is read from the top
+            self.response_scroll = u16::MAX;
+            if self.confirm.is_none() {
+                self.input_mode = EditMode::Compare;
+            } else {
+                self.notice = Some("Answers compared, press c once the open question is answered".to_string());
+            }
+        }
+        if finished && self.comparing.take().is_some() {
+            self.error = Some("Comparison of the models ended unexpectedly".to_string());
+        }
+    }
+
+    fn select_answer(&mut self, step: i32) {
+        if let Some(comparison) = self.comparison.as_mut() {
+            let last = comparison.answers.len().saturating_sub(1) as i32;
+            comparison.selected = (comparison.selected as i32 + step).clamp(0, last) as usize;
+        }
+    }
+
+    /// Queue the commands of the selected answer instead of the current queue
+    fn queue_answer(&mut self) {
+        let Some(comparison) = self.comparison.as_ref() else {
+            return;
+        };
+        let answer = &comparison.answers[comparison.selected];
+        let commands = match &answer.commands {
+            Ok(commands) if !commands.is_empty() => commands.clone(),
+            _ => {
+                self.error = Some(format!("{} has no commands to queue, pick the other answer", answer.model));
+                return;
+            },
+        };
+        let (model, prompt) = (answer.model.clone(), comparison.prompt.clone());
+        let queued = self.recv_from(commands.clone());
+        if queued == 0 {
+            return;
+        }
+        self.session.prompt_sent(&model, &prompt);
+        self.session.commands_received(&commands);
+        self.notice = Some(format!("Queued the {} command(s) of {}", queued, model));
+        self.record_exchange(prompt, commands);
+        self.comparison = None;
+        self.input_mode = EditMode::Normal;
+    }
+
+    /// Leave Compare mode keeping the queue as it is
+    fn close_comparison(&mut self) {
+        self.comparison = None;
+        self.response_scroll = 0;
+        self.input_mode = EditMode::Normal;
+    }
+
+    /// The prompt and each model's answer, with its timing, the selected one highlighted
+    fn comparison_text(&self, comparison: &Comparison) -> Text<'static> {
+        let mut lines = vec![Line::from(vec![
+            Span::styled("> ", Style::default().fg(self.theme.status)),
+            Span::styled(comparison.prompt.clone(), Style::default().add_modifier(Modifier::BOLD)),
+        ])];
+        for (idx, answer) in comparison.answers.iter().enumerate() {
+            lines.push(Line::default());
+            let heading = format!("{} ({})", answer.model, format_elapsed(answer.latency));
+            lines.push(if idx == comparison.selected {
+                Line::styled(format!("▶ {}", heading), Style::default().fg(self.theme.active).add_modifier(Modifier::REVERSED))
+            } else {
+                Line::styled(format!("  {}", heading), Style::default().fg(self.theme.model))
+            });
+            match &answer.commands {
+                Ok(commands) if commands.is_empty() => {
+                    lines.push(Line::styled("  (no commands)", Style::default().add_modifier(Modifier::DIM)));
+                },
+                Ok(commands) => {
+                    for (idx, command) in commands.iter().enumerate() {
+                        lines.push(Line::raw(format!("  {}. {}", idx + 1, command)));
+                    }
+                },
+                Err(e) => lines.push(Line::styled(format!("  {}", e), Style::default().fg(self.theme.stderr))),
+            }
+        }
+        Text::from(lines)
+    }
+
+    /// Remember a prompt and the commands the model answered with
+    fn record_exchange(&mut self, prompt: String, commands: Vec<String>) {
+        self.conversation.push(Exchange { prompt, commands });
+        self.response_scroll = 0;
+    }
+
+    /// Scroll the Response block, positive `lines` go back in history
+    fn scroll_response(&mut self, lines: i32) {
+        self.response_scroll = (self.response_scroll as i32 + lines).clamp(0, u16::MAX as i32) as u16;
+    }
+
+    fn conversation_text(&self) -> Text<'static> {
+        let mut lines = Vec::new();
+        for exchange in &self.conversation {
+            if !lines.is_empty() {
+                lines.push(Line::default());
+            }
+            lines.push(Line::from(vec![
+                Span::styled("> ", Style::default().fg(self.theme.status)),
+                Span::styled(exchange.prompt.clone(), Style::default().add_modifier(Modifier::BOLD)),
+            ]));
+            if exchange.commands.is_empty() {
+                lines.push(Line::styled("  (no commands)", Style::default().add_modifier(Modifier::DIM)));
+            }
+            for (idx, command) in exchange.commands.iter().enumerate() {
+                lines.push(Line::raw(format!("  {}. {}", idx + 1, command)));
+            }
+        }
+        Text::from(lines)
+    }
+
+    /// Currently selected pending command
+    /// The selected command if `command` is an edit of it
+    fn edited_suggestion(&self, command: &str) -> Option<&str> {
+        self.selected_command().filter(|suggested| *suggested != command)
+    }
+
+    pub fn selected_command(&self) -> Option<&str> {
+        self.queue_state.selected()
+            .and_then(|idx| self.shell_commands.get(idx))
+            .map(String::as_str)
+    }
+
+    fn select_prev(&mut self) {
+        if let Some(idx) = self.queue_state.selected() {
+            self.queue_state.select(Some(idx.saturating_sub(1)));
+            self.sync_shell_input();
+        }
+    }
+
+    fn select_next(&mut self) {
+        if let Some(idx) = self.queue_state.selected() {
+            self.queue_state.select(Some((idx + 1).min(self.shell_commands.len() - 1)));
+            self.sync_shell_input();
+        }
+    }
+
+    fn move_selected_up(&mut self) {
+        if let Some(idx) = self.queue_state.selected().filter(|&idx| idx > 0) {
+            self.shell_commands.swap(idx, idx - 1);
+            self.queue_state.select(Some(idx - 1));
+        }
+    }
+
+    fn move_selected_down(&mut self) {
+        if let Some(idx) = self.queue_state.selected().filter(|&idx| idx + 1 < self.shell_commands.len()) {
+            self.shell_commands.swap(idx, idx + 1);
+            self.queue_state.select(Some(idx + 1));
+        }
+    }
+
+    /// Drop the selected command from the queue and show the next one in the Shell box.
+    /// Returns the dropped command, `None` if the queue was already empty.
+    pub fn advance_queue(&mut self) -> Option<String> {
+        let removed = self.queue_state.selected().and_then(|idx| {
+            let removed = self.shell_commands.remove(idx);
+            self.queue_state.select(match self.shell_commands.len() {
+                0 => None,
+                len => Some(idx.min(len - 1)),
+            });
+            removed
+        });
+        self.sync_shell_input();
+        removed
+    }
+
+    /// Skip the selected command without running it
+    fn skip_selected(&mut self) {
+        if let Some(command) = self.advance_queue() {
+            self.session.command_skipped(&command);
+            self.log_output(ExecutedCommand::note(&command, "skipped".to_string()));
+        }
+    }
+
+    /// Drop every pending command
+    fn discard_queue(&mut self) {
+        if !self.shell_commands.is_empty() {
+            let note = format!("discarded {} command(s)", self.shell_commands.len());
+            self.log_output(ExecutedCommand::note("", note));
+        }
+        for command in &self.shell_commands {
+            self.session.command_skipped(command);
+        }
+        self.shell_commands.clear();
+        self.queue_state.select(None);
+        self.sync_shell_input();
+    }
+
+    /// Run `command` through IShell, or start it as a job if it ends with `&`,
+    /// and record the result in the Output history. `suggested` is the command it is an edit of.
+    fn execute_command(&mut self, command: String, suggested: Option<String>) {
+        if let Some(job_comm) = strip_background(&command) {
+            let note = match self.shell.shell.spawn_job(job_comm) {
+                Ok(job) => {
+                    self.session.command_started(&command, suggested.as_deref());
+                    format!("[{}] started in background", job.id())
+                },
+                Err(e) => format!("Failed to start job: {}", e),
+            };
+            self.log_output(ExecutedCommand { suggested, ..ExecutedCommand::note(&command, note) });
+            return;
+        }
+
+        let started = Instant::now();
+        let out_msg = self.shell.shell.run_command(&command);
+        self.record_output(command, suggested, out_msg, started.elapsed());
+    }
+
+    /// Add what a command printed to the Output history
+    fn record_output(&mut self, command: String, suggested: Option<String>, out_msg: ShellOutput, duration: Duration) {
+        let (stdout, stderr) = (String::from_utf8(out_msg.stdout), String::from_utf8(out_msg.stderr));
+        if stdout.is_err() || stderr.is_err() {
+            self.error = Some("Command output is not valid UTF-8".to_string());
+        }
+        let lossy = |output: Result<String, std::string::FromUtf8Error>| {
+            output.unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+        };
+        let (stdout, stderr) = (lossy(stdout), lossy(stderr));
+        self.session.command_executed(&command, suggested.as_deref(), out_msg.code, duration, &stdout, &stderr);
+        self.log_output(ExecutedCommand {
+            command,
+            exit_code: out_msg.code,
+            stdout,
+            stderr,
+            duration,
+            paged: None,
+            suggested,
+            interactive: false,
+        });
+    }
+
+    /// Run a command from the Shell box, asking first if it looks dangerous.
+    /// An empty Shell box is ignored.
+    fn submit_command(&mut self, command: String) {
+        self.submit(command, false);
+    }
+
+    /// `submit_command` attached to the terminal, for commands that ask for input
+    fn submit_interactive(&mut self, command: String) {
+        self.submit(command, true);
+    }
+
+    fn submit(&mut self, command: String, interactive: bool) {
+        if command.trim().is_empty() {
+            return;
+        }
+        match danger_reason(&command, &self.dangerous_patterns) {
+            Some(reason) => {
+                self.confirm = Some(Confirmation::Dangerous { command, reason, interactive });
+                self.input_mode = EditMode::Confirm;
+            },
+            None if interactive => self.tty_due = Some(TtyRun::Command(command)),
+            None => self.run_submitted(command),
+        }
+    }
+
+    /// Run a command from the Shell box, unless sudo would ask for a password in it, which
+    /// is asked about first
+    fn run_submitted(&mut self, command: String) {
+        if self.needs_sudo_password(&command) {
+            self.confirm = Some(Confirmation::SudoPassword { command });
+            self.input_mode = EditMode::Confirm;
+            return;
+        }
+        let suggested = self.take_edit(&command);
+        self.execute_command(command, suggested);
+        self.finish_submitted();
+    }
+
+    /// The suggested command `command` is an edit of, if it is one, noted in the session
+    fn take_edit(&mut self, command: &str) -> Option<String> {
+        let suggested = self.edited_suggestion(command).map(str::to_string);
+        if let Some(suggested) = &suggested {
+            self.session.command_edited(suggested, command);
+        }
+        suggested
+    }
+
+    fn finish_submitted(&mut self) {
+        // executed command is consumed from the queue
+        self.advance_queue();
+        self.input_mode = EditMode::Normal;
+        self.continue_auto_fix();
+    }
+
+    /// Whether `command` starts with `sudo` and sudo has no cached credentials
+    fn needs_sudo_password(&self, command: &str) -> bool {
+        self.shell.shell.shell_type().is_posix() && interactive::needs_sudo_password(command, self.sudo_cached)
+    }
+
+    /// Run the command of the sudo popup with `sudo -n`, which fails at once without cached
+    /// credentials instead of asking for a password
+    fn run_without_password(&mut self) {
+        let Some(Confirmation::SudoPassword { command }) = self.confirm.take_if(|confirm| matches!(confirm, Confirmation::SudoPassword { .. })) else {
+            return;
+        };
+        self.input_mode = EditMode::Normal;
+        self.run_submitted(interactive::non_interactive_sudo(&command));
+        if self.shell.history.last().is_some_and(|entry| entry.exit_code != Some(0)) {
+            let key = self.keymap.iter()
+                .find(|binding| binding.mode == EditMode::Shell && binding.action == Action::ExecuteInteractive)
+                .map_or("the terminal key".to_string(), |binding| binding.label());
+            self.notice = Some(format!("sudo needs a password, press {} in the Shell box to run `{}` on the terminal", key, command));
+        }
+    }
+
+    /// Run `run` with the TUI suspended, reading and writing the terminal itself
+    fn run_on_terminal(&mut self, terminal: &mut DefaultTerminal, run: TtyRun) -> io::Result<()> {
+        suspend(terminal)?;
+        match run {
+            TtyRun::Command(command) => {
+                println!("$ {}", command);
+                let started = Instant::now();
+                let output = self.shell.shell.run_interactive(&command);
+                let duration = started.elapsed();
+                match output.code {
+                    Some(code) => println!("\n[exit {}] Press Enter to go back to aurish", code),
+                    None => println!("\n[killed] Press Enter to go back to aurish"),
+                }
+                io::stdin().read_line(&mut String::new())?;
+                resume(terminal)?;
+                self.record_interactive(command, output.code, duration);
+            },
+            TtyRun::SudoThen(command) => {
+                println!("sudo asks for your password before running: {}", command);
+                let validated = self.shell.shell.run_interactive(interactive::SUDO_VALIDATE).is_success();
+                resume(terminal)?;
+                if validated {
+                    self.run_submitted(command);
+                } else {
+                    self.error = Some(format!("{} failed, `{}` did not run", interactive::SUDO_VALIDATE, command));
+                    self.input_mode = EditMode::Shell;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Add a command that ran on the terminal to the Output history, which only knows its
+    /// exit code
+    fn record_interactive(&mut self, command: String, exit_code: Option<i32>, duration: Duration) {
+        let suggested = self.take_edit(&command);
+        self.session.command_ran_interactively(&command, suggested.as_deref(), exit_code, duration);
+        self.log_output(ExecutedCommand {
+            interactive: true,
+            exit_code,
+            duration,
+            suggested,
+            ..ExecutedCommand::note(&command, String::new())
+        });
+        self.finish_submitted();
+    }
+
+    fn cycle_suggestion_mode(&mut self) {
+        let mode = self.messages.suggestion_mode().next();
+        self.messages.set_suggestion_mode(mode);
+        self.notice = Some(match mode {
+            SuggestionMode::Auto => "Auto mode: the model suggests as many commands as the task needs".to_string(),
+            SuggestionMode::Single => "Single mode: the model suggests one command".to_string(),
+            SuggestionMode::Plan => "Plan mode: the model suggests every step of the task".to_string(),
+        });
+    }
+
+    fn toggle_auto_fix(&mut self) {
+        self.auto_fix = !self.auto_fix;
+        self.fixing = None;
+        self.notice = Some(match self.auto_fix {
+            true => format!("Auto-fix on: a failed command is sent back to the model, up to {} times in a row", self.auto_fix_attempts),
+            false => "Auto-fix off".to_string(),
+        });
+    }
+
+    /// After a command from the Shell box ran: with auto-fix on, ask for a correction if
+    /// it failed, and stop once it succeeded or the attempts are used up
+    fn continue_auto_fix(&mut self) {
+        let Some(entry) = self.shell.history.last().filter(|_| self.auto_fix) else {
+            return;
+        };
+        match entry.exit_code {
+            // started as a job, or killed
+            None => self.fixing = None,
+            Some(0) => {
+                if let Some(fix) = self.fixing.take() {
+                    self.notice = Some(format!("Fixed by {}", fix.label()));
+                }
+            },
+            Some(code) => {
+                let fix = self.fixing.get_or_insert_with(|| AutoFix::new(&entry.command, self.auto_fix_attempts));
+                if fix.next_attempt() {
+                    self.fix_due = Some(autofix::fix_prompt(&entry.command, Some(code), &entry.stdout, &entry.stderr));
+                } else {
+                    let note = format!("auto-fix gave up after {} attempt(s) at `{}`", fix.attempt(), fix.original);
+                    self.fixing = None;
+                    self.log_output(ExecutedCommand::note("", note));
+                }
+            },
+        }
+    }
+
+    /// Ask the model for a correction, continuing the conversation
+    fn send_fix(&mut self, prompt: String, client: &Bclient, res_tx: &ResponseSender) {
+        if self.pending.is_some() || self.comparing.is_some() {
+            self.fixing = None;
+            self.notice = Some("Auto-fix stopped, another request is pending".to_string());
+            return;
+        }
+        self.refresh_project_context();
+        let mut req = self.messages.clone();
+        req.prompt(&prompt);
+        self.spawn_request(req, prompt, false, true, client, res_tx);
+    }
+
+    /// Offer to run the correction the model answered, which goes first in the queue. A
+    /// correction that looks dangerous ends auto-fix instead, it is never run unasked.
+    fn finish_fix(&mut self, res: Result<Generation, BackendError>) {
+        // turned off meanwhile
+        let Some(fix) = self.fixing.clone() else {
+            return;
+        };
+        let commands = match res {
+            Ok(generation) => generation.commands,
+            Err(e) => {
+                let message = self.request_error(&e);
+                self.session.error(&message);
+                self.fixing = None;
+                self.error = Some(format!("Auto-fix stopped: {}", message));
+                return;
+            },
+        };
+        self.session.commands_received(&commands);
+        match autofix::correction(commands, self.multiline, &self.dangerous_patterns) {
+            Correction::Nothing => {
+                self.fixing = None;
+                self.log_output(ExecutedCommand::note("", format!("{}: the model suggested no command, auto-fix stopped", fix.label())));
+            },
+            Correction::Dangerous { command, reason } => {
+                self.fixing = None;
+                self.log_output(ExecutedCommand::note(&command, format!("{}: not run, this command {}", fix.label(), reason)));
+                self.queue_first(command);
+                self.notice = Some("Auto-fix stopped at a dangerous correction, check it in the Shell box".to_string());
+            },
+            Correction::Run(command) => {
+                self.log_output(ExecutedCommand::note(&command, format!("{}: suggested for `{}`", fix.label(), fix.original)));
+                self.queue_first(command.clone());
+                if self.confirm.is_some() {
+                    self.fixing = None;
+                    self.notice = Some("Auto-fix stopped, answer the open question first".to_string());
+                    return;
+                }
+                let failed = self.shell.history.iter().rev().find(|entry| entry.exit_code.is_some()).map_or(String::new(), |entry| entry.command.clone());
+                self.confirm = Some(Confirmation::AutoFix { command, failed, attempt: fix.label() });
+                self.input_mode = EditMode::Confirm;
+            },
+        }
+    }
+
+    /// Put `command` in the queue at the selection and select it
+    fn queue_first(&mut self, command: String) {
+        let idx = self.queue_state.selected().unwrap_or(0).min(self.shell_commands.len());
+        self.shell_commands.insert(idx, command);
+        self.queue_state.select(Some(idx));
+        self.sync_shell_input();
+    }
+
+    fn accept_confirmation(&mut self, commands_tx: &CommandSender) {
+        self.input_mode = EditMode::Normal;
+        match self.confirm.take() {
+            Some(Confirmation::Dangerous { .. }) if self.run_all.is_some() => self.run_next_queued(commands_tx, true),
+            Some(Confirmation::Dangerous { command, interactive: true, .. }) => self.tty_due = Some(TtyRun::Command(command)),
+            Some(Confirmation::Dangerous { command, .. } | Confirmation::AutoFix { command, .. }) => self.run_submitted(command),
+            Some(Confirmation::SudoPassword { command }) => self.tty_due = Some(TtyRun::SudoThen(command)),
+            Some(Confirmation::RunAll { .. }) => self.start_run_all(commands_tx),
+            Some(Confirmation::ReplaceQueue { commands, .. }) => {
+                self.recv_from(commands);
+            },
+            Some(Confirmation::RestoreQueue { saved }) => self.restore_queue(saved),
+            Some(Confirmation::ReplaceFavorite { name, command, .. }) => self.replace_favorite(&name, &command),
+            // handled by the event loop
+            Some(Confirmation::Quit { .. }) | None => {},
+        }
+    }
+
+    /// Back to the Shell box with a rejected dangerous command still in it, which also
+    /// ends "run all". Declining to replace a favorite goes back to typing its name.
+    fn reject_confirmation(&mut self) {
+        self.input_mode = match self.confirm.take() {
+            Some(Confirmation::Dangerous { .. } | Confirmation::SudoPassword { .. }) => EditMode::Shell,
+            Some(Confirmation::AutoFix { .. }) => {
+                self.fixing = None;
+                self.notice = Some("Auto-fix stopped, the correction is left in the Shell box".to_string());
+                EditMode::Shell
+            },
+            Some(Confirmation::ReplaceQueue { .. }) => {
+                self.notice = Some("Kept the queued commands".to_string());
+                EditMode::Normal
+            },
+            Some(Confirmation::RestoreQueue { .. }) => {
+                self.queue_file.clear();
+                self.notice = Some("Discarded the commands of the previous session".to_string());
+                EditMode::Normal
+            },
+            Some(Confirmation::ReplaceFavorite { .. }) if self.favorite_draft.is_some() => EditMode::FavoriteName,
+            _ => EditMode::Normal,
+        };
+        self.run_all = None;
+    }
+
+    /// Ask whether to queue the commands an earlier session left. A file that cannot be
+    /// read is removed with a warning.
+    fn offer_saved_queue(&mut self) {
+        match self.queue_file.load() {
+            Ok(Some(saved)) => {
+                self.confirm = Some(Confirmation::RestoreQueue { saved });
+                self.input_mode = EditMode::Confirm;
+            },
+            Ok(None) => {},
+            Err(e) => {
+                log::warn!("{}", e);
+                self.error = Some(format!("Ignored the queue of the previous session: {}", e));
+                self.queue_file.clear();
+            },
+        }
+    }
+
+    /// Queue the commands of `saved`, in the directory they were going to run in
+    fn restore_queue(&mut self, saved: SavedQueue) {
+        let count = saved.commands.len();
+        self.notice = Some(match self.shell.shell.set_current_dir(&saved.directory) {
+            true => format!("Restored {} command(s)", count),
+            false => format!("Restored {} command(s), {} is gone", count, saved.directory.display()),
+        });
+        self.shell.renew_path();
+        self.refresh_project_context();
+        if saved.prompt.is_some() {
+            self.last_prompt = saved.prompt;
+        }
+        self.shell_commands = VecDeque::from(saved.commands);
+        self.queue_state.select(Some(0));
+        self.sync_shell_input();
+    }
+
+    /// Keep the queue file in step with the queue, unless the saved one is still on offer
+    fn save_queue(&mut self) {
+        if !matches!(self.confirm, Some(Confirmation::RestoreQueue { .. })) {
+            self.queue_file.update(self.last_prompt.as_deref(), &self.shell.shell.current_dir(), &self.shell_commands);
+        }
+    }
+
+    /// Ask before running the whole queue
+    fn ask_run_all(&mut self) {
+        if self.run_all.is_some() {
+            self.error = Some("Wait for the queued commands to finish".to_string());
+        } else if !self.shell_commands.is_empty() {
+            self.confirm = Some(Confirmation::RunAll { count: self.shell_commands.len() });
+            self.input_mode = EditMode::Confirm;
+        }
+    }
+
+    fn start_run_all(&mut self, commands_tx: &CommandSender) {
+        self.run_all = Some(RunAll {
+            total: self.shell_commands.len(),
+            done: 0,
+            started: Instant::now(),
+            command_started: None,
+            stopping: false,
+        });
+        self.run_next_queued(commands_tx, false);
+    }
+
+    /// Start the first queued command in the background, or end "run all" once the queue is
+    /// empty. Dangerous commands wait for confirmation unless already `confirmed`.
+    fn run_next_queued(&mut self, commands_tx: &CommandSender, mut confirmed: bool) {
+        loop {
+            let Some(run) = self.run_all.as_ref() else {
+                return;
+            };
+            if run.stopping || self.shell_commands.is_empty() {
+                self.run_all = None;
+                return;
+            }
+
+            // the Shell box shows the command that runs next
+            self.queue_state.select(Some(0));
+            self.sync_shell_input();
+            let command = self.shell_commands[0].clone();
+            if !confirmed {
+                if let Some(reason) = danger_reason(&command, &self.dangerous_patterns) {
+                    self.confirm = Some(Confirmation::Dangerous { command, reason, interactive: false });
+                    self.input_mode = EditMode::Confirm;
+                    return;
+                }
+            }
+            if self.needs_sudo_password(&command) {
+                self.run_all = None;
+                self.notice = Some("Stopped running the queue, sudo would wait for a password".to_string());
+                self.confirm = Some(Confirmation::SudoPassword { command });
+                self.input_mode = EditMode::Confirm;
+                return;
+            }
+            confirmed = false;
+            self.advance_queue();
+
+            // jobs only take a moment to start
+            if strip_background(&command).is_some() {
+                self.execute_command(command, None);
+                if let Some(run) = self.run_all.as_mut() {
+                    run.done += 1;
+                }
+                continue;
+            }
+
+            if let Some(run) = self.run_all.as_mut() {
+                run.command_started = Some(Instant::now());
+            }
+            let (shell, commands_tx) = (Arc::clone(&self.shell.shell), commands_tx.clone());
+            tokio::task::spawn_blocking(move || {
+                let started = Instant::now();
+                let output = shell.run_command(&command);
+                let _ = commands_tx.send(CommandResult { command, output, duration: started.elapsed() });
+            });
+            return;
+        }
+    }
+
+    /// Pick up commands "run all" finished and start the next ones
+    fn poll_commands(&mut self, commands_rx: &mut CommandReceiver, commands_tx: &CommandSender) {
+        while let Ok(result) = commands_rx.try_recv() {
+            self.finish_queued(result, commands_tx);
+        }
+    }
+
+    /// Record a command run by "run all". A failed command goes back to the front of the
+    /// queue and stops the run, so it can be fixed and the rest resumed.
+    fn finish_queued(&mut self, result: CommandResult, commands_tx: &CommandSender) {
+        let CommandResult { command, output, duration } = result;
+        let success = output.is_success();
+        self.record_output(command.clone(), None, output, duration);
+        if let Some(run) = self.run_all.as_mut() {
+            run.done += 1;
+            run.command_started = None;
+        }
+        if success {
+            self.run_next_queued(commands_tx, false);
+        } else {
+            self.error = Some(format!("`{}` failed, stopped running the queue", command));
+            self.shell_commands.push_front(command);
+            self.queue_state.select(Some(0));
+            self.sync_shell_input();
+            self.run_all = None;
+        }
+    }
+
+    /// Append to the Output history and scroll to it. The command may have changed
+    /// the directory, so the prompt path follows the shell.
+    fn log_output(&mut self, mut entry: ExecutedCommand) {
+        match PagedOutput::write(&entry.stdout, &entry.stderr) {
+            Ok(paged) => entry.paged = paged,
+            // shown in full then, slow as it may be
+            Err(e) => self.error = Some(format!("Could not write the long output to a file: {}", e)),
+        }
+        self.shell.history.push(entry);
+        self.shell.history_scroll = 0;
+        self.shell.renew_path();
+        self.refresh_project_context();
+    }
+
+    /// Go back to the directory aurish was started in
+    fn reset_directory(&mut self) {
+        self.shell.shell.forget_current_directory();
+        self.shell.renew_path();
+        self.refresh_project_context();
+        self.notice = Some(format!("Back in {}", self.shell.get_path()));
+    }
+
+    /// Terminal window title, naming the shell's directory
+    fn window_title(&self) -> String {
+        format!("aurish — {}", self.shell.get_path())
+    }
+
+    /// Scroll the Output block, positive `lines` go back in history
+    fn scroll_history(&mut self, lines: i32) {
+        self.shell.history_scroll = (self.shell.history_scroll as i32 + lines).clamp(0, u16::MAX as i32) as u16;
+    }
+
+    /// Output history and the line each entry starts at
+    fn history_lines(&self) -> (Text<'static>, Vec<usize>) {
+        let mut lines = Vec::new();
+        let mut starts = Vec::new();
+        for (idx, entry) in self.shell.history.iter().enumerate() {
+            starts.push(lines.len());
+            let status = match entry.exit_code {
+                Some(code) => format!(" [exit {}, {:.2}s]", code, entry.duration.as_secs_f32()),
+                None => String::new(),
+            };
+            let status_style = match entry.exit_code {
+                Some(0) => Style::default().fg(self.theme.status),
+                Some(_) => Style::default().fg(self.theme.error),
+                None => Style::default().add_modifier(Modifier::DIM),
+            };
+            let mut header = Line::from(vec![
+                Span::styled(format!("$ {}", entry.command), Style::default().add_modifier(Modifier::BOLD)),
+                Span::styled(status, status_style),
+            ]);
+            if self.input_mode == EditMode::Output && self.output_selected == Some(idx) {
+                header = header.patch_style(Style::default().add_modifier(Modifier::REVERSED));
+            }
+            lines.push(header);
+            if let Some(suggested) = &entry.suggested {
+                lines.push(Line::styled(format!("edited from: {}", suggested), Style::default().add_modifier(Modifier::DIM | Modifier::ITALIC)));
+            }
+            if entry.interactive {
+                lines.push(Line::styled(interactive::INTERACTIVE_NOTE, Style::default().add_modifier(Modifier::DIM | Modifier::ITALIC)));
+            }
+            if let Some(paged) = &entry.paged {
+                lines.extend(ansi::to_text(paged.head.strip_suffix('\n').unwrap_or(&paged.head)).lines);
+                lines.push(Line::styled(paged.notice(), Style::default().fg(self.theme.status).add_modifier(Modifier::ITALIC)));
+                lines.extend(ansi::to_text(&paged.tail).lines);
+                continue;
+            }
+            for (stream, text) in output_parts(&entry.stdout, &entry.stderr) {
+                let text = ansi::to_text(text);
+                match stream {
+                    OutputStream::Stdout => lines.extend(text.lines),
+                    OutputStream::Stderr => {
+                        let style = Style::default().fg(self.theme.stderr).add_modifier(Modifier::DIM);
+                        lines.extend(text.lines.into_iter().map(|line| line.patch_style(style)));
+                    },
+                }
+            }
+        }
+        (Text::from(lines), starts)
+    }
+
+    /// Enter Search mode with an empty query, the search stays until Esc
+    fn start_search(&mut self) {
+        if self.input_mode != EditMode::Search {
+            self.search_return = self.input_mode;
+        }
+        self.search_input.reset();
+        self.update_search();
+        self.input_mode = EditMode::Search;
+    }
+
+    /// Search the Output history for the query being typed, jumping to the newest match
+    fn update_search(&mut self) {
+        let mut search = Search::new(self.search_input.value());
+        search.update(&plain_lines(&self.history_lines().0));
+        self.search = Some(search);
+        self.search_jump = true;
+    }
+
+    /// Go back to the mode the search was started from, keeping the search for n/N
+    /// unless `keep` is false or the query is empty
+    fn end_search(&mut self, keep: bool) {
+        if !keep || self.search_input.value().is_empty() {
+            self.search = None;
+        }
+        self.input_mode = self.search_return;
+    }
+
+    /// Jump to the next (or previous) match of the search, selecting its entry in Output mode
+    fn jump_to_match(&mut self, forward: bool) {
+        let (text, starts) = self.history_lines();
+        let Some(search) = self.search.as_mut() else {
+            self.notice = Some("Press / to search the output first".to_string());
+            return;
+        };
+        search.update(&plain_lines(&text));
+        let found = if forward { search.next_match() } else { search.prev_match() };
+        let Some(line) = found else {
+            self.notice = Some(format!("Nothing matches `{}`", search.query()));
+            return;
+        };
+        self.search_jump = true;
+        if self.input_mode == EditMode::Output || self.search_return == EditMode::Output {
+            self.output_selected = starts.iter().rposition(|start| *start <= line);
+        }
+    }
+
+    /// Enter Output mode with the newest entry selected
+    fn focus_output(&mut self) {
+        self.output_selected = self.shell.history.len().checked_sub(1);
+        self.input_mode = EditMode::Output;
+    }
+
+    fn select_output(&mut self, step: i32) {
+        let len = self.shell.history.len();
+        if len > 0 {
+            let idx = self.output_selected.unwrap_or(len - 1) as i32 + step;
+            self.output_selected = Some(idx.clamp(0, len as i32 - 1) as usize);
+        }
+    }
+
+    /// Output entry to copy from, the selected one in Output mode and the newest otherwise
+    fn output_entry(&self) -> Option<&ExecutedCommand> {
+        match self.input_mode {
+            EditMode::Output => self.output_selected.and_then(|idx| self.shell.history.get(idx)),
+            _ => self.shell.history.last(),
+        }
+    }
+
+    /// Open the long output of the selected Output entry in the pager once `perform` returns
+    fn request_pager(&mut self) {
+        match self.output_entry() {
+            Some(entry) if entry.paged.is_some() => self.pager_requested = true,
+            Some(_) => self.notice = Some("The whole output is shown already".to_string()),
+            None => self.notice = Some("Nothing has been run yet".to_string()),
+        }
+    }
+
+    /// Leave the alternate screen and raw mode, run the pager on the selected entry's
+    /// output and take the terminal back, redrawing it from scratch
+    fn open_pager(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        let Some(path) = self.output_entry().and_then(|entry| entry.paged.as_ref()).map(|paged| paged.path().to_path_buf()) else {
+            return Ok(());
+        };
+        suspend(terminal)?;
+        let status = pager::open(&path);
+        resume(terminal)?;
+        let (program, _) = pager::pager_command();
+        match status {
+            Ok(status) if !status.success() => self.error = Some(format!("{} exited with {}", program, status)),
+            Ok(_) => {},
+            Err(e) => self.error = Some(format!("Could not run {}: {}, set $PAGER to another pager", program, e)),
+        }
+        Ok(())
+    }
+
+    /// Copy what the selected Output entry printed, without colors
+    fn copy_output(&mut self) {
+        let text = self.output_entry().map(|entry| ansi::strip(&entry.stdout)).unwrap_or_default();
+        self.copy_to_clipboard(text);
+    }
+
+    /// Copy the selected Output entry's command in Output mode,
+    /// the selected queued command (or the Shell box) otherwise
+    fn copy_command(&mut self) {
+        let text = match self.input_mode {
+            EditMode::Output => self.output_entry().map(|entry| entry.command.clone()).unwrap_or_default(),
+            _ => self.selected_command()
+                .map(str::to_string)
+                .unwrap_or_else(|| self.shell.sh_input.borrow().value().to_string()),
+        };
+        self.copy_to_clipboard(text);
+    }
+
+    fn copy_to_clipboard(&mut self, text: String) {
+        if text.is_empty() {
+            self.notice = Some("Nothing to copy".to_string());
+            return;
+        }
+        match self.clipboard.copy(&text) {
+            Ok(()) => self.notice = Some(format!("Copied {} bytes", text.len())),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// Queue the commands on the clipboard, e.g. copied from a wiki
+    fn paste_commands(&mut self) {
+        match self.clipboard.paste() {
+            Ok(text) => self.queue_loaded(commands_from_text(&text), "the clipboard"),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// Queue `commands` from `source` after those queued already and select the first of
+    /// them in Shell mode. They are run like suggested ones, dangerous ones asked about first.
+    fn queue_loaded(&mut self, commands: Vec<String>, source: &str) {
+        if self.run_all.is_some() {
+            self.error = Some("Wait for the queued commands to finish".to_string());
+            return;
+        }
+        if commands.is_empty() {
+            self.notice = Some(format!("No commands in {}", source));
+            return;
+        }
+        let first = self.shell_commands.len();
+        self.notice = Some(format!("Queued {} command(s) from {}", commands.len(), source));
+        self.shell_commands.extend(commands);
+        self.queue_state.select(Some(first));
+        self.sync_shell_input();
+        self.input_mode = EditMode::Shell;
+    }
+
+    /// Put the selected command into the Shell box, or clear it when the queue is empty
+    fn sync_shell_input(&mut self) {
+        match self.selected_command().map(str::to_string) {
+            Some(command) => {
+                let mut input_ref = self.shell.sh_input.borrow_mut();
+                *input_ref = input_ref.clone().with_value(command);
+            },
+            None => self.shell.input_reset(),
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+/// Hand the terminal back to the shell: leave raw mode and the alternate screen
+fn suspend(terminal: &mut DefaultTerminal) -> io::Result<()> {
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), DisableBracketedPaste, LeaveAlternateScreen)?;
+    terminal.show_cursor()
+}
+
+#[cfg(feature = "tui")]
+/// Take the terminal back after `suspend`, redrawing it from scratch
+fn resume(terminal: &mut DefaultTerminal) -> io::Result<()> {
+    enable_raw_mode()?;
+    execute!(terminal.backend_mut(), EnterAlternateScreen, EnableBracketedPaste)?;
+    terminal.clear()
+}
+
+#[cfg(feature = "tui")]
+/// Run `restore` before the current panic hook whenever a panic happens.
+///
+/// Install this before switching the terminal into raw mode, so the panic message is printed
+/// to a usable terminal instead of the alternate screen.
+pub fn install_panic_hook<F>(restore: F)
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let original_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic_info| {
+        restore();
+        original_hook(panic_info);
+    }));
+}
+
+#[cfg(feature = "tui")]
+/// Set `flag` once the program is asked to end from outside: SIGTERM or SIGHUP, e.g. when
+/// the terminal window is closed or systemd stops the session, or closing the console on
+/// Windows. The signals no longer end the program right away, `App::with_shutdown` quits it.
+/// The commands and jobs of `shell` are killed first, as a command running in the
+/// foreground holds up the loop that checks `flag`, see `App::shell`.
+pub fn shutdown_on_signals(flag: Arc<AtomicBool>, shell: Arc<IShell>) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term = signal(SignalKind::terminate())?;
+        let mut hangup = signal(SignalKind::hangup())?;
+        tokio::spawn(async move {
+            let name = tokio::select! {
+                _ = term.recv() => "SIGTERM",
+                _ = hangup.recv() => "SIGHUP",
+            };
+            log::info!("{} received, quitting", name);
+            shell.kill_all();
+            flag.store(true, Ordering::SeqCst);
+        });
+    }
+    #[cfg(windows)]
+    {
+        let mut close = tokio::signal::windows::ctrl_close()?;
+        tokio::spawn(async move {
+            close.recv().await;
+            log::info!("console closed, quitting");
+            shell.kill_all();
+            flag.store(true, Ordering::SeqCst);
+        });
+    }
+    Ok(())
+}
+
+#[cfg(feature = "tui")]
+/// Lines of pasted text, with `\r\n` line endings and the trailing newlines removed
+fn paste_lines(text: &str) -> Vec<String> {
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    let text = text.trim_end_matches('\n');
+    if text.is_empty() {
+        return Vec::new();
+    }
+    text.split('\n').map(str::to_string).collect()
+}
+
+#[cfg(feature = "tui")]
+/// Type `text` into a single line input at its cursor, tabs become spaces
+fn insert_str(input: &mut Input, text: &str) {
+    for c in text.chars().map(|c| if c == '\t' { ' ' } else { c }).filter(|c| !c.is_control()) {
+        input.handle(InputRequest::InsertChar(c));
+    }
+}
+
+#[cfg(feature = "tui")]
+/// Centered popup asking to confirm a dangerous command or running the whole queue
+fn render_confirmation(frame: &mut Frame, confirm: &Confirmation, theme: &Theme) {
+    let (title, mut lines) = match confirm {
+        Confirmation::Dangerous { command, reason, .. } => ("Run dangerous command?", vec![
+            Line::raw(format!("This command {}:", reason)),
+            Line::raw(""),
+            Line::styled(command.clone(), Style::default().add_modifier(Modifier::BOLD)),
+        ]),
+        Confirmation::SudoPassword { command } => ("sudo needs your password", vec![
+            Line::raw("sudo would ask for a password here, where it can't be typed:"),
+            Line::styled(command.clone(), Style::default().add_modifier(Modifier::BOLD)),
+            Line::raw(""),
+            Line::raw(format!("y runs `{}` on the terminal to type it first,", interactive::SUDO_VALIDATE)),
+            Line::raw("s runs the command with `sudo -n`, which fails without a cached password"),
+        ]),
+        Confirmation::AutoFix { command, failed, attempt } => ("Run the correction?", vec![
+            Line::raw(format!("{}, in place of:", attempt)),
+            Line::raw(failed.clone()),
+            Line::raw(""),
+            Line::styled(command.clone(), Style::default().add_modifier(Modifier::BOLD)),
+        ]),
+        Confirmation::RunAll { count } => ("Run all queued commands?", vec![
+            Line::raw(format!("Run the {} queued command(s) one after another.", count)),
+            Line::raw("Stops at the first command that fails."),
+        ]),
+        Confirmation::Quit { reasons } => ("Really quit?", reasons.iter()
+            .map(|reason| Line::raw(format!("- {}", reason)))
+            .collect()),
+        Confirmation::ReplaceQueue { queued, commands } => {
+            let mut lines = vec![Line::raw(format!("Replace the {} queued command(s) with the new answer?", queued)), Line::raw("")];
+            lines.extend(commands.iter().map(|command| Line::styled(command.clone(), Style::default().add_modifier(Modifier::BOLD))));
+            ("Replace the queue?", lines)
+        },
+        Confirmation::RestoreQueue { saved } => {
+            let mut lines = vec![Line::raw(format!("{} pending command(s) from the previous session, restore?", saved.commands.len()))];
+            if let Some(prompt) = &saved.prompt {
+                lines.push(Line::raw(format!("Asked for: {}", prompt.lines().next().unwrap_or_default())));
+            }
+            lines.push(Line::raw(format!("In: {}", display_path(&saved.directory, None))));
+            lines.push(Line::raw(""));
+            lines.extend(saved.commands.iter().map(|command| Line::styled(command.clone(), Style::default().add_modifier(Modifier::BOLD))));
+            ("Restore the queue?", lines)
+        },
+        Confirmation::ReplaceFavorite { name, command, replaced } => ("Replace the favorite?", vec![
+            Line::raw(format!("There is already a favorite named `{}`:", name)),
+            Line::styled(replaced.clone(), Style::default().add_modifier(Modifier::BOLD)),
+            Line::raw(""),
+            Line::raw("Replace it with:"),
+            Line::styled(command.clone(), Style::default().add_modifier(Modifier::BOLD)),
+        ]),
+    };
+    let (confirm_keys, confirm_label, cancel_label) = match confirm {
+        Confirmation::Quit { .. } => ("y/q", " quit  ", " cancel"),
+        Confirmation::ReplaceQueue { .. } => ("y", " replace  ", " cancel"),
+        Confirmation::RestoreQueue { .. } => ("y", " restore  ", " discard"),
+        Confirmation::ReplaceFavorite { .. } => ("y", " replace  ", " pick another name"),
+        Confirmation::SudoPassword { .. } => ("y/s", " run  ", " cancel"),
+        _ => ("y", " run  ", " cancel"),
+    };
+    lines.extend([
+        Line::raw(""),
+        Line::from(vec![
+            Span::styled(confirm_keys, Style::default().add_modifier(Modifier::BOLD)),
+            Span::raw(confirm_label),
+            Span::styled("n/Esc", Style::default().add_modifier(Modifier::BOLD)),
+            Span::raw(cancel_label),
+        ]),
+    ]);
+    let text = Text::from(lines);
+    let area = frame.area();
+    let width = (text.width() as u16 + 4).clamp(40, 80).min(area.width);
+    let height = (wrapped_height(&text, width.saturating_sub(2)) + 2).min(area.height);
+    let popup = centered_rect(width, height, area);
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(title)
+        .style(Style::default().fg(theme.error));
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(text).wrap(Wrap { trim: false }).block(block), popup);
+}
+
+#[cfg(feature = "tui")]
+/// Centered popup listing the models on the server.
+/// Returns the cursor in the model name typed if the list could not be fetched.
+fn render_model_picker(frame: &mut Frame, picker: &mut ModelPicker, active: &str, theme: &Theme) -> Option<Position> {
+    let rows = picker.listed().map_or(3, |models| models.len() as u16);
+    let popup = centered_rect(50, rows + 4, frame.area());
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("Switch model")
+        .style(Style::default().fg(theme.active));
+    let inner = block.inner(popup);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(block, popup);
+
+    let active_line = Line::from(vec![
+        Span::raw("Active: "),
+        Span::styled(active.to_string(), Style::default().add_modifier(Modifier::BOLD)),
+    ]);
+    frame.render_widget(Paragraph::new(active_line), Rect { height: 1, ..inner });
+    let body = Rect { y: inner.y + 2, height: inner.height.saturating_sub(2), ..inner };
+
+    if let Some(models) = picker.listed() {
+        let items: Vec<ListItem> = models.iter().map(|model| ListItem::new(model.clone())).collect();
+        let list = List::new(items)
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, body, &mut picker.list_state);
+        return None;
+    }
+
+    let status = match &picker.models {
+        None => {
+            frame.render_widget(Paragraph::new("Loading models…"), body);
+            return None;
+        },
+        Some(Ok(_)) => "No models found on the server".to_string(),
+        Some(Err(e)) => format!("Could not list models: {}", e),
+    };
+    let status_area = Rect { height: body.height.saturating_sub(1), ..body };
+    frame.render_widget(
+        Paragraph::new(status).style(Style::default().fg(theme.error)).wrap(Wrap { trim: true }),
+        status_area,
+    );
+    let input_row = Rect { y: body.bottom().saturating_sub(1), height: 1, ..body };
+    Some(render_line_input(frame, "Model name: ", &picker.input, input_row, Style::default()))
+}
+
+#[cfg(feature = "tui")]
+/// Centered popup asking for the name of a new favorite.
+/// Returns the cursor in the name.
+fn render_favorite_name(frame: &mut Frame, draft: &FavoriteDraft, theme: &Theme) -> Position {
+    let popup = centered_rect(60, 5, frame.area());
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("Save favorite")
+        .style(Style::default().fg(theme.active));
+    let inner = block.inner(popup);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(block, popup);
+
+    let command = Line::styled(draft.command.lines().next().unwrap_or_default().to_string(), Style::default().add_modifier(Modifier::BOLD));
+    frame.render_widget(Paragraph::new(command), Rect { height: 1, ..inner });
+    let input_row = Rect { y: inner.y + 2, height: 1, ..inner };
+    render_line_input(frame, "Name: ", &draft.name, input_row, Style::default())
+}
+
+#[cfg(feature = "tui")]
+/// Centered popup asking for one of the values shared by the queued commands.
+/// Returns the cursor in the value.
+fn render_params(frame: &mut Frame, draft: &ParamsDraft, theme: &Theme) -> Position {
+    let popup = centered_rect(60, 5, frame.area());
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!("Shared values {}/{}", draft.current + 1, draft.params.len()))
+        .style(Style::default().fg(theme.active));
+    let inner = block.inner(popup);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(block, popup);
+
+    let param = &draft.params[draft.current];
+    let found = Line::from(vec![
+        Span::styled(param.value.clone(), Style::default().add_modifier(Modifier::BOLD)),
+        Span::raw(format!(" is used by {} commands", param.uses)),
+    ]);
+    frame.render_widget(Paragraph::new(found), Rect { height: 1, ..inner });
+    let input_row = Rect { y: inner.y + 2, height: 1, ..inner };
+    render_line_input(frame, "Value: ", &draft.value, input_row, Style::default())
+}
+
+#[cfg(feature = "tui")]
+/// Draw `prompt` and then `input` in the one-line `row`, the input scrolled so its cursor
+/// shows. Widths are display columns, so wide characters like `漢` or `🦀` take two.
+/// Returns the cursor.
+fn render_line_input(frame: &mut Frame, prompt: &str, input: &Input, row: Rect, style: Style) -> Position {
+    // at least a column for the cursor
+    let prompt_width = (Line::raw(prompt).width() as u16).min(row.width.saturating_sub(1));
+    let area = Rect { x: row.x + prompt_width, width: row.width - prompt_width, ..row };
+    // whole characters are scrolled out, and the cursor can go after the last one
+    let scroll = input.visual_scroll(area.width.saturating_sub(1) as usize);
+    frame.render_widget(Paragraph::new(prompt).style(style), Rect { width: prompt_width, ..row });
+    frame.render_widget(Paragraph::new(input.value()).style(style).scroll((0, scroll as u16)), area);
+    let cursor = (input.visual_cursor().max(scroll) - scroll) as u16;
+    Position::new(area.x + cursor.min(area.width.saturating_sub(1)), area.y)
+}
+
+#[cfg(feature = "tui")]
+/// Centered popup listing the favorites, name and command
+fn render_favorites(frame: &mut Frame, list: &mut FavoritesList, theme: &Theme) {
+    let area = frame.area();
+    let rows = list.favorites.as_ref().map_or(2, |favorites| favorites.len().max(1) as u16);
+    let popup = centered_rect(area.width.saturating_sub(10).clamp(40, 100), rows + 2, area);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("Favorites")
+        .style(Style::default().fg(theme.active));
+    let inner = block.inner(popup);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(block, popup);
+
+    let favorites = match &list.favorites {
+        Ok(favorites) if favorites.is_empty() => {
+            frame.render_widget(Paragraph::new("No favorites yet, save the selected command with f"), inner);
+            return;
+        },
+        Ok(favorites) => favorites,
+        Err(e) => {
+            let error = Paragraph::new(format!("Could not read the favorites: {}", e))
+                .style(Style::default().fg(theme.error))
+                .wrap(Wrap { trim: true });
+            frame.render_widget(error, inner);
+            return;
+        },
+    };
+    // padded by display width, names in CJK take two columns a character
+    let name_width = |favorite: &Favorite| Line::raw(favorite.name.as_str()).width();
+    let width = favorites.iter().map(name_width).max().unwrap_or(0);
+    let items: Vec<ListItem> = favorites.iter()
+        .map(|favorite| ListItem::new(Line::from(vec![
+            Span::styled(
+                format!("{}{}  ", favorite.name, " ".repeat(width - name_width(favorite))),
+                Style::default().add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(favorite.command.lines().next().unwrap_or_default().to_string()),
+        ])))
+        .collect();
+    let favorites = List::new(items)
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .highlight_symbol("> ");
+    frame.render_stateful_widget(favorites, inner, &mut list.list_state);
+}
+
+#[cfg(feature = "tui")]
+/// Centered overlay listing every keybinding.
+/// Returns `scroll` clamped to the content height.
+fn render_help(frame: &mut Frame, bindings: &[Binding], scroll: u16, theme: &Theme) -> u16 {
+    let text = keymap::help_text(bindings);
+    let area = frame.area();
+    let popup = centered_rect(
+        (text.width() as u16 + 4).max(30),
+        (text.height() as u16 + 2).min(area.height.saturating_sub(2)),
+        area,
+    );
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("Keys")
+        .style(Style::default().fg(theme.active));
+    let inner = block.inner(popup);
+    let scroll = scroll.min((text.height() as u16).saturating_sub(inner.height));
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(text).scroll((scroll, 0)).block(block), popup);
+    scroll
+}
+
+#[cfg(feature = "tui")]
+/// `width` x `height` rectangle in the middle of `area`
+fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+#[cfg(feature = "tui")]
+/// Text of each line, without styles
+fn plain_lines(text: &Text) -> Vec<String> {
+    text.lines.iter().map(|line| line.spans.iter().map(|span| span.content.as_ref()).collect()).collect()
+}
+
+#[cfg(feature = "tui")]
+/// Number of rows `text` takes up when wrapped to `width` columns
+fn wrapped_height(text: &Text, width: u16) -> u16 {
+    let width = width.max(1) as usize;
+    text.lines
+        .iter()
+        .map(|line| line.width().max(1).div_ceil(width))
+        .sum::<usize>()
+        .min(u16::MAX as usize) as u16
+}
+
+#[cfg(all(test, feature = "tui"))]
+mod tests {
+    use super::*;
+    use crate::backend::ClientInit;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Serve a canned Ollama response whose `response` field is `model_output`.
+    /// Returns the generate endpoint url.
+    fn mock_ollama(model_output: &str) -> String {
+        mock_server(ollama_body(model_output), usize::MAX)
+    }
+
+    /// Ollama response whose `response` field is `model_output`
+    fn ollama_body(model_output: &str) -> String {
+        serde_json::json!({
+            "model": "llama3:latest",
+            "created_at": "2025-01-01T00:00:00Z",
+            "response": model_output,
+            "done": true,
+            "done_reason": "stop",
+            "context": [1, 2, 3],
+            "total_duration": 1000,
+            "load_duration": 10,
+            "prompt_eval_count": 5,
+            "prompt_eval_duration": 100,
+            "eval_count": 10,
+            "eval_duration": 800,
+        }).to_string()
+    }
+
+    /// Serve `body` as JSON to the first `requests` requests, whatever their path, the
+    /// `/api/ps` check before each prompt included. Returns the `/api/generate` url of the server.
+    fn mock_server(body: String, requests: usize) -> String {
+        mock_server_with(requests, move |_| body.clone())
+    }
+
+    /// Answer the prompts with `model_outputs` in turn and every other request, like the
+    /// `/api/ps` check, with no running model. Returns the `/api/generate` url of the server.
+    fn mock_script(model_outputs: &[&str]) -> String {
+        let mut bodies: VecDeque<String> = model_outputs.iter().map(|output| ollama_body(output)).collect();
+        mock_server_with(usize::MAX, move |request| match request.starts_with("POST /api/generate") {
+            true => bodies.pop_front().expect("more prompts than scripted answers"),
+            false => r#"{"models": []}"#.to_string(),
+        })
+    }
+
+    /// Serve the first `requests` requests, answering each with the JSON `respond` makes of it
+    fn mock_server_with(requests: usize, mut respond: impl FnMut(&str) -> String + Send + 'static) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/generate", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || for stream in listener.incoming().take(requests) {
+            let mut stream = stream.unwrap();
+            // read the whole request before answering
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some(head_end) = text.find("\r\n\r\n") {
+                    let content_length = text[..head_end]
+                        .lines()
+                        .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if request.len() >= head_end + 4 + content_length {
+                        break;
+                    }
+                }
+                if n == 0 {
+                    break;
+                }
+            }
+            let body = respond(&String::from_utf8_lossy(&request));
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            ).unwrap();
+        });
+
+        url
+    }
+
+    /// Send the current Asking AI content through `client` and wait for the answer
+    async fn ask(app: &mut App, client: &Bclient, prompt: &str) {
+        let (res_tx, mut res_rx) = mpsc::unbounded_channel();
+        app.input = PromptEditor::new(prompt.to_string());
+        app.send_request(client, &res_tx);
+        while app.pending.is_some() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            app.poll_responses(&mut res_rx);
+        }
+    }
+
+    fn app_with_queue(commands: &[&str]) -> App {
+        let mut app = App::new("llama3:latest");
+        app.recv_from(commands.iter().map(|c| c.to_string()).collect());
+        app
+    }
+
+    fn shell_input(app: &App) -> String {
+        app.shell.sh_input.borrow().value().to_string()
+    }
+
+    #[test]
+    fn queue_selection() {
+        let mut app = app_with_queue(&["ls", "pwd", "whoami"]);
+        assert_eq!(app.selected_command(), Some("ls"));
+        assert_eq!(shell_input(&app), "ls");
+
+        app.select_next();
+        app.select_next();
+        app.select_next();
+        assert_eq!(app.selected_command(), Some("whoami"));
+        assert_eq!(shell_input(&app), "whoami");
+
+        app.select_prev();
+        assert_eq!(shell_input(&app), "pwd");
+    }
+
+    #[test]
+    fn queue_reorder() {
+        let mut app = app_with_queue(&["ls", "pwd", "whoami"]);
+        app.move_selected_down();
+        assert_eq!(app.shell_commands, ["pwd", "ls", "whoami"]);
+        assert_eq!(app.selected_command(), Some("ls"));
+
+        app.move_selected_up();
+        app.move_selected_up();
+        assert_eq!(app.shell_commands, ["ls", "pwd", "whoami"]);
+    }
+
+    #[test]
+    fn queue_remove() {
+        let mut app = app_with_queue(&["ls", "pwd"]);
+        app.select_next();
+        assert_eq!(app.advance_queue().as_deref(), Some("pwd"));
+        assert_eq!(app.shell_commands, ["ls"]);
+        assert_eq!(shell_input(&app), "ls");
+
+        assert_eq!(app.advance_queue().as_deref(), Some("ls"));
+        assert!(app.shell_commands.is_empty());
+        assert_eq!(app.selected_command(), None);
+        assert_eq!(shell_input(&app), "");
+
+        // advancing an empty queue is a no-op
+        assert_eq!(app.advance_queue(), None);
+        assert_eq!(app.selected_command(), None);
+    }
+
+    #[test]
+    fn skip_and_discard() {
+        let mut app = app_with_queue(&["rm -rf build", "make", "make install"]);
+        app.skip_selected();
+        assert_eq!(app.shell_commands, ["make", "make install"]);
+        assert_eq!(app.shell.history[0].command, "rm -rf build");
+        assert_eq!(app.shell.history[0].stdout, "skipped");
+        assert_eq!(shell_input(&app), "make");
+
+        app.discard_queue();
+        assert!(app.shell_commands.is_empty());
+        assert_eq!(shell_input(&app), "");
+        assert_eq!(app.shell.history[1].stdout, "discarded 2 command(s)");
+    }
+
+    #[test]
+    fn conversation_log() {
+        let mut app = App::new("llama3:latest");
+        app.record_exchange("list files".to_string(), vec!["ls -la".to_string()]);
+        app.record_exchange("nothing".to_string(), Vec::new());
+
+        let text = app.conversation_text();
+        let rendered: Vec<String> = text.lines.iter().map(|line| line.to_string()).collect();
+        assert_eq!(rendered, ["> list files", "  1. ls -la", "", "> nothing", "  (no commands)"]);
+
+        app.scroll_response(3);
+        app.scroll_response(-5);
+        assert_eq!(app.response_scroll, 0);
+    }
+
+    #[test]
+    fn wrapped_rows() {
+        let text = Text::from(vec![Line::raw("abcdefghij"), Line::default(), Line::raw("abc")]);
+        assert_eq!(wrapped_height(&text, 4), 3 + 1 + 1);
+        assert_eq!(wrapped_height(&text, 20), 3);
+    }
+
+    #[tokio::test]
+    async fn stale_results_are_dropped() {
+        let mut app = App::new("llama3:latest");
+        let (res_tx, mut res_rx) = mpsc::unbounded_channel();
+        app.pending = Some(PendingRequest {
+            id: 2,
+            started: Instant::now(),
+            handle: tokio::spawn(std::future::pending()),
+            regenerate: false,
+            fix: false,
+            loading: Default::default(),
+        });
+
+        // result of a request that was cancelled before
+        res_tx.send(RequestResult { id: 1, prompt: "old".to_string(), res: Ok(generation(&["rm -r old"])) }).unwrap();
+        app.poll_responses(&mut res_rx);
+        assert!(app.pending.is_some());
+        assert!(app.shell_commands.is_empty());
+
+        res_tx.send(RequestResult { id: 2, prompt: "list".to_string(), res: Ok(generation(&["ls"])) }).unwrap();
+        app.poll_responses(&mut res_rx);
+        assert!(app.pending.is_none());
+        assert_eq!(app.shell_commands, ["ls"]);
+        assert_eq!(app.conversation.len(), 1);
+        assert!(app.timing.is_some());
+    }
+
+    #[tokio::test]
+    async fn regenerate_asks_before_replacing_the_queue() {
+        let (res_tx, mut res_rx) = mpsc::unbounded_channel();
+        let (commands_tx, _commands_rx) = mpsc::unbounded_channel();
+        let regenerating = |id| PendingRequest {
+            id,
+            started: Instant::now(),
+            handle: tokio::spawn(std::future::pending()),
+            regenerate: true,
+            fix: false,
+            loading: Default::default(),
+        };
+
+        let mut app = app_with_queue(&["du -sh *"]);
+        app.input = PromptEditor::new("a draft".to_string());
+        app.pending = Some(regenerating(1));
+        res_tx.send(RequestResult { id: 1, prompt: "disk usage".to_string(), res: Ok(generation(&["df -h"])) }).unwrap();
+        app.poll_responses(&mut res_rx);
+        assert!(matches!(app.confirm, Some(Confirmation::ReplaceQueue { queued: 1, .. })));
+        assert_eq!(app.shell_commands, ["du -sh *"]);
+        app.accept_confirmation(&commands_tx);
+        assert_eq!(app.shell_commands, ["df -h"]);
+        assert_eq!(shell_input(&app), "df -h");
+        // the prompt being typed is kept
+        assert_eq!(app.input.value(), "a draft");
+
+        app.pending = Some(regenerating(2));
+        res_tx.send(RequestResult { id: 2, prompt: "disk usage".to_string(), res: Ok(generation(&["ncdu"])) }).unwrap();
+        app.poll_responses(&mut res_rx);
+        app.reject_confirmation();
+        assert_eq!(app.shell_commands, ["df -h"]);
+        assert_eq!(app.notice.as_deref(), Some("Kept the queued commands"));
+        // both answers are in the conversation either way
+        assert_eq!(app.conversation.len(), 2);
+
+        // an empty queue is simply filled
+        app.discard_queue();
+        app.pending = Some(regenerating(3));
+        res_tx.send(RequestResult { id: 3, prompt: "disk usage".to_string(), res: Ok(generation(&["ncdu"])) }).unwrap();
+        app.poll_responses(&mut res_rx);
+        assert!(app.confirm.is_none());
+        assert_eq!(app.shell_commands, ["ncdu"]);
+    }
+
+    #[tokio::test]
+    async fn reask_last_prompt() {
+        let client = Bclient::new(&mock_ollama(r#"{"commands": ["ls"]}"#));
+        let (res_tx, _res_rx) = mpsc::unbounded_channel();
+        let mut app = App::new("llama3:latest");
+        app.edit_last_prompt();
+        assert_eq!(app.notice.as_deref(), Some("No prompt sent yet"));
+
+        ask(&mut app, &client, "list files").await;
+        assert_eq!(app.input.value(), "");
+        app.edit_last_prompt();
+        assert_eq!((app.input.value(), app.input_mode), ("list files".to_string(), EditMode::Input));
+
+        app.regenerate(&client, &res_tx);
+        assert!(app.pending.as_ref().is_some_and(|pending| pending.regenerate));
+        let seed = app.messages.seed().unwrap();
+        assert!(serde_json::to_string(&app.messages).unwrap().contains(&format!(r#""options":{{"seed":{}}}"#, seed)));
+        // the box still holds the prompt being edited
+        assert_eq!(app.input.value(), "list files");
+        app.cancel_request();
+
+        // a prompt sent normally lets the server pick the seed again
+        ask(&mut app, &client, "list files").await;
+        assert_eq!(app.messages.seed(), None);
+        assert!(!serde_json::to_string(&app.messages).unwrap().contains("options"));
+    }
+
+    #[tokio::test]
+    async fn compare_models() {
+        // the mock answers only one of the two requests, the other one fails
+        let client = Bclient::new(&mock_server(ollama_body(r#"{"commands": ["ls -a"]}"#), 1));
+        let (compare_tx, mut compare_rx) = mpsc::unbounded_channel();
+        let mut app = App::new("llama3:latest");
+        app.last_prompt = Some("list files".to_string());
+        app.compare(&client, &compare_tx);
+        assert!(app.error.take().is_some_and(|e| e.contains("compare_model")));
+
+        let mut app = App::new("llama3:latest").with_compare_model(Some("mistral".to_string()));
+        app.compare(&client, &compare_tx);
+        app.last_prompt = Some("list files".to_string());
+        assert_eq!(app.notice.take().as_deref(), Some("No prompt to compare yet"));
+        app.compare(&client, &compare_tx);
+        while app.comparing.is_some() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            app.poll_comparisons(&mut compare_rx);
+        }
+        assert_eq!(app.input_mode, EditMode::Compare);
+        let comparison = app.comparison.as_ref().unwrap();
+        let models: Vec<_> = comparison.answers.iter().map(|answer| answer.model.as_str()).collect();
+        assert_eq!(models, ["llama3:latest", "mistral"]);
+        let answered = comparison.answers.iter().position(|answer| answer.commands.is_ok()).unwrap();
+        assert!(comparison.answers[1 - answered].commands.is_err());
+
+        // the failed answer cannot be queued
+        app.select_answer(if answered == 0 { 1 } else { -1 });
+        app.queue_answer();
+        assert!(app.error.take().is_some_and(|e| e.contains("no commands to queue")));
+        app.select_answer(if answered == 0 { -5 } else { 5 });
+        app.queue_answer();
+        assert_eq!(app.shell_commands, ["ls -a"]);
+        assert_eq!((app.input_mode, app.comparison.is_none()), (EditMode::Normal, true));
+        assert_eq!(app.conversation.len(), 1);
+
+        app.comparison = Some(Comparison { prompt: "list files".to_string(), answers: Vec::new(), selected: 0 });
+        app.input_mode = EditMode::Compare;
+        app.close_comparison();
+        assert_eq!((app.input_mode, app.comparison.is_none()), (EditMode::Normal, true));
+        assert_eq!(app.shell_commands, ["ls -a"]);
+    }
+
+    fn generation(commands: &[&str]) -> Generation {
+        let stats = GenerationStats {
+            eval_count: 84,
+            eval_duration: Duration::from_secs(2),
+            total_duration: Duration::from_secs(3),
+            load_duration: Duration::from_millis(10),
+        };
+        Generation { commands: commands.iter().map(|c| c.to_string()).collect(), stats, context: Vec::new(), dropped: 0, cached: false }
+    }
+
+    #[test]
+    fn timing_format() {
+        assert_eq!(format_elapsed(Duration::from_millis(420)), "0.4s");
+        assert_eq!(format_elapsed(Duration::from_millis(59_940)), "59.9s");
+        assert_eq!(format_elapsed(Duration::from_secs(125)), "2m05s");
+
+        let timing = Timing { elapsed: Duration::from_millis(3_240), stats: generation(&[]).stats };
+        assert_eq!(timing.status(), "answered in 3.2s, 42.0 tok/s");
+        let timing = Timing { stats: GenerationStats::default(), ..timing };
+        assert_eq!(timing.status(), "answered in 3.2s");
+        let stats = GenerationStats { load_duration: Duration::from_millis(31_200), ..generation(&[]).stats };
+        let timing = Timing { elapsed: Duration::from_secs(35), stats };
+        assert_eq!(timing.status(), "answered in 35.0s, 31.2s of it loading the model, 42.0 tok/s");
+    }
+
+    #[tokio::test]
+    async fn loading_model_status() {
+        let mut app = App::new("llama3:latest");
+        let loading = Arc::new(AtomicBool::new(false));
+        let handle = tokio::spawn(std::future::pending::<()>());
+        app.pending = Some(PendingRequest { id: 1, started: Instant::now(), handle, regenerate: false, fix: false, loading: loading.clone() });
+        let title = |app: &mut App| render(app, 100, 12).into_iter().find(|row| row.contains("Asking AI")).unwrap();
+        assert!(title(&mut app).contains("s (Esc to cancel)"));
+        loading.store(true, Ordering::Relaxed);
+        let status = title(&mut app);
+        assert!(status.contains("s, loading the model into memory, this can take a while… (Esc to cancel)"), "{}", status);
+    }
+
+    #[tokio::test]
+    async fn prompt_token_budget() {
+        let client = Bclient::new(&mock_ollama(r#"{"commands": ["pwd"]}"#));
+        let mut app = App::new("llama3:latest");
+        app.messages.set_context(vec![1; 5000]);
+        ask(&mut app, &client, "where am i").await;
+        assert_eq!(app.notice.as_deref(), Some("Left out the conversation so far to keep the prompt within max_prompt_tokens (4096)"));
+        assert_eq!(app.shell_commands, ["pwd"]);
+
+        app.input_mode = EditMode::Input;
+        app.input = PromptEditor::new("list files".to_string());
+        let title = |app: &mut App| render(app, 60, 12).into_iter().find(|row| row.contains("Asking AI")).unwrap();
+        assert!(title(&mut app).contains("/4096 tokens"));
+        let mut app = app.with_max_prompt_tokens(10);
+        assert!(title(&mut app).contains("/10 tokens"));
+    }
+
+    #[tokio::test]
+    async fn cancel_pending_request() {
+        let mut app = App::new("llama3:latest");
+        let (_res_tx, mut res_rx) = mpsc::unbounded_channel::<RequestResult>();
+        let handle = tokio::spawn(std::future::pending::<()>());
+        app.pending = Some(PendingRequest { id: 1, started: Instant::now(), handle, regenerate: false, fix: false, loading: Default::default() });
+
+        app.cancel_request();
+        assert!(app.pending.is_none());
+        app.poll_responses(&mut res_rx);
+        assert!(app.pending.is_none());
+    }
+
+    #[tokio::test]
+    async fn mock_backend_commands() {
+        let client = Bclient::new(&mock_ollama(r#"{"commands": ["ls", "pwd"]}"#));
+        let mut app = App::new("llama3:latest");
+
+        ask(&mut app, &client, "where am i").await;
+        assert_eq!(app.error, None);
+        assert_eq!(app.shell_commands, ["ls", "pwd"]);
+        assert_eq!(shell_input(&app), "ls");
+        assert_eq!(app.input.value(), "");
+    }
+
+    #[tokio::test]
+    async fn mock_backend_empty_commands() {
+        let client = Bclient::new(&mock_ollama(r#"{"commands": []}"#));
+        let mut app = App::new("llama3:latest");
+
+        ask(&mut app, &client, "do nothing").await;
+        assert_eq!(app.error.as_deref(), Some("Model returned no commands"));
+        assert!(app.shell_commands.is_empty());
+        assert_eq!(shell_input(&app), "");
+    }
+
+    #[tokio::test]
+    async fn unreachable_backend() {
+        // bind and drop to get a port nobody listens on
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let client = Bclient::new(&format!("http://{}/api/generate", addr));
+        let mut app = App::new("llama3:latest");
+
+        ask(&mut app, &client, "list files").await;
+        assert!(app.error.as_deref().unwrap().starts_with("Ollama request error"));
+        assert_eq!(app.input.value(), "list files");
+    }
+
+    #[tokio::test]
+    async fn prompt_kept_until_answered() {
+        use ratatui::crossterm::event::{KeyCode, KeyModifiers};
+        let (res_tx, mut res_rx) = mpsc::unbounded_channel();
+        let mut app = App::new("llama3:latest");
+        app.input_mode = EditMode::Input;
+        app.input = PromptEditor::new("list files".to_string());
+        app.pending = Some(PendingRequest { id: 1, started: Instant::now(), handle: tokio::spawn(std::future::pending()), regenerate: false, fix: false, loading: Default::default() });
+        // read-only while it is answered
+        app.type_key(Event::Key(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE)));
+        app.paste("and more");
+        assert_eq!(app.input.value(), "list files");
+        assert!(render(&mut app, 80, 12)[0].starts_with("Waiting for the answer, press Esc to cancel"));
+
+        // no usable commands keeps it for another try
+        res_tx.send(RequestResult { id: 1, prompt: "list files".to_string(), res: Ok(generation(&[])) }).unwrap();
+        app.poll_responses(&mut res_rx);
+        assert_eq!((app.input.value(), app.input_mode), ("list files".to_string(), EditMode::Input));
+        app.type_key(Event::Key(KeyEvent::new(KeyCode::Char('!'), KeyModifiers::NONE)));
+        assert_eq!(app.input.value(), "list files!");
+
+        // cleared once the commands are queued
+        app.pending = Some(PendingRequest { id: 2, started: Instant::now(), handle: tokio::spawn(std::future::pending()), regenerate: false, fix: false, loading: Default::default() });
+        res_tx.send(RequestResult { id: 2, prompt: "list files!".to_string(), res: Ok(generation(&["ls"])) }).unwrap();
+        app.poll_responses(&mut res_rx);
+        assert_eq!((app.input.value(), app.input_mode), (String::new(), EditMode::Normal));
+        assert_eq!(app.shell_commands, ["ls"]);
+    }
+
+    #[test]
+    fn loaded_commands_join_the_queue() {
+        let mut app = app_with_queue(&["ls"]);
+        app.queue_loaded(commands_from_text("df -h\r\n\r\n  rm -rf build  \r\n"), "the clipboard");
+        assert_eq!(app.shell_commands, ["ls", "df -h", "rm -rf build"]);
+        assert_eq!((app.selected_command(), app.input_mode), (Some("df -h"), EditMode::Shell));
+        assert_eq!(app.notice.as_deref(), Some("Queued 2 command(s) from the clipboard"));
+
+        // asked about like a suggested command
+        app.select_next();
+        app.submit_command(shell_input(&app));
+        assert!(matches!(app.confirm, Some(Confirmation::Dangerous { .. })));
+
+        let mut app = app_with_queue(&["ls"]);
+        app.queue_loaded(commands_from_text(" \n\n"), "the clipboard");
+        assert_eq!((app.shell_commands.len(), app.notice.as_deref()), (1, Some("No commands in the clipboard")));
+    }
+
+    #[tokio::test]
+    async fn busy_and_cached_answers_are_notices() {
+        let (res_tx, mut res_rx) = mpsc::unbounded_channel();
+        let mut app = App::new("llama3:latest");
+        let pending = |id| PendingRequest { id, started: Instant::now(), handle: tokio::spawn(std::future::pending()), regenerate: false, fix: false, loading: Default::default() };
+        app.pending = Some(pending(1));
+        res_tx.send(RequestResult { id: 1, prompt: "list files".to_string(), res: Err(BackendError::Busy) }).unwrap();
+        app.poll_responses(&mut res_rx);
+        assert_eq!((app.error.as_deref(), app.notice.as_deref()), (None, Some("Still waiting for the previous answer, try again in a moment")));
+        assert!(app.session.events.is_empty());
+
+        app.pending = Some(pending(2));
+        let cached = Generation { cached: true, ..generation(&["ls"]) };
+        res_tx.send(RequestResult { id: 2, prompt: "list files".to_string(), res: Ok(cached) }).unwrap();
+        app.poll_responses(&mut res_rx);
+        assert!(app.notice.as_deref().unwrap().starts_with("Same prompt as a moment ago"));
+        assert_eq!(app.shell_commands, ["ls"]);
+    }
+
+    #[tokio::test]
+    async fn failed_request_keeps_prompt() {
+        use ratatui::crossterm::event::{KeyCode, KeyModifiers};
+        // an answer that is not the JSON asked for
+        let client = Bclient::new(&mock_ollama("sure, run ls"));
+        let mut app = App::new("llama3:latest");
+        app.input_mode = EditMode::Input;
+        ask(&mut app, &client, "list files").await;
+        assert!(app.error.as_deref().unwrap().starts_with("Ollama response error"), "{:?}", app.error);
+        assert_eq!((app.input.value(), app.input_mode), ("list files".to_string(), EditMode::Input));
+        assert!(app.shell_commands.is_empty());
+        app.type_key(Event::Key(KeyEvent::new(KeyCode::Char('?'), KeyModifiers::NONE)));
+        assert_eq!(app.input.value(), "list files?");
+
+        // so does cancelling
+        let client = Bclient::new(&mock_ollama(r#"{"commands": ["ls"]}"#));
+        let (res_tx, _res_rx) = mpsc::unbounded_channel();
+        app.send_request(&client, &res_tx);
+        assert!(app.prompt_in_flight());
+        app.cancel_request();
+        assert!(!app.prompt_in_flight());
+        assert_eq!((app.input.value(), app.input_mode), ("list files?".to_string(), EditMode::Input));
+    }
+
+    #[tokio::test]
+    async fn prompt_templates() {
+        let dir = std::env::temp_dir().join(format!("aurish-tui-templates-{}", std::process::id()));
+        let path = dir.join("templates.toml");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, "install = \"Install {1} with apt\"\nlarge = \"Find files under {1} larger than {2}\"\nlist = \"List {1}\"\n").unwrap();
+        let client = Bclient::new(&mock_ollama(r#"{"commands": ["sudo apt install ripgrep"]}"#));
+        let mut app = App::new("llama3:latest").with_templates(path.clone());
+        app.input_mode = EditMode::Input;
+        ask(&mut app, &client, "/install ripgrep").await;
+        assert_eq!(app.last_prompt.as_deref(), Some("Install ripgrep with apt"));
+        assert_eq!(app.shell_commands, ["sudo apt install ripgrep"]);
+
+        // mistakes keep the prompt with a banner naming the templates
+        app.input_mode = EditMode::Input;
+        let (res_tx, _res_rx) = mpsc::unbounded_channel();
+        app.input = PromptEditor::new("/instal ripgrep".to_string());
+        assert!(!app.send_request(&client, &res_tx));
+        assert_eq!(app.error.as_deref(), Some("Prompt not sent: there is no template named `instal`, the templates are: install, large, list. \
+            Start it with // to send a prompt beginning with /"));
+        app.input = PromptEditor::new("/large /var".to_string());
+        assert!(!app.send_request(&client, &res_tx));
+        assert_eq!(app.error.as_deref(), Some("Prompt not sent: template `large` needs 2 arguments, got 1: Find files under {1} larger than {2}"));
+        assert_eq!(app.input.value(), "/large /var");
+
+        // Tab completes as far as the names agree
+        app.error = None;
+        app.input = PromptEditor::new("/i".to_string());
+        app.complete_template();
+        assert_eq!(app.input.value(), "/install ");
+        app.input = PromptEditor::new("/l".to_string());
+        app.complete_template();
+        assert_eq!(app.input.value(), "/l");
+        assert_eq!(app.notice.as_deref(), Some("Templates: large, list"));
+        app.input = PromptEditor::new("/la".to_string());
+        app.input.insert_str(" /tmp");
+        (0..5).for_each(|_| app.input.move_left());
+        app.complete_template();
+        assert_eq!((app.input.value(), app.input.cursor()), ("/large /tmp".to_string(), (0, 6)));
+        app.input = PromptEditor::new("/x".to_string());
+        app.complete_template();
+        assert_eq!(app.notice.as_deref(), Some("No template starts with `x`, the templates are: install, large, list"));
+        // not a template name
+        app.input = PromptEditor::new("install /i".to_string());
+        app.complete_template();
+        assert_eq!(app.input.value(), "install /i");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_model_points_to_switcher() {
+        let missing = BackendError::Server { status: reqwest::StatusCode::NOT_FOUND, message: "model 'llama9' not found".to_string() };
+        let app = App::new("llama9");
+        assert_eq!(app.request_error(&missing), "Ollama answered 404 Not Found: model 'llama9' not found, press m to pick an installed model");
+        let overrides = BTreeMap::from([("switch_model".to_string(), keymap::KeySpecs::One("ctrl+o".to_string()))]);
+        let app = App::new("llama9").with_keymap(keymap::with_overrides(&keymap::default_bindings(), &overrides).unwrap());
+        assert!(app.request_error(&missing).ends_with("press Ctrl-O to pick an installed model"));
+        let failed = BackendError::Server { status: reqwest::StatusCode::INTERNAL_SERVER_ERROR, message: "out of memory".to_string() };
+        assert_eq!(app.request_error(&failed), "Ollama answered 500 Internal Server Error: out of memory");
+    }
+
+    #[test]
+    fn panic_hook_restores() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let restored = Arc::new(AtomicBool::new(false));
+        install_panic_hook({
+            let restored = Arc::clone(&restored);
+            move || restored.store(true, Ordering::SeqCst)
+        });
+
+        let result = std::panic::catch_unwind(|| panic!("controlled panic"));
+        // put the default hook back for the other tests
+        let _ = std::panic::take_hook();
+
+        assert!(result.is_err());
+        assert!(restored.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn both_streams_on_success() {
+        let mut app = App::new("llama3:latest");
+        app.execute_command("echo built; echo 'warning: unused variable' 1>&2".to_string(), None);
+        let entry = &app.shell.history[0];
+        assert_eq!((entry.exit_code, entry.stdout.as_str(), entry.stderr.as_str()), (Some(0), "built", "warning: unused variable"));
+
+        let (text, _) = app.history_lines();
+        assert_eq!(text.lines[1].to_string(), "built");
+        assert_eq!(text.lines[1].style.fg, None);
+        assert_eq!(text.lines[2].to_string(), "warning: unused variable");
+        assert_eq!(text.lines[2].style.fg, Some(app.theme.stderr));
+        assert!(text.lines[2].style.add_modifier.contains(Modifier::DIM));
+    }
+
+    #[test]
+    fn output_history() {
+        let mut app = App::new("llama3:latest");
+        app.execute_command("echo out; echo err 1>&2; exit 3".to_string(), None);
+        app.execute_command("true".to_string(), None);
+
+        let entry = &app.shell.history[0];
+        assert_eq!(entry.exit_code, Some(3));
+        assert_eq!(entry.stdout, "out");
+        assert_eq!(entry.stderr, "err");
+
+        let (text, starts) = app.history_lines();
+        let rendered: Vec<String> = text.lines.iter().map(|line| line.to_string()).collect();
+        assert!(rendered[0].starts_with("$ echo out; echo err 1>&2; exit 3 [exit 3, "));
+        assert_eq!(rendered[1], "out");
+        assert_eq!(rendered[2], "err");
+        assert_eq!(text.lines[2].style.fg, Some(Color::Red));
+        assert!(rendered[3].starts_with("$ true [exit 0, "));
+        assert_eq!(starts, [0, 3]);
+    }
+
+    /// Render `app` into a `width`x`height` test terminal and return the cursor cell
+    fn render_cursor(app: &mut App, width: u16, height: u16) -> (u16, u16) {
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(width, height)).unwrap();
+        terminal.draw(|f| app.ui(f)).unwrap();
+        let position = terminal.get_cursor_position().unwrap();
+        (position.x, position.y)
+    }
+
+    #[test]
+    fn shell_tab_completion() {
+        // tests run in the crate root, where Cargo.lock and Cargo.toml are
+        let mut app = app_with_queue(&["cat Cargo. | head"]);
+        app.input_mode = EditMode::Shell;
+        app.shell.sh_input.borrow_mut().handle(tui_input::InputRequest::GoToStart);
+        for _ in 0..10 {
+            app.shell.sh_input.borrow_mut().handle(tui_input::InputRequest::GoToNextChar);
+        }
+        app.complete_path();
+        assert_eq!(shell_input(&app), "cat Cargo.lock | head");
+        app.complete_path();
+        assert_eq!(shell_input(&app), "cat Cargo.toml | head");
+
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(80, 30)).unwrap();
+        terminal.draw(|f| app.ui(f)).unwrap();
+        let title: String = (0..80).map(|x| terminal.backend().buffer()[(x, 4)].symbol().to_string()).collect();
+        assert!(title.contains("Shell [2/2]"), "{}", title);
+    }
+
+    #[test]
+    fn shell_cursor_after_prefix() {
+        let mut app = app_with_queue(&["ls"]);
+        app.shell.curr_path = PathBuf::from("/srv/www");
+        app.input_mode = EditMode::Shell;
+
+        // Shell box starts on row 4, its text on row 5 after the border;
+        // "/srv/www > " is 11 columns, the cursor sits after "ls"
+        assert_eq!(render_cursor(&mut app, 80, 30), (1 + 11 + 2, 5));
+
+        app.shell.sh_input.borrow_mut().handle(tui_input::InputRequest::GoToStart);
+        assert_eq!(render_cursor(&mut app, 80, 30), (1 + 11, 5));
+    }
+
+    #[test]
+    fn wide_characters_in_input_boxes() {
+        // `漢字` and `🦀` take two columns each
+        let mut app = App::new("llama3:latest");
+        app.input_mode = EditMode::Input;
+        app.input = PromptEditor::new("ls 漢字 🦀".to_string());
+        // the Asking AI box starts on row 1 under the status line, its text on row 2
+        assert_eq!(render_cursor(&mut app, 80, 30), (1 + 10, 2));
+        assert!(render(&mut app, 80, 30)[2].starts_with("│ls 漢字 🦀"), "{}", render(&mut app, 80, 30)[2]);
+        app.input.move_left();
+        app.input.move_left();
+        assert_eq!(render_cursor(&mut app, 80, 30), (1 + 7, 2));
+        // wider than the box, wrapped at 5 columns without splitting a character
+        app.input.move_end();
+        assert_eq!(render_cursor(&mut app, 8, 30), (1 + 5, 3));
+        let rows = render(&mut app, 8, 30);
+        assert_eq!((rows[2].as_str(), rows[3].as_str()), ("│ls 漢 │", "│字 🦀 │"));
+
+        let mut app = app_with_queue(&["echo 漢字🦀"]);
+        app.shell.curr_path = PathBuf::from("/srv");
+        app.input_mode = EditMode::Shell;
+        // "/srv > " is 7 columns, the command 11
+        assert_eq!(render_cursor(&mut app, 80, 30), (1 + 7 + 11, 5));
+        app.shell.sh_input.borrow_mut().handle(tui_input::InputRequest::GoToPrevChar);
+        assert_eq!(render_cursor(&mut app, 80, 30), (1 + 7 + 9, 5));
+        // scrolled by whole characters once wider than the box, the cursor on the last column
+        app.shell.sh_input.borrow_mut().handle(tui_input::InputRequest::GoToEnd);
+        assert_eq!(render_cursor(&mut app, 20, 30), (1 + 7 + 10, 5));
+        assert_eq!(render(&mut app, 20, 30)[5], "│/srv > cho 漢字🦀 │");
+
+        // too narrow for anything, but drawn
+        for width in 1..8 {
+            render(&mut app, width, 30);
+            app.input_mode = EditMode::Input;
+            render(&mut app, width, 30);
+            app.input_mode = EditMode::Shell;
+        }
+    }
+
+    #[test]
+    fn prompt_follows_cd() {
+        let mut app = App::new("llama3:latest");
+        let started_in = app.shell.get_path();
+        app.execute_command("cd /".to_string(), None);
+        assert_eq!(app.window_title(), "aurish — /");
+
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(40, 30)).unwrap();
+        terminal.draw(|f| app.ui(f)).unwrap();
+        let prompt_line: String = (0..40).map(|x| terminal.backend().buffer()[(x, 5)].symbol().to_string()).collect();
+        assert_eq!(prompt_line, format!("│/ > {}│", " ".repeat(34)));
+
+        app.reset_directory();
+        assert_eq!(app.shell.get_path(), started_in);
+        app.execute_command("true".to_string(), None);
+        assert_eq!(app.window_title(), format!("aurish — {}", started_in));
+    }
+
+    #[test]
+    fn shell_cursor_wide_chars() {
+        let mut app = app_with_queue(&["cat 文件.txt"]);
+        app.shell.curr_path = PathBuf::from("/srv/项目");
+        app.input_mode = EditMode::Shell;
+
+        // "/srv/项目 > " is 5 + 4 + 3 columns, "cat 文件.txt" is 12 columns
+        assert_eq!(render_cursor(&mut app, 80, 30), (1 + 12 + 12, 5));
+    }
+
+    #[test]
+    fn shell_cursor_long_command() {
+        let long_command = format!("echo {}", "x".repeat(100));
+        let mut app = app_with_queue(&[long_command.as_str()]);
+        app.shell.curr_path = PathBuf::from("/srv/www");
+        app.input_mode = EditMode::Shell;
+
+        // scrolled so the cursor stays on the last interior column
+        assert_eq!(render_cursor(&mut app, 40, 30), (38, 5));
+    }
+
+    #[test]
+    fn input_cursor() {
+        let mut app = App::new("llama3:latest");
+        app.input = PromptEditor::new("list 文件".to_string());
+        app.input_mode = EditMode::Input;
+
+        assert_eq!(render_cursor(&mut app, 80, 30), (1 + 9, 2));
+    }
+
+    #[test]
+    fn paste_lines_trims_line_endings() {
+        assert_eq!(paste_lines("ls -la\r\n"), ["ls -la"]);
+        assert_eq!(paste_lines("cd /tmp\r\nls\n\n"), ["cd /tmp", "ls"]);
+        assert_eq!(paste_lines("a\n\nb"), ["a", "", "b"]);
+        assert!(paste_lines("\n\r\n").is_empty());
+    }
+
+    #[tokio::test]
+    async fn prompts_tell_the_directory() {
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let received = Arc::clone(&requests);
+        let client = Bclient::new(&mock_server_with(usize::MAX, move |request| {
+            received.lock().unwrap().push(request.to_string());
+            ollama_body(r#"{"commands": ["ls"]}"#)
+        }));
+        let dir = std::env::temp_dir();
+        for (level, told) in [(ContextLevel::Directory, true), (ContextLevel::None, false)] {
+            let mut app = App::new("llama3:latest").with_context_level(level);
+            app.execute_command(format!("cd {}", dir.display()), None);
+            ask(&mut app, &client, "list files").await;
+
+            let request = requests.lock().unwrap().iter().rev().find(|request| request.starts_with("POST /api/generate")).cloned().unwrap();
+            let body: serde_json::Value = serde_json::from_str(&request[request.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+            let prompt = body["prompt"].as_str().unwrap();
+            assert_eq!(prompt.contains(&format!("Current directory: {}\n", dir.display())), told, "{:?}", level);
+            assert!(prompt.ends_with("list files"));
+        }
+    }
+
+    #[test]
+    fn status_line_language() {
+        let status = |app: &App| plain_lines(&app.status_text()).concat();
+        let mut app = App::new("llama3:latest").with_language(Language::Spanish);
+        assert!(status(&app).starts_with("Pulsa q para salir, a para preguntar a la IA, "), "{}", status(&app));
+        assert!(serde_json::to_value(&app.messages).unwrap()["system"].as_str().unwrap().starts_with("Eres experto en "));
+        app.input_mode = EditMode::Shell;
+        assert!(status(&app).starts_with("Pulsa Esc deja de usar la Shell, Enter para ejecutar el comando, "));
+        // the keys are the same in every language
+        app.language = Language::Chinese;
+        assert!(status(&app).starts_with("按 Esc 停止使用 Shell，Enter 执行命令，Ctrl-P"));
+    }
+
+    #[tokio::test]
+    async fn project_context_follows_the_directory() {
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let received = Arc::clone(&requests);
+        let client = Bclient::new(&mock_server_with(usize::MAX, move |request| {
+            received.lock().unwrap().push(request.to_string());
+            ollama_body(r#"{"commands": ["ls"]}"#)
+        }));
+        let root = std::env::temp_dir().join(format!("aurish-project-app-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("app/src")).unwrap();
+        fs::create_dir_all(root.join("other")).unwrap();
+        fs::write(root.join("app/AURISH.md"), "Build with `just build`.").unwrap();
+        let status = |app: &App| plain_lines(&app.status_text()).concat();
+        let system = || {
+            let request = requests.lock().unwrap().iter().rev().find(|request| request.starts_with("POST /api/generate")).cloned().unwrap();
+            let body: serde_json::Value = serde_json::from_str(&request[request.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+            body["system"].as_str().unwrap().to_string()
+        };
+
+        let mut app = App::new("llama3:latest");
+        app.execute_command(format!("cd {}", root.join("app/src").display()), None);
+        assert!(status(&app).contains(&format!("[context: {}]", display_path(&root.join("app/AURISH.md"), None))));
+        ask(&mut app, &client, "build it").await;
+        assert!(system().starts_with(&format!("Notes on the project the commands run in, from {}:\nBuild with `just build`.\n\n", root.join("app/AURISH.md").display())));
+
+        app.execute_command(format!("cd {}", root.join("other").display()), None);
+        assert!(!status(&app).contains(&format!("[context: {}", display_path(&root, None))));
+        ask(&mut app, &client, "build it").await;
+        assert!(!system().contains("just build"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn startup_check_in_background() {
+        use ratatui::crossterm::event::{KeyCode, KeyModifiers};
+        let status = |app: &App| plain_lines(&app.status_text()).concat();
+        let (health_tx, mut health_rx) = mpsc::unbounded_channel();
+        let mut app = App::new("llama3:latest");
+        assert!(status(&app).contains("[connecting…]"));
+
+        // nothing waits for the server, which does not answer on port 9
+        let started = Instant::now();
+        app.check_health(&Bclient::new("http://127.0.0.1:9/api/generate"), &health_tx);
+        let key = app.resolve_key(KeyEvent::new(KeyCode::Char('a'), KeyModifiers::NONE));
+        app.type_key(Event::Key(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE)));
+        assert!(started.elapsed() < Duration::from_millis(50));
+        assert_eq!(key, Lookup::Action(Action::AskAi));
+
+        while app.health.is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            app.poll_health(&mut health_rx);
+        }
+        assert!(status(&app).contains("[Ollama unreachable]"));
+        // until it answers after all
+        ask(&mut app, &Bclient::new(&mock_script(&[r#"{"commands": ["ls"]}"#])), "list files").await;
+        assert!(!status(&app).contains("[Ollama unreachable]") && !status(&app).contains("[connecting…]"));
+    }
+
+    #[tokio::test]
+    async fn single_mode_keeps_one_command() {
+        let client = Bclient::new(&mock_script(&[r#"{"commands": ["df -h", "du -sh *"]}"#, r#"{"commands": ["df -h", "du -sh *"]}"#]));
+        let status = |app: &App| plain_lines(&app.status_text()).concat();
+        let mut app = App::new("llama3:latest");
+        assert!(!status(&app).contains("[single]"));
+        ask(&mut app, &client, "free disk space").await;
+        assert_eq!((app.shell_commands.len(), app.notice.as_deref()), (2, None));
+
+        app.cycle_suggestion_mode();
+        assert!(status(&app).contains("[single]"));
+        assert_eq!(app.messages.suggestion_mode(), SuggestionMode::Single);
+        app.shell_commands.clear();
+        ask(&mut app, &client, "free disk space").await;
+        assert_eq!(app.shell_commands, ["df -h"]);
+        assert_eq!(app.notice.as_deref(), Some("Single mode: kept the first command, the model suggested 1 more"));
+
+        app.cycle_suggestion_mode();
+        assert!(status(&app).contains("[plan]"));
+        app.cycle_suggestion_mode();
+        assert!(!status(&app).contains("[plan]"));
+    }
+
+    #[tokio::test]
+    async fn failed_commands_are_fixed() {
+        let client = Bclient::new(&mock_script(&[
+            r#"{"commands": ["false"]}"#,
+            r#"{"commands": ["false"]}"#,
+            r#"{"commands": ["echo fixed"]}"#,
+            r#"{"commands": ["rm -rf /"]}"#,
+        ]));
+        let (res_tx, mut res_rx) = mpsc::unbounded_channel();
+        let (models_tx, _models_rx) = mpsc::unbounded_channel();
+        let (commands_tx, _commands_rx) = mpsc::unbounded_channel();
+        let (compare_tx, _compare_rx) = mpsc::unbounded_channel();
+        let mut perform = async |app: &mut App, action| {
+            app.perform(action, &client, &res_tx, &models_tx, &commands_tx, &compare_tx);
+            while app.pending.is_some() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                app.poll_responses(&mut res_rx);
+            }
+        };
+        let status = |app: &App| plain_lines(&app.status_text()).concat();
+
+        // off by default
+        let mut app = app_with_queue(&["cat missing-notes.txt"]);
+        perform(&mut app, Action::ExecuteCommand).await;
+        assert!(app.confirm.is_none());
+
+        let mut app = app_with_queue(&["cat missing-notes.txt", "pwd"]).with_auto_fix(true, 2);
+        assert!(status(&app).contains("[auto-fix on]"));
+        perform(&mut app, Action::ExecuteCommand).await;
+        let Some(Confirmation::AutoFix { command, failed, attempt }) = &app.confirm else {
+            panic!("no correction to confirm");
+        };
+        assert_eq!((command.as_str(), failed.as_str(), attempt.as_str()), ("false", "cat missing-notes.txt", "auto-fix attempt 1/2"));
+        assert!(status(&app).contains("[auto-fix attempt 1/2]"));
+        // the correction goes first in the queue
+        assert_eq!(app.shell_commands, ["false", "pwd"]);
+
+        // it fails too, and so does the second one, which is the last
+        perform(&mut app, Action::ConfirmRun).await;
+        assert!(matches!(&app.confirm, Some(Confirmation::AutoFix { attempt, .. }) if attempt == "auto-fix attempt 2/2"));
+        perform(&mut app, Action::ConfirmRun).await;
+        assert!(app.confirm.is_none() && app.fixing.is_none());
+        assert!(app.shell.history.last().unwrap().stdout.contains("gave up after 2 attempt(s) at `cat missing-notes.txt`"));
+        assert_eq!(app.shell_commands, ["pwd"]);
+
+        // a correction that works ends the loop
+        app.recv_from(vec!["ls missing-dir".to_string()]);
+        perform(&mut app, Action::ExecuteCommand).await;
+        perform(&mut app, Action::ConfirmRun).await;
+        assert_eq!(app.shell.history.last().unwrap().stdout, "fixed");
+        assert_eq!(app.notice.as_deref(), Some("Fixed by auto-fix attempt 1/2"));
+        assert!(app.fixing.is_none());
+
+        // a dangerous one is never run, it is left in the queue
+        app.recv_from(vec!["ls missing-dir".to_string()]);
+        perform(&mut app, Action::ExecuteCommand).await;
+        assert!(app.confirm.is_none() && app.fixing.is_none());
+        assert_eq!(app.shell_commands, ["rm -rf /"]);
+        assert!(app.shell.history.last().unwrap().stdout.starts_with("auto-fix attempt 1/2: not run, this command"));
+
+        perform(&mut app, Action::ToggleAutoFix).await;
+        assert_eq!(app.notice.as_deref(), Some("Auto-fix off"));
+        assert!(!status(&app).contains("auto-fix"));
+    }
+
+    #[test]
+    fn paste_into_shell() {
+        let mut app = app_with_queue(&["echo", "whoami"]);
+        app.input_mode = EditMode::Shell;
+        app.paste(" hello\n");
+        // a trailing newline does not run the command
+        assert_eq!(shell_input(&app), "echo hello");
+        assert!(app.shell.history.is_empty());
+        assert!(matches!(app.input_mode, EditMode::Shell));
+
+        app.paste("\tfirst\r\n\nmkdir build\ncd build\n");
+        assert_eq!(shell_input(&app), "echo hello first");
+        assert_eq!(app.shell_commands, ["echo", "mkdir build", "cd build", "whoami"]);
+
+        // without a queue, the typed command becomes its first entry
+        let mut app = App::new("llama3:latest");
+        app.input_mode = EditMode::Shell;
+        app.paste("ls\npwd");
+        assert_eq!(app.shell_commands, ["ls", "pwd"]);
+        assert_eq!(app.selected_command(), Some("ls"));
+        app.submit_command(shell_input(&app));
+        assert_eq!(shell_input(&app), "pwd");
+    }
+
+    #[test]
+    fn paste_into_prompt() {
+        let mut app = App::new("llama3:latest");
+        app.input_mode = EditMode::Input;
+        app.paste("explain:\r\nerror: not found\r\n");
+        assert_eq!(app.input.value(), "explain:\nerror: not found");
+        // nothing reaches the boxes from modes without one
+        app.input_mode = EditMode::Normal;
+        app.paste("q");
+        assert_eq!(app.input.value(), "explain:\nerror: not found");
+        assert_eq!(shell_input(&app), "");
+    }
+
+    #[test]
+    fn multi_line_prompt() {
+        let mut app = App::new("llama3:latest");
+        app.input_mode = EditMode::Input;
+        app.input = PromptEditor::new("why does this fail?".to_string());
+        app.input.insert_newline();
+        app.paste("error: linker `cc` not found\r\nnote: install a C compiler");
+        assert_eq!(app.input.value(), "why does this fail?\nerror: linker `cc` not found\nnote: install a C compiler");
+        // the box grows with the prompt, the cursor stays at the end of the last line
+        assert_eq!(render_cursor(&mut app, 80, 30), (1 + 26, 1 + 3));
+
+        // and stops growing, scrolling to the cursor instead
+        for _ in 0..MAX_PROMPT_ROWS {
+            app.input.insert_newline();
+        }
+        app.input.insert_char('x');
+        assert_eq!(render_cursor(&mut app, 80, 30), (1 + 1, 1 + MAX_PROMPT_ROWS as u16));
+    }
+
+    #[test]
+    fn prompt_recall() {
+        let mut app = App::new("llama3:latest");
+        app.prompt_history.push("list files");
+        app.prompt_history.push("show disk usage");
+        app.input = PromptEditor::new("draft".to_string());
+
+        app.recall_prev_prompt();
+        assert_eq!(app.input.value(), "show disk usage");
+        app.recall_prev_prompt();
+        assert_eq!(app.input.value(), "list files");
+        app.recall_next_prompt();
+        app.recall_next_prompt();
+        assert_eq!(app.input.value(), "draft");
+        assert_eq!(app.input.cursor(), (0, "draft".len()));
+    }
+
+    #[test]
+    fn confirm_dangerous_command() {
+        let mut app = app_with_queue(&["rm -rf build", "ls"]);
+        app.input_mode = EditMode::Shell;
+        app.submit_command(shell_input(&app));
+        assert!(matches!(app.input_mode, EditMode::Confirm));
+        assert!(app.shell.history.is_empty());
+
+        // cancelling returns to the Shell box with the command kept for editing
+        app.reject_confirmation();
+        assert!(matches!(app.input_mode, EditMode::Shell));
+        assert_eq!(shell_input(&app), "rm -rf build");
+        assert_eq!(app.shell_commands.len(), 2);
+
+        // harmless commands run right away
+        app.submit_command("echo ok".to_string());
+        assert!(matches!(app.input_mode, EditMode::Normal));
+        assert_eq!(app.shell.history.len(), 1);
+    }
+
+    #[test]
+    fn sudo_password_popup() {
+        let (commands_tx, _commands_rx) = mpsc::unbounded_channel();
+        let mut app = app_with_queue(&["sudo true", "ls"]);
+        app.sudo_cached = || false;
+        app.submit_command(shell_input(&app));
+        assert!(matches!(&app.confirm, Some(Confirmation::SudoPassword { command }) if command == "sudo true"));
+        assert!(app.shell.history.is_empty());
+
+        // typing the password first happens on the terminal, before the next draw
+        app.accept_confirmation(&commands_tx);
+        assert!(matches!(&app.tty_due, Some(TtyRun::SudoThen(command)) if command == "sudo true"));
+        app.tty_due = None;
+
+        // the alternative runs the command without a password prompt
+        app.submit_command(shell_input(&app));
+        app.run_without_password();
+        assert!(app.confirm.is_none());
+        assert_eq!(app.shell.history[0].command, "sudo -n true");
+        assert_eq!(app.shell_commands, ["ls"]);
+
+        // cached credentials need no popup
+        app.sudo_cached = || true;
+        app.submit_command("sudo -V".to_string());
+        assert!(app.confirm.is_none());
+        assert_eq!(app.shell.history.len(), 2);
+    }
+
+    #[test]
+    fn interactive_commands() {
+        let (commands_tx, _commands_rx) = mpsc::unbounded_channel();
+        let mut app = app_with_queue(&["vim notes", "ls"])
+            .with_dangerous_patterns(vec!["rm ".to_string()]);
+        app.submit_interactive(shell_input(&app));
+        assert!(matches!(&app.tty_due, Some(TtyRun::Command(command)) if command == "vim notes"));
+        app.tty_due = None;
+        app.record_interactive("vim notes".to_string(), Some(0), Duration::from_secs(3));
+        let entry = &app.shell.history[0];
+        assert!(entry.interactive && entry.stdout.is_empty());
+        assert_eq!(app.shell_commands, ["ls"]);
+        assert!(render(&mut app, 80, 24).iter().any(|line| line.contains(interactive::INTERACTIVE_NOTE)));
+
+        // dangerous commands are confirmed before going to the terminal
+        app.submit_interactive("rm -i notes".to_string());
+        assert!(matches!(app.confirm, Some(Confirmation::Dangerous { interactive: true, .. })));
+        app.accept_confirmation(&commands_tx);
+        assert!(matches!(&app.tty_due, Some(TtyRun::Command(command)) if command == "rm -i notes"));
+    }
+
+    #[test]
+    fn restore_saved_queue() {
+        let dir = std::env::temp_dir().join(format!("aurish-restore-queue-{}", std::process::id()));
+        let path = dir.join("queue.json");
+        let (commands_tx, _commands_rx) = mpsc::unbounded_channel();
+
+        // the queue of a session that quit with commands left
+        let mut app = app_with_queue(&["make", "make install"]).with_queue_file(path.clone());
+        app.last_prompt = Some("build it".to_string());
+        app.shell.shell.set_current_dir(&std::env::temp_dir());
+        app.quit().unwrap();
+        assert!(path.exists());
+
+        let mut app = App::new("llama3:latest").with_queue_file(path.clone());
+        app.offer_saved_queue();
+        assert!(matches!(app.input_mode, EditMode::Confirm));
+        // nothing is written while the saved queue is on offer
+        app.save_queue();
+        assert!(path.exists());
+        app.accept_confirmation(&commands_tx);
+        assert_eq!(app.shell_commands, ["make", "make install"]);
+        assert_eq!(shell_input(&app), "make");
+        assert_eq!(app.last_prompt.as_deref(), Some("build it"));
+        assert_eq!(app.shell.shell.current_dir(), std::env::temp_dir());
+        assert_eq!(app.notice.as_deref(), Some("Restored 2 command(s)"));
+
+        // the file follows the queue and goes once it is empty
+        app.advance_queue();
+        app.save_queue();
+        assert_eq!(QueueFile::new(Some(path.clone())).load().unwrap().unwrap().commands, ["make install"]);
+        app.discard_queue();
+        app.save_queue();
+        assert!(!path.exists());
+
+        // declining removes it, and so does a corrupted file after a warning
+        let mut app = app_with_queue(&["ls"]).with_queue_file(path.clone());
+        app.save_queue();
+        let mut app = App::new("llama3:latest").with_queue_file(path.clone());
+        app.offer_saved_queue();
+        app.reject_confirmation();
+        assert!(app.shell_commands.is_empty());
+        assert!(!path.exists());
+        fs::write(&path, "{\"version\": 1, \"commands\": [\"l").unwrap();
+        let mut app = App::new("llama3:latest").with_queue_file(path.clone());
+        app.offer_saved_queue();
+        assert!(matches!(app.input_mode, EditMode::Normal));
+        assert!(app.error.as_deref().unwrap().starts_with("Ignored the queue of the previous session: the saved queue is corrupted"));
+        assert!(!path.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn shared_values() {
+        let value = |app: &mut App, value: &str| {
+            app.params_draft.as_mut().unwrap().value = Input::new(value.to_string());
+            app.set_param();
+        };
+        let mut app = app_with_queue(&["mkdir -p demo", "cd demo", "cp ../notes.txt .", "less notes.txt", "git init"]);
+        app.start_params();
+        assert_eq!(app.input_mode, EditMode::Params);
+        let screen = render(&mut app, 60, 20).join("\n");
+        assert!(screen.contains("Shared values 1/2"), "{}", screen);
+        assert!(screen.contains("demo is used by 2 commands"), "{}", screen);
+        assert!(screen.contains("Value: demo"), "{}", screen);
+
+        value(&mut app, " ");
+        assert!(app.error.take().is_some());
+        value(&mut app, "site");
+        assert_eq!(app.params_draft.as_ref().unwrap().value.value(), "notes.txt");
+        value(&mut app, "todo.md");
+        assert_eq!(app.shell_commands, ["mkdir -p site", "cd site", "cp ../todo.md .", "less todo.md", "git init"]);
+        assert_eq!(shell_input(&app), "mkdir -p site");
+        assert_eq!(app.notice.as_deref(), Some("Changed 4 queued command(s)"));
+        assert_eq!(app.input_mode, EditMode::Normal);
+
+        // Esc keeps the queue
+        app.start_params();
+        value(&mut app, "blog");
+        app.close_params();
+        assert_eq!(app.shell_commands[1], "cd site");
+
+        let mut app = app_with_queue(&["ls", "pwd"]);
+        app.start_params();
+        assert_eq!(app.input_mode, EditMode::Normal);
+        assert_eq!(app.notice.as_deref(), Some("The queued commands share no value to change"));
+    }
+
+    #[test]
+    fn favorites() {
+        let dir = std::env::temp_dir().join(format!("aurish-tui-favorites-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("favorites.json");
+        let (commands_tx, _commands_rx) = mpsc::unbounded_channel();
+        let name = |app: &mut App, name: &str| {
+            app.favorite_draft.as_mut().unwrap().name = Input::new(name.to_string());
+            app.name_favorite();
+        };
+
+        let mut app = app_with_queue(&["ss -tlnp", "df -h"]).with_favorites(path.clone());
+        app.start_favorite();
+        assert_eq!(app.input_mode, EditMode::FavoriteName);
+        name(&mut app, " \n");
+        assert!(app.error.take().unwrap().contains("cannot name a favorite"));
+        name(&mut app, "ports");
+        assert_eq!(app.notice.as_deref(), Some("Saved as favorite `ports`"));
+        assert_eq!(app.input_mode, EditMode::Normal);
+
+        // a taken name asks first, declining goes back to the name
+        app.select_next();
+        app.start_favorite();
+        name(&mut app, "ports");
+        assert!(matches!(&app.confirm, Some(Confirmation::ReplaceFavorite { replaced, .. }) if replaced == "ss -tlnp"));
+        app.reject_confirmation();
+        assert_eq!(app.input_mode, EditMode::FavoriteName);
+        name(&mut app, "ports");
+        app.accept_confirmation(&commands_tx);
+        assert_eq!(app.notice.as_deref(), Some("Replaced favorite `ports`"));
+        assert_eq!(Favorites::new(&path).get("ports").unwrap().unwrap().command, "df -h");
+
+        // browsing inserts into the Shell box
+        Favorites::new(&path).add("disk", "du -sh").unwrap();
+        app.discard_queue();
+        app.open_favorites();
+        app.select_favorite(5);
+        app.insert_favorite();
+        assert_eq!(app.input_mode, EditMode::Shell);
+        assert_eq!(shell_input(&app), "du -sh");
+        app.open_favorites();
+        app.remove_favorite();
+        assert_eq!(app.notice.as_deref(), Some("Removed favorite `ports`"));
+        assert_eq!(app.favorites_list.as_ref().unwrap().selected().unwrap().name, "disk");
+        app.insert_favorite();
+        assert_eq!(shell_input(&app), "du -shdu -sh");
+
+        // nothing selected, nothing to save
+        app.start_favorite();
+        assert!(app.favorite_draft.is_none());
+        assert!(app.error.is_some());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn quit_confirmation() {
+        // nothing to lose, a single key press
+        let mut app = App::new("llama3:latest");
+        assert!(app.confirms_quit(Action::Quit));
+
+        let mut app = app_with_queue(&["ls", "pwd"]);
+        assert!(!app.confirms_quit(Action::Quit));
+        assert!(matches!(app.input_mode, EditMode::Confirm));
+        assert!(matches!(&app.confirm, Some(Confirmation::Quit { reasons }) if reasons == &["2 command(s) still queued"]));
+        assert!(!app.confirms_quit(Action::ConfirmCancel));
+        app.reject_confirmation();
+        assert!(matches!(app.input_mode, EditMode::Normal));
+
+        // a second q, or y, confirms
+        assert!(!app.confirms_quit(Action::Quit));
+        assert!(app.confirms_quit(Action::Quit));
+        assert!(app.confirms_quit(Action::ConfirmRun));
+
+        // but q does not answer other questions
+        let mut app = app_with_queue(&["rm -rf build"]);
+        app.submit_command(shell_input(&app));
+        assert!(!app.confirms_quit(Action::Quit));
+        assert!(matches!(app.confirm, Some(Confirmation::Dangerous { .. })));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn shutdown_runs_once() {
+        let dir = std::env::temp_dir().join(format!("aurish-shutdown-{}", std::process::id()));
+        let path = dir.join("queue.json");
+        let mut app = app_with_queue(&["make", "make install"])
+            .with_queue_file(path.clone())
+            .with_session_export(Some(dir.clone()), true);
+        app.session.prompt_sent("llama3:latest", "build it");
+        let job = app.shell.shell.spawn_job("sleep 30").unwrap();
+        let exported = |dir: &PathBuf| fs::read_dir(dir).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with("session-"))
+            .count();
+
+        // a signal quits without asking although commands are queued
+        app.quit().unwrap();
+        assert!(!job.is_running());
+        assert_eq!(QueueFile::new(Some(path.clone())).load().unwrap().unwrap().commands, ["make", "make install"]);
+        assert_eq!(exported(&dir), 1);
+
+        // a q pressed meanwhile, or a second signal, changes nothing
+        app.advance_queue();
+        app.quit().unwrap();
+        assert_eq!(QueueFile::new(Some(path.clone())).load().unwrap().unwrap().commands, ["make", "make install"]);
+        assert_eq!(exported(&dir), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn signal_stops_running_command() {
+        use std::time::{Duration, Instant};
+
+        let flag = Arc::new(AtomicBool::new(false));
+        let shell = Arc::new(IShell::new().with_process_groups(true));
+        shutdown_on_signals(Arc::clone(&flag), Arc::clone(&shell)).unwrap();
+        let started = Instant::now();
+        let runner = {
+            let shell = Arc::clone(&shell);
+            std::thread::spawn(move || shell.run_command("sleep 30"))
+        };
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        // SAFETY: plain syscall, SIGHUP is handled by the task set up above
+        unsafe {
+            libc::kill(libc::getpid(), libc::SIGHUP);
+        }
+        while !flag.load(Ordering::SeqCst) && started.elapsed() < Duration::from_secs(10) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let output = tokio::task::spawn_blocking(move || runner.join().unwrap()).await.unwrap();
+        assert!(flag.load(Ordering::SeqCst));
+        assert!(!output.is_success());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn confirm_popup_snapshot() {
+        let mut app = app_with_queue(&["rm -rf build"]);
+        app.shell.curr_path = PathBuf::from("/srv");
+        app.submit_command(shell_input(&app));
+
+        assert_eq!(render(&mut app, 72, 16), [
+            "Press y to run the command, n/Esc to go back and edit it  [model: llama3",
+            "┌Asking AI─────────────────────────────────────────────────────────────┐",
+            "│                                                                      │",
+            "└──────────────────────────────────────────────────────────────────────┘",
+            "┌Shel┌Run dangerous command?─────────────────────────────────────┐─────┐",
+            "│/srv│This command deletes files recursively or without asking:  │     │",
+            "└────│                                                           │─────┘",
+            "┌Resp│rm -rf build                                               │─────┐",
+            "│    │                                                           │     │",
+            "│    │y run  n/Esc cancel                                        │     │",
+            "│    └───────────────────────────────────────────────────────────┘     │",
+            "└───────────────────────┘│                                             │",
+            "┌Queue (1)──────────────┐│                                             │",
+            "│> rm -rf build         ││                                             │",
+            "│                       ││                                             │",
+            "└───────────────────────┘└─────────────────────────────────────────────┘",
+        ]);
+    }
+
+    /// Open the model switcher and wait for the model list
+    async fn pick_model(app: &mut App, client: &Bclient) {
+        let (models_tx, mut models_rx) = mpsc::unbounded_channel();
+        app.open_model_picker(client, &models_tx);
+        assert!(matches!(app.input_mode, EditMode::Models));
+        let models = models_rx.recv().await.unwrap();
+        models_tx.send(models).unwrap();
+        app.poll_models(&mut models_rx);
+    }
+
+    #[tokio::test]
+    async fn switch_model_from_list() {
+        let body = serde_json::json!({
+            "models": [{"name": "llama3:latest"}, {"name": "qwen2.5-coder:7b"}],
+        }).to_string();
+        let client = Bclient::new(&mock_server(body, 1));
+        let mut app = App::new("llama3:latest");
+
+        pick_model(&mut app, &client).await;
+        let picker = app.model_picker.as_ref().unwrap();
+        // the active model is preselected
+        assert_eq!(picker.list_state.selected(), Some(0));
+
+        app.select_model(1);
+        app.select_model(1);
+        app.choose_model(false);
+        assert_eq!(app.messages.model(), "qwen2.5-coder:7b");
+        assert!(app.model_picker.is_none());
+        assert!(matches!(app.input_mode, EditMode::Normal));
+    }
+
+    #[tokio::test]
+    async fn switch_model_by_name() {
+        let client = Bclient::new("http://127.0.0.1:9/api/generate");
+        let config_path = std::env::temp_dir().join(format!("aurish_config_{}.json", rand::random::<u32>()));
+        Config::default().save(&config_path).unwrap();
+        let mut app = App::new("llama3:latest").with_config_path(config_path.clone());
+
+        // listing fails, the name is typed instead
+        pick_model(&mut app, &client).await;
+        assert!(matches!(app.model_picker.as_ref().unwrap().models, Some(Err(_))));
+        app.model_picker.as_mut().unwrap().input = Input::default().with_value("mistral:7b".to_string());
+        app.choose_model(true);
+
+        assert_eq!(app.messages.model(), "mistral:7b");
+        assert_eq!(app.error, None);
+        assert_eq!(Config::load(&config_path).unwrap().get_model(), "mistral:7b");
+        fs::remove_file(&config_path).unwrap();
+        let _ = fs::remove_file(config_path.with_file_name(format!(".{}.lock", config_path.file_name().unwrap().to_string_lossy())));
+    }
+
+    #[tokio::test]
+    async fn config_reloaded_mid_session() {
+        let dir = std::env::temp_dir().join(format!("aurish_reload_{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("config.json");
+        Config::default().save(&config_path).unwrap();
+        let before = fs::metadata(&config_path).unwrap().modified().unwrap();
+        let (reload_tx, reload_rx) = mpsc::unbounded_channel();
+        let _watch = Config::watch(&config_path, Duration::from_millis(10), move |loaded| {
+            let _ = reload_tx.send(loaded.map(|config| ConfigReload { config, theme: Theme::monochrome() }));
+        });
+        let mut app = App::new("llama3:latest").with_config_reloads(reload_rx);
+        let mut client = Bclient::from_config(&Config::default());
+        let (health_tx, _health_rx) = mpsc::unbounded_channel();
+
+        // `aurish-cli --set-model` and an edit of the keymap, from another terminal
+        fs::write(&config_path, r#"{"ollama_api": "http://127.0.0.1:9/api/generate", "model": "mistral:7b", "proxy": "", "keymap": "vim"}"#).unwrap();
+        fs::File::options().write(true).open(&config_path).unwrap().set_modified(before + Duration::from_secs(5)).unwrap();
+        for _ in 0..500 {
+            app.poll_config_reloads(&mut client, &health_tx);
+            if app.notice.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(app.notice.as_deref(), Some("Config reloaded: model, endpoint, theme, keybindings changed"));
+        assert_eq!(app.messages.model(), "mistral:7b");
+        assert!(client.same_settings(&Bclient::new("http://127.0.0.1:9/api/generate")));
+        assert_eq!(app.theme, Theme::monochrome());
+        assert_eq!(app.keymap, keymap::by_name("vim").unwrap());
+
+        // the same values again change nothing, a broken file leaves everything as it was
+        app.notice = None;
+        let same = Config::load(&config_path).unwrap();
+        app.reload_config(Ok(ConfigReload { config: same, theme: Theme::monochrome() }), &mut client, &health_tx);
+        assert_eq!(app.notice, None);
+        app.reload_config(Config::load(&dir.join("missing.json")).map(|config| ConfigReload { config, theme: Theme::default() }), &mut client, &health_tx);
+        assert!(app.error.as_deref().unwrap().starts_with("Config not reloaded"));
+        assert_eq!((app.messages.model(), app.theme), ("mistral:7b", Theme::monochrome()));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn key_sequences() {
+        use ratatui::crossterm::event::{KeyCode, KeyModifiers};
+
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        let mut app = App::new("llama3:latest").with_keymap(keymap::vim_bindings());
+        assert_eq!(app.resolve_key(key('g')), Lookup::Prefix);
+        assert_eq!(app.resolve_key(key('g')), Lookup::Action(Action::ScrollOutput(u16::MAX as i32)));
+        // a key breaking a sequence off is looked up on its own
+        assert_eq!(app.resolve_key(key(':')), Lookup::Prefix);
+        assert_eq!(app.resolve_key(key('j')), Lookup::Action(Action::ScrollOutput(-1)));
+        assert_eq!(app.resolve_key(key('g')), Lookup::Prefix);
+        assert_eq!(app.resolve_key(key('z')), Lookup::Unbound);
+        assert!(app.pending_keys.is_empty());
+    }
+
+    #[test]
+    fn help_overlay() {
+        use ratatui::crossterm::event::{KeyCode, KeyModifiers};
+
+        let mut app = App::new("llama3:latest");
+        app.toggle_help();
+        assert_eq!(app.input_mode, EditMode::Help);
+
+        // keys without a binding do not reach the boxes behind the overlay
+        let key = KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE);
+        assert_eq!(keymap::lookup(&app.keymap, app.input_mode, &key), None);
+        app.type_key(Event::Key(key));
+        assert_eq!(app.input.value(), "");
+
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(100, 100)).unwrap();
+        terminal.draw(|f| app.ui(f)).unwrap();
+        let buffer = terminal.backend().buffer();
+        let screen: String = buffer.content().iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("┌Keys"));
+        assert!(screen.contains("use the model and save it to the config"));
+
+        app.toggle_help();
+        assert_eq!(app.input_mode, EditMode::Normal);
+    }
+
+    /// Drive "run all" until it ends or waits for confirmation
+    async fn wait_run_all(app: &mut App, commands_tx: &CommandSender, commands_rx: &mut CommandReceiver) {
+        while app.run_all.is_some() && app.confirm.is_none() {
+            let result = commands_rx.recv().await.unwrap();
+            app.finish_queued(result, commands_tx);
+        }
+    }
+
+    #[tokio::test]
+    async fn run_all_stops_on_failure() {
+        let (commands_tx, mut commands_rx) = mpsc::unbounded_channel();
+        let mut app = app_with_queue(&["echo one", "exit 3", "echo three"]);
+        app.ask_run_all();
+        assert!(matches!(app.confirm, Some(Confirmation::RunAll { count: 3 })));
+        app.accept_confirmation(&commands_tx);
+        assert_eq!(app.run_all.as_ref().unwrap().status().split_once(' ').unwrap().1, "running 1/3");
+
+        wait_run_all(&mut app, &commands_tx, &mut commands_rx).await;
+        let ran: Vec<_> = app.shell.history.iter().map(|entry| (entry.command.as_str(), entry.exit_code)).collect();
+        assert_eq!(ran, [("echo one", Some(0)), ("exit 3", Some(3))]);
+        // the failed command waits in the Shell box to be fixed
+        assert_eq!(app.shell_commands, ["exit 3", "echo three"]);
+        assert_eq!(shell_input(&app), "exit 3");
+        assert!(app.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn run_all_confirms_dangerous_commands() {
+        let (commands_tx, mut commands_rx) = mpsc::unbounded_channel();
+        let mut app = app_with_queue(&["echo safe", "echo risky", "echo after"])
+            .with_dangerous_patterns(vec!["risky".to_string()]);
+        app.ask_run_all();
+        app.accept_confirmation(&commands_tx);
+
+        wait_run_all(&mut app, &commands_tx, &mut commands_rx).await;
+        assert!(matches!(app.confirm, Some(Confirmation::Dangerous { .. })));
+        assert_eq!(app.run_all.as_ref().unwrap().done, 1);
+
+        app.accept_confirmation(&commands_tx);
+        wait_run_all(&mut app, &commands_tx, &mut commands_rx).await;
+        assert!(app.run_all.is_none());
+        assert!(app.shell_commands.is_empty());
+        assert_eq!(app.shell.history.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn run_all_stops_at_sudo() {
+        let (commands_tx, mut commands_rx) = mpsc::unbounded_channel();
+        let mut app = app_with_queue(&["echo one", "sudo true", "echo three"]);
+        app.sudo_cached = || false;
+        app.ask_run_all();
+        app.accept_confirmation(&commands_tx);
+
+        wait_run_all(&mut app, &commands_tx, &mut commands_rx).await;
+        assert!(app.run_all.is_none());
+        assert!(matches!(app.confirm, Some(Confirmation::SudoPassword { .. })));
+        assert_eq!(app.shell.history.len(), 1);
+        assert_eq!(app.shell_commands, ["sudo true", "echo three"]);
+    }
+
+    #[test]
+    fn empty_answer_keeps_queue() {
+        let mut app = app_with_queue(&["ls"]);
+        assert_eq!(app.recv_from(Vec::new()), 0);
+        assert_eq!(app.error.as_deref(), Some("Model returned no commands"));
+        assert_eq!(app.recv_from(vec![" ".to_string(), "\n".to_string()]), 0);
+        assert_eq!(app.error.as_deref(), Some("Model returned 0 usable commands"));
+        assert_eq!(app.shell_commands, ["ls"]);
+        assert_eq!(shell_input(&app), "ls");
+    }
+
+    #[test]
+    fn multiline_answer() {
+        let mut app = App::new("llama3:latest");
+        assert_eq!(app.recv_from(vec!["mkdir test\ncd test ".to_string(), "".to_string()]), 2);
+        assert_eq!(app.shell_commands, ["mkdir test", "cd test"]);
+        assert_eq!(shell_input(&app), "mkdir test");
+
+        let mut app = app.with_multiline_commands(MultilineCommands::Join);
+        assert_eq!(app.recv_from(vec!["mkdir test\ncd test".to_string()]), 1);
+        assert_eq!(shell_input(&app), "mkdir test && cd test");
+    }
+
+    #[test]
+    fn advance_empty_queue() {
+        let mut app = App::new("llama3:latest");
+        app.shell.sh_input.replace(Input::default().with_value("echo typed".to_string()));
+        app.submit_command("echo typed".to_string());
+        assert_eq!(app.shell.history.len(), 1);
+        assert_eq!(app.advance_queue(), None);
+        assert!(app.shell_commands.is_empty());
+        assert_eq!(app.queue_state.selected(), None);
+        assert_eq!(shell_input(&app), "");
+
+        // nothing to run in an empty Shell box
+        app.submit_command("   ".to_string());
+        assert_eq!(app.shell.history.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn blank_prompt_not_sent() {
+        let (res_tx, _res_rx) = mpsc::unbounded_channel();
+        let mut app = App::new("llama3:latest");
+        app.input = PromptEditor::new("  \t ".to_string());
+        assert!(!app.send_request(&Bclient::default(), &res_tx));
+        assert!(app.pending.is_none());
+        assert!(app.prompt_history.entries().is_empty());
+    }
+
+    #[test]
+    fn session_transcript() {
+        use crate::session::Event;
+
+        let dir = std::env::temp_dir().join(format!("aurish-session-test-{}", std::process::id()));
+        let mut app = app_with_queue(&["printf one", "printf two"]).with_session_export(Some(dir.clone()), true);
+        app.submit_command("printf one!".to_string());
+        app.submit_command(shell_input(&app));
+
+        assert!(matches!(&app.session.events[0], Event::CommandEdited { suggested, command, .. }
+            if suggested == "printf one" && command == "printf one!"));
+        assert!(matches!(&app.session.events[1], Event::CommandExecuted { output, exit_code: Some(0), suggested: Some(suggested), .. }
+            if output.stdout == "one!" && suggested == "printf one"));
+        assert!(matches!(&app.session.events[2], Event::CommandExecuted { command, suggested: None, .. } if command == "printf two"));
+
+        app.quit().unwrap();
+        let exported: Vec<_> = fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.file_name().unwrap().to_string_lossy().starts_with("session-"))
+            .collect();
+        assert_eq!(exported.len(), 1);
+        assert!(dir.join(crate::transcripts::INDEX_FILE).exists());
+        let saved: Session = serde_json::from_str(&fs::read_to_string(&exported[0]).unwrap()).unwrap();
+        assert_eq!(saved, app.session);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn copy_selection() {
+        let mut app = app_with_queue(&["ls -la"]);
+        app.copy_output();
+        assert_eq!(app.notice.as_deref(), Some("Nothing to copy"));
+
+        app.execute_command("printf first".to_string(), None);
+        app.execute_command("printf second".to_string(), None);
+        app.focus_output();
+        assert_eq!(app.output_selected, Some(1));
+        app.select_output(-5);
+        assert_eq!(app.output_entry().unwrap().stdout, "first");
+        app.select_output(1);
+        assert_eq!(app.output_entry().unwrap().command, "printf second");
+
+        // without a display there is no clipboard, which must not panic
+        app.notice = None;
+        app.copy_command();
+        match (&app.notice, &app.error) {
+            (Some(notice), None) => assert_eq!(notice, "Copied 13 bytes"),
+            (None, Some(error)) => assert!(error.starts_with("Clipboard")),
+            other => panic!("unexpected state {:?}", other),
+        }
+    }
+
+    /// Colors of the main parts of the screen, rendered with `theme`
+    fn theme_snapshot(theme: Theme) -> Vec<(&'static str, Option<Color>)> {
+        let mut app = App::new("llama3:latest").with_theme(theme);
+        app.execute_command("printf 'pro''blem' 1>&2".to_string(), None);
+        app.input_mode = EditMode::Input;
+        app.error = Some("boom".to_string());
+
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(100, 20)).unwrap();
+        terminal.draw(|f| app.ui(f)).unwrap();
+        let buffer = terminal.backend().buffer();
+        let find = |needle: &str| {
+            let (x, y) = (0..buffer.area.height)
+                .find_map(|y| {
+                    let row: String = (0..buffer.area.width).map(|x| buffer[(x, y)].symbol()).collect();
+                    row.find(needle).map(|byte| (row[..byte].chars().count() as u16, y))
+                })
+                .unwrap();
+            &buffer[(x, y)]
+        };
+        vec![
+            ("model", find("[model:").fg.into()),
+            ("banner", find("Error: boom").bg.into()),
+            ("active box", find("Asking AI").fg.into()),
+            ("inactive box", find("Shell").fg.into()),
+            ("stderr", find("problem").fg.into()),
+        ]
+    }
+
+    #[test]
+    fn theme_presets() {
+        use crate::theme::Theme;
+
+        assert_eq!(theme_snapshot(Theme::dark()), [
+            ("model", Some(Color::Cyan)),
+            ("banner", Some(Color::Red)),
+            ("active box", Some(Color::Yellow)),
+            ("inactive box", Some(Color::Blue)),
+            ("stderr", Some(Color::Red)),
+        ]);
+        assert_eq!(theme_snapshot(Theme::light()), [
+            ("model", Some(Color::Magenta)),
+            ("banner", Some(Color::Red)),
+            ("active box", Some(Color::Blue)),
+            ("inactive box", Some(Color::DarkGray)),
+            ("stderr", Some(Color::Red)),
+        ]);
+    }
+
+    #[test]
+    fn output_search() {
+        use ratatui::crossterm::event::{KeyCode, KeyModifiers};
+
+        let mut app = App::new("llama3:latest");
+        for (command, stdout) in [("cat log", "ok\nError: disk full\nok"), ("dmesg", "error: timeout\nErrors: 2")] {
+            app.log_output(ExecutedCommand {
+                command: command.to_string(),
+                exit_code: Some(0),
+                stdout: stdout.to_string(),
+                stderr: String::new(),
+                duration: Duration::ZERO,
+                paged: None,
+                suggested: None,
+                interactive: false,
+            });
+        }
+        app.jump_to_match(true);
+        assert_eq!(app.notice.as_deref(), Some("Press / to search the output first"));
+
+        app.start_search();
+        for c in "error".chars() {
+            app.type_key(Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)));
+        }
+        let search = app.search.as_ref().unwrap();
+        // lines are "$ cat log", "ok", "Error: disk full", "ok", "$ dmesg", "error: timeout", "Errors: 2"
+        assert_eq!((search.current(), search.position()), (Some(6), (3, 3)));
+
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(100, 30)).unwrap();
+        terminal.draw(|f| app.ui(f)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("Output /error [3/3]"));
+
+        // capitals make the search case-sensitive
+        app.type_key(Event::Key(KeyEvent::new(KeyCode::Home, KeyModifiers::NONE)));
+        app.type_key(Event::Key(KeyEvent::new(KeyCode::Delete, KeyModifiers::NONE)));
+        app.type_key(Event::Key(KeyEvent::new(KeyCode::Char('E'), KeyModifiers::NONE)));
+        assert_eq!(app.search.as_ref().unwrap().position(), (2, 2));
+
+        app.end_search(true);
+        assert_eq!(app.input_mode, EditMode::Normal);
+        app.focus_output();
+        app.jump_to_match(true);
+        assert_eq!((app.search.as_ref().unwrap().current(), app.output_selected), (Some(2), Some(0)));
+        app.jump_to_match(false);
+        assert_eq!((app.search.as_ref().unwrap().current(), app.output_selected), (Some(6), Some(1)));
+
+        app.start_search();
+        app.end_search(false);
+        assert_eq!((app.input_mode, app.search.is_none()), (EditMode::Output, true));
+        terminal.draw(|f| app.ui(f)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        assert!(!screen.contains("Output /"));
+    }
+
+    /// Rows of `app` rendered into a `width`x`height` test terminal, without the cells
+    /// covered by the right half of a wide character
+    fn render(app: &mut App, width: u16, height: u16) -> Vec<String> {
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(width, height)).unwrap();
+        terminal.draw(|f| app.ui(f)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..height).map(|y| {
+            let mut row = String::new();
+            let mut x = 0;
+            while x < width {
+                let symbol = buffer[(x, y)].symbol();
+                row.push_str(symbol);
+                x += (Line::raw(symbol).width() as u16).max(1);
+            }
+            row
+        }).collect()
+    }
+
+    /// Where the cursor is left after rendering `app`
+    fn cursor(app: &mut App, width: u16, height: u16) -> (u16, u16) {
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(width, height)).unwrap();
+        terminal.draw(|f| app.ui(f)).unwrap();
+        terminal.get_cursor_position().unwrap().into()
+    }
+
+    #[test]
+    fn edits_are_previewed() {
+        let mut app = app_with_queue(&["cp a.txt b.txt"]);
+        app.input_mode = EditMode::Shell;
+        let rows = render(&mut app, 60, 20);
+        assert!(!rows.iter().any(|row| row.contains("edited")));
+
+        *app.shell.sh_input.borrow_mut() = Input::new("cp -v a.txt c.txt".to_string());
+        let preview = app.edit_preview().unwrap();
+        let changed: Vec<_> = preview.spans.iter()
+            .filter(|span| span.style.add_modifier.intersects(Modifier::BOLD | Modifier::CROSSED_OUT))
+            .map(|span| span.content.as_ref())
+            .collect();
+        assert_eq!(changed, ["-v ", "b", "c"]);
+        // the Shell box grows by a row for it
+        let rows = render(&mut app, 60, 20);
+        let input_row = rows.iter().position(|row| row.contains("> cp -v a.txt c.txt")).unwrap();
+        assert!(rows[input_row + 1].contains("edited: cp -v a.txt bc.txt"));
+        assert_eq!(cursor(&mut app, 60, 20).1, input_row as u16);
+
+        app.submit_command(shell_input(&app));
+        let entry = app.shell.history.last().unwrap();
+        assert_eq!((entry.command.as_str(), entry.suggested.as_deref()), ("cp -v a.txt c.txt", Some("cp a.txt b.txt")));
+        let (text, _) = app.history_lines();
+        assert_eq!(plain_lines(&text)[1], "edited from: cp a.txt b.txt");
+    }
+
+    #[test]
+    fn long_output_is_paged() {
+        let mut app = App::new("llama3:latest");
+        let files: String = (1..=4253).map(|n| format!("/srv/file{}\n", n)).collect();
+        app.record_output("find /srv".to_string(), None, ShellOutput { code: Some(0), stdout: files.into_bytes(), stderr: Vec::new() }, Duration::ZERO);
+        let path = app.shell.history[0].paged.as_ref().unwrap().path().to_path_buf();
+        assert!(path.exists());
+
+        let lines = plain_lines(&app.history_lines().0);
+        // the header, the excerpts with the notice between them and the blank line after any output
+        assert_eq!(lines.len(), 1 + 2 * pager::EXCERPT_LINES + 2);
+        assert_eq!(lines[pager::EXCERPT_LINES], "/srv/file20");
+        assert_eq!(lines[pager::EXCERPT_LINES + 1], "… 4,213 lines omitted — press o to open in pager");
+        assert_eq!(lines[lines.len() - 2], "/srv/file4253");
+        // copied and exported in full all the same
+        assert_eq!(app.output_entry().unwrap().stdout.lines().count(), 4253);
+
+        app.focus_output();
+        app.request_pager();
+        assert!(app.pager_requested);
+
+        app.pager_requested = false;
+        app.record_output("ls".to_string(), None, ShellOutput { code: Some(0), stdout: b"Cargo.toml\n".to_vec(), stderr: Vec::new() }, Duration::ZERO);
+        app.focus_output();
+        app.request_pager();
+        assert!(!app.pager_requested);
+        assert_eq!(app.notice.as_deref(), Some("The whole output is shown already"));
+
+        // the file goes with the app
+        drop(app);
+        assert!(!path.exists());
+    }
+
+    /// App with a prompt answered, two commands queued and one run, the same on every machine
+    fn snapshot_app() -> App {
+        let mut app = app_with_queue(&["cargo build", "cargo test"]);
+        app.record_exchange("build and test".to_string(), vec!["cargo build".to_string(), "cargo test".to_string()]);
+        app.shell.history.push(ExecutedCommand {
+            command: "ls".to_string(),
+            exit_code: Some(0),
+            stdout: "Cargo.toml\nsrc\n".to_string(),
+            stderr: String::new(),
+            duration: Duration::from_millis(120),
+            paged: None,
+            suggested: None,
+            interactive: false,
+        });
+        app.shell.curr_path = PathBuf::from("/srv");
+        app
+    }
+
+    #[test]
+    fn normal_mode_snapshots() {
+        let mut app = snapshot_app();
+        assert_eq!(render(&mut app, 80, 20), [
+            "Press q to exit, a to ask AI, s to interact with Shell, v to read responses, m t",
+            "┌Asking AI─────────────────────────────────────────────────────────────────────┐",
+            "│                                                                              │",
+            "└──────────────────────────────────────────────────────────────────────────────┘",
+            "┌Shell─────────────────────────────────────────────────────────────────────────┐",
+            "│/srv > cargo build                                                            │",
+            "└──────────────────────────────────────────────────────────────────────────────┘",
+            "┌Response──────────────────┐┌Output────────────────────────────────────────────┐",
+            "│> build and test          ││$ ls [exit 0, 0.12s]                              │",
+            "│  1. cargo build          ││Cargo.toml                                        │",
+            "│  2. cargo test           ││src                                               │",
+            "│                          ││                                                  │",
+            "│                          ││                                                  │",
+            "└──────────────────────────┘│                                                  │",
+            "┌Queue (2)─────────────────┐│                                                  │",
+            "│> cargo build             ││                                                  │",
+            "│  cargo test              ││                                                  │",
+            "│                          ││                                                  │",
+            "│                          ││                                                  │",
+            "└──────────────────────────┘└──────────────────────────────────────────────────┘",
+        ]);
+
+        // nothing asked or run yet, in a narrow terminal
+        let mut app = App::new("llama3:latest");
+        app.shell.curr_path = PathBuf::from("/home/user/projects/aurish");
+        assert_eq!(render(&mut app, 40, 10), [
+            "Press q to exit, a to ask AI, s to inter",
+            "┌Asking AI─────────────────────────────┐",
+            "└──────────────────────────────────────┘",
+            "┌Shell─────────────────────────────────┐",
+            "└──────────────────────────────────────┘",
+            "┌Response────┐┌Output──────────────────┐",
+            "│            ││                        │",
+            "└────────────┘│                        │",
+            "┌Queue (0)───┐│                        │",
+            "└────────────┘└────────────────────────┘",
+        ]);
+    }
+
+    #[test]
+    fn error_banner_snapshot() {
+        let mut app = snapshot_app();
+        app.error = Some("Ollama answered 404 Not Found: model 'llama9' not found".to_string());
+        assert_eq!(render(&mut app, 60, 14), [
+            "Press q to exit, a to ask AI, s to interact with Shell, v to",
+            "Error: Ollama answered 404 Not Found: model 'llama9' not fou",
+            "┌Asking AI─────────────────────────────────────────────────┐",
+            "│                                                          │",
+            "└──────────────────────────────────────────────────────────┘",
+            "┌Shell─────────────────────────────────────────────────────┐",
+            "│/srv > cargo build                                        │",
+            "└──────────────────────────────────────────────────────────┘",
+            "┌Response───────────┐┌Output───────────────────────────────┐",
+            "│  2. cargo test    ││$ ls [exit 0, 0.12s]                 │",
+            "└───────────────────┘│Cargo.toml                           │",
+            "┌Queue (2)──────────┐│src                                  │",
+            "│> cargo build      ││                                     │",
+            "└───────────────────┘└─────────────────────────────────────┘",
+        ]);
+    }
+
+    #[test]
+    fn plan_under_the_shell_box() {
+        let mut app = snapshot_app().with_dangerous_patterns(vec!["cargo build".to_string()]);
+        app.shell.shell.set_shell_type(ShellType::Bash);
+        app.shell.shell.set_current_dir(std::path::Path::new("/"));
+        app.input_mode = EditMode::Shell;
+        app.toggle_plan();
+        let screen = render(&mut app, 60, 22);
+        assert_eq!(screen[7..15], [
+            "┌Plan──────────────────────────────────────────────────────┐",
+            "│runs: sh -c 'cargo build'                                 │",
+            "│in: /                                                     │",
+            "│env: inherited, with CLICOLOR_FORCE=1 FORCE_COLOR=1 LANG=C│",
+            "│LC_ALL=C                                                  │",
+            "│risk: asked about first, matches the configured pattern   │",
+            "│`cargo build`                                             │",
+            "└──────────────────────────────────────────────────────────┘",
+        ]);
+        assert!(matches!(app.session.events.last(), Some(crate::session::Event::PlanShown { plan, .. }) if plan.command == "cargo build"));
+
+        app.shell.sh_input.replace(Input::default());
+        // under the preview of the edit
+        assert_eq!(render(&mut app, 60, 18)[9], "│Type a command to see what it would do                    │");
+        app.toggle_plan();
+        assert!(render(&mut app, 60, 18)[8].starts_with("┌Response"));
+    }
+
+    #[test]
+    fn editing_snapshots() {
+        let mut app = snapshot_app();
+        app.input_mode = EditMode::Input;
+        app.input = PromptEditor::new("find the ten largest files under this directory and show their sizes".to_string());
+        let mut screen = render(&mut app, 40, 14);
+        // the system prompt and the directory, and so the estimate, depend on the system the tests run on
+        let mut req = app.messages.clone();
+        req.prompt_with(&app.directory_context(), &app.input.value());
+        assert_eq!(screen.remove(1), format!("{:─<39}┐", format!("┌Asking AI ~{}/4096 tokens", req.estimated_tokens())));
+        assert_eq!(screen, [
+            "Press Esc stop asking AI, Enter to send,",
+            "│find the ten largest files under this │",
+            "│ directory and show their sizes       │",
+            "└──────────────────────────────────────┘",
+            "┌Shell─────────────────────────────────┐",
+            "│/srv > cargo build                    │",
+            "└──────────────────────────────────────┘",
+            "┌Response────┐┌Output──────────────────┐",
+            "│test        ││$ ls [exit 0, 0.12s]    │",
+            "└────────────┘│Cargo.toml              │",
+            "┌Queue (2)───┐│src                     │",
+            "│> cargo buil││                        │",
+            "└────────────┘└────────────────────────┘",
+        ]);
+        assert_eq!(cursor(&mut app, 40, 14), (32, 3));
+
+        app.input_mode = EditMode::Shell;
+        let screen = render(&mut app, 60, 12);
+        assert!(screen[0].starts_with("Press Esc stop Shell interaction, Enter to execute"), "{}", screen[0]);
+        assert_eq!(screen[5], "│/srv > cargo build                                        │");
+        assert_eq!(cursor(&mut app, 60, 12), (19, 5));
+
+        let mut app = snapshot_app();
+        app.input_mode = EditMode::Output;
+        app.start_search();
+        app.search_input = Input::new("src".to_string());
+        app.update_search();
+        assert_eq!(render(&mut app, 60, 12), [
+            "Press Enter to keep the search, ↑/↓ previous/next match, Esc",
+            "┌Asking AI─────────────────────────────────────────────────┐",
+            "│                                                          │",
+            "└──────────────────────────────────────────────────────────┘",
+            "┌Shell─────────────────────────────────────────────────────┐",
+            "│/srv > cargo build                                        │",
+            "└──────────────────────────────────────────────────────────┘",
+            "┌Response───────────┐┌Output /src [1/1]────────────────────┐",
+            "│  2. cargo test    ││Cargo.toml                           │",
+            "└───────────────────┘│src                                  │",
+            "┌Queue (2)──────────┐│                                     │",
+            "└───────────────────┘└─────────────────────────────────────┘",
+        ]);
+        assert_eq!(cursor(&mut app, 60, 12), (33, 7));
+    }
+
+    #[tokio::test]
+    async fn model_picker_snapshot() {
+        let mut app = snapshot_app();
+        app.model_picker = Some(ModelPicker {
+            models: Some(Ok(vec!["llama3:latest".to_string(), "mistral:7b".to_string()])),
+            list_state: ListState::default().with_selected(Some(1)),
+            input: Input::default(),
+            handle: tokio::spawn(async {}),
+        });
+        app.input_mode = EditMode::Models;
+        assert_eq!(render(&mut app, 60, 16), [
+            "Press Enter to use the model, Ctrl-S to use it and save it t",
+            "┌Asking AI─────────────────────────────────────────────────┐",
+            "│                                                          │",
+            "└──────────────────────────────────────────────────────────┘",
+            "┌Shell─────────────────────────────────────────────────────┐",
+            "│/srv┌Switch model────────────────────────────────────┐    │",
+            "└────│Active: llama3:latest                           │────┘",
+            "┌Resp│                                                │────┐",
+            "│> bu│  llama3:latest                                 │    │",
+            "│  1.│> mistral:7b                                    │    │",
+            "│  2.└────────────────────────────────────────────────┘    │",
+            "└───────────────────┘│                                     │",
+            "┌Queue (2)──────────┐│                                     │",
+            "│> cargo build      ││                                     │",
+            "│  cargo test       ││                                     │",
+            "└───────────────────┘└─────────────────────────────────────┘",
+        ]);
+    }
+
+    #[test]
+    fn compare_snapshot() {
+        let mut app = snapshot_app();
+        app.comparison = Some(Comparison {
+            prompt: "build and test".to_string(),
+            selected: 1,
+            answers: vec![
+                ModelAnswer { model: "llama3:latest".to_string(), latency: Duration::from_millis(1500), commands: Ok(vec!["cargo build".to_string()]) },
+                ModelAnswer { model: "mistral:7b".to_string(), latency: Duration::from_millis(2250), commands: Ok(vec!["make".to_string(), "make test".to_string()]) },
+            ],
+        });
+        app.input_mode = EditMode::Compare;
+        assert_eq!(render(&mut app, 60, 18), [
+            "Press ↑/↓ to pick an answer, Enter to queue its commands, Es",
+            "┌Asking AI─────────────────────────────────────────────────┐",
+            "│                                                          │",
+            "└──────────────────────────────────────────────────────────┘",
+            "┌Shell─────────────────────────────────────────────────────┐",
+            "│/srv > cargo build                                        │",
+            "└──────────────────────────────────────────────────────────┘",
+            "┌Response: compare──┐┌Output───────────────────────────────┐",
+            "│                   ││$ ls [exit 0, 0.12s]                 │",
+            "│▶ mistral:7b (2.2s)││Cargo.toml                           │",
+            "│  1. make          ││src                                  │",
+            "│  2. make test     ││                                     │",
+            "└───────────────────┘│                                     │",
+            "┌Queue (2)──────────┐│                                     │",
+            "│> cargo build      ││                                     │",
+            "│  cargo test       ││                                     │",
+            "│                   ││                                     │",
+            "└───────────────────┘└─────────────────────────────────────┘",
+        ]);
+    }
+
+    #[test]
+    fn help_snapshot() {
+        let mut app = snapshot_app();
+        app.toggle_help();
+        assert_eq!(render(&mut app, 60, 16), [
+            "Press Esc or ? to close the help  [model: llama3:latest]  [c",
+            "┌Keys──────────────────────────────────────────────────────┐",
+            "│Normal                                                    │",
+            "│  q       quit, asking first if commands are queued or run│",
+            "│  a       ask AI                                          │",
+            "│  s       interact with the Shell                         │",
+            "│  v       read responses                                  │",
+            "│  r       edit the last prompt and ask again              │",
+            "│  Ctrl-R  regenerate the answer to the last prompt, asking│",
+            "│  m       switch model                                    │",
+            "│  c       compare the answers of this model and `compare_m│",
+            "│  ?       show this help                                  │",
+            "│  Esc     cancel the pending request, or stop running the │",
+            "│  ↑       select previous queued command                  │",
+            "└──────────────────────────────────────────────────────────┘",
+            "└───────────────────┘└─────────────────────────────────────┘",
+        ]);
+    }
+
+    #[test]
+    fn favorite_snapshots() {
+        let mut app = snapshot_app();
+        app.start_favorite();
+        app.favorite_draft.as_mut().unwrap().name = Input::new("build".to_string());
+        assert_eq!(render(&mut app, 60, 14), [
+            "Type a name and press Enter to save the favorite, Esc to can",
+            "┌Asking AI─────────────────────────────────────────────────┐",
+            "│                                                          │",
+            "└──────────────────────────────────────────────────────────┘",
+            "┌Save favorite─────────────────────────────────────────────┐",
+            "│cargo build                                               │",
+            "│                                                          │",
+            "│Name: build                                               │",
+            "└──────────────────────────────────────────────────────────┘",
+            "│  2. cargo test    ││Cargo.toml                           │",
+            "└───────────────────┘│src                                  │",
+            "┌Queue (2)──────────┐│                                     │",
+            "│> cargo build      ││                                     │",
+            "└───────────────────┘└─────────────────────────────────────┘",
+        ]);
+        assert_eq!(cursor(&mut app, 60, 14), (12, 7));
+        // `構建` takes four columns
+        app.favorite_draft.as_mut().unwrap().name = Input::new("構建".to_string());
+        assert_eq!(cursor(&mut app, 60, 14), (11, 7));
+
+        let mut app = snapshot_app();
+        app.favorites_list = Some(FavoritesList::new(Ok(vec![
+            Favorite { name: "ports".to_string(), command: "ss -tlnp".to_string(), added: 0 },
+            Favorite { name: "gif".to_string(), command: "ffmpeg -i in.mp4 out.gif".to_string(), added: 0 },
+        ])));
+        app.input_mode = EditMode::Favorites;
+        assert_eq!(render(&mut app, 60, 14), [
+            "Press ↑/↓ select, Enter insert into the Shell box, Del remov",
+            "┌Asking AI─────────────────────────────────────────────────┐",
+            "│                                                          │",
+            "└──────────────────────────────────────────────────────────┘",
+            "┌Shell─────────────────────────────────────────────────────┐",
+            "│/srv┌Favorites───────────────────────────────────────┐    │",
+            "└────│> ports  ss -tlnp                               │────┘",
+            "┌Resp│  gif    ffmpeg -i in.mp4 out.gif               │────┐",
+            "│  1.└────────────────────────────────────────────────┘    │",
+            "│  2. cargo test    ││Cargo.toml                           │",
+            "└───────────────────┘│src                                  │",
+            "┌Queue (2)──────────┐│                                     │",
+            "│> cargo build      ││                                     │",
+            "└───────────────────┘└─────────────────────────────────────┘",
+        ]);
+
+        // names are padded by the columns they take
+        app.favorites_list = Some(FavoritesList::new(Ok(vec![
+            Favorite { name: "ports".to_string(), command: "ss -tlnp".to_string(), added: 0 },
+            Favorite { name: "動画".to_string(), command: "ffmpeg -i in.mp4 out.gif".to_string(), added: 0 },
+        ])));
+        let rows = render(&mut app, 60, 14);
+        assert!(rows[6].contains("│> ports  ss -tlnp "), "{}", rows[6]);
+        assert!(rows[7].contains("│  動画   ffmpeg -i in.mp4 out.gif "), "{}", rows[7]);
+    }
+
+    #[test]
+    fn welcome_panel() {
+        let screen = |app: &mut App| render(app, 120, 30).join("\n");
+        let mut app = App::new("llama3").with_welcome(Some("http://localhost:11434/api/generate".to_string()));
+        let (health_tx, mut health_rx) = mpsc::unbounded_channel();
+        let shown = screen(&mut app);
+        assert!(shown.contains("Welcome to aurish"), "{}", shown);
+        assert!(shown.contains("⠋ checking localhost:11434"));
+        assert!(shown.contains("Endpoint  http://localhost:11434/api/generate"));
+        assert!(shown.contains("a     ask AI"));
+        assert!(shown.contains("?     show this help"));
+
+        health_tx.send(Ok(vec!["llama3:latest".to_string()])).unwrap();
+        app.poll_health(&mut health_rx);
+        assert!(screen(&mut app).contains("✓ connected to localhost:11434, llama3 is installed"));
+        health_tx.send(Ok(vec!["mistral:7b".to_string()])).unwrap();
+        app.poll_health(&mut health_rx);
+        let shown = screen(&mut app);
+        assert!(shown.contains("✗ connected to localhost:11434, but llama3 is not installed"));
+        assert!(shown.contains("Download it with `ollama pull llama3`, or press m to pick another model"));
+        health_tx.send(Err(BackendError::Server { status: reqwest::StatusCode::BAD_GATEWAY, message: String::new() })).unwrap();
+        app.poll_health(&mut health_rx);
+        let shown = screen(&mut app);
+        assert!(shown.contains("✗ could not reach localhost:11434"));
+        assert!(shown.contains("Is Ollama running? Start it with `ollama serve`"));
+        assert!(shown.contains("try `aurish-cli doctor`"));
+
+        // replaced by the Output history once something is asked
+        app.record_exchange("list files".to_string(), vec!["ls".to_string()]);
+        assert!(!screen(&mut app).contains("Welcome to aurish"));
+        assert!(!screen(&mut App::new("llama3")).contains("Welcome to aurish"));
+    }
+
+    #[test]
+    fn tiny_terminals() {
+        let modes = [
+            EditMode::Input, EditMode::Normal, EditMode::Shell, EditMode::Response, EditMode::Confirm, EditMode::Models,
+            EditMode::Help, EditMode::Output, EditMode::Search, EditMode::Compare, EditMode::FavoriteName, EditMode::Favorites,
+            EditMode::Params,
+        ];
+        for mode in modes {
+            for (width, height) in [(1, 1), (10, 5), (20, 8), (80, 3)] {
+                let mut app = snapshot_app();
+                app.error = Some("no answer".to_string());
+                app.input_mode = mode;
+                assert_eq!(render(&mut app, width, height).len(), usize::from(height), "{:?}", mode);
+            }
+        }
+    }
+}
@@ -1,536 +1,1850 @@
-//! Interactive shell for Rust
-//!
-//! Provides an IShell interface to run commands through.
-//! These are the advantages:
-//! - Each command returns an `std::process::Output` type with stdout and stderr captured (while also being logged)
-//! - `cd` commands are remembered, despite each command running sequentially, each in a new true shell (i.e. `sh`)
-
-#![warn(missing_docs)]
-
-use std::env;
-use std::io::{BufRead, BufReader};
-use std::path::{Path, PathBuf};
-use std::process::{Command, ExitStatus, Stdio};
-use std::sync::{Arc, Mutex};
-use std::thread;
-
-/// A module for handling shell initialization errors.
-///
-/// This module defines the `ShellInitError` enum, which represents various errors
-/// that can occur when attempting to initialize a shell. These errors primarily
-/// relate to directory access, including issues with directory existence and permissions.
-///
-/// The `ShellInitError` enum provides a way to handle errors when constructing an
-/// `IShell` instance with `IShell::from_path(...).
-
-
-use crate::error::ShellInitError;
-
-#[cfg(feature = "logging")]
-use log::{error, info, warn};
-
-/// Leech output from stdout/stderr while also storing the resulting output
-macro_rules! leech_output {
-    ($out:ident, $out_buf:ident, $log_method:ident) => {
-        thread::spawn({
-            let output_buffer_clone = Arc::clone($out_buf);
-            move || {
-                if let Some(output) = $out {
-                    let reader = BufReader::new(output);
-                    for line in reader.lines() {
-                        if let Ok(line) = line {
-                            #[cfg(feature = "logging")]
-                            $log_method!("{}", line);
-                            match output_buffer_clone.lock() {
-                                Err(_err) => {
-                                    #[cfg(feature = "logging")]
-                                    error!("Failed to lock {} buffer! {}", stringify!($out), _err);
-                                    return;
-                                }
-                                Ok(mut vec) => {
-                                    vec.push(line);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        })
-    };
-}
-
-/// Representation of the output of a command executed in an IShell.
-///
-/// The `ShellOutput` struct holds the results of a command that was run through a shell,
-/// including the exit code, standard output, and standard error output.
-pub struct ShellOutput {
-    /// An optional exit code returned by the command.
-    /// - If the command executed successfully, this will typically be `0`.
-    /// - If the command failed or was terminated, this will contain a non-zero value.
-    /// - If the command did not return an exit code, this will be `None`.
-    pub code: Option<i32>,
-
-    /// A vector of bytes containing the standard output produced by the command.
-    /// - This field captures any output that the command printed to the standard output stream (if any).
-    pub stdout: Vec<u8>,
-
-    /// A vector of bytes containing the standard error output produced by the command.
-    /// - This field captures any error messages or diagnostics that the command printed to the standard error stream.
-    pub stderr: Vec<u8>,
-}
-
-impl ShellOutput {
-    /// Check if output indicates a command was successful
-    ///
-    /// The check is done by comparing to 0.
-    /// If no output is found, returns false
-    pub fn is_success(&self) -> bool {
-        self.code.unwrap_or(1) == 0
-    }
-}
-
-/// A shell interface with memory
-pub struct IShell {
-    initial_dir: PathBuf,
-    current_dir: Arc<Mutex<PathBuf>>,
-    shell_type: ShellType,
-}
-
-#[derive(Debug)]
-pub enum ShellType {
-    PowerShell,
-    Cmd,
-    Bash,
-    Fish,
-    Zsh,
-    Ksh,
-    Unknown,
-}
-
-fn which_shell() -> ShellType {
-    /// Detect which shell AI interact with.
-    /// On windows, the default shell this function returned is PowerShell.
-    if cfg!(target_os = "windows") {
-        match env::var("PSModulePath") {
-            Ok(_p) => return ShellType::PowerShell,
-            Err(_e) => {
-                match env::var("COMSPEC") {
-                    Ok(_c) => return ShellType::Cmd,
-                    Err(_e) => panic!("Shell Not found!"),
-                }
-            },
-        }
-    } else {
-        match env::var("SHELL") {
-            Ok(shell) => {
-                let shell_lower = shell.to_lowercase();
-                if shell_lower.contains("bash") {
-                    return ShellType::Bash;
-                } else if shell_lower.contains("zsh") {
-                    return ShellType::Zsh;
-                } else if shell_lower.contains("fish") {
-                    return ShellType::Fish;
-                } else if shell_lower.contains("ksh") {
-                    return ShellType::Ksh;
-                } else {
-                    return ShellType::Unknown
-                }
-            },
-            Err(_e) => panic!("Shell Not found!"),
-        }
-    }
-}
-
-impl Default for IShell {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-impl IShell {
-    /// Constructs a new IShell with internal shell's
-    /// directory set to the value of `std::env::current_dir()`.
-    ///
-    /// # Panics
-    ///
-    /// This function will panic due to `std::env::current_dir()` if any of the following is true:
-    /// - Current directory (from where your program is ran) does not exist
-    /// - There are insufficient permissions to access the current directory (from where your program is ran)
-    /// - Directory (from where your program is ran) contains invalid UTF-8
-    pub fn new() -> Self {
-        let current_dir = env::current_dir().expect(
-            "Failed to get current directory; it may not exist or you may not have permissions",
-        );
-
-        IShell {
-            initial_dir: current_dir.clone(),
-            current_dir: Arc::new(Mutex::new(current_dir)),
-            shell_type: which_shell()
-        }
-    }
-
-    /// Constructs a new IShell with internal shell's directory
-    /// set to the value of
-    ///
-    /// <current_dir> / `initial_dir`
-    ///
-    /// if it exists.
-    /// Otherwise, initial_dir is treated as a full path
-    pub fn from_path(initial_dir: impl AsRef<Path>) -> Result<Self, ShellInitError> {
-        let initial_dir = initial_dir.as_ref();
-
-        let current_dir = env::current_dir().expect(
-            "Failed to get current directory; it may not exist or you may not have permissions.",
-        );
-
-        match Self::determine_new_directory(&current_dir, initial_dir) {
-            Some(new_dir) => Ok(IShell {
-                initial_dir: new_dir.clone(),
-                current_dir: Arc::new(Mutex::new(new_dir)),
-                shell_type: which_shell(),
-            }),
-            None => Err(ShellInitError::DirectoryError(format!(
-                "Couldn't open shell at either of {:#?} or {:#?}",
-                initial_dir,
-                current_dir.join(initial_dir)
-            ))),
-        }
-    }
-
-    /// Runs a command through IShell within its `current_dir`.
-    ///
-    /// Any `cd` command will not be _actually_ ran. Instead, inner directory of IShell (`current_dir`) will change
-    /// accordingly. If `cd` is aliased to something else, (i.e. `changedir`), and you use this alias instead of `cd`,
-    /// then IShell won't understand that you wanted it to change directory.
-    pub fn run_command(&self, command: &str) -> ShellOutput {
-        #[cfg(feature = "logging")]
-        info!("Running: `{}`", command);
-
-        if let Some(stripped_command) = command.strip_prefix("cd") {
-            let new_dir = stripped_command.trim();
-            let mut current_dir = self.current_dir.lock().unwrap();
-
-            match Self::determine_new_directory(&*current_dir, new_dir) {
-                Some(new_dir) => {
-                    *current_dir = new_dir;
-                    return self.create_output(Some(0), Vec::new(), Vec::new());
-                }
-                None => {
-                    #[cfg(feature = "logging")]
-                    {
-                        error!("Failed to change directory to: {}", new_dir);
-                        error!("Current directory: '{}'", current_dir.display());
-                    }
-                    return self.create_output(
-                        Some(1),
-                        Vec::new(),
-                        Vec::from("Specified directory does not exist!"),
-                    );
-                }
-            }
-        }
-
-        let child_process = self.spawn_process(command);
-        match child_process {
-            Ok(mut process) => {
-                let (stdout_buffer, stderr_buffer) = (
-                    Arc::new(Mutex::new(Vec::new())),
-                    Arc::new(Mutex::new(Vec::new())),
-                );
-
-                let (stdout_handle, stderr_handle) = self.spawn_output_threads(
-                    process.stdout.take(),
-                    process.stderr.take(),
-                    &stdout_buffer,
-                    &stderr_buffer,
-                );
-
-                let status = process.wait().unwrap_or_else(|_err| {
-                    #[cfg(feature = "logging")]
-                    error!("Failed to wait for process: {}", _err);
-                    ExitStatus::default()
-                });
-
-                if let Err(_err) = stdout_handle.join() {
-                    #[cfg(feature = "logging")]
-                    error!("Failed to join stdout thread: {:?}", _err);
-                }
-                if let Err(_err) = stderr_handle.join() {
-                    #[cfg(feature = "logging")]
-                    error!("Failed to join stderr thread: {:?}", _err);
-                }
-
-                let stdout = self.collect_output(&stdout_buffer);
-                let stderr = self.collect_output(&stderr_buffer);
-
-                ShellOutput {
-                    code: status.code(),
-                    stdout,
-                    stderr,
-                }
-            }
-            Err(e) => {
-                #[cfg(feature = "logging")]
-                error!("Couldn't spawn child process! {}", e);
-
-                self.create_output(Some(-1), Vec::new(), Vec::from(format!("Error: {}", e)))
-            }
-        }
-    }
-
-    /// Forget current directory and go back to the directory initially specified.
-    pub fn forget_current_directory(&self) {
-        let mut current_dir = self.current_dir.lock().unwrap();
-        *current_dir = self.initial_dir.clone();
-    }
-
-    fn create_output(&self, code: Option<i32>, stdout: Vec<u8>, stderr: Vec<u8>) -> ShellOutput {
-        ShellOutput {
-            code,
-            stdout,
-            stderr,
-        }
-    }
-
-    fn spawn_process(&self, command: &str) -> std::io::Result<std::process::Child> {
-        let current_dir = self.current_dir.lock().unwrap().clone();
-        let (shell, arg) = match self.shell_type {
-            ShellType::PowerShell => {
-                ("powershell", "-Command")
-            },
-            ShellType::Cmd => {
-                ("cmd", "/C")
-            },
-            ShellType::Bash => {
-                ("sh", "-c")
-            },
-            ShellType::Fish => {
-                ("fish", "-c")
-            },
-            ShellType::Zsh => {
-                ("zsh", "-c")
-            },
-            ShellType::Ksh => {
-                ("ksh", "-c")
-            }
-            ShellType::Unknown => {
-                panic!("Unknown Shell type")
-            }
-        };
-
-        Command::new(shell)
-            .arg(arg)
-            .arg(command)
-            .current_dir(current_dir)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-    }
-
-    fn spawn_output_threads(
-        &self,
-        stdout: Option<std::process::ChildStdout>,
-        stderr: Option<std::process::ChildStderr>,
-        stdout_buffer: &Arc<Mutex<Vec<String>>>,
-        stderr_buffer: &Arc<Mutex<Vec<String>>>,
-    ) -> (thread::JoinHandle<()>, thread::JoinHandle<()>) {
-        let stdout_handle = leech_output!(stdout, stdout_buffer, info);
-        let stderr_handle = leech_output!(stderr, stderr_buffer, warn);
-
-        (stdout_handle, stderr_handle)
-    }
-
-    fn collect_output(&self, buffer: &Arc<Mutex<Vec<String>>>) -> Vec<u8> {
-        match buffer.lock() {
-            Ok(buffer) => buffer.join("\n").into_bytes(),
-            Err(_err) => {
-                #[cfg(feature = "logging")]
-                error!("Couldn't lock buffer! {}", _err);
-                // Need to return SOMETHING here.
-                Vec::new()
-            }
-        }
-    }
-
-    /// Method to quickly check if given path is a valid directory
-    fn is_valid_directory(path: &Path) -> bool {
-        path.exists() && path.is_dir()
-    }
-
-    /// Method to determine the new directory
-    /// Checks if `current_dir`/`new_dir` is a valid dir (and returns it if it is),
-    /// if it isn't - checks if `new_dir` is a valid dir (and returns it if it is);
-    /// if it isn't - returns None
-    fn determine_new_directory<U: AsRef<Path>, T: AsRef<Path>>(
-        current_dir: U,
-        new_dir: T,
-    ) -> Option<PathBuf> {
-        let new_dir = new_dir.as_ref();
-        let current_dir = current_dir.as_ref();
-
-        // Perhaps the `new_dir` is relative to `current_dir`?
-        let wanted_dir = current_dir.join(new_dir);
-        if Self::is_valid_directory(&wanted_dir) {
-            return Some(wanted_dir.to_path_buf());
-        }
-
-        // Maybe `new_dir` wasn't relative?
-        if let Some(sanitized_dir) = Self::sanitize_path(new_dir) {
-            if Self::is_valid_directory(&sanitized_dir) {
-                return Some(sanitized_dir);
-            } else {
-                #[cfg(feature = "logging")]
-                warn!(
-                    "Neither the combined path {:#?} nor the sanitized path {:#?} is a valid directory.",
-                    wanted_dir, sanitized_dir
-                );
-            }
-        }
-
-        // I guess `new_dir` doesn't exist...
-        None
-    }
-
-    /// Expand tilde
-    /// Inspired by https://github.com/splurf/simple-expand-tilde/blob/master/src/lib.rs
-    fn sanitize_path(path: impl AsRef<Path>) -> Option<PathBuf> {
-        let resolved_path = path.as_ref();
-
-        if !resolved_path.starts_with("~") {
-            return Some(resolved_path.to_path_buf());
-        }
-        if resolved_path == Path::new("~") {
-            return dirs::home_dir();
-        }
-
-        dirs::home_dir().map(|mut home_dir| {
-            if home_dir == Path::new("/") {
-                // For when running as root
-                resolved_path.strip_prefix("~").unwrap().to_path_buf()
-            } else {
-                home_dir.push(resolved_path.strip_prefix("~/").unwrap());
-                home_dir
-            }
-        })
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn true_command() {
-        let shell = IShell::new();
-
-        let result = shell.run_command("true");
-        assert!(result.is_success());
-    }
-
-    #[test]
-    fn false_command() {
-        let shell = IShell::new();
-
-        let result = shell.run_command("false");
-        assert!(!result.is_success());
-    }
-
-    #[test]
-    fn echo_command() {
-        // Checking stdout capture
-        let shell = IShell::new();
-
-        let result = shell.run_command("echo \"Hello, World!\"");
-        let stdout_res = String::from_utf8(result.stdout).expect("Stdout contained invalid UTF-8!");
-        assert_eq!(stdout_res, "Hello, World!");
-    }
-
-    #[test]
-    fn dir_memory() {
-        // Check for whether CD is remembered
-
-        let shell = IShell::new();
-
-        let unique_dir_1 = format!("test_{}", rand::random::<u32>());
-        let unique_dir_2 = format!("test2_{}", rand::random::<u32>());
-
-        shell.run_command(&format!("mkdir {}", unique_dir_1));
-        shell.run_command(&format!("cd {}", unique_dir_1));
-        shell.run_command(&format!("mkdir {}", unique_dir_2));
-
-        let result = shell.run_command("ls");
-        let stdout_res = String::from_utf8(result.stdout).expect("Stdout contained invalid UTF-8!");
-        assert_eq!(stdout_res.trim(), unique_dir_2);
-
-        shell.run_command("cd ..");
-        shell.run_command(&format!("rm -r {}", unique_dir_1));
-    }
-
-    #[test]
-    fn forget_current_dir() {
-        let shell = IShell::new();
-
-        let result = shell.run_command("echo $PWD");
-        let pwd = String::from_utf8(result.stdout).expect("Stdout contained invalid UTF-8!");
-
-        let unique_dir = format!("test_{}", rand::random::<u32>());
-
-        shell.run_command(&format!("mkdir {}", unique_dir));
-        shell.run_command(&format!("cd {}", unique_dir));
-        shell.forget_current_directory();
-
-        let result = shell.run_command("echo $PWD");
-        let forgotten_pwd =
-            String::from_utf8(result.stdout).expect("Stdout contained invalid UTF-8!");
-
-        assert_eq!(pwd, forgotten_pwd);
-
-        shell.run_command(&format!("rm -r {}", unique_dir));
-    }
-
-    #[test]
-    fn dir_doesnt_exist() {
-        let shell = IShell::new();
-
-        let current_dir = shell.current_dir.lock().unwrap().clone();
-        let res = shell.run_command("cd directory_that_doesnt_exist");
-        let next_dir = shell.current_dir.lock().unwrap().clone();
-
-        assert!(!res.is_success());
-        assert_eq!(current_dir, next_dir);
-    }
-
-    #[test]
-    fn relative_construct() {
-        let main_shell = IShell::new();
-        main_shell.run_command("cd target");
-        let main_result = main_shell.run_command("ls");
-        assert!(main_result.is_success());
-
-        let target_shell = IShell::from_path("target").unwrap();
-        let target_result = target_shell.run_command("ls");
-
-        let target_result =
-            String::from_utf8(target_result.stdout).expect("Stdout contained invalid UTF-8!");
-        let main_result =
-            String::from_utf8(main_result.stdout).expect("Stdout contained invalid UTF-8!");
-
-        assert_eq!(target_result, main_result);
-    }
-
-    #[test]
-    fn tilda_init() {
-        let desktop_shell = IShell::from_path("~").unwrap();
-        let shell = IShell::new();
-
-        shell.run_command("cd ~");
-        let res = shell.run_command("ls");
-        let desktop_res = desktop_shell.run_command("ls");
-
-        let res = String::from_utf8(res.stdout).expect("Stdout contained invalid UTF-8!");
-        let desktop_res =
-            String::from_utf8(desktop_res.stdout).expect("Stdout contained invalid UTF-8!");
-
-        assert_eq!(res, desktop_res);
-    }
-}
+//! Interactive shell for Rust
+//!
+//! Provides an IShell interface to run commands through.
+//! These are the advantages:
+//! - Each command returns an `std::process::Output` type with stdout and stderr captured (while also being logged)
+//! - `cd` commands are remembered, despite each command running sequentially, each in a new true shell (i.e. `sh`)
+
+#![warn(missing_docs)]
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::env;
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// A module for handling shell initialization errors.
+//
+// This module defines the `ShellInitError` enum, which represents various errors
+// that can occur when attempting to initialize a shell. These errors primarily
+// relate to directory access, including issues with directory existence and permissions.
+//
+// The `ShellInitError` enum provides a way to handle errors when constructing an
+// `IShell` instance with `IShell::from_path(...).
+use crate::ansi;
+use crate::error::{ShellInitError, ShellTypeError};
+use crate::interactive;
+use crate::risk;
+use crate::shell_history::ShellHistory;
+use serde::{Deserialize, Serialize};
+
+use log::{error, info, warn};
+
+/// Leech output from stdout/stderr while also storing the resulting output
+///
+/// Expands to a `(DrainJob, Receiver<()>)` pair: the job is handed to one of the
+/// [`OutputReaders`] threads and the receiver fires once the pipe is drained.
+/// An optional fourth argument caps the buffer to its last lines.
+macro_rules! leech_output {
+    ($out:ident, $out_buf:ident, $log_method:ident) => {
+        leech_output!($out, $out_buf, $log_method, usize::MAX)
+    };
+    ($out:ident, $out_buf:ident, $log_method:ident, $max_lines:expr) => {{
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let output_buffer_clone = Arc::clone($out_buf);
+        let job: DrainJob = Box::new(move || {
+            if let Some(output) = $out {
+                let reader = BufReader::new(output);
+                for line in reader.lines() {
+                    if let Ok(line) = line {
+                        #[cfg(feature = "logging")]
+                        $log_method!("{}", line);
+                        match output_buffer_clone.lock() {
+                            Err(_err) => {
+                                error!("Failed to lock {} buffer! {}", stringify!($out), _err);
+                                break;
+                            }
+                            Ok(mut vec) => {
+                                if vec.len() >= $max_lines {
+                                    vec.pop_front();
+                                }
+                                vec.push_back(line);
+                            }
+                        }
+                    }
+                }
+            }
+            let _ = done_tx.send(());
+        });
+        (job, done_rx)
+    }};
+}
+
+/// Work handed to one of the persistent output reader threads
+type DrainJob = Box<dyn FnOnce() + Send + 'static>;
+
+/// Long-lived threads that drain the pipes of spawned commands
+///
+/// Every pipe gets a reader of its own: an idle reader is reused when there is one,
+/// otherwise the pool grows by one thread. Commands run concurrently on a shared `IShell`
+/// therefore never wait for each other's output, and a pipe held open by a stray
+/// grandchild only ties up its own reader.
+/// Idle readers exit once the owning `IShell` is dropped.
+struct OutputReaders {
+    idle: Arc<Mutex<Vec<Sender<DrainJob>>>>,
+}
+
+impl OutputReaders {
+    fn new() -> Self {
+        OutputReaders {
+            idle: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    fn spawn_reader() -> Sender<DrainJob> {
+        let (job_tx, job_rx) = mpsc::channel::<DrainJob>();
+        thread::Builder::new()
+            .name("ishell-reader".to_string())
+            .spawn(move || {
+                for job in job_rx {
+                    job();
+                }
+            })
+            .expect("Failed to spawn output reader thread");
+        job_tx
+    }
+
+    /// Hand one pipe to an idle reader, or to a new one if every reader is busy
+    fn run(&self, job: DrainJob) {
+        let popped = match self.idle.lock() {
+            Ok(mut idle) => idle.pop(),
+            Err(poisoned) => poisoned.into_inner().pop(),
+        };
+        let reader = popped.unwrap_or_else(Self::spawn_reader);
+        // The reader goes back to the idle list once the pipe is drained. Only a weak
+        // handle is kept, so dropping the pool ends the readers; a reader whose job
+        // panicked drops `back` while unwinding and is never reused.
+        let idle = Arc::downgrade(&self.idle);
+        let back = reader.clone();
+        let wrapped: DrainJob = Box::new(move || {
+            job();
+            if let Some(idle) = idle.upgrade() {
+                match idle.lock() {
+                    Ok(mut idle) => idle.push(back),
+                    Err(poisoned) => poisoned.into_inner().push(back),
+                }
+            }
+        });
+        if reader.send(wrapped).is_err() {
+            error!("Output reader went away before taking its pipe");
+        }
+    }
+
+    /// Drain both pipes of one command, each on a reader of its own
+    fn drain(&self, stdout_job: DrainJob, stderr_job: DrainJob) {
+        self.run(stdout_job);
+        self.run(stderr_job);
+    }
+}
+
+/// Representation of the output of a command executed in an IShell.
+///
+/// The `ShellOutput` struct holds the results of a command that was run through a shell,
+/// including the exit code, standard output, and standard error output.
+pub struct ShellOutput {
+    /// An optional exit code returned by the command.
+    /// - If the command executed successfully, this will typically be `0`.
+    /// - If the command failed or was terminated, this will contain a non-zero value.
+    /// - If the command did not return an exit code, this will be `None`.
+    pub code: Option<i32>,
+
+    /// A vector of bytes containing the standard output produced by the command.
+    /// - This field captures any output that the command printed to the standard output stream (if any).
+    pub stdout: Vec<u8>,
+
+    /// A vector of bytes containing the standard error output produced by the command.
+    /// - This field captures any error messages or diagnostics that the command printed to the standard error stream.
+    pub stderr: Vec<u8>,
+}
+
+impl ShellOutput {
+    /// Check if output indicates a command was successful
+    ///
+    /// The check is done by comparing to 0.
+    /// If no output is found, returns false
+    pub fn is_success(&self) -> bool {
+        self.code.unwrap_or(1) == 0
+    }
+
+    /// Standard output as a string, ANSI escape sequences left untouched.
+    /// Invalid UTF-8 is replaced lossily.
+    pub fn stdout_str(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).into_owned()
+    }
+
+    /// Standard error as a string, ANSI escape sequences left untouched.
+    /// Invalid UTF-8 is replaced lossily.
+    pub fn stderr_str(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).into_owned()
+    }
+
+    /// Standard output as a string with all ANSI escape sequences stripped
+    pub fn stdout_str_plain(&self) -> String {
+        ansi::strip(&self.stdout_str())
+    }
+
+    /// Standard error as a string with all ANSI escape sequences stripped
+    pub fn stderr_str_plain(&self) -> String {
+        ansi::strip(&self.stderr_str())
+    }
+}
+
+/// What an IShell would do with a command, worked out without running it.
+///
+/// Returned by `IShell::explain_command`, and kept in the session transcript when shown.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionPlan {
+    /// The command as given
+    pub command: String,
+    /// Directory the command runs in, or a `cd` starts from
+    pub directory: PathBuf,
+    /// What is done with the command
+    pub action: PlannedAction,
+    /// Variables set for the command on top of the inherited environment, empty for a `cd`
+    pub env: BTreeMap<String, String>,
+    /// The command starts without the inherited environment, see `IShell::with_hermetic_env`
+    pub hermetic: bool,
+    /// Why the command is asked about before it runs, see `risk::danger_reason`
+    pub danger: Option<String>,
+    /// The command starts with a `sudo` that may ask for a password, see `interactive`
+    pub sudo: bool,
+}
+
+/// What an IShell does with a command, see `ExecutionPlan`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PlannedAction {
+    /// A `cd`, which runs nothing: the IShell moves to `to`, or stays if it is `None`
+    /// because there is no such directory
+    ChangeDirectory {
+        /// The directory moved to
+        to: Option<PathBuf>,
+    },
+    /// A `cd` refused because `to` is outside the sandbox root, see `IShell::set_sandbox_root`
+    LeavesSandbox {
+        /// The directory the `cd` leads to
+        to: PathBuf,
+        /// The sandbox root
+        root: PathBuf,
+    },
+    /// The shell is started as `program` with `args`
+    Spawn {
+        /// Shell binary, like `sh`
+        program: String,
+        /// Its flag and the command
+        args: Vec<String>,
+        /// Started as a job for a trailing `&`, the `&` left out of `args`
+        background: bool,
+    },
+}
+
+impl ExecutionPlan {
+    /// The plan in a few lines of plain text, for showing it
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        match &self.action {
+            PlannedAction::ChangeDirectory { to: Some(to) } => {
+                lines.push(format!("cd: moves to {}, nothing is run", to.display()));
+            },
+            PlannedAction::ChangeDirectory { to: None } => {
+                lines.push(format!("cd: no such directory from {}, nothing is run", self.directory.display()));
+            },
+            PlannedAction::LeavesSandbox { to, root } => {
+                lines.push(format!("cd: refused, {} is outside the sandbox {}", to.display(), root.display()));
+            },
+            PlannedAction::Spawn { program, args, background } => {
+                let args: Vec<String> = args.iter().map(|arg| quote(arg)).collect();
+                let job = if *background { ", as a background job" } else { "" };
+                lines.push(format!("runs: {} {}{}", program, args.join(" "), job));
+                lines.push(format!("in: {}", self.directory.display()));
+                let env: Vec<String> = self.env.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+                match (self.hermetic, env.is_empty()) {
+                    (true, true) => lines.push("env: empty".to_string()),
+                    (true, false) => lines.push(format!("env: only {}", env.join(" "))),
+                    (false, true) => lines.push("env: inherited".to_string()),
+                    (false, false) => lines.push(format!("env: inherited, with {}", env.join(" "))),
+                }
+            },
+        }
+        match &self.danger {
+            Some(reason) => lines.push(format!("risk: asked about first, {}", reason)),
+            None => lines.push("risk: nothing dangerous found".to_string()),
+        }
+        if self.sudo {
+            lines.push("sudo: may ask for a password".to_string());
+        }
+        lines
+    }
+}
+
+/// `arg` in single quotes if a shell would split it or it is empty
+fn quote(arg: &str) -> String {
+    if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || "'\"$`\\;&|<>()*?".contains(c)) {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    } else {
+        arg.to_string()
+    }
+}
+
+/// Handle to a command running in the background of an IShell.
+///
+/// Created by `IShell::spawn_job`. Handles are cheap to clone and all clones refer to the
+/// same process. Output is captured continuously and can be collected piecewise with
+/// `take_output_so_far`.
+#[derive(Clone)]
+pub struct Job {
+    inner: Arc<JobInner>,
+}
+
+struct JobInner {
+    id: usize,
+    command: String,
+    child: Mutex<Child>,
+    stdout: Arc<Mutex<VecDeque<String>>>,
+    stderr: Arc<Mutex<VecDeque<String>>>,
+    detached: AtomicBool,
+}
+
+impl Job {
+    /// Identifier of this job, unique within the IShell that spawned it
+    pub fn id(&self) -> usize {
+        self.inner.id
+    }
+
+    /// Command line this job was started with
+    pub fn command(&self) -> &str {
+        &self.inner.command
+    }
+
+    /// Check whether the process is still alive.
+    ///
+    /// A process that can no longer be queried is reported as not running.
+    pub fn is_running(&self) -> bool {
+        match self.inner.child.lock() {
+            Ok(mut child) => matches!(child.try_wait(), Ok(None)),
+            Err(_err) => false,
+        }
+    }
+
+    /// Kill the process and wait for it to exit.
+    /// On unix everything in the job's process group is killed, also when the shell running
+    /// the command already exited and left e.g. `cmd &` behind.
+    /// Killing a job that already finished is not an error.
+    pub fn kill(&self) -> std::io::Result<()> {
+        let mut child = match self.inner.child.lock() {
+            Ok(child) => child,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        #[cfg(unix)]
+        {
+            // SAFETY: plain syscall; the negative pid targets the job's own process group
+            if unsafe { libc::kill(-(child.id() as i32), libc::SIGKILL) } == -1 {
+                let err = std::io::Error::last_os_error();
+                // nothing left in the group
+                if err.raw_os_error() != Some(libc::ESRCH) {
+                    return Err(err);
+                }
+            }
+            child.wait()?;
+        }
+        #[cfg(not(unix))]
+        if child.try_wait()?.is_none() {
+            child.kill()?;
+            child.wait()?;
+        }
+        Ok(())
+    }
+
+    /// Keep this job running when the owning IShell is dropped.
+    pub fn detach(&self) {
+        self.inner.detached.store(true, Ordering::SeqCst);
+    }
+
+    /// Check whether this job survives its IShell
+    pub fn is_detached(&self) -> bool {
+        self.inner.detached.load(Ordering::SeqCst)
+    }
+
+    /// Collect the output captured since the last call.
+    ///
+    /// Only the last `JOB_OUTPUT_LINES` lines of each stream are kept in between calls.
+    /// `code` stays `None` while the job is running and holds the exit code once it finished.
+    pub fn take_output_so_far(&self) -> ShellOutput {
+        let code = match self.inner.child.lock() {
+            Ok(mut child) => child.try_wait().ok().flatten().and_then(|status| status.code()),
+            Err(_err) => None,
+        };
+        let take = |buffer: &Arc<Mutex<VecDeque<String>>>| match buffer.lock() {
+            Ok(mut lines) => lines.drain(..).collect::<Vec<String>>().join("\n").into_bytes(),
+            Err(_err) => Vec::new(),
+        };
+
+        ShellOutput {
+            code,
+            stdout: take(&self.inner.stdout),
+            stderr: take(&self.inner.stderr),
+        }
+    }
+}
+
+/// A shell interface with memory
+pub struct IShell {
+    initial_dir: PathBuf,
+    current_dir: Arc<Mutex<PathBuf>>,
+    /// Detected by `detect`, unless changed with `set_shell_type`
+    shell_type: Mutex<ShellType>,
+    readers: OutputReaders,
+    jobs: Mutex<Vec<Job>>,
+    next_job_id: AtomicUsize,
+    /// Process ids of the commands `run_command` is waiting for
+    foreground: Mutex<Vec<u32>>,
+    force_color: bool,
+    /// Run commands in their own process group, see `with_process_groups`
+    process_groups: bool,
+    env: Mutex<HashMap<String, String>>,
+    hermetic: bool,
+    /// Keep the caller's locale and `TERM`, see `set_inherit_locale`
+    inherit_locale: AtomicBool,
+    /// Where commands that succeed are recorded, see `set_history`
+    history: Mutex<Option<ShellHistory>>,
+    /// Canonical directory `cd` cannot leave, see `set_sandbox_root`
+    sandbox_root: Mutex<Option<PathBuf>>,
+}
+
+/// Lines of stdout and of stderr a job keeps until `Job::take_output_so_far` collects them
+pub const JOB_OUTPUT_LINES: usize = 1000;
+
+/// Variable holding the sandbox root for the commands, see `IShell::set_sandbox_root`
+pub const SANDBOX_VAR: &str = "AURISH_SANDBOX";
+
+/// Whether `dir` is the canonical `root` or below it once resolved, a directory that can't
+/// be resolved counting as outside
+fn inside(dir: &Path, root: &Path) -> bool {
+    dir.canonicalize().is_ok_and(|dir| dir.starts_with(root))
+}
+
+/// Shell flavour an IShell spawns its commands through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellType {
+    /// Windows PowerShell, the default on windows
+    PowerShell,
+    /// Windows `cmd.exe`
+    Cmd,
+    /// Bash, commands are run with `sh`, also what `sh` is parsed to
+    Bash,
+    /// Fish shell
+    Fish,
+    /// Z shell
+    Zsh,
+    /// Korn shell
+    Ksh,
+    /// Shell could not be recognised, commands are run with `sh`
+    Unknown,
+}
+
+impl ShellType {
+    /// PowerShell or `cmd.exe`, which have their own commands, quoting and `\` paths
+    pub fn is_windows(self) -> bool {
+        matches!(self, ShellType::PowerShell | ShellType::Cmd)
+    }
+
+    /// A Unix shell, with `ls`, `$VAR` and backslash escapes
+    pub fn is_posix(self) -> bool {
+        !self.is_windows()
+    }
+
+    /// Every shell that can be chosen by name, `Unknown` is only ever detected
+    pub const KNOWN: [ShellType; 6] = [
+        ShellType::PowerShell,
+        ShellType::Cmd,
+        ShellType::Bash,
+        ShellType::Fish,
+        ShellType::Zsh,
+        ShellType::Ksh,
+    ];
+
+    /// Other names `from_str` accepts, lowercase
+    fn aliases(self) -> &'static [&'static str] {
+        match self {
+            ShellType::PowerShell => &["pwsh"],
+            ShellType::Bash => &["sh"],
+            _ => &[],
+        }
+    }
+}
+
+impl fmt::Display for ShellType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ShellType::PowerShell => "PowerShell",
+            ShellType::Cmd => "Cmd",
+            ShellType::Bash => "Bash",
+            ShellType::Fish => "Fish",
+            ShellType::Zsh => "Zsh",
+            ShellType::Ksh => "Ksh",
+            ShellType::Unknown => "POSIX sh",
+        })
+    }
+}
+
+impl FromStr for ShellType {
+    type Err = ShellTypeError;
+
+    /// Parse the name of a shell, in any case, like `bash`, `PowerShell` or `pwsh`
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let lower = name.trim().to_lowercase();
+        ShellType::KNOWN
+            .into_iter()
+            .find(|shell| shell.to_string().to_lowercase() == lower || shell.aliases().contains(&lower.as_str()))
+            .ok_or_else(|| ShellTypeError::Unknown(name.trim().to_string()))
+    }
+}
+
+/// Strip a trailing `&` that asks for a command to be run as a background job.
+///
+/// Returns the command without the marker, or `None` if it should run in the foreground.
+/// A trailing `&&` is not treated as a background marker.
+pub fn strip_background(command: &str) -> Option<&str> {
+    let command = command.trim_end();
+    let stripped = command.strip_suffix('&')?;
+    if stripped.ends_with('&') || stripped.trim().is_empty() {
+        None
+    } else {
+        Some(stripped.trim_end())
+    }
+}
+
+/// The directory of a `cd` command, which an IShell follows itself instead of running it
+fn cd_argument(command: &str) -> Option<&str> {
+    command.strip_prefix("cd").map(str::trim)
+}
+
+/// Detect which shell AI interact with, from the name of `$SHELL` like `/usr/bin/zsh`.
+/// On windows, the default shell this function returned is PowerShell.
+///
+/// # Panics
+///
+/// On windows, if neither `PSModulePath` nor `COMSPEC` is set.
+pub fn detect() -> ShellType {
+    if cfg!(target_os = "windows") {
+        match env::var("PSModulePath") {
+            Ok(_p) => ShellType::PowerShell,
+            Err(_e) => {
+                match env::var("COMSPEC") {
+                    Ok(_c) => ShellType::Cmd,
+                    Err(_e) => panic!("Shell Not found!"),
+                }
+            },
+        }
+    } else {
+        env::var("SHELL")
+            .ok()
+            .and_then(|shell| Path::new(&shell).file_name()?.to_str()?.parse().ok())
+            .unwrap_or(ShellType::Unknown)
+    }
+}
+
+impl Default for IShell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for IShell {
+    /// Kill every job that was not detached
+    fn drop(&mut self) {
+        self.kill_jobs();
+    }
+}
+
+impl IShell {
+    /// Constructs a new IShell with internal shell's
+    /// directory set to the value of `std::env::current_dir()`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic due to `std::env::current_dir()` if any of the following is true:
+    /// - Current directory (from where your program is ran) does not exist
+    /// - There are insufficient permissions to access the current directory (from where your program is ran)
+    /// - Directory (from where your program is ran) contains invalid UTF-8
+    pub fn new() -> Self {
+        let current_dir = env::current_dir().expect(
+            "Failed to get current directory; it may not exist or you may not have permissions",
+        );
+
+        IShell {
+            initial_dir: current_dir.clone(),
+            current_dir: Arc::new(Mutex::new(current_dir)),
+            shell_type: Mutex::new(detect()),
+            readers: OutputReaders::new(),
+            jobs: Mutex::new(Vec::new()),
+            next_job_id: AtomicUsize::new(1),
+            foreground: Mutex::new(Vec::new()),
+            force_color: false,
+            process_groups: false,
+            env: Mutex::new(HashMap::new()),
+            hermetic: false,
+            inherit_locale: AtomicBool::new(false),
+            history: Mutex::new(None),
+            sandbox_root: Mutex::new(None),
+        }
+    }
+
+    /// Constructs a new IShell with internal shell's directory
+    /// set to the value of
+    ///
+    /// <current_dir> / `initial_dir`
+    ///
+    /// if it exists.
+    /// Otherwise, initial_dir is treated as a full path
+    pub fn from_path(initial_dir: impl AsRef<Path>) -> Result<Self, ShellInitError> {
+        let initial_dir = initial_dir.as_ref();
+
+        let current_dir = env::current_dir().expect(
+            "Failed to get current directory; it may not exist or you may not have permissions.",
+        );
+
+        match Self::determine_new_directory(&current_dir, initial_dir) {
+            Some(new_dir) => Ok(IShell {
+                initial_dir: new_dir.clone(),
+                current_dir: Arc::new(Mutex::new(new_dir)),
+                shell_type: Mutex::new(detect()),
+                readers: OutputReaders::new(),
+                jobs: Mutex::new(Vec::new()),
+                next_job_id: AtomicUsize::new(1),
+                foreground: Mutex::new(Vec::new()),
+                force_color: false,
+                process_groups: false,
+                env: Mutex::new(HashMap::new()),
+                hermetic: false,
+                inherit_locale: AtomicBool::new(false),
+                history: Mutex::new(None),
+                sandbox_root: Mutex::new(None),
+            }),
+            None => Err(ShellInitError::DirectoryError(format!(
+                "Couldn't open shell at either of {:#?} or {:#?}",
+                initial_dir,
+                current_dir.join(initial_dir)
+            ))),
+        }
+    }
+
+    /// Ask spawned commands to keep colouring their output even though it goes into a pipe.
+    ///
+    /// Sets `CLICOLOR_FORCE` and `FORCE_COLOR` for every spawned command, which most tools
+    /// (ls, git, cargo, npm, ...) honour. Off by default.
+    pub fn with_force_color(mut self, enabled: bool) -> Self {
+        self.force_color = enabled;
+        self
+    }
+
+    /// Run every command in its own process group, so `kill_all` also stops the processes
+    /// it started. Off by default, since such commands do not get Ctrl-C from the terminal.
+    pub fn with_process_groups(mut self, enabled: bool) -> Self {
+        self.process_groups = enabled;
+        self
+    }
+
+    /// Start every command with an empty environment instead of inheriting the caller's.
+    ///
+    /// Only variables set through `set_env` or passed to `run_command_with_env` are visible,
+    /// next to the ones IShell sets itself: `LC_ALL=C`, `LANG=C` and `TERM=dumb` unless
+    /// `set_inherit_locale` is on, `CLICOLOR_FORCE` and `FORCE_COLOR` with `with_force_color`,
+    /// and `AURISH_SANDBOX` with a sandbox root. This makes command behaviour reproducible
+    /// across machines. Off by default.
+    pub fn with_hermetic_env(mut self, enabled: bool) -> Self {
+        self.hermetic = enabled;
+        self
+    }
+
+    /// Let the following commands inherit the caller's locale and `TERM`.
+    ///
+    /// By default commands run with `LC_ALL=C` and `LANG=C`, so the output of tools like `ls`,
+    /// `df` or `date` has the same format everywhere and the commands parsing it work, and
+    /// with `TERM=dumb`, so tools don't write terminal control sequences into the captured
+    /// output. `TERM` is kept with `with_force_color`. Variables set with `set_env` or passed
+    /// to `run_command_with_env` override both.
+    pub fn set_inherit_locale(&self, inherit: bool) {
+        self.inherit_locale.store(inherit, Ordering::Relaxed);
+    }
+
+    /// Keep `cd` inside `root` and the directories below it, for using aurish on a server
+    /// confined to a project. A `cd` leading elsewhere, also through `..` or a symlink, is
+    /// refused with a `sandbox:` message on stderr; a current directory outside `root` is
+    /// left for `root`. Commands get the root as `AURISH_SANDBOX`. This only guards the
+    /// directory aurish keeps: a command can still `cd` out inside its own shell.
+    pub fn set_sandbox_root(&self, root: Option<&Path>) -> Result<(), ShellInitError> {
+        let root = root
+            .map(|root| root.canonicalize().map_err(|e| {
+                ShellInitError::DirectoryError(format!("sandbox root {}: {}", root.display(), e))
+            }))
+            .transpose()?;
+        if let Some(root) = &root {
+            let mut current_dir = self.current_dir.lock().unwrap();
+            if !inside(&current_dir, root) {
+                info!("Leaving {} for the sandbox root {}", current_dir.display(), root.display());
+                *current_dir = root.clone();
+            }
+        }
+        *self.sandbox_root.lock().unwrap() = root;
+        Ok(())
+    }
+
+    /// Keep `cd` inside `root`, see `set_sandbox_root`
+    pub fn with_sandbox_root(self, root: &Path) -> Result<Self, ShellInitError> {
+        self.set_sandbox_root(Some(root))?;
+        Ok(self)
+    }
+
+    /// Canonical directory `cd` cannot leave, if any
+    pub fn sandbox_root(&self) -> Option<PathBuf> {
+        self.sandbox_root.lock().unwrap().clone()
+    }
+
+    /// Set an environment variable for every following command
+    pub fn set_env(&self, key: &str, value: &str) {
+        self.env.lock().unwrap().insert(key.to_string(), value.to_string());
+    }
+
+    /// Remove a variable previously set with `set_env`
+    pub fn unset_env(&self, key: &str) {
+        self.env.lock().unwrap().remove(key);
+    }
+
+    /// Append every following command that succeeds to `history`, e.g. the user's
+    /// `~/.bash_history`. `None`, the default, records nothing.
+    pub fn set_history(&self, history: Option<ShellHistory>) {
+        *self.history.lock().unwrap() = history;
+    }
+
+    /// Runs a command through IShell within its `current_dir`.
+    ///
+    /// Any `cd` command will not be _actually_ ran. Instead, inner directory of IShell (`current_dir`) will change
+    /// accordingly. If `cd` is aliased to something else, (i.e. `changedir`), and you use this alias instead of `cd`,
+    /// then IShell won't understand that you wanted it to change directory.
+    pub fn run_command(&self, command: &str) -> ShellOutput {
+        self.run_command_with_env(command, &HashMap::new())
+    }
+
+    /// Runs a command like `run_command`, with extra environment variables for this command only.
+    ///
+    /// `env` is merged over IShell's persistent variables (see `set_env`), which in turn are
+    /// merged over the inherited environment. Nothing in `env` outlives this call.
+    pub fn run_command_with_env(&self, command: &str, env: &HashMap<String, String>) -> ShellOutput {
+        let output = self.run_in_shell(command, env);
+        if output.is_success() {
+            if let Some(history) = self.history.lock().unwrap().as_ref() {
+                history.append(command);
+            }
+        }
+        output
+    }
+
+    /// What `run_command` would do with `command`: the shell started and its flags, the
+    /// directory, the variables set, whether a `cd` is handled without running anything, and
+    /// whether the command looks dangerous. Nothing is spawned.
+    pub fn explain_command(&self, command: &str) -> ExecutionPlan {
+        self.explain_command_with_patterns(command, &[])
+    }
+
+    /// Like `explain_command`, with the danger also checked against user configured
+    /// `patterns`, see `risk::danger_reason`
+    pub fn explain_command_with_patterns(&self, command: &str, patterns: &[String]) -> ExecutionPlan {
+        let directory = self.current_dir();
+        let (action, env) = match (strip_background(command), cd_argument(command)) {
+            (None, Some(new_dir)) => {
+                let action = match self.sandboxed_directory(&directory, new_dir) {
+                    Ok(to) => PlannedAction::ChangeDirectory { to },
+                    Err((to, root)) => PlannedAction::LeavesSandbox { to, root },
+                };
+                (action, BTreeMap::new())
+            },
+            (background, _) => {
+                let (program, flag) = self.shell_program();
+                let args = vec![flag.to_string(), background.unwrap_or(command).to_string()];
+                let action = PlannedAction::Spawn { program: program.to_string(), args, background: background.is_some() };
+                (action, self.injected_env(&HashMap::new()))
+            },
+        };
+        ExecutionPlan {
+            command: command.to_string(),
+            directory,
+            action,
+            env,
+            hermetic: self.hermetic,
+            danger: risk::danger_reason(command, patterns),
+            sudo: interactive::starts_with_sudo(command),
+        }
+    }
+
+    /// Runs a command attached to the terminal, for commands that ask for input like a
+    /// password, see `interactive`.
+    ///
+    /// The command reads and writes the terminal directly and keeps the caller's `TERM`, so
+    /// only its exit code is returned. A `cd` is remembered like with `run_command`.
+    pub fn run_interactive(&self, command: &str) -> ShellOutput {
+        if command.starts_with("cd") {
+            return self.run_command(command);
+        }
+        info!("Running on the terminal: `{}`", command);
+        let mut process = self.build_command(command, &HashMap::new());
+        process.stdin(Stdio::inherit()).stdout(Stdio::inherit()).stderr(Stdio::inherit());
+        if let Some(term) = env::var_os("TERM") {
+            process.env("TERM", term);
+        }
+        let output = match process.spawn() {
+            Ok(mut child) => {
+                self.foreground.lock().unwrap().push(child.id());
+                let status = child.wait();
+                self.foreground.lock().unwrap().retain(|pid| *pid != child.id());
+                let code = status.map(|status| status.code()).unwrap_or_else(|_err| {
+                    error!("Failed to wait for process: {}", _err);
+                    None
+                });
+                info!("`{}` exited with {:?}", command, code);
+                self.create_output(code, Vec::new(), Vec::new())
+            },
+            Err(e) => {
+                error!("Couldn't spawn child process! {}", e);
+                self.create_output(Some(-1), Vec::new(), Vec::from(format!("Error: {}", e)))
+            },
+        };
+        if output.is_success() {
+            if let Some(history) = self.history.lock().unwrap().as_ref() {
+                history.append(command);
+            }
+        }
+        output
+    }
+
+    fn run_in_shell(&self, command: &str, env: &HashMap<String, String>) -> ShellOutput {
+        info!("Running: `{}`", command);
+
+        if let Some(new_dir) = cd_argument(command) {
+            let mut current_dir = self.current_dir.lock().unwrap();
+
+            match self.sandboxed_directory(&current_dir, new_dir) {
+                Ok(Some(new_dir)) => {
+                    *current_dir = new_dir;
+                    return self.create_output(Some(0), Vec::new(), Vec::new());
+                }
+                Err((to, root)) => {
+                    warn!("Refused to change directory to {}, outside the sandbox root {}", to.display(), root.display());
+                    return self.create_output(
+                        Some(1),
+                        Vec::new(),
+                        Vec::from(format!("sandbox: {} is outside {}, staying in {}", to.display(), root.display(), current_dir.display())),
+                    );
+                }
+                Ok(None) => {
+                    error!("Failed to change directory to: {}", new_dir);
+                    error!("Current directory: '{}'", current_dir.display());
+                    return self.create_output(
+                        Some(1),
+                        Vec::new(),
+                        Vec::from("Specified directory does not exist!"),
+                    );
+                }
+            }
+        }
+
+        let child_process = self.spawn_process(command, env);
+        match child_process {
+            Ok(mut process) => {
+                let (stdout_buffer, stderr_buffer) = (
+                    Arc::new(Mutex::new(VecDeque::new())),
+                    Arc::new(Mutex::new(VecDeque::new())),
+                );
+
+                let (stdout_done, stderr_done) = self.drain_output(
+                    process.stdout.take(),
+                    process.stderr.take(),
+                    &stdout_buffer,
+                    &stderr_buffer,
+                );
+
+                self.foreground.lock().unwrap().push(process.id());
+                let status = process.wait();
+                self.foreground.lock().unwrap().retain(|pid| *pid != process.id());
+                let status = status.unwrap_or_else(|_err| {
+                    error!("Failed to wait for process: {}", _err);
+                    ExitStatus::default()
+                });
+
+                if let Err(_err) = stdout_done.recv() {
+                    error!("Stdout reader stopped before draining: {:?}", _err);
+                }
+                if let Err(_err) = stderr_done.recv() {
+                    error!("Stderr reader stopped before draining: {:?}", _err);
+                }
+
+                let stdout = self.collect_output(&stdout_buffer);
+                let stderr = self.collect_output(&stderr_buffer);
+                info!("`{}` exited with {:?}", command, status.code());
+
+                ShellOutput {
+                    code: status.code(),
+                    stdout,
+                    stderr,
+                }
+            }
+            Err(e) => {
+                error!("Couldn't spawn child process! {}", e);
+
+                self.create_output(Some(-1), Vec::new(), Vec::from(format!("Error: {}", e)))
+            }
+        }
+    }
+
+    /// Starts a command in the background and returns immediately.
+    ///
+    /// The job runs inside IShell's `current_dir` and its output is captured on dedicated
+    /// threads, so long-running servers or watchers never hold up `run_command`.
+    /// The job is registered with this IShell and killed when the IShell is dropped,
+    /// unless `Job::detach` was called.
+    pub fn spawn_job(&self, command: &str) -> std::io::Result<Job> {
+        info!("Starting job: `{}`", command);
+
+        let mut process = self.build_command(command, &HashMap::new());
+        // Own process group, so `kill` reaches everything the job started
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut process, 0);
+        let mut process = process.spawn()?;
+        let (stdout_buffer, stderr_buffer) = (
+            &Arc::new(Mutex::new(VecDeque::new())),
+            &Arc::new(Mutex::new(VecDeque::new())),
+        );
+        let (stdout, stderr) = (process.stdout.take(), process.stderr.take());
+        let (stdout_job, _) = leech_output!(stdout, stdout_buffer, info, JOB_OUTPUT_LINES);
+        let (stderr_job, _) = leech_output!(stderr, stderr_buffer, warn, JOB_OUTPUT_LINES);
+        thread::spawn(stdout_job);
+        thread::spawn(stderr_job);
+
+        let job = Job {
+            inner: Arc::new(JobInner {
+                id: self.next_job_id.fetch_add(1, Ordering::SeqCst),
+                command: command.to_string(),
+                child: Mutex::new(process),
+                stdout: Arc::clone(stdout_buffer),
+                stderr: Arc::clone(stderr_buffer),
+                detached: AtomicBool::new(false),
+            }),
+        };
+        self.jobs.lock().unwrap().push(job.clone());
+        Ok(job)
+    }
+
+    /// Lists jobs that are still running.
+    /// Finished jobs are reaped from the registry on every call.
+    pub fn jobs(&self) -> Vec<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|job| job.is_running());
+        jobs.clone()
+    }
+
+    /// Looks up a running job by its id
+    pub fn job(&self, id: usize) -> Option<Job> {
+        self.jobs().into_iter().find(|job| job.id() == id)
+    }
+
+    /// Kill the commands `run_command` is waiting for on other threads, and every job that
+    /// was not detached. What those commands started is only reached with `with_process_groups`.
+    pub fn kill_all(&self) {
+        for pid in self.foreground.lock().unwrap().drain(..) {
+            #[cfg(unix)]
+            // SAFETY: plain syscall on a child that has not been waited for yet,
+            // a negative pid targets its process group
+            unsafe {
+                libc::kill(if self.process_groups { -(pid as i32) } else { pid as i32 }, libc::SIGKILL);
+            }
+            #[cfg(not(unix))]
+            let _ = Command::new("taskkill").args(["/F", "/T", "/PID", &pid.to_string()]).output();
+        }
+        self.kill_jobs();
+    }
+
+    fn kill_jobs(&self) {
+        let mut jobs = match self.jobs.lock() {
+            Ok(jobs) => jobs,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        for job in jobs.drain(..).filter(|job| !job.is_detached()) {
+            if let Err(_err) = job.kill() {
+                error!("Failed to kill job {}: {}", job.id(), _err);
+            }
+        }
+    }
+
+    /// Directory the next command runs in, changed by `cd` commands
+    pub fn current_dir(&self) -> PathBuf {
+        self.current_dir.lock().unwrap().clone()
+    }
+
+    /// Shell the commands are run with
+    pub fn shell_type(&self) -> ShellType {
+        *self.shell_type.lock().unwrap()
+    }
+
+    /// Run the next commands with `shell` instead of the detected one, for a shell chosen
+    /// by the user
+    pub fn set_shell_type(&self, shell: ShellType) {
+        *self.shell_type.lock().unwrap() = shell;
+    }
+
+    /// Run the next commands in `dir`, as after a `cd` to it. Returns false and stays where
+    /// it is if `dir` is not a directory.
+    pub fn set_current_dir(&self, dir: &Path) -> bool {
+        if !dir.is_dir() || self.sandbox_root().is_some_and(|root| !inside(dir, &root)) {
+            return false;
+        }
+        *self.current_dir.lock().unwrap() = dir.to_path_buf();
+        true
+    }
+
+    /// Forget current directory and go back to the directory initially specified.
+    pub fn forget_current_directory(&self) {
+        let mut current_dir = self.current_dir.lock().unwrap();
+        *current_dir = match self.sandbox_root() {
+            Some(root) if !inside(&self.initial_dir, &root) => root,
+            _ => self.initial_dir.clone(),
+        };
+    }
+
+    fn create_output(&self, code: Option<i32>, stdout: Vec<u8>, stderr: Vec<u8>) -> ShellOutput {
+        ShellOutput {
+            code,
+            stdout,
+            stderr,
+        }
+    }
+
+    fn spawn_process(&self, command: &str, env: &HashMap<String, String>) -> std::io::Result<std::process::Child> {
+        let mut process = self.build_command(command, env);
+        #[cfg(unix)]
+        if self.process_groups {
+            std::os::unix::process::CommandExt::process_group(&mut process, 0);
+            // reading the terminal from outside its foreground group would stop the command
+            process.stdin(Stdio::null());
+        }
+        process.spawn()
+    }
+
+    /// The shell binary commands are run with, and its flag for a command
+    fn shell_program(&self) -> (&'static str, &'static str) {
+        match self.shell_type() {
+            ShellType::PowerShell => {
+                ("powershell", "-Command")
+            },
+            ShellType::Cmd => {
+                ("cmd", "/C")
+            },
+            ShellType::Bash | ShellType::Unknown => {
+                ("sh", "-c")
+            },
+            ShellType::Fish => {
+                ("fish", "-c")
+            },
+            ShellType::Zsh => {
+                ("zsh", "-c")
+            },
+            ShellType::Ksh => {
+                ("ksh", "-c")
+            },
+        }
+    }
+
+    /// Variables set for a command besides the inherited ones, `env` over those of `set_env`
+    /// over the locale and colour ones
+    fn injected_env(&self, env: &HashMap<String, String>) -> BTreeMap<String, String> {
+        let mut injected = BTreeMap::new();
+        if !self.inherit_locale.load(Ordering::Relaxed) {
+            injected.insert("LC_ALL".to_string(), "C".to_string());
+            injected.insert("LANG".to_string(), "C".to_string());
+            if !self.force_color {
+                injected.insert("TERM".to_string(), "dumb".to_string());
+            }
+        }
+        if self.force_color {
+            injected.insert("CLICOLOR_FORCE".to_string(), "1".to_string());
+            injected.insert("FORCE_COLOR".to_string(), "1".to_string());
+        }
+        injected.extend(self.env.lock().unwrap().iter().map(|(key, value)| (key.clone(), value.clone())));
+        injected.extend(env.iter().map(|(key, value)| (key.clone(), value.clone())));
+        if let Some(root) = self.sandbox_root() {
+            injected.insert(SANDBOX_VAR.to_string(), root.display().to_string());
+        }
+        injected
+    }
+
+    fn build_command(&self, command: &str, env: &HashMap<String, String>) -> Command {
+        let current_dir = self.current_dir.lock().unwrap().clone();
+        let (shell, arg) = self.shell_program();
+
+        let mut process = Command::new(shell);
+        process
+            .arg(arg)
+            .arg(command)
+            .current_dir(current_dir)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if self.hermetic {
+            process.env_clear();
+        }
+        process.envs(self.injected_env(env));
+        process
+    }
+
+    /// Hand both pipes to the persistent reader threads.
+    /// Returned receivers fire once the respective pipe has been fully drained.
+    fn drain_output(
+        &self,
+        stdout: Option<std::process::ChildStdout>,
+        stderr: Option<std::process::ChildStderr>,
+        stdout_buffer: &Arc<Mutex<VecDeque<String>>>,
+        stderr_buffer: &Arc<Mutex<VecDeque<String>>>,
+    ) -> (Receiver<()>, Receiver<()>) {
+        let (stdout_job, stdout_done) = leech_output!(stdout, stdout_buffer, info);
+        let (stderr_job, stderr_done) = leech_output!(stderr, stderr_buffer, warn);
+        self.readers.drain(stdout_job, stderr_job);
+
+        (stdout_done, stderr_done)
+    }
+
+    fn collect_output(&self, buffer: &Arc<Mutex<VecDeque<String>>>) -> Vec<u8> {
+        match buffer.lock() {
+            Ok(buffer) => Vec::from_iter(buffer.iter().map(String::as_str)).join("\n").into_bytes(),
+            Err(_err) => {
+                error!("Couldn't lock buffer! {}", _err);
+                // Need to return SOMETHING here.
+                Vec::new()
+            }
+        }
+    }
+
+    /// Method to quickly check if given path is a valid directory
+    fn is_valid_directory(path: &Path) -> bool {
+        path.exists() && path.is_dir()
+    }
+
+    /// Method to determine the new directory
+    /// Checks if `current_dir`/`new_dir` is a valid dir (and returns it if it is),
+    /// if it isn't - checks if `new_dir` is a valid dir (and returns it if it is);
+    /// if it isn't - returns None
+    fn determine_new_directory<U: AsRef<Path>, T: AsRef<Path>>(
+        current_dir: U,
+        new_dir: T,
+    ) -> Option<PathBuf> {
+        let new_dir = new_dir.as_ref();
+        let current_dir = current_dir.as_ref();
+
+        // Perhaps the `new_dir` is relative to `current_dir`?
+        let wanted_dir = current_dir.join(new_dir);
+        if Self::is_valid_directory(&wanted_dir) {
+            return Some(wanted_dir.to_path_buf());
+        }
+
+        // Maybe `new_dir` wasn't relative?
+        if let Some(sanitized_dir) = Self::sanitize_path(new_dir) {
+            if Self::is_valid_directory(&sanitized_dir) {
+                return Some(sanitized_dir);
+            } else {
+                warn!(
+                    "Neither the combined path {:#?} nor the sanitized path {:#?} is a valid directory.",
+                    wanted_dir, sanitized_dir
+                );
+            }
+        }
+
+        // I guess `new_dir` doesn't exist...
+        None
+    }
+
+    /// `determine_new_directory` kept inside the sandbox root: `Err` with the directory a `cd`
+    /// leads to and the root if it is outside, after resolving `..` and symlinks
+    fn sandboxed_directory(&self, current_dir: &Path, new_dir: &str) -> Result<Option<PathBuf>, (PathBuf, PathBuf)> {
+        let found = Self::determine_new_directory(current_dir, new_dir);
+        match (found, self.sandbox_root()) {
+            (Some(dir), Some(root)) if !inside(&dir, &root) => Err((dir, root)),
+            (found, _) => Ok(found),
+        }
+    }
+
+    /// Expand tilde
+    /// Inspired by https://github.com/splurf/simple-expand-tilde/blob/master/src/lib.rs
+    fn sanitize_path(path: impl AsRef<Path>) -> Option<PathBuf> {
+        let resolved_path = path.as_ref();
+
+        if !resolved_path.starts_with("~") {
+            return Some(resolved_path.to_path_buf());
+        }
+        if resolved_path == Path::new("~") {
+            return dirs::home_dir();
+        }
+
+        dirs::home_dir().map(|mut home_dir| {
+            if home_dir == Path::new("/") {
+                // For when running as root
+                resolved_path.strip_prefix("~").unwrap().to_path_buf()
+            } else {
+                home_dir.push(resolved_path.strip_prefix("~/").unwrap());
+                home_dir
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Command printing the names in the current directory, one per line
+    fn list_dir(shell: ShellType) -> &'static str {
+        match shell {
+            ShellType::PowerShell => "Get-ChildItem -Name",
+            ShellType::Cmd => "dir /b",
+            _ => "ls",
+        }
+    }
+
+    /// Command removing `dir` and everything in it
+    fn remove_dir(shell: ShellType, dir: &str) -> String {
+        match shell {
+            ShellType::PowerShell => format!("Remove-Item -Recurse -Force {}", dir),
+            ShellType::Cmd => format!("rmdir /s /q {}", dir),
+            _ => format!("rm -r {}", dir),
+        }
+    }
+
+    /// Command printing the current directory
+    fn print_cwd(shell: ShellType) -> &'static str {
+        match shell {
+            ShellType::PowerShell => "(Get-Location).Path",
+            ShellType::Cmd => "echo %CD%",
+            _ => "echo $PWD",
+        }
+    }
+
+    #[test]
+    fn shell_families() {
+        assert!(ShellType::PowerShell.is_windows() && ShellType::Cmd.is_windows());
+        for shell in [ShellType::Bash, ShellType::Fish, ShellType::Zsh, ShellType::Ksh, ShellType::Unknown] {
+            assert!(shell.is_posix() && !shell.is_windows(), "{:?}", shell);
+        }
+        assert_eq!(IShell::new().shell_type().is_windows(), cfg!(windows));
+    }
+
+    #[test]
+    fn shell_names() {
+        for shell in ShellType::KNOWN {
+            assert_eq!(shell.to_string().parse::<ShellType>(), Ok(shell));
+            assert_eq!(shell.to_string().to_uppercase().parse::<ShellType>(), Ok(shell));
+        }
+        assert_eq!("pwsh".parse::<ShellType>(), Ok(ShellType::PowerShell));
+        assert_eq!(" sh ".parse::<ShellType>(), Ok(ShellType::Bash));
+        let error = "tcsh".parse::<ShellType>().unwrap_err();
+        assert_eq!(error, ShellTypeError::Unknown("tcsh".to_string()));
+        assert_eq!(
+            error.to_string(),
+            "unknown shell `tcsh`, valid shells are powershell (or pwsh), cmd, bash (or sh), fish, zsh and ksh"
+        );
+        // only ever detected
+        assert!(ShellType::Unknown.to_string().parse::<ShellType>().is_err());
+    }
+
+    #[test]
+    fn plans_of_plain_commands() {
+        let shell = IShell::new().with_force_color(true);
+        shell.set_env("AURISH_TEST_VAR", "set");
+        for (shell_type, program, flag) in [
+            (ShellType::Bash, "sh", "-c"),
+            (ShellType::Unknown, "sh", "-c"),
+            (ShellType::Zsh, "zsh", "-c"),
+            (ShellType::Fish, "fish", "-c"),
+            (ShellType::PowerShell, "powershell", "-Command"),
+            (ShellType::Cmd, "cmd", "/C"),
+        ] {
+            shell.set_shell_type(shell_type);
+            let plan = shell.explain_command("ls -la");
+            let args = vec![flag.to_string(), "ls -la".to_string()];
+            assert_eq!(plan.action, PlannedAction::Spawn { program: program.to_string(), args, background: false }, "{}", shell_type);
+            assert_eq!(plan.directory, shell.current_dir());
+            assert_eq!((plan.danger.as_deref(), plan.sudo, plan.hermetic), (None, false, false));
+        }
+        let plan = shell.explain_command("ls -la");
+        let env: Vec<(&str, &str)> = plan.env.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect();
+        assert_eq!(env, [("AURISH_TEST_VAR", "set"), ("CLICOLOR_FORCE", "1"), ("FORCE_COLOR", "1"), ("LANG", "C"), ("LC_ALL", "C")]);
+        assert_eq!(plan.lines()[0], "runs: cmd /C 'ls -la'");
+
+        shell.set_shell_type(ShellType::Bash);
+        let job = shell.explain_command("sleep 10 &");
+        assert!(matches!(&job.action, PlannedAction::Spawn { args, background: true, .. } if args[1] == "sleep 10"));
+        assert_eq!(job.lines()[0], "runs: sh -c 'sleep 10', as a background job");
+        assert!(IShell::new().with_hermetic_env(true).explain_command("env").lines().contains(&"env: only LANG=C LC_ALL=C TERM=dumb".to_string()));
+    }
+
+    #[test]
+    fn plans_of_cd_commands() {
+        let shell = IShell::new();
+        let start = shell.current_dir();
+        let plan = shell.explain_command("cd ..");
+        assert_eq!(plan.action, PlannedAction::ChangeDirectory { to: Some(start.join("..")) });
+        assert!(plan.env.is_empty());
+        // nothing happened
+        assert_eq!(shell.current_dir(), start);
+        let missing = shell.explain_command("cd no-such-directory-here");
+        assert_eq!(missing.action, PlannedAction::ChangeDirectory { to: None });
+        assert_eq!(missing.lines()[0], format!("cd: no such directory from {}, nothing is run", start.display()));
+        // a `cd` in the background is a job like any other
+        assert!(matches!(shell.explain_command("cd .. &").action, PlannedAction::Spawn { background: true, .. }));
+    }
+
+    #[test]
+    fn sandbox_root() {
+        let base = env::temp_dir().join(format!("aurish-sandbox-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        std::fs::create_dir_all(base.join("project/src/bin")).unwrap();
+        std::fs::create_dir_all(base.join("elsewhere")).unwrap();
+        let base = base.canonicalize().unwrap();
+        let root = base.join("project");
+
+        // a shell started outside moves to the root
+        let shell = IShell::from_path(&base).unwrap().with_sandbox_root(&root).unwrap();
+        assert_eq!((shell.current_dir(), shell.sandbox_root()), (root.clone(), Some(root.clone())));
+        assert!(shell.run_command("cd src/bin").is_success());
+        assert!(shell.run_command("cd ../..").is_success());
+        assert_eq!(shell.current_dir().canonicalize().unwrap(), root);
+
+        // `..` chains and absolute paths out of it are refused
+        for outside in ["..", "src/../../elsewhere", "src/bin/../../../..", &base.join("elsewhere").display().to_string(), "/"] {
+            let before = shell.current_dir();
+            let output = shell.run_command(&format!("cd {}", outside));
+            assert_eq!(output.code, Some(1), "{}", outside);
+            assert!(output.stderr_str().starts_with("sandbox: "), "{}", output.stderr_str());
+            assert_eq!(shell.current_dir(), before);
+        }
+        assert!(!shell.set_current_dir(&base));
+        assert!(shell.set_current_dir(&root.join("src")));
+        let plan = shell.explain_command("cd ../..");
+        assert_eq!(plan.action, PlannedAction::LeavesSandbox { to: root.join("src/../.."), root: root.clone() });
+        assert_eq!(plan.lines()[0], format!("cd: refused, {} is outside the sandbox {}", root.join("src/../..").display(), root.display()));
+        assert_eq!(shell.explain_command("cd bin").action, PlannedAction::ChangeDirectory { to: Some(root.join("src/bin")) });
+
+        // `~` goes back to the root when the shell started outside it
+        shell.forget_current_directory();
+        assert_eq!(shell.current_dir(), root);
+        assert_eq!(shell.explain_command("pwd").env.get(SANDBOX_VAR), Some(&root.display().to_string()));
+        shell.set_sandbox_root(None).unwrap();
+        assert!(shell.run_command("cd ..").is_success());
+        assert!(shell.set_sandbox_root(Some(&base.join("missing"))).is_err());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn sandbox_symlinks() {
+        let base = env::temp_dir().join(format!("aurish-sandbox-links-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        std::fs::create_dir_all(base.join("project/src")).unwrap();
+        std::fs::create_dir_all(base.join("elsewhere")).unwrap();
+        let base = base.canonicalize().unwrap();
+        let root = base.join("project");
+        std::os::unix::fs::symlink(base.join("elsewhere"), root.join("escape")).unwrap();
+        std::os::unix::fs::symlink(root.join("src"), root.join("code")).unwrap();
+        // a root given through a symlink is resolved too
+        std::os::unix::fs::symlink(&root, base.join("link-to-project")).unwrap();
+
+        let shell = IShell::from_path(&root).unwrap().with_sandbox_root(&base.join("link-to-project")).unwrap();
+        assert_eq!(shell.sandbox_root(), Some(root.clone()));
+        let output = shell.run_command("cd escape");
+        assert!(output.stderr_str().starts_with("sandbox: "), "{}", output.stderr_str());
+        assert!(shell.run_command("cd escape/..").stderr_str().starts_with("sandbox: "));
+        assert_eq!(shell.current_dir(), root);
+        // links staying inside are fine
+        assert!(shell.run_command("cd code").is_success());
+        assert_eq!(shell.run_command("echo $AURISH_SANDBOX").stdout_str(), root.display().to_string());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn plans_of_dangerous_commands() {
+        let shell = IShell::new();
+        let plan = shell.explain_command("sudo rm -rf /");
+        assert!(plan.danger.is_some() && plan.sudo);
+        assert!(plan.lines().contains(&"sudo: may ask for a password".to_string()));
+        let patterns = vec!["terraform destroy".to_string()];
+        assert_eq!(shell.explain_command("terraform destroy").danger, None);
+        let plan = shell.explain_command_with_patterns("terraform destroy", &patterns);
+        assert_eq!(plan.danger.as_deref(), Some("matches the configured pattern `terraform destroy`"));
+        assert!(plan.lines().contains(&"risk: asked about first, matches the configured pattern `terraform destroy`".to_string()));
+        // kept in transcripts as JSON
+        let json = serde_json::to_string(&plan).unwrap();
+        assert!(json.contains(r#""action":{"kind":"spawn","#));
+        assert_eq!(serde_json::from_str::<ExecutionPlan>(&json).unwrap(), plan);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn interactive_commands() {
+        let shell = IShell::new();
+        let output = shell.run_interactive("exit 3");
+        assert_eq!((output.code, output.stdout.len(), output.stderr.len()), (Some(3), 0, 0));
+        // the terminal's, not `dumb`
+        if let Ok(term) = env::var("TERM") {
+            assert!(shell.run_interactive(&format!("test \"$TERM\" = '{}'", term)).is_success());
+        }
+        assert!(shell.run_interactive("cd src").is_success());
+        assert!(shell.current_dir().ends_with("src"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn unknown_shell_runs_sh() {
+        let shell = IShell::new();
+        shell.set_shell_type(ShellType::Unknown);
+        assert_eq!(shell.shell_type(), ShellType::Unknown);
+        assert_eq!(shell.run_command("echo $((1 + 2))").stdout, b"3");
+    }
+
+    // Tests written in sh syntax only run on unix, the others pick their commands with the
+    // helpers above so they also pass with PowerShell or cmd on Windows
+
+    #[test]
+    #[cfg(unix)]
+    fn true_command() {
+        let shell = IShell::new();
+
+        let result = shell.run_command("true");
+        assert!(result.is_success());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn false_command() {
+        let shell = IShell::new();
+
+        let result = shell.run_command("false");
+        assert!(!result.is_success());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn echo_command() {
+        // Checking stdout capture
+        let shell = IShell::new();
+
+        let result = shell.run_command("echo \"Hello, World!\"");
+        let stdout_res = String::from_utf8(result.stdout).expect("Stdout contained invalid UTF-8!");
+        assert_eq!(stdout_res, "Hello, World!");
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn echo_command_windows() {
+        let shell = IShell::new();
+        let command = match shell.shell_type() {
+            ShellType::Cmd => "echo Hello, World!",
+            _ => "Write-Output 'Hello, World!'",
+        };
+
+        let result = shell.run_command(command);
+        assert_eq!(result.stdout_str().trim_end(), "Hello, World!");
+    }
+
+    #[test]
+    fn dir_memory() {
+        // Check for whether CD is remembered
+
+        let shell = IShell::new();
+        let shell_type = shell.shell_type();
+
+        let unique_dir_1 = format!("test_{}", rand::random::<u32>());
+        let unique_dir_2 = format!("test2_{}", rand::random::<u32>());
+
+        shell.run_command(&format!("mkdir {}", unique_dir_1));
+        shell.run_command(&format!("cd {}", unique_dir_1));
+        shell.run_command(&format!("mkdir {}", unique_dir_2));
+
+        let result = shell.run_command(list_dir(shell_type));
+        let stdout_res = String::from_utf8(result.stdout).expect("Stdout contained invalid UTF-8!");
+        assert_eq!(stdout_res.trim(), unique_dir_2);
+
+        shell.run_command("cd ..");
+        shell.run_command(&remove_dir(shell_type, &unique_dir_1));
+    }
+
+    #[test]
+    fn forget_current_dir() {
+        let shell = IShell::new();
+        let shell_type = shell.shell_type();
+
+        let result = shell.run_command(print_cwd(shell_type));
+        let pwd = String::from_utf8(result.stdout).expect("Stdout contained invalid UTF-8!");
+
+        let unique_dir = format!("test_{}", rand::random::<u32>());
+
+        shell.run_command(&format!("mkdir {}", unique_dir));
+        shell.run_command(&format!("cd {}", unique_dir));
+        shell.forget_current_directory();
+
+        let result = shell.run_command(print_cwd(shell_type));
+        let forgotten_pwd =
+            String::from_utf8(result.stdout).expect("Stdout contained invalid UTF-8!");
+
+        assert_eq!(pwd, forgotten_pwd);
+
+        shell.run_command(&remove_dir(shell_type, &unique_dir));
+    }
+
+    #[test]
+    fn dir_doesnt_exist() {
+        let shell = IShell::new();
+
+        let current_dir = shell.current_dir.lock().unwrap().clone();
+        let res = shell.run_command("cd directory_that_doesnt_exist");
+        let next_dir = shell.current_dir.lock().unwrap().clone();
+
+        assert!(!res.is_success());
+        assert_eq!(current_dir, next_dir);
+    }
+
+    #[test]
+    fn relative_construct() {
+        let main_shell = IShell::new();
+        let list = list_dir(main_shell.shell_type());
+        main_shell.run_command("cd target");
+        let main_result = main_shell.run_command(list);
+        assert!(main_result.is_success());
+
+        let target_shell = IShell::from_path("target").unwrap();
+        let target_result = target_shell.run_command(list);
+
+        let target_result =
+            String::from_utf8(target_result.stdout).expect("Stdout contained invalid UTF-8!");
+        let main_result =
+            String::from_utf8(main_result.stdout).expect("Stdout contained invalid UTF-8!");
+
+        assert_eq!(target_result, main_result);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn reader_threads_are_reused() {
+        let shell = IShell::new();
+
+        for i in 0..20 {
+            let result = shell.run_command(&format!("echo {}; echo err{} 1>&2", i, i));
+            assert_eq!(String::from_utf8(result.stdout).unwrap(), i.to_string());
+            assert_eq!(String::from_utf8(result.stderr).unwrap(), format!("err{}", i));
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn concurrent_commands_do_not_wait_for_each_other() {
+        use std::time::{Duration, Instant};
+
+        let shell = Arc::new(IShell::new());
+        let slow = thread::spawn({
+            let shell = Arc::clone(&shell);
+            move || shell.run_command("sleep 3; echo slow")
+        });
+        thread::sleep(Duration::from_millis(200));
+
+        let start = Instant::now();
+        let fast = shell.run_command("echo fast");
+        assert_eq!(String::from_utf8(fast.stdout).unwrap(), "fast");
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // A grandchild holding the pipe open only delays its own command
+        let start = Instant::now();
+        let held = thread::spawn({
+            let shell = Arc::clone(&shell);
+            move || shell.run_command("(sleep 2 &); echo held")
+        });
+        thread::sleep(Duration::from_millis(200));
+        let after = shell.run_command("echo after");
+        assert_eq!(String::from_utf8(after.stdout).unwrap(), "after");
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        assert_eq!(String::from_utf8(held.join().unwrap().stdout).unwrap(), "held");
+        assert_eq!(String::from_utf8(slow.join().unwrap().stdout).unwrap(), "slow");
+    }
+
+    #[test]
+    #[ignore = "timing comparison, run with --ignored --nocapture"]
+    fn output_capture_overhead() {
+        use std::time::Instant;
+
+        const ROUNDS: u32 = 500;
+        let readers = OutputReaders::new();
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            let (out, err) = (Some(std::io::empty()), Some(std::io::empty()));
+            let (out_buf, err_buf) = (&Arc::new(Mutex::new(VecDeque::new())), &Arc::new(Mutex::new(VecDeque::new())));
+            let (out_job, out_done) = leech_output!(out, out_buf, info);
+            let (err_job, err_done) = leech_output!(err, err_buf, warn);
+            readers.drain(out_job, err_job);
+            out_done.recv().unwrap();
+            err_done.recv().unwrap();
+        }
+        let pooled = start.elapsed() / ROUNDS;
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            let (out, err) = (Some(std::io::empty()), Some(std::io::empty()));
+            let (out_buf, err_buf) = (&Arc::new(Mutex::new(VecDeque::new())), &Arc::new(Mutex::new(VecDeque::new())));
+            let (out_job, _out_done) = leech_output!(out, out_buf, info);
+            let (err_job, _err_done) = leech_output!(err, err_buf, warn);
+            let handles = (thread::spawn(out_job), thread::spawn(err_job));
+            handles.0.join().unwrap();
+            handles.1.join().unwrap();
+        }
+        let spawned = start.elapsed() / ROUNDS;
+
+        println!("per-command capture overhead: reused readers {:?}, fresh threads {:?}", pooled, spawned);
+        assert!(pooled < spawned);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn plain_output() {
+        let shell = IShell::new();
+
+        let result = shell.run_command("printf '\\033[31mred\\033[0m'");
+        assert_eq!(result.stdout_str(), "\x1b[31mred\x1b[0m");
+        assert_eq!(result.stdout_str_plain(), "red");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn force_color_env() {
+        let shell = IShell::new().with_force_color(true);
+        let result = shell.run_command("echo $CLICOLOR_FORCE$FORCE_COLOR");
+        assert_eq!(result.stdout_str(), "11");
+
+        let shell = IShell::new();
+        let result = shell.run_command("echo $CLICOLOR_FORCE$FORCE_COLOR");
+        assert_eq!(result.stdout_str(), "");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn injected_env() {
+        let shell = IShell::new();
+        let env = HashMap::from([("AURISH_TEST_VAR".to_string(), "injected".to_string())]);
+
+        let result = shell.run_command_with_env("echo $AURISH_TEST_VAR", &env);
+        assert_eq!(result.stdout_str(), "injected");
+
+        let result = shell.run_command("echo $AURISH_TEST_VAR");
+        assert_eq!(result.stdout_str(), "");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn persistent_env() {
+        let shell = IShell::new();
+        shell.set_env("AURISH_TEST_VAR", "persistent");
+        let env = HashMap::from([("AURISH_TEST_VAR".to_string(), "override".to_string())]);
+
+        assert_eq!(shell.run_command("echo $AURISH_TEST_VAR").stdout_str(), "persistent");
+        assert_eq!(shell.run_command_with_env("echo $AURISH_TEST_VAR", &env).stdout_str(), "override");
+
+        shell.unset_env("AURISH_TEST_VAR");
+        assert_eq!(shell.run_command("echo $AURISH_TEST_VAR").stdout_str(), "");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn c_locale() {
+        let shell = IShell::new();
+        assert_eq!(shell.run_command("echo $LC_ALL $LANG $TERM").stdout_str(), "C C dumb");
+        // month names are English in the C locale, whatever the user's locale is
+        let month = shell.run_command("date -u -d @0 +%b 2>/dev/null || date -u -r 0 +%b");
+        assert_eq!(month.stdout_str(), "Jan");
+        shell.set_env("LANG", "en_US.UTF-8");
+        assert_eq!(shell.run_command("echo $LANG").stdout_str(), "en_US.UTF-8");
+
+        let colored = IShell::new().with_force_color(true);
+        let term = env::var("TERM").unwrap_or_default();
+        assert_eq!(colored.run_command("echo $LC_ALL $TERM").stdout_str(), format!("C {}", term).trim_end());
+
+        // the escape hatch keeps whatever the caller has
+        let inherited = IShell::new();
+        inherited.set_inherit_locale(true);
+        let expected: Vec<_> = ["LC_ALL", "LANG", "TERM"].iter().map(|var| env::var(var).unwrap_or_default()).collect();
+        assert_eq!(inherited.run_command("echo \"$LC_ALL|$LANG|$TERM\"").stdout_str(), expected.join("|"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn successful_commands_in_history() {
+        use crate::shell_history::HistoryFormat;
+
+        let path = env::temp_dir().join(format!("aurish-ishell-history-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let shell = IShell::new();
+        shell.set_history(Some(ShellHistory::new(&path, HistoryFormat::Bash)));
+        shell.run_command("true");
+        shell.run_command("false");
+        shell.run_command("cd /");
+        shell.set_history(None);
+        shell.run_command("echo not recorded");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "true\ncd /\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn hermetic_env() {
+        let shell = IShell::new().with_hermetic_env(true);
+        let env = HashMap::from([("AURISH_TEST_VAR".to_string(), "only".to_string())]);
+
+        let result = shell.run_command_with_env("echo $HOME$AURISH_TEST_VAR", &env);
+        assert_eq!(result.stdout_str(), "only");
+
+        // exactly what IShell sets, apart from what the shell itself adds
+        let output = shell.run_command_with_env("env", &env).stdout_str();
+        let mut vars: Vec<&str> = output.lines()
+            .filter(|var| !["PWD=", "OLDPWD=", "SHLVL=", "_="].iter().any(|shell_var| var.starts_with(shell_var)))
+            .collect();
+        vars.sort();
+        assert_eq!(vars, ["AURISH_TEST_VAR=only", "LANG=C", "LC_ALL=C", "TERM=dumb"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn background_job() {
+        let shell = IShell::new();
+
+        let job = shell.spawn_job("echo started; sleep 5").unwrap();
+        assert!(job.is_running());
+        assert_eq!(shell.jobs().len(), 1);
+
+        thread::sleep(std::time::Duration::from_millis(200));
+        let output = job.take_output_so_far();
+        assert_eq!(output.code, None);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "started");
+        assert!(job.take_output_so_far().stdout.is_empty());
+
+        job.kill().unwrap();
+        assert!(!job.is_running());
+        assert!(shell.jobs().is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn job_output_keeps_last_lines() {
+        let shell = IShell::new();
+        let total = JOB_OUTPUT_LINES + 500;
+        let job = shell.spawn_job(&format!("seq 1 {}", total)).unwrap();
+
+        for _ in 0..50 {
+            if !job.is_running() {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(100));
+        }
+        thread::sleep(std::time::Duration::from_millis(200));
+        let output = String::from_utf8(job.take_output_so_far().stdout).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), JOB_OUTPUT_LINES);
+        assert_eq!(lines.first(), Some(&"501"));
+        assert_eq!(lines.last(), Some(&total.to_string().as_str()));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn kill_reaches_what_the_job_left_behind() {
+        let shell = IShell::new();
+        let job = shell.spawn_job("sleep 30 & echo $!").unwrap();
+        thread::sleep(std::time::Duration::from_millis(300));
+        let pid = String::from_utf8(job.take_output_so_far().stdout).unwrap();
+        // alive, and not a zombie nobody reaps
+        let alive = || match std::fs::read_to_string(format!("/proc/{}/stat", pid.trim())) {
+            Ok(stat) => !stat.rsplit(')').next().unwrap_or_default().trim_start().starts_with(['Z', 'X']),
+            Err(_) => false,
+        };
+        assert!(alive());
+        assert!(!job.is_running());
+
+        job.kill().unwrap();
+        for _ in 0..50 {
+            if !alive() {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert!(!alive());
+        // nothing left to kill
+        job.kill().unwrap();
+    }
+
+    #[test]
+    fn background_marker() {
+        assert_eq!(strip_background("npm run dev &"), Some("npm run dev"));
+        assert_eq!(strip_background("python -m http.server&  "), Some("python -m http.server"));
+        assert_eq!(strip_background("make && make install"), None);
+        assert_eq!(strip_background("ls"), None);
+        assert_eq!(strip_background("&"), None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn jobs_killed_on_drop() {
+        let shell = IShell::new();
+        let job = shell.spawn_job("sleep 5").unwrap();
+        let detached = shell.spawn_job("sleep 5").unwrap();
+        detached.detach();
+        assert_ne!(job.id(), detached.id());
+
+        drop(shell);
+        assert!(!job.is_running());
+        assert!(detached.is_running());
+        detached.kill().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn kill_all_stops_foreground_commands() {
+        let shell = Arc::new(IShell::new().with_process_groups(true));
+        let job = shell.spawn_job("sleep 30").unwrap();
+        let runner = {
+            let shell = Arc::clone(&shell);
+            thread::spawn(move || shell.run_command("sleep 30"))
+        };
+        while shell.foreground.lock().unwrap().is_empty() {
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let started = std::time::Instant::now();
+        shell.kill_all();
+        assert!(!runner.join().unwrap().is_success());
+        assert!(started.elapsed().as_secs() < 10);
+        assert!(!job.is_running());
+    }
+
+    #[test]
+    fn tilda_init() {
+        let desktop_shell = IShell::from_path("~").unwrap();
+        let shell = IShell::new();
+        let list = list_dir(shell.shell_type());
+
+        shell.run_command("cd ~");
+        let res = shell.run_command(list);
+        let desktop_res = desktop_shell.run_command(list);
+
+        let res = String::from_utf8(res.stdout).expect("Stdout contained invalid UTF-8!");
+        let desktop_res =
+            String::from_utf8(desktop_res.stdout).expect("Stdout contained invalid UTF-8!");
+
+        assert_eq!(res, desktop_res);
+    }
+}
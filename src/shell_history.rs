@@ -0,0 +1,249 @@
+//! Appending the commands aurish runs to the history file of the user's shell
+//!
+//! With `append_to_shell_history` set in the config, each command that succeeds is added to
+//! `~/.bash_history`, the zsh or fish history, or the PSReadLine history of PowerShell, so
+//! it can be found again with the shell's own history search. Entries are written in the
+//! shell's format with a single append to the locked file, so concurrent shells do not
+//! corrupt it. Failures are only logged.
+
+use log::warn;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::shell::ShellType;
+
+/// How far from the end of a history file to look for bash timestamps and the last newline
+const BASH_TAIL: u64 = 4096;
+
+/// Format of a history file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryFormat {
+    /// One line per command, with `#<time>` lines before them if the file already has them
+    Bash,
+    /// Extended zsh history, `: <time>:0;<command>`, stored metafied
+    Zsh,
+    /// fish's YAML-like `- cmd: ...` entries
+    Fish,
+    /// PSReadLine, lines of multi-line commands ending with a backtick
+    PowerShell,
+}
+
+/// History file commands are appended to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShellHistory {
+    path: PathBuf,
+    format: HistoryFormat,
+}
+
+impl ShellHistory {
+    pub fn new(path: impl Into<PathBuf>, format: HistoryFormat) -> Self {
+        ShellHistory { path: path.into(), format }
+    }
+
+    /// History file of `shell` for the current user, `None` for shells whose history
+    /// aurish cannot write (cmd, ksh) or without a home directory
+    pub fn for_shell(shell: ShellType) -> Option<ShellHistory> {
+        let home = dirs::home_dir()?;
+        let histfile = || env::var_os("HISTFILE").filter(|file| !file.is_empty()).map(PathBuf::from);
+        let history = match shell {
+            ShellType::Bash => ShellHistory::new(histfile().unwrap_or_else(|| home.join(".bash_history")), HistoryFormat::Bash),
+            ShellType::Zsh => {
+                let dir = env::var_os("ZDOTDIR").filter(|dir| !dir.is_empty()).map_or(home, PathBuf::from);
+                ShellHistory::new(histfile().unwrap_or_else(|| dir.join(".zsh_history")), HistoryFormat::Zsh)
+            },
+            ShellType::Fish => {
+                // an empty `fish_history` turns the history off
+                let name = env::var("fish_history").unwrap_or_else(|_| "fish".to_string());
+                if name.is_empty() {
+                    return None;
+                }
+                ShellHistory::new(xdg_data_dir(&home).join("fish").join(format!("{}_history", name)), HistoryFormat::Fish)
+            },
+            ShellType::PowerShell => {
+                let dir = if cfg!(target_os = "windows") {
+                    dirs::data_dir()?.join("Microsoft").join("Windows").join("PowerShell")
+                } else {
+                    xdg_data_dir(&home).join("powershell")
+                };
+                ShellHistory::new(dir.join("PSReadLine").join("ConsoleHost_history.txt"), HistoryFormat::PowerShell)
+            },
+            ShellType::Cmd | ShellType::Ksh | ShellType::Unknown => return None,
+        };
+        Some(history)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn format(&self) -> HistoryFormat {
+        self.format
+    }
+
+    /// Append `command`, run just now. Failing to is only logged, the command still ran.
+    pub fn append(&self, command: &str) {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        if let Err(e) = self.try_append(command, time) {
+            warn!("could not add `{}` to the shell history {}: {}", command, self.path.display(), e);
+        }
+    }
+
+    fn try_append(&self, command: &str, time: u64) -> io::Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut options = OpenOptions::new();
+        options.create(true).append(true).read(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&self.path)?;
+        // shells appending at the same time wait for each other, released when closed
+        file.lock()?;
+        let tail = read_tail(&mut file)?;
+        let timestamps = self.format == HistoryFormat::Bash && has_bash_timestamps(&tail);
+        let mut bytes = Vec::new();
+        // a last line without its newline, e.g. after editing the file, is kept on its own
+        if tail.last().is_some_and(|&last| last != b'\n') {
+            bytes.push(b'\n');
+        }
+        bytes.extend(entry(self.format, command, time, timestamps));
+        file.write_all(&bytes)
+    }
+}
+
+/// `$XDG_DATA_HOME`, or `~/.local/share` as fish and PowerShell use it on every platform
+fn xdg_data_dir(home: &Path) -> PathBuf {
+    env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(|| home.join(".local").join("share"))
+}
+
+/// The last `BASH_TAIL` bytes of a history file
+fn read_tail(file: &mut File) -> io::Result<Vec<u8>> {
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(BASH_TAIL)))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    Ok(tail)
+}
+
+/// Whether the end of a bash history file has `#<time>` lines. Mixing entries with and
+/// without them makes bash join the ones without to the entry before.
+fn has_bash_timestamps(tail: &[u8]) -> bool {
+    String::from_utf8_lossy(tail).lines().any(is_bash_timestamp)
+}
+
+fn is_bash_timestamp(line: &str) -> bool {
+    line.strip_prefix('#').is_some_and(|time| !time.is_empty() && time.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// The bytes appended for `command`, run at `time` in seconds since the epoch. `timestamps`
+/// only matters for bash.
+pub fn entry(format: HistoryFormat, command: &str, time: u64, timestamps: bool) -> Vec<u8> {
+    match format {
+        HistoryFormat::Bash if timestamps => format!("#{}\n{}\n", time, command).into_bytes(),
+        HistoryFormat::Bash => format!("{}\n", command).into_bytes(),
+        HistoryFormat::Zsh => {
+            let mut entry = format!(": {}:0;", time).into_bytes();
+            metafy(command.replace('\n', "\\\n").as_bytes(), &mut entry);
+            entry.push(b'\n');
+            entry
+        },
+        HistoryFormat::Fish => {
+            let command = command.replace('\\', "\\\\").replace('\n', "\\n");
+            format!("- cmd: {}\n  when: {}\n", command, time).into_bytes()
+        },
+        HistoryFormat::PowerShell => format!("{}\n", command.replace('\n', "`\n")).into_bytes(),
+    }
+}
+
+/// Escape `bytes` the way zsh stores its history: NUL and the bytes zsh uses internally
+/// (0x83-0xa2) become 0x83 followed by the byte xor 32
+fn metafy(bytes: &[u8], out: &mut Vec<u8>) {
+    const META: u8 = 0x83;
+    for &byte in bytes {
+        if byte == 0 || (META..=0xa2).contains(&byte) {
+            out.extend([META, byte ^ 32]);
+        } else {
+            out.push(byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aurish-shell-history-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn entry_formats() {
+        let time = 1_700_000_000;
+        assert_eq!(entry(HistoryFormat::Bash, "ls -a", time, false), b"ls -a\n");
+        assert_eq!(entry(HistoryFormat::Bash, "ls -a", time, true), b"#1700000000\nls -a\n");
+        assert_eq!(entry(HistoryFormat::Zsh, "for f in *\ndo echo $f\ndone", time, false), b": 1700000000:0;for f in *\\\ndo echo $f\\\ndone\n");
+        assert_eq!(entry(HistoryFormat::Fish, "echo 'a\\b'\nls", time, false), b"- cmd: echo 'a\\\\b'\\nls\n  when: 1700000000\n");
+        assert_eq!(entry(HistoryFormat::PowerShell, "Get-ChildItem |\nSort-Object", time, false), b"Get-ChildItem |`\nSort-Object\n");
+    }
+
+    #[test]
+    fn zsh_metafied() {
+        // `é` is c3 a9 and untouched, `ă` is c4 83 and `Ģ` is c4 a2
+        let entry = entry(HistoryFormat::Zsh, "echo é ă Ģ", 1, false);
+        assert_eq!(entry, b": 1:0;echo \xc3\xa9 \xc4\x83\xa3 \xc4\x83\x82\n");
+    }
+
+    #[test]
+    fn appended_to_files() {
+        let dir = temp_dir("append");
+        let bash = ShellHistory::new(dir.join(".bash_history"), HistoryFormat::Bash);
+        bash.try_append("ls", 10).unwrap();
+        assert_eq!(fs::read_to_string(bash.path()).unwrap(), "ls\n");
+        fs::write(bash.path(), "ls\n#20\npwd\n").unwrap();
+        bash.try_append("make", 30).unwrap();
+        assert_eq!(fs::read_to_string(bash.path()).unwrap(), "ls\n#20\npwd\n#30\nmake\n");
+        // a last line without its newline is not joined with the new entry
+        fs::write(bash.path(), "ls\npwd").unwrap();
+        bash.try_append("make", 30).unwrap();
+        assert_eq!(fs::read_to_string(bash.path()).unwrap(), "ls\npwd\nmake\n");
+
+        let fish = ShellHistory::new(dir.join("fish").join("fish_history"), HistoryFormat::Fish);
+        fish.try_append("ls", 10).unwrap();
+        fish.append("cd src");
+        let written = fs::read_to_string(fish.path()).unwrap();
+        assert!(written.starts_with("- cmd: ls\n  when: 10\n- cmd: cd src\n  when: "));
+
+        let zsh = ShellHistory::new(dir.join(".zsh_history"), HistoryFormat::Zsh);
+        zsh.try_append("git status", 10).unwrap();
+        assert_eq!(fs::read(zsh.path()).unwrap(), b": 10:0;git status\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(zsh.path()).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bash_timestamps() {
+        for (line, expected) in [("#1700000000", true), ("# comment", false), ("#", false), ("ls", false)] {
+            assert_eq!(is_bash_timestamp(line), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn history_files() {
+        assert!(ShellHistory::for_shell(ShellType::Cmd).is_none());
+        assert!(ShellHistory::for_shell(ShellType::Unknown).is_none());
+        let zsh = ShellHistory::for_shell(ShellType::Zsh).unwrap();
+        assert_eq!(zsh.format(), HistoryFormat::Zsh);
+    }
+}
@@ -0,0 +1,100 @@
+//! How many commands the model is asked for
+//!
+//! `single` asks for the one best command, for quick tasks; `plan` for every step of a
+//! bigger task; `auto`, the default, leaves it to the model. The mode changes both the
+//! JSON schema of the answer and the wording of the system prompt. Models do not always
+//! keep to `maxItems`, so a single-mode answer with more commands is cut by `limit`.
+
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SuggestionMode {
+    /// A single command when one is enough, the steps of the task otherwise
+    #[default]
+    Auto,
+    /// Exactly one command
+    Single,
+    /// Every command the task needs, in order
+    Plan,
+}
+
+impl SuggestionMode {
+    /// JSON schema of an answer with commands
+    pub fn schema(self) -> Value {
+        let mut commands = json!({ "type": "array" });
+        if self == SuggestionMode::Single {
+            commands["maxItems"] = json!(1);
+        }
+        json!({
+            "type": "object",
+            "properties": { "commands": commands },
+            "required": ["commands"],
+        })
+    }
+
+    /// The mode after this one, for going through them with a single key
+    pub fn next(self) -> Self {
+        match self {
+            SuggestionMode::Auto => SuggestionMode::Single,
+            SuggestionMode::Single => SuggestionMode::Plan,
+            SuggestionMode::Plan => SuggestionMode::Auto,
+        }
+    }
+
+    /// Keep only the commands the mode allows, returns how many were dropped
+    pub fn limit(self, commands: &mut Vec<String>) -> usize {
+        match self {
+            SuggestionMode::Single if commands.len() > 1 => {
+                let dropped = commands.len() - 1;
+                commands.truncate(1);
+                dropped
+            },
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for SuggestionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SuggestionMode::Auto => "auto",
+            SuggestionMode::Single => "single",
+            SuggestionMode::Plan => "plan",
+        })
+    }
+}
+
+/// What to tell the user about `dropped` commands left out of a single-mode answer
+pub fn dropped_notice(dropped: usize) -> Option<String> {
+    (dropped > 0).then(|| format!("Single mode: kept the first command, the model suggested {} more", dropped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schemas() {
+        assert_eq!(SuggestionMode::Single.schema()["properties"]["commands"]["maxItems"], 1);
+        for mode in [SuggestionMode::Auto, SuggestionMode::Plan] {
+            assert!(mode.schema()["properties"]["commands"].get("maxItems").is_none(), "{}", mode);
+            assert_eq!(mode.schema()["required"], json!(["commands"]));
+        }
+        assert_eq!(serde_json::from_str::<SuggestionMode>(r#""plan""#).unwrap(), SuggestionMode::Plan);
+        assert_eq!(SuggestionMode::Plan.next().next(), SuggestionMode::Single);
+    }
+
+    #[test]
+    fn single_keeps_the_first_command() {
+        let mut commands = vec!["df -h".to_string(), "du -sh *".to_string(), "ncdu".to_string()];
+        assert_eq!(SuggestionMode::Plan.limit(&mut commands), 0);
+        assert_eq!(commands.len(), 3);
+        assert_eq!(SuggestionMode::Single.limit(&mut commands), 2);
+        assert_eq!(commands, ["df -h"]);
+        assert_eq!(dropped_notice(2).unwrap(), "Single mode: kept the first command, the model suggested 2 more");
+        assert_eq!(dropped_notice(0), None);
+    }
+}
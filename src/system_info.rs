@@ -0,0 +1,162 @@
+//! What aurish knows about the machine it runs on
+//!
+//! The operating system, distribution, architecture and package manager are detected once
+//! and summed up in the system prompt of `OllamaReq::new`, so that the model suggests `dnf`
+//! rather than `apt` on Fedora. `skip_system_info` in the config leaves them out.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Files the distribution is read from, the first one that exists is used
+const OS_RELEASE: [&str; 2] = ["/etc/os-release", "/usr/lib/os-release"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemInfo {
+    /// Operating system family, e.g. `linux`, `macos` or `windows`
+    pub os: String,
+    /// Distribution, e.g. `Fedora Linux 40 (Workstation Edition)`, if os-release names one
+    pub distro: Option<String>,
+    /// CPU architecture, e.g. `x86_64` or `aarch64`
+    pub arch: String,
+    /// First of the package managers of `os` found in `PATH`, see `package_managers`
+    pub package_manager: Option<String>,
+}
+
+impl SystemInfo {
+    /// One line for the system prompt, e.g. `The user's system is Fedora Linux 40 (linux,
+    /// x86_64), its package manager is dnf.`
+    pub fn summary(&self) -> String {
+        let system = match &self.distro {
+            Some(distro) => format!("{} ({}, {})", distro, self.os, self.arch),
+            None => format!("{} ({})", self.os, self.arch),
+        };
+        match &self.package_manager {
+            Some(package_manager) => format!("The user's system is {}, its package manager is {}.", system, package_manager),
+            None => format!("The user's system is {}.", system),
+        }
+    }
+}
+
+/// The system aurish runs on, detected on first use
+pub fn current() -> &'static SystemInfo {
+    static CURRENT: OnceLock<SystemInfo> = OnceLock::new();
+    CURRENT.get_or_init(detect)
+}
+
+/// Detect the system aurish runs on, `current` does it only once
+pub fn detect() -> SystemInfo {
+    let os = env::consts::OS;
+    let distro = OS_RELEASE
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| parse_os_release(&contents));
+    SystemInfo {
+        os: os.to_string(),
+        distro,
+        arch: env::consts::ARCH.to_string(),
+        package_manager: find_package_manager(os, in_path).map(String::from),
+    }
+}
+
+/// Package managers looked for on `os`, in order of preference
+pub fn package_managers(os: &str) -> &'static [&'static str] {
+    match os {
+        "macos" => &["brew"],
+        "windows" => &["winget", "choco"],
+        // brew is also used on Linux, but rarely for system packages
+        _ => &["apt", "dnf", "pacman", "brew"],
+    }
+}
+
+/// The first of the package managers of `os` that `in_path` finds
+pub fn find_package_manager(os: &str, in_path: impl Fn(&str) -> bool) -> Option<&'static str> {
+    package_managers(os).iter().copied().find(|name| in_path(name))
+}
+
+/// Whether `PATH` has an executable called `name`, with `.exe` on windows
+fn in_path(name: &str) -> bool {
+    let Some(paths) = env::var_os("PATH") else {
+        return false;
+    };
+    let file = if cfg!(target_os = "windows") { format!("{}.exe", name) } else { name.to_string() };
+    env::split_paths(&paths).any(|dir| Path::new(&dir).join(&file).is_file())
+}
+
+/// Name of the distribution in the contents of an os-release file: `PRETTY_NAME`, or
+/// else `NAME` and `VERSION_ID`
+pub fn parse_os_release(contents: &str) -> Option<String> {
+    let value = |key: &str| {
+        contents.lines().find_map(|line| {
+            let (name, value) = line.trim().split_once('=')?;
+            Some(unquote(value)).filter(|value| name == key && !value.is_empty())
+        })
+    };
+    value("PRETTY_NAME").or_else(|| {
+        let name = value("NAME")?;
+        Some(match value("VERSION_ID") {
+            Some(version) => format!("{} {}", name, version),
+            None => name,
+        })
+    })
+}
+
+/// A value of os-release without its quotes and backslash escapes
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    let inner = ['"', '\'']
+        .iter()
+        .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+        .unwrap_or(value);
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn os_release() {
+        let fedora = "NAME=\"Fedora Linux\"\nVERSION=\"40 (Workstation Edition)\"\nID=fedora\nVERSION_ID=40\n\
+            PRETTY_NAME=\"Fedora Linux 40 (Workstation Edition)\"\n";
+        assert_eq!(parse_os_release(fedora).as_deref(), Some("Fedora Linux 40 (Workstation Edition)"));
+        let arch = "NAME='Arch Linux'\nID=arch\nBUILD_ID=rolling\n";
+        assert_eq!(parse_os_release(arch).as_deref(), Some("Arch Linux"));
+        let debian = "# comment\nNAME=\"Debian GNU/Linux\"\nVERSION_ID=\"12\"\nPRETTY_NAME=\"\"\n";
+        assert_eq!(parse_os_release(debian).as_deref(), Some("Debian GNU/Linux 12"));
+        assert_eq!(parse_os_release("PRETTY_NAME=\"Say \\\"hi\\\"\"").as_deref(), Some("Say \"hi\""));
+        assert_eq!(parse_os_release("ID=alpine\n"), None);
+    }
+
+    #[test]
+    fn package_manager_lookup() {
+        assert_eq!(find_package_manager("linux", |name| ["dnf", "brew"].contains(&name)), Some("dnf"));
+        assert_eq!(find_package_manager("linux", |name| name == "pacman"), Some("pacman"));
+        // `apt` on macOS is not the Debian one
+        assert_eq!(find_package_manager("macos", |name| ["apt", "brew"].contains(&name)), Some("brew"));
+        assert_eq!(find_package_manager("windows", |name| name == "choco"), Some("choco"));
+        assert_eq!(find_package_manager("linux", |_| false), None);
+    }
+
+    #[test]
+    fn summary_line() {
+        let info = SystemInfo {
+            os: "linux".to_string(),
+            distro: Some("Fedora Linux 40".to_string()),
+            arch: "x86_64".to_string(),
+            package_manager: Some("dnf".to_string()),
+        };
+        assert_eq!(info.summary(), "The user's system is Fedora Linux 40 (linux, x86_64), its package manager is dnf.");
+        let info = SystemInfo { distro: None, package_manager: None, os: "macos".to_string(), arch: "aarch64".to_string() };
+        assert_eq!(info.summary(), "The user's system is macos (aarch64).");
+    }
+}
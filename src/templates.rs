@@ -0,0 +1,336 @@
+//! Prompt templates with placeholders
+//!
+//! `templates.toml` in the aurish config directory (e.g. `~/.config/aurish/templates.toml`)
+//! maps names to prompts, in which `{1}`, `{2}`, ... stand for the arguments given when
+//! the template is used:
+//!
+//! ```toml
+//! install = "Install {1} with my package manager"
+//! large = "Find the files under {1} larger than {2}"
+//! ```
+//!
+//! `aurish-cli ask -t install ripgrep` and `/install ripgrep` in the Asking AI box both ask
+//! "Install ripgrep with my package manager". Arguments are separated by whitespace and can
+//! be quoted; words after the last placeholder's are added to it, so `/install ripgrep fd`
+//! installs both. `{{` and `}}` are literal braces.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::error::TemplateError;
+
+/// Part of a template, between placeholders
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    /// `{n}`, replaced by argument `n`, counting from 1
+    Arg(usize),
+}
+
+/// A prompt with placeholders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    name: String,
+    text: String,
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Check `name` and find the placeholders of `text`
+    pub fn parse(name: &str, text: &str) -> Result<Template, TemplateError> {
+        if !is_valid_name(name) {
+            return Err(TemplateError::InvalidName(name.to_string()));
+        }
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+        while let Some(idx) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..idx]);
+            rest = &rest[idx..];
+            if let Some(after) = rest.strip_prefix("{{").or_else(|| rest.strip_prefix("}}")) {
+                literal.push_str(&rest[..1]);
+                rest = after;
+                continue;
+            }
+            let placeholder = rest.strip_prefix('{')
+                .and_then(|after| after.split_once('}'))
+                .filter(|(digits, _)| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()));
+            match placeholder {
+                Some((digits, after)) => {
+                    let n = digits.parse().unwrap_or(usize::MAX);
+                    if n == 0 {
+                        return Err(TemplateError::ZeroPlaceholder(name.to_string()));
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Arg(n));
+                    rest = after;
+                },
+                // a lone brace is taken as it is
+                None => {
+                    literal.push_str(&rest[..1]);
+                    rest = &rest[1..];
+                },
+            }
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        Ok(Template { name: name.to_string(), text: text.to_string(), parts })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The template as written in the file
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Number of arguments needed, the highest placeholder
+    pub fn arity(&self) -> usize {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Arg(n) => Some(*n),
+            Part::Text(_) => None,
+        }).max().unwrap_or(0)
+    }
+
+    /// The prompt with the placeholders replaced by the arguments in `args`, see
+    /// `split_args`. Without placeholders, `args` is added to the end.
+    pub fn expand(&self, args: &str) -> Result<String, TemplateError> {
+        let arity = self.arity();
+        let args = args.trim();
+        if arity == 0 {
+            return Ok(match args {
+                "" => self.text_without_escapes(),
+                args => format!("{} {}", self.text_without_escapes(), args),
+            });
+        }
+        let mut words = split_args(args);
+        if words.len() < arity {
+            return Err(TemplateError::MissingArgs {
+                name: self.name.clone(),
+                needed: arity,
+                given: words.len(),
+                template: self.text.clone(),
+            });
+        }
+        let last = words.split_off(arity - 1).join(" ");
+        words.push(last);
+        Ok(self.parts.iter().map(|part| match part {
+            Part::Text(text) => text.as_str(),
+            Part::Arg(n) => words[n - 1].as_str(),
+        }).collect())
+    }
+
+    fn text_without_escapes(&self) -> String {
+        self.parts.iter().map(|part| match part {
+            Part::Text(text) => text.as_str(),
+            Part::Arg(_) => "",
+        }).collect()
+    }
+}
+
+/// The templates of the templates file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Templates {
+    /// File they were read from, for telling where to add more
+    path: Option<PathBuf>,
+    templates: BTreeMap<String, Template>,
+}
+
+impl Templates {
+    /// Parse the contents of a templates file
+    pub fn parse(text: &str) -> Result<Templates, TemplateError> {
+        let table: BTreeMap<String, String> = toml::from_str(text).map_err(TemplateError::Corrupt)?;
+        let templates = table.iter()
+            .map(|(name, text)| Template::parse(name, text).map(|template| (name.clone(), template)))
+            .collect::<Result<_, _>>()?;
+        Ok(Templates { path: None, templates })
+    }
+
+    /// Read the templates file at `path`, none if it does not exist
+    pub fn load(path: &Path) -> Result<Templates, TemplateError> {
+        let templates = match fs::read_to_string(path) {
+            Ok(text) => Templates::parse(&text)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Templates::default(),
+            Err(e) => return Err(TemplateError::Read(e)),
+        };
+        Ok(Templates { path: Some(path.to_path_buf()), ..templates })
+    }
+
+    /// The templates of `default_path`, none without a config directory
+    pub fn load_default() -> Result<Templates, TemplateError> {
+        default_path().map_or(Ok(Templates::default()), |path| Templates::load(&path))
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    /// Names in alphabetical order
+    pub fn names(&self) -> Vec<&str> {
+        self.templates.keys().map(String::as_str).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Template> {
+        self.templates.get(name)
+    }
+
+    /// Template `name` expanded with `args`, see `Template::expand`
+    pub fn expand(&self, name: &str, args: &str) -> Result<String, TemplateError> {
+        match self.get(name) {
+            Some(template) => template.expand(args),
+            None => Err(TemplateError::Unknown {
+                name: name.to_string(),
+                available: self.names().into_iter().map(String::from).collect(),
+                path: self.path.clone(),
+            }),
+        }
+    }
+
+    /// A prompt typed as `/name arguments` expanded, any other prompt as it is. A prompt
+    /// starting with `//` is sent with one `/`, and one starting with a path like
+    /// `/etc/hosts` is no template.
+    pub fn expand_prompt(&self, prompt: &str) -> Result<String, TemplateError> {
+        let Some(rest) = prompt.strip_prefix('/') else {
+            return Ok(prompt.to_string());
+        };
+        if rest.starts_with('/') {
+            return Ok(rest.to_string());
+        }
+        let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if !is_valid_name(name) {
+            return Ok(prompt.to_string());
+        }
+        self.expand(name, args)
+    }
+
+    /// Names starting with `prefix`
+    pub fn complete(&self, prefix: &str) -> Vec<&str> {
+        self.templates.keys().filter(|name| name.starts_with(prefix)).map(String::as_str).collect()
+    }
+}
+
+/// `<config dir>/aurish/templates.toml`; unit tests never touch the real file
+pub fn default_path() -> Option<PathBuf> {
+    if cfg!(test) {
+        return None;
+    }
+    dirs::config_dir().map(|dir| dir.join("aurish").join("templates.toml"))
+}
+
+/// Template names are letters, digits, `-` and `_`
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Arguments separated by whitespace, where `"..."` and `'...'` keep whitespace in one
+pub fn split_args(text: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut quote = None;
+    for c in text.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => arg.get_or_insert_with(String::new).push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                arg.get_or_insert_with(String::new);
+            },
+            None if c.is_whitespace() => args.extend(arg.take()),
+            None => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates() -> Templates {
+        Templates::parse(r#"
+            install = "Install {1} with my package manager"
+            large = "Find the files under {1} larger than {2}"
+            status = "Show the git status"
+            json = "Print {{\"name\": \"{1}\"}}"
+        "#).unwrap()
+    }
+
+    #[test]
+    fn expanded() {
+        let templates = templates();
+        assert_eq!(templates.names(), ["install", "json", "large", "status"]);
+        assert_eq!(templates.expand("install", "ripgrep").unwrap(), "Install ripgrep with my package manager");
+        // words after the last placeholder's are added to it
+        assert_eq!(templates.expand("install", " ripgrep  fd ").unwrap(), "Install ripgrep fd with my package manager");
+        assert_eq!(templates.expand("large", "'My Documents' 100M").unwrap(), "Find the files under My Documents larger than 100M");
+        assert_eq!(templates.expand("status", "").unwrap(), "Show the git status");
+        assert_eq!(templates.expand("status", "of src").unwrap(), "Show the git status of src");
+        assert_eq!(templates.expand("json", "aurish").unwrap(), r#"Print {"name": "aurish"}"#);
+        assert_eq!(templates.get("large").unwrap().arity(), 2);
+    }
+
+    #[test]
+    fn typed_prompts() {
+        let templates = templates();
+        assert_eq!(templates.expand_prompt("/install ripgrep").unwrap(), "Install ripgrep with my package manager");
+        assert_eq!(templates.expand_prompt("/large\n/var 1G").unwrap(), "Find the files under /var larger than 1G");
+        assert_eq!(templates.expand_prompt("list files").unwrap(), "list files");
+        assert_eq!(templates.expand_prompt("//tmp is full").unwrap(), "/tmp is full");
+        assert_eq!(templates.expand_prompt("/etc/hosts is wrong").unwrap(), "/etc/hosts is wrong");
+        assert_eq!(templates.complete("in"), ["install"]);
+        assert_eq!(templates.complete(""), ["install", "json", "large", "status"]);
+        assert!(templates.complete("x").is_empty());
+    }
+
+    #[test]
+    fn friendly_errors() {
+        let templates = templates();
+        let unknown = templates.expand_prompt("/instal ripgrep").unwrap_err();
+        assert_eq!(unknown.to_string(), "there is no template named `instal`, the templates are: install, json, large, status");
+        let missing = templates.expand("large", "/var").unwrap_err();
+        assert_eq!(missing.to_string(), "template `large` needs 2 arguments, got 1: Find the files under {1} larger than {2}");
+        let none = Templates { path: Some(PathBuf::from("/home/me/.config/aurish/templates.toml")), ..Templates::default() };
+        assert_eq!(
+            none.expand("install", "fd").unwrap_err().to_string(),
+            "there is no template named `install`, add templates to /home/me/.config/aurish/templates.toml",
+        );
+    }
+
+    #[test]
+    fn bad_files() {
+        assert!(matches!(Templates::parse("install = 3"), Err(TemplateError::Corrupt(_))));
+        assert!(matches!(Templates::parse("install = \"Install {1}"), Err(TemplateError::Corrupt(_))));
+        assert!(matches!(Templates::parse("\"in stall\" = \"Install {1}\""), Err(TemplateError::InvalidName(_))));
+        assert!(matches!(Templates::parse("install = \"Install {0}\""), Err(TemplateError::ZeroPlaceholder(_))));
+        // lone braces and non-numeric ones are text
+        let template = Template::parse("braces", "use {x} and { or } for {1}").unwrap();
+        assert_eq!(template.expand("it").unwrap(), "use {x} and { or } for it");
+
+        let path = std::env::temp_dir().join(format!("aurish-templates-{}.toml", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert!(Templates::load(&path).unwrap().is_empty());
+        fs::write(&path, "install = \"Install {1}\"\n").unwrap();
+        assert_eq!(Templates::load(&path).unwrap().path(), Some(path.as_path()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(split_args("a  b\tc"), ["a", "b", "c"]);
+        assert_eq!(split_args("\"a b\" 'c d' e\"f g\""), ["a b", "c d", "ef g"]);
+        assert_eq!(split_args("'' x"), ["", "x"]);
+        assert!(split_args("   ").is_empty());
+    }
+}
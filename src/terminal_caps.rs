@@ -0,0 +1,94 @@
+//! What the terminal `aurish` starts in can do
+//!
+//! The TUI needs stdin and stdout to be a terminal that moves the cursor: with output piped
+//! or `TERM=dumb`, raw mode and box drawing only print garbage, so `aurish` stops before
+//! touching the terminal and points to `aurish-cli run`. `NO_COLOR` (set and not empty,
+//! see <https://no-color.org>) selects the monochrome theme.
+
+use std::io::{self, IsTerminal};
+use crate::error::TerminalError;
+
+/// What was found out about the terminal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TerminalCaps {
+    /// Both stdin and stdout are a terminal
+    pub interactive: bool,
+    /// Value of `TERM`, `None` if unset
+    pub term: Option<String>,
+    /// `NO_COLOR` is set to something
+    pub no_color: bool,
+}
+
+impl TerminalCaps {
+    /// Look at the standard streams and the environment of this process
+    pub fn detect() -> TerminalCaps {
+        let interactive = io::stdin().is_terminal() && io::stdout().is_terminal();
+        TerminalCaps::from_env(interactive, |name| std::env::var(name).ok())
+    }
+
+    /// Caps of a terminal that is `interactive` or not, with the variables `var` returns
+    pub fn from_env(interactive: bool, var: impl Fn(&str) -> Option<String>) -> TerminalCaps {
+        TerminalCaps {
+            interactive,
+            term: var("TERM"),
+            no_color: var("NO_COLOR").is_some_and(|value| !value.is_empty()),
+        }
+    }
+
+    /// Why the TUI cannot be drawn here, `None` if it can. An unset `TERM` only counts on
+    /// Unix: the Windows console does without it.
+    pub fn tui_problem(&self) -> Option<TerminalError> {
+        if !self.interactive {
+            return Some(TerminalError::NotATerminal);
+        }
+        match self.term.as_deref().map(str::trim) {
+            Some("dumb") => Some(TerminalError::Incapable(self.term.clone())),
+            Some("") | None if cfg!(unix) => Some(TerminalError::Incapable(None)),
+            _ => None,
+        }
+    }
+
+    /// Whether to draw in color
+    pub fn color(&self) -> bool {
+        !self.no_color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caps(interactive: bool, vars: &[(&str, &str)]) -> TerminalCaps {
+        TerminalCaps::from_env(interactive, |name| {
+            vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn terminals() {
+        assert_eq!(caps(true, &[("TERM", "xterm-256color")]).tui_problem(), None);
+        assert_eq!(caps(false, &[("TERM", "xterm-256color")]).tui_problem(), Some(TerminalError::NotATerminal));
+        // piped output wins over anything in the environment
+        assert_eq!(caps(false, &[("TERM", "dumb")]).tui_problem(), Some(TerminalError::NotATerminal));
+        assert_eq!(caps(true, &[("TERM", "dumb")]).tui_problem(), Some(TerminalError::Incapable(Some("dumb".to_string()))));
+        let unset = caps(true, &[]).tui_problem();
+        let empty = caps(true, &[("TERM", "")]).tui_problem();
+        if cfg!(unix) {
+            assert_eq!(unset, Some(TerminalError::Incapable(None)));
+            assert_eq!(empty, Some(TerminalError::Incapable(None)));
+        } else {
+            assert_eq!((unset, empty), (None, None));
+        }
+    }
+
+    #[test]
+    fn no_color() {
+        assert!(caps(true, &[("TERM", "xterm")]).color());
+        assert!(!caps(true, &[("TERM", "xterm"), ("NO_COLOR", "1")]).color());
+        // only a value counts, see no-color.org
+        assert!(caps(true, &[("NO_COLOR", "")]).color());
+        // colors do not make a dumb terminal usable, nor the other way around
+        let dumb = caps(true, &[("TERM", "dumb"), ("NO_COLOR", "1")]);
+        assert!(!dumb.color() && dumb.tui_problem().is_some());
+    }
+}
@@ -0,0 +1,247 @@
+//! Colors of the TUI
+//!
+//! A `Theme` is built from the `theme` section of `config.json`, which is either the name of
+//! a preset or an object picking a preset and overriding some of its colors:
+//!
+//! ```json
+//! "theme": "light"
+//! "theme": { "preset": "dark", "active": "light-cyan", "stderr": "#ff8800" }
+//! ```
+
+#[cfg(feature = "tui")]
+use std::str::FromStr;
+#[cfg(feature = "tui")]
+use ratatui::style::Color;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "tui")]
+use crate::error::ThemeError;
+
+/// Names accepted for presets
+pub const PRESETS: [&str; 4] = ["dark", "light", "high-contrast", "monochrome"];
+
+/// Color names accepted in the config, besides `#rrggbb` and 256-color indices
+pub const COLOR_NAMES: [&str; 17] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "gray", "dark-gray",
+    "light-red", "light-green", "light-yellow", "light-blue", "light-magenta", "light-cyan",
+    "white", "reset",
+];
+
+/// Colors used by the TUI
+#[cfg(feature = "tui")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    /// Box the user is typing in or scrolling
+    pub active: Color,
+    /// Other boxes while one is active
+    pub inactive: Color,
+    /// Output text while another box is active
+    pub text: Color,
+    /// Error banner background, failed exit codes and dangerous command warnings
+    pub error: Color,
+    /// Text on the error banner
+    pub error_text: Color,
+    /// Lines commands printed to stderr
+    pub stderr: Color,
+    /// Spinners, progress and successful exit codes
+    pub status: Color,
+    /// Background of notices like "Copied 12 bytes"
+    pub notice: Color,
+    /// Text on notices
+    pub notice_text: Color,
+    /// Active model in the status line
+    pub model: Color,
+}
+
+#[cfg(feature = "tui")]
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::dark()
+    }
+}
+
+#[cfg(feature = "tui")]
+impl Theme {
+    pub fn dark() -> Theme {
+        Theme {
+            active: Color::Yellow,
+            inactive: Color::Blue,
+            text: Color::White,
+            error: Color::Red,
+            error_text: Color::White,
+            stderr: Color::Red,
+            status: Color::Green,
+            notice: Color::Green,
+            notice_text: Color::Black,
+            model: Color::Cyan,
+        }
+    }
+
+    /// For terminals with a light background, where yellow and white are hard to read
+    pub fn light() -> Theme {
+        Theme {
+            active: Color::Blue,
+            inactive: Color::DarkGray,
+            text: Color::Black,
+            error: Color::Red,
+            error_text: Color::White,
+            stderr: Color::Red,
+            status: Color::Green,
+            notice: Color::Green,
+            notice_text: Color::White,
+            model: Color::Magenta,
+        }
+    }
+
+    /// Bright colors only, for readability on dark terminals
+    pub fn high_contrast() -> Theme {
+        Theme {
+            active: Color::LightYellow,
+            inactive: Color::White,
+            text: Color::White,
+            error: Color::LightRed,
+            error_text: Color::Black,
+            stderr: Color::LightRed,
+            status: Color::LightGreen,
+            notice: Color::LightGreen,
+            notice_text: Color::Black,
+            model: Color::LightCyan,
+        }
+    }
+
+    /// The terminal's own colors only, picked when `NO_COLOR` is set
+    pub fn monochrome() -> Theme {
+        Theme {
+            active: Color::Reset,
+            inactive: Color::Reset,
+            text: Color::Reset,
+            error: Color::Reset,
+            error_text: Color::Reset,
+            stderr: Color::Reset,
+            status: Color::Reset,
+            notice: Color::Reset,
+            notice_text: Color::Reset,
+            model: Color::Reset,
+        }
+    }
+
+    pub fn preset(name: &str) -> Result<Theme, ThemeError> {
+        match name {
+            "dark" => Ok(Theme::dark()),
+            "light" => Ok(Theme::light()),
+            "high-contrast" => Ok(Theme::high_contrast()),
+            "monochrome" => Ok(Theme::monochrome()),
+            other => Err(ThemeError::UnknownPreset(other.to_string())),
+        }
+    }
+
+    /// Build the theme described by the `theme` section of the config
+    pub fn from_config(config: &ThemeConfig) -> Result<Theme, ThemeError> {
+        let colors = match config {
+            ThemeConfig::Preset(name) => return Theme::preset(name),
+            ThemeConfig::Custom(colors) => colors,
+        };
+        let mut theme = Theme::preset(colors.preset.as_deref().unwrap_or("dark"))?;
+        let overrides = [
+            ("active", &colors.active, &mut theme.active),
+            ("inactive", &colors.inactive, &mut theme.inactive),
+            ("text", &colors.text, &mut theme.text),
+            ("error", &colors.error, &mut theme.error),
+            ("error_text", &colors.error_text, &mut theme.error_text),
+            ("stderr", &colors.stderr, &mut theme.stderr),
+            ("status", &colors.status, &mut theme.status),
+            ("notice", &colors.notice, &mut theme.notice),
+            ("notice_text", &colors.notice_text, &mut theme.notice_text),
+            ("model", &colors.model, &mut theme.model),
+        ];
+        for (field, value, color) in overrides {
+            if let Some(value) = value {
+                *color = parse_color(value).ok_or_else(|| ThemeError::UnknownColor {
+                    field: field.to_string(),
+                    value: value.clone(),
+                })?;
+            }
+        }
+        Ok(theme)
+    }
+}
+
+/// `theme` section of the config
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ThemeConfig {
+    /// Name of a preset, see `PRESETS`
+    Preset(String),
+    /// A preset with some colors replaced
+    Custom(Box<ThemeColors>),
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        ThemeConfig::Preset("dark".to_string())
+    }
+}
+
+/// Colors overriding the preset, unset ones are taken from it
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThemeColors {
+    pub preset: Option<String>,
+    pub active: Option<String>,
+    pub inactive: Option<String>,
+    pub text: Option<String>,
+    pub error: Option<String>,
+    pub error_text: Option<String>,
+    pub stderr: Option<String>,
+    pub status: Option<String>,
+    pub notice: Option<String>,
+    pub notice_text: Option<String>,
+    pub model: Option<String>,
+}
+
+/// Parse a color name from `COLOR_NAMES`, `#rrggbb` or a 256-color index
+#[cfg(feature = "tui")]
+fn parse_color(value: &str) -> Option<Color> {
+    let value = value.trim().to_lowercase();
+    if COLOR_NAMES.contains(&value.as_str()) || value.starts_with('#') || value.parse::<u8>().is_ok() {
+        Color::from_str(&value).ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(all(test, feature = "tui"))]
+mod tests {
+    use super::*;
+
+    fn custom(json: &str) -> Result<Theme, ThemeError> {
+        Theme::from_config(&serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn presets() {
+        assert_eq!(custom(r#""light""#).unwrap(), Theme::light());
+        assert_eq!(custom(r#""high-contrast""#).unwrap(), Theme::high_contrast());
+        assert_eq!(custom(r#""monochrome""#).unwrap(), Theme::monochrome());
+        assert!(matches!(custom(r#""solarized""#), Err(ThemeError::UnknownPreset(_))));
+    }
+
+    #[test]
+    fn overrides() {
+        let theme = custom(r##"{"preset": "light", "active": "light-cyan", "stderr": "#ff8800", "model": "208"}"##).unwrap();
+        assert_eq!(theme.active, Color::LightCyan);
+        assert_eq!(theme.stderr, Color::Rgb(0xff, 0x88, 0x00));
+        assert_eq!(theme.model, Color::Indexed(208));
+        assert_eq!(theme.inactive, Theme::light().inactive);
+        // without a preset, the dark one is the base
+        assert_eq!(custom(r#"{"text": "gray"}"#).unwrap().active, Color::Yellow);
+    }
+
+    #[test]
+    fn unknown_color() {
+        let err = custom(r#"{"error": "crimson"}"#).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("`crimson`"));
+        assert!(message.contains("error"));
+        assert!(message.contains("light-red"));
+    }
+}
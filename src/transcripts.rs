@@ -0,0 +1,454 @@
+//! Saved session transcripts, listed, shown and searched by `aurish-cli history`
+//!
+//! Each exported session is a file `session-<YYYYMMDD-HHMMSS>.json` in the sessions
+//! directory (`session_dir` of the config, or `session::default_dir`), and the part after
+//! `session-` is its id. `index.json` next to them keeps a summary of each, so listing
+//! needs not read every transcript. Like the favorites, the index is written to a temporary
+//! file renamed over it while holding a lock on `.index.json.lock`.
+//!
+//! Transcripts exported before the index existed are added to it when the sessions are
+//! listed. Files that cannot be read or parsed are skipped and reported, they never stop
+//! the listing or a search.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::error::HistoryError;
+use crate::search::Search;
+use crate::session::{self, Event, Session};
+use crate::utils;
+
+/// Name of the index file in the sessions directory
+pub const INDEX_FILE: &str = "index.json";
+
+/// Format of the index written by this version, an index of another one is rebuilt
+pub const INDEX_VERSION: u32 = 1;
+
+const PREFIX: &str = "session-";
+
+/// What `aurish-cli history` lists about a session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Summary {
+    pub id: String,
+    /// Seconds since the Unix epoch
+    pub started: u64,
+    pub first_prompt: Option<String>,
+    /// Commands run, background jobs included
+    pub executed: usize,
+    /// Commands that exited with an error or were killed
+    pub failed: usize,
+}
+
+impl Summary {
+    pub fn of(id: &str, session: &Session) -> Summary {
+        let executed: Vec<_> = session.events.iter()
+            .filter_map(|event| match event {
+                Event::CommandExecuted { exit_code, background, .. } => Some(*background || *exit_code == Some(0)),
+                _ => None,
+            })
+            .collect();
+        Summary {
+            id: id.to_string(),
+            started: session.started,
+            first_prompt: session.events.iter().find_map(|event| match event {
+                Event::PromptSent { prompt, .. } => Some(prompt.clone()),
+                _ => None,
+            }),
+            executed: executed.len(),
+            failed: executed.iter().filter(|succeeded| !**succeeded).count(),
+        }
+    }
+}
+
+/// A prompt or command of a session containing the text searched for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Hit {
+    pub id: String,
+    pub started: u64,
+    /// `prompt`, `suggested` or `executed`
+    pub kind: &'static str,
+    pub text: String,
+}
+
+/// Result of reading many transcripts: what could be read, and why the others could not
+#[derive(Debug)]
+pub struct Found<T> {
+    pub items: Vec<T>,
+    pub skipped: Vec<HistoryError>,
+}
+
+impl<T> Default for Found<T> {
+    fn default() -> Self {
+        Found { items: Vec::new(), skipped: Vec::new() }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexFile {
+    version: u32,
+    sessions: Vec<Summary>,
+}
+
+/// The transcripts in a sessions directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcripts {
+    dir: PathBuf,
+}
+
+impl Transcripts {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Transcripts { dir: dir.into() }
+    }
+
+    /// The transcripts in `dir`, or in `session::default_dir` if `None`
+    pub fn open(dir: Option<&Path>) -> Result<Transcripts, HistoryError> {
+        dir.map(Path::to_path_buf).or_else(session::default_dir).map(Transcripts::new).ok_or(HistoryError::NoDataDir)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Write `session` to its file, replacing an earlier export of it, and add it to the index
+    pub fn save(&self, session: &Session) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let id = session::file_timestamp(session.started);
+        let path = self.path_of(&id);
+        utils::write_atomically(&path, &serde_json::to_string_pretty(session)?)?;
+        let summary = Summary::of(&id, session);
+        let _lock = utils::lock_beside(&self.dir.join(INDEX_FILE))?;
+        let mut sessions = self.read_index().unwrap_or_default();
+        sessions.retain(|saved| saved.id != id);
+        sessions.push(summary);
+        self.write_index(sessions)?;
+        Ok(path)
+    }
+
+    /// Every session, newest first. Transcripts missing from the index are read and added
+    /// to it, and entries whose file is gone are dropped.
+    pub fn list(&self) -> Found<Summary> {
+        let mut found = Found::default();
+        if !self.dir.exists() {
+            return found;
+        }
+        let Ok(_lock) = utils::lock_beside(&self.dir.join(INDEX_FILE)) else {
+            // e.g. a directory nobody can write to, the transcripts can still be read
+            return self.summarize_all();
+        };
+        let indexed = match self.read_index() {
+            Ok(indexed) => indexed,
+            Err(e) => {
+                found.skipped.push(e);
+                Vec::new()
+            },
+        };
+        let ids = match self.ids() {
+            Ok(ids) => ids,
+            Err(source) => {
+                found.skipped.push(HistoryError::Read { path: self.dir.clone(), source });
+                return found;
+            },
+        };
+        let mut changed = indexed.iter().any(|summary| !ids.contains(&summary.id));
+        for id in &ids {
+            match indexed.iter().find(|summary| summary.id == *id) {
+                Some(summary) => found.items.push(summary.clone()),
+                None => match self.load(id) {
+                    Ok(session) => {
+                        found.items.push(Summary::of(id, &session));
+                        changed = true;
+                    },
+                    Err(e) => found.skipped.push(e),
+                },
+            }
+        }
+        if changed {
+            if let Err(e) = self.write_index(found.items.clone()) {
+                log::warn!("could not update {}: {}", self.dir.join(INDEX_FILE).display(), e);
+            }
+        }
+        sort_newest_first(&mut found.items);
+        found
+    }
+
+    /// `list` without the index
+    fn summarize_all(&self) -> Found<Summary> {
+        let mut found = Found::default();
+        match self.ids() {
+            Ok(ids) => for id in ids {
+                match self.load(&id) {
+                    Ok(session) => found.items.push(Summary::of(&id, &session)),
+                    Err(e) => found.skipped.push(e),
+                }
+            },
+            Err(source) => found.skipped.push(HistoryError::Read { path: self.dir.clone(), source }),
+        }
+        sort_newest_first(&mut found.items);
+        found
+    }
+
+    /// The session of id `id`
+    pub fn load(&self, id: &str) -> Result<Session, HistoryError> {
+        let id = id.trim();
+        if id.is_empty() || id.contains(['/', '\\']) {
+            return Err(HistoryError::NotFound(id.to_string()));
+        }
+        let path = self.path_of(id);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(HistoryError::NotFound(id.to_string())),
+            Err(source) => return Err(HistoryError::Read { path, source }),
+        };
+        serde_json::from_str(&text).map_err(|source| HistoryError::Corrupt { path, source })
+    }
+
+    /// The prompts and commands containing `text`, newest session first; smart case like
+    /// the Output search of the TUI
+    pub fn search(&self, text: &str) -> Found<Hit> {
+        let search = Search::new(text.trim());
+        let listed = self.list();
+        let mut found = Found { items: Vec::new(), skipped: listed.skipped };
+        for summary in listed.items {
+            let session = match self.load(&summary.id) {
+                Ok(session) => session,
+                Err(e) => {
+                    found.skipped.push(e);
+                    continue;
+                },
+            };
+            let mut hits: Vec<Hit> = Vec::new();
+            for (kind, text) in searchable(&session) {
+                if search.is_match(text) && !hits.iter().any(|hit| hit.kind == kind && hit.text == text) {
+                    hits.push(Hit { id: summary.id.clone(), started: summary.started, kind, text: text.to_string() });
+                }
+            }
+            found.items.extend(hits);
+        }
+        found
+    }
+
+    fn path_of(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}{}.json", PREFIX, id))
+    }
+
+    /// Ids of the transcripts in the directory, none if it does not exist yet
+    fn ids(&self) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut ids = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            let id = name.to_str()
+                .and_then(|name| name.strip_prefix(PREFIX))
+                .and_then(|name| name.strip_suffix(".json"));
+            if let Some(id) = id {
+                ids.push(id.to_string());
+            }
+        }
+        Ok(ids)
+    }
+
+    /// The summaries in the index, none if there is no index or it has another version
+    fn read_index(&self) -> Result<Vec<Summary>, HistoryError> {
+        let path = self.dir.join(INDEX_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(source) => return Err(HistoryError::Read { path, source }),
+        };
+        match serde_json::from_str::<IndexFile>(&text) {
+            Ok(index) if index.version == INDEX_VERSION => Ok(index.sessions),
+            Ok(_) => Ok(Vec::new()),
+            Err(source) => Err(HistoryError::Corrupt { path, source }),
+        }
+    }
+
+    fn write_index(&self, mut sessions: Vec<Summary>) -> io::Result<()> {
+        sort_newest_first(&mut sessions);
+        let text = serde_json::to_string_pretty(&IndexFile { version: INDEX_VERSION, sessions })?;
+        utils::write_atomically(&self.dir.join(INDEX_FILE), &text)
+    }
+}
+
+fn sort_newest_first(sessions: &mut [Summary]) {
+    sessions.sort_by(|a, b| b.started.cmp(&a.started).then_with(|| b.id.cmp(&a.id)));
+}
+
+/// The prompts and commands of `session`, with what they are
+fn searchable(session: &Session) -> Vec<(&'static str, &str)> {
+    let mut texts = Vec::new();
+    for event in &session.events {
+        match event {
+            Event::PromptSent { prompt, .. } => texts.push(("prompt", prompt.as_str())),
+            Event::CommandsReceived { commands, .. } => texts.extend(commands.iter().map(|command| ("suggested", command.as_str()))),
+            Event::CommandExecuted { command, .. } => texts.push(("executed", command.as_str())),
+            Event::CommandSkipped { .. } | Event::CommandEdited { .. } | Event::PlanShown { .. } | Event::Error { .. } | Event::NewConversation { .. } => {},
+        }
+    }
+    texts
+}
+
+/// `session` as lines of plain text, one or more per event
+pub fn transcript_lines(session: &Session) -> Vec<String> {
+    let mut lines = Vec::new();
+    for event in &session.events {
+        match event {
+            Event::PromptSent { at, model, prompt } => {
+                lines.push(format!("[{}] {} was asked:", session::clock_time(*at), model));
+                lines.extend(prompt.lines().map(|line| format!("  {}", line)));
+            },
+            Event::CommandsReceived { commands, .. } => {
+                lines.extend(commands.iter().map(|command| format!("  suggested: {}", command)));
+            },
+            Event::CommandSkipped { command, .. } => lines.push(format!("skipped: {}", command)),
+            Event::CommandEdited { .. } => {},
+            Event::CommandExecuted { at, command, suggested, exit_code, background, interactive, duration_ms, output } => {
+                let status = match (exit_code, background) {
+                    (_, true) => "started in background".to_string(),
+                    (Some(code), _) => format!("exit {}, {} ms", code, duration_ms),
+                    (None, _) => format!("killed, {} ms", duration_ms),
+                };
+                lines.push(format!("[{}] $ {}  [{}]", session::clock_time(*at), command, status));
+                if let Some(suggested) = suggested {
+                    lines.push(format!("  edited from: {}", suggested));
+                }
+                if *interactive {
+                    lines.push(format!("  {}", crate::interactive::INTERACTIVE_NOTE));
+                }
+                lines.extend(output.stdout.lines().map(|line| format!("  {}", line)));
+                lines.extend(output.stderr.lines().map(|line| format!("  stderr: {}", line)));
+            },
+            Event::PlanShown { at, plan } => {
+                lines.push(format!("[{}] plan of: {}", session::clock_time(*at), plan.command));
+                lines.extend(plan.lines().iter().map(|line| format!("  {}", line)));
+            },
+            Event::Error { at, message } => lines.push(format!("[{}] error: {}", session::clock_time(*at), message)),
+            Event::NewConversation { .. } => lines.push("--- new conversation ---".to_string()),
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::shell::{ExecutionPlan, PlannedAction};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aurish-transcripts-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn session(started: u64, prompt: &str, commands: &[(&str, Option<i32>)]) -> Session {
+        let mut session = Session { started, events: Vec::new() };
+        session.prompt_sent("llama3:latest", prompt);
+        session.commands_received(&commands.iter().map(|(command, _)| command.to_string()).collect::<Vec<_>>());
+        for (command, code) in commands {
+            session.command_executed(command, None, *code, Duration::from_millis(5), "out\n", "");
+        }
+        // every event at the start, for lines that do not change with the clock
+        for event in &mut session.events {
+            let mut value = serde_json::to_value(&*event).unwrap();
+            value["at"] = started.into();
+            *event = serde_json::from_value(value).unwrap();
+        }
+        session
+    }
+
+    #[test]
+    fn saved_listed_and_loaded() {
+        let store = Transcripts::new(temp_dir("saved"));
+        assert!(store.list().items.is_empty());
+
+        let first = session(1_700_000_000, "free disk space", &[("df -h", Some(0)), ("du -sh *", Some(1))]);
+        store.save(&first).unwrap();
+        let mut second = session(1_700_000_100, "list ports", &[("ss -tlnp", None)]);
+        store.save(&second).unwrap();
+        // exporting the same session again replaces it
+        second.command_started("sleep 9 &", None);
+        store.save(&second).unwrap();
+
+        let listed = store.list();
+        assert!(listed.skipped.is_empty());
+        let summaries: Vec<_> = listed.items.iter().map(|summary| (summary.id.as_str(), summary.executed, summary.failed)).collect();
+        assert_eq!(summaries, [("20231114-221500", 2, 1), ("20231114-221320", 2, 1)]);
+        assert_eq!(listed.items[1].first_prompt.as_deref(), Some("free disk space"));
+        assert_eq!(store.load("20231114-221320").unwrap(), first);
+        assert!(matches!(store.load("20990101-000000"), Err(HistoryError::NotFound(_))));
+        assert!(matches!(store.load("../index"), Err(HistoryError::NotFound(_))));
+        fs::remove_dir_all(store.dir()).unwrap();
+    }
+
+    #[test]
+    fn unindexed_and_corrupted_files() {
+        let dir = temp_dir("corrupted");
+        // exported before there was an index
+        session(1_700_000_000, "free disk space", &[("df -h", Some(0))]).export(&dir).unwrap();
+        fs::remove_file(dir.join(INDEX_FILE)).unwrap();
+        fs::write(dir.join("session-20231114-230000.json"), "{\"started\": ").unwrap();
+        let store = Transcripts::new(&dir);
+
+        let listed = store.list();
+        assert_eq!(listed.items.len(), 1);
+        assert!(matches!(&listed.skipped[..], [HistoryError::Corrupt { path, .. }] if path.ends_with("session-20231114-230000.json")));
+        assert_eq!(store.read_index().unwrap(), listed.items);
+
+        // a broken index is rebuilt from the transcripts
+        fs::write(dir.join(INDEX_FILE), "[").unwrap();
+        let listed = store.list();
+        assert_eq!((listed.items.len(), listed.skipped.len()), (1, 2));
+        assert_eq!(store.read_index().unwrap().len(), 1);
+        // and a transcript removed by hand leaves it
+        fs::remove_file(store.path_of(&listed.items[0].id)).unwrap();
+        assert!(store.list().items.is_empty());
+        assert!(store.read_index().unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn search_prompts_and_commands() {
+        let store = Transcripts::new(temp_dir("search"));
+        store.save(&session(1_700_000_000, "free disk space", &[("df -h", Some(0)), ("du -sh *", Some(0))])).unwrap();
+        store.save(&session(1_700_000_100, "Show the Disk of /srv", &[("du -sh /srv", Some(0))])).unwrap();
+
+        let hits: Vec<_> = store.search("disk").items.into_iter().map(|hit| (hit.kind, hit.text)).collect();
+        assert_eq!(hits, [("prompt", "Show the Disk of /srv".to_string()), ("prompt", "free disk space".to_string())]);
+        // suggested and executed, but once each
+        let hits: Vec<_> = store.search("du -sh").items.into_iter().map(|hit| (hit.kind, hit.text)).collect();
+        assert_eq!(hits.len(), 4);
+        assert!(store.search("Disk").items.len() == 1);
+        fs::remove_dir_all(store.dir()).unwrap();
+    }
+
+    #[test]
+    fn plain_transcript() {
+        let mut session = session(1_700_000_000, "free disk space", &[("df -h", Some(0))]);
+        session.command_skipped("du -sh *");
+        session.plan_shown(&ExecutionPlan {
+            command: "cd /srv".to_string(),
+            directory: PathBuf::from("/home"),
+            action: PlannedAction::ChangeDirectory { to: Some(PathBuf::from("/srv")) },
+            env: Default::default(),
+            hermetic: false,
+            danger: None,
+            sudo: false,
+        });
+        session.error("connection refused");
+        session.events.iter_mut().skip(3).for_each(|event| match event {
+            Event::CommandSkipped { at, .. } | Event::PlanShown { at, .. } | Event::Error { at, .. } => *at = 1_700_000_000,
+            _ => {},
+        });
+        let lines = transcript_lines(&session);
+        assert_eq!(lines[..3], ["[22:13:20] llama3:latest was asked:", "  free disk space", "  suggested: df -h"]);
+        assert_eq!(lines[3], "[22:13:20] $ df -h  [exit 0, 5 ms]");
+        assert_eq!(lines[4..6], ["  out", "skipped: du -sh *"]);
+        assert_eq!(lines[6..9], ["[22:13:20] plan of: cd /srv", "  cd: moves to /srv, nothing is run", "  risk: nothing dangerous found"]);
+        assert_eq!(lines[9..], ["[22:13:20] error: connection refused"]);
+    }
+}
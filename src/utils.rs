@@ -0,0 +1,354 @@
+//! Small helpers shared by the TUI (`aurish`) and the CLI (`aurish-cli`)
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::path::{Component, Path, MAIN_SEPARATOR};
+use unicode_width::UnicodeWidthStr;
+use crate::error::PromptError;
+
+const ELLIPSIS: &str = "…";
+
+/// Largest prompt read from a file or stdin, in bytes
+pub const MAX_PROMPT_BYTES: usize = 64 * 1024;
+
+/// Render `path` for a shell prompt.
+///
+/// The home directory prefix is replaced with `~`. If `max_width` is given and the result is
+/// wider than that, middle components are collapsed into `…` until it fits, always keeping
+/// the leading root (or `~`) and the last component. Non-UTF-8 components are converted lossily.
+pub fn display_path(path: &Path, max_width: Option<usize>) -> String {
+    display_path_with_home(path, dirs::home_dir().as_deref(), max_width)
+}
+
+/// `display_path` with an explicit home directory
+pub fn display_path_with_home(path: &Path, home: Option<&Path>, max_width: Option<usize>) -> String {
+    let home = home.filter(|home| home.components().count() > 1);
+    let (mut head, relative) = match home.and_then(|home| path.strip_prefix(home).ok()) {
+        Some(rest) => ("~".to_string(), rest),
+        None => (String::new(), path),
+    };
+
+    let mut parts = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Prefix(prefix) => head.push_str(&prefix.as_os_str().to_string_lossy()),
+            Component::RootDir => head.push(MAIN_SEPARATOR),
+            Component::CurDir => {}
+            other => parts.push(other.as_os_str().to_string_lossy().into_owned()),
+        }
+    }
+
+    let mut rendered = join(&head, &parts);
+    if let Some(max_width) = max_width {
+        let mut dropped = 0;
+        while rendered.width() > max_width && parts.len() - dropped > 1 {
+            dropped += 1;
+            let mut shown = vec![ELLIPSIS.to_string()];
+            shown.extend_from_slice(&parts[dropped..]);
+            rendered = join(&head, &shown);
+        }
+    }
+
+    rendered
+}
+
+/// Stream a part of a command's output was printed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Both streams of a command's output in the order they are shown, empty ones left out.
+/// The streams are captured separately, so how they interleaved is unknown and stdout
+/// comes first.
+pub fn output_parts<'a>(stdout: &'a str, stderr: &'a str) -> Vec<(OutputStream, &'a str)> {
+    [(OutputStream::Stdout, stdout), (OutputStream::Stderr, stderr)]
+        .into_iter()
+        .filter(|(_, text)| !text.is_empty())
+        .collect()
+}
+
+/// Break `text` into lines at most `max_width` columns wide, at spaces. Line breaks and
+/// blank lines are kept, and words wider than `max_width` get a line of their own.
+pub fn wrap_text(text: &str, max_width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.width() + 1 + word.width() > max_width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Read a prompt from a file or stdin, refusing more than `limit` bytes and anything that
+/// is not text. One trailing newline is removed.
+pub fn read_prompt(reader: impl Read, limit: usize) -> Result<String, PromptError> {
+    let mut bytes = Vec::new();
+    reader.take(limit as u64 + 1).read_to_end(&mut bytes).map_err(PromptError::Read)?;
+    if bytes.len() > limit {
+        return Err(PromptError::TooLarge { limit });
+    }
+    if bytes.contains(&0) {
+        return Err(PromptError::Binary);
+    }
+    let mut prompt = String::from_utf8(bytes).map_err(|_| PromptError::NotUtf8)?;
+    if prompt.ends_with('\n') {
+        prompt.pop();
+        if prompt.ends_with('\r') {
+            prompt.pop();
+        }
+    }
+    Ok(prompt)
+}
+
+/// What to do with a suggested command of several lines, see `sanitize_commands`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MultilineCommands {
+    /// Queue each line as a command of its own
+    #[default]
+    Split,
+    /// Keep the lines in one command, joined with ` && `
+    Join,
+}
+
+/// The suggested commands as they can be queued: one line each, trimmed, and without empty
+/// ones. A line ending in `\` goes on with the next one; other commands of several lines
+/// are split or joined as `multiline` says.
+pub fn sanitize_commands(commands: Vec<String>, multiline: MultilineCommands) -> Vec<String> {
+    let mut sanitized = Vec::with_capacity(commands.len());
+    for command in commands {
+        let lines = command_lines(&command);
+        match multiline {
+            MultilineCommands::Split => sanitized.extend(lines),
+            MultilineCommands::Join if lines.is_empty() => {},
+            MultilineCommands::Join => sanitized.push(lines.join(" && ")),
+        }
+    }
+    sanitized
+}
+
+/// Commands pasted or read from a file to be queued: a line each, trimmed, without blank
+/// lines, and a line ending in `\` going on with the next, as `sanitize_commands` splits them
+pub fn commands_from_text(text: &str) -> Vec<String> {
+    sanitize_commands(vec![text.to_string()], MultilineCommands::Split)
+}
+
+/// `commands_from_text` of the file at `path`, which must be UTF-8 text
+pub fn read_commands(path: &Path) -> io::Result<Vec<String>> {
+    let text = fs::read_to_string(path)?;
+    if text.contains('\0') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "it looks like a binary file"));
+    }
+    Ok(commands_from_text(&text))
+}
+
+/// Write `text` to a temporary file next to `path`, then move it over `path`, so a crash
+/// halfway leaves the old file intact. The temporary file is named after the process, should
+/// two processes write without holding `lock_beside`.
+pub fn write_atomically(path: &Path, text: &str) -> io::Result<()> {
+    let name = path.file_name().map_or("file".into(), |name| name.to_string_lossy());
+    let tmp = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+    let written = fs::write(&tmp, text).and_then(|()| fs::rename(&tmp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    written
+}
+
+/// Lock `.<name>.lock` next to `path`. The lock is held until the returned file is dropped
+/// and stays while `path` itself is replaced.
+pub fn lock_beside(path: &Path) -> io::Result<File> {
+    let name = path.file_name().map_or("file".into(), |name| name.to_string_lossy());
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.with_file_name(format!(".{}.lock", name)))?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// The non-empty lines of `command`, trimmed, with the continued ones joined. A `\` ending
+/// the last line is kept, as in `dir C:\`.
+fn command_lines(command: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut rest = command.lines().map(str::trim).peekable();
+    while let Some(part) = rest.next() {
+        match part.strip_suffix('\\').filter(|_| rest.peek().is_some()) {
+            Some(start) => {
+                line.push_str(start.trim_end());
+                line.push(' ');
+            },
+            None => {
+                line.push_str(part);
+                lines.push(std::mem::take(&mut line).trim().to_string());
+            },
+        }
+    }
+    lines.retain(|line| !line.is_empty());
+    lines
+}
+
+fn join(head: &str, parts: &[String]) -> String {
+    let separator = MAIN_SEPARATOR.to_string();
+    let body = parts.join(&separator);
+    if head.is_empty() || head.ends_with(MAIN_SEPARATOR) {
+        format!("{}{}", head, body)
+    } else if body.is_empty() {
+        head.to_string()
+    } else {
+        format!("{}{}{}", head, separator, body)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    const HOME: &str = "/home/user";
+
+    fn render(path: &str, max_width: Option<usize>) -> String {
+        display_path_with_home(Path::new(path), Some(Path::new(HOME)), max_width)
+    }
+
+    #[test]
+    fn home_substitution() {
+        assert_eq!(render("/home/user", None), "~");
+        assert_eq!(render("/home/user/projects/aurish", None), "~/projects/aurish");
+        assert_eq!(render("/home/username", None), "/home/username");
+        assert_eq!(render("/etc/nginx", None), "/etc/nginx");
+        assert_eq!(render("/", None), "/");
+    }
+
+    #[test]
+    fn middle_truncation() {
+        let path = "/home/user/projects/rust/aurish/src/bin";
+        assert_eq!(render(path, None), "~/projects/rust/aurish/src/bin");
+        assert_eq!(render(path, Some(100)), "~/projects/rust/aurish/src/bin");
+        assert_eq!(render(path, Some(20)), "~/…/aurish/src/bin");
+        assert_eq!(render(path, Some(8)), "~/…/bin");
+        assert_eq!(render("/var/lib/docker/overlay2", Some(15)), "/…/overlay2");
+
+        // CJK names take two columns per character
+        let path = "/home/user/文档/项目/源代码";
+        assert_eq!(render(path, Some(18)), "~/文档/项目/源代码");
+        assert_eq!(render(path, Some(15)), "~/…/项目/源代码");
+        assert_eq!(render(path, Some(12)), "~/…/源代码");
+    }
+
+    #[test]
+    fn both_output_streams() {
+        use OutputStream::*;
+        assert_eq!(output_parts("done", "warning: deprecated"), [(Stdout, "done"), (Stderr, "warning: deprecated")]);
+        assert_eq!(output_parts("", "error"), [(Stderr, "error")]);
+        assert!(output_parts("", "").is_empty());
+    }
+
+    #[test]
+    fn wrapped_text() {
+        let text = "rm -rf deletes files recursively\n\nWARNING: it does not ask";
+        assert_eq!(wrap_text(text, 16), ["rm -rf deletes", "files", "recursively", "", "WARNING: it does", "not ask"]);
+        assert_eq!(wrap_text("a https://example.com/long b", 5), ["a", "https://example.com/long", "b"]);
+    }
+
+    #[test]
+    fn prompt_from_input() {
+        let read = |input: &[u8]| read_prompt(input, 16);
+        assert_eq!(read(b"first\nsecond\r\n").unwrap(), "first\nsecond");
+        assert_eq!(read(b"keep one\n\n").unwrap(), "keep one\n");
+        assert_eq!(read(&[b'a'; 16]).unwrap().len(), 16);
+        assert!(matches!(read(&[b'a'; 17]), Err(PromptError::TooLarge { limit: 16 })));
+        assert!(matches!(read(b"\x7fELF\x02\x01\x00"), Err(PromptError::Binary)));
+        assert!(matches!(read(b"caf\xe9"), Err(PromptError::NotUtf8)));
+    }
+
+    #[test]
+    fn sanitized_commands() {
+        let commands = ["ls -a  ", "", " \t\n ", "mkdir test\ncd test\r\n", "find . \\\n  -name '*.rs'", "dir C:\\"];
+        let commands = commands.map(String::from).to_vec();
+        assert_eq!(
+            sanitize_commands(commands.clone(), MultilineCommands::Split),
+            ["ls -a", "mkdir test", "cd test", "find . -name '*.rs'", "dir C:\\"],
+        );
+        assert_eq!(
+            sanitize_commands(commands, MultilineCommands::Join),
+            ["ls -a", "mkdir test && cd test", "find . -name '*.rs'", "dir C:\\"],
+        );
+        assert!(sanitize_commands(vec![" ".to_string(), "\n\n".to_string()], MultilineCommands::Split).is_empty());
+    }
+
+    #[test]
+    fn commands_from_pasted_text() {
+        let text = "ls -a\n\n   \n  mkdir test  \r\ncd test\r\n\r\nfind . \\\r\n  -name '*.rs'\r\n";
+        assert_eq!(commands_from_text(text), ["ls -a", "mkdir test", "cd test", "find . -name '*.rs'"]);
+        assert!(commands_from_text("\r\n \n\t\n").is_empty());
+
+        let path = std::env::temp_dir().join(format!("aurish-commands-{}.txt", std::process::id()));
+        fs::write(&path, "uname -a\r\n\r\ndf -h").unwrap();
+        assert_eq!(read_commands(&path).unwrap(), ["uname -a", "df -h"]);
+        fs::write(&path, b"\x7fELF\x02\x01\x00").unwrap();
+        assert_eq!(read_commands(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read_commands(&path).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn atomic_writes() {
+        let dir = std::env::temp_dir().join(format!("aurish-atomic-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("saved.json");
+
+        let lock = lock_beside(&path).unwrap();
+        write_atomically(&path, "first").unwrap();
+        write_atomically(&path, "second").unwrap();
+        drop(lock);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+
+        let mut names: Vec<String> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        assert_eq!(names, [".saved.json.lock", "saved.json"]);
+
+        // a failed write leaves neither the file nor a temporary file behind
+        assert!(write_atomically(&dir.join("missing").join("saved.json"), "text").is_err());
+        assert!(!dir.join("missing").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn non_utf8_component() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(HOME).join(OsStr::from_bytes(b"bad\xffname"));
+        let rendered = display_path_with_home(&path, Some(Path::new(HOME)), None);
+        assert_eq!(rendered, "~/bad\u{fffd}name");
+    }
+}
+
+#[cfg(all(test, windows))]
+mod windows_tests {
+    use super::*;
+
+    #[test]
+    fn drive_prefix() {
+        let home = Path::new(r"C:\Users\user");
+        let render = |path: &str, max_width| display_path_with_home(Path::new(path), Some(home), max_width);
+
+        assert_eq!(render(r"C:\Users\user\projects", None), r"~\projects");
+        assert_eq!(render(r"D:\data\logs\2024", None), r"D:\data\logs\2024");
+        assert_eq!(render(r"D:\data\logs\2024", Some(12)), r"D:\…\2024");
+    }
+}
@@ -0,0 +1,176 @@
+//! Waiting for a prompt file to change, for `aurish-cli ask --file <path> --watch`
+//!
+//! `watch` turns the events of the file system into `()`s on a channel, which `Changes`
+//! debounces: editors often write a file twice or more when saving it, so a change only
+//! counts once no other arrived for `DEBOUNCE`. Tests send the `()`s themselves.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Quiet time after the last event before the change counts
+pub const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Turns bursts of events into single changes
+#[derive(Debug, Clone)]
+pub struct Debouncer {
+    delay: Duration,
+    /// Last event of the burst not reported yet
+    last: Option<Instant>,
+}
+
+impl Debouncer {
+    pub fn new(delay: Duration) -> Self {
+        Debouncer { delay, last: None }
+    }
+
+    /// Note an event seen at `at`
+    pub fn event(&mut self, at: Instant) {
+        self.last = Some(at);
+    }
+
+    /// How long to wait for more events before the burst is over, `None` without one
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.last.map(|last| self.delay.saturating_sub(now.duration_since(last)))
+    }
+
+    /// Whether a burst was over by `now`, which reports it
+    pub fn settled(&mut self, now: Instant) -> bool {
+        match self.remaining(now) {
+            Some(remaining) if remaining.is_zero() => {
+                self.last = None;
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Changes of a file, from the events sent to the other end of `events`
+pub struct Changes {
+    events: Receiver<()>,
+    debouncer: Debouncer,
+}
+
+impl Changes {
+    pub fn new(events: Receiver<()>, delay: Duration) -> Self {
+        Changes { events, debouncer: Debouncer::new(delay) }
+    }
+
+    /// Wait for the next change, `None` once no more events can come
+    pub fn wait(&mut self) -> Option<()> {
+        loop {
+            let received = match self.debouncer.remaining(Instant::now()) {
+                None => self.events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                Some(remaining) => self.events.recv_timeout(remaining),
+            };
+            match received {
+                Ok(()) => self.debouncer.event(Instant::now()),
+                Err(RecvTimeoutError::Timeout) => {},
+                // a burst cut short still changed the file
+                Err(RecvTimeoutError::Disconnected) => return self.debouncer.last.take().map(|_| ()),
+            }
+            if self.debouncer.settled(Instant::now()) {
+                return Some(());
+            }
+        }
+    }
+}
+
+/// Watch `path` for changes, until the returned watcher is dropped.
+///
+/// The directory of the file is watched rather than the file itself, since editors that
+/// save by writing a new file and renaming it over the old one would end a watch on it.
+pub fn watch(path: &Path) -> notify::Result<(RecommendedWatcher, Changes)> {
+    let path = path.canonicalize()?;
+    let dir = path.parent().map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        if let Ok(event) = event {
+            if is_change(&event, &path) {
+                let _ = tx.send(());
+            }
+        }
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    Ok((watcher, Changes::new(rx, DEBOUNCE)))
+}
+
+/// Whether `event` changed the contents of `path`, reading it does not
+fn is_change(event: &Event, path: &Path) -> bool {
+    matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) && event.paths.iter().any(|changed| changed == path)
+}
+
+/// UTC time of day of `secs` since the Unix epoch, like `14:03:22 UTC`
+pub fn clock(secs: u64) -> String {
+    let secs = secs % 86_400;
+    format!("{:02}:{:02}:{:02} UTC", secs / 3_600, secs % 3_600 / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use notify::event::{AccessKind, CreateKind, ModifyKind};
+    use super::*;
+
+    #[test]
+    fn debouncing() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut debouncer = Debouncer::new(Duration::from_millis(100));
+        assert_eq!(debouncer.remaining(start), None);
+        assert!(!debouncer.settled(ms(500)));
+
+        // an editor writing twice
+        debouncer.event(ms(0));
+        debouncer.event(ms(30));
+        assert_eq!(debouncer.remaining(ms(80)), Some(Duration::from_millis(50)));
+        assert!(!debouncer.settled(ms(120)));
+        assert!(debouncer.settled(ms(130)));
+        assert!(!debouncer.settled(ms(400)));
+    }
+
+    #[test]
+    fn injected_events() {
+        let (tx, rx) = mpsc::channel();
+        let mut changes = Changes::new(rx, Duration::from_millis(50));
+        let sender = thread::spawn(move || {
+            for _ in 0..3 {
+                tx.send(()).unwrap();
+            }
+            thread::sleep(Duration::from_millis(200));
+            tx.send(()).unwrap();
+            tx.send(()).unwrap();
+        });
+        let started = Instant::now();
+        assert_eq!(changes.wait(), Some(()));
+        assert!(started.elapsed() < Duration::from_millis(200));
+        // the second burst counts once, then the sender is gone
+        assert_eq!(changes.wait(), Some(()));
+        sender.join().unwrap();
+        assert_eq!(changes.wait(), None);
+    }
+
+    #[test]
+    fn event_kinds() {
+        let path = Path::new("/tmp/task.md");
+        let event = |kind| Event::new(kind).add_path(path.to_path_buf());
+        assert!(is_change(&event(EventKind::Modify(ModifyKind::Any)), path));
+        assert!(is_change(&event(EventKind::Create(CreateKind::File)), path));
+        assert!(!is_change(&event(EventKind::Access(AccessKind::Any)), path));
+        assert!(!is_change(&event(EventKind::Modify(ModifyKind::Any)), Path::new("/tmp/other.md")));
+        assert_eq!(clock(86_400 * 3 + 14 * 3_600 + 3 * 60 + 22), "14:03:22 UTC");
+    }
+
+    #[test]
+    fn file_changes() {
+        let path = std::env::temp_dir().join(format!("aurish-watch-{}.md", std::process::id()));
+        std::fs::write(&path, "list files").unwrap();
+        let (watcher, mut changes) = watch(&path).unwrap();
+        std::fs::write(&path, "list hidden files").unwrap();
+        assert_eq!(changes.wait(), Some(()));
+        drop(watcher);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+//! The directory commands run in, told to the model with each prompt
+//!
+//! So that the model suggests paths relative to where its commands will run, the prompt is
+//! preceded by the shell's current directory, and with `context_level = "listing"` by the
+//! names in it too. Hidden files are left out, and the names are sorted and cut after
+//! `MAX_ENTRIES`, so the same directory always gives the same text.
+
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+/// Names listed at most
+pub const MAX_ENTRIES: usize = 40;
+
+/// How much the model is told about the current directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextLevel {
+    /// Nothing
+    None,
+    /// Its path
+    #[default]
+    Directory,
+    /// Its path and the names in it
+    Listing,
+}
+
+/// Context attached before a prompt about `dir`, see `OllamaReq::prompt_with`. Empty for
+/// `ContextLevel::None`.
+pub fn context(dir: &Path, level: ContextLevel) -> String {
+    let mut text = match level {
+        ContextLevel::None => return String::new(),
+        ContextLevel::Directory | ContextLevel::Listing => format!("Current directory: {}\n", dir.display()),
+    };
+    if level == ContextLevel::Listing {
+        match listing(dir, MAX_ENTRIES) {
+            Some((names, _)) if names.is_empty() => text.push_str("It is empty.\n"),
+            Some((names, 0)) => text.push_str(&format!("It contains: {}\n", names.join(", "))),
+            Some((names, more)) => text.push_str(&format!("It contains: {} (and {} more)\n", names.join(", "), more)),
+            // the path alone then
+            None => {},
+        }
+    }
+    text.push('\n');
+    text
+}
+
+/// The first `max` names in `dir` that are not hidden, sorted, directories ending with
+/// `/`, and how many were left out. `None` if `dir` cannot be read.
+pub fn listing(dir: &Path, max: usize) -> Option<(Vec<String>, usize)> {
+    let mut names: Vec<String> = fs::read_dir(dir).ok()?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                return None;
+            }
+            match entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                true => Some(format!("{}/", name)),
+                false => Some(name),
+            }
+        })
+        .collect();
+    names.sort();
+    let more = names.len().saturating_sub(max);
+    names.truncate(max);
+    Some((names, more))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        let dir = std::env::temp_dir().join(format!("aurish-workdir-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        for name in ["b.txt", "a.txt", ".env"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        assert_eq!(context(&dir, ContextLevel::None), "");
+        assert_eq!(context(&dir, ContextLevel::Directory), format!("Current directory: {}\n\n", dir.display()));
+        assert_eq!(
+            context(&dir, ContextLevel::Listing),
+            format!("Current directory: {}\nIt contains: a.txt, b.txt, src/\n\n", dir.display()),
+        );
+        // the first names in order, whatever order the file system lists them in
+        assert_eq!(listing(&dir, 2), Some((vec!["a.txt".to_string(), "b.txt".to_string()], 1)));
+        assert!(context(&dir.join("src"), ContextLevel::Listing).ends_with("It is empty.\n\n"));
+        // a directory that cannot be read is still named
+        let missing = dir.join("missing");
+        assert_eq!(context(&missing, ContextLevel::Listing), format!("Current directory: {}\n\n", missing.display()));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn config_values() {
+        let level: ContextLevel = serde_json::from_str(r#""listing""#).unwrap();
+        assert_eq!(level, ContextLevel::Listing);
+        assert!(serde_json::from_str::<ContextLevel>(r#""everything""#).is_err());
+        assert_eq!(ContextLevel::default(), ContextLevel::Directory);
+    }
+}